@@ -0,0 +1,183 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::*;
+
+use std::borrow::Cow;
+
+// How buffer data is encoded on disk before lz4, buffers in memory always hold decoded data
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DiskBufferEncoding {
+    Raw,
+    MeshoptVertex, // vertices of `stride` bytes
+    MeshoptIndex,  // triangle list with 16 or 32 bit indices
+}
+
+#[derive(Serialize)]
+struct EncodedDiskBufferRef<'a> {
+    stride: u64,
+    usage_flags: u32,
+    encoding: DiskBufferEncoding,
+    decoded_size: usize,
+
+    #[serde(with = "crate::resource_compression")]
+    data: Cow<'a, [u8]>,
+}
+
+#[derive(Deserialize)]
+struct EncodedDiskBuffer {
+    stride: u64,
+    usage_flags: u32,
+    encoding: DiskBufferEncoding,
+    decoded_size: usize,
+
+    #[serde(with = "crate::resource_compression")]
+    data: Vec<u8>,
+}
+
+impl Serialize for DiskBuffer {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let data = match get_applied_encoding(self.encoding, self.stride, self.data.len()) {
+            DiskBufferEncoding::Raw => Cow::Borrowed(self.data.as_slice()),
+            DiskBufferEncoding::MeshoptVertex => Cow::Owned(encode_vertex_buffer(&self.data, self.stride as usize)),
+            DiskBufferEncoding::MeshoptIndex => Cow::Owned(encode_index_buffer(&self.data, self.stride as usize)),
+        };
+        EncodedDiskBufferRef {
+            stride: self.stride,
+            usage_flags: self.usage_flags,
+            encoding: self.encoding,
+            decoded_size: self.data.len(),
+            data,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DiskBuffer {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // Corrupt or truncated buffers are reported as errors, bundles come from files we don't control
+        let buffer = EncodedDiskBuffer::deserialize(deserializer)?;
+        let data = match get_applied_encoding(buffer.encoding, buffer.stride, buffer.decoded_size) {
+            DiskBufferEncoding::Raw => Ok(buffer.data),
+            DiskBufferEncoding::MeshoptVertex => {
+                decode_vertex_buffer(&buffer.data, buffer.stride as usize, buffer.decoded_size)
+            }
+            DiskBufferEncoding::MeshoptIndex => {
+                decode_index_buffer(&buffer.data, buffer.stride as usize, buffer.decoded_size)
+            }
+        }
+        .map_err(serde::de::Error::custom)?;
+        if data.len() != buffer.decoded_size {
+            return Err(serde::de::Error::custom(format!(
+                "decoded buffer size mismatch, expected {} bytes, got {}",
+                buffer.decoded_size,
+                data.len()
+            )));
+        }
+
+        Ok(DiskBuffer {
+            stride: buffer.stride,
+            usage_flags: buffer.usage_flags,
+            encoding: buffer.encoding,
+            data,
+        })
+    }
+}
+
+// Buffers that the codecs don't support are stored raw, this only depends on values that are stored on disk
+fn get_applied_encoding(encoding: DiskBufferEncoding, stride: u64, size: usize) -> DiskBufferEncoding {
+    let stride = stride as usize;
+    let is_supported = match encoding {
+        DiskBufferEncoding::Raw => true,
+        DiskBufferEncoding::MeshoptVertex => {
+            stride > 0 && stride <= 256 && stride.is_multiple_of(4) && size.is_multiple_of(stride)
+        }
+        DiskBufferEncoding::MeshoptIndex => (stride == 2 || stride == 4) && size.is_multiple_of(stride * 3),
+    };
+    if is_supported && size > 0 {
+        encoding
+    } else {
+        DiskBufferEncoding::Raw
+    }
+}
+
+fn encode_vertex_buffer(data: &[u8], stride: usize) -> Vec<u8> {
+    let vertex_count = data.len() / stride;
+    let mut encoded = vec![0u8; unsafe { meshopt::ffi::meshopt_encodeVertexBufferBound(vertex_count, stride) }];
+    let encoded_size = unsafe {
+        meshopt::ffi::meshopt_encodeVertexBuffer(
+            encoded.as_mut_ptr(),
+            encoded.len(),
+            data.as_ptr() as _,
+            vertex_count,
+            stride,
+        )
+    };
+    assert!(encoded_size > 0, "failed to encode vertex buffer");
+    encoded.truncate(encoded_size);
+    encoded
+}
+
+fn decode_vertex_buffer(encoded: &[u8], stride: usize, size: usize) -> Result<Vec<u8>, String> {
+    let mut data = vec![0u8; size];
+    let result = unsafe {
+        meshopt::ffi::meshopt_decodeVertexBuffer(
+            data.as_mut_ptr() as _,
+            size / stride,
+            stride,
+            encoded.as_ptr(),
+            encoded.len(),
+        )
+    };
+    if result != 0 {
+        return Err(format!("failed to decode vertex buffer: {}", result));
+    }
+    Ok(data)
+}
+
+// Triangles can come back rotated, winding order is preserved
+fn encode_index_buffer(data: &[u8], stride: usize) -> Vec<u8> {
+    let indices: Vec<u32> = if stride == 4 {
+        data.chunks_exact(4)
+            .map(|index| u32::from_le_bytes([index[0], index[1], index[2], index[3]]))
+            .collect()
+    } else {
+        data.chunks_exact(2)
+            .map(|index| u16::from_le_bytes([index[0], index[1]]) as u32)
+            .collect()
+    };
+    let vertex_count = indices.iter().max().map_or(0, |index| *index as usize + 1);
+
+    let mut encoded = vec![0u8; unsafe { meshopt::ffi::meshopt_encodeIndexBufferBound(indices.len(), vertex_count) }];
+    let encoded_size = unsafe {
+        meshopt::ffi::meshopt_encodeIndexBuffer(encoded.as_mut_ptr(), encoded.len(), indices.as_ptr(), indices.len())
+    };
+    assert!(encoded_size > 0, "failed to encode index buffer");
+    encoded.truncate(encoded_size);
+    encoded
+}
+
+fn decode_index_buffer(encoded: &[u8], stride: usize, size: usize) -> Result<Vec<u8>, String> {
+    let mut data = vec![0u8; size];
+    let result = unsafe {
+        meshopt::ffi::meshopt_decodeIndexBuffer(
+            data.as_mut_ptr() as _,
+            size / stride,
+            stride,
+            encoded.as_ptr(),
+            encoded.len(),
+        )
+    };
+    if result != 0 {
+        return Err(format!("failed to decode index buffer: {}", result));
+    }
+    Ok(data)
+}
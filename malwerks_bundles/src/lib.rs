@@ -3,11 +3,27 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
 // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+mod buffer_encoding;
+mod mesh_packing;
+#[cfg(any(test, feature = "mock"))]
+mod mock_bundle;
 mod resource_compression;
+mod resource_handles;
+mod resource_validation;
+mod sort_keys;
+mod stable_hasher;
+mod stress_scene;
+
+pub use buffer_encoding::DiskBufferEncoding;
+#[cfg(any(test, feature = "mock"))]
+pub use mock_bundle::*;
+pub use resource_handles::*;
+pub use sort_keys::{get_sort_key_pipeline_hash, make_sort_key};
+pub use stable_hasher::StableHasher;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Copy, Clone)]
 pub struct DiskSampler {
     pub mag_filter: i32,     // vk::Filter pretending to be i32
     pub min_filter: i32,     // vk::Filter pretending to be i32
@@ -15,6 +31,14 @@ pub struct DiskSampler {
     pub address_mode_u: i32, // vk::SamplerAddressMode pretending to be i32
     pub address_mode_v: i32, // vk::SamplerAddressMode pretending to be i32
     pub address_mode_w: i32, // vk::SamplerAddressMode pretending to be i32
+    pub max_anisotropy: f32, // 1.0 disables anisotropic filtering
+}
+
+// Color data is stored gamma encoded, everything else (normals, roughness, lookup tables) is linear
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DiskColorSpace {
+    Srgb,
+    Linear,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -28,6 +52,8 @@ pub struct DiskImage {
     pub image_type: i32, // vk::ImageType pretending to be i32
     pub view_type: i32,  // vk::ImageViewType pretending to be i32
     pub format: i32,     // vk::Format pretending to be i32
+    pub color_space: DiskColorSpace,
+    pub generate_mipmaps: bool, // pixels only contain the top mip, the full chain is generated when loading
 
     #[serde(with = "resource_compression")]
     pub pixels: Vec<u8>,
@@ -40,9 +66,9 @@ pub struct DiskMaterialLayout {
 
 #[derive(Serialize, Deserialize)]
 pub struct DiskMaterialInstance {
-    pub material_layout: usize,
+    pub material_layout: MaterialLayoutHandle,
     pub material_instance_data: Vec<u8>, // arbitrary material data that goes into push constants
-    pub images: Vec<(usize, usize)>,     // (texture_id, sampler_id)
+    pub images: Vec<(ImageHandle, SamplerHandle)>,
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone)]
@@ -51,6 +77,7 @@ pub enum DiskVertexSemantic {
     Normal,
     Tangent,
     Interpolated,
+    SkyVisibility, // bent normal in xyz, encoded as n * 0.5 + 0.5, unoccluded sky fraction in w
 }
 
 #[derive(Serialize, Deserialize)]
@@ -64,48 +91,92 @@ pub struct DiskVertexAttribute {
 
 #[derive(Serialize, Deserialize)]
 pub struct DiskMaterial {
-    pub material_layout: usize,
+    pub material_layout: MaterialLayoutHandle,
 
     pub vertex_stride: u64,
     pub vertex_format: Vec<DiskVertexAttribute>,
 
     pub fragment_alpha_test: bool,
+    pub fragment_alpha_blend: bool,
     pub fragment_cull_flags: u32, // vk::CullModeFlags pretending to be u32
 
     pub shader_image_mapping: Vec<(String, String)>, // image_name, uv_channel_name
     pub shader_macro_definitions: Vec<(String, String)>, // name, value
+    pub shader_parameters: Vec<String>,              // vec4 material instance parameters in push constants
 }
 
-#[derive(Serialize, Deserialize)]
+// Serialized with the encoding applied, see buffer_encoding.rs
 pub struct DiskBuffer {
     pub stride: u64,
     pub usage_flags: u32, // vk::BufferUsageFlags pretending to be u32
-
-    #[serde(with = "resource_compression")]
+    pub encoding: DiskBufferEncoding,
     pub data: Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct DiskRenderMesh {
-    pub vertex_buffer: usize,
-    pub index_buffer: (i32, usize), // vk::IndexType pretending to be i32, buffer_id
+    pub vertex_buffer: BufferHandle,
+    pub index_buffer: (i32, BufferHandle), // vk::IndexType pretending to be i32
     pub index_count: usize,
+    pub first_index: usize,   // ranges in shared buffers, zero unless mesh geometry is packed
+    pub vertex_offset: usize, // in vertices of this mesh
+    pub occluder: Option<(BufferHandle, usize)>, // simplified 32 bit triangle list over the mesh vertices, index count
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct DiskRenderInstance {
-    pub mesh: usize,
-    pub material_instance: usize,
+    pub mesh: MeshHandle,
+    pub material_instance: MaterialInstanceHandle,
 
     pub total_instance_count: usize,
     pub total_draw_count: usize,
+    pub sort_key: u64, // material pipeline hash in the high half, material instance texture set hash in the low half
 }
 
+// Buckets and their instances are ordered by sort keys, see sort_keys.rs.
+// Buckets with the same pipeline hash in their sort keys can be drawn with the same pipeline.
 #[derive(Serialize, Deserialize)]
 pub struct DiskRenderBucket {
-    pub material: usize,
+    pub material: MaterialHandle,
     pub instances: Vec<DiskRenderInstance>,
-    pub instance_transform_buffer: usize,
+    pub instance_transform_buffer: BufferHandle,
+    pub sort_key: u64, // sort key of the first instance
+}
+
+// Authored glTF node, GPU instances of the node are referenced by their index in the bucket transform buffer
+#[derive(Serialize, Deserialize)]
+pub struct DiskSceneNode {
+    pub name: String, // empty for unnamed nodes
+    pub parent: Option<usize>,
+    pub local_transform: [f32; 16],     // column major
+    pub instances: Vec<(usize, usize)>, // bucket_id, instance_transform_id
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DiskCollisionShape {
+    TriangleMesh, // simplified triangle soup, concave meshes are kept as is
+    ConvexHull,
+}
+
+// Collision geometry for physics engines, not used by the renderer
+#[derive(Serialize, Deserialize)]
+pub struct DiskCollision {
+    pub mesh: MeshHandle,
+    pub shape: DiskCollisionShape,
+    pub positions: Vec<[f32; 3]>, // in mesh space
+    pub indices: Vec<u32>,        // triangle list with outward facing counter clockwise triangles for convex hulls
+}
+
+// UV scrolling and flipbook animation of a material instance, the animated UV scale and offset
+// are written into the material instance data parameter `parameter_id` every frame
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct DiskMaterialAnimation {
+    pub material_instance: MaterialInstanceHandle,
+    pub parameter_id: usize,
+    pub uv_scroll: [f32; 2],       // UV units per second
+    pub flipbook_size: [u32; 2],   // columns, rows, frames go left to right and top to bottom
+    pub flipbook_frame_count: u32, // up to columns * rows, 1 disables the flipbook
+    pub flipbook_frame_rate: f32,  // frames per second
 }
 
 #[derive(Serialize, Deserialize)]
@@ -118,6 +189,9 @@ pub struct DiskResourceBundle {
     pub material_instances: Vec<DiskMaterialInstance>,
     pub materials: Vec<DiskMaterial>,
     pub buckets: Vec<DiskRenderBucket>,
+    pub scene_nodes: Vec<DiskSceneNode>, // empty if the node hierarchy is not preserved
+    pub collision: Vec<DiskCollision>,   // empty unless collision export is enabled at import
+    pub material_animations: Vec<DiskMaterialAnimation>,
 }
 
 impl DiskResourceBundle {
@@ -193,3 +267,75 @@ impl DiskShaderStageBundle {
         }
     }
 }
+
+// Grid of ambient cube probes covering the scene, probes are placed at the cell corners.
+// Ambient cube faces (+X, -X, +Y, -Y, +Z, -Z) are stored as separate blocks of the probe grid
+// laid out next to each other along X in a single mip RGBA32F 3D image.
+#[derive(Serialize, Deserialize)]
+pub struct DiskIrradianceVolume {
+    pub volume_min: [f32; 3],
+    pub volume_max: [f32; 3],
+    pub probe_counts: [u32; 3],
+    pub ambient_cube_image: DiskImage,
+}
+
+impl DiskIrradianceVolume {
+    pub fn serialize_into<W>(&self, writer: W, _compression_level: u32) -> Result<(), ()>
+    where
+        W: std::io::Write,
+    {
+        match bincode::serialize_into(writer, self) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(()),
+        }
+    }
+
+    pub fn deserialize_from<R>(reader: R) -> Result<Self, ()>
+    where
+        R: std::io::Read,
+    {
+        match bincode::deserialize_from(reader) {
+            Ok(bundle) => Ok(bundle),
+            Err(_) => Err(()),
+        }
+    }
+}
+
+// Linearly transformed cosine tables for area lights, both are 64x64 single mip RGBA32F images
+// indexed by roughness along X and sqrt(1 - cos(theta)) along Y.
+// Matrix image stores (m00, m02, m20, m22) of the inverse transform normalized by m11,
+// amplitude image stores the BRDF norm and its Fresnel part.
+#[derive(Serialize, Deserialize)]
+pub struct DiskLtcTables {
+    pub ltc_matrix_image: DiskImage,
+    pub ltc_amplitude_image: DiskImage,
+}
+
+impl DiskLtcTables {
+    pub fn serialize_into<W>(&self, writer: W, _compression_level: u32) -> Result<(), ()>
+    where
+        W: std::io::Write,
+    {
+        match bincode::serialize_into(writer, self) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(()),
+        }
+    }
+
+    pub fn deserialize_from<R>(reader: R) -> Result<Self, ()>
+    where
+        R: std::io::Read,
+    {
+        match bincode::deserialize_from(reader) {
+            Ok(bundle) => Ok(bundle),
+            Err(_) => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_buffer_encoding;
+#[cfg(test)]
+mod test_resource_validation;
+#[cfg(test)]
+mod test_sort_keys;
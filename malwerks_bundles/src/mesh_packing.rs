@@ -0,0 +1,116 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::*;
+
+// vk::IndexType values, this crate doesn't depend on ash
+const INDEX_TYPE_UINT16: i32 = 0;
+const INDEX_TYPE_UINT32: i32 = 1;
+
+impl DiskResourceBundle {
+    pub fn has_packed_mesh_geometry(&self) -> bool {
+        self.meshes.windows(2).all(|pair| {
+            pair[0].vertex_buffer == pair[1].vertex_buffer && pair[0].index_buffer.1 == pair[1].index_buffer.1
+        })
+    }
+
+    // Moves geometry of all meshes into one vertex and one index buffer, meshes reference their ranges
+    // with first_index and vertex_offset. Vertex ranges are aligned to their stride, so vertex formats can differ.
+    // Index buffers are widened to 32 bits if any mesh needs them.
+    pub fn pack_mesh_geometry(&mut self) {
+        if self.has_packed_mesh_geometry() {
+            return;
+        }
+
+        let index_type = if self.meshes.iter().any(|mesh| mesh.index_buffer.0 == INDEX_TYPE_UINT32) {
+            INDEX_TYPE_UINT32
+        } else {
+            INDEX_TYPE_UINT16
+        };
+        let index_size = if index_type == INDEX_TYPE_UINT32 { 4 } else { 2 };
+
+        // Meshes can share buffers, every buffer is copied once
+        let mut packed_vertex_buffer = DiskBuffer {
+            stride: self.buffers[self.meshes[0].vertex_buffer.index()].stride,
+            usage_flags: 0,
+            encoding: DiskBufferEncoding::MeshoptVertex,
+            data: Vec::new(),
+        };
+        let mut packed_index_buffer = DiskBuffer {
+            stride: index_size,
+            usage_flags: 0,
+            encoding: DiskBufferEncoding::MeshoptIndex,
+            data: Vec::new(),
+        };
+        let mut vertex_buffer_offsets = vec![None; self.buffers.len()]; // in vertices
+        let mut index_buffer_offsets = vec![None; self.buffers.len()]; // in indices
+        for mesh in &mut self.meshes {
+            let vertex_buffer = &self.buffers[mesh.vertex_buffer.index()];
+            let vertex_offset = *vertex_buffer_offsets[mesh.vertex_buffer.index()].get_or_insert_with(|| {
+                let stride = vertex_buffer.stride as usize;
+                let aligned_size = packed_vertex_buffer.data.len().div_ceil(stride) * stride;
+                packed_vertex_buffer.data.resize(aligned_size, 0);
+                packed_vertex_buffer.data.extend_from_slice(&vertex_buffer.data);
+                packed_vertex_buffer.usage_flags |= vertex_buffer.usage_flags;
+                if packed_vertex_buffer.stride != vertex_buffer.stride {
+                    packed_vertex_buffer.stride = 1; // mixed vertex formats
+                }
+                aligned_size / stride
+            });
+
+            let index_buffer = &self.buffers[mesh.index_buffer.1.index()];
+            let first_index = *index_buffer_offsets[mesh.index_buffer.1.index()].get_or_insert_with(|| {
+                let first_index = packed_index_buffer.data.len() / index_size as usize;
+                if mesh.index_buffer.0 == index_type {
+                    packed_index_buffer.data.extend_from_slice(&index_buffer.data);
+                } else {
+                    for index in index_buffer.data.chunks_exact(2) {
+                        let index = u16::from_le_bytes([index[0], index[1]]) as u32;
+                        packed_index_buffer.data.extend_from_slice(&index.to_le_bytes());
+                    }
+                }
+                packed_index_buffer.usage_flags |= index_buffer.usage_flags;
+                first_index
+            });
+
+            mesh.vertex_offset += vertex_offset;
+            mesh.first_index += first_index;
+            mesh.index_buffer.0 = index_type;
+        }
+
+        // Old mesh buffers are dropped, remaining buffers keep their order and the packed ones go last
+        let mut buffer_remap = vec![None; self.buffers.len()];
+        let mut remaining_buffers = Vec::with_capacity(self.buffers.len());
+        for (buffer_id, buffer) in self.buffers.drain(..).enumerate() {
+            if vertex_buffer_offsets[buffer_id].is_none() && index_buffer_offsets[buffer_id].is_none() {
+                buffer_remap[buffer_id] = Some(remaining_buffers.len());
+                remaining_buffers.push(buffer);
+            }
+        }
+        let vertex_buffer = BufferHandle::new(remaining_buffers.len());
+        let index_buffer = BufferHandle::new(remaining_buffers.len() + 1);
+        remaining_buffers.push(packed_vertex_buffer);
+        remaining_buffers.push(packed_index_buffer);
+        self.buffers = remaining_buffers;
+
+        for mesh in &mut self.meshes {
+            mesh.vertex_buffer = vertex_buffer;
+            mesh.index_buffer.1 = index_buffer;
+        }
+        // Occluder indices are relative to the mesh vertex range, only their buffers move
+        for mesh in &mut self.meshes {
+            if let Some((occluder_buffer, _)) = &mut mesh.occluder {
+                let remapped_buffer = buffer_remap[occluder_buffer.index()];
+                *occluder_buffer =
+                    BufferHandle::new(remapped_buffer.expect("occluder index buffer is also used as mesh geometry"));
+            }
+        }
+        for bucket in &mut self.buckets {
+            let remapped_buffer = buffer_remap[bucket.instance_transform_buffer.index()];
+            bucket.instance_transform_buffer =
+                BufferHandle::new(remapped_buffer.expect("instance transform buffer is also used as mesh geometry"));
+        }
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Bundle fixtures shared by unit tests of this crate and of the crates that load bundles
+
+use ash::vk;
+
+use crate::*;
+
+pub fn create_test_buffer(stride: u64, usage_flags: vk::BufferUsageFlags, size: usize) -> DiskBuffer {
+    DiskBuffer {
+        stride,
+        usage_flags: usage_flags.as_raw(),
+        encoding: DiskBufferEncoding::Raw,
+        data: vec![0u8; size],
+    }
+}
+
+// Single triangle with one BC7 texture
+pub fn create_test_bundle() -> DiskResourceBundle {
+    DiskResourceBundle {
+        buffers: vec![
+            create_test_buffer(12, vk::BufferUsageFlags::VERTEX_BUFFER, 36),
+            create_test_buffer(2, vk::BufferUsageFlags::INDEX_BUFFER, 6),
+            create_test_buffer(64, vk::BufferUsageFlags::STORAGE_BUFFER, 64),
+        ],
+        meshes: vec![DiskRenderMesh {
+            vertex_buffer: BufferHandle::new(0),
+            index_buffer: (vk::IndexType::UINT16.as_raw(), BufferHandle::new(1)),
+            index_count: 3,
+            first_index: 0,
+            vertex_offset: 0,
+            occluder: None,
+        }],
+        images: vec![DiskImage {
+            width: 4,
+            height: 4,
+            depth: 1,
+            block_size: 16,
+            mipmap_count: 1,
+            layer_count: 1,
+            image_type: vk::ImageType::TYPE_2D.as_raw(),
+            view_type: vk::ImageViewType::TYPE_2D.as_raw(),
+            format: vk::Format::BC7_UNORM_BLOCK.as_raw(),
+            color_space: DiskColorSpace::Linear,
+            generate_mipmaps: false,
+            pixels: vec![0u8; 16],
+        }],
+        samplers: vec![DiskSampler {
+            mag_filter: vk::Filter::LINEAR.as_raw(),
+            min_filter: vk::Filter::LINEAR.as_raw(),
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR.as_raw(),
+            address_mode_u: vk::SamplerAddressMode::REPEAT.as_raw(),
+            address_mode_v: vk::SamplerAddressMode::REPEAT.as_raw(),
+            address_mode_w: vk::SamplerAddressMode::REPEAT.as_raw(),
+            max_anisotropy: 8.0,
+        }],
+        material_layouts: vec![DiskMaterialLayout { image_count: 1 }],
+        material_instances: vec![DiskMaterialInstance {
+            material_layout: MaterialLayoutHandle::new(0),
+            material_instance_data: vec![0u8; 64],
+            images: vec![(ImageHandle::new(0), SamplerHandle::new(0))],
+        }],
+        materials: vec![DiskMaterial {
+            material_layout: MaterialLayoutHandle::new(0),
+            vertex_stride: 12,
+            vertex_format: vec![DiskVertexAttribute {
+                attribute_name: String::from("position"),
+                attribute_semantic: DiskVertexSemantic::Position,
+                attribute_format: vk::Format::R32G32B32_SFLOAT.as_raw(),
+                attribute_location: 0,
+                attribute_offset: 0,
+            }],
+            fragment_alpha_test: false,
+            fragment_alpha_blend: false,
+            fragment_cull_flags: vk::CullModeFlags::BACK.as_raw(),
+            shader_image_mapping: vec![(String::from("base_color"), String::from("uv0"))],
+            shader_macro_definitions: Vec::new(),
+            shader_parameters: Vec::new(),
+        }],
+        buckets: vec![DiskRenderBucket {
+            material: MaterialHandle::new(0),
+            instances: vec![DiskRenderInstance {
+                mesh: MeshHandle::new(0),
+                material_instance: MaterialInstanceHandle::new(0),
+                total_instance_count: 1,
+                total_draw_count: 1,
+                sort_key: 0,
+            }],
+            instance_transform_buffer: BufferHandle::new(2),
+            sort_key: 0,
+        }],
+        scene_nodes: Vec::new(),
+        collision: Vec::new(),
+        material_animations: Vec::new(),
+    }
+}
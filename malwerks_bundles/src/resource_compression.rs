@@ -93,27 +93,45 @@ impl<'de> serde::de::Visitor<'de> for CowVisitor {
 
 impl CompressibleStorage for Vec<u8> {
     fn compress(&self) -> Vec<u8> {
-        use std::io::Write;
-
-        let mut encoder = lz4::EncoderBuilder::new()
-            .level(9)
-            .build(Vec::with_capacity(self.capacity()))
-            .expect("failed to create lz4 encoder");
-        let _ = encoder.write(self.as_slice()).expect("failed to write lz4 stream");
-        let (output, result) = encoder.finish();
-        result.expect("failed to compress lz4 data");
-        output
+        compress_bytes(self.as_slice())
     }
 
     fn decompress(bytes: &[u8]) -> Self {
-        use std::io::Read;
+        decompress_bytes(bytes)
+    }
+}
 
-        let mut target = Vec::with_capacity(bytes.len());
+impl CompressibleStorage for std::borrow::Cow<'_, [u8]> {
+    fn compress(&self) -> Vec<u8> {
+        compress_bytes(self)
+    }
 
-        let mut decoder = lz4::Decoder::new(bytes).expect("failed to create lz4 decoder");
-        decoder.read_to_end(&mut target).expect("failed to read lz4 data");
-        let (_, result) = decoder.finish();
-        result.expect("failed to decompress lz4 data");
-        target
+    fn decompress(bytes: &[u8]) -> Self {
+        std::borrow::Cow::Owned(decompress_bytes(bytes))
     }
 }
+
+fn compress_bytes(bytes: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut encoder = lz4::EncoderBuilder::new()
+        .level(9)
+        .build(Vec::with_capacity(bytes.len()))
+        .expect("failed to create lz4 encoder");
+    let _ = encoder.write(bytes).expect("failed to write lz4 stream");
+    let (output, result) = encoder.finish();
+    result.expect("failed to compress lz4 data");
+    output
+}
+
+fn decompress_bytes(bytes: &[u8]) -> Vec<u8> {
+    use std::io::Read;
+
+    let mut target = Vec::with_capacity(bytes.len());
+
+    let mut decoder = lz4::Decoder::new(bytes).expect("failed to create lz4 decoder");
+    decoder.read_to_end(&mut target).expect("failed to read lz4 data");
+    let (_, result) = decoder.finish();
+    result.expect("failed to decompress lz4 data");
+    target
+}
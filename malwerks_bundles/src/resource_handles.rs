@@ -0,0 +1,33 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use serde::{Deserialize, Serialize};
+
+// Handles serialize as plain indices, so bundles stay compatible with the untyped format
+macro_rules! define_resource_handle {
+    ($handle: ident) => {
+        #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[serde(transparent)]
+        pub struct $handle(usize);
+
+        impl $handle {
+            pub fn new(index: usize) -> Self {
+                Self(index)
+            }
+
+            pub fn index(self) -> usize {
+                self.0
+            }
+        }
+    };
+}
+
+define_resource_handle!(BufferHandle);
+define_resource_handle!(ImageHandle);
+define_resource_handle!(SamplerHandle);
+define_resource_handle!(MeshHandle);
+define_resource_handle!(MaterialHandle);
+define_resource_handle!(MaterialLayoutHandle);
+define_resource_handle!(MaterialInstanceHandle);
@@ -0,0 +1,330 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::*;
+
+impl DiskResourceBundle {
+    // Checks that all cross references are in range and that buffer and image data matches their layout,
+    // returns one message per problem found
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        for (buffer_id, buffer) in self.buffers.iter().enumerate() {
+            if buffer.stride == 0 {
+                errors.push(format!("buffer {}: stride is zero", buffer_id));
+            } else if !(buffer.data.len() as u64).is_multiple_of(buffer.stride) {
+                errors.push(format!(
+                    "buffer {}: data length {} is not a multiple of stride {}",
+                    buffer_id,
+                    buffer.data.len(),
+                    buffer.stride
+                ));
+            }
+        }
+
+        for (mesh_id, mesh) in self.meshes.iter().enumerate() {
+            let context = format!("mesh {}", mesh_id);
+            self.validate_buffer(&context, "vertex buffer", mesh.vertex_buffer, &mut errors);
+            if self.validate_buffer(&context, "index buffer", mesh.index_buffer.1, &mut errors) {
+                let index_buffer = &self.buffers[mesh.index_buffer.1.index()];
+                let required_size = (mesh.first_index + mesh.index_count) as u64 * index_buffer.stride;
+                if required_size > index_buffer.data.len() as u64 {
+                    errors.push(format!(
+                        "{}: index count {} exceeds index buffer {} size",
+                        context,
+                        mesh.index_count,
+                        mesh.index_buffer.1.index()
+                    ));
+                }
+            }
+            if let Some((occluder_buffer, occluder_index_count)) = mesh.occluder {
+                if self.validate_buffer(&context, "occluder index buffer", occluder_buffer, &mut errors) {
+                    let index_buffer = &self.buffers[occluder_buffer.index()];
+                    if index_buffer.stride != 4 {
+                        errors.push(format!(
+                            "{}: occluder index buffer {} is not 32 bit",
+                            context,
+                            occluder_buffer.index()
+                        ));
+                    } else if occluder_index_count as u64 * 4 > index_buffer.data.len() as u64 {
+                        errors.push(format!(
+                            "{}: occluder index count {} exceeds index buffer {} size",
+                            context,
+                            occluder_index_count,
+                            occluder_buffer.index()
+                        ));
+                    }
+                }
+            }
+        }
+
+        for (image_id, image) in self.images.iter().enumerate() {
+            validate_image(image_id, image, &mut errors);
+        }
+
+        for (sampler_id, sampler) in self.samplers.iter().enumerate() {
+            if sampler.max_anisotropy.is_nan() || sampler.max_anisotropy < 1.0 {
+                errors.push(format!(
+                    "sampler {}: max anisotropy {} is less than 1.0",
+                    sampler_id, sampler.max_anisotropy
+                ));
+            }
+        }
+
+        for (material_instance_id, material_instance) in self.material_instances.iter().enumerate() {
+            let context = format!("material instance {}", material_instance_id);
+            if self.validate_material_layout(&context, material_instance.material_layout, &mut errors) {
+                let image_count = self.material_layouts[material_instance.material_layout.index()].image_count;
+                if material_instance.images.len() != image_count {
+                    errors.push(format!(
+                        "{}: has {} images, material layout {} expects {}",
+                        context,
+                        material_instance.images.len(),
+                        material_instance.material_layout.index(),
+                        image_count
+                    ));
+                }
+            }
+            for (image, sampler) in &material_instance.images {
+                if image.index() >= self.images.len() {
+                    errors.push(format!("{}: image {} is out of range", context, image.index()));
+                }
+                if sampler.index() >= self.samplers.len() {
+                    errors.push(format!("{}: sampler {} is out of range", context, sampler.index()));
+                }
+            }
+        }
+
+        for (material_id, material) in self.materials.iter().enumerate() {
+            let context = format!("material {}", material_id);
+            self.validate_material_layout(&context, material.material_layout, &mut errors);
+        }
+
+        for (bucket_id, bucket) in self.buckets.iter().enumerate() {
+            let context = format!("bucket {}", bucket_id);
+            let material = self.materials.get(bucket.material.index());
+            if material.is_none() {
+                errors.push(format!(
+                    "{}: material {} is out of range",
+                    context,
+                    bucket.material.index()
+                ));
+            }
+
+            for (instance_id, instance) in bucket.instances.iter().enumerate() {
+                if instance.mesh.index() >= self.meshes.len() {
+                    errors.push(format!(
+                        "{} instance {}: mesh {} is out of range",
+                        context,
+                        instance_id,
+                        instance.mesh.index()
+                    ));
+                }
+                match self.material_instances.get(instance.material_instance.index()) {
+                    Some(material_instance) => {
+                        if material
+                            .is_some_and(|material| material.material_layout != material_instance.material_layout)
+                        {
+                            errors.push(format!(
+                                "{} instance {}: material instance {} layout doesn't match bucket material layout",
+                                context,
+                                instance_id,
+                                instance.material_instance.index()
+                            ));
+                        }
+                    }
+                    None => errors.push(format!(
+                        "{} instance {}: material instance {} is out of range",
+                        context,
+                        instance_id,
+                        instance.material_instance.index()
+                    )),
+                }
+            }
+
+            if self.validate_buffer(
+                &context,
+                "instance transform buffer",
+                bucket.instance_transform_buffer,
+                &mut errors,
+            ) {
+                let transform_count: usize = bucket
+                    .instances
+                    .iter()
+                    .map(|instance| instance.total_instance_count)
+                    .sum();
+                let required_size = transform_count * std::mem::size_of::<[f32; 16]>();
+                let buffer_size = self.buffers[bucket.instance_transform_buffer.index()].data.len();
+                if required_size > buffer_size {
+                    errors.push(format!(
+                        "{}: {} instance transforms don't fit into {} bytes",
+                        context, transform_count, buffer_size
+                    ));
+                }
+            }
+        }
+
+        for (node_id, node) in self.scene_nodes.iter().enumerate() {
+            if let Some(parent) = node.parent {
+                if parent >= self.scene_nodes.len() {
+                    errors.push(format!("scene node {}: parent {} is out of range", node_id, parent));
+                }
+            }
+            for (bucket_id, transform_id) in &node.instances {
+                match self.buckets.get(*bucket_id) {
+                    Some(bucket) => {
+                        let transform_count: usize = bucket
+                            .instances
+                            .iter()
+                            .map(|instance| instance.total_instance_count)
+                            .sum();
+                        if *transform_id >= transform_count {
+                            errors.push(format!(
+                                "scene node {}: instance transform {} is out of range in bucket {}",
+                                node_id, transform_id, bucket_id
+                            ));
+                        }
+                    }
+                    None => errors.push(format!("scene node {}: bucket {} is out of range", node_id, bucket_id)),
+                }
+            }
+        }
+
+        for (collision_id, collision) in self.collision.iter().enumerate() {
+            if collision.mesh.index() >= self.meshes.len() {
+                errors.push(format!(
+                    "collision {}: mesh {} is out of range",
+                    collision_id,
+                    collision.mesh.index()
+                ));
+            }
+            if collision.indices.len() % 3 != 0 {
+                errors.push(format!(
+                    "collision {}: index count {} is not a triangle list",
+                    collision_id,
+                    collision.indices.len()
+                ));
+            }
+            if collision
+                .indices
+                .iter()
+                .any(|index| *index as usize >= collision.positions.len())
+            {
+                errors.push(format!("collision {}: index is out of range", collision_id));
+            }
+        }
+
+        for (animation_id, animation) in self.material_animations.iter().enumerate() {
+            let context = format!("material animation {}", animation_id);
+            match self.material_instances.get(animation.material_instance.index()) {
+                Some(material_instance) => {
+                    if (animation.parameter_id + 1) * 16 > material_instance.material_instance_data.len() {
+                        errors.push(format!(
+                            "{}: parameter {} is out of range",
+                            context, animation.parameter_id
+                        ));
+                    }
+                }
+                None => errors.push(format!(
+                    "{}: material instance {} is out of range",
+                    context,
+                    animation.material_instance.index()
+                )),
+            }
+            let [columns, rows] = animation.flipbook_size;
+            if animation.flipbook_frame_count == 0 || animation.flipbook_frame_count > columns * rows {
+                errors.push(format!(
+                    "{}: {} flipbook frames don't fit into {}x{} grid",
+                    context, animation.flipbook_frame_count, columns, rows
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn validate_buffer(&self, context: &str, name: &str, buffer: BufferHandle, errors: &mut Vec<String>) -> bool {
+        let is_valid = buffer.index() < self.buffers.len();
+        if !is_valid {
+            errors.push(format!("{}: {} {} is out of range", context, name, buffer.index()));
+        }
+        is_valid
+    }
+
+    fn validate_material_layout(
+        &self,
+        context: &str,
+        material_layout: MaterialLayoutHandle,
+        errors: &mut Vec<String>,
+    ) -> bool {
+        let is_valid = material_layout.index() < self.material_layouts.len();
+        if !is_valid {
+            errors.push(format!(
+                "{}: material layout {} is out of range",
+                context,
+                material_layout.index()
+            ));
+        }
+        is_valid
+    }
+}
+
+// Pixel data is laid out the same way the upload batch reads it: layers, then mips, 4x4 blocks per mip
+fn validate_image(image_id: usize, image: &DiskImage, errors: &mut Vec<String>) {
+    if image.width == 0 || image.height == 0 || image.depth == 0 {
+        errors.push(format!(
+            "image {}: size {}x{}x{} is empty",
+            image_id, image.width, image.height, image.depth
+        ));
+        return;
+    }
+    if image.block_size == 0 || image.mipmap_count == 0 || image.layer_count == 0 {
+        errors.push(format!(
+            "image {}: block size {}, {} mips and {} layers must be non zero",
+            image_id, image.block_size, image.mipmap_count, image.layer_count
+        ));
+        return;
+    }
+
+    let max_dimension = image.width.max(image.height).max(image.depth);
+    let max_mipmap_count = (32 - max_dimension.leading_zeros()) as usize;
+    if image.mipmap_count > max_mipmap_count {
+        errors.push(format!(
+            "image {}: {} mips exceed {} mips of a full chain",
+            image_id, image.mipmap_count, max_mipmap_count
+        ));
+        return;
+    }
+    if image.generate_mipmaps && image.mipmap_count != 1 {
+        errors.push(format!(
+            "image {}: {} mips are stored but the rest of the chain is generated at runtime",
+            image_id, image.mipmap_count
+        ));
+    }
+
+    let mut required_size = 0;
+    for mip in 0..image.mipmap_count {
+        let mip_width = (image.width >> mip).max(1) as usize;
+        let mip_height = (image.height >> mip).max(1) as usize;
+        let mip_depth = (image.depth >> mip).max(1) as usize;
+        let row_pitch = image.block_size * mip_width.div_ceil(4).max(1);
+        required_size += mip_depth * row_pitch * mip_height.div_ceil(4).max(1);
+    }
+    required_size *= image.layer_count;
+    if image.pixels.len() < required_size {
+        errors.push(format!(
+            "image {}: {} bytes of pixel data, {} mips and {} layers require {}",
+            image_id,
+            image.pixels.len(),
+            image.mipmap_count,
+            image.layer_count,
+            required_size
+        ));
+    }
+}
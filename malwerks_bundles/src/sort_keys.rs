@@ -0,0 +1,162 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::*;
+
+// Pipeline hash goes into the high half, so sorting by the key groups draws by pipeline first
+pub fn make_sort_key(pipeline_hash: u32, texture_set_hash: u32) -> u64 {
+    ((pipeline_hash as u64) << 32) | texture_set_hash as u64
+}
+
+pub fn get_sort_key_pipeline_hash(sort_key: u64) -> u32 {
+    (sort_key >> 32) as u32
+}
+
+impl DiskMaterial {
+    // Everything that goes into the material pipeline and its shaders, equal hashes can share a pipeline
+    pub fn compute_pipeline_hash(&self) -> u32 {
+        let mut hasher = StableHasher::new();
+        hasher.write_u64(self.material_layout.index() as u64);
+        hasher.write_u64(self.vertex_stride);
+        hasher.write_u64(self.vertex_format.len() as u64);
+        for attribute in &self.vertex_format {
+            hasher.write_str(&attribute.attribute_name);
+            hasher.write_u64(attribute.attribute_semantic as u64);
+            hasher.write_u64(attribute.attribute_format as u64);
+            hasher.write_u64(attribute.attribute_location as u64);
+            hasher.write_u64(attribute.attribute_offset as u64);
+        }
+        hasher.write_u64(self.fragment_alpha_test as u64);
+        hasher.write_u64(self.fragment_alpha_blend as u64);
+        hasher.write_u64(self.fragment_cull_flags as u64);
+        hasher.write_u64(self.shader_image_mapping.len() as u64);
+        for (image_name, uv_channel_name) in &self.shader_image_mapping {
+            hasher.write_str(image_name);
+            hasher.write_str(uv_channel_name);
+        }
+        hasher.write_u64(self.shader_macro_definitions.len() as u64);
+        for (name, value) in &self.shader_macro_definitions {
+            hasher.write_str(name);
+            hasher.write_str(value);
+        }
+        hasher.write_u64(self.shader_parameters.len() as u64);
+        for parameter in &self.shader_parameters {
+            hasher.write_str(parameter);
+        }
+        hasher.finish_u32()
+    }
+}
+
+impl DiskMaterialInstance {
+    // Images and samplers bound by the material instance, push constant data doesn't change bindings
+    pub fn compute_texture_set_hash(&self) -> u32 {
+        let mut hasher = StableHasher::new();
+        hasher.write_u64(self.material_layout.index() as u64);
+        for (image, sampler) in &self.images {
+            hasher.write_u64(image.index() as u64);
+            hasher.write_u64(sampler.index() as u64);
+        }
+        hasher.finish_u32()
+    }
+}
+
+impl DiskResourceBundle {
+    // Stores sort keys of all buckets and instances and orders both by them, so draws are recorded in the same
+    // order on every import. Instance transforms and scene node references are moved along with the instances.
+    // Has to be called again after materials or material instances change.
+    pub fn sort_render_buckets(&mut self) {
+        let pipeline_hashes: Vec<u32> = self
+            .materials
+            .iter()
+            .map(|material| material.compute_pipeline_hash())
+            .collect();
+        let texture_set_hashes: Vec<u32> = self
+            .material_instances
+            .iter()
+            .map(|material_instance| material_instance.compute_texture_set_hash())
+            .collect();
+
+        // Transform ids of every bucket before sorting map to the new ones
+        let mut transform_remaps = Vec::with_capacity(self.buckets.len());
+        for bucket in &mut self.buckets {
+            let pipeline_hash = pipeline_hashes[bucket.material.index()];
+            let mut first_transforms = Vec::with_capacity(bucket.instances.len());
+            let mut transform_count = 0;
+            for instance in &mut bucket.instances {
+                instance.sort_key =
+                    make_sort_key(pipeline_hash, texture_set_hashes[instance.material_instance.index()]);
+                first_transforms.push(transform_count);
+                transform_count += instance.total_instance_count;
+            }
+
+            let mut instance_order: Vec<usize> = (0..bucket.instances.len()).collect();
+            instance_order.sort_by_key(|instance_id| {
+                let instance = &bucket.instances[*instance_id];
+                (
+                    instance.sort_key,
+                    instance.mesh.index(),
+                    instance.material_instance.index(),
+                )
+            });
+
+            let transform_buffer = &mut self.buffers[bucket.instance_transform_buffer.index()];
+            assert!(
+                matches!(transform_buffer.encoding, DiskBufferEncoding::Raw),
+                "instance transform buffer is encoded"
+            );
+            let stride = transform_buffer.stride as usize;
+            let mut transform_data = Vec::with_capacity(transform_buffer.data.len());
+            let mut transform_remap = vec![0; transform_count];
+            for instance_id in &instance_order {
+                let first_transform = first_transforms[*instance_id];
+                let instance_count = bucket.instances[*instance_id].total_instance_count;
+                let new_first_transform = transform_data.len() / stride;
+                for (offset, new_transform) in transform_remap[first_transform..first_transform + instance_count]
+                    .iter_mut()
+                    .enumerate()
+                {
+                    *new_transform = new_first_transform + offset;
+                }
+                transform_data.extend_from_slice(
+                    &transform_buffer.data[first_transform * stride..(first_transform + instance_count) * stride],
+                );
+            }
+            transform_buffer.data = transform_data;
+            transform_remaps.push(transform_remap);
+
+            let mut instances: Vec<Option<DiskRenderInstance>> = bucket.instances.drain(..).map(Some).collect();
+            bucket.instances = instance_order
+                .iter()
+                .map(|instance_id| instances[*instance_id].take().unwrap())
+                .collect();
+            bucket.sort_key = match bucket.instances.first() {
+                Some(instance) => instance.sort_key,
+                None => make_sort_key(pipeline_hash, 0),
+            };
+        }
+
+        let mut bucket_order: Vec<usize> = (0..self.buckets.len()).collect();
+        bucket_order.sort_by_key(|bucket_id| {
+            let bucket = &self.buckets[*bucket_id];
+            (bucket.sort_key, bucket.material.index())
+        });
+        let mut bucket_remap = vec![0; self.buckets.len()];
+        for (new_bucket_id, bucket_id) in bucket_order.iter().enumerate() {
+            bucket_remap[*bucket_id] = new_bucket_id;
+        }
+        for scene_node in &mut self.scene_nodes {
+            for (bucket_id, transform_id) in &mut scene_node.instances {
+                *transform_id = transform_remaps[*bucket_id][*transform_id];
+                *bucket_id = bucket_remap[*bucket_id];
+            }
+        }
+
+        let mut buckets: Vec<Option<DiskRenderBucket>> = self.buckets.drain(..).map(Some).collect();
+        self.buckets = bucket_order
+            .iter()
+            .map(|bucket_id| buckets[*bucket_id].take().unwrap())
+            .collect();
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::*;
+
+// vk::IndexType and vk::Format values, this crate doesn't depend on ash
+const INDEX_TYPE_UINT32: i32 = 1;
+const FORMAT_R32G32B32_SFLOAT: i32 = 106;
+
+impl DiskResourceBundle {
+    // Replaces the scene with a cube shaped grid of instance_count instances of one mesh. Material instances that
+    // were used with the mesh material in the original scene alternate between grid cells.
+    // Materials and geometry are kept as is, so shaders compiled for the original bundle still match.
+    pub fn generate_stress_scene(&mut self, mesh: MeshHandle, instance_count: usize) {
+        assert!(instance_count > 0, "stress scene needs at least one instance");
+
+        let material = self
+            .buckets
+            .iter()
+            .find(|bucket| bucket.instances.iter().any(|instance| instance.mesh == mesh))
+            .map(|bucket| bucket.material)
+            .expect("stress scene mesh is not used by any bucket");
+        let mut material_instances = Vec::new();
+        for bucket in self.buckets.iter().filter(|bucket| bucket.material == material) {
+            for instance in &bucket.instances {
+                if !material_instances.contains(&instance.material_instance) {
+                    material_instances.push(instance.material_instance);
+                }
+            }
+        }
+
+        // Cells are a bit larger than the mesh, so instances don't overlap
+        let cell_size = (self.get_mesh_extent(mesh, material) * 1.5).max(1.0);
+        let grid_size = (1..).find(|size| size * size * size >= instance_count).unwrap();
+        let grid_offset = (grid_size - 1) as f32 * 0.5;
+
+        // Transforms of one DiskRenderInstance have to be contiguous
+        let mut instance_transform_data = Vec::with_capacity(instance_count * std::mem::size_of::<[f32; 16]>());
+        let mut instances = Vec::with_capacity(material_instances.len());
+        for (material_instance_id, material_instance) in material_instances.iter().enumerate() {
+            let mut total_instance_count = 0;
+            for cell in (material_instance_id..instance_count).step_by(material_instances.len()) {
+                let x = (cell % grid_size) as f32 - grid_offset;
+                let y = (cell / grid_size % grid_size) as f32 - grid_offset;
+                let z = (cell / (grid_size * grid_size)) as f32 - grid_offset;
+
+                let mut transform = [0.0f32; 16];
+                transform[0] = 1.0;
+                transform[5] = 1.0;
+                transform[10] = 1.0;
+                transform[12] = x * cell_size;
+                transform[13] = y * cell_size;
+                transform[14] = z * cell_size;
+                transform[15] = 1.0;
+                for value in &transform {
+                    instance_transform_data.extend_from_slice(&value.to_le_bytes());
+                }
+                total_instance_count += 1;
+            }
+            if total_instance_count > 0 {
+                instances.push(DiskRenderInstance {
+                    mesh,
+                    material_instance: *material_instance,
+                    total_instance_count,
+                    total_draw_count: total_instance_count,
+                    sort_key: 0,
+                });
+            }
+        }
+
+        // Transform buffers of the original scene are dropped, remaining buffers keep their order
+        let usage_flags = self.buffers[self.buckets[0].instance_transform_buffer.index()].usage_flags;
+        let mut buffer_remap = vec![None; self.buffers.len()];
+        let mut remaining_buffers = Vec::with_capacity(self.buffers.len());
+        for (buffer_id, buffer) in self.buffers.drain(..).enumerate() {
+            if !self
+                .buckets
+                .iter()
+                .any(|bucket| bucket.instance_transform_buffer.index() == buffer_id)
+            {
+                buffer_remap[buffer_id] = Some(remaining_buffers.len());
+                remaining_buffers.push(buffer);
+            }
+        }
+        let instance_transform_buffer = BufferHandle::new(remaining_buffers.len());
+        remaining_buffers.push(DiskBuffer {
+            stride: std::mem::size_of::<[f32; 16]>() as u64,
+            usage_flags,
+            encoding: DiskBufferEncoding::Raw,
+            data: instance_transform_data,
+        });
+        self.buffers = remaining_buffers;
+
+        let remap_buffer = |buffer: BufferHandle| {
+            BufferHandle::new(
+                buffer_remap[buffer.index()].expect("instance transform buffer is also used as mesh geometry"),
+            )
+        };
+        for mesh in &mut self.meshes {
+            mesh.vertex_buffer = remap_buffer(mesh.vertex_buffer);
+            mesh.index_buffer.1 = remap_buffer(mesh.index_buffer.1);
+            if let Some((occluder_buffer, _)) = &mut mesh.occluder {
+                *occluder_buffer = remap_buffer(*occluder_buffer);
+            }
+        }
+
+        self.buckets = vec![DiskRenderBucket {
+            material,
+            instances,
+            instance_transform_buffer,
+            sort_key: 0,
+        }];
+        self.scene_nodes.clear();
+        self.sort_render_buckets();
+    }
+
+    // Largest side of the mesh bounding box
+    fn get_mesh_extent(&self, mesh: MeshHandle, material: MaterialHandle) -> f32 {
+        let mesh = &self.meshes[mesh.index()];
+        let material = &self.materials[material.index()];
+        let position = material
+            .vertex_format
+            .iter()
+            .find(|attribute| matches!(attribute.attribute_semantic, DiskVertexSemantic::Position))
+            .expect("stress scene mesh has no positions");
+        assert_eq!(
+            position.attribute_format, FORMAT_R32G32B32_SFLOAT,
+            "stress scene mesh positions have to be 32 bit floats"
+        );
+
+        let vertex_data = &self.buffers[mesh.vertex_buffer.index()].data;
+        let index_data = &self.buffers[mesh.index_buffer.1.index()].data;
+        let index_size = if mesh.index_buffer.0 == INDEX_TYPE_UINT32 { 4 } else { 2 };
+        let mut bounds_min = [f32::MAX; 3];
+        let mut bounds_max = [f32::MIN; 3];
+        for index in
+            index_data[mesh.first_index * index_size..][..mesh.index_count * index_size].chunks_exact(index_size)
+        {
+            let index = if index_size == 4 {
+                u32::from_le_bytes([index[0], index[1], index[2], index[3]]) as usize
+            } else {
+                u16::from_le_bytes([index[0], index[1]]) as usize
+            };
+            let vertex_offset =
+                (mesh.vertex_offset + index) * material.vertex_stride as usize + position.attribute_offset;
+            for (axis, value) in vertex_data[vertex_offset..][..12].chunks_exact(4).enumerate() {
+                let value = f32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+                bounds_min[axis] = bounds_min[axis].min(value);
+                bounds_max[axis] = bounds_max[axis].max(value);
+            }
+        }
+
+        (0..3)
+            .map(|axis| bounds_max[axis] - bounds_min[axis])
+            .fold(0.0, f32::max)
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::*;
+
+// Bundle with a single buffer, bincode puts its fields right after the buffer count
+fn serialize_buffer_bundle(buffer: DiskBuffer) -> Vec<u8> {
+    let bundle = DiskResourceBundle {
+        buffers: vec![buffer],
+        meshes: Vec::new(),
+        images: Vec::new(),
+        samplers: Vec::new(),
+        material_layouts: Vec::new(),
+        material_instances: Vec::new(),
+        materials: Vec::new(),
+        buckets: Vec::new(),
+        scene_nodes: Vec::new(),
+        collision: Vec::new(),
+        material_animations: Vec::new(),
+    };
+    let mut file = Vec::new();
+    bundle.serialize_into(&mut file, 9).unwrap();
+    file
+}
+
+const ENCODING_OFFSET: usize = 20; // buffer count, stride and usage flags
+const DECODED_SIZE_OFFSET: usize = 24;
+
+#[test]
+fn test_buffer_encoding_round_trip() {
+    let vertex_data: Vec<u8> = (0..48).collect();
+    let file = serialize_buffer_bundle(DiskBuffer {
+        stride: 16,
+        usage_flags: 0,
+        encoding: DiskBufferEncoding::MeshoptVertex,
+        data: vertex_data.clone(),
+    });
+    let bundle = DiskResourceBundle::deserialize_from(file.as_slice()).unwrap();
+    assert_eq!(bundle.buffers[0].data, vertex_data);
+}
+
+#[test]
+fn test_buffer_encoding_corrupted_data() {
+    // Raw garbage tagged as meshopt vertices doesn't have a valid codec header
+    let mut file = serialize_buffer_bundle(DiskBuffer {
+        stride: 16,
+        usage_flags: 0,
+        encoding: DiskBufferEncoding::Raw,
+        data: vec![0x5a; 48],
+    });
+    file[ENCODING_OFFSET..ENCODING_OFFSET + 4].copy_from_slice(&1u32.to_le_bytes());
+    assert!(DiskResourceBundle::deserialize_from(file.as_slice()).is_err());
+
+    // Same for indices
+    file[ENCODING_OFFSET..ENCODING_OFFSET + 4].copy_from_slice(&2u32.to_le_bytes());
+    file[8..16].copy_from_slice(&4u64.to_le_bytes());
+    assert!(DiskResourceBundle::deserialize_from(file.as_slice()).is_err());
+}
+
+#[test]
+fn test_buffer_encoding_truncated_data() {
+    // Encoded stream is shorter than the stored size claims
+    let mut file = serialize_buffer_bundle(DiskBuffer {
+        stride: 16,
+        usage_flags: 0,
+        encoding: DiskBufferEncoding::MeshoptVertex,
+        data: (0..48).collect(),
+    });
+    file[DECODED_SIZE_OFFSET..DECODED_SIZE_OFFSET + 8].copy_from_slice(&4800u64.to_le_bytes());
+    assert!(DiskResourceBundle::deserialize_from(file.as_slice()).is_err());
+
+    // Raw buffers have to match the stored size as well
+    let mut file = serialize_buffer_bundle(DiskBuffer {
+        stride: 16,
+        usage_flags: 0,
+        encoding: DiskBufferEncoding::Raw,
+        data: (0..48).collect(),
+    });
+    file[DECODED_SIZE_OFFSET..DECODED_SIZE_OFFSET + 8].copy_from_slice(&64u64.to_le_bytes());
+    assert!(DiskResourceBundle::deserialize_from(file.as_slice()).is_err());
+}
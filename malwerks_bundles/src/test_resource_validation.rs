@@ -0,0 +1,58 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use ash::vk;
+
+use crate::*;
+
+#[test]
+fn test_resource_validation_occluder_meshes() {
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle
+        .buffers
+        .push(create_test_buffer(2, vk::BufferUsageFlags::INDEX_BUFFER, 6));
+    disk_bundle.meshes[0].occluder = Some((BufferHandle::new(3), 3));
+    assert_eq!(disk_bundle.validate().unwrap_err().len(), 1);
+
+    disk_bundle.buffers[3] = create_test_buffer(4, vk::BufferUsageFlags::INDEX_BUFFER, 12);
+    assert!(disk_bundle.validate().is_ok());
+
+    // Occluder buffers are kept when packing, they go before the packed buffers
+    disk_bundle
+        .buffers
+        .push(create_test_buffer(12, vk::BufferUsageFlags::VERTEX_BUFFER, 36));
+    disk_bundle
+        .buffers
+        .push(create_test_buffer(2, vk::BufferUsageFlags::INDEX_BUFFER, 6));
+    disk_bundle.meshes.push(DiskRenderMesh {
+        vertex_buffer: BufferHandle::new(4),
+        index_buffer: (vk::IndexType::UINT16.as_raw(), BufferHandle::new(5)),
+        index_count: 3,
+        first_index: 0,
+        vertex_offset: 0,
+        occluder: None,
+    });
+    disk_bundle.pack_mesh_geometry();
+    assert!(disk_bundle.validate().is_ok());
+    assert_eq!(disk_bundle.buffers.len(), 4);
+    assert_eq!(disk_bundle.meshes[0].occluder, Some((BufferHandle::new(1), 3)));
+    assert_eq!(disk_bundle.buckets[0].instance_transform_buffer, BufferHandle::new(0));
+}
+
+#[test]
+fn test_resource_validation_collision() {
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle.collision.push(DiskCollision {
+        mesh: MeshHandle::new(0),
+        shape: DiskCollisionShape::TriangleMesh,
+        positions: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        indices: vec![0, 1, 2],
+    });
+    assert!(disk_bundle.validate().is_ok());
+
+    disk_bundle.collision[0].mesh = MeshHandle::new(1);
+    disk_bundle.collision[0].indices = vec![0, 1, 3, 2];
+    assert_eq!(disk_bundle.validate().unwrap_err().len(), 3);
+}
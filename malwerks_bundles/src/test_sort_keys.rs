@@ -0,0 +1,100 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use ash::vk;
+
+use crate::*;
+
+#[test]
+fn test_sort_keys_render_buckets() {
+    // Second material only differs in culling, second material instance only in the sampler
+    let mut disk_bundle = create_test_bundle();
+    let mut second_material = create_test_bundle().materials.remove(0);
+    second_material.fragment_cull_flags = vk::CullModeFlags::NONE.as_raw();
+    disk_bundle.materials.push(second_material);
+    disk_bundle.samplers.push(disk_bundle.samplers[0]);
+    disk_bundle.material_instances.push(DiskMaterialInstance {
+        material_layout: MaterialLayoutHandle::new(0),
+        material_instance_data: vec![0u8; 64],
+        images: vec![(ImageHandle::new(0), SamplerHandle::new(1))],
+    });
+
+    // Every transform is tagged with its original bucket and transform id in the first byte
+    let create_transform_buffer = |bucket_id: u8, transform_count: usize| {
+        let mut buffer = create_test_buffer(64, vk::BufferUsageFlags::STORAGE_BUFFER, 64 * transform_count);
+        for transform_id in 0..transform_count {
+            buffer.data[transform_id * 64] = bucket_id * 16 + transform_id as u8;
+        }
+        buffer
+    };
+    disk_bundle.buffers[2] = create_transform_buffer(0, 3);
+    disk_bundle.buffers.push(create_transform_buffer(1, 1));
+    disk_bundle.buckets[0].instances[0].material_instance = MaterialInstanceHandle::new(1);
+    disk_bundle.buckets[0].instances.push(DiskRenderInstance {
+        mesh: MeshHandle::new(0),
+        material_instance: MaterialInstanceHandle::new(0),
+        total_instance_count: 2,
+        total_draw_count: 2,
+        sort_key: 0,
+    });
+    disk_bundle.buckets.push(DiskRenderBucket {
+        material: MaterialHandle::new(1),
+        instances: vec![DiskRenderInstance {
+            mesh: MeshHandle::new(0),
+            material_instance: MaterialInstanceHandle::new(0),
+            total_instance_count: 1,
+            total_draw_count: 1,
+            sort_key: 0,
+        }],
+        instance_transform_buffer: BufferHandle::new(3),
+        sort_key: 0,
+    });
+    disk_bundle.scene_nodes = (0..4)
+        .map(|node_id| DiskSceneNode {
+            name: String::new(),
+            parent: None,
+            local_transform: [0.0; 16],
+            instances: vec![[(0, 0), (0, 1), (0, 2), (1, 0)][node_id]],
+        })
+        .collect();
+    let get_node_transform_tags = |disk_bundle: &DiskResourceBundle| -> Vec<u8> {
+        disk_bundle
+            .scene_nodes
+            .iter()
+            .map(|scene_node| {
+                let (bucket_id, transform_id) = scene_node.instances[0];
+                let bucket = &disk_bundle.buckets[bucket_id];
+                disk_bundle.buffers[bucket.instance_transform_buffer.index()].data[transform_id * 64]
+            })
+            .collect()
+    };
+    assert_eq!(get_node_transform_tags(&disk_bundle), vec![0, 1, 2, 16]);
+
+    disk_bundle.sort_render_buckets();
+    assert!(disk_bundle.validate().is_ok());
+    assert_eq!(get_node_transform_tags(&disk_bundle), vec![0, 1, 2, 16]);
+
+    let mut bucket_keys = Vec::new();
+    for bucket in &disk_bundle.buckets {
+        let pipeline_hash = disk_bundle.materials[bucket.material.index()].compute_pipeline_hash();
+        let instance_keys: Vec<u64> = bucket.instances.iter().map(|instance| instance.sort_key).collect();
+        for instance in &bucket.instances {
+            let material_instance = &disk_bundle.material_instances[instance.material_instance.index()];
+            assert_eq!(
+                instance.sort_key,
+                make_sort_key(pipeline_hash, material_instance.compute_texture_set_hash())
+            );
+        }
+        assert!(instance_keys.windows(2).all(|keys| keys[0] <= keys[1]));
+        assert_eq!(bucket.sort_key, instance_keys[0]);
+        assert_eq!(get_sort_key_pipeline_hash(bucket.sort_key), pipeline_hash);
+        bucket_keys.push(bucket.sort_key);
+    }
+    assert!(bucket_keys.windows(2).all(|keys| keys[0] <= keys[1]));
+    assert_ne!(
+        get_sort_key_pipeline_hash(bucket_keys[0]),
+        get_sort_key_pipeline_hash(bucket_keys[1])
+    );
+}
@@ -0,0 +1,171 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Generates malwerks.h from the declarations in src/capi.rs into OUT_DIR, test_capi_header checks that the copy in
+// include/ is up to date. The parser only understands the subset of Rust that src/capi.rs is allowed to use:
+// constants, type aliases, #[repr(C)] structs, opaque structs and extern "C" fns.
+
+fn main() {
+    let manifest_path = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let source_path = manifest_path.join("src").join("capi.rs");
+    let header_path = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("malwerks.h");
+    println!("cargo:rerun-if-changed={}", source_path.display());
+
+    let source = std::fs::read_to_string(&source_path).expect("failed to read capi.rs");
+    std::fs::write(&header_path, generate_header(&source)).expect("failed to write malwerks.h");
+}
+
+fn generate_header(source: &str) -> String {
+    let mut header = String::from(
+        "// Copyright (c) 2020-2021 Kyrylo Bazhenov\n\
+         //\n\
+         // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.\n\
+         // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.\n\
+         \n\
+         // Generated from malwerks_capi/src/capi.rs, do not edit\n\
+         \n\
+         #ifndef MALWERKS_H\n\
+         #define MALWERKS_H\n\
+         \n\
+         #include <stddef.h>\n\
+         #include <stdint.h>\n\
+         \n\
+         #ifdef __cplusplus\n\
+         extern \"C\" {\n\
+         #endif\n",
+    );
+
+    let mut comments = Vec::new();
+    let mut is_repr_c = false;
+    let mut lines = source.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        if let Some(comment) = line.strip_prefix("///").or_else(|| line.strip_prefix("//")) {
+            comments.push(comment.trim().to_string());
+            continue;
+        }
+        if line == "#[repr(C)]" {
+            is_repr_c = true;
+            continue;
+        }
+        if line.starts_with("#[") {
+            continue;
+        }
+
+        let declaration = if let Some(constant) = line.strip_prefix("pub const ") {
+            let (constant, comment) = split_comment(constant);
+            let (name, value) = constant.split_once(':').unwrap();
+            let value = value.split_once('=').unwrap().1.trim().trim_end_matches(';');
+            Some(format!("#define {} {}{}\n", name.trim(), value, comment))
+        } else if let Some(alias) = line.strip_prefix("pub type ") {
+            let (name, ty) = alias.trim_end_matches(';').split_once('=').unwrap();
+            Some(format!("typedef {};\n", get_declaration(ty, name.trim())))
+        } else if let Some(name) = line.strip_prefix("pub struct ") {
+            if is_repr_c {
+                let name = name.trim_end_matches('{').trim();
+                let mut declaration = format!("typedef struct {} {{\n", name);
+                for field in lines.by_ref().take_while(|line| *line != "}") {
+                    let (field, comment) = split_comment(field.strip_prefix("pub ").expect("fields have to be pub"));
+                    let (field_name, ty) = field.trim_end_matches(',').split_once(':').unwrap();
+                    declaration += &format!("    {};{}\n", get_declaration(ty, field_name.trim()), comment);
+                }
+                declaration += &format!("}} {};\n", name);
+                Some(declaration)
+            } else {
+                let name = name.split(['(', '{', ';']).next().unwrap().trim();
+                Some(format!("typedef struct {0} {0};\n", name))
+            }
+        } else if line.starts_with("pub extern \"C\" fn") || line.starts_with("pub unsafe extern \"C\" fn") {
+            let mut signature = String::from(line);
+            while !signature.ends_with('{') {
+                signature += lines.next().expect("unterminated function signature");
+            }
+            Some(get_function_declaration(&signature))
+        } else {
+            None
+        };
+
+        if let Some(declaration) = declaration {
+            header += "\n";
+            for comment in comments
+                .iter()
+                .filter(|comment| !comment.is_empty() && *comment != "# Safety")
+            {
+                header += &format!("// {}\n", comment);
+            }
+            header += &declaration;
+        }
+        comments.clear();
+        is_repr_c = false;
+    }
+
+    header += "\n#ifdef __cplusplus\n}\n#endif\n\n#endif // MALWERKS_H\n";
+    header
+}
+
+fn split_comment(line: &str) -> (&str, String) {
+    match line.split_once("//") {
+        Some((declaration, comment)) => (declaration.trim(), format!(" // {}", comment.trim())),
+        None => (line.trim(), String::new()),
+    }
+}
+
+fn get_function_declaration(signature: &str) -> String {
+    let name = signature.split("fn ").nth(1).unwrap().split('(').next().unwrap().trim();
+    let arguments_begin = signature.find('(').unwrap() + 1;
+    let arguments_end = signature.rfind(')').unwrap();
+    let arguments: Vec<String> = signature[arguments_begin..arguments_end]
+        .split(',')
+        .filter(|argument| !argument.trim().is_empty())
+        .map(|argument| {
+            let (argument_name, ty) = argument.split_once(':').unwrap();
+            get_declaration(ty, argument_name.trim())
+        })
+        .collect();
+    let return_type = match signature[arguments_end..].split_once("->") {
+        Some((_, return_type)) => get_type(return_type.trim_end_matches('{')),
+        None => String::from("void"),
+    };
+
+    if arguments.is_empty() {
+        format!("{} {}(void);\n", return_type, name)
+    } else {
+        format!("{} {}({});\n", return_type, name, arguments.join(", "))
+    }
+}
+
+fn get_declaration(ty: &str, name: &str) -> String {
+    let ty = ty.trim();
+    match ty.strip_prefix('[').and_then(|array| array.strip_suffix(']')) {
+        Some(array) => {
+            let (element_type, length) = array.split_once(';').unwrap();
+            format!("{} {}[{}]", get_type(element_type), name, length.trim())
+        }
+        None => format!("{} {}", get_type(ty), name),
+    }
+}
+
+fn get_type(ty: &str) -> String {
+    let ty = ty.trim();
+    if let Some(pointee) = ty.strip_prefix("*mut ") {
+        format!("{}*", get_type(pointee))
+    } else if let Some(pointee) = ty.strip_prefix("*const ") {
+        let pointee = get_type(pointee);
+        assert!(!pointee.ends_with('*'), "const pointers to pointers are not supported");
+        format!("const {}*", pointee)
+    } else {
+        String::from(match ty {
+            "u8" => "uint8_t",
+            "u32" => "uint32_t",
+            "u64" => "uint64_t",
+            "i32" => "int32_t",
+            "i64" => "int64_t",
+            "f32" => "float",
+            "usize" => "size_t",
+            "c_char" => "char",
+            "c_void" => "void",
+            _ => ty,
+        })
+    }
+}
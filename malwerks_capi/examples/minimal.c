@@ -0,0 +1,61 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Renders one frame of the lantern scene and prints the center pixel.
+// Build from the repository root after cargo build -p malwerks_capi --release:
+//   cc malwerks_capi/examples/minimal.c -Imalwerks_capi/include -Ltarget/release -lmalwerks_capi -o minimal
+//   LD_LIBRARY_PATH=target/release ./minimal
+
+#include <malwerks.h>
+
+#include <stdio.h>
+#include <stdlib.h>
+
+int main(void)
+{
+    if (malwerks_get_api_version() != MALWERKS_API_VERSION)
+    {
+        fprintf(stderr, "malwerks library doesn't match the header\n");
+        return 1;
+    }
+
+    MalwerksRendererParameters parameters = {0};
+    parameters.render_width = 512;
+    parameters.render_height = 512;
+    parameters.enable_validation = 0;
+    parameters.base_path = ".";
+
+    MalwerksRenderer* renderer = NULL;
+    if (malwerks_renderer_create(&parameters, &renderer) != MALWERKS_SUCCESS)
+    {
+        fprintf(stderr, "failed to create renderer\n");
+        return 1;
+    }
+
+    MalwerksResult result = malwerks_renderer_load_bundle(
+        renderer, "lantern", "assets/lantern/Lantern.gltf", "assets/Lantern.resource_bundle");
+    if (result == MALWERKS_SUCCESS)
+    {
+        MalwerksCamera camera = {{0.0f, -12.0f, -35.0f}, {0.0f, 0.0f, 0.0f, 1.0f}, 45.0f};
+        malwerks_renderer_set_camera(renderer, &camera);
+
+        uint32_t width = 0;
+        uint32_t height = 0;
+        malwerks_renderer_get_render_size(renderer, &width, &height);
+
+        size_t pixel_count = (size_t)width * height;
+        float* pixels = malloc(pixel_count * 4 * sizeof(float));
+        result = malwerks_renderer_render_frame(renderer, pixels, pixel_count);
+        if (result == MALWERKS_SUCCESS)
+        {
+            const float* center = pixels + ((height / 2) * width + width / 2) * 4;
+            printf("center pixel: %f %f %f\n", center[0], center[1], center[2]);
+        }
+        free(pixels);
+    }
+
+    malwerks_renderer_destroy(renderer);
+    return result == MALWERKS_SUCCESS ? 0 : 1;
+}
@@ -0,0 +1,71 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Generated from malwerks_capi/src/capi.rs, do not edit
+
+#ifndef MALWERKS_H
+#define MALWERKS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// Bumped when existing declarations change, additions keep the version
+#define MALWERKS_API_VERSION 1
+
+typedef int32_t MalwerksResult;
+
+#define MALWERKS_SUCCESS 0
+
+#define MALWERKS_ERROR_INVALID_ARGUMENT 1
+
+#define MALWERKS_ERROR_INTERNAL 2 // renderer panicked, details are in the log
+
+typedef struct MalwerksRendererParameters {
+    uint32_t render_width;
+    uint32_t render_height;
+    uint32_t enable_validation;
+    const char* base_path; // folder with assets and malwerks_shaders, UTF-8
+} MalwerksRendererParameters;
+
+typedef struct MalwerksCamera {
+    float position[3];
+    float orientation[4]; // quaternion in x, y, z, w order
+    float field_of_view; // degrees
+} MalwerksCamera;
+
+// Opaque renderer handle
+typedef struct MalwerksRenderer MalwerksRenderer;
+
+uint32_t malwerks_get_api_version(void);
+
+// parameters has to point to valid parameters, the created renderer is written to renderer.
+MalwerksResult malwerks_renderer_create(const MalwerksRendererParameters* parameters, MalwerksRenderer** renderer);
+
+// renderer has to be created by malwerks_renderer_create or be null.
+void malwerks_renderer_destroy(MalwerksRenderer* renderer);
+
+// renderer has to be valid, width and height have to be writable.
+MalwerksResult malwerks_renderer_get_render_size(const MalwerksRenderer* renderer, uint32_t* width, uint32_t* height);
+
+// renderer has to be valid, strings have to be null terminated UTF-8.
+// The glTF file is imported into bundle_file if the bundle is missing.
+MalwerksResult malwerks_renderer_load_bundle(MalwerksRenderer* renderer, const char* name, const char* gltf_file, const char* bundle_file);
+
+// renderer and camera have to be valid.
+MalwerksResult malwerks_renderer_set_camera(MalwerksRenderer* renderer, const MalwerksCamera* camera);
+
+// renderer has to be valid, rgba_pixels is either null or holds pixel_count * 4 floats.
+// Frames are read back as linear HDR colors, pixel_count has to be render width * render height.
+MalwerksResult malwerks_renderer_render_frame(MalwerksRenderer* renderer, float* rgba_pixels, size_t pixel_count);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // MALWERKS_H
@@ -0,0 +1,199 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// C interface of the renderer, malwerks.h is generated from this file by build.rs and checked in to include/.
+// Only plain C types are allowed in public declarations here, panics never cross the boundary.
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::headless_renderer::*;
+
+// Bumped when existing declarations change, additions keep the version
+pub const MALWERKS_API_VERSION: u32 = 1;
+
+pub type MalwerksResult = i32;
+pub const MALWERKS_SUCCESS: MalwerksResult = 0;
+pub const MALWERKS_ERROR_INVALID_ARGUMENT: MalwerksResult = 1;
+pub const MALWERKS_ERROR_INTERNAL: MalwerksResult = 2; // renderer panicked, details are in the log
+
+#[repr(C)]
+pub struct MalwerksRendererParameters {
+    pub render_width: u32,
+    pub render_height: u32,
+    pub enable_validation: u32,
+    pub base_path: *const c_char, // folder with assets and malwerks_shaders, UTF-8
+}
+
+#[repr(C)]
+pub struct MalwerksCamera {
+    pub position: [f32; 3],
+    pub orientation: [f32; 4], // quaternion in x, y, z, w order
+    pub field_of_view: f32,    // degrees
+}
+
+// Opaque renderer handle
+pub struct MalwerksRenderer(HeadlessRenderer);
+
+fn guard<F: FnOnce() -> MalwerksResult>(f: F) -> MalwerksResult {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(_) => {
+            log::error!("malwerks renderer panicked");
+            MALWERKS_ERROR_INTERNAL
+        }
+    }
+}
+
+unsafe fn get_str<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        None
+    } else {
+        CStr::from_ptr(string).to_str().ok()
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn malwerks_get_api_version() -> u32 {
+    MALWERKS_API_VERSION
+}
+
+/// # Safety
+/// parameters has to point to valid parameters, the created renderer is written to renderer.
+#[no_mangle]
+pub unsafe extern "C" fn malwerks_renderer_create(
+    parameters: *const MalwerksRendererParameters,
+    renderer: *mut *mut MalwerksRenderer,
+) -> MalwerksResult {
+    if parameters.is_null() || renderer.is_null() {
+        return MALWERKS_ERROR_INVALID_ARGUMENT;
+    }
+    let parameters = &*parameters;
+    let base_path = match get_str(parameters.base_path) {
+        Some(base_path) => std::path::PathBuf::from(base_path),
+        None => return MALWERKS_ERROR_INVALID_ARGUMENT,
+    };
+    if parameters.render_width == 0 || parameters.render_height == 0 {
+        return MALWERKS_ERROR_INVALID_ARGUMENT;
+    }
+
+    let _ = pretty_env_logger::try_init();
+    guard(|| {
+        let headless_renderer = HeadlessRenderer::new(&HeadlessRendererParameters {
+            render_width: parameters.render_width,
+            render_height: parameters.render_height,
+            base_path: &base_path,
+            enable_validation: parameters.enable_validation != 0,
+        });
+        *renderer = Box::into_raw(Box::new(MalwerksRenderer(headless_renderer)));
+        MALWERKS_SUCCESS
+    })
+}
+
+/// # Safety
+/// renderer has to be created by malwerks_renderer_create or be null.
+#[no_mangle]
+pub unsafe extern "C" fn malwerks_renderer_destroy(renderer: *mut MalwerksRenderer) {
+    if !renderer.is_null() {
+        let mut renderer = Box::from_raw(renderer);
+        guard(|| {
+            renderer.0.destroy();
+            MALWERKS_SUCCESS
+        });
+    }
+}
+
+/// # Safety
+/// renderer has to be valid, width and height have to be writable.
+#[no_mangle]
+pub unsafe extern "C" fn malwerks_renderer_get_render_size(
+    renderer: *const MalwerksRenderer,
+    width: *mut u32,
+    height: *mut u32,
+) -> MalwerksResult {
+    if renderer.is_null() || width.is_null() || height.is_null() {
+        return MALWERKS_ERROR_INVALID_ARGUMENT;
+    }
+    guard(|| {
+        let (render_width, render_height) = (*renderer).0.get_render_size();
+        *width = render_width;
+        *height = render_height;
+        MALWERKS_SUCCESS
+    })
+}
+
+/// # Safety
+/// renderer has to be valid, strings have to be null terminated UTF-8.
+/// The glTF file is imported into bundle_file if the bundle is missing.
+#[no_mangle]
+pub unsafe extern "C" fn malwerks_renderer_load_bundle(
+    renderer: *mut MalwerksRenderer,
+    name: *const c_char,
+    gltf_file: *const c_char,
+    bundle_file: *const c_char,
+) -> MalwerksResult {
+    if renderer.is_null() {
+        return MALWERKS_ERROR_INVALID_ARGUMENT;
+    }
+    let (name, gltf_file, bundle_file) = match (get_str(name), get_str(gltf_file), get_str(bundle_file)) {
+        (Some(name), Some(gltf_file), Some(bundle_file)) => (name, gltf_file, bundle_file),
+        _ => return MALWERKS_ERROR_INVALID_ARGUMENT,
+    };
+    guard(|| {
+        (*renderer)
+            .0
+            .add_render_bundle(name, std::path::Path::new(gltf_file), std::path::Path::new(bundle_file));
+        MALWERKS_SUCCESS
+    })
+}
+
+/// # Safety
+/// renderer and camera have to be valid.
+#[no_mangle]
+pub unsafe extern "C" fn malwerks_renderer_set_camera(
+    renderer: *mut MalwerksRenderer,
+    camera: *const MalwerksCamera,
+) -> MalwerksResult {
+    if renderer.is_null() || camera.is_null() {
+        return MALWERKS_ERROR_INVALID_ARGUMENT;
+    }
+    let camera = &*camera;
+    guard(|| {
+        (*renderer)
+            .0
+            .set_camera(camera.position, camera.orientation, camera.field_of_view);
+        MALWERKS_SUCCESS
+    })
+}
+
+/// # Safety
+/// renderer has to be valid, rgba_pixels is either null or holds pixel_count * 4 floats.
+/// Frames are read back as linear HDR colors, pixel_count has to be render width * render height.
+#[no_mangle]
+pub unsafe extern "C" fn malwerks_renderer_render_frame(
+    renderer: *mut MalwerksRenderer,
+    rgba_pixels: *mut f32,
+    pixel_count: usize,
+) -> MalwerksResult {
+    if renderer.is_null() {
+        return MALWERKS_ERROR_INVALID_ARGUMENT;
+    }
+    let (render_width, render_height) = (*renderer).0.get_render_size();
+    let pixels = if rgba_pixels.is_null() {
+        None
+    } else if pixel_count == render_width as usize * render_height as usize {
+        Some(std::slice::from_raw_parts_mut(
+            rgba_pixels as *mut [f32; 4],
+            pixel_count,
+        ))
+    } else {
+        return MALWERKS_ERROR_INVALID_ARGUMENT;
+    };
+    guard(|| {
+        (*renderer).0.render_frame(pixels);
+        MALWERKS_SUCCESS
+    })
+}
@@ -0,0 +1,228 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_dds::*;
+use malwerks_render::*;
+use malwerks_vk::*;
+
+use ultraviolet as utv;
+
+pub struct HeadlessRendererParameters<'a> {
+    pub render_width: u32,
+    pub render_height: u32,
+    pub base_path: &'a std::path::Path, // folder with assets and malwerks_shaders
+    pub enable_validation: bool,
+}
+
+// Offscreen renderer that owns its device, frames are rendered on request and can be read back
+pub struct HeadlessRenderer {
+    bundle_loader: BundleLoader,
+    pbr_forward_lit: PbrForwardLit,
+    camera: Camera,
+
+    base_path: std::path::PathBuf,
+    render_width: u32,
+    render_height: u32,
+
+    factory: DeviceFactory,
+    queue: DeviceQueue,
+    device: Device,
+}
+
+impl HeadlessRenderer {
+    pub fn new(parameters: &HeadlessRendererParameters) -> Self {
+        let base_path = parameters.base_path;
+        let device = Device::from_surface_provider(
+            &HeadlessSurface,
+            &[],
+            DeviceOptions {
+                enable_validation: parameters.enable_validation,
+                enable_render_target_export: true,
+                ..Default::default()
+            },
+        );
+        let mut queue = device.get_graphics_queue();
+        let mut factory = device.create_factory();
+
+        let bundle_loader = BundleLoader::new(
+            &BundleLoaderParameters {
+                bundle_compression_level: 9,
+                temporary_folder: &base_path.join("assets").join("temporary_folder"),
+                base_path,
+                shader_bundle_path: &base_path.join("assets").join("common_shaders.bundle"),
+                pbr_resource_folder: &base_path.join("assets").join("pbr_resources"),
+                force_import_bundles: false,
+                force_compile_shaders: false,
+                pack_mesh_geometry: false,
+                vertex_pulling: false,
+                compress_environment_probes: false,
+                shader_debug_printf: false,
+                stress_instance_count: 0,
+                stress_mesh: 0,
+                asset_cache_size_budget: DEFAULT_ASSET_CACHE_SIZE_BUDGET,
+            },
+            &device,
+            &mut factory,
+            &mut queue,
+        );
+        let pbr_forward_lit = PbrForwardLit::new(
+            &PbrForwardLitParameters {
+                render_width: parameters.render_width,
+                render_height: parameters.render_height,
+                target_layer: None,
+                bundle_loader: &bundle_loader,
+                enable_anti_aliasing: true,
+                transparency_mode: TransparencyMode::WeightedBlended,
+                hdr_color_format: HdrColorFormat::R11G11B10Float,
+                num_recording_threads: 0,
+            },
+            &device,
+            &mut factory,
+        );
+        let camera = Camera::new(
+            45.0,
+            Viewport {
+                x: 0,
+                y: 0,
+                width: parameters.render_width,
+                height: parameters.render_height,
+            },
+        );
+
+        Self {
+            bundle_loader,
+            pbr_forward_lit,
+            camera,
+
+            base_path: base_path.to_path_buf(),
+            render_width: parameters.render_width,
+            render_height: parameters.render_height,
+
+            factory,
+            queue,
+            device,
+        }
+    }
+
+    pub fn destroy(&mut self) {
+        self.queue.wait_idle();
+        self.device.wait_idle();
+
+        self.pbr_forward_lit.destroy(&mut self.factory);
+        self.bundle_loader.destroy(&mut self.factory);
+
+        self.factory.destroy();
+        self.device.destroy();
+    }
+
+    pub fn get_render_size(&self) -> (u32, u32) {
+        (self.render_width, self.render_height)
+    }
+
+    pub fn add_render_bundle(&mut self, name: &str, gltf_file: &std::path::Path, bundle_file: &std::path::Path) {
+        self.pbr_forward_lit.add_render_bundle(
+            name,
+            &mut self.bundle_loader,
+            gltf_file,
+            bundle_file,
+            &self.base_path.join("malwerks_shaders").join("gltf_pbr_material.glsl"),
+            &self.device,
+            &mut self.factory,
+            &mut self.queue,
+        );
+    }
+
+    // Orientation is a quaternion in x, y, z, w order, field of view is in degrees
+    pub fn set_camera(&mut self, position: [f32; 3], orientation: [f32; 4], field_of_view: f32) {
+        self.camera = Camera::new(
+            field_of_view,
+            Viewport {
+                x: 0,
+                y: 0,
+                width: self.render_width,
+                height: self.render_height,
+            },
+        );
+        self.camera.position = utv::Vec3::from(position);
+        self.camera.orientation = utv::Rotor3::new(
+            orientation[3],
+            utv::Bivec3::new(-orientation[2], orientation[1], -orientation[0]),
+        )
+        .normalized();
+    }
+
+    // Renders one frame and waits for it, linear HDR colors are written as RGBA rows if pixels are given
+    pub fn render_frame(&mut self, pixels: Option<&mut [[f32; 4]]>) {
+        let frame_context = self.device.begin_frame();
+        self.pbr_forward_lit.render(
+            &self.camera,
+            &frame_context,
+            &mut self.device,
+            &mut self.factory,
+            &mut self.queue,
+        );
+
+        let captured_image = pixels.as_ref().map(|_| {
+            let render_layer = self.pbr_forward_lit.get_render_layer();
+            capture_render_target(
+                Some((
+                    render_layer.get_signal_semaphore(&frame_context),
+                    vk::PipelineStageFlags::ALL_GRAPHICS,
+                )),
+                &RenderTargetCaptureParameters {
+                    image: render_layer.get_image_resource(0),
+                    image_extent: vk::Extent3D {
+                        width: self.render_width,
+                        height: self.render_height,
+                        depth: 1,
+                    },
+                    image_aspect: vk::ImageAspectFlags::COLOR,
+                    image_dxgi_format: DXGI_FORMAT_R11G11B10_FLOAT,
+                    num_mip_levels: 1,
+                    num_array_layers: 1,
+                },
+                self.bundle_loader.get_command_buffer_mut(),
+                &mut self.factory,
+                &mut self.queue,
+            )
+        });
+
+        self.device.end_frame(frame_context);
+        self.queue.wait_idle();
+        self.device.wait_idle();
+
+        if let (Some(pixels), Some(captured_image)) = (pixels, captured_image) {
+            let packed_pixels = captured_image.as_typed_slice::<u32>();
+            assert_eq!(
+                pixels.len(),
+                packed_pixels.len(),
+                "pixel count doesn't match the render size"
+            );
+            for (pixel, packed_pixel) in pixels.iter_mut().zip(packed_pixels) {
+                *pixel = unpack_r11g11b10(*packed_pixel);
+            }
+        }
+    }
+}
+
+// Red and green have 6 mantissa bits, blue has 5, all channels share a 5 bit exponent layout
+fn unpack_r11g11b10(packed: u32) -> [f32; 4] {
+    let unpack_float = |bits: u32, mantissa_bits: u32| {
+        let exponent = (bits >> mantissa_bits) as i32;
+        let mantissa = (bits & ((1 << mantissa_bits) - 1)) as f32 / (1 << mantissa_bits) as f32;
+        match exponent {
+            0 => mantissa * 2.0f32.powi(-14),
+            31 if mantissa == 0.0 => f32::INFINITY,
+            31 => f32::NAN,
+            _ => (1.0 + mantissa) * 2.0f32.powi(exponent - 15),
+        }
+    };
+    [
+        unpack_float(packed & 0x7ff, 6),
+        unpack_float((packed >> 11) & 0x7ff, 6),
+        unpack_float(packed >> 22, 5),
+        1.0,
+    ]
+}
@@ -0,0 +1,15 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod capi;
+mod headless_renderer;
+
+pub use capi::*;
+pub use headless_renderer::*;
+
+#[cfg(test)]
+mod test_capi_header;
+#[cfg(test)]
+mod test_headless_renderer;
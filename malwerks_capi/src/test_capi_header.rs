@@ -0,0 +1,17 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// build.rs only writes the header to OUT_DIR, the checked in copy is updated by hand after changes to capi.rs
+#[test]
+fn test_capi_header() {
+    let generated_path = std::path::Path::new(env!("OUT_DIR")).join("malwerks.h");
+    let generated_header = std::fs::read_to_string(&generated_path).expect("failed to read generated malwerks.h");
+    let checked_in_header = include_str!("../include/malwerks.h");
+    assert!(
+        generated_header == checked_in_header,
+        "include/malwerks.h is out of date, copy {:?} over it",
+        generated_path
+    );
+}
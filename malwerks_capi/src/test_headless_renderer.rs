@@ -0,0 +1,28 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::capi::*;
+
+// Every renderer owns its device, so destroying one has to release the device before the next is created
+#[test]
+fn test_renderer_create_destroy_create() {
+    let base_path = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("..");
+    let base_path = std::ffi::CString::new(base_path.to_str().unwrap()).unwrap();
+    let parameters = MalwerksRendererParameters {
+        render_width: 64,
+        render_height: 64,
+        enable_validation: 1,
+        base_path: base_path.as_ptr(),
+    };
+
+    for _ in 0..2 {
+        let mut renderer = std::ptr::null_mut();
+        unsafe {
+            assert_eq!(malwerks_renderer_create(&parameters, &mut renderer), MALWERKS_SUCCESS);
+            assert!(!renderer.is_null());
+            malwerks_renderer_destroy(renderer);
+        }
+    }
+}
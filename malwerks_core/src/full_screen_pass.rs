@@ -0,0 +1,293 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_vk::*;
+
+use crate::render_layer::*;
+
+// Set 0 of the fragment stage has the sampler at binding 0 and input images at bindings 1..=N,
+// additional set layouts follow it starting from set 1
+pub struct FullScreenPassParameters<'a> {
+    pub vertex_stage: &'a [u32], // draws a single triangle covering the target and outputs uv at location 0
+    pub fragment_stage: &'a [u32],
+    pub input_images: &'a [&'a [vk::ImageView]], // one descriptor set per entry, all of the same length
+    pub sampler_filter: vk::Filter,
+    pub additional_set_layouts: &'a [vk::DescriptorSetLayout],
+    pub push_constants_size: u32, // visible to the fragment stage, 0 if there are none
+}
+
+// Full screen triangle with a user provided fragment stage that writes the first color image of the target layer
+pub struct FullScreenPass {
+    sampler: vk::Sampler,
+
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+
+    vert_module: vk::ShaderModule,
+    frag_module: vk::ShaderModule,
+
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl FullScreenPass {
+    pub fn new(parameters: &FullScreenPassParameters, target_layer: &RenderLayer, factory: &mut DeviceFactory) -> Self {
+        assert!(
+            !parameters.input_images.is_empty(),
+            "full screen pass requires at least one set of input images"
+        );
+        let input_count = parameters.input_images[0].len();
+        assert!(
+            parameters.input_images.iter().all(|images| images.len() == input_count),
+            "all sets of input images have to be of the same length"
+        );
+        let set_count = parameters.input_images.len();
+
+        let vert_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(parameters.vertex_stage)
+                .build(),
+        );
+        let frag_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(parameters.fragment_stage)
+                .build(),
+        );
+
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let vertex_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(vert_module)
+            .stage(vk::ShaderStageFlags::VERTEX);
+        let fragment_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(frag_module)
+            .stage(vk::ShaderStageFlags::FRAGMENT);
+
+        let sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(parameters.sampler_filter)
+                .min_filter(parameters.sampler_filter)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .min_lod(0.0)
+                .max_lod(f32::MAX)
+                .build(),
+        );
+
+        let mut pool_sizes = vec![vk::DescriptorPoolSize::builder()
+            .ty(vk::DescriptorType::SAMPLER)
+            .descriptor_count(set_count as _)
+            .build()];
+        if input_count > 0 {
+            pool_sizes.push(
+                vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                    .descriptor_count((set_count * input_count) as _)
+                    .build(),
+            );
+        }
+        let descriptor_pool = factory.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(set_count as _)
+                .pool_sizes(&pool_sizes),
+        );
+
+        let mut bindings = Vec::with_capacity(input_count + 1);
+        bindings.push(
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        );
+        for input_id in 0..input_count {
+            bindings.push(
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding((input_id + 1) as _)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+            );
+        }
+        let descriptor_set_layout =
+            factory.create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings));
+        let temp_per_descriptor_set_layouts = vec![descriptor_set_layout; set_count];
+        let descriptor_sets = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&temp_per_descriptor_set_layouts)
+                .build(),
+        );
+
+        let sampler_info = vk::DescriptorImageInfo::builder().sampler(sampler).build();
+        for (descriptor_set, images) in descriptor_sets.iter().zip(parameters.input_images) {
+            let image_infos: Vec<vk::DescriptorImageInfo> = images
+                .iter()
+                .map(|image_view| {
+                    vk::DescriptorImageInfo::builder()
+                        .image_view(*image_view)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .build()
+                })
+                .collect();
+
+            let mut descriptor_writes = Vec::with_capacity(input_count + 1);
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLER)
+                    .image_info(std::slice::from_ref(&sampler_info))
+                    .build(),
+            );
+            for (input_id, image_info) in image_infos.iter().enumerate() {
+                descriptor_writes.push(
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*descriptor_set)
+                        .dst_binding((input_id + 1) as _)
+                        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                        .image_info(std::slice::from_ref(image_info))
+                        .build(),
+                );
+            }
+            factory.update_descriptor_sets(&descriptor_writes, &[]);
+        }
+
+        let mut set_layouts = Vec::with_capacity(parameters.additional_set_layouts.len() + 1);
+        set_layouts.push(descriptor_set_layout);
+        set_layouts.extend_from_slice(parameters.additional_set_layouts);
+        let push_constant_ranges = if parameters.push_constants_size > 0 {
+            vec![vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset(0)
+                .size(parameters.push_constants_size)
+                .build()]
+        } else {
+            Vec::new()
+        };
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&set_layouts)
+                .push_constant_ranges(&push_constant_ranges)
+                .build(),
+        );
+        let pipeline = factory.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[target_layer.make_pipeline_create_info(
+                vk::GraphicsPipelineCreateInfo::builder()
+                    .stages(&[vertex_stage.build(), fragment_stage.build()])
+                    .vertex_input_state(
+                        &vk::PipelineVertexInputStateCreateInfo::builder()
+                            .vertex_binding_descriptions(&[])
+                            .build(),
+                    )
+                    .input_assembly_state(
+                        &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                            .primitive_restart_enable(false)
+                            .build(),
+                    )
+                    .tessellation_state(&Default::default())
+                    .viewport_state(
+                        &vk::PipelineViewportStateCreateInfo::builder()
+                            .viewport_count(1)
+                            .scissor_count(1)
+                            .build(),
+                    )
+                    .rasterization_state(
+                        &vk::PipelineRasterizationStateCreateInfo::builder()
+                            .line_width(1.0)
+                            .build(),
+                    )
+                    .multisample_state(
+                        &vk::PipelineMultisampleStateCreateInfo::builder()
+                            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                            .build(),
+                    )
+                    .depth_stencil_state(&Default::default())
+                    .color_blend_state(
+                        &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                            vk::PipelineColorBlendAttachmentState::builder()
+                                .blend_enable(false)
+                                .color_write_mask(
+                                    vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B
+                                        | vk::ColorComponentFlags::A,
+                                )
+                                .build(),
+                        ]),
+                    )
+                    .dynamic_state(
+                        &vk::PipelineDynamicStateCreateInfo::builder()
+                            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                            .build(),
+                    )
+                    .layout(pipeline_layout)
+                    .subpass(0)
+                    .base_pipeline_handle(vk::Pipeline::null())
+                    .base_pipeline_index(0)
+                    .build(),
+            )],
+        )[0];
+
+        Self {
+            sampler,
+            descriptor_pool,
+            descriptor_set_layout,
+            descriptor_sets,
+            vert_module,
+            frag_module,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        factory.destroy_sampler(self.sampler);
+        factory.destroy_descriptor_pool(self.descriptor_pool);
+        factory.destroy_descriptor_set_layout(self.descriptor_set_layout);
+        factory.destroy_shader_module(self.vert_module);
+        factory.destroy_shader_module(self.frag_module);
+        factory.destroy_pipeline_layout(self.pipeline_layout);
+        factory.destroy_pipeline(self.pipeline);
+    }
+
+    pub fn get_input_set_count(&self) -> usize {
+        self.descriptor_sets.len()
+    }
+
+    // Has to be recorded before render() if push constants size isn't 0
+    pub fn push_constants<T>(&self, command_buffer: &mut CommandBuffer, constants: &[T]) {
+        command_buffer.push_constants(self.pipeline_layout, vk::ShaderStageFlags::FRAGMENT, 0, constants);
+    }
+
+    // Has to be recorded inside of the target layer render pass, viewport and scissor are left as they are
+    pub fn render(
+        &self,
+        command_buffer: &mut CommandBuffer,
+        input_set: usize,
+        additional_sets: &[vk::DescriptorSet],
+        dynamic_offsets: &[u32],
+    ) {
+        let mut descriptor_sets = Vec::with_capacity(additional_sets.len() + 1);
+        descriptor_sets.push(self.descriptor_sets[input_set]);
+        descriptor_sets.extend_from_slice(additional_sets);
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &descriptor_sets,
+            dynamic_offsets,
+        );
+        command_buffer.draw(3, 1, 0, 0);
+    }
+}
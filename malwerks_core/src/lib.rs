@@ -3,17 +3,41 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
 // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+mod full_screen_pass;
+mod null_rhi;
+mod parallel_recorder;
 mod pipeline_bundle;
+mod pipeline_library;
+mod render_hardware_interface;
 mod render_layer;
 mod resource_bundle;
+mod shader_binding_table;
 mod shader_module_bundle;
+mod transient_image_pool;
 mod upload_batch;
+mod vulkan_rhi;
 
+pub use full_screen_pass::*;
+pub use null_rhi::*;
+pub use parallel_recorder::*;
 pub use pipeline_bundle::*;
+pub use render_hardware_interface::*;
 pub use render_layer::*;
 pub use resource_bundle::*;
+pub use shader_binding_table::*;
 pub use shader_module_bundle::*;
+pub use transient_image_pool::*;
 pub use upload_batch::*;
+pub use vulkan_rhi::*;
 
 // #[cfg(test)]
 // mod test_render_passes;
+
+#[cfg(test)]
+mod test_render_hardware_interface;
+#[cfg(test)]
+mod test_resource_bundle;
+#[cfg(test)]
+mod test_shader_binding_table;
+#[cfg(test)]
+mod test_transient_image_pool;
@@ -0,0 +1,244 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::render_hardware_interface::*;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NullRhiPipelineType {
+    Graphics,
+    Compute,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct NullRhiStats {
+    pub num_passes: usize,
+    pub num_barriers: usize,
+    pub num_draws: usize,
+    pub num_dispatches: usize,
+}
+
+// Backend without a GPU for tests, validates API usage and keeps buffer contents in memory
+#[derive(Default)]
+pub struct NullRhi {
+    buffers: Vec<Option<(RhiBufferParameters, Vec<u8>)>>,
+    images: Vec<Option<RhiImageParameters>>,
+    pipelines: Vec<Option<(NullRhiPipelineType, u32)>>, // pipeline type, push constant size
+    active_pass: bool,
+    bound_pipeline: Option<RhiPipeline>,
+    stats: NullRhiStats,
+}
+
+impl NullRhi {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn get_stats(&self) -> NullRhiStats {
+        self.stats
+    }
+
+    pub fn get_buffer_data(&self, buffer: RhiBuffer) -> &[u8] {
+        &self.get_buffer(buffer).1
+    }
+
+    pub fn get_live_resource_count(&self) -> usize {
+        self.buffers.iter().filter(|buffer| buffer.is_some()).count()
+            + self.images.iter().filter(|image| image.is_some()).count()
+            + self.pipelines.iter().filter(|pipeline| pipeline.is_some()).count()
+    }
+
+    fn get_buffer(&self, buffer: RhiBuffer) -> &(RhiBufferParameters, Vec<u8>) {
+        self.buffers[buffer.index()].as_ref().expect("buffer was destroyed")
+    }
+
+    fn get_image(&self, image: RhiImage) -> &RhiImageParameters {
+        self.images[image.index()].as_ref().expect("image was destroyed")
+    }
+
+    fn get_pipeline(&self, pipeline: RhiPipeline) -> (NullRhiPipelineType, u32) {
+        self.pipelines[pipeline.index()].expect("pipeline was destroyed")
+    }
+
+    fn get_bound_pipeline(&self, pipeline_type: NullRhiPipelineType) -> RhiPipeline {
+        let pipeline = self.bound_pipeline.expect("no pipeline is bound");
+        assert_eq!(
+            self.get_pipeline(pipeline).0,
+            pipeline_type,
+            "bound pipeline has a wrong type"
+        );
+        match pipeline_type {
+            NullRhiPipelineType::Graphics => assert!(self.active_pass, "draws have to be inside passes"),
+            NullRhiPipelineType::Compute => assert!(!self.active_pass, "dispatches can't be inside passes"),
+        }
+        pipeline
+    }
+}
+
+impl RenderHardwareInterface for NullRhi {
+    type Factory = ();
+    type CommandList = ();
+
+    fn create_buffer(&mut self, parameters: &RhiBufferParameters, _factory: &mut ()) -> RhiBuffer {
+        assert!(parameters.size > 0, "buffers can't be empty");
+        self.buffers
+            .push(Some((*parameters, vec![0u8; parameters.size as usize])));
+        RhiBuffer::new(self.buffers.len() - 1)
+    }
+
+    fn destroy_buffer(&mut self, buffer: RhiBuffer, _factory: &mut ()) {
+        self.buffers[buffer.index()].take().expect("buffer was destroyed twice");
+    }
+
+    fn write_buffer(&mut self, buffer: RhiBuffer, offset: u64, data: &[u8], _factory: &mut ()) {
+        let (parameters, buffer_data) = self.buffers[buffer.index()].as_mut().expect("buffer was destroyed");
+        assert!(parameters.host_visible, "only host visible buffers can be written");
+        buffer_data[offset as usize..][..data.len()].copy_from_slice(data);
+    }
+
+    fn create_image(&mut self, parameters: &RhiImageParameters, _factory: &mut ()) -> RhiImage {
+        assert!(parameters.width > 0 && parameters.height > 0, "images can't be empty");
+        assert!(
+            !(parameters.storage && parameters.format.is_depth()),
+            "depth images can't be storage images"
+        );
+        self.images.push(Some(*parameters));
+        RhiImage::new(self.images.len() - 1)
+    }
+
+    fn destroy_image(&mut self, image: RhiImage, _factory: &mut ()) {
+        self.images[image.index()].take().expect("image was destroyed twice");
+    }
+
+    fn create_graphics_pipeline(
+        &mut self,
+        parameters: &RhiGraphicsPipelineParameters,
+        _factory: &mut (),
+    ) -> RhiPipeline {
+        assert!(!parameters.vertex_shader.is_empty() && !parameters.fragment_shader.is_empty());
+        self.pipelines
+            .push(Some((NullRhiPipelineType::Graphics, parameters.push_constant_size)));
+        RhiPipeline::new(self.pipelines.len() - 1)
+    }
+
+    fn create_compute_pipeline(&mut self, parameters: &RhiComputePipelineParameters, _factory: &mut ()) -> RhiPipeline {
+        assert!(!parameters.compute_shader.is_empty());
+        self.pipelines
+            .push(Some((NullRhiPipelineType::Compute, parameters.push_constant_size)));
+        RhiPipeline::new(self.pipelines.len() - 1)
+    }
+
+    fn destroy_pipeline(&mut self, pipeline: RhiPipeline, _factory: &mut ()) {
+        self.pipelines[pipeline.index()]
+            .take()
+            .expect("pipeline was destroyed twice");
+        if self.bound_pipeline == Some(pipeline) {
+            self.bound_pipeline = None;
+        }
+    }
+
+    fn begin_pass(&mut self, parameters: &RhiPassParameters, _command_list: &mut ()) {
+        assert!(!self.active_pass, "passes can't be nested");
+        let mut extent = None;
+        for image in parameters.color_targets.iter().chain(parameters.depth_target.iter()) {
+            let image_parameters = self.get_image(*image);
+            assert!(image_parameters.render_target, "pass targets have to be render targets");
+            let image_extent = (image_parameters.width, image_parameters.height);
+            assert_eq!(
+                *extent.get_or_insert(image_extent),
+                image_extent,
+                "pass targets differ in size"
+            );
+        }
+        if let Some(depth_target) = parameters.depth_target {
+            assert!(
+                self.get_image(depth_target).format.is_depth(),
+                "depth target has no depth format"
+            );
+        }
+        assert!(extent.is_some(), "passes need at least one target");
+        self.active_pass = true;
+        self.stats.num_passes += 1;
+    }
+
+    fn end_pass(&mut self, _command_list: &mut ()) {
+        assert!(self.active_pass, "no pass is active");
+        self.active_pass = false;
+    }
+
+    fn barrier(&mut self, _command_list: &mut ()) {
+        assert!(!self.active_pass, "barriers can't be inside passes");
+        self.stats.num_barriers += 1;
+    }
+
+    fn bind_pipeline(&mut self, pipeline: RhiPipeline, _command_list: &mut ()) {
+        self.get_pipeline(pipeline);
+        self.bound_pipeline = Some(pipeline);
+    }
+
+    fn bind_resources(
+        &mut self,
+        pipeline: RhiPipeline,
+        storage_buffers: &[RhiBuffer],
+        storage_images: &[RhiImage],
+        _command_list: &mut (),
+    ) {
+        self.get_pipeline(pipeline);
+        for buffer in storage_buffers {
+            assert!(
+                self.get_buffer(*buffer).0.usage.storage,
+                "buffer isn't a storage buffer"
+            );
+        }
+        for image in storage_images {
+            assert!(self.get_image(*image).storage, "image isn't a storage image");
+        }
+    }
+
+    fn push_constants(&mut self, pipeline: RhiPipeline, data: &[u8], _command_list: &mut ()) {
+        let (_, push_constant_size) = self.get_pipeline(pipeline);
+        assert!(data.len() as u32 <= push_constant_size, "push constants don't fit");
+    }
+
+    fn draw(&mut self, _vertex_count: u32, _instance_count: u32, _command_list: &mut ()) {
+        self.get_bound_pipeline(NullRhiPipelineType::Graphics);
+        self.stats.num_draws += 1;
+    }
+
+    fn draw_indexed_indirect(
+        &mut self,
+        index_buffer: RhiBuffer,
+        indirect_buffer: RhiBuffer,
+        offset: u64,
+        draw_count: u32,
+        _command_list: &mut (),
+    ) {
+        self.get_bound_pipeline(NullRhiPipelineType::Graphics);
+        assert!(
+            self.get_buffer(index_buffer).0.usage.index,
+            "buffer isn't an index buffer"
+        );
+        let (parameters, _) = self.get_buffer(indirect_buffer);
+        assert!(parameters.usage.indirect, "buffer isn't an indirect buffer");
+        assert!(
+            offset + draw_count as u64 * 20 <= parameters.size,
+            "indirect draws don't fit"
+        );
+        self.stats.num_draws += draw_count as usize;
+    }
+
+    fn dispatch(&mut self, group_count: [u32; 3], _command_list: &mut ()) {
+        self.get_bound_pipeline(NullRhiPipelineType::Compute);
+        assert!(group_count.iter().all(|count| *count > 0), "empty dispatch");
+        self.stats.num_dispatches += 1;
+    }
+
+    fn dispatch_indirect(&mut self, buffer: RhiBuffer, offset: u64, _command_list: &mut ()) {
+        self.get_bound_pipeline(NullRhiPipelineType::Compute);
+        let (parameters, _) = self.get_buffer(buffer);
+        assert!(parameters.usage.indirect, "buffer isn't an indirect buffer");
+        assert!(offset + 12 <= parameters.size, "indirect dispatch doesn't fit");
+        self.stats.num_dispatches += 1;
+    }
+}
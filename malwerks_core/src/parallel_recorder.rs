@@ -0,0 +1,204 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_vk::*;
+
+use rayon::prelude::*;
+
+use crate::render_layer::*;
+
+// Records render pass contents on worker threads into secondary command buffers.
+// Jobs are split into one contiguous chunk per worker, so the order of draws is preserved.
+// Every worker owns a command pool per buffered frame, pools are reset on the first use in a frame.
+pub struct ParallelRecorder {
+    thread_pool: rayon::ThreadPool,
+    worker_resources: FrameLocal<Vec<WorkerResources>>,
+    recorded_frame_index: Option<u64>,
+    pending_command_buffers: Vec<CommandBuffer>,
+}
+
+impl ParallelRecorder {
+    pub fn new(num_threads: usize, device: &Device, factory: &mut DeviceFactory) -> Self {
+        assert!(num_threads > 0, "parallel recorder needs at least one thread");
+
+        let thread_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|thread_id| format!("malwerks_recorder_{}", thread_id))
+            .build()
+            .expect("failed to create recording threads");
+        let worker_resources = FrameLocal::new(factory.get_num_buffered_frames(), |_| {
+            (0..num_threads)
+                .map(|_| WorkerResources {
+                    command_pool: factory.create_command_pool(
+                        &vk::CommandPoolCreateInfo::builder()
+                            .queue_family_index(device.get_graphics_queue_index())
+                            .build(),
+                    ),
+                    command_buffers: Vec::new(),
+                    used_command_buffers: 0,
+                })
+                .collect()
+        });
+
+        Self {
+            thread_pool,
+            worker_resources,
+            recorded_frame_index: None,
+            pending_command_buffers: Vec::new(),
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        self.worker_resources.destroy(|worker_resources| {
+            for worker in worker_resources {
+                factory.free_command_buffers(worker.command_pool, &worker.command_buffers);
+                factory.destroy_command_pool(worker.command_pool);
+            }
+        });
+    }
+
+    pub fn get_num_threads(&self) -> usize {
+        self.thread_pool.current_num_threads()
+    }
+
+    // Layers that don't support secondary commands are recorded inline on the calling thread
+    pub fn begin_render_pass(
+        &mut self,
+        render_layer: &mut RenderLayer,
+        frame_context: &FrameContext,
+        render_area: vk::Rect2D,
+        factory: &mut DeviceFactory,
+    ) {
+        assert!(
+            self.pending_command_buffers.is_empty(),
+            "begin_render_pass() called twice without end_render_pass()"
+        );
+
+        if self.recorded_frame_index != Some(frame_context.frame_index()) {
+            self.recorded_frame_index = Some(frame_context.frame_index());
+            for worker in self.worker_resources.get_mut(frame_context) {
+                factory.reset_command_pool(worker.command_pool);
+                worker.used_command_buffers = 0;
+            }
+        }
+
+        if render_layer.supports_secondary_commands() {
+            render_layer.begin_render_pass_with_secondary_commands(frame_context, render_area);
+        } else {
+            render_layer.begin_render_pass(frame_context, render_area);
+        }
+    }
+
+    // Results of record_job are returned in the order of jobs
+    pub fn record<T, R, F>(
+        &mut self,
+        render_layer: &mut RenderLayer,
+        frame_context: &FrameContext,
+        factory: &mut DeviceFactory,
+        jobs: &[T],
+        record_job: F,
+    ) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&mut CommandBuffer, &T) -> R + Sync,
+    {
+        puffin::profile_function!();
+
+        if !render_layer.supports_secondary_commands() {
+            let command_buffer = render_layer.get_command_buffer(frame_context);
+            return jobs.iter().map(|job| record_job(command_buffer, job)).collect();
+        }
+        if jobs.is_empty() {
+            return Vec::new();
+        }
+
+        let worker_resources = self.worker_resources.get_mut(frame_context);
+        let chunk_size = jobs.len().div_ceil(worker_resources.len());
+        let mut command_buffers = Vec::with_capacity(worker_resources.len());
+        for worker in worker_resources.iter_mut().take(jobs.len().div_ceil(chunk_size)) {
+            command_buffers.push(worker.acquire_command_buffer(factory));
+        }
+
+        let render_pass = render_layer.get_render_pass();
+        let framebuffer = render_layer.get_framebuffer(frame_context);
+        let viewport = render_layer.get_active_viewport();
+        let scissor = render_layer.get_active_scissor();
+        let pipeline_statistics = if render_layer.has_pipeline_statistics() {
+            PIPELINE_STATISTICS_FLAGS
+        } else {
+            vk::QueryPipelineStatisticFlags::empty()
+        };
+
+        let results: Vec<Vec<R>> = self.thread_pool.install(|| {
+            jobs.par_chunks(chunk_size)
+                .zip(command_buffers.par_iter_mut())
+                .map(|(chunk, command_buffer)| {
+                    puffin::profile_scope!("record_chunk");
+
+                    // Inheritance info holds a raw pointer, so it can't be shared between workers
+                    let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+                        .render_pass(render_pass)
+                        .subpass(0)
+                        .framebuffer(framebuffer)
+                        .pipeline_statistics(pipeline_statistics)
+                        .build();
+                    command_buffer.begin(
+                        &vk::CommandBufferBeginInfo::builder()
+                            .flags(
+                                vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                                    | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+                            )
+                            .inheritance_info(&inheritance_info)
+                            .build(),
+                    );
+                    command_buffer.set_viewport(0, &[viewport]);
+                    command_buffer.set_scissor(0, &[scissor]);
+                    let chunk_results = chunk.iter().map(|job| record_job(command_buffer, job)).collect();
+                    command_buffer.end();
+                    chunk_results
+                })
+                .collect()
+        });
+
+        self.pending_command_buffers.extend_from_slice(&command_buffers);
+        results.into_iter().flatten().collect()
+    }
+
+    pub fn end_render_pass(&mut self, render_layer: &mut RenderLayer, frame_context: &FrameContext) {
+        if !self.pending_command_buffers.is_empty() {
+            render_layer
+                .get_command_buffer(frame_context)
+                .execute_commands(&self.pending_command_buffers);
+            self.pending_command_buffers.clear();
+        }
+        render_layer.end_render_pass(frame_context);
+    }
+}
+
+struct WorkerResources {
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<CommandBuffer>,
+    used_command_buffers: usize, // reset together with the command pool
+}
+
+impl WorkerResources {
+    // Render passes recorded in the same frame get separate command buffers
+    fn acquire_command_buffer(&mut self, factory: &mut DeviceFactory) -> CommandBuffer {
+        if self.used_command_buffers == self.command_buffers.len() {
+            self.command_buffers.extend(
+                factory.allocate_command_buffers(
+                    &vk::CommandBufferAllocateInfo::builder()
+                        .command_pool(self.command_pool)
+                        .level(vk::CommandBufferLevel::SECONDARY)
+                        .command_buffer_count(1)
+                        .build(),
+                ),
+            );
+        }
+        self.used_command_buffers += 1;
+        self.command_buffers[self.used_command_buffers - 1]
+    }
+}
@@ -3,147 +3,343 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
 // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use malwerks_bundles::*;
 use malwerks_vk::*;
 
+use crate::pipeline_library::*;
 use crate::render_layer::*;
 use crate::resource_bundle::*;
 use crate::shader_module_bundle::*;
 
+#[derive(Copy, Clone)]
+pub enum PipelineBlending<'a> {
+    // Alpha blended materials are rendered as opaque
+    Opaque,
+    // Alpha blended materials are skipped, they are rendered by a separate bundle
+    SkipAlphaBlended,
+    // Only alpha blended materials get pipelines, depth is neither tested nor written
+    AlphaBlendedOnly(&'a [vk::PipelineColorBlendAttachmentState]),
+    // Every material gets pipelines with the provided blend states, depth is neither tested nor written
+    AllBlended(&'a [vk::PipelineColorBlendAttachmentState]),
+}
+
 pub struct PipelineBundleParameters<'a> {
     pub resource_bundle: &'a ResourceBundle,
     pub shader_module_bundle: &'a ShaderModuleBundle,
     pub render_layer: &'a RenderLayer,
 
     pub descriptor_set_layouts: &'a [vk::DescriptorSetLayout],
+    pub use_push_descriptors: bool,
+    pub use_vertex_pulling: bool, // vertex buffers are read in shaders, pipelines have no vertex input
+    pub blending: PipelineBlending<'a>,
+    pub pipeline_cache_data: &'a [u8], // initial data of the pipeline cache, can be empty
+    pub use_pipeline_libraries: bool,  // requires VK_EXT_graphics_pipeline_library
 }
 
 pub struct PipelineBundle {
     pub descriptor_pool: vk::DescriptorPool,
     pub descriptor_layout: vk::DescriptorSetLayout,
-    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub descriptor_sets: Vec<vk::DescriptorSet>, // empty if push descriptors are used
+    pub instance_buffer_infos: Vec<vk::DescriptorBufferInfo>, // `binding_count` per render instance
+    pub bucket_descriptor_sets: Vec<vk::DescriptorSet>, // empty if push descriptors are used
+    pub bucket_buffer_infos: Vec<vk::DescriptorBufferInfo>, // `binding_count` per bucket, used by multi draws
+    pub binding_count: usize, // instance transforms, followed by vertex data with vertex pulling without buffer addresses
 
     pub pipeline_cache: vk::PipelineCache,
     pub pipeline_layouts: Vec<vk::PipelineLayout>, // directly maps to `materials` in the render bundle
-    pub pipelines: Vec<vk::Pipeline>,              // directly maps to `materials` in the render bundle
+    pub pipelines: Vec<vk::Pipeline>,              // directly maps to `materials` in the render bundle, null if skipped
+    pub pipeline_libraries: Vec<vk::Pipeline>,     // pipelines are linked from these, empty if libraries aren't used
 }
 
 impl PipelineBundle {
     pub fn destroy(&mut self, factory: &mut DeviceFactory) {
-        factory.destroy_descriptor_pool(self.descriptor_pool);
+        if self.descriptor_pool != vk::DescriptorPool::null() {
+            factory.destroy_descriptor_pool(self.descriptor_pool);
+        }
         factory.destroy_descriptor_set_layout(self.descriptor_layout);
         factory.destroy_pipeline_cache(self.pipeline_cache);
         for pipeline_layout in &self.pipeline_layouts {
             factory.destroy_pipeline_layout(*pipeline_layout);
         }
-        for pipeline in &self.pipelines {
+        for pipeline in self
+            .pipelines
+            .iter()
+            .filter(|pipeline| **pipeline != vk::Pipeline::null())
+        {
             factory.destroy_pipeline(*pipeline);
         }
+        for pipeline_library in &self.pipeline_libraries {
+            factory.destroy_pipeline(*pipeline_library);
+        }
     }
 
     pub fn new<'a>(parameters: &PipelineBundleParameters<'a>, factory: &mut DeviceFactory) -> Self {
-        let (descriptor_pool, descriptor_layout, descriptor_sets) =
-            initialize_descriptor_pool(parameters.resource_bundle, factory);
-        let (pipeline_cache, pipeline_layouts, pipelines) = initialize_pipelines(
-            parameters.resource_bundle,
-            parameters.shader_module_bundle,
-            parameters.render_layer,
+        // Vertex data is addressed through ObjectData when the resource bundle has buffer addresses
+        let use_vertex_data_binding =
+            parameters.use_vertex_pulling && !parameters.resource_bundle.uses_buffer_device_address();
+        let (
+            descriptor_pool,
             descriptor_layout,
-            parameters.descriptor_set_layouts,
+            descriptor_sets,
+            instance_buffer_infos,
+            bucket_descriptor_sets,
+            bucket_buffer_infos,
+        ) = initialize_descriptor_pool(
+            parameters.resource_bundle,
+            parameters.use_push_descriptors,
+            use_vertex_data_binding,
             factory,
         );
+        let (pipeline_cache, pipeline_layouts, pipelines, pipeline_libraries) =
+            initialize_pipelines(parameters, descriptor_layout, factory);
 
         Self {
             descriptor_pool,
             descriptor_layout,
             descriptor_sets,
+            instance_buffer_infos,
+            bucket_descriptor_sets,
+            bucket_buffer_infos,
+            binding_count: get_binding_count(use_vertex_data_binding),
 
             pipeline_cache,
             pipeline_layouts,
             pipelines,
+            pipeline_libraries,
         }
     }
+
+    pub fn uses_push_descriptors(&self) -> bool {
+        self.descriptor_pool == vk::DescriptorPool::null()
+    }
+
+    pub fn push_instance_descriptor_set(
+        &self,
+        command_buffer: &mut CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        set: u32,
+        render_instance_id: usize,
+    ) {
+        let buffer_infos = &self.instance_buffer_infos[render_instance_id * self.binding_count..][..self.binding_count];
+        command_buffer.push_descriptor_set(
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            set,
+            &make_buffer_writes(vk::DescriptorSet::null(), buffer_infos),
+        );
+    }
+
+    // Binds transforms of the whole bucket, draws select theirs with first_instance
+    pub fn push_bucket_descriptor_set(
+        &self,
+        command_buffer: &mut CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+        set: u32,
+        bucket_id: usize,
+    ) {
+        let buffer_infos = &self.bucket_buffer_infos[bucket_id * self.binding_count..][..self.binding_count];
+        command_buffer.push_descriptor_set(
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            set,
+            &make_buffer_writes(vk::DescriptorSet::null(), buffer_infos),
+        );
+    }
+}
+
+// Instance transforms and object data, followed by vertex data if it's bound as a storage buffer
+fn get_binding_count(use_vertex_data_binding: bool) -> usize {
+    2 + use_vertex_data_binding as usize
+}
+
+// Every buffer info is written to its own binding
+fn make_buffer_writes(
+    descriptor_set: vk::DescriptorSet,
+    buffer_infos: &[vk::DescriptorBufferInfo],
+) -> Vec<vk::WriteDescriptorSet> {
+    buffer_infos
+        .iter()
+        .enumerate()
+        .map(|(binding, buffer_info)| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(binding as _)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(std::slice::from_ref(buffer_info))
+                .build()
+        })
+        .collect()
 }
 
 fn initialize_descriptor_pool(
     resource_bundle: &ResourceBundle,
+    use_push_descriptors: bool,
+    use_vertex_data_binding: bool,
     factory: &mut DeviceFactory,
-) -> (vk::DescriptorPool, vk::DescriptorSetLayout, Vec<vk::DescriptorSet>) {
+) -> (
+    vk::DescriptorPool,
+    vk::DescriptorSetLayout,
+    Vec<vk::DescriptorSet>,
+    Vec<vk::DescriptorBufferInfo>,
+    Vec<vk::DescriptorSet>,
+    Vec<vk::DescriptorBufferInfo>,
+) {
+    let binding_count = get_binding_count(use_vertex_data_binding);
+    let get_vertex_buffer_info = |mesh: MeshHandle| {
+        let mesh = &resource_bundle.meshes[mesh.index()];
+        vk::DescriptorBufferInfo::builder()
+            .buffer(resource_bundle.buffers[mesh.vertex_buffer.index()].0)
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()
+    };
+
     let mut render_instance_count = 0;
     for bucket in &resource_bundle.buckets {
         render_instance_count += bucket.instances.len();
     }
 
+    let mut instance_buffer_infos = Vec::with_capacity(render_instance_count * binding_count);
+    let mut bucket_buffer_infos = Vec::with_capacity(resource_bundle.buckets.len() * binding_count);
+    let get_object_buffer_info = |first_object_id: usize, object_count: usize| {
+        vk::DescriptorBufferInfo::builder()
+            .buffer(resource_bundle.object_data_buffer.0)
+            .offset((first_object_id * OBJECT_DATA_SIZE) as _)
+            .range((object_count * OBJECT_DATA_SIZE) as _)
+            .build()
+    };
+    for bucket in &resource_bundle.buckets {
+        let mut current_offset = 0;
+        let mut current_object_id = bucket.first_object_id;
+        for instance in &bucket.instances {
+            let range = instance.total_instance_count * std::mem::size_of::<[f32; 16]>();
+            instance_buffer_infos.push(
+                vk::DescriptorBufferInfo::builder()
+                    .buffer(resource_bundle.buffers[bucket.instance_transform_buffer.index()].0)
+                    .offset(current_offset as _)
+                    .range(range as _)
+                    .build(),
+            );
+            instance_buffer_infos.push(get_object_buffer_info(current_object_id, instance.total_instance_count));
+            if use_vertex_data_binding {
+                instance_buffer_infos.push(get_vertex_buffer_info(instance.mesh));
+            }
+            current_offset += range;
+            current_object_id += instance.total_instance_count;
+        }
+
+        // Multi draws are only used with packed geometry, all instances share the vertex buffer
+        bucket_buffer_infos.push(
+            vk::DescriptorBufferInfo::builder()
+                .buffer(resource_bundle.buffers[bucket.instance_transform_buffer.index()].0)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build(),
+        );
+        bucket_buffer_infos.push(get_object_buffer_info(
+            bucket.first_object_id,
+            current_object_id - bucket.first_object_id,
+        ));
+        if use_vertex_data_binding {
+            let mesh = bucket.instances.first().map(|instance| instance.mesh);
+            bucket_buffer_infos.push(get_vertex_buffer_info(mesh.unwrap_or_else(|| MeshHandle::new(0))));
+        }
+    }
+
+    let layout_bindings: Vec<vk::DescriptorSetLayoutBinding> = (0..binding_count)
+        .map(|binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding as _)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .build()
+        })
+        .collect();
+
+    // Per-instance bindings are pushed directly into command buffers, no need to preallocate anything
+    if use_push_descriptors {
+        let descriptor_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder()
+                .flags(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR)
+                .bindings(&layout_bindings)
+                .build(),
+        );
+        return (
+            vk::DescriptorPool::null(),
+            descriptor_layout,
+            Vec::new(),
+            instance_buffer_infos,
+            Vec::new(),
+            bucket_buffer_infos,
+        );
+    }
+
+    let set_count = render_instance_count + resource_bundle.buckets.len();
     let descriptor_pool = factory.create_descriptor_pool(
         &vk::DescriptorPoolCreateInfo::builder()
-            .max_sets(render_instance_count as _)
+            .max_sets(set_count as _)
             .pool_sizes(&[vk::DescriptorPoolSize::builder()
                 .ty(vk::DescriptorType::STORAGE_BUFFER)
-                .descriptor_count(1)
+                .descriptor_count((set_count * binding_count) as _)
                 .build()])
             .build(),
     );
     let descriptor_layout = factory.create_descriptor_set_layout(
         &vk::DescriptorSetLayoutCreateInfo::builder()
-            .bindings(&[vk::DescriptorSetLayoutBinding::builder()
-                .binding(0)
-                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-                .descriptor_count(1)
-                .stage_flags(vk::ShaderStageFlags::VERTEX)
-                .build()])
+            .bindings(&layout_bindings)
             .build(),
     );
 
-    let temp_per_descriptor_layouts = vec![descriptor_layout; render_instance_count];
-    let descriptor_sets = factory.allocate_descriptor_sets(
+    // Instance sets go first, followed by bucket sets
+    let temp_per_descriptor_layouts = vec![descriptor_layout; set_count];
+    let mut descriptor_sets = factory.allocate_descriptor_sets(
         &vk::DescriptorSetAllocateInfo::builder()
             .descriptor_pool(descriptor_pool)
             .set_layouts(&temp_per_descriptor_layouts)
             .build(),
     );
-
-    let mut temp_write_infos = Vec::with_capacity(render_instance_count);
-    let mut descriptor_writes = Vec::with_capacity(render_instance_count);
-    {
-        let mut current_descriptor_set = 0;
-        for bucket in &resource_bundle.buckets {
-            let mut current_offset = 0;
-            for instance in &bucket.instances {
-                let range = instance.total_instance_count * std::mem::size_of::<[f32; 16]>();
-                let current_write_info = temp_write_infos.len();
-                temp_write_infos.push(
-                    vk::DescriptorBufferInfo::builder()
-                        .buffer(resource_bundle.buffers[bucket.instance_transform_buffer].0)
-                        .offset(current_offset as _)
-                        .range(range as _)
-                        .build(),
-                );
-
-                descriptor_writes.push(
-                    vk::WriteDescriptorSet::builder()
-                        .dst_set(descriptor_sets[current_descriptor_set])
-                        .dst_binding(0)
-                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
-                        .buffer_info(&temp_write_infos[current_write_info..current_write_info + 1])
-                        .build(),
-                );
-                current_offset += range;
-                current_descriptor_set += 1;
-            }
-        }
-    }
+    let bucket_descriptor_sets = descriptor_sets.split_off(render_instance_count);
+
+    let descriptor_writes: Vec<vk::WriteDescriptorSet> = descriptor_sets
+        .iter()
+        .zip(instance_buffer_infos.chunks_exact(binding_count))
+        .chain(
+            bucket_descriptor_sets
+                .iter()
+                .zip(bucket_buffer_infos.chunks_exact(binding_count)),
+        )
+        .flat_map(|(descriptor_set, buffer_infos)| make_buffer_writes(*descriptor_set, buffer_infos))
+        .collect();
     factory.update_descriptor_sets(&descriptor_writes, &[]);
 
-    (descriptor_pool, descriptor_layout, descriptor_sets)
+    (
+        descriptor_pool,
+        descriptor_layout,
+        descriptor_sets,
+        instance_buffer_infos,
+        bucket_descriptor_sets,
+        bucket_buffer_infos,
+    )
 }
 
 fn initialize_pipelines(
-    resource_bundle: &ResourceBundle,
-    shader_module_bundle: &ShaderModuleBundle,
-    render_layer: &RenderLayer,
+    parameters: &PipelineBundleParameters,
     descriptor_layout: vk::DescriptorSetLayout,
-    extra_descriptor_layouts: &[vk::DescriptorSetLayout],
     factory: &mut DeviceFactory,
-) -> (vk::PipelineCache, Vec<vk::PipelineLayout>, Vec<vk::Pipeline>) {
+) -> (
+    vk::PipelineCache,
+    Vec<vk::PipelineLayout>,
+    Vec<vk::Pipeline>,
+    Vec<vk::Pipeline>,
+) {
+    let resource_bundle = parameters.resource_bundle;
+    let shader_module_bundle = parameters.shader_module_bundle;
+    let render_layer = parameters.render_layer;
+    let extra_descriptor_layouts = parameters.descriptor_set_layouts;
+    let use_vertex_pulling = parameters.use_vertex_pulling;
+    let blending = parameters.blending;
+
     assert!(
         shader_module_bundle.shader_stages.len() == resource_bundle.materials.len(),
         "incompatible stage bundle, shader stages are not directly mapped to bundle materials"
@@ -174,7 +370,8 @@ fn initialize_pipelines(
     let mut temp_shader_stages = Vec::with_capacity(resource_bundle.materials.len() * max_shader_stages);
     let mut temp_vertex_bindings = Vec::with_capacity(resource_bundle.materials.len());
     let mut temp_attributes = Vec::with_capacity(resource_bundle.materials.len() * max_vertex_attributes);
-    let mut temp_attachments = Vec::with_capacity(resource_bundle.materials.len());
+    let color_attachment_count = render_layer.get_render_image_count();
+    let mut temp_attachments = Vec::with_capacity(resource_bundle.materials.len() * color_attachment_count);
     let mut temp_dynamic_state_values = Vec::with_capacity(resource_bundle.materials.len() * 2);
 
     let mut temp_vertex_input_states = Vec::with_capacity(resource_bundle.materials.len());
@@ -187,16 +384,26 @@ fn initialize_pipelines(
     let mut temp_color_blend_states = Vec::with_capacity(resource_bundle.materials.len());
     let mut temp_dynamic_states = Vec::with_capacity(resource_bundle.materials.len());
     let mut temp_pipelines = Vec::with_capacity(resource_bundle.materials.len());
+    let mut temp_pipeline_materials = Vec::with_capacity(resource_bundle.materials.len());
 
     let mut temp_descriptor_layouts = vec![vk::DescriptorSetLayout::null(); 2 + extra_descriptor_layouts.len()];
     for (layout_id, layout) in extra_descriptor_layouts.iter().enumerate() {
         temp_descriptor_layouts[2 + layout_id] = *layout;
     }
 
+    // Material pipelines are linked from shared libraries when they're available
+    let pipeline_cache = factory.create_pipeline_cache(
+        &vk::PipelineCacheCreateInfo::builder()
+            .initial_data(parameters.pipeline_cache_data)
+            .build(),
+    );
+    let mut library_cache = PipelineLibraryCache::default();
+    let mut temp_pipeline_libraries = Vec::with_capacity(resource_bundle.materials.len());
+
     let entry_point = std::ffi::CString::new("main").unwrap();
     let mut pipeline_layouts = Vec::with_capacity(resource_bundle.materials.len());
     for (material_id, disk_material) in resource_bundle.materials.iter().enumerate() {
-        temp_descriptor_layouts[0] = resource_bundle.descriptor_layouts[disk_material.material_layout];
+        temp_descriptor_layouts[0] = resource_bundle.descriptor_layouts[disk_material.material_layout.index()];
         temp_descriptor_layouts[1] = descriptor_layout;
 
         let temp_push_constant_ranges = [
@@ -218,9 +425,25 @@ fn initialize_pipelines(
                 .push_constant_ranges(&temp_push_constant_ranges)
                 .build(),
         );
+        pipeline_layouts.push(pipeline_layout);
+
+        let blend_attachments = match blending {
+            PipelineBlending::Opaque => None,
+            PipelineBlending::SkipAlphaBlended if disk_material.fragment_alpha_blend => continue,
+            PipelineBlending::SkipAlphaBlended => None,
+            PipelineBlending::AlphaBlendedOnly(attachments) if disk_material.fragment_alpha_blend => Some(attachments),
+            PipelineBlending::AlphaBlendedOnly(_) => continue,
+            PipelineBlending::AllBlended(attachments) => Some(attachments),
+        };
 
+        // Vertex pulling pipelines have no vertex input, shaders read vertex buffers themselves
         let vertex_attributes_start = temp_attributes.len();
-        for attribute in &disk_material.vertex_format {
+        let vertex_format: &[VertexAttribute] = if use_vertex_pulling {
+            &[]
+        } else {
+            &disk_material.vertex_format
+        };
+        for attribute in vertex_format {
             temp_attributes.push(
                 vk::VertexInputAttributeDescription::builder()
                     .location(attribute.attribute_location)
@@ -285,13 +508,15 @@ fn initialize_pipelines(
         }
 
         let vertex_bindings_start = temp_vertex_bindings.len();
-        temp_vertex_bindings.push(
-            vk::VertexInputBindingDescription::builder()
-                .binding(0)
-                .stride(disk_material.vertex_stride)
-                .input_rate(vk::VertexInputRate::VERTEX)
-                .build(),
-        );
+        if !use_vertex_pulling {
+            temp_vertex_bindings.push(
+                vk::VertexInputBindingDescription::builder()
+                    .binding(0)
+                    .stride(disk_material.vertex_stride)
+                    .input_rate(vk::VertexInputRate::VERTEX)
+                    .build(),
+            );
+        }
 
         let states_start = temp_vertex_input_states.len();
         temp_vertex_input_states.push(
@@ -327,25 +552,32 @@ fn initialize_pipelines(
         temp_depth_stencil_states.push(
             vk::PipelineDepthStencilStateCreateInfo::builder()
                 .flags(Default::default())
-                .depth_test_enable(true)
-                .depth_write_enable(true)
+                .depth_test_enable(blend_attachments.is_none())
+                .depth_write_enable(blend_attachments.is_none())
                 .depth_compare_op(vk::CompareOp::GREATER_OR_EQUAL)
                 .stencil_test_enable(false)
                 .build(),
         );
 
         let attachments_start = temp_attachments.len();
-        temp_attachments.push(
-            vk::PipelineColorBlendAttachmentState::builder()
-                .blend_enable(false)
-                .color_write_mask(
-                    vk::ColorComponentFlags::R
-                        | vk::ColorComponentFlags::G
-                        | vk::ColorComponentFlags::B
-                        | vk::ColorComponentFlags::A,
-                )
-                .build(),
-        );
+        if let Some(blend_attachments) = blend_attachments {
+            assert_eq!(blend_attachments.len(), color_attachment_count);
+            temp_attachments.extend_from_slice(blend_attachments);
+        } else {
+            for _ in 0..color_attachment_count {
+                temp_attachments.push(
+                    vk::PipelineColorBlendAttachmentState::builder()
+                        .blend_enable(false)
+                        .color_write_mask(
+                            vk::ColorComponentFlags::R
+                                | vk::ColorComponentFlags::G
+                                | vk::ColorComponentFlags::B
+                                | vk::ColorComponentFlags::A,
+                        )
+                        .build(),
+                );
+            }
+        }
         temp_color_blend_states.push(
             vk::PipelineColorBlendStateCreateInfo::builder()
                 .attachments(&temp_attachments[attachments_start..temp_attachments.len()])
@@ -373,20 +605,148 @@ fn initialize_pipelines(
             .color_blend_state(&temp_color_blend_states[states_start])
             .dynamic_state(&temp_dynamic_states[states_start])
             .layout(pipeline_layout)
-            .render_pass(render_layer.get_render_pass())
             .subpass(0)
             .base_pipeline_handle(vk::Pipeline::null())
             .base_pipeline_index(0)
             .build();
 
-        pipeline_layouts.push(pipeline_layout);
-        temp_pipelines.push(pipeline_create_info);
+        let pipeline_create_info = render_layer.make_pipeline_create_info(pipeline_create_info);
+        if parameters.use_pipeline_libraries {
+            let shader_stages = &temp_shader_stages[shader_stages_start..temp_shader_stages.len()];
+            let fragment_stage_start = shader_stages
+                .iter()
+                .position(|stage| stage.stage == vk::ShaderStageFlags::FRAGMENT)
+                .unwrap_or(shader_stages.len());
+            let (pre_rasterization_stages, fragment_stages) = shader_stages.split_at(fragment_stage_start);
+            let material_layout = temp_descriptor_layouts[0];
+
+            let vertex_input_library = library_cache.get_or_create(
+                PipelineLibraryKey::VertexInput {
+                    vertex_stride: if use_vertex_pulling {
+                        0
+                    } else {
+                        disk_material.vertex_stride
+                    },
+                    vertex_attributes: vertex_format
+                        .iter()
+                        .map(|attribute| {
+                            (
+                                attribute.attribute_location,
+                                attribute.attribute_format,
+                                attribute.attribute_offset,
+                            )
+                        })
+                        .collect(),
+                },
+                vk::GraphicsPipelineCreateInfo {
+                    p_vertex_input_state: pipeline_create_info.p_vertex_input_state,
+                    p_input_assembly_state: pipeline_create_info.p_input_assembly_state,
+                    ..Default::default()
+                },
+                pipeline_cache,
+                factory,
+            );
+            let pre_rasterization_library = library_cache.get_or_create(
+                PipelineLibraryKey::PreRasterization {
+                    shader_stages: pre_rasterization_stages.iter().map(|stage| stage.module).collect(),
+                    cull_mode: disk_material.fragment_cull_flags,
+                    material_layout,
+                },
+                vk::GraphicsPipelineCreateInfo {
+                    stage_count: pre_rasterization_stages.len() as _,
+                    p_stages: pre_rasterization_stages.as_ptr(),
+                    p_vertex_input_state: std::ptr::null(),
+                    p_input_assembly_state: std::ptr::null(),
+                    p_multisample_state: std::ptr::null(),
+                    p_depth_stencil_state: std::ptr::null(),
+                    p_color_blend_state: std::ptr::null(),
+                    ..pipeline_create_info
+                },
+                pipeline_cache,
+                factory,
+            );
+            let fragment_shader_library = library_cache.get_or_create(
+                PipelineLibraryKey::FragmentShader {
+                    shader_stage: fragment_stages
+                        .first()
+                        .map_or(vk::ShaderModule::null(), |stage| stage.module),
+                    depth_test: blend_attachments.is_none(),
+                    material_layout,
+                },
+                vk::GraphicsPipelineCreateInfo {
+                    stage_count: fragment_stages.len() as _,
+                    p_stages: fragment_stages.as_ptr(),
+                    p_vertex_input_state: std::ptr::null(),
+                    p_input_assembly_state: std::ptr::null(),
+                    p_tessellation_state: std::ptr::null(),
+                    p_viewport_state: std::ptr::null(),
+                    p_rasterization_state: std::ptr::null(),
+                    p_color_blend_state: std::ptr::null(),
+                    p_dynamic_state: std::ptr::null(),
+                    ..pipeline_create_info
+                },
+                pipeline_cache,
+                factory,
+            );
+            let fragment_output_library = library_cache.get_or_create(
+                PipelineLibraryKey::FragmentOutput {
+                    blended: blend_attachments.is_some(),
+                },
+                vk::GraphicsPipelineCreateInfo {
+                    stage_count: 0,
+                    p_stages: std::ptr::null(),
+                    p_vertex_input_state: std::ptr::null(),
+                    p_input_assembly_state: std::ptr::null(),
+                    p_tessellation_state: std::ptr::null(),
+                    p_viewport_state: std::ptr::null(),
+                    p_rasterization_state: std::ptr::null(),
+                    p_depth_stencil_state: std::ptr::null(),
+                    p_dynamic_state: std::ptr::null(),
+                    layout: vk::PipelineLayout::null(),
+                    ..pipeline_create_info
+                },
+                pipeline_cache,
+                factory,
+            );
+            temp_pipeline_libraries.push([
+                vertex_input_library,
+                pre_rasterization_library,
+                fragment_shader_library,
+                fragment_output_library,
+            ]);
+            temp_pipelines.push(vk::GraphicsPipelineCreateInfo {
+                layout: pipeline_layout,
+                ..Default::default()
+            });
+        } else {
+            temp_pipelines.push(pipeline_create_info);
+        }
+        temp_pipeline_materials.push(material_id);
     }
 
-    log::info!("allocating {} graphics pipelines", temp_pipelines.len());
+    // Libraries of every pipeline are chained once all of them are created, so that the infos don't move
+    let temp_library_infos: Vec<vk::PipelineLibraryCreateInfoKHR> = temp_pipeline_libraries
+        .iter()
+        .map(|libraries| vk::PipelineLibraryCreateInfoKHR::builder().libraries(libraries).build())
+        .collect();
+    for (pipeline, library_info) in temp_pipelines.iter_mut().zip(&temp_library_infos) {
+        pipeline.p_next = library_info as *const vk::PipelineLibraryCreateInfoKHR as _;
+    }
+    let pipeline_libraries = library_cache.into_libraries();
+
+    log::info!(
+        "allocating {} graphics pipelines from {} pipeline libraries",
+        temp_pipelines.len(),
+        pipeline_libraries.len()
+    );
 
-    let pipeline_cache = factory.create_pipeline_cache(&vk::PipelineCacheCreateInfo::default());
-    let pipelines = factory.create_graphics_pipelines(pipeline_cache, &temp_pipelines);
+    let mut pipelines = vec![vk::Pipeline::null(); resource_bundle.materials.len()];
+    if !temp_pipelines.is_empty() {
+        let created_pipelines = factory.create_graphics_pipelines(pipeline_cache, &temp_pipelines);
+        for (material_id, pipeline) in temp_pipeline_materials.iter().zip(created_pipelines) {
+            pipelines[*material_id] = pipeline;
+        }
+    }
 
-    (pipeline_cache, pipeline_layouts, pipelines)
+    (pipeline_cache, pipeline_layouts, pipelines, pipeline_libraries)
 }
@@ -0,0 +1,78 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_vk::*;
+
+// Everything that goes into a partial pipeline, materials with equal keys share the library
+#[derive(PartialEq, Eq, Hash)]
+pub(crate) enum PipelineLibraryKey {
+    VertexInput {
+        vertex_stride: u32,
+        vertex_attributes: Vec<(u32, vk::Format, u32)>, // location, format and offset
+    },
+    PreRasterization {
+        shader_stages: Vec<vk::ShaderModule>,
+        cull_mode: vk::CullModeFlags,
+        material_layout: vk::DescriptorSetLayout,
+    },
+    FragmentShader {
+        shader_stage: vk::ShaderModule,
+        depth_test: bool,
+        material_layout: vk::DescriptorSetLayout,
+    },
+    FragmentOutput {
+        blended: bool,
+    },
+}
+
+impl PipelineLibraryKey {
+    fn get_library_flags(&self) -> vk::Flags {
+        match self {
+            Self::VertexInput { .. } => GRAPHICS_PIPELINE_LIBRARY_VERTEX_INPUT_INTERFACE_EXT,
+            Self::PreRasterization { .. } => GRAPHICS_PIPELINE_LIBRARY_PRE_RASTERIZATION_SHADERS_EXT,
+            Self::FragmentShader { .. } => GRAPHICS_PIPELINE_LIBRARY_FRAGMENT_SHADER_EXT,
+            Self::FragmentOutput { .. } => GRAPHICS_PIPELINE_LIBRARY_FRAGMENT_OUTPUT_INTERFACE_EXT,
+        }
+    }
+}
+
+// Libraries are created on first use and linked into material pipelines without link time optimization,
+// so that the cost of linking stays low for bundles with many materials
+#[derive(Default)]
+pub(crate) struct PipelineLibraryCache {
+    libraries: std::collections::HashMap<PipelineLibraryKey, vk::Pipeline>,
+}
+
+impl PipelineLibraryCache {
+    // Create info only needs the state of the library kind, render pass and rendering info are kept in the chain
+    pub fn get_or_create(
+        &mut self,
+        key: PipelineLibraryKey,
+        create_info: vk::GraphicsPipelineCreateInfo,
+        pipeline_cache: vk::PipelineCache,
+        factory: &mut DeviceFactory,
+    ) -> vk::Pipeline {
+        if let Some(library) = self.libraries.get(&key) {
+            return *library;
+        }
+
+        let library_info = GraphicsPipelineLibraryCreateInfoEXT {
+            p_next: create_info.p_next as *mut _,
+            flags: key.get_library_flags(),
+            ..Default::default()
+        };
+        let mut create_info = create_info;
+        create_info.flags |= vk::PipelineCreateFlags::LIBRARY_KHR;
+        create_info.p_next = &library_info as *const GraphicsPipelineLibraryCreateInfoEXT as _;
+
+        let library = factory.create_graphics_pipelines(pipeline_cache, &[create_info])[0];
+        self.libraries.insert(key, library);
+        library
+    }
+
+    pub fn into_libraries(self) -> Vec<vk::Pipeline> {
+        self.libraries.into_values().collect()
+    }
+}
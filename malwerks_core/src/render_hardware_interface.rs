@@ -0,0 +1,175 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Thin backend-neutral interface over the subset of Vulkan that render modules use. Resources are referenced by
+// handles that index backend tables, resource binding is limited to storage buffers and storage images, passes
+// render into images directly and synchronization is a full barrier. Render modules still talk to malwerks_vk
+// directly, this is the layer that they can be moved onto one by one.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RhiBuffer(usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RhiImage(usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RhiPipeline(usize);
+
+impl RhiBuffer {
+    pub fn new(index: usize) -> Self {
+        Self(index)
+    }
+
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+impl RhiImage {
+    pub fn new(index: usize) -> Self {
+        Self(index)
+    }
+
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+impl RhiPipeline {
+    pub fn new(index: usize) -> Self {
+        Self(index)
+    }
+
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RhiFormat {
+    Rgba8Unorm,
+    Rgba16Float,
+    Rgba32Float,
+    R11G11B10Float,
+    Depth32Float,
+}
+
+impl RhiFormat {
+    pub fn is_depth(&self) -> bool {
+        matches!(self, RhiFormat::Depth32Float)
+    }
+
+    pub fn get_pixel_size(&self) -> usize {
+        match self {
+            RhiFormat::Rgba8Unorm => 4,
+            RhiFormat::Rgba16Float => 8,
+            RhiFormat::Rgba32Float => 16,
+            RhiFormat::R11G11B10Float => 4,
+            RhiFormat::Depth32Float => 4,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct RhiBufferUsage {
+    pub index: bool,
+    pub uniform: bool,
+    pub storage: bool,
+    pub indirect: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RhiBufferParameters {
+    pub size: u64,
+    pub usage: RhiBufferUsage,
+    pub host_visible: bool, // required for write_buffer
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RhiImageParameters {
+    pub width: u32,
+    pub height: u32,
+    pub format: RhiFormat,
+    pub render_target: bool,
+    pub storage: bool,
+}
+
+// Shaders are SPIR-V, storage buffers are bound first and storage images follow them in set 0
+pub struct RhiGraphicsPipelineParameters<'a> {
+    pub vertex_shader: &'a [u32],
+    pub fragment_shader: &'a [u32],
+    pub color_formats: &'a [RhiFormat],
+    pub depth_format: Option<RhiFormat>,
+    pub storage_buffer_count: u32,
+    pub storage_image_count: u32,
+    pub push_constant_size: u32,
+}
+
+pub struct RhiComputePipelineParameters<'a> {
+    pub compute_shader: &'a [u32],
+    pub storage_buffer_count: u32,
+    pub storage_image_count: u32,
+    pub push_constant_size: u32,
+}
+
+// Targets are cleared if a clear value is given and loaded otherwise
+pub struct RhiPassParameters<'a> {
+    pub color_targets: &'a [RhiImage],
+    pub depth_target: Option<RhiImage>,
+    pub clear_color: Option<[f32; 4]>,
+    pub clear_depth: Option<f32>,
+}
+
+pub trait RenderHardwareInterface {
+    type Factory;
+    type CommandList;
+
+    fn create_buffer(&mut self, parameters: &RhiBufferParameters, factory: &mut Self::Factory) -> RhiBuffer;
+    fn destroy_buffer(&mut self, buffer: RhiBuffer, factory: &mut Self::Factory);
+    fn write_buffer(&mut self, buffer: RhiBuffer, offset: u64, data: &[u8], factory: &mut Self::Factory);
+
+    fn create_image(&mut self, parameters: &RhiImageParameters, factory: &mut Self::Factory) -> RhiImage;
+    fn destroy_image(&mut self, image: RhiImage, factory: &mut Self::Factory);
+
+    fn create_graphics_pipeline(
+        &mut self,
+        parameters: &RhiGraphicsPipelineParameters,
+        factory: &mut Self::Factory,
+    ) -> RhiPipeline;
+    fn create_compute_pipeline(
+        &mut self,
+        parameters: &RhiComputePipelineParameters,
+        factory: &mut Self::Factory,
+    ) -> RhiPipeline;
+    fn destroy_pipeline(&mut self, pipeline: RhiPipeline, factory: &mut Self::Factory);
+
+    fn begin_pass(&mut self, parameters: &RhiPassParameters, command_list: &mut Self::CommandList);
+    fn end_pass(&mut self, command_list: &mut Self::CommandList);
+
+    // Makes all previous writes visible to all following commands, can't be used inside passes
+    fn barrier(&mut self, command_list: &mut Self::CommandList);
+
+    fn bind_pipeline(&mut self, pipeline: RhiPipeline, command_list: &mut Self::CommandList);
+    fn bind_resources(
+        &mut self,
+        pipeline: RhiPipeline,
+        storage_buffers: &[RhiBuffer],
+        storage_images: &[RhiImage],
+        command_list: &mut Self::CommandList,
+    );
+    fn push_constants(&mut self, pipeline: RhiPipeline, data: &[u8], command_list: &mut Self::CommandList);
+
+    fn draw(&mut self, vertex_count: u32, instance_count: u32, command_list: &mut Self::CommandList);
+    fn draw_indexed_indirect(
+        &mut self,
+        index_buffer: RhiBuffer,
+        indirect_buffer: RhiBuffer,
+        offset: u64,
+        draw_count: u32,
+        command_list: &mut Self::CommandList,
+    );
+    fn dispatch(&mut self, group_count: [u32; 3], command_list: &mut Self::CommandList);
+    fn dispatch_indirect(&mut self, buffer: RhiBuffer, offset: u64, command_list: &mut Self::CommandList);
+}
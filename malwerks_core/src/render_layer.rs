@@ -5,6 +5,8 @@
 
 use malwerks_vk::*;
 
+use crate::transient_image_pool::*;
+
 pub struct RenderImageParameters {
     pub image_format: vk::Format,
     pub image_usage: vk::ImageUsageFlags,
@@ -21,11 +23,29 @@ pub struct RenderPassParameters<'a> {
     pub preserve_attachments: Option<&'a [u32]>,
 }
 
+// Color attachment that references a render image of another layer, existing contents are preserved
+pub struct SharedImageParameters {
+    pub image_index: usize,
+    pub image_format: vk::Format,
+    pub initial_layout: vk::ImageLayout,
+}
+
+// Images of a transient image pool used as attachments, indices map to `render_image_parameters`
+// and `depth_image_parameters` of the layer and formats have to match
+pub struct TransientLayerImages<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub transient_images: &'a TransientImagePool,
+    pub image_indices: &'a [usize],
+    pub depth_image_index: Option<usize>,
+}
+
 pub struct RenderLayerParameters<'a> {
     pub render_image_parameters: &'a [RenderImageParameters],
     pub depth_image_parameters: Option<RenderImageParameters>,
     pub render_pass_parameters: &'a [RenderPassParameters<'a>],
     pub render_pass_dependencies: Option<&'a [vk::SubpassDependency]>,
+    pub view_mask: u32, // non-zero enables multiview, every image gets one array layer per view
 }
 
 pub struct RenderLayer {
@@ -37,10 +57,19 @@ pub struct RenderLayer {
     signal_fence: FrameLocal<vk::Fence>,
     wait_semaphores: Vec<vk::Semaphore>,
     wait_stage_mask: Vec<vk::PipelineStageFlags>,
-    timestamp_query_pool: vk::QueryPool,
+    submitted_waits: Vec<(vk::Semaphore, vk::PipelineStageFlags)>, // waits of the last submission
+    query_scope: QueryScope,                                       // from acquire_frame() to end_render_pass()
     render_images: Vec<RenderImage>,
     depth_image: Option<RenderImage>,
+    color_formats: Vec<vk::Format>,
+    depth_format: Option<vk::Format>,
     clear_values: Vec<vk::ClearValue>,
+    dynamic_rendering: Option<DynamicRendering>,
+    viewport_rect: Option<vk::Rect2D>,
+    scissor_rect: Option<vk::Rect2D>,
+    active_viewport: vk::Viewport,
+    active_scissor: vk::Rect2D,
+    view_mask: u32,
 }
 
 impl RenderLayer {
@@ -51,53 +80,29 @@ impl RenderLayer {
         height: u32,
         layer_parameters: &RenderLayerParameters<'a>,
     ) -> Self {
-        let command_pool = FrameLocal::new(|_| {
-            factory.create_command_pool(
-                &vk::CommandPoolCreateInfo::builder()
-                    .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
-                    .queue_family_index(device.get_graphics_queue_index())
-                    .build(),
-            )
-        });
-        let command_buffer = FrameLocal::new(|f| {
-            factory.allocate_command_buffers(
-                &vk::CommandBufferAllocateInfo::builder()
-                    .command_buffer_count(1)
-                    .command_pool(*command_pool.get_frame(f))
-                    .level(vk::CommandBufferLevel::PRIMARY)
-                    .build(),
-            )[0]
-        });
-        let signal_semaphore = FrameLocal::new(|_| factory.create_semaphore(&vk::SemaphoreCreateInfo::default()));
-        let signal_fence = FrameLocal::new(|_| {
-            factory.create_fence(
-                &vk::FenceCreateInfo::builder()
-                    .flags(vk::FenceCreateFlags::SIGNALED)
-                    .build(),
-            )
-        });
-
-        let timestamp_query_pool = factory.create_query_pool(
-            &vk::QueryPoolCreateInfo::builder()
-                .query_type(vk::QueryType::TIMESTAMP)
-                .query_count((2 * NUM_BUFFERED_GPU_FRAMES) as _)
-                .build(),
-        );
-
-        let mut clear_values = Vec::with_capacity(
-            layer_parameters.render_image_parameters.len()
-                + (layer_parameters.depth_image_parameters.is_some() as usize),
+        let view_mask = layer_parameters.view_mask;
+        let layer_count = get_view_layer_count(view_mask);
+        assert!(
+            view_mask == 0 || device.is_multiview_enabled(),
+            "multiview render layer requested, but multiview is not enabled"
         );
-        let mut all_image_views = Vec::with_capacity(clear_values.len());
 
         let mut render_images = Vec::with_capacity(layer_parameters.render_image_parameters.len());
         for parameters in layer_parameters.render_image_parameters {
-            let (image, image_view) =
-                allocate_render_image(device, factory, width, height, parameters, vk::ImageAspectFlags::COLOR);
-
-            clear_values.push(parameters.image_clear_value);
-            all_image_views.push(image_view);
-            render_images.push(RenderImage { image, image_view });
+            let (image, image_view) = allocate_render_image(
+                device,
+                factory,
+                width,
+                height,
+                layer_count,
+                parameters,
+                vk::ImageAspectFlags::COLOR,
+            );
+            render_images.push(RenderImage {
+                image,
+                image_view,
+                owned: true,
+            });
         }
 
         let depth_image = if let Some(depth_image_parameters) = layer_parameters.depth_image_parameters.as_ref() {
@@ -106,17 +111,181 @@ impl RenderLayer {
                 factory,
                 width,
                 height,
+                layer_count,
                 &depth_image_parameters,
                 vk::ImageAspectFlags::DEPTH,
             );
-            clear_values.push(depth_image_parameters.image_clear_value);
-            all_image_views.push(image_view);
-
-            Some(RenderImage { image, image_view })
+            Some(RenderImage {
+                image,
+                image_view,
+                owned: true,
+            })
         } else {
             None
         };
 
+        Self::from_render_images(
+            device,
+            factory,
+            width,
+            height,
+            layer_parameters,
+            render_images,
+            depth_image,
+        )
+    }
+
+    // Creates a layer on top of images of a transient image pool, the pool owns the images
+    pub fn from_transient_images<'a>(
+        device: &Device,
+        factory: &mut DeviceFactory,
+        layer_parameters: &RenderLayerParameters<'a>,
+        layer_images: &TransientLayerImages,
+    ) -> Self {
+        let &TransientLayerImages {
+            width,
+            height,
+            transient_images,
+            image_indices,
+            depth_image_index,
+        } = layer_images;
+
+        assert_eq!(
+            layer_parameters.view_mask, 0,
+            "transient images don't support multiview"
+        );
+        assert_eq!(
+            image_indices.len(),
+            layer_parameters.render_image_parameters.len(),
+            "every render image needs a transient image"
+        );
+        assert_eq!(
+            depth_image_index.is_some(),
+            layer_parameters.depth_image_parameters.is_some(),
+            "depth image needs a transient image"
+        );
+
+        let get_transient_image = |index: usize, image_format: vk::Format| {
+            assert_eq!(
+                transient_images.get_image_format(index),
+                image_format,
+                "transient image format doesn't match"
+            );
+            let (image, image_view) = transient_images.get_image(index);
+            RenderImage {
+                image: image.clone(),
+                image_view,
+                owned: false,
+            }
+        };
+        let render_images = image_indices
+            .iter()
+            .zip(layer_parameters.render_image_parameters)
+            .map(|(index, parameters)| get_transient_image(*index, parameters.image_format))
+            .collect();
+        let depth_image = depth_image_index.map(|index| {
+            get_transient_image(
+                index,
+                layer_parameters.depth_image_parameters.as_ref().unwrap().image_format,
+            )
+        });
+
+        Self::from_render_images(
+            device,
+            factory,
+            width,
+            height,
+            layer_parameters,
+            render_images,
+            depth_image,
+        )
+    }
+
+    fn from_render_images<'a>(
+        device: &Device,
+        factory: &mut DeviceFactory,
+        width: u32,
+        height: u32,
+        layer_parameters: &RenderLayerParameters<'a>,
+        render_images: Vec<RenderImage>,
+        depth_image: Option<RenderImage>,
+    ) -> Self {
+        let num_buffered_frames = factory.get_num_buffered_frames();
+        let (command_pool, command_buffer, signal_semaphore, signal_fence, query_scope) =
+            create_submission_resources(device, factory);
+
+        let view_mask = layer_parameters.view_mask;
+
+        let mut clear_values: Vec<vk::ClearValue> = layer_parameters
+            .render_image_parameters
+            .iter()
+            .map(|parameters| parameters.image_clear_value)
+            .collect();
+        let mut all_image_views: Vec<vk::ImageView> = render_images.iter().map(|image| image.image_view).collect();
+        if let (Some(depth_image_parameters), Some(depth_image)) =
+            (layer_parameters.depth_image_parameters.as_ref(), depth_image.as_ref())
+        {
+            clear_values.push(depth_image_parameters.image_clear_value);
+            all_image_views.push(depth_image.image_view);
+        }
+        let color_formats: Vec<vk::Format> = layer_parameters
+            .render_image_parameters
+            .iter()
+            .map(|parameters| parameters.image_format)
+            .collect();
+
+        // Dynamic rendering has no notion of subpasses, so only simple layouts can skip the render pass
+        let use_dynamic_rendering = device.is_dynamic_rendering_enabled()
+            && layer_parameters.render_pass_parameters.len() == 1
+            && layer_parameters.render_pass_parameters[0].input_attachments.is_none()
+            && layer_parameters.render_pass_parameters[0].resolve_attachments.is_none()
+            && layer_parameters.render_pass_dependencies.is_none();
+        if use_dynamic_rendering {
+            // Pipeline rendering info points into its own copy of the formats
+            let rendering_color_formats = color_formats.clone();
+            let depth_format = match layer_parameters.depth_image_parameters.as_ref() {
+                Some(parameters) => parameters.image_format,
+                None => vk::Format::UNDEFINED,
+            };
+            let pipeline_rendering_info = PipelineRenderingCreateInfoKHR {
+                view_mask,
+                color_attachment_count: rendering_color_formats.len() as _,
+                p_color_attachment_formats: rendering_color_formats.as_ptr(),
+                depth_attachment_format: depth_format,
+                ..Default::default()
+            };
+
+            return Self {
+                render_pass: vk::RenderPass::null(),
+                framebuffer: FrameLocal::new(num_buffered_frames, |_| vk::Framebuffer::null()),
+                command_pool,
+                command_buffer,
+                signal_semaphore,
+                signal_fence,
+                wait_semaphores: Vec::new(),
+                wait_stage_mask: Vec::new(),
+                submitted_waits: Vec::new(),
+                query_scope,
+                render_images,
+                depth_image,
+                color_formats,
+                depth_format: layer_parameters
+                    .depth_image_parameters
+                    .as_ref()
+                    .map(|parameters| parameters.image_format),
+                clear_values,
+                dynamic_rendering: Some(DynamicRendering {
+                    _color_formats: rendering_color_formats,
+                    pipeline_rendering_info,
+                }),
+                viewport_rect: None,
+                scissor_rect: None,
+                active_viewport: vk::Viewport::default(),
+                active_scissor: vk::Rect2D::default(),
+                view_mask,
+            };
+        }
+
         let render_pass = {
             let mut attachments = Vec::with_capacity(render_images.len() + (depth_image.is_some() as usize));
             let mut color_attachments = Vec::with_capacity(render_images.len());
@@ -183,6 +352,14 @@ impl RenderLayer {
                 subpasses.push(subpass_builder.build());
             }
 
+            // All subpasses render every view, views are rendered concurrently
+            let view_masks = vec![view_mask; subpasses.len()];
+            let correlation_masks = [view_mask];
+            let mut multiview_create_info = vk::RenderPassMultiviewCreateInfo::builder()
+                .view_masks(&view_masks)
+                .correlation_masks(&correlation_masks)
+                .build();
+
             let mut render_pass_builder = vk::RenderPassCreateInfo::builder()
                 .flags(Default::default())
                 .attachments(&attachments)
@@ -190,11 +367,14 @@ impl RenderLayer {
             if let Some(dependencies) = layer_parameters.render_pass_dependencies {
                 render_pass_builder = render_pass_builder.dependencies(dependencies)
             }
+            if view_mask != 0 {
+                render_pass_builder = render_pass_builder.push_next(&mut multiview_create_info);
+            }
 
             factory.create_render_pass(&render_pass_builder.build())
         };
 
-        let framebuffer = FrameLocal::new(|_| {
+        let framebuffer = FrameLocal::new(num_buffered_frames, |_| {
             factory.create_framebuffer(
                 &vk::FramebufferCreateInfo::builder()
                     .flags(Default::default())
@@ -216,10 +396,22 @@ impl RenderLayer {
             signal_fence,
             wait_semaphores: Vec::new(),
             wait_stage_mask: Vec::new(),
-            timestamp_query_pool,
+            submitted_waits: Vec::new(),
+            query_scope,
             render_images,
             depth_image,
+            color_formats,
+            depth_format: layer_parameters
+                .depth_image_parameters
+                .as_ref()
+                .map(|parameters| parameters.image_format),
             clear_values,
+            dynamic_rendering: None,
+            viewport_rect: None,
+            scissor_rect: None,
+            active_viewport: vk::Viewport::default(),
+            active_scissor: vk::Rect2D::default(),
+            view_mask,
         }
     }
 
@@ -228,41 +420,118 @@ impl RenderLayer {
         factory: &mut DeviceFactory,
         render_pass: vk::RenderPass,
         framebuffer: FrameLocal<vk::Framebuffer>,
+        color_formats: Vec<vk::Format>,
         clear_values: Vec<vk::ClearValue>,
     ) -> Self {
-        let command_pool = FrameLocal::new(|_| {
-            factory.create_command_pool(
-                &vk::CommandPoolCreateInfo::builder()
-                    .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
-                    .queue_family_index(device.get_graphics_queue_index())
-                    .build(),
-            )
-        });
-        let command_buffer = FrameLocal::new(|f| {
-            factory.allocate_command_buffers(
-                &vk::CommandBufferAllocateInfo::builder()
-                    .command_buffer_count(1)
-                    .command_pool(*command_pool.get_frame(f))
-                    .level(vk::CommandBufferLevel::PRIMARY)
+        let (command_pool, command_buffer, signal_semaphore, signal_fence, query_scope) =
+            create_submission_resources(device, factory);
+
+        Self {
+            render_pass,
+            framebuffer,
+            command_pool,
+            command_buffer,
+            signal_semaphore,
+            signal_fence,
+            wait_semaphores: Vec::new(),
+            wait_stage_mask: Vec::new(),
+            submitted_waits: Vec::new(),
+            query_scope,
+            render_images: Vec::new(),
+            depth_image: None,
+            color_formats,
+            depth_format: None,
+            clear_values,
+            dynamic_rendering: None,
+            viewport_rect: None,
+            scissor_rect: None,
+            active_viewport: vk::Viewport::default(),
+            active_scissor: vk::Rect2D::default(),
+            view_mask: 0,
+        }
+    }
+
+    // Creates a layer that renders on top of color images owned by the source layer,
+    // images are left in COLOR_ATTACHMENT_OPTIMAL layout at the end of the render pass
+    pub fn from_shared_images(
+        device: &Device,
+        factory: &mut DeviceFactory,
+        source_layer: &RenderLayer,
+        width: u32,
+        height: u32,
+        shared_images: &[SharedImageParameters],
+    ) -> Self {
+        let num_buffered_frames = factory.get_num_buffered_frames();
+        let (command_pool, command_buffer, signal_semaphore, signal_fence, query_scope) =
+            create_submission_resources(device, factory);
+
+        let mut attachments = Vec::with_capacity(shared_images.len());
+        let mut color_attachments = Vec::with_capacity(shared_images.len());
+        let mut render_images = Vec::with_capacity(shared_images.len());
+        for parameters in shared_images {
+            let source_image = &source_layer.render_images[parameters.image_index];
+            color_attachments.push(
+                vk::AttachmentReference::builder()
+                    .attachment(attachments.len() as _)
+                    .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
                     .build(),
-            )[0]
-        });
-        let signal_semaphore = FrameLocal::new(|_| factory.create_semaphore(&vk::SemaphoreCreateInfo::default()));
-        let signal_fence = FrameLocal::new(|_| {
-            factory.create_fence(
-                &vk::FenceCreateInfo::builder()
-                    .flags(vk::FenceCreateFlags::SIGNALED)
+            );
+            attachments.push(
+                vk::AttachmentDescription::builder()
+                    .flags(Default::default())
+                    .format(parameters.image_format)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .load_op(vk::AttachmentLoadOp::LOAD)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                    .initial_layout(parameters.initial_layout)
+                    .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
                     .build(),
-            )
-        });
+            );
+            render_images.push(RenderImage {
+                image: source_image.image.clone(),
+                image_view: source_image.image_view,
+                owned: false,
+            });
+        }
 
-        let timestamp_query_pool = factory.create_query_pool(
-            &vk::QueryPoolCreateInfo::builder()
-                .query_type(vk::QueryType::TIMESTAMP)
-                .query_count((2 * NUM_BUFFERED_GPU_FRAMES) as _)
+        // Dynamic rendering path always clears, so shared images need a render pass
+        let render_pass = factory.create_render_pass(
+            &vk::RenderPassCreateInfo::builder()
+                .flags(Default::default())
+                .attachments(&attachments)
+                .subpasses(&[vk::SubpassDescription::builder()
+                    .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                    .color_attachments(&color_attachments)
+                    .build()])
+                .dependencies(&[vk::SubpassDependency::builder()
+                    .src_subpass(vk::SUBPASS_EXTERNAL)
+                    .dst_subpass(0)
+                    .src_stage_mask(
+                        vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    )
+                    .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                    .src_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .build()])
                 .build(),
         );
 
+        let all_image_views: Vec<vk::ImageView> = render_images.iter().map(|image| image.image_view).collect();
+        let framebuffer = FrameLocal::new(num_buffered_frames, |_| {
+            factory.create_framebuffer(
+                &vk::FramebufferCreateInfo::builder()
+                    .flags(Default::default())
+                    .render_pass(render_pass)
+                    .attachments(&all_image_views)
+                    .width(width)
+                    .height(height)
+                    .layers(1)
+                    .build(),
+            )
+        });
+
         Self {
             render_pass,
             framebuffer,
@@ -272,25 +541,36 @@ impl RenderLayer {
             signal_fence,
             wait_semaphores: Vec::new(),
             wait_stage_mask: Vec::new(),
-            timestamp_query_pool,
-            render_images: Vec::new(),
+            submitted_waits: Vec::new(),
+            query_scope,
+            render_images,
             depth_image: None,
-            clear_values,
+            color_formats: shared_images.iter().map(|parameters| parameters.image_format).collect(),
+            depth_format: None,
+            clear_values: Vec::new(),
+            dynamic_rendering: None,
+            viewport_rect: None,
+            scissor_rect: None,
+            active_viewport: vk::Viewport::default(),
+            active_scissor: vk::Rect2D::default(),
+            view_mask: 0,
         }
     }
 
     pub fn destroy(&mut self, factory: &mut DeviceFactory) {
-        factory.destroy_render_pass(self.render_pass);
-        self.framebuffer.destroy(|res| factory.destroy_framebuffer(*res));
+        if self.dynamic_rendering.is_none() {
+            factory.destroy_render_pass(self.render_pass);
+            self.framebuffer.destroy(|res| factory.destroy_framebuffer(*res));
+        }
         self.command_pool.destroy(|res| factory.destroy_command_pool(*res));
         self.signal_semaphore.destroy(|res| factory.destroy_semaphore(*res));
         self.signal_fence.destroy(|res| factory.destroy_fence(*res));
-        factory.destroy_query_pool(self.timestamp_query_pool);
-        for image in &self.render_images {
+        self.query_scope.destroy(factory);
+        for image in self.render_images.iter().filter(|image| image.owned) {
             factory.deallocate_image(&image.image);
             factory.destroy_image_view(image.image_view);
         }
-        if let Some(depth_image) = self.depth_image.as_ref() {
+        if let Some(depth_image) = self.depth_image.as_ref().filter(|image| image.owned) {
             factory.deallocate_image(&depth_image.image);
             factory.destroy_image_view(depth_image.image_view);
         }
@@ -300,11 +580,53 @@ impl RenderLayer {
         self.render_pass
     }
 
+    pub fn get_pipeline_rendering_info(&self) -> Option<&PipelineRenderingCreateInfoKHR> {
+        match &self.dynamic_rendering {
+            Some(dynamic_rendering) => Some(&dynamic_rendering.pipeline_rendering_info),
+            None => None,
+        }
+    }
+
+    // Points the pipeline either to the render pass or to the attachment formats if dynamic rendering is used
+    pub fn make_pipeline_create_info(
+        &self,
+        create_info: vk::GraphicsPipelineCreateInfo,
+    ) -> vk::GraphicsPipelineCreateInfo {
+        let mut create_info = create_info;
+        create_info.render_pass = self.render_pass;
+        if let Some(pipeline_rendering_info) = self.get_pipeline_rendering_info() {
+            assert!(create_info.p_next.is_null());
+            create_info.p_next = pipeline_rendering_info as *const PipelineRenderingCreateInfoKHR as _;
+        }
+        create_info
+    }
+
+    pub fn get_view_mask(&self) -> u32 {
+        self.view_mask
+    }
+
+    // Number of array layers in every render image, one per view
+    pub fn get_view_layer_count(&self) -> u32 {
+        get_view_layer_count(self.view_mask)
+    }
+
+    pub fn get_render_image_count(&self) -> usize {
+        self.render_images.len()
+    }
+
     pub fn get_render_image(&self, index: usize) -> (vk::Image, vk::ImageView) {
         let image = &self.render_images[index];
         (image.image.0, image.image_view)
     }
 
+    pub fn get_color_format(&self, index: usize) -> vk::Format {
+        self.color_formats[index]
+    }
+
+    pub fn get_depth_format(&self) -> Option<vk::Format> {
+        self.depth_format
+    }
+
     pub fn get_depth_image(&self) -> Option<(vk::Image, vk::ImageView)> {
         match &self.depth_image {
             Some(depth_image) => Some((depth_image.image.0, depth_image.image_view)),
@@ -354,39 +676,176 @@ impl RenderLayer {
                 //.inheritance_info(...)
                 .build(),
         );
-        let start_pass_query = frame_context.current_gpu_frame() * 2;
-        command_buffer.reset_query_pool(self.timestamp_query_pool, start_pass_query as _, 2);
+        self.query_scope.begin(command_buffer, frame_context);
+    }
 
-        command_buffer.write_timestamp(
-            vk::PipelineStageFlags::ALL_GRAPHICS,
-            self.timestamp_query_pool,
-            start_pass_query as _,
-        );
+    // Viewport rect remaps rendering into a sub-rectangle of the target (split-screen),
+    // scissor rect only limits which pixels are touched (picking regions, progressive tiles).
+    // Both stay in effect until reset with None.
+    pub fn set_viewport_rect(&mut self, viewport_rect: Option<vk::Rect2D>) {
+        self.viewport_rect = viewport_rect;
+    }
+
+    pub fn set_scissor_rect(&mut self, scissor_rect: Option<vk::Rect2D>) {
+        self.scissor_rect = scissor_rect;
+    }
+
+    pub fn get_viewport_rect(&self) -> Option<vk::Rect2D> {
+        self.viewport_rect
     }
 
+    pub fn get_scissor_rect(&self) -> Option<vk::Rect2D> {
+        self.scissor_rect
+    }
+
+    // Render area is clipped to the viewport and scissor rects, pipelines use dynamic viewport and scissor
+    // so both are set right after the render pass begins
     pub fn begin_render_pass(&mut self, frame_context: &FrameContext, render_area: vk::Rect2D) {
+        self.update_active_area(render_area);
+        if self.dynamic_rendering.is_some() {
+            self.begin_rendering(frame_context, self.active_scissor);
+        } else {
+            self.begin_render_pass_with_contents(frame_context, vk::SubpassContents::INLINE);
+        }
+
+        let command_buffer = self.command_buffer.get_mut(frame_context);
+        command_buffer.set_viewport(0, &[self.active_viewport]);
+        command_buffer.set_scissor(0, &[self.active_scissor]);
+    }
+
+    // Render pass contents are recorded into secondary command buffers that continue this render pass,
+    // nothing can be recorded inline until end_render_pass(). Dynamic viewport and scissor are not
+    // inherited, every secondary command buffer sets the active ones itself.
+    pub fn begin_render_pass_with_secondary_commands(&mut self, frame_context: &FrameContext, render_area: vk::Rect2D) {
+        assert!(
+            self.supports_secondary_commands(),
+            "secondary commands are not supported with dynamic rendering"
+        );
+        self.update_active_area(render_area);
+        self.begin_render_pass_with_contents(frame_context, vk::SubpassContents::SECONDARY_COMMAND_BUFFERS);
+    }
+
+    pub fn supports_secondary_commands(&self) -> bool {
+        self.dynamic_rendering.is_none()
+    }
+
+    // Valid between begin_render_pass() and end_render_pass()
+    pub fn get_active_viewport(&self) -> vk::Viewport {
+        self.active_viewport
+    }
+
+    pub fn get_active_scissor(&self) -> vk::Rect2D {
+        self.active_scissor
+    }
+
+    fn update_active_area(&mut self, render_area: vk::Rect2D) {
+        let viewport_area = self.viewport_rect.unwrap_or(render_area);
+        let mut clipped_area = intersect_rects(render_area, viewport_area);
+        if let Some(scissor_rect) = self.scissor_rect {
+            clipped_area = intersect_rects(clipped_area, scissor_rect);
+        }
+
+        self.active_viewport = vk::Viewport {
+            x: viewport_area.offset.x as _,
+            y: viewport_area.offset.y as _,
+            width: viewport_area.extent.width as _,
+            height: viewport_area.extent.height as _,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        self.active_scissor = clipped_area;
+    }
+
+    fn begin_render_pass_with_contents(&mut self, frame_context: &FrameContext, contents: vk::SubpassContents) {
         let command_buffer = self.command_buffer.get_mut(frame_context);
         command_buffer.begin_render_pass(
             &vk::RenderPassBeginInfo::builder()
                 .render_pass(self.render_pass)
                 .framebuffer(*self.framebuffer.get(frame_context))
-                .render_area(render_area)
+                .render_area(self.active_scissor)
                 .clear_values(&self.clear_values)
                 .build(),
-            vk::SubpassContents::INLINE,
+            contents,
         );
     }
 
     pub fn end_render_pass(&mut self, frame_context: &FrameContext) {
         let command_buffer = self.command_buffer.get_mut(frame_context);
-        command_buffer.end_render_pass();
+        if self.dynamic_rendering.is_some() {
+            command_buffer.end_rendering();
+        } else {
+            command_buffer.end_render_pass();
+        }
+        // Only the first render pass of the frame is measured
+        if self.query_scope.is_active() {
+            self.query_scope.end(command_buffer, frame_context);
+        }
+    }
 
-        let end_pass_query = frame_context.current_gpu_frame() * 2 + 1;
-        command_buffer.write_timestamp(
-            vk::PipelineStageFlags::ALL_GRAPHICS,
-            self.timestamp_query_pool,
-            end_pass_query as _,
+    fn begin_rendering(&mut self, frame_context: &FrameContext, render_area: vk::Rect2D) {
+        // Render pass path does these transitions implicitly via attachment initial and final layouts
+        let mut image_barriers = Vec::with_capacity(self.render_images.len() + 1);
+        let mut color_attachments = Vec::with_capacity(self.render_images.len());
+        let layer_count = get_view_layer_count(self.view_mask);
+        for (image_id, image) in self.render_images.iter().enumerate() {
+            image_barriers.push(make_attachment_barrier(
+                image.image.0,
+                layer_count,
+                vk::ImageAspectFlags::COLOR,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ));
+            color_attachments.push(RenderingAttachmentInfoKHR {
+                image_view: image.image_view,
+                image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::STORE,
+                clear_value: self.clear_values[image_id],
+                ..Default::default()
+            });
+        }
+
+        let depth_attachment = match &self.depth_image {
+            Some(depth_image) => {
+                image_barriers.push(make_attachment_barrier(
+                    depth_image.image.0,
+                    layer_count,
+                    vk::ImageAspectFlags::DEPTH,
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                ));
+                Some(RenderingAttachmentInfoKHR {
+                    image_view: depth_image.image_view,
+                    image_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    load_op: vk::AttachmentLoadOp::CLEAR,
+                    store_op: vk::AttachmentStoreOp::STORE,
+                    clear_value: self.clear_values[self.render_images.len()],
+                    ..Default::default()
+                })
+            }
+            None => None,
+        };
+
+        let command_buffer = self.command_buffer.get_mut(frame_context);
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            None,
+            &[],
+            &[],
+            &image_barriers,
         );
+        command_buffer.begin_rendering(&RenderingInfoKHR {
+            render_area,
+            view_mask: self.view_mask,
+            color_attachment_count: color_attachments.len() as _,
+            p_color_attachments: color_attachments.as_ptr(),
+            p_depth_attachment: match &depth_attachment {
+                Some(attachment) => attachment,
+                None => std::ptr::null(),
+            },
+            ..Default::default()
+        });
     }
 
     pub fn submit_commands(&mut self, frame_context: &FrameContext, queue: &mut DeviceQueue) {
@@ -394,6 +853,10 @@ impl RenderLayer {
         let signal_fence = self.signal_fence.get(frame_context);
 
         let command_buffer = self.command_buffer.get_mut(frame_context);
+        // Layers that only record commands outside of a render pass end their queries here
+        if self.query_scope.is_active() {
+            self.query_scope.end(command_buffer, frame_context);
+        }
         command_buffer.end();
 
         queue.submit(
@@ -406,8 +869,9 @@ impl RenderLayer {
             *signal_fence,
         );
 
-        self.wait_semaphores.clear();
-        self.wait_stage_mask.clear();
+        self.submitted_waits.clear();
+        self.submitted_waits
+            .extend(self.wait_semaphores.drain(..).zip(self.wait_stage_mask.drain(..)));
     }
 }
 
@@ -416,6 +880,16 @@ impl RenderLayer {
         *self.signal_semaphore.get(frame_context)
     }
 
+    // Any buffered frame, dependencies are matched after the frame has ended
+    pub fn is_signal_semaphore(&self, semaphore: vk::Semaphore) -> bool {
+        self.signal_semaphore.contains(&semaphore)
+    }
+
+    // Semaphores and wait stages of dependencies and wait conditions of the last submission
+    pub fn get_submitted_waits(&self) -> &[(vk::Semaphore, vk::PipelineStageFlags)] {
+        &self.submitted_waits
+    }
+
     pub fn get_signal_fence(&self, frame_context: &FrameContext) -> vk::Fence {
         *self.signal_fence.get(frame_context)
     }
@@ -433,28 +907,130 @@ impl RenderLayer {
         frame_context: &FrameContext,
         factory: &mut DeviceFactory,
     ) -> Option<[u64; 2]> {
-        let oldest_frame = (frame_context.current_gpu_frame() + NUM_BUFFERED_GPU_FRAMES) % NUM_BUFFERED_GPU_FRAMES;
-        let mut data = [0u64; 2];
-        let result = factory.get_query_pool_results(
-            self.timestamp_query_pool,
-            (oldest_frame * 2) as _,
-            2,
-            &mut data,
-            vk::QueryResultFlags::TYPE_64,
-        );
-        match result {
-            Ok(_) => Some(data),
-            Err(code) => match code {
-                vk::Result::NOT_READY => None,
-                _ => panic!("try_get_oldest_timestamp(): Internal GPU error: {}", code),
-            },
-        }
+        self.query_scope.try_get_oldest_timestamps(frame_context, factory)
+    }
+
+    pub fn try_get_oldest_pipeline_statistics(
+        &self,
+        frame_context: &FrameContext,
+        factory: &mut DeviceFactory,
+    ) -> Option<PipelineStatistics> {
+        self.query_scope
+            .try_get_oldest_pipeline_statistics(frame_context, factory)
+    }
+
+    pub fn has_pipeline_statistics(&self) -> bool {
+        self.query_scope.has_pipeline_statistics()
     }
 }
 
 struct RenderImage {
     image: HeapAllocatedResource<vk::Image>,
     image_view: vk::ImageView,
+    owned: bool, // shared images are destroyed by the layer that allocated them
+}
+
+struct DynamicRendering {
+    // Referenced by pipeline_rendering_info
+    _color_formats: Vec<vk::Format>,
+    pipeline_rendering_info: PipelineRenderingCreateInfoKHR,
+}
+
+fn create_submission_resources(
+    device: &Device,
+    factory: &mut DeviceFactory,
+) -> (
+    FrameLocal<vk::CommandPool>,
+    FrameLocal<CommandBuffer>,
+    FrameLocal<vk::Semaphore>,
+    FrameLocal<vk::Fence>,
+    QueryScope,
+) {
+    let num_buffered_frames = factory.get_num_buffered_frames();
+    let command_pool = FrameLocal::new(num_buffered_frames, |_| {
+        factory.create_command_pool(
+            &vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(device.get_graphics_queue_index())
+                .build(),
+        )
+    });
+    let command_buffer = FrameLocal::new(num_buffered_frames, |f| {
+        factory.allocate_command_buffers(
+            &vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(1)
+                .command_pool(*command_pool.get_frame(f))
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .build(),
+        )[0]
+    });
+    let signal_semaphore = FrameLocal::new(num_buffered_frames, |_| {
+        factory.create_semaphore(&vk::SemaphoreCreateInfo::default())
+    });
+    let signal_fence = FrameLocal::new(num_buffered_frames, |_| {
+        factory.create_fence(
+            &vk::FenceCreateInfo::builder()
+                .flags(vk::FenceCreateFlags::SIGNALED)
+                .build(),
+        )
+    });
+
+    let query_scope = QueryScope::new(device, factory);
+
+    (
+        command_pool,
+        command_buffer,
+        signal_semaphore,
+        signal_fence,
+        query_scope,
+    )
+}
+
+// Rects that don't overlap produce a zero extent
+fn intersect_rects(a: vk::Rect2D, b: vk::Rect2D) -> vk::Rect2D {
+    let min_x = a.offset.x.max(b.offset.x);
+    let min_y = a.offset.y.max(b.offset.y);
+    let max_x = (a.offset.x + a.extent.width as i32).min(b.offset.x + b.extent.width as i32);
+    let max_y = (a.offset.y + a.extent.height as i32).min(b.offset.y + b.extent.height as i32);
+    vk::Rect2D {
+        offset: vk::Offset2D { x: min_x, y: min_y },
+        extent: vk::Extent2D {
+            width: (max_x - min_x).max(0) as _,
+            height: (max_y - min_y).max(0) as _,
+        },
+    }
+}
+
+// Views are mapped to array layers by bit index, so the highest view bit defines the layer count
+fn get_view_layer_count(view_mask: u32) -> u32 {
+    (32 - view_mask.leading_zeros()).max(1)
+}
+
+fn make_attachment_barrier(
+    image: vk::Image,
+    layer_count: u32,
+    aspect_mask: vk::ImageAspectFlags,
+    dst_access_mask: vk::AccessFlags,
+    new_layout: vk::ImageLayout,
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::default())
+        .dst_access_mask(dst_access_mask)
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(new_layout)
+        .src_queue_family_index(!0)
+        .dst_queue_family_index(!0)
+        .image(image)
+        .subresource_range(
+            vk::ImageSubresourceRange::builder()
+                .aspect_mask(aspect_mask)
+                .base_mip_level(0)
+                .level_count(1)
+                .base_array_layer(0)
+                .layer_count(layer_count)
+                .build(),
+        )
+        .build()
 }
 
 fn allocate_render_image(
@@ -462,6 +1038,7 @@ fn allocate_render_image(
     factory: &mut DeviceFactory,
     width: u32,
     height: u32,
+    layer_count: u32,
     parameters: &RenderImageParameters,
     aspect_mask: vk::ImageAspectFlags,
 ) -> (HeapAllocatedResource<vk::Image>, vk::ImageView) {
@@ -483,7 +1060,7 @@ fn allocate_render_image(
             .format(parameters.image_format)
             .extent(image_extent)
             .mip_levels(1)
-            .array_layers(1)
+            .array_layers(layer_count)
             .samples(vk::SampleCountFlags::TYPE_1)
             .tiling(vk::ImageTiling::OPTIMAL)
             .usage(parameters.image_usage | extra_image_usage_flags)
@@ -498,7 +1075,11 @@ fn allocate_render_image(
     let image_view = factory.create_image_view(
         &vk::ImageViewCreateInfo::builder()
             .image(image.0)
-            .view_type(vk::ImageViewType::TYPE_2D)
+            .view_type(if layer_count > 1 {
+                vk::ImageViewType::TYPE_2D_ARRAY
+            } else {
+                vk::ImageViewType::TYPE_2D
+            })
             .format(parameters.image_format)
             .components(vk::ComponentMapping::default())
             .subresource_range(
@@ -507,7 +1088,7 @@ fn allocate_render_image(
                     .base_mip_level(0)
                     .level_count(1)
                     .base_array_layer(0)
-                    .layer_count(1)
+                    .layer_count(layer_count)
                     .build(),
             )
             .build(),
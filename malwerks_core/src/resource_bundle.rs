@@ -10,6 +10,8 @@ use crate::upload_batch::*;
 
 pub type VertexSemantic = DiskVertexSemantic;
 
+pub const OBJECT_DATA_SIZE: usize = 32;
+
 pub struct VertexAttribute {
     pub attribute_name: String,
     pub attribute_semantic: VertexSemantic,
@@ -19,52 +21,103 @@ pub struct VertexAttribute {
 }
 
 pub struct RenderMesh {
-    pub vertex_buffer: usize,
-    pub index_buffer: (vk::IndexType, usize),
+    pub vertex_buffer: BufferHandle,
+    pub index_buffer: (vk::IndexType, BufferHandle),
     pub index_count: usize,
+    pub first_index: usize,
+    pub vertex_offset: usize,
 }
 
 pub struct RenderInstance {
-    pub mesh: usize,
-    pub material_instance: usize,
+    pub mesh: MeshHandle,
+    pub material_instance: MaterialInstanceHandle,
     pub material_instance_data: [u8; 64],
 
     pub total_instance_count: usize,
     pub total_draw_count: usize,
+    pub sort_key: u64, // computed at import, see DiskRenderInstance
+}
+
+// Consecutive bucket instances that share material bindings, drawn with a single indirect call
+pub struct RenderMultiDraw {
+    pub first_instance: usize, // index into bucket `instances`
+    pub instance_count: usize,
+    pub first_draw_command: usize, // index into `draw_command_buffer`
+    pub draw_count_id: usize,      // index into `draw_count_buffer`
 }
 
 pub struct RenderBucket {
-    pub material: usize,
+    pub material: MaterialHandle,
     pub instances: Vec<RenderInstance>,
-    pub instance_transform_buffer: usize,
+    pub instance_transform_buffer: BufferHandle,
+    pub multi_draws: Vec<RenderMultiDraw>, // empty if mesh geometry is not packed
+    pub first_object_id: usize,            // object IDs of the bucket follow its instance transforms
+    pub sort_key: u64,                     // buckets with equal pipeline hashes in their keys can share pipelines
 }
 
 pub struct RenderMaterial {
-    pub material_layout: usize,
+    pub material_layout: MaterialLayoutHandle,
 
     pub vertex_stride: u32,
     pub vertex_format: Vec<VertexAttribute>,
 
     pub fragment_alpha_test: bool,
+    pub fragment_alpha_blend: bool,
     pub fragment_cull_flags: vk::CullModeFlags,
 
     pub shader_image_mapping: Vec<(String, String)>, // image_name, uv_channel_name
     pub shader_macro_definitions: Vec<(String, String)>, // name, value
+    pub shader_parameters: Vec<String>,              // vec4 material instance parameters in push constants
+}
+
+pub struct SceneNode {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub local_transform: [f32; 16],     // column major
+    pub instances: Vec<(usize, usize)>, // bucket_id, instance_transform_id
 }
 
 pub struct ResourceBundle {
     pub buffers: Vec<HeapAllocatedResource<vk::Buffer>>,
+    pub buffer_addresses: Vec<vk::DeviceAddress>, // directly maps to `buffers`, empty without buffer device address
     pub meshes: Vec<RenderMesh>,
     pub images: Vec<HeapAllocatedResource<vk::Image>>,
     pub image_views: Vec<vk::ImageView>,
+    pub image_sizes: Vec<(u32, u32, usize)>, // width, height, mipmap count, directly maps to `images`
     pub samplers: Vec<vk::Sampler>,
+    pub sampler_parameters: Vec<DiskSampler>, // directly maps to `samplers`
+
+    // Copies of material samplers with raised min lod for images above the factory texture resolution clamp
+    pub clamped_samplers: Vec<((usize, usize), vk::Sampler)>, // (image id, sampler id), sampler
     pub buckets: Vec<RenderBucket>,
 
+    // One DrawIndexedIndirectCommand per render instance, first_instance points at its transforms in the bucket.
+    // Counts are static for now, a culling pass can overwrite both buffers.
+    pub draw_command_buffer: Option<HeapAllocatedResource<vk::Buffer>>,
+    pub draw_count_buffer: Option<HeapAllocatedResource<vk::Buffer>>,
+
+    // One u32 per render instance for conditional rendering, zero skips all draws of the instance.
+    // Predicates start visible, a culling pass can overwrite them.
+    pub draw_predicate_buffer: Option<HeapAllocatedResource<vk::Buffer>>,
+
+    // ObjectData per instance transform, shaders index it like transforms of the bucket
+    pub object_data_buffer: HeapAllocatedResource<vk::Buffer>,
+    pub object_count: usize,
+
     pub descriptor_pool: vk::DescriptorPool,
     pub descriptor_layouts: Vec<vk::DescriptorSetLayout>, // directly maps to `material_layouts`
     pub descriptor_sets: Vec<vk::DescriptorSet>,          // directly maps to `material_instances`
+    pub material_instance_images: Vec<Vec<(ImageHandle, SamplerHandle)>>, // directly maps to `material_instances`
+
+    // Images stored in a format that doesn't match their color space are replaced with magenta
+    pub color_space_debug_image_views: Vec<(usize, vk::ImageView)>, // image id, magenta view
+    pub color_space_debug_descriptor_pool: vk::DescriptorPool,      // null if the bundle has no mismatches
+    pub color_space_debug_descriptor_sets: Vec<vk::DescriptorSet>,  // directly maps to `material_instances`
+    pub color_space_debug: bool,
 
     pub materials: Vec<RenderMaterial>,
+    pub scene_nodes: Vec<SceneNode>,
+    pub material_animations: Vec<DiskMaterialAnimation>,
 }
 
 impl ResourceBundle {
@@ -81,7 +134,26 @@ impl ResourceBundle {
         for sampler in &self.samplers {
             factory.destroy_sampler(*sampler);
         }
+        for (_, sampler) in &self.clamped_samplers {
+            factory.destroy_sampler(*sampler);
+        }
+        if let Some(draw_command_buffer) = &self.draw_command_buffer {
+            factory.deallocate_buffer(draw_command_buffer);
+        }
+        if let Some(draw_count_buffer) = &self.draw_count_buffer {
+            factory.deallocate_buffer(draw_count_buffer);
+        }
+        if let Some(draw_predicate_buffer) = &self.draw_predicate_buffer {
+            factory.deallocate_buffer(draw_predicate_buffer);
+        }
+        factory.deallocate_buffer(&self.object_data_buffer);
         factory.destroy_descriptor_pool(self.descriptor_pool);
+        for (_, image_view) in &self.color_space_debug_image_views {
+            factory.destroy_image_view(*image_view);
+        }
+        if self.color_space_debug_descriptor_pool != vk::DescriptorPool::null() {
+            factory.destroy_descriptor_pool(self.color_space_debug_descriptor_pool);
+        }
         for descriptor_layout in &self.descriptor_layouts {
             factory.destroy_descriptor_set_layout(*descriptor_layout);
         }
@@ -94,28 +166,283 @@ impl ResourceBundle {
         queue: &mut DeviceQueue,
     ) -> Self {
         let buffers = initialize_buffers(&disk_bundle, command_buffer, factory, queue);
+        let buffer_addresses = get_buffer_addresses(disk_bundle, &buffers, factory);
         let meshes = initialize_meshes(&disk_bundle);
         let (images, image_views, samplers) = initialize_images(&disk_bundle, command_buffer, factory, queue);
         let (descriptor_pool, descriptor_layouts, descriptor_sets) =
             initialize_descriptor_pool(&disk_bundle, &image_views, &samplers, factory);
-        let buckets = initialize_buckets(&disk_bundle, command_buffer, factory, queue);
+        let (color_space_debug_image_views, color_space_debug_descriptor_pool, color_space_debug_descriptor_sets) =
+            initialize_color_space_debug(
+                disk_bundle,
+                &images,
+                &image_views,
+                &samplers,
+                &descriptor_layouts,
+                &descriptor_sets,
+                factory,
+            );
+        let (buckets, draw_command_buffer, draw_count_buffer) = initialize_buckets(
+            disk_bundle,
+            &descriptor_sets,
+            &color_space_debug_descriptor_sets,
+            command_buffer,
+            factory,
+            queue,
+        );
+        let (object_data_buffer, object_count) =
+            initialize_object_data(&buckets, &meshes, &buffer_addresses, command_buffer, factory, queue);
         let materials = initialize_materials(&disk_bundle);
+        let scene_nodes = initialize_scene_nodes(&disk_bundle);
 
-        Self {
+        let mut resource_bundle = Self {
             buffers,
+            buffer_addresses,
             meshes,
             images,
             image_views,
+            image_sizes: disk_bundle
+                .images
+                .iter()
+                .map(|disk_image| {
+                    (
+                        disk_image.width,
+                        disk_image.height,
+                        get_runtime_mipmap_count(disk_image),
+                    )
+                })
+                .collect(),
             samplers,
+            sampler_parameters: disk_bundle.samplers.clone(),
+            clamped_samplers: Vec::new(),
             buckets,
 
+            draw_command_buffer,
+            draw_count_buffer,
+            draw_predicate_buffer: None,
+
+            object_data_buffer,
+            object_count,
+
             descriptor_pool,
             descriptor_layouts,
             descriptor_sets,
+            material_instance_images: get_material_instance_images(disk_bundle),
+
+            color_space_debug_image_views,
+            color_space_debug_descriptor_pool,
+            color_space_debug_descriptor_sets,
+            color_space_debug: false,
 
             materials,
+            scene_nodes,
+            material_animations: disk_bundle.material_animations.clone(),
+        };
+        if factory.get_max_texture_resolution().is_some() {
+            resource_bundle.recreate_samplers(factory);
         }
+        resource_bundle
     }
+
+    // The device must have conditional rendering enabled, predicate buffers can't be created without it
+    pub fn initialize_draw_predicates(
+        &mut self,
+        command_buffer: &mut CommandBuffer,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) {
+        assert!(
+            self.draw_predicate_buffer.is_none(),
+            "draw predicates are already initialized"
+        );
+        let render_instance_count: usize = self.buckets.iter().map(|bucket| bucket.instances.len()).sum();
+        if render_instance_count == 0 {
+            return;
+        }
+
+        let predicate_data = 1u32.to_le_bytes().repeat(render_instance_count);
+        let draw_predicate_buffer = factory.allocate_buffer(
+            &vk::BufferCreateInfo::builder()
+                .size(predicate_data.len() as _)
+                .usage(
+                    vk::BufferUsageFlags::CONDITIONAL_RENDERING_EXT
+                        | vk::BufferUsageFlags::STORAGE_BUFFER
+                        | vk::BufferUsageFlags::TRANSFER_DST,
+                )
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ..Default::default()
+            },
+        );
+        let mut upload_batch = UploadBatch::new(command_buffer);
+        upload_batch.upload_buffer_memory(
+            vk::PipelineStageFlags::CONDITIONAL_RENDERING_EXT,
+            &draw_predicate_buffer,
+            &predicate_data,
+            0,
+            factory,
+        );
+        upload_batch.flush(factory, queue);
+
+        log::info!("initialized {} draw predicates", render_instance_count);
+        self.draw_predicate_buffer = Some(draw_predicate_buffer);
+    }
+
+    // Vertex pulling shaders read vertex data through the address in ObjectData instead of a descriptor
+    pub fn uses_buffer_device_address(&self) -> bool {
+        !self.buffer_addresses.is_empty()
+    }
+
+    // Picks up the sampler anisotropy and texture lod overrides of the factory, descriptor sets must not be in use
+    pub fn recreate_samplers(&mut self, factory: &mut DeviceFactory) {
+        for sampler in &self.samplers {
+            factory.destroy_sampler(*sampler);
+        }
+        for (_, sampler) in &self.clamped_samplers {
+            factory.destroy_sampler(*sampler);
+        }
+        self.samplers = self
+            .sampler_parameters
+            .iter()
+            .map(|disk_sampler| create_material_sampler(disk_sampler, 0, factory))
+            .collect();
+
+        self.clamped_samplers.clear();
+        if let Some(max_texture_resolution) = factory.get_max_texture_resolution() {
+            for &(image, sampler) in self.material_instance_images.iter().flatten() {
+                let key = (image.index(), sampler.index());
+                let (width, height, mipmap_count) = self.image_sizes[key.0];
+                let min_lod = get_clamped_min_lod((width, height), mipmap_count, max_texture_resolution);
+                if min_lod > 0 && !self.clamped_samplers.iter().any(|(clamped_key, _)| *clamped_key == key) {
+                    let clamped_sampler = create_material_sampler(&self.sampler_parameters[key.1], min_lod, factory);
+                    self.clamped_samplers.push((key, clamped_sampler));
+                }
+            }
+        }
+
+        write_material_descriptor_sets(
+            &self.descriptor_sets,
+            &self.material_instance_images,
+            &self.image_views,
+            &self.samplers,
+            &self.clamped_samplers,
+            factory,
+        );
+        if !self.color_space_debug_image_views.is_empty() {
+            let mut temp_image_views = self.image_views.clone();
+            for (image_id, image_view) in &self.color_space_debug_image_views {
+                temp_image_views[*image_id] = *image_view;
+            }
+            write_material_descriptor_sets(
+                &self.color_space_debug_descriptor_sets,
+                &self.material_instance_images,
+                &temp_image_views,
+                &self.samplers,
+                &self.clamped_samplers,
+                factory,
+            );
+        }
+    }
+
+    pub fn get_material_descriptor_set(&self, material_instance: MaterialInstanceHandle) -> vk::DescriptorSet {
+        if self.color_space_debug {
+            self.color_space_debug_descriptor_sets[material_instance.index()]
+        } else {
+            self.descriptor_sets[material_instance.index()]
+        }
+    }
+
+    // Writes UV scale and offset of animated material instances at `time` seconds into their instance data
+    pub fn update_material_animations(&mut self, time: f32) {
+        for animation in &self.material_animations {
+            let scale_offset = get_material_animation_scale_offset(animation, time);
+            for instance in self.buckets.iter_mut().flat_map(|bucket| bucket.instances.iter_mut()) {
+                if instance.material_instance == animation.material_instance {
+                    let parameter_data = &mut instance.material_instance_data[animation.parameter_id * 16..][..16];
+                    for (value, packed_value) in scale_offset.iter().zip(parameter_data.chunks_exact_mut(4)) {
+                        packed_value.copy_from_slice(&value.to_le_bytes());
+                    }
+                }
+            }
+        }
+    }
+
+    // Maps an object ID read back from the GPU to bucket_id and instance_transform_id
+    pub fn find_object(&self, object_id: usize) -> Option<(usize, usize)> {
+        if object_id >= self.object_count {
+            return None;
+        }
+        let bucket_id = self
+            .buckets
+            .iter()
+            .rposition(|bucket| bucket.first_object_id <= object_id)?;
+        Some((bucket_id, object_id - self.buckets[bucket_id].first_object_id))
+    }
+
+    // Maps a GPU instance back to the authored node, None if the bundle doesn't have the node hierarchy
+    pub fn find_scene_node(&self, bucket_id: usize, instance_transform_id: usize) -> Option<usize> {
+        self.scene_nodes
+            .iter()
+            .position(|node| node.instances.contains(&(bucket_id, instance_transform_id)))
+    }
+
+    // Transform of the node relative to the bundle root, column major
+    pub fn get_scene_node_world_transform(&self, node_id: usize) -> [f32; 16] {
+        let node = &self.scene_nodes[node_id];
+        match node.parent {
+            Some(parent) => multiply_transforms(&self.get_scene_node_world_transform(parent), &node.local_transform),
+            None => node.local_transform,
+        }
+    }
+
+    // Replaces the local transform of the node and returns new instance transforms of the node and its children,
+    // the caller is responsible for uploading them to the instance transform buffers
+    pub fn set_scene_node_transform(
+        &mut self,
+        node_id: usize,
+        local_transform: &[f32; 16],
+    ) -> Vec<(usize, usize, [f32; 16])> {
+        self.scene_nodes[node_id].local_transform = *local_transform;
+
+        let mut instance_transforms = Vec::new();
+        let mut pending_nodes = vec![(node_id, self.get_scene_node_world_transform(node_id))];
+        while let Some((current_node, world_transform)) = pending_nodes.pop() {
+            for (bucket_id, instance_transform_id) in &self.scene_nodes[current_node].instances {
+                instance_transforms.push((*bucket_id, *instance_transform_id, world_transform));
+            }
+            for (child_id, child) in self.scene_nodes.iter().enumerate() {
+                if child.parent == Some(current_node) {
+                    pending_nodes.push((child_id, multiply_transforms(&world_transform, &child.local_transform)));
+                }
+            }
+        }
+        instance_transforms
+    }
+}
+
+// Flipbook frames are selected by the scale and offset, scrolling wraps around to keep precision
+pub(crate) fn get_material_animation_scale_offset(animation: &DiskMaterialAnimation, time: f32) -> [f32; 4] {
+    let [columns, rows] = animation.flipbook_size;
+    let frame = ((time * animation.flipbook_frame_rate).floor() as i64)
+        .rem_euclid(animation.flipbook_frame_count as i64) as u32;
+    let scale = [1.0 / columns as f32, 1.0 / rows as f32];
+    [
+        scale[0],
+        scale[1],
+        (frame % columns) as f32 * scale[0] + (animation.uv_scroll[0] * time).fract(),
+        (frame / columns) as f32 * scale[1] + (animation.uv_scroll[1] * time).fract(),
+    ]
+}
+
+fn multiply_transforms(a: &[f32; 16], b: &[f32; 16]) -> [f32; 16] {
+    let mut result = [0.0; 16];
+    for column in 0..4 {
+        for row in 0..4 {
+            result[column * 4 + row] = (0..4).map(|i| a[i * 4 + row] * b[column * 4 + i]).sum();
+        }
+    }
+    result
 }
 
 fn initialize_buffers(
@@ -130,10 +457,21 @@ fn initialize_buffers(
 
     let mut upload_batch = UploadBatch::new(command_buffer);
     for disk_buffer in &disk_bundle.buffers {
+        // Vertex and index buffers can also be read as words in shaders with vertex pulling and ray tracing
+        let mut usage_flags =
+            vk::BufferUsageFlags::from_raw(disk_buffer.usage_flags) | vk::BufferUsageFlags::TRANSFER_DST;
+        let mut size = disk_buffer.data.len();
+        if usage_flags.intersects(vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER) {
+            usage_flags |= vk::BufferUsageFlags::STORAGE_BUFFER;
+            if factory.is_buffer_device_address_enabled() {
+                usage_flags |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+            }
+            size = size.div_ceil(4) * 4;
+        }
         let buffer = factory.allocate_buffer(
             &vk::BufferCreateInfo::builder()
-                .size(disk_buffer.data.len() as _)
-                .usage(vk::BufferUsageFlags::from_raw(disk_buffer.usage_flags) | vk::BufferUsageFlags::TRANSFER_DST)
+                .size(size as _)
+                .usage(usage_flags)
                 .build(),
             &vk_mem::AllocationCreateInfo {
                 usage: vk_mem::MemoryUsage::GpuOnly,
@@ -164,6 +502,8 @@ fn initialize_meshes(disk_bundle: &DiskResourceBundle) -> Vec<RenderMesh> {
                 disk_mesh.index_buffer.1,
             ),
             index_count: disk_mesh.index_count,
+            first_index: disk_mesh.first_index,
+            vertex_offset: disk_mesh.vertex_offset,
             // indirect_draw_buffer: disk_mesh.indirect_draw_buffer,
             // indirect_draw_count: disk_mesh.indirect_draw_count,
         });
@@ -192,6 +532,7 @@ fn initialize_images(
 
     let mut upload_batch = UploadBatch::new(command_buffer);
     for disk_image in &disk_bundle.images {
+        let mipmap_count = get_runtime_mipmap_count(disk_image);
         let image_view_type = vk::ImageViewType::from_raw(disk_image.view_type);
         let image_flags = match image_view_type {
             vk::ImageViewType::CUBE => vk::ImageCreateFlags::CUBE_COMPATIBLE,
@@ -212,11 +553,15 @@ fn initialize_images(
                     height: disk_image.height,
                     depth: disk_image.depth,
                 })
-                .mip_levels(disk_image.mipmap_count as _)
+                .mip_levels(mipmap_count as _)
                 .array_layers(disk_image.layer_count as _)
                 .samples(vk::SampleCountFlags::TYPE_1)
                 .tiling(vk::ImageTiling::OPTIMAL)
-                .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+                .usage(
+                    vk::ImageUsageFlags::SAMPLED
+                        | vk::ImageUsageFlags::TRANSFER_SRC
+                        | vk::ImageUsageFlags::TRANSFER_DST,
+                )
                 .initial_layout(vk::ImageLayout::UNDEFINED)
                 .build(),
             &vk_mem::AllocationCreateInfo {
@@ -233,6 +578,13 @@ fn initialize_images(
             &disk_image.pixels,
             factory,
         );
+        if mipmap_count > disk_image.mipmap_count {
+            upload_batch.generate_image_mipmaps(
+                &allocated_image,
+                (disk_image.width, disk_image.height, disk_image.depth),
+                (mipmap_count, disk_image.layer_count),
+            );
+        }
 
         image_views.push(
             factory.create_image_view(
@@ -245,7 +597,7 @@ fn initialize_images(
                         vk::ImageSubresourceRange::builder()
                             .aspect_mask(vk::ImageAspectFlags::COLOR)
                             .base_mip_level(0)
-                            .level_count(disk_image.mipmap_count as _)
+                            .level_count(mipmap_count as _)
                             .base_array_layer(0)
                             .layer_count(disk_image.layer_count as _)
                             .build(),
@@ -257,27 +609,30 @@ fn initialize_images(
     }
     upload_batch.flush(factory, queue);
 
-    let mut samplers = Vec::with_capacity(disk_bundle.samplers.len());
-    for disk_sampler in &disk_bundle.samplers {
-        samplers.push(
-            factory.create_sampler(
-                &vk::SamplerCreateInfo::builder()
-                    .address_mode_u(vk::SamplerAddressMode::from_raw(disk_sampler.address_mode_u))
-                    .address_mode_v(vk::SamplerAddressMode::from_raw(disk_sampler.address_mode_v))
-                    .address_mode_w(vk::SamplerAddressMode::from_raw(disk_sampler.address_mode_w))
-                    .mag_filter(vk::Filter::from_raw(disk_sampler.mag_filter))
-                    .min_filter(vk::Filter::from_raw(disk_sampler.min_filter))
-                    .mipmap_mode(vk::SamplerMipmapMode::from_raw(disk_sampler.mipmap_mode))
-                    .min_lod(0.0)
-                    .max_lod(std::f32::MAX)
-                    .build(),
-            ),
-        );
-    }
+    let samplers = disk_bundle
+        .samplers
+        .iter()
+        .map(|disk_sampler| create_material_sampler(disk_sampler, 0, factory))
+        .collect();
 
     (images, image_views, samplers)
 }
 
+// Images that generate mips at runtime only store the top one
+fn get_runtime_mipmap_count(disk_image: &DiskImage) -> usize {
+    if !disk_image.generate_mipmaps {
+        return disk_image.mipmap_count;
+    }
+
+    let block_compressed_formats =
+        vk::Format::BC1_RGB_UNORM_BLOCK.as_raw()..=vk::Format::ASTC_12X12_SRGB_BLOCK.as_raw();
+    assert!(
+        !block_compressed_formats.contains(&disk_image.format),
+        "mipmaps of block compressed images can't be generated at runtime"
+    );
+    get_full_mipmap_count((disk_image.width, disk_image.height, disk_image.depth))
+}
+
 fn initialize_descriptor_pool(
     disk_bundle: &DiskResourceBundle,
     image_views: &[vk::ImageView],
@@ -312,43 +667,47 @@ fn initialize_descriptor_pool(
         temp_bindings.clear();
     }
 
-    let max_descriptor_count = disk_bundle.material_instances.len() * max_descriptor_image_count;
-    let mut temp_writes = Vec::with_capacity(max_descriptor_count);
-    let mut temp_write_ids = Vec::with_capacity(max_descriptor_count);
-    let mut temp_image_infos = Vec::with_capacity(max_descriptor_count);
-    let mut temp_per_descriptor_layouts = Vec::with_capacity(disk_bundle.material_instances.len());
+    let (descriptor_pool, descriptor_sets) =
+        allocate_material_descriptor_sets(disk_bundle, &descriptor_set_layouts, image_views, samplers, factory);
+    (descriptor_pool, descriptor_set_layouts, descriptor_sets)
+}
 
+// Material instances with identical layouts, images and samplers share a descriptor set
+fn allocate_material_descriptor_sets(
+    disk_bundle: &DiskResourceBundle,
+    descriptor_set_layouts: &[vk::DescriptorSetLayout],
+    image_views: &[vk::ImageView],
+    samplers: &[vk::Sampler],
+    factory: &mut DeviceFactory,
+) -> (vk::DescriptorPool, Vec<vk::DescriptorSet>) {
+    let mut unique_instances: Vec<&DiskMaterialInstance> = Vec::with_capacity(disk_bundle.material_instances.len());
+    let mut instance_set_ids = Vec::with_capacity(disk_bundle.material_instances.len());
     for disk_material_instance in &disk_bundle.material_instances {
-        let layout = descriptor_set_layouts[disk_material_instance.material_layout];
-
-        let descriptor_id = temp_per_descriptor_layouts.len();
-        temp_per_descriptor_layouts.push(layout);
-
-        for (binding_id, image) in disk_material_instance.images.iter().enumerate() {
-            let image_info_index = temp_image_infos.len();
-            temp_image_infos.push(
-                vk::DescriptorImageInfo::builder()
-                    .image_view(image_views[image.0])
-                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                    .sampler(samplers[image.1])
-                    .build(),
-            );
-            temp_writes.push(
-                vk::WriteDescriptorSet::builder()
-                    .dst_binding(binding_id as _)
-                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                    .image_info(&temp_image_infos[image_info_index..temp_image_infos.len()])
-                    .build(),
-            );
-            temp_write_ids.push(descriptor_id);
-        }
+        let set_id = unique_instances.iter().position(|unique_instance| {
+            unique_instance.material_layout == disk_material_instance.material_layout
+                && unique_instance.images == disk_material_instance.images
+        });
+        instance_set_ids.push(set_id.unwrap_or_else(|| {
+            unique_instances.push(disk_material_instance);
+            unique_instances.len() - 1
+        }));
     }
 
+    let temp_per_descriptor_layouts: Vec<vk::DescriptorSetLayout> = unique_instances
+        .iter()
+        .map(|disk_material_instance| descriptor_set_layouts[disk_material_instance.material_layout.index()])
+        .collect();
+    let binding_count: usize = unique_instances
+        .iter()
+        .map(|disk_material_instance| disk_material_instance.images.len())
+        .sum();
+
     log::info!(
-        "allocating {} set layouts, {} descriptors and {} bindings",
+        "allocating {} set layouts, {} descriptors for {} material instances and {} bindings",
         descriptor_set_layouts.len(),
         temp_per_descriptor_layouts.len(),
-        temp_writes.len()
+        disk_bundle.material_instances.len(),
+        binding_count
     );
 
     let descriptor_pool = factory.create_descriptor_pool(
@@ -356,33 +715,245 @@ fn initialize_descriptor_pool(
             .max_sets(temp_per_descriptor_layouts.len() as _)
             .pool_sizes(&[vk::DescriptorPoolSize::builder()
                 .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                .descriptor_count(temp_writes.len() as _)
+                .descriptor_count(binding_count as _)
                 .build()])
             .build(),
     );
-    let descriptor_sets = factory.allocate_descriptor_sets(
+    let unique_descriptor_sets = factory.allocate_descriptor_sets(
         &vk::DescriptorSetAllocateInfo::builder()
             .descriptor_pool(descriptor_pool)
             .set_layouts(&temp_per_descriptor_layouts)
             .build(),
     );
 
-    for i in 0..temp_writes.len() {
-        temp_writes[i].dst_set = descriptor_sets[temp_write_ids[i]];
+    let unique_instance_images: Vec<_> = unique_instances
+        .iter()
+        .map(|disk_material_instance| disk_material_instance.images.clone())
+        .collect();
+    write_material_descriptor_sets(
+        &unique_descriptor_sets,
+        &unique_instance_images,
+        image_views,
+        samplers,
+        &[],
+        factory,
+    );
+
+    let descriptor_sets = instance_set_ids
+        .iter()
+        .map(|&set_id| unique_descriptor_sets[set_id])
+        .collect();
+    (descriptor_pool, descriptor_sets)
+}
+
+fn get_material_instance_images(disk_bundle: &DiskResourceBundle) -> Vec<Vec<(ImageHandle, SamplerHandle)>> {
+    disk_bundle
+        .material_instances
+        .iter()
+        .map(|disk_material_instance| disk_material_instance.images.clone())
+        .collect()
+}
+
+fn write_material_descriptor_sets(
+    descriptor_sets: &[vk::DescriptorSet],
+    material_instance_images: &[Vec<(ImageHandle, SamplerHandle)>],
+    image_views: &[vk::ImageView],
+    samplers: &[vk::Sampler],
+    clamped_samplers: &[((usize, usize), vk::Sampler)],
+    factory: &mut DeviceFactory,
+) {
+    let binding_count = material_instance_images.iter().map(|images| images.len()).sum();
+    let mut temp_image_infos = Vec::with_capacity(binding_count);
+    for images in material_instance_images {
+        for image in images {
+            let key = (image.0.index(), image.1.index());
+            let sampler = clamped_samplers
+                .iter()
+                .find(|(clamped_key, _)| *clamped_key == key)
+                .map_or(samplers[key.1], |(_, clamped_sampler)| *clamped_sampler);
+            temp_image_infos.push(
+                vk::DescriptorImageInfo::builder()
+                    .image_view(image_views[key.0])
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .sampler(sampler)
+                    .build(),
+            );
+        }
+    }
+
+    let mut temp_writes = Vec::with_capacity(binding_count);
+    let mut image_info_index = 0;
+    for (descriptor_set, images) in descriptor_sets.iter().zip(material_instance_images) {
+        for binding_id in 0..images.len() {
+            temp_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(binding_id as _)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&temp_image_infos[image_info_index..image_info_index + 1])
+                    .build(),
+            );
+            image_info_index += 1;
+        }
     }
     factory.update_descriptor_sets(&temp_writes, &[]);
+}
 
-    (descriptor_pool, descriptor_set_layouts, descriptor_sets)
+fn create_material_sampler(disk_sampler: &DiskSampler, min_lod: u32, factory: &mut DeviceFactory) -> vk::Sampler {
+    factory.create_sampler(
+        &vk::SamplerCreateInfo::builder()
+            .address_mode_u(vk::SamplerAddressMode::from_raw(disk_sampler.address_mode_u))
+            .address_mode_v(vk::SamplerAddressMode::from_raw(disk_sampler.address_mode_v))
+            .address_mode_w(vk::SamplerAddressMode::from_raw(disk_sampler.address_mode_w))
+            .mag_filter(vk::Filter::from_raw(disk_sampler.mag_filter))
+            .min_filter(vk::Filter::from_raw(disk_sampler.min_filter))
+            .mipmap_mode(vk::SamplerMipmapMode::from_raw(disk_sampler.mipmap_mode))
+            .anisotropy_enable(disk_sampler.max_anisotropy > 1.0)
+            .max_anisotropy(disk_sampler.max_anisotropy)
+            .mip_lod_bias(factory.get_texture_lod_bias())
+            .min_lod(min_lod as f32)
+            .max_lod(f32::MAX)
+            .build(),
+    )
+}
+
+// Number of top mips to skip so the largest sampled mip fits into the resolution, the last mip is always kept
+pub(crate) fn get_clamped_min_lod(image_size: (u32, u32), mipmap_count: usize, max_resolution: u32) -> u32 {
+    let mut min_lod = 0;
+    let mut largest_extent = image_size.0.max(image_size.1);
+    while largest_extent > max_resolution.max(1) && (min_lod as usize) + 1 < mipmap_count {
+        largest_extent = (largest_extent / 2).max(1);
+        min_lod += 1;
+    }
+    min_lod
+}
+
+// Formats without alpha only get their color channels written, so blend states don't depend on alpha storage
+pub fn get_color_write_mask(format: vk::Format) -> vk::ColorComponentFlags {
+    let color = vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B;
+    match format {
+        vk::Format::B10G11R11_UFLOAT_PACK32
+        | vk::Format::E5B9G9R9_UFLOAT_PACK32
+        | vk::Format::R16G16B16_SFLOAT
+        | vk::Format::R32G32B32_SFLOAT => color,
+        _ => color | vk::ColorComponentFlags::A,
+    }
+}
+
+pub fn is_srgb_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8_SRGB
+            | vk::Format::R8G8_SRGB
+            | vk::Format::R8G8B8_SRGB
+            | vk::Format::B8G8R8_SRGB
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::B8G8R8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+            | vk::Format::BC1_RGB_SRGB_BLOCK
+            | vk::Format::BC1_RGBA_SRGB_BLOCK
+            | vk::Format::BC2_SRGB_BLOCK
+            | vk::Format::BC3_SRGB_BLOCK
+            | vk::Format::BC7_SRGB_BLOCK
+            | vk::Format::ETC2_R8G8B8_SRGB_BLOCK
+            | vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK
+            | vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK
+    )
+}
+
+// Mismatched images get a constant magenta view, material instances that don't use them keep their sets
+fn initialize_color_space_debug(
+    disk_bundle: &DiskResourceBundle,
+    images: &[HeapAllocatedResource<vk::Image>],
+    image_views: &[vk::ImageView],
+    samplers: &[vk::Sampler],
+    descriptor_set_layouts: &[vk::DescriptorSetLayout],
+    descriptor_sets: &[vk::DescriptorSet],
+    factory: &mut DeviceFactory,
+) -> (Vec<(usize, vk::ImageView)>, vk::DescriptorPool, Vec<vk::DescriptorSet>) {
+    let mismatched_images: Vec<usize> = disk_bundle
+        .images
+        .iter()
+        .enumerate()
+        .filter(|(_, disk_image)| {
+            is_srgb_format(vk::Format::from_raw(disk_image.format)) != (disk_image.color_space == DiskColorSpace::Srgb)
+        })
+        .map(|(image_id, _)| image_id)
+        .collect();
+    if mismatched_images.is_empty() {
+        return (Vec::new(), vk::DescriptorPool::null(), descriptor_sets.to_vec());
+    }
+    log::warn!(
+        "{} of {} images are stored in a format that doesn't match their color space",
+        mismatched_images.len(),
+        disk_bundle.images.len()
+    );
+
+    let mut debug_image_views = Vec::with_capacity(mismatched_images.len());
+    let mut temp_image_views = image_views.to_vec();
+    for &image_id in &mismatched_images {
+        let disk_image = &disk_bundle.images[image_id];
+        let image_view = factory.create_image_view(
+            &vk::ImageViewCreateInfo::builder()
+                .image(images[image_id].0)
+                .view_type(vk::ImageViewType::from_raw(disk_image.view_type))
+                .format(vk::Format::from_raw(disk_image.format))
+                .components(vk::ComponentMapping {
+                    r: vk::ComponentSwizzle::ONE,
+                    g: vk::ComponentSwizzle::ZERO,
+                    b: vk::ComponentSwizzle::ONE,
+                    a: vk::ComponentSwizzle::ONE,
+                })
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(get_runtime_mipmap_count(disk_image) as _)
+                        .base_array_layer(0)
+                        .layer_count(disk_image.layer_count as _)
+                        .build(),
+                )
+                .build(),
+        );
+        temp_image_views[image_id] = image_view;
+        debug_image_views.push((image_id, image_view));
+    }
+
+    let (debug_descriptor_pool, mut debug_descriptor_sets) = allocate_material_descriptor_sets(
+        disk_bundle,
+        descriptor_set_layouts,
+        &temp_image_views,
+        samplers,
+        factory,
+    );
+    for (instance_id, disk_material_instance) in disk_bundle.material_instances.iter().enumerate() {
+        let uses_mismatched_image = disk_material_instance
+            .images
+            .iter()
+            .any(|image| mismatched_images.contains(&image.0.index()));
+        if !uses_mismatched_image {
+            debug_descriptor_sets[instance_id] = descriptor_sets[instance_id];
+        }
+    }
+
+    (debug_image_views, debug_descriptor_pool, debug_descriptor_sets)
 }
 
 fn initialize_buckets(
     disk_bundle: &DiskResourceBundle,
-    _command_buffer: &mut CommandBuffer,
-    _factory: &mut DeviceFactory,
-    _queue: &mut DeviceQueue,
-) -> Vec<RenderBucket> {
+    descriptor_sets: &[vk::DescriptorSet],
+    color_space_debug_descriptor_sets: &[vk::DescriptorSet],
+    command_buffer: &mut CommandBuffer,
+    factory: &mut DeviceFactory,
+    queue: &mut DeviceQueue,
+) -> (
+    Vec<RenderBucket>,
+    Option<HeapAllocatedResource<vk::Buffer>>,
+    Option<HeapAllocatedResource<vk::Buffer>>,
+) {
     let mut buckets = Vec::with_capacity(disk_bundle.buckets.len());
 
+    let mut first_object_id = 0;
     for disk_bucket in &disk_bundle.buckets {
         let material = disk_bucket.material;
         let mut instances = Vec::with_capacity(disk_bucket.instances.len());
@@ -393,7 +964,7 @@ fn initialize_buckets(
 
             let mut material_instance_data = [0u8; 64];
             {
-                let disk_data = &disk_bundle.material_instances[material_instance].material_instance_data;
+                let disk_data = &disk_bundle.material_instances[material_instance.index()].material_instance_data;
                 assert_eq!(disk_data.len(), 64);
 
                 material_instance_data.copy_from_slice(disk_data);
@@ -409,6 +980,7 @@ fn initialize_buckets(
 
                 total_instance_count,
                 total_draw_count,
+                sort_key: disk_instance.sort_key,
             });
         }
 
@@ -416,10 +988,205 @@ fn initialize_buckets(
             material,
             instances,
             instance_transform_buffer: disk_bucket.instance_transform_buffer,
+            multi_draws: Vec::new(),
+            first_object_id,
+            sort_key: disk_bucket.sort_key,
         });
+        first_object_id += disk_bucket
+            .instances
+            .iter()
+            .map(|instance| instance.total_instance_count)
+            .sum::<usize>();
+    }
+
+    // Indirect draws need every mesh in the same vertex and index buffer
+    if disk_bundle.meshes.is_empty() || !disk_bundle.has_packed_mesh_geometry() {
+        return (buckets, None, None);
+    }
+
+    // Data of animated instances changes every frame, they are only batched with the same material instance
+    let is_animated = |instance: &RenderInstance| {
+        disk_bundle
+            .material_animations
+            .iter()
+            .any(|animation| animation.material_instance == instance.material_instance)
+    };
+    let shares_material_bindings = |a: &RenderInstance, b: &RenderInstance| {
+        let (a_id, b_id) = (a.material_instance.index(), b.material_instance.index());
+        descriptor_sets[a_id] == descriptor_sets[b_id]
+            && color_space_debug_descriptor_sets.get(a_id) == color_space_debug_descriptor_sets.get(b_id)
+            && a.material_instance_data == b.material_instance_data
+            && (a_id == b_id || !is_animated(a) && !is_animated(b))
+    };
+
+    let mut draw_commands = Vec::new();
+    let mut draw_counts = Vec::new();
+    for bucket in &mut buckets {
+        let mut first_instance = 0;
+        for (instance_id, instance) in bucket.instances.iter().enumerate() {
+            let mesh = &disk_bundle.meshes[instance.mesh.index()];
+            let command = [
+                mesh.index_count as u32,
+                instance.total_instance_count as u32,
+                mesh.first_index as u32,
+                mesh.vertex_offset as u32,
+                first_instance as u32,
+            ];
+            first_instance += instance.total_instance_count;
+
+            let is_batched = match bucket.multi_draws.last() {
+                Some(multi_draw) => shares_material_bindings(&bucket.instances[multi_draw.first_instance], instance),
+                None => false,
+            };
+            if is_batched {
+                bucket.multi_draws.last_mut().unwrap().instance_count += 1;
+                *draw_counts.last_mut().unwrap() += 1;
+            } else {
+                bucket.multi_draws.push(RenderMultiDraw {
+                    first_instance: instance_id,
+                    instance_count: 1,
+                    first_draw_command: draw_commands.len(),
+                    draw_count_id: draw_counts.len(),
+                });
+                draw_counts.push(1u32);
+            }
+            draw_commands.push(command);
+        }
+    }
+    if draw_commands.is_empty() {
+        return (buckets, None, None);
+    }
+
+    let command_data: Vec<u8> = draw_commands
+        .iter()
+        .flat_map(|command| command.iter().flat_map(|value| value.to_le_bytes()))
+        .collect();
+    let count_data: Vec<u8> = draw_counts.iter().flat_map(|count| count.to_le_bytes()).collect();
+    log::info!(
+        "initializing {} indirect draws in {} multi draws",
+        draw_commands.len(),
+        draw_counts.len()
+    );
+
+    let mut upload_batch = UploadBatch::new(command_buffer);
+    let mut allocate_indirect_buffer = |data: &[u8]| {
+        let buffer = factory.allocate_buffer(
+            &vk::BufferCreateInfo::builder()
+                .size(data.len() as _)
+                .usage(
+                    vk::BufferUsageFlags::INDIRECT_BUFFER
+                        | vk::BufferUsageFlags::STORAGE_BUFFER
+                        | vk::BufferUsageFlags::TRANSFER_DST,
+                )
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ..Default::default()
+            },
+        );
+        upload_batch.upload_buffer_memory(vk::PipelineStageFlags::DRAW_INDIRECT, &buffer, data, 0, factory);
+        buffer
+    };
+    let draw_command_buffer = allocate_indirect_buffer(&command_data);
+    let draw_count_buffer = allocate_indirect_buffer(&count_data);
+    upload_batch.flush(factory, queue);
+
+    (buckets, Some(draw_command_buffer), Some(draw_count_buffer))
+}
+
+// Only vertex and index buffers are created with device addresses, other buffers get zero
+fn get_buffer_addresses(
+    disk_bundle: &DiskResourceBundle,
+    buffers: &[HeapAllocatedResource<vk::Buffer>],
+    factory: &DeviceFactory,
+) -> Vec<vk::DeviceAddress> {
+    if !factory.is_buffer_device_address_enabled() {
+        return Vec::new();
     }
+    disk_bundle
+        .buffers
+        .iter()
+        .zip(buffers)
+        .map(|(disk_buffer, buffer)| {
+            let usage_flags = vk::BufferUsageFlags::from_raw(disk_buffer.usage_flags);
+            if usage_flags.intersects(vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER) {
+                factory.get_buffer_device_address(buffer.0)
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+// Layout matches ObjectData in generated shaders: object ID, material instance, transform index and LOD,
+// followed by the vertex buffer address that is zero without buffer device address and 8 reserved bytes.
+// Entries are static for now, culling can update LODs in place
+fn initialize_object_data(
+    buckets: &[RenderBucket],
+    meshes: &[RenderMesh],
+    buffer_addresses: &[vk::DeviceAddress],
+    command_buffer: &mut CommandBuffer,
+    factory: &mut DeviceFactory,
+    queue: &mut DeviceQueue,
+) -> (HeapAllocatedResource<vk::Buffer>, usize) {
+    let mut object_data = Vec::new();
+    for bucket in buckets {
+        let mut transform_id = 0;
+        for instance in &bucket.instances {
+            for _ in 0..instance.total_instance_count {
+                let object_id = bucket.first_object_id + transform_id;
+                for value in &[object_id, instance.material_instance.index(), transform_id, 0] {
+                    object_data.extend_from_slice(&(*value as u32).to_le_bytes());
+                }
+                let vertex_buffer = meshes[instance.mesh.index()].vertex_buffer;
+                let vertex_data_address = buffer_addresses.get(vertex_buffer.index()).copied().unwrap_or(0);
+                object_data.extend_from_slice(&vertex_data_address.to_le_bytes());
+                object_data.extend_from_slice(&0u64.to_le_bytes());
+                transform_id += 1;
+            }
+        }
+    }
+    let object_count = object_data.len() / OBJECT_DATA_SIZE;
+    log::info!("initializing {} objects", object_count);
+
+    // Empty bundles still get a valid buffer to bind
+    object_data.resize(object_data.len().max(OBJECT_DATA_SIZE), 0);
+    let object_data_buffer = factory.allocate_buffer(
+        &vk::BufferCreateInfo::builder()
+            .size(object_data.len() as _)
+            .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
+            .build(),
+        &vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ..Default::default()
+        },
+    );
+    let mut upload_batch = UploadBatch::new(command_buffer);
+    upload_batch.upload_buffer_memory(
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        &object_data_buffer,
+        &object_data,
+        0,
+        factory,
+    );
+    upload_batch.flush(factory, queue);
+
+    (object_data_buffer, object_count)
+}
 
-    buckets
+fn initialize_scene_nodes(disk_bundle: &DiskResourceBundle) -> Vec<SceneNode> {
+    disk_bundle
+        .scene_nodes
+        .iter()
+        .map(|disk_node| SceneNode {
+            name: disk_node.name.clone(),
+            parent: disk_node.parent,
+            local_transform: disk_node.local_transform,
+            instances: disk_node.instances.clone(),
+        })
+        .collect()
 }
 
 fn initialize_materials(disk_bundle: &DiskResourceBundle) -> Vec<RenderMaterial> {
@@ -440,19 +1207,23 @@ fn initialize_materials(disk_bundle: &DiskResourceBundle) -> Vec<RenderMaterial>
         }
 
         let fragment_alpha_test = disk_material.fragment_alpha_test;
+        let fragment_alpha_blend = disk_material.fragment_alpha_blend;
         let fragment_cull_flags = vk::CullModeFlags::from_raw(disk_material.fragment_cull_flags);
 
         let shader_image_mapping = disk_material.shader_image_mapping.clone();
         let shader_macro_definitions = disk_material.shader_macro_definitions.clone();
+        let shader_parameters = disk_material.shader_parameters.clone();
 
         materials.push(RenderMaterial {
             material_layout,
             vertex_stride,
             vertex_format,
             fragment_alpha_test,
+            fragment_alpha_blend,
             fragment_cull_flags,
             shader_image_mapping,
             shader_macro_definitions,
+            shader_parameters,
         });
     }
     materials
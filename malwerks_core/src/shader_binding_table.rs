@@ -0,0 +1,205 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_vk::*;
+
+// Shader group handle followed by shader record data, e.g. material indices of an instance
+#[derive(Debug, Copy, Clone)]
+pub struct ShaderRecord<'a> {
+    pub group: u32,
+    pub data: &'a [u8],
+}
+
+// Hit records are selected by the instance shader binding table offset plus the ray type,
+// so instances with their own hit groups reserve one record per ray type.
+pub struct ShaderBindingTableParameters<'a> {
+    pub raygen_record: ShaderRecord<'a>,
+    pub miss_records: &'a [ShaderRecord<'a>],
+    pub hit_records: &'a [ShaderRecord<'a>],
+    pub callable_records: &'a [ShaderRecord<'a>],
+}
+
+// Offsets and strides are in bytes, every region starts at the base alignment
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ShaderBindingTableRegion {
+    pub offset: u64,
+    pub stride: u64,
+    pub count: u64,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ShaderBindingTableLayout {
+    pub raygen: ShaderBindingTableRegion,
+    pub miss: ShaderBindingTableRegion,
+    pub hit: ShaderBindingTableRegion,
+    pub callable: ShaderBindingTableRegion,
+    pub size: u64,
+}
+
+pub struct ShaderBindingTable {
+    buffer: HeapAllocatedResource<vk::Buffer>,
+    layout: ShaderBindingTableLayout,
+    handle_size: u64,
+}
+
+impl ShaderBindingTable {
+    pub fn new(
+        parameters: &ShaderBindingTableParameters,
+        pipeline: vk::Pipeline,
+        group_count: u32,
+        ray_tracing_properties: &vk::PhysicalDeviceRayTracingPropertiesNV,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        let handle_size = ray_tracing_properties.shader_group_handle_size as u64;
+        let layout = get_shader_binding_table_layout(
+            parameters,
+            handle_size,
+            ray_tracing_properties.shader_group_base_alignment as u64,
+            ray_tracing_properties.max_shader_group_stride as u64,
+        );
+
+        let mut handles = vec![0u8; (group_count as u64 * handle_size) as usize];
+        factory.get_ray_tracing_shader_group_handles_nv(pipeline, 0, group_count, &mut handles);
+        let data = build_shader_binding_table(parameters, &layout, &handles, handle_size);
+
+        let buffer = factory.allocate_buffer(
+            &vk::BufferCreateInfo::builder()
+                .size(layout.size)
+                .usage(vk::BufferUsageFlags::RAY_TRACING_NV)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::CpuToGpu,
+                required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                ..Default::default()
+            },
+        );
+        let memory = factory.map_allocation_memory(&buffer);
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), memory, data.len());
+        }
+        factory.unmap_allocation_memory(&buffer);
+
+        Self {
+            buffer,
+            layout,
+            handle_size,
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        factory.deallocate_buffer(&self.buffer);
+    }
+
+    pub fn get_layout(&self) -> &ShaderBindingTableLayout {
+        &self.layout
+    }
+
+    // Replaces shader record data of one hit record, the table must not be in use by the GPU
+    pub fn update_hit_record_data(&mut self, record: usize, data: &[u8], factory: &mut DeviceFactory) {
+        assert!((record as u64) < self.layout.hit.count, "hit record is out of range");
+        assert!(
+            self.handle_size + data.len() as u64 <= self.layout.hit.stride,
+            "hit record data doesn't fit"
+        );
+
+        let offset = self.layout.hit.offset + record as u64 * self.layout.hit.stride + self.handle_size;
+        let memory = factory.map_allocation_memory(&self.buffer);
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), memory.add(offset as usize), data.len());
+        }
+        factory.unmap_allocation_memory(&self.buffer);
+    }
+
+    pub fn trace_rays(&self, width: u32, height: u32, depth: u32, command_buffer: &mut CommandBuffer) {
+        let buffer = self.buffer.0;
+        let get_buffer = |region: &ShaderBindingTableRegion| {
+            if region.count > 0 {
+                buffer
+            } else {
+                vk::Buffer::null()
+            }
+        };
+        command_buffer.trace_rays_nv(
+            buffer,
+            self.layout.raygen.offset,
+            get_buffer(&self.layout.miss),
+            self.layout.miss.offset,
+            self.layout.miss.stride,
+            get_buffer(&self.layout.hit),
+            self.layout.hit.offset,
+            self.layout.hit.stride,
+            get_buffer(&self.layout.callable),
+            self.layout.callable.offset,
+            self.layout.callable.stride,
+            width,
+            height,
+            depth,
+        );
+    }
+}
+
+pub(crate) fn get_shader_binding_table_layout(
+    parameters: &ShaderBindingTableParameters,
+    handle_size: u64,
+    base_alignment: u64,
+    max_stride: u64,
+) -> ShaderBindingTableLayout {
+    let align_up = |value: u64, alignment: u64| value.div_ceil(alignment) * alignment;
+
+    // Records of one region share the stride of the largest one
+    let mut region_end = 0;
+    let mut make_region = |records: &[ShaderRecord]| {
+        let data_size = records.iter().map(|record| record.data.len() as u64).max().unwrap_or(0);
+        let stride = align_up(handle_size + data_size, handle_size);
+        assert!(
+            stride <= max_stride,
+            "shader record stride {} exceeds {}",
+            stride,
+            max_stride
+        );
+
+        let region = ShaderBindingTableRegion {
+            offset: align_up(region_end, base_alignment),
+            stride,
+            count: records.len() as u64,
+        };
+        region_end = region.offset + region.stride * region.count;
+        region
+    };
+
+    ShaderBindingTableLayout {
+        raygen: make_region(std::slice::from_ref(&parameters.raygen_record)),
+        miss: make_region(parameters.miss_records),
+        hit: make_region(parameters.hit_records),
+        callable: make_region(parameters.callable_records),
+        size: region_end,
+    }
+}
+
+pub(crate) fn build_shader_binding_table(
+    parameters: &ShaderBindingTableParameters,
+    layout: &ShaderBindingTableLayout,
+    handles: &[u8],
+    handle_size: u64,
+) -> Vec<u8> {
+    let handle_size = handle_size as usize;
+    let mut data = vec![0u8; layout.size as usize];
+    let regions = [
+        (&layout.raygen, std::slice::from_ref(&parameters.raygen_record)),
+        (&layout.miss, parameters.miss_records),
+        (&layout.hit, parameters.hit_records),
+        (&layout.callable, parameters.callable_records),
+    ];
+    for (region, records) in regions.iter() {
+        for (record_id, record) in records.iter().enumerate() {
+            let handle = &handles[record.group as usize * handle_size..][..handle_size];
+            let record_data = &mut data[(region.offset + record_id as u64 * region.stride) as usize..];
+            record_data[..handle_size].copy_from_slice(handle);
+            record_data[handle_size..][..record.data.len()].copy_from_slice(record.data);
+        }
+    }
+    data
+}
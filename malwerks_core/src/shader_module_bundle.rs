@@ -28,15 +28,17 @@ pub enum ShaderModules {
     Compute(vk::ShaderModule),
 }
 
+// Stages with identical code share a shader module, pipeline libraries are shared between materials through it
 pub struct ShaderModuleBundle {
     pub shader_stages: Vec<ShaderModules>,
 }
 
 impl ShaderModuleBundle {
     pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        let mut destroyed_modules = std::collections::HashSet::new();
         macro_rules! destroy_shader_stage {
             ($stage: expr) => {
-                if $stage != vk::ShaderModule::null() {
+                if $stage != vk::ShaderModule::null() && destroyed_modules.insert($stage) {
                     factory.destroy_shader_module($stage);
                 }
             };
@@ -67,12 +69,15 @@ impl ShaderModuleBundle {
     }
 
     pub fn new(disk_stages: &DiskShaderStageBundle, factory: &mut DeviceFactory) -> Self {
+        let mut shader_modules: std::collections::HashMap<&[u32], vk::ShaderModule> = Default::default();
         macro_rules! create_shader_stage {
             ($code: expr) => {
                 if $code.is_empty() {
                     vk::ShaderModule::null()
                 } else {
-                    factory.create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(&$code).build())
+                    *shader_modules.entry(&$code).or_insert_with(|| {
+                        factory.create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(&$code).build())
+                    })
                 }
             };
         }
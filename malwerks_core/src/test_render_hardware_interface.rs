@@ -0,0 +1,253 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_vk::*;
+
+use crate::null_rhi::*;
+use crate::render_hardware_interface::*;
+use crate::vulkan_rhi::*;
+
+const TEST_SHADER: [u32; 1] = [0x0723_0203]; // SPIR-V magic, null backend doesn't look at the code
+
+struct TestFrame {
+    color_target: RhiImage,
+    depth_target: RhiImage,
+    storage_image: RhiImage,
+    constants: RhiBuffer,
+    indices: RhiBuffer,
+    indirect_draws: RhiBuffer,
+    draw_pipeline: RhiPipeline,
+    compute_pipeline: RhiPipeline,
+}
+
+fn create_test_frame<R: RenderHardwareInterface>(rhi: &mut R, factory: &mut R::Factory) -> TestFrame {
+    let image_parameters = RhiImageParameters {
+        width: 64,
+        height: 64,
+        format: RhiFormat::Rgba16Float,
+        render_target: true,
+        storage: false,
+    };
+    let frame = TestFrame {
+        color_target: rhi.create_image(&image_parameters, factory),
+        depth_target: rhi.create_image(
+            &RhiImageParameters {
+                format: RhiFormat::Depth32Float,
+                ..image_parameters
+            },
+            factory,
+        ),
+        storage_image: rhi.create_image(
+            &RhiImageParameters {
+                render_target: false,
+                storage: true,
+                ..image_parameters
+            },
+            factory,
+        ),
+        constants: rhi.create_buffer(
+            &RhiBufferParameters {
+                size: 16,
+                usage: RhiBufferUsage {
+                    storage: true,
+                    ..Default::default()
+                },
+                host_visible: true,
+            },
+            factory,
+        ),
+        indices: rhi.create_buffer(
+            &RhiBufferParameters {
+                size: 12,
+                usage: RhiBufferUsage {
+                    index: true,
+                    ..Default::default()
+                },
+                host_visible: false,
+            },
+            factory,
+        ),
+        indirect_draws: rhi.create_buffer(
+            &RhiBufferParameters {
+                size: 40,
+                usage: RhiBufferUsage {
+                    indirect: true,
+                    ..Default::default()
+                },
+                host_visible: false,
+            },
+            factory,
+        ),
+        draw_pipeline: rhi.create_graphics_pipeline(
+            &RhiGraphicsPipelineParameters {
+                vertex_shader: &TEST_SHADER,
+                fragment_shader: &TEST_SHADER,
+                color_formats: &[RhiFormat::Rgba16Float],
+                depth_format: Some(RhiFormat::Depth32Float),
+                storage_buffer_count: 1,
+                storage_image_count: 0,
+                push_constant_size: 0,
+            },
+            factory,
+        ),
+        compute_pipeline: rhi.create_compute_pipeline(
+            &RhiComputePipelineParameters {
+                compute_shader: &TEST_SHADER,
+                storage_buffer_count: 1,
+                storage_image_count: 1,
+                push_constant_size: 8,
+            },
+            factory,
+        ),
+    };
+    rhi.write_buffer(frame.constants, 4, &[1, 2, 3, 4], factory);
+    frame
+}
+
+fn record_test_frame<R: RenderHardwareInterface>(rhi: &mut R, frame: &TestFrame, command_list: &mut R::CommandList) {
+    rhi.bind_pipeline(frame.compute_pipeline, command_list);
+    rhi.bind_resources(
+        frame.compute_pipeline,
+        &[frame.constants],
+        &[frame.storage_image],
+        command_list,
+    );
+    rhi.push_constants(frame.compute_pipeline, &[0u8; 8], command_list);
+    rhi.dispatch([8, 8, 1], command_list);
+    rhi.barrier(command_list);
+
+    rhi.begin_pass(
+        &RhiPassParameters {
+            color_targets: &[frame.color_target],
+            depth_target: Some(frame.depth_target),
+            clear_color: Some([0.0, 0.0, 0.0, 1.0]),
+            clear_depth: Some(1.0),
+        },
+        command_list,
+    );
+    rhi.bind_pipeline(frame.draw_pipeline, command_list);
+    rhi.bind_resources(frame.draw_pipeline, &[frame.constants], &[], command_list);
+    rhi.draw(3, 1, command_list);
+    rhi.draw_indexed_indirect(frame.indices, frame.indirect_draws, 0, 2, command_list);
+    rhi.end_pass(command_list);
+}
+
+fn destroy_test_frame<R: RenderHardwareInterface>(rhi: &mut R, frame: TestFrame, factory: &mut R::Factory) {
+    for image in [frame.color_target, frame.depth_target, frame.storage_image] {
+        rhi.destroy_image(image, factory);
+    }
+    for buffer in [frame.constants, frame.indices, frame.indirect_draws] {
+        rhi.destroy_buffer(buffer, factory);
+    }
+    rhi.destroy_pipeline(frame.draw_pipeline, factory);
+    rhi.destroy_pipeline(frame.compute_pipeline, factory);
+}
+
+#[test]
+fn test_null_rhi_frame() {
+    let mut rhi = NullRhi::new();
+    let frame = create_test_frame(&mut rhi, &mut ());
+    assert_eq!(rhi.get_live_resource_count(), 8);
+    assert_eq!(
+        rhi.get_buffer_data(frame.constants),
+        &[0, 0, 0, 0, 1, 2, 3, 4, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+
+    record_test_frame(&mut rhi, &frame, &mut ());
+    assert_eq!(
+        rhi.get_stats(),
+        NullRhiStats {
+            num_passes: 1,
+            num_barriers: 1,
+            num_draws: 3,
+            num_dispatches: 1,
+        }
+    );
+
+    destroy_test_frame(&mut rhi, frame, &mut ());
+    assert_eq!(rhi.get_live_resource_count(), 0);
+}
+
+#[test]
+#[should_panic(expected = "draws have to be inside passes")]
+fn test_null_rhi_draw_outside_of_pass() {
+    let mut rhi = NullRhi::new();
+    let frame = create_test_frame(&mut rhi, &mut ());
+    rhi.bind_pipeline(frame.draw_pipeline, &mut ());
+    rhi.draw(3, 1, &mut ());
+}
+
+#[test]
+fn test_vulkan_rhi_resources() {
+    let mock_device = MockDevice::new();
+    let mut factory = mock_device.create_factory();
+    let mut command_buffer = mock_device.create_command_buffer();
+
+    let mut rhi = VulkanRhi::new();
+    let buffer = rhi.create_buffer(
+        &RhiBufferParameters {
+            size: 256,
+            usage: RhiBufferUsage {
+                storage: true,
+                indirect: true,
+                ..Default::default()
+            },
+            host_visible: true,
+        },
+        &mut factory,
+    );
+    rhi.write_buffer(buffer, 0, &[0xff; 16], &mut factory);
+    let image = rhi.create_image(
+        &RhiImageParameters {
+            width: 32,
+            height: 16,
+            format: RhiFormat::R11G11B10Float,
+            render_target: true,
+            storage: false,
+        },
+        &mut factory,
+    );
+    rhi.barrier(&mut command_buffer);
+
+    let calls = mock_device.take_calls();
+    assert!(calls.iter().any(|call| match call {
+        MockCall::CreateBuffer {
+            buffer: vk_buffer,
+            size,
+            usage,
+        } => {
+            *vk_buffer == rhi.get_buffer(buffer)
+                && *size == 256
+                && usage.contains(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER)
+        }
+        _ => false,
+    }));
+    assert!(calls.contains(&MockCall::CreateImage {
+        image: rhi.get_image(image),
+        format: vk::Format::B10G11R11_UFLOAT_PACK32,
+        extent: (32, 16, 1),
+        mip_levels: 1,
+        array_layers: 1,
+    }));
+    assert!(calls.contains(&MockCall::CreateImageView {
+        image_view: rhi.get_image_view(image),
+        image: rhi.get_image(image),
+    }));
+    assert!(calls.iter().any(|call| matches!(
+        call,
+        MockCall::PipelineBarrier {
+            src_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
+            ..
+        }
+    )));
+
+    let (vk_buffer, vk_image) = (rhi.get_buffer(buffer), rhi.get_image(image));
+    rhi.destroy(&mut factory);
+    let calls = mock_device.take_calls();
+    assert!(calls.contains(&MockCall::DestroyBuffer { buffer: vk_buffer }));
+    assert!(calls.contains(&MockCall::DestroyImage { image: vk_image }));
+
+    factory.destroy();
+}
@@ -0,0 +1,1008 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+use malwerks_vk::vk::Handle;
+use malwerks_vk::*;
+
+use crate::resource_bundle::*;
+
+// Mock device objects that resource bundles are loaded with, destroy() returns the device to inspect teardown calls
+struct TestDevice {
+    mock_device: MockDevice,
+    factory: DeviceFactory,
+    queue: DeviceQueue,
+    command_buffer: CommandBuffer,
+}
+
+impl TestDevice {
+    fn new() -> Self {
+        let mock_device = MockDevice::new();
+        let factory = mock_device.create_factory();
+        Self::from_factory(mock_device, factory)
+    }
+
+    fn with_buffer_device_address() -> Self {
+        let mock_device = MockDevice::new();
+        let factory = mock_device.create_factory_with_buffer_device_address();
+        Self::from_factory(mock_device, factory)
+    }
+
+    fn from_factory(mock_device: MockDevice, factory: DeviceFactory) -> Self {
+        let queue = mock_device.get_queue();
+        let command_buffer = mock_device.create_command_buffer();
+        Self {
+            mock_device,
+            factory,
+            queue,
+            command_buffer,
+        }
+    }
+
+    fn load_bundle(&mut self, disk_bundle: &DiskResourceBundle) -> ResourceBundle {
+        ResourceBundle::from_disk(
+            disk_bundle,
+            &mut self.command_buffer,
+            &mut self.factory,
+            &mut self.queue,
+        )
+    }
+
+    fn destroy(mut self, mut resource_bundle: ResourceBundle) -> MockDevice {
+        resource_bundle.destroy(&mut self.factory);
+        self.factory.destroy();
+        self.mock_device
+    }
+}
+
+#[test]
+fn test_resource_bundle_descriptor_writes() {
+    let mut test_device = TestDevice::new();
+
+    let disk_bundle = create_test_bundle();
+    assert!(disk_bundle.validate().is_ok());
+
+    let resource_bundle = test_device.load_bundle(&disk_bundle);
+    let calls = test_device.mock_device.take_calls();
+
+    assert_eq!(resource_bundle.buffers.len(), 3);
+    assert_eq!(resource_bundle.image_views.len(), 1);
+    assert_eq!(resource_bundle.descriptor_sets.len(), 1);
+
+    let descriptor_writes: Vec<_> = calls
+        .iter()
+        .filter_map(|call| match call {
+            MockCall::WriteDescriptorSet {
+                descriptor_set,
+                binding,
+                descriptor_type,
+                image_views,
+                ..
+            } => Some((*descriptor_set, *binding, *descriptor_type, image_views.clone())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        descriptor_writes,
+        vec![(
+            resource_bundle.descriptor_sets[0],
+            0,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            vec![resource_bundle.image_views[0]],
+        )]
+    );
+
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_upload_barriers() {
+    let mut test_device = TestDevice::new();
+
+    let disk_bundle = create_test_bundle();
+    let resource_bundle = test_device.load_bundle(&disk_bundle);
+    let calls = test_device.mock_device.take_calls();
+
+    // Every buffer is copied from a staging buffer between a pair of barriers
+    for buffer in &resource_bundle.buffers {
+        let barriers: Vec<_> = calls
+            .iter()
+            .filter_map(|call| match call {
+                MockCall::PipelineBarrier {
+                    src_stage_mask,
+                    dst_stage_mask,
+                    buffers,
+                    ..
+                } if buffers.contains(&buffer.0) => Some((*src_stage_mask, *dst_stage_mask)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            barriers,
+            vec![
+                (vk::PipelineStageFlags::HOST, vk::PipelineStageFlags::TRANSFER),
+                (vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::ALL_COMMANDS),
+            ]
+        );
+    }
+
+    // Image goes through transfer layout before the copy and ends up shader readable
+    let image = resource_bundle.images[0].0;
+    let image_commands: Vec<_> = calls
+        .iter()
+        .filter_map(|call| match call {
+            MockCall::PipelineBarrier { images, .. } if !images.is_empty() => Some(images.clone()),
+            MockCall::CopyBufferToImage {
+                dst_image,
+                dst_image_layout,
+                ..
+            } => Some(vec![(*dst_image, *dst_image_layout, *dst_image_layout)]),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        image_commands,
+        vec![
+            vec![(image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL)],
+            vec![(
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL
+            )],
+            vec![(
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            )],
+        ]
+    );
+
+    // Uploads are submitted and waited on before staging buffers are released,
+    // a single mesh counts as packed geometry so indirect draws are uploaded as well as object data
+    let submit_count = calls
+        .iter()
+        .filter(|call| matches!(call, MockCall::QueueSubmit { .. }))
+        .count();
+    assert_eq!(submit_count, 4);
+
+    let (buffer_count, image_count) = (resource_bundle.buffers.len(), resource_bundle.images.len());
+    let mock_device = test_device.destroy(resource_bundle);
+
+    // Everything that was created is destroyed
+    let calls = mock_device.take_calls();
+    let destroyed_buffer_count = calls
+        .iter()
+        .filter(|call| matches!(call, MockCall::DestroyBuffer { .. }))
+        .count();
+    let destroyed_image_count = calls
+        .iter()
+        .filter(|call| matches!(call, MockCall::DestroyImage { .. }))
+        .count();
+    assert_eq!(destroyed_buffer_count, buffer_count + 3);
+    assert_eq!(destroyed_image_count, image_count);
+}
+
+#[test]
+fn test_resource_bundle_color_space_debug() {
+    let mut test_device = TestDevice::new();
+
+    // Matching format and color space don't need debug resources
+    let disk_bundle = create_test_bundle();
+    let mut resource_bundle = test_device.load_bundle(&disk_bundle);
+    assert!(resource_bundle.color_space_debug_image_views.is_empty());
+    assert_eq!(
+        resource_bundle.color_space_debug_descriptor_sets,
+        resource_bundle.descriptor_sets
+    );
+    resource_bundle.destroy(&mut test_device.factory);
+    test_device.mock_device.take_calls();
+
+    // Color texture stored as BC7_UNORM is sampled without decoding
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle.images[0].color_space = DiskColorSpace::Srgb;
+    let mut resource_bundle = test_device.load_bundle(&disk_bundle);
+    let calls = test_device.mock_device.take_calls();
+
+    assert_eq!(resource_bundle.color_space_debug_image_views.len(), 1);
+    let debug_descriptor_set = resource_bundle.color_space_debug_descriptor_sets[0];
+    assert_ne!(debug_descriptor_set, resource_bundle.descriptor_sets[0]);
+
+    let debug_image_views: Vec<_> = calls
+        .iter()
+        .filter_map(|call| match call {
+            MockCall::WriteDescriptorSet {
+                descriptor_set,
+                image_views,
+                ..
+            } if *descriptor_set == debug_descriptor_set => Some(image_views.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        debug_image_views,
+        vec![vec![resource_bundle.color_space_debug_image_views[0].1]]
+    );
+
+    let material_instance = MaterialInstanceHandle::new(0);
+    assert_eq!(
+        resource_bundle.get_material_descriptor_set(material_instance),
+        resource_bundle.descriptor_sets[0]
+    );
+    resource_bundle.color_space_debug = true;
+    assert_eq!(
+        resource_bundle.get_material_descriptor_set(material_instance),
+        debug_descriptor_set
+    );
+
+    let mock_device = test_device.destroy(resource_bundle);
+    let destroyed_image_view_count = mock_device
+        .take_calls()
+        .iter()
+        .filter(|call| matches!(call, MockCall::DestroyImageView { .. }))
+        .count();
+    assert_eq!(destroyed_image_view_count, 2);
+}
+
+#[test]
+fn test_resource_bundle_sampler_anisotropy() {
+    let mut test_device = TestDevice::new();
+
+    let sampler_anisotropy = |calls: &[MockCall]| -> Vec<_> {
+        calls
+            .iter()
+            .filter_map(|call| match call {
+                MockCall::CreateSampler { max_anisotropy, .. } => Some(*max_anisotropy),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let disk_bundle = create_test_bundle();
+    let mut resource_bundle = test_device.load_bundle(&disk_bundle);
+    assert_eq!(
+        sampler_anisotropy(&test_device.mock_device.take_calls()),
+        vec![Some(8.0)]
+    );
+
+    // Override replaces the authored anisotropy and descriptor sets are written again
+    let old_sampler = resource_bundle.samplers[0];
+    test_device.factory.set_sampler_anisotropy_override(Some(2.0));
+    resource_bundle.recreate_samplers(&mut test_device.factory);
+    let calls = test_device.mock_device.take_calls();
+    assert_eq!(sampler_anisotropy(&calls), vec![Some(2.0)]);
+    assert!(calls.contains(&MockCall::DestroySampler { sampler: old_sampler }));
+    assert!(calls.iter().any(|call| matches!(
+        call,
+        MockCall::WriteDescriptorSet { descriptor_set, .. } if *descriptor_set == resource_bundle.descriptor_sets[0]
+    )));
+
+    // Anisotropy is clamped to the device limit and disabled below 2x
+    test_device.factory.set_sampler_anisotropy_override(Some(64.0));
+    resource_bundle.recreate_samplers(&mut test_device.factory);
+    assert_eq!(
+        sampler_anisotropy(&test_device.mock_device.take_calls()),
+        vec![Some(16.0)]
+    );
+    test_device.factory.set_sampler_anisotropy_override(Some(1.0));
+    resource_bundle.recreate_samplers(&mut test_device.factory);
+    assert_eq!(sampler_anisotropy(&test_device.mock_device.take_calls()), vec![None]);
+
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_texture_lod_override() {
+    assert_eq!(get_clamped_min_lod((2048, 1024), 12, 512), 2);
+    assert_eq!(get_clamped_min_lod((512, 512), 10, 512), 0);
+    assert_eq!(get_clamped_min_lod((4096, 4096), 3, 256), 2); // last mip is kept
+
+    let mut test_device = TestDevice::new();
+
+    let sampler_lods = |calls: &[MockCall]| -> Vec<_> {
+        calls
+            .iter()
+            .filter_map(|call| match call {
+                MockCall::CreateSampler {
+                    mip_lod_bias, min_lod, ..
+                } => Some((*mip_lod_bias, *min_lod)),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle.images[0].width = 16;
+    disk_bundle.images[0].height = 8;
+    disk_bundle.images[0].mipmap_count = 5;
+    disk_bundle.images[0].pixels = vec![0u8; 16 * 8];
+    let mut resource_bundle = test_device.load_bundle(&disk_bundle);
+    assert_eq!(sampler_lods(&test_device.mock_device.take_calls()), vec![(0.0, 0.0)]);
+
+    // Clamped image gets its own sampler, the shared one only changes the bias
+    test_device.factory.set_texture_lod_override(1.5, Some(4));
+    resource_bundle.recreate_samplers(&mut test_device.factory);
+    assert_eq!(
+        sampler_lods(&test_device.mock_device.take_calls()),
+        vec![(1.5, 0.0), (1.5, 2.0)]
+    );
+    let clamped_sampler = resource_bundle.clamped_samplers[0].1;
+    assert_eq!(resource_bundle.clamped_samplers, vec![((0, 0), clamped_sampler)]);
+
+    test_device.factory.set_texture_lod_override(0.0, None);
+    resource_bundle.recreate_samplers(&mut test_device.factory);
+    let calls = test_device.mock_device.take_calls();
+    assert_eq!(sampler_lods(&calls), vec![(0.0, 0.0)]);
+    assert!(calls.contains(&MockCall::DestroySampler {
+        sampler: clamped_sampler
+    }));
+    assert!(resource_bundle.clamped_samplers.is_empty());
+
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_runtime_mipmaps() {
+    let mut test_device = TestDevice::new();
+
+    // Only the top mip is stored, the remaining two are blitted from the previous ones
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle.images[0].format = vk::Format::R8G8B8A8_UNORM.as_raw();
+    disk_bundle.images[0].pixels = vec![0u8; 64];
+    disk_bundle.images[0].generate_mipmaps = true;
+    assert!(disk_bundle.validate().is_ok());
+
+    let resource_bundle = test_device.load_bundle(&disk_bundle);
+    let calls = test_device.mock_device.take_calls();
+
+    let image = resource_bundle.images[0].0;
+    assert!(calls.iter().any(|call| matches!(
+        call,
+        MockCall::CreateImage { image: created_image, mip_levels: 3, .. } if *created_image == image
+    )));
+    let blits: Vec<_> = calls
+        .iter()
+        .filter_map(|call| match call {
+            MockCall::BlitImage {
+                src_image,
+                dst_image,
+                mip_levels,
+                ..
+            } if *src_image == image && *dst_image == image => Some(mip_levels.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(blits, vec![vec![(0, 1)], vec![(1, 2)]]);
+
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_shared_descriptor_sets() {
+    let mut test_device = TestDevice::new();
+
+    // Instances that only differ in parameters (e.g. atlas rects) bind the same images
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle.material_instances.push(DiskMaterialInstance {
+        material_layout: MaterialLayoutHandle::new(0),
+        material_instance_data: vec![1u8; 64],
+        images: vec![(ImageHandle::new(0), SamplerHandle::new(0))],
+    });
+
+    let resource_bundle = test_device.load_bundle(&disk_bundle);
+    let calls = test_device.mock_device.take_calls();
+
+    assert_eq!(resource_bundle.descriptor_sets.len(), 2);
+    assert_eq!(resource_bundle.descriptor_sets[0], resource_bundle.descriptor_sets[1]);
+    let allocated_sets: Vec<_> = calls
+        .iter()
+        .filter_map(|call| match call {
+            MockCall::AllocateDescriptorSets { descriptor_sets, .. } => Some(descriptor_sets.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(allocated_sets, vec![vec![resource_bundle.descriptor_sets[0]]]);
+
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_packed_mesh_geometry() {
+    let mut test_device = TestDevice::new();
+
+    // Second triangle has 32 bit indices, so both meshes end up with them
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle
+        .buffers
+        .push(create_test_buffer(12, vk::BufferUsageFlags::VERTEX_BUFFER, 36));
+    let mut index_buffer = create_test_buffer(4, vk::BufferUsageFlags::INDEX_BUFFER, 12);
+    index_buffer.data[4] = 1;
+    disk_bundle.buffers.push(index_buffer);
+    disk_bundle.meshes.push(DiskRenderMesh {
+        vertex_buffer: BufferHandle::new(3),
+        index_buffer: (vk::IndexType::UINT32.as_raw(), BufferHandle::new(4)),
+        index_count: 3,
+        first_index: 0,
+        vertex_offset: 0,
+        occluder: None,
+    });
+    disk_bundle.pack_mesh_geometry();
+    assert!(disk_bundle.validate().is_ok());
+    assert!(disk_bundle.has_packed_mesh_geometry());
+
+    let resource_bundle = test_device.load_bundle(&disk_bundle);
+
+    // Instance transforms stay, mesh buffers are replaced with the packed ones
+    assert_eq!(resource_bundle.buffers.len(), 3);
+    assert_eq!(
+        resource_bundle.buckets[0].instance_transform_buffer,
+        BufferHandle::new(0)
+    );
+    let mesh_ranges: Vec<_> = resource_bundle
+        .meshes
+        .iter()
+        .map(|mesh| {
+            (
+                mesh.vertex_buffer,
+                mesh.index_buffer,
+                mesh.first_index,
+                mesh.vertex_offset,
+            )
+        })
+        .collect();
+    let packed_index_buffer = (vk::IndexType::UINT32, BufferHandle::new(2));
+    assert_eq!(
+        mesh_ranges,
+        vec![
+            (BufferHandle::new(1), packed_index_buffer, 0, 0),
+            (BufferHandle::new(1), packed_index_buffer, 3, 3),
+        ]
+    );
+
+    let index_data = &disk_bundle.buffers[2].data;
+    assert_eq!(index_data.len(), 24);
+    assert_eq!(&index_data[16..20], &[1, 0, 0, 0]);
+
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_multi_draws() {
+    let mut test_device = TestDevice::new();
+
+    // Two instances share the material, the third one has different parameters and starts a new multi draw
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle.buffers[2] = create_test_buffer(64, vk::BufferUsageFlags::STORAGE_BUFFER, 64 * 4);
+    disk_bundle.material_instances.push(DiskMaterialInstance {
+        material_layout: MaterialLayoutHandle::new(0),
+        material_instance_data: vec![1u8; 64],
+        images: vec![(ImageHandle::new(0), SamplerHandle::new(0))],
+    });
+    let instances = &mut disk_bundle.buckets[0].instances;
+    instances[0].total_instance_count = 2;
+    for material_instance in [0, 1] {
+        instances.push(DiskRenderInstance {
+            mesh: MeshHandle::new(0),
+            material_instance: MaterialInstanceHandle::new(material_instance),
+            total_instance_count: 1,
+            total_draw_count: 1,
+            sort_key: 0,
+        });
+    }
+
+    let resource_bundle = test_device.load_bundle(&disk_bundle);
+    let calls = test_device.mock_device.take_calls();
+
+    let multi_draws: Vec<_> = resource_bundle.buckets[0]
+        .multi_draws
+        .iter()
+        .map(|multi_draw| {
+            (
+                multi_draw.first_instance,
+                multi_draw.instance_count,
+                multi_draw.first_draw_command,
+                multi_draw.draw_count_id,
+            )
+        })
+        .collect();
+    assert_eq!(multi_draws, vec![(0, 2, 0, 0), (2, 1, 2, 1)]);
+
+    // Draw commands are 5 dwords each, counts are a dword per multi draw
+    let draw_command_buffer = resource_bundle.draw_command_buffer.as_ref().unwrap().0;
+    let draw_count_buffer = resource_bundle.draw_count_buffer.as_ref().unwrap().0;
+    let indirect_buffers: Vec<_> = calls
+        .iter()
+        .filter_map(|call| match call {
+            MockCall::CreateBuffer { buffer, size, usage }
+                if *buffer == draw_command_buffer || *buffer == draw_count_buffer =>
+            {
+                Some((*size, usage.contains(vk::BufferUsageFlags::INDIRECT_BUFFER)))
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(indirect_buffers, vec![(60, true), (8, true)]);
+
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_draw_predicates() {
+    let mut test_device = TestDevice::new();
+
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle.buckets[0].instances.push(DiskRenderInstance {
+        mesh: MeshHandle::new(0),
+        material_instance: MaterialInstanceHandle::new(0),
+        total_instance_count: 1,
+        total_draw_count: 1,
+        sort_key: 0,
+    });
+
+    let mut resource_bundle = test_device.load_bundle(&disk_bundle);
+    assert!(resource_bundle.draw_predicate_buffer.is_none());
+    test_device.mock_device.take_calls();
+
+    // A dword per render instance, visible until something overwrites it
+    resource_bundle.initialize_draw_predicates(
+        &mut test_device.command_buffer,
+        &mut test_device.factory,
+        &mut test_device.queue,
+    );
+    let draw_predicate_buffer = resource_bundle.draw_predicate_buffer.as_ref().unwrap().0;
+    let calls = test_device.mock_device.take_calls();
+    assert!(calls.iter().any(|call| match call {
+        MockCall::CreateBuffer { buffer, size, usage } if *buffer == draw_predicate_buffer => {
+            *size == 8 && usage.contains(vk::BufferUsageFlags::CONDITIONAL_RENDERING_EXT)
+        }
+        _ => false,
+    }));
+    assert!(calls.iter().any(|call| match call {
+        MockCall::CopyBuffer { dst_buffer, .. } => *dst_buffer == draw_predicate_buffer,
+        _ => false,
+    }));
+
+    test_device
+        .command_buffer
+        .begin_conditional_rendering(draw_predicate_buffer, 4, false);
+    test_device.command_buffer.end_conditional_rendering();
+    let calls = test_device.mock_device.take_calls();
+    assert_eq!(calls.len(), 2);
+    match calls[0] {
+        MockCall::BeginConditionalRendering {
+            buffer,
+            offset,
+            inverted,
+            ..
+        } => assert_eq!((buffer, offset, inverted), (draw_predicate_buffer, 4, false)),
+        _ => panic!("expected conditional rendering to begin"),
+    }
+    assert!(matches!(calls[1], MockCall::EndConditionalRendering { .. }));
+
+    let mock_device = test_device.destroy(resource_bundle);
+    assert!(mock_device.take_calls().iter().any(|call| match call {
+        MockCall::DestroyBuffer { buffer } => *buffer == draw_predicate_buffer,
+        _ => false,
+    }));
+}
+
+#[test]
+fn test_resource_bundle_vertex_pulling_buffers() {
+    let mut test_device = TestDevice::new();
+
+    // Vertex and index buffers are readable as whole words, other buffers are left as is
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle.buffers[0] = create_test_buffer(10, vk::BufferUsageFlags::VERTEX_BUFFER, 30);
+
+    let resource_bundle = test_device.load_bundle(&disk_bundle);
+    let calls = test_device.mock_device.take_calls();
+
+    let buffers: Vec<_> = resource_bundle.buffers.iter().map(|buffer| buffer.0).collect();
+    let created_buffers: Vec<_> = calls
+        .iter()
+        .filter_map(|call| match call {
+            MockCall::CreateBuffer { buffer, size, usage } if buffers.contains(buffer) => {
+                Some((*size, usage.contains(vk::BufferUsageFlags::STORAGE_BUFFER)))
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(created_buffers, vec![(32, true), (8, true), (64, true)]);
+
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_buffer_device_address() {
+    let mut test_device = TestDevice::with_buffer_device_address();
+
+    // Only vertex and index buffers get addresses, the mock keeps buffer handles in the upper dword
+    let resource_bundle = test_device.load_bundle(&create_test_bundle());
+    let calls = test_device.mock_device.take_calls();
+    assert!(resource_bundle.uses_buffer_device_address());
+
+    let buffers: Vec<_> = resource_bundle.buffers.iter().map(|buffer| buffer.0).collect();
+    let expected_addresses = vec![buffers[0].as_raw() << 32, buffers[1].as_raw() << 32, 0];
+    assert_eq!(resource_bundle.buffer_addresses, expected_addresses);
+
+    let address_usages: Vec<_> = calls
+        .iter()
+        .filter_map(|call| match call {
+            MockCall::CreateBuffer { buffer, usage, .. } if buffers.contains(buffer) => {
+                Some(usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS))
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(address_usages, vec![true, true, false]);
+
+    // Every memory block can back buffers with device addresses
+    let memory_flags: Vec<_> = calls
+        .iter()
+        .filter_map(|call| match call {
+            MockCall::AllocateMemory { device_address, .. } => Some(*device_address),
+            _ => None,
+        })
+        .collect();
+    assert!(!memory_flags.is_empty() && memory_flags.iter().all(|device_address| *device_address));
+
+    test_device.destroy(resource_bundle);
+
+    // Without buffer device address nothing changes
+    let mut test_device = TestDevice::new();
+    let resource_bundle = test_device.load_bundle(&create_test_bundle());
+    assert!(!resource_bundle.uses_buffer_device_address());
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_object_ids() {
+    let mut test_device = TestDevice::new();
+
+    // Object IDs are assigned per instance transform and continue across buckets
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle.buffers[2] = create_test_buffer(64, vk::BufferUsageFlags::STORAGE_BUFFER, 64 * 2);
+    disk_bundle
+        .buffers
+        .push(create_test_buffer(64, vk::BufferUsageFlags::STORAGE_BUFFER, 64));
+    disk_bundle.buckets[0].instances[0].total_instance_count = 2;
+    disk_bundle.buckets.push(DiskRenderBucket {
+        material: MaterialHandle::new(0),
+        instances: vec![DiskRenderInstance {
+            mesh: MeshHandle::new(0),
+            material_instance: MaterialInstanceHandle::new(0),
+            total_instance_count: 1,
+            total_draw_count: 1,
+            sort_key: 0,
+        }],
+        instance_transform_buffer: BufferHandle::new(3),
+        sort_key: 0,
+    });
+
+    let resource_bundle = test_device.load_bundle(&disk_bundle);
+    let calls = test_device.mock_device.take_calls();
+
+    assert_eq!(resource_bundle.object_count, 3);
+    assert_eq!(resource_bundle.buckets[1].first_object_id, 2);
+    assert_eq!(resource_bundle.find_object(1), Some((0, 1)));
+    assert_eq!(resource_bundle.find_object(2), Some((1, 0)));
+    assert_eq!(resource_bundle.find_object(3), None);
+
+    let object_data_size = calls.iter().find_map(|call| match call {
+        MockCall::CreateBuffer { buffer, size, .. } if *buffer == resource_bundle.object_data_buffer.0 => Some(*size),
+        _ => None,
+    });
+    assert_eq!(object_data_size, Some(3 * OBJECT_DATA_SIZE as vk::DeviceSize));
+
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_stress_scene() {
+    let mut test_device = TestDevice::new();
+
+    // Second material instance of the same material is used by another bucket, grid cells alternate between them
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle.material_instances.push(DiskMaterialInstance {
+        material_layout: MaterialLayoutHandle::new(0),
+        material_instance_data: vec![1u8; 64],
+        images: vec![(ImageHandle::new(0), SamplerHandle::new(0))],
+    });
+    disk_bundle
+        .buffers
+        .push(create_test_buffer(64, vk::BufferUsageFlags::STORAGE_BUFFER, 64));
+    disk_bundle.buckets.push(DiskRenderBucket {
+        material: MaterialHandle::new(0),
+        instances: vec![DiskRenderInstance {
+            mesh: MeshHandle::new(0),
+            material_instance: MaterialInstanceHandle::new(1),
+            total_instance_count: 1,
+            total_draw_count: 1,
+            sort_key: 0,
+        }],
+        instance_transform_buffer: BufferHandle::new(3),
+        sort_key: 0,
+    });
+    disk_bundle.generate_stress_scene(MeshHandle::new(0), 9);
+
+    assert_eq!(disk_bundle.buckets.len(), 1);
+    assert_eq!(disk_bundle.buffers.len(), 3);
+    assert_eq!(disk_bundle.buffers[2].data.len(), 9 * 64);
+    assert_eq!(disk_bundle.buckets[0].instance_transform_buffer, BufferHandle::new(2));
+    let instance_counts: Vec<usize> = disk_bundle.buckets[0]
+        .instances
+        .iter()
+        .map(|instance| instance.total_instance_count)
+        .collect();
+    assert_eq!(instance_counts, vec![5, 4]);
+    assert!(disk_bundle.validate().is_ok());
+
+    let resource_bundle = test_device.load_bundle(&disk_bundle);
+    assert_eq!(resource_bundle.object_count, 9);
+    assert_eq!(resource_bundle.find_object(8), Some((0, 8)));
+
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_sort_keys() {
+    let mut test_device = TestDevice::new();
+
+    // Material 2 has the same pipeline as material 0, material 1 blends and needs another one.
+    // Material instance 1 samples differently, so it has another texture set.
+    let mut disk_bundle = create_test_bundle();
+    let mut blended_material = create_test_bundle().materials.pop().unwrap();
+    blended_material.fragment_alpha_blend = true;
+    disk_bundle.materials.push(blended_material);
+    disk_bundle
+        .materials
+        .push(create_test_bundle().materials.pop().unwrap());
+    let mut sampler = create_test_bundle().samplers.pop().unwrap();
+    sampler.max_anisotropy = 1.0;
+    disk_bundle.samplers.push(sampler);
+    disk_bundle.material_instances.push(DiskMaterialInstance {
+        material_layout: MaterialLayoutHandle::new(0),
+        material_instance_data: vec![0u8; 64],
+        images: vec![(ImageHandle::new(0), SamplerHandle::new(1))],
+    });
+
+    // Instances of the first bucket interleave material instances, each bucket has its own transforms
+    let create_instance = |material_instance| DiskRenderInstance {
+        mesh: MeshHandle::new(0),
+        material_instance: MaterialInstanceHandle::new(material_instance),
+        total_instance_count: 1,
+        total_draw_count: 1,
+        sort_key: 0,
+    };
+    disk_bundle.buffers[2] = create_test_buffer(64, vk::BufferUsageFlags::STORAGE_BUFFER, 64 * 3);
+    disk_bundle.buckets[0].instances = vec![create_instance(1), create_instance(0), create_instance(1)];
+    for material in [1, 2] {
+        disk_bundle.buckets.push(DiskRenderBucket {
+            material: MaterialHandle::new(material),
+            instances: vec![create_instance(0)],
+            instance_transform_buffer: BufferHandle::new(disk_bundle.buffers.len()),
+            sort_key: 0,
+        });
+        disk_bundle
+            .buffers
+            .push(create_test_buffer(64, vk::BufferUsageFlags::STORAGE_BUFFER, 64));
+    }
+    disk_bundle.sort_render_buckets();
+
+    // Keys are only computed at import, loading copies them
+    let resource_bundle = test_device.load_bundle(&disk_bundle);
+    let bucket_keys: Vec<_> = resource_bundle.buckets.iter().map(|bucket| bucket.sort_key).collect();
+    let disk_bucket_keys: Vec<_> = disk_bundle.buckets.iter().map(|bucket| bucket.sort_key).collect();
+    assert_eq!(bucket_keys, disk_bucket_keys);
+    assert!(bucket_keys.windows(2).all(|keys| keys[0] <= keys[1]));
+    for bucket in &resource_bundle.buckets {
+        let material = &disk_bundle.materials[bucket.material.index()];
+        assert_eq!(
+            get_sort_key_pipeline_hash(bucket.sort_key),
+            material.compute_pipeline_hash()
+        );
+        assert_eq!(bucket.sort_key, bucket.instances[0].sort_key);
+        assert!(bucket
+            .instances
+            .windows(2)
+            .all(|pair| pair[0].sort_key <= pair[1].sort_key));
+    }
+
+    // Buckets sharing a pipeline end up next to each other and can be merged by comparing their keys
+    let bucket_pipelines: Vec<_> = resource_bundle
+        .buckets
+        .iter()
+        .map(|bucket| get_sort_key_pipeline_hash(bucket.sort_key))
+        .collect();
+    let shared_pipeline = disk_bundle.materials[0].compute_pipeline_hash();
+    assert_ne!(shared_pipeline, disk_bundle.materials[1].compute_pipeline_hash());
+    assert_eq!(
+        bucket_pipelines
+            .windows(2)
+            .filter(|pipelines| pipelines[0] == pipelines[1])
+            .collect::<Vec<_>>(),
+        vec![&[shared_pipeline, shared_pipeline]]
+    );
+
+    // Instances with the same texture set are adjacent, so the interleaved ones merge into two multi draws
+    let bucket = resource_bundle
+        .buckets
+        .iter()
+        .find(|bucket| bucket.instances.len() == 3)
+        .unwrap();
+    let material_instances: Vec<_> = bucket
+        .instances
+        .iter()
+        .map(|instance| instance.material_instance.index())
+        .collect();
+    assert!(material_instances == vec![0, 1, 1] || material_instances == vec![1, 1, 0]);
+    let multi_draws: Vec<_> = bucket
+        .multi_draws
+        .iter()
+        .map(|multi_draw| (multi_draw.first_instance, multi_draw.instance_count))
+        .collect();
+    assert_eq!(multi_draws.len(), 2);
+    assert!(multi_draws.contains(&(material_instances.iter().position(|id| *id == 1).unwrap(), 2)));
+
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_resource_tracking() {
+    let mut test_device = TestDevice::new();
+
+    test_device.factory.enable_resource_tracking();
+    test_device.factory.push_resource_tag("test_bundle");
+    let mut resource_bundle = test_device.load_bundle(&create_test_bundle());
+    test_device.factory.pop_resource_tag();
+
+    // Call sites are reported instead of the factory functions
+    let tracked_resources = test_device.factory.get_tracked_resources();
+    assert!(tracked_resources
+        .iter()
+        .any(|resource| resource.resource_type == TrackedResourceType::Buffer));
+    assert!(tracked_resources
+        .iter()
+        .any(|resource| resource.resource_type == TrackedResourceType::Image));
+    assert!(tracked_resources
+        .iter()
+        .any(|resource| resource.resource_type == TrackedResourceType::DescriptorPool));
+    for resource in &tracked_resources {
+        assert_eq!(resource.tag, "test_bundle");
+        assert!(resource.location.file().ends_with("resource_bundle.rs"));
+    }
+
+    resource_bundle.destroy(&mut test_device.factory);
+    assert!(test_device.factory.get_tracked_resources().is_empty());
+    test_device.factory.destroy();
+}
+
+#[test]
+fn test_resource_bundle_geometry_encoding() {
+    let mut test_device = TestDevice::new();
+
+    let mut disk_bundle = create_test_bundle();
+    let vertex_data: Vec<u8> = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+        .iter()
+        .flat_map(|value| value.to_le_bytes().to_vec())
+        .collect();
+    disk_bundle.buffers[0].encoding = DiskBufferEncoding::MeshoptVertex;
+    disk_bundle.buffers[0].data = vertex_data.clone();
+    disk_bundle.buffers[1].encoding = DiskBufferEncoding::MeshoptIndex;
+    disk_bundle.buffers[1].data = [2u16, 0, 1]
+        .iter()
+        .flat_map(|index| index.to_le_bytes().to_vec())
+        .collect();
+    disk_bundle.buffers[2].data = (0..64).collect();
+
+    let mut file = Vec::new();
+    disk_bundle.serialize_into(&mut file, 9).unwrap();
+    let loaded_bundle = DiskResourceBundle::deserialize_from(file.as_slice()).unwrap();
+
+    // Index codec can rotate triangles, winding is kept
+    assert_eq!(loaded_bundle.buffers[0].encoding, DiskBufferEncoding::MeshoptVertex);
+    assert_eq!(loaded_bundle.buffers[0].data, vertex_data);
+    assert_eq!(loaded_bundle.buffers[1].encoding, DiskBufferEncoding::MeshoptIndex);
+    let indices: Vec<u16> = loaded_bundle.buffers[1]
+        .data
+        .chunks_exact(2)
+        .map(|index| u16::from_le_bytes([index[0], index[1]]))
+        .collect();
+    assert!([[2, 0, 1], [0, 1, 2], [1, 2, 0]].contains(&[indices[0], indices[1], indices[2]]));
+    assert_eq!(loaded_bundle.buffers[2].encoding, DiskBufferEncoding::Raw);
+    assert_eq!(loaded_bundle.buffers[2].data, disk_bundle.buffers[2].data);
+    assert!(loaded_bundle.validate().is_ok());
+
+    // Unsupported strides are stored raw and keep their tag
+    disk_bundle.buffers[0].stride = 9;
+    disk_bundle.buffers[0].data.truncate(27);
+    let mut file = Vec::new();
+    disk_bundle.serialize_into(&mut file, 9).unwrap();
+    let loaded_bundle = DiskResourceBundle::deserialize_from(file.as_slice()).unwrap();
+    assert_eq!(loaded_bundle.buffers[0].encoding, DiskBufferEncoding::MeshoptVertex);
+    assert_eq!(loaded_bundle.buffers[0].data, disk_bundle.buffers[0].data);
+
+    let resource_bundle = test_device.load_bundle(&loaded_bundle);
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_scene_node_transforms() {
+    let mut test_device = TestDevice::new();
+
+    let translation =
+        |x: f32, y: f32, z: f32| [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, x, y, z, 1.0];
+
+    // Child node follows the parent, the parent itself doesn't own any instances
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle.scene_nodes = vec![
+        DiskSceneNode {
+            name: "parent".to_string(),
+            parent: None,
+            local_transform: translation(1.0, 0.0, 0.0),
+            instances: Vec::new(),
+        },
+        DiskSceneNode {
+            name: "child".to_string(),
+            parent: Some(0),
+            local_transform: translation(0.0, 2.0, 0.0),
+            instances: vec![(0, 0)],
+        },
+    ];
+
+    let mut resource_bundle = test_device.load_bundle(&disk_bundle);
+    assert_eq!(
+        resource_bundle.get_scene_node_world_transform(1),
+        translation(1.0, 2.0, 0.0)
+    );
+
+    let instance_transforms = resource_bundle.set_scene_node_transform(0, &translation(0.0, 0.0, 3.0));
+    assert_eq!(instance_transforms, vec![(0, 0, translation(0.0, 2.0, 3.0))]);
+    assert_eq!(
+        resource_bundle.scene_nodes[0].local_transform,
+        translation(0.0, 0.0, 3.0)
+    );
+
+    test_device.destroy(resource_bundle);
+}
+
+#[test]
+fn test_resource_bundle_material_animations() {
+    let mut test_device = TestDevice::new();
+
+    // 4x2 flipbook with 6 frames at 2 frames per second, scrolling horizontally
+    let mut disk_bundle = create_test_bundle();
+    disk_bundle.material_animations = vec![DiskMaterialAnimation {
+        material_instance: MaterialInstanceHandle::new(0),
+        parameter_id: 3,
+        uv_scroll: [0.5, 0.0],
+        flipbook_size: [4, 2],
+        flipbook_frame_count: 6,
+        flipbook_frame_rate: 2.0,
+    }];
+    assert!(disk_bundle.validate().is_ok());
+
+    let read_parameter = |resource_bundle: &ResourceBundle| {
+        let data = &resource_bundle.buckets[0].instances[0].material_instance_data[48..64];
+        let mut values = [0.0f32; 4];
+        for (value, bytes) in values.iter_mut().zip(data.chunks_exact(4)) {
+            *value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        values
+    };
+
+    let mut resource_bundle = test_device.load_bundle(&disk_bundle);
+    resource_bundle.update_material_animations(2.25);
+    assert_eq!(read_parameter(&resource_bundle), [0.25, 0.5, 0.125, 0.5]); // frame 4
+
+    // Frames wrap after the last one
+    resource_bundle.update_material_animations(3.25);
+    assert_eq!(read_parameter(&resource_bundle), [0.25, 0.5, 0.625, 0.0]);
+
+    test_device.destroy(resource_bundle);
+
+    disk_bundle.material_animations[0].parameter_id = 4;
+    disk_bundle.material_animations[0].flipbook_frame_count = 9;
+    assert_eq!(disk_bundle.validate().unwrap_err().len(), 2);
+}
@@ -0,0 +1,91 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::shader_binding_table::*;
+
+const HANDLE_SIZE: u64 = 16;
+const BASE_ALIGNMENT: u64 = 64;
+const MAX_STRIDE: u64 = 4096;
+
+#[test]
+fn test_shader_binding_table_layout() {
+    let material_indices = [7u32.to_le_bytes(), 9u32.to_le_bytes()];
+    let parameters = ShaderBindingTableParameters {
+        raygen_record: ShaderRecord { group: 0, data: &[] },
+        miss_records: &[
+            ShaderRecord { group: 1, data: &[] },
+            ShaderRecord { group: 2, data: &[] },
+            ShaderRecord { group: 2, data: &[] },
+        ],
+        hit_records: &[
+            ShaderRecord {
+                group: 3,
+                data: &material_indices[0],
+            },
+            ShaderRecord {
+                group: 4,
+                data: &material_indices[1],
+            },
+        ],
+        callable_records: &[],
+    };
+
+    let layout = get_shader_binding_table_layout(&parameters, HANDLE_SIZE, BASE_ALIGNMENT, MAX_STRIDE);
+    assert_eq!(
+        layout,
+        ShaderBindingTableLayout {
+            raygen: ShaderBindingTableRegion {
+                offset: 0,
+                stride: 16,
+                count: 1,
+            },
+            miss: ShaderBindingTableRegion {
+                offset: 64,
+                stride: 16,
+                count: 3,
+            },
+            hit: ShaderBindingTableRegion {
+                offset: 128,
+                stride: 32,
+                count: 2,
+            },
+            callable: ShaderBindingTableRegion {
+                offset: 192,
+                stride: 16,
+                count: 0,
+            },
+            size: 192,
+        }
+    );
+
+    // Every handle is filled with its group index
+    let handles: Vec<u8> = (0..5u8)
+        .flat_map(|group| std::iter::repeat_n(group, HANDLE_SIZE as usize))
+        .collect();
+    let data = build_shader_binding_table(&parameters, &layout, &handles, HANDLE_SIZE);
+    assert_eq!(data.len(), 192);
+    assert_eq!(&data[0..16], &[0u8; 16]);
+    assert_eq!(&data[64 + 16..64 + 32], &[2u8; 16]);
+    assert_eq!(&data[64 + 32..64 + 48], &[2u8; 16]);
+    assert_eq!(&data[160..176], &[4u8; 16]);
+    assert_eq!(&data[176..180], &9u32.to_le_bytes());
+    assert_eq!(&data[180..192], &[0u8; 12]);
+}
+
+#[test]
+#[should_panic(expected = "exceeds")]
+fn test_shader_binding_table_max_stride() {
+    let record_data = [0u8; 64];
+    let parameters = ShaderBindingTableParameters {
+        raygen_record: ShaderRecord {
+            group: 0,
+            data: &record_data,
+        },
+        miss_records: &[],
+        hit_records: &[],
+        callable_records: &[],
+    };
+    get_shader_binding_table_layout(&parameters, HANDLE_SIZE, BASE_ALIGNMENT, 64);
+}
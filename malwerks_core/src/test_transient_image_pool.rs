@@ -0,0 +1,57 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::transient_image_pool::*;
+
+fn make_interval(size: u64, alignment: u64, first_pass: u32, last_pass: u32) -> TransientImageInterval {
+    TransientImageInterval {
+        size,
+        alignment,
+        first_pass,
+        last_pass,
+    }
+}
+
+#[test]
+fn test_disjoint_passes_share_memory() {
+    // Accumulation targets of pass 0, half resolution color and depth of pass 1
+    let intervals = [
+        make_interval(1024, 256, 0, 0),
+        make_interval(512, 256, 0, 0),
+        make_interval(256, 256, 1, 1),
+        make_interval(256, 256, 1, 1),
+    ];
+    let (offsets, size) = get_aliased_image_offsets(&intervals);
+    assert_eq!(offsets, vec![0, 1024, 0, 256]);
+    assert_eq!(size, 1536);
+}
+
+#[test]
+fn test_overlapping_passes_dont_alias() {
+    let intervals = [
+        make_interval(300, 256, 0, 1),
+        make_interval(200, 256, 1, 2),
+        make_interval(100, 64, 2, 3),
+    ];
+    let (offsets, size) = get_aliased_image_offsets(&intervals);
+
+    // Second image is aligned after the first one, the third one fits before the second one
+    assert_eq!(offsets, vec![0, 512, 0]);
+    assert_eq!(size, 712);
+    for (a, interval_a) in intervals.iter().enumerate() {
+        for (b, interval_b) in intervals.iter().enumerate().skip(a + 1) {
+            let passes_overlap =
+                interval_a.first_pass <= interval_b.last_pass && interval_b.first_pass <= interval_a.last_pass;
+            let memory_overlaps =
+                offsets[a] < offsets[b] + interval_b.size && offsets[b] < offsets[a] + interval_a.size;
+            assert!(!(passes_overlap && memory_overlaps), "images {} and {} alias", a, b);
+        }
+    }
+}
+
+#[test]
+fn test_no_transient_images() {
+    assert_eq!(get_aliased_image_offsets(&[]), (Vec::new(), 0));
+}
@@ -0,0 +1,233 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_vk::*;
+
+// Image that is only used by passes in [first_pass, last_pass], contents are undefined when the first pass starts
+pub struct TransientImageParameters {
+    pub width: u32,
+    pub height: u32,
+    pub image_format: vk::Format,
+    pub image_usage: vk::ImageUsageFlags,
+    pub aspect_mask: vk::ImageAspectFlags,
+    pub first_pass: u32,
+    pub last_pass: u32,
+}
+
+// Placement of one image in the shared memory block, memory size and alignment come from the image requirements
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TransientImageInterval {
+    pub size: u64,
+    pub alignment: u64,
+    pub first_pass: u32,
+    pub last_pass: u32,
+}
+
+// Images with disjoint pass intervals share memory, images are bound to one allocation per memory type
+pub struct TransientImagePool {
+    images: Vec<(HeapAllocatedResource<vk::Image>, vk::ImageView)>,
+    image_formats: Vec<vk::Format>,
+    memory: Vec<HeapAllocatedMemory>,
+    memory_size: u64,
+    unaliased_memory_size: u64,
+}
+
+impl TransientImagePool {
+    pub fn new(image_parameters: &[TransientImageParameters], device: &Device, factory: &mut DeviceFactory) -> Self {
+        let extra_image_usage_flags = if device.get_device_options().enable_render_target_export {
+            vk::ImageUsageFlags::TRANSFER_SRC
+        } else {
+            vk::ImageUsageFlags::default()
+        };
+
+        let mut images = Vec::with_capacity(image_parameters.len());
+        let mut requirements = Vec::with_capacity(image_parameters.len());
+        for parameters in image_parameters {
+            assert!(
+                parameters.first_pass <= parameters.last_pass,
+                "transient image is released before it is used"
+            );
+            let image = factory.create_image(
+                &vk::ImageCreateInfo::builder()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(parameters.image_format)
+                    .extent(vk::Extent3D {
+                        width: parameters.width,
+                        height: parameters.height,
+                        depth: 1,
+                    })
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(parameters.image_usage | extra_image_usage_flags)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .build(),
+            );
+            images.push(image);
+            requirements.push(factory.get_image_memory_requirements(image));
+        }
+
+        // Images that can't live in the same memory type are aliased separately
+        let mut memory_type_groups: Vec<(u32, Vec<usize>)> = Vec::new();
+        for (image_id, image_requirements) in requirements.iter().enumerate() {
+            match memory_type_groups
+                .iter_mut()
+                .find(|(memory_type_bits, _)| *memory_type_bits == image_requirements.memory_type_bits)
+            {
+                Some((_, image_ids)) => image_ids.push(image_id),
+                None => memory_type_groups.push((image_requirements.memory_type_bits, vec![image_id])),
+            }
+        }
+
+        let mut bound_images = vec![None; images.len()];
+        let mut memory = Vec::with_capacity(memory_type_groups.len());
+        let mut memory_size = 0;
+        for (memory_type_bits, image_ids) in &memory_type_groups {
+            let intervals: Vec<TransientImageInterval> = image_ids
+                .iter()
+                .map(|image_id| TransientImageInterval {
+                    size: requirements[*image_id].size,
+                    alignment: requirements[*image_id].alignment,
+                    first_pass: image_parameters[*image_id].first_pass,
+                    last_pass: image_parameters[*image_id].last_pass,
+                })
+                .collect();
+            let (offsets, block_size) = get_aliased_image_offsets(&intervals);
+            let block_memory = factory.allocate_heap_memory(
+                &vk::MemoryRequirements {
+                    size: block_size,
+                    alignment: intervals.iter().map(|interval| interval.alignment).max().unwrap_or(1),
+                    memory_type_bits: *memory_type_bits,
+                },
+                &vk_mem::AllocationCreateInfo {
+                    usage: vk_mem::MemoryUsage::GpuOnly,
+                    required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                    ..Default::default()
+                },
+            );
+            for (image_id, offset) in image_ids.iter().zip(offsets) {
+                bound_images[*image_id] = Some(factory.bind_aliased_image(images[*image_id], &block_memory, offset));
+            }
+            memory.push(block_memory);
+            memory_size += block_size;
+        }
+
+        let images = bound_images
+            .into_iter()
+            .zip(image_parameters)
+            .map(|(image, parameters)| {
+                let image = image.unwrap();
+                let image_view = factory.create_image_view(
+                    &vk::ImageViewCreateInfo::builder()
+                        .image(image.0)
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .format(parameters.image_format)
+                        .components(vk::ComponentMapping::default())
+                        .subresource_range(
+                            vk::ImageSubresourceRange::builder()
+                                .aspect_mask(parameters.aspect_mask)
+                                .base_mip_level(0)
+                                .level_count(1)
+                                .base_array_layer(0)
+                                .layer_count(1)
+                                .build(),
+                        )
+                        .build(),
+                );
+                (image, image_view)
+            })
+            .collect();
+
+        let unaliased_memory_size = requirements.iter().map(|requirements| requirements.size).sum();
+        log::info!(
+            "{} transient images use {} KiB instead of {} KiB",
+            image_parameters.len(),
+            memory_size / 1024,
+            unaliased_memory_size / 1024
+        );
+
+        Self {
+            images,
+            image_formats: image_parameters
+                .iter()
+                .map(|parameters| parameters.image_format)
+                .collect(),
+            memory,
+            memory_size,
+            unaliased_memory_size,
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        for (image, image_view) in &self.images {
+            factory.destroy_image_view(*image_view);
+            factory.destroy_image(image.0);
+        }
+        for memory in &self.memory {
+            factory.deallocate_heap_memory(memory);
+        }
+    }
+
+    pub fn get_image_count(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn get_image(&self, index: usize) -> (&HeapAllocatedResource<vk::Image>, vk::ImageView) {
+        let (image, image_view) = &self.images[index];
+        (image, *image_view)
+    }
+
+    pub fn get_image_format(&self, index: usize) -> vk::Format {
+        self.image_formats[index]
+    }
+
+    pub fn get_memory_size(&self) -> u64 {
+        self.memory_size
+    }
+
+    // Memory the images would use if every image had its own allocation
+    pub fn get_unaliased_memory_size(&self) -> u64 {
+        self.unaliased_memory_size
+    }
+}
+
+// Largest images are placed first, every image goes to the lowest offset that doesn't overlap
+// with an already placed image used by the same passes. Returns offsets and the size of the memory block.
+pub(crate) fn get_aliased_image_offsets(intervals: &[TransientImageInterval]) -> (Vec<u64>, u64) {
+    let align_up = |value: u64, alignment: u64| value.div_ceil(alignment.max(1)) * alignment.max(1);
+
+    let mut placement_order: Vec<usize> = (0..intervals.len()).collect();
+    placement_order.sort_by(|a, b| intervals[*b].size.cmp(&intervals[*a].size).then(a.cmp(b)));
+
+    let mut offsets = vec![0; intervals.len()];
+    let mut placed: Vec<usize> = Vec::with_capacity(intervals.len());
+    let mut block_size = 0;
+    for image_id in placement_order {
+        let interval = &intervals[image_id];
+        let mut live_ranges: Vec<(u64, u64)> = placed
+            .iter()
+            .filter(|other_id| {
+                let other = &intervals[**other_id];
+                interval.first_pass <= other.last_pass && other.first_pass <= interval.last_pass
+            })
+            .map(|other_id| (offsets[*other_id], offsets[*other_id] + intervals[*other_id].size))
+            .collect();
+        live_ranges.sort_unstable();
+
+        let mut offset = 0;
+        for (range_start, range_end) in live_ranges {
+            if offset + interval.size <= range_start {
+                break;
+            }
+            offset = offset.max(align_up(range_end, interval.alignment));
+        }
+
+        offsets[image_id] = offset;
+        placed.push(image_id);
+        block_size = block_size.max(offset + interval.size);
+    }
+    (offsets, block_size)
+}
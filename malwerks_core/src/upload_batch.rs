@@ -67,6 +67,22 @@ impl<'a> UploadBatch<'a> {
         self.temporary_buffers.push(temp_buffer);
     }
 
+    // Top mip has to be uploaded first, see generate_mipmaps()
+    pub fn generate_image_mipmaps(
+        &mut self,
+        image: &HeapAllocatedResource<vk::Image>,
+        image_size: (u32, u32, u32),
+        image_params: (usize, usize),
+    ) {
+        generate_mipmaps(
+            image,
+            image_size,
+            image_params,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            self.command_buffer,
+        );
+    }
+
     pub fn upload_buffer_memory(
         &mut self,
         dst_stage_flags: vk::PipelineStageFlags,
@@ -104,7 +120,8 @@ impl<'a> UploadBatch<'a> {
     }
 }
 
-fn upload_image_memory(
+// Records the upload without waiting for it, returned temporary buffer has to live until the command buffer is done
+pub fn upload_image_memory(
     image: &HeapAllocatedResource<vk::Image>,
     image_size: (u32, u32, u32),
     image_params: (usize, usize, usize),
@@ -211,6 +228,138 @@ fn upload_image_memory(
     temp_buffer
 }
 
+pub fn get_full_mipmap_count(image_size: (u32, u32, u32)) -> usize {
+    let max_dimension = image_size.0.max(image_size.1).max(image_size.2);
+    (32 - max_dimension.leading_zeros()) as usize
+}
+
+// Fills mips 1..mipmap_count of all layers by repeatedly blitting the previous mip with linear filtering.
+// Top mip is expected in `top_mip_layout`, the rest is discarded. Image needs TRANSFER_SRC and TRANSFER_DST usage,
+// its format has to support linear blits, so block compressed formats can't be used.
+// All mips end up in SHADER_READ_ONLY_OPTIMAL layout.
+pub fn generate_mipmaps(
+    image: &HeapAllocatedResource<vk::Image>,
+    image_size: (u32, u32, u32),
+    image_params: (usize, usize),
+    top_mip_layout: vk::ImageLayout,
+    command_buffer: &mut CommandBuffer,
+) {
+    let (num_mip_levels, num_array_layers) = image_params;
+    assert!(num_mip_levels > 1, "image has no mips to generate");
+
+    let mip_barrier = |base_mip_level: usize,
+                       level_count: usize,
+                       src_access_mask: vk::AccessFlags,
+                       dst_access_mask: vk::AccessFlags,
+                       old_layout: vk::ImageLayout,
+                       new_layout: vk::ImageLayout| {
+        vk::ImageMemoryBarrier::builder()
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(!0)
+            .dst_queue_family_index(!0)
+            .image(image.0)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(base_mip_level as _)
+                    .level_count(level_count as _)
+                    .base_array_layer(0)
+                    .layer_count(num_array_layers as _)
+                    .build(),
+            )
+            .build()
+    };
+
+    command_buffer.pipeline_barrier(
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::PipelineStageFlags::TRANSFER,
+        None,
+        &[],
+        &[],
+        &[
+            mip_barrier(
+                0,
+                1,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+                top_mip_layout,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ),
+            mip_barrier(
+                1,
+                num_mip_levels - 1,
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ),
+        ],
+    );
+
+    let mip_extent = |mip: usize| vk::Offset3D {
+        x: (image_size.0 >> mip).max(1) as _,
+        y: (image_size.1 >> mip).max(1) as _,
+        z: (image_size.2 >> mip).max(1) as _,
+    };
+    let mip_layers = |mip: usize| {
+        vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(mip as _)
+            .base_array_layer(0)
+            .layer_count(num_array_layers as _)
+            .build()
+    };
+    for mip in 1..num_mip_levels {
+        command_buffer.blit_image(
+            image.0,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            image.0,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[vk::ImageBlit::builder()
+                .src_subresource(mip_layers(mip - 1))
+                .src_offsets([vk::Offset3D::default(), mip_extent(mip - 1)])
+                .dst_subresource(mip_layers(mip))
+                .dst_offsets([vk::Offset3D::default(), mip_extent(mip)])
+                .build()],
+            vk::Filter::LINEAR,
+        );
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            None,
+            &[],
+            &[],
+            &[mip_barrier(
+                mip,
+                1,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            )],
+        );
+    }
+
+    command_buffer.pipeline_barrier(
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        None,
+        &[],
+        &[],
+        &[mip_barrier(
+            0,
+            num_mip_levels,
+            vk::AccessFlags::TRANSFER_READ,
+            vk::AccessFlags::SHADER_READ,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )],
+    );
+}
+
 fn upload_buffer_memory(
     dst_stage_flags: vk::PipelineStageFlags,
     buffer: &HeapAllocatedResource<vk::Buffer>,
@@ -0,0 +1,709 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_vk::*;
+
+use crate::render_hardware_interface::*;
+
+struct VulkanRhiImage {
+    image: HeapAllocatedResource<vk::Image>,
+    image_view: vk::ImageView,
+    parameters: RhiImageParameters,
+    layout: vk::ImageLayout,
+}
+
+struct VulkanRhiPipeline {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    bind_point: vk::PipelineBindPoint,
+    storage_buffer_count: u32,
+}
+
+// Vulkan backend, needs a device with dynamic rendering and push descriptors enabled.
+// Image layouts are tracked per image and transitioned when images are used as targets or storage images.
+#[derive(Default)]
+pub struct VulkanRhi {
+    buffers: Vec<Option<(HeapAllocatedResource<vk::Buffer>, RhiBufferParameters)>>,
+    images: Vec<Option<VulkanRhiImage>>,
+    pipelines: Vec<Option<VulkanRhiPipeline>>,
+    active_pass: bool,
+}
+
+impl VulkanRhi {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Destroys resources that are still alive
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        for buffer in (0..self.buffers.len()).map(RhiBuffer::new) {
+            if self.buffers[buffer.index()].is_some() {
+                self.destroy_buffer(buffer, factory);
+            }
+        }
+        for image in (0..self.images.len()).map(RhiImage::new) {
+            if self.images[image.index()].is_some() {
+                self.destroy_image(image, factory);
+            }
+        }
+        for pipeline in (0..self.pipelines.len()).map(RhiPipeline::new) {
+            if self.pipelines[pipeline.index()].is_some() {
+                self.destroy_pipeline(pipeline, factory);
+            }
+        }
+    }
+
+    pub fn get_buffer(&self, buffer: RhiBuffer) -> vk::Buffer {
+        self.get_buffer_resource(buffer).0 .0
+    }
+
+    pub fn get_image(&self, image: RhiImage) -> vk::Image {
+        self.get_image_resource(image).image.0
+    }
+
+    pub fn get_image_view(&self, image: RhiImage) -> vk::ImageView {
+        self.get_image_resource(image).image_view
+    }
+
+    fn get_buffer_resource(&self, buffer: RhiBuffer) -> &(HeapAllocatedResource<vk::Buffer>, RhiBufferParameters) {
+        self.buffers[buffer.index()].as_ref().expect("buffer was destroyed")
+    }
+
+    fn get_image_resource(&self, image: RhiImage) -> &VulkanRhiImage {
+        self.images[image.index()].as_ref().expect("image was destroyed")
+    }
+
+    fn get_pipeline_resource(&self, pipeline: RhiPipeline) -> &VulkanRhiPipeline {
+        self.pipelines[pipeline.index()]
+            .as_ref()
+            .expect("pipeline was destroyed")
+    }
+
+    fn make_layout_transition(
+        &mut self,
+        image: RhiImage,
+        new_layout: vk::ImageLayout,
+    ) -> Option<vk::ImageMemoryBarrier> {
+        let image = self.images[image.index()].as_mut().expect("image was destroyed");
+        if image.layout == new_layout {
+            return None;
+        }
+        let old_layout = std::mem::replace(&mut image.layout, new_layout);
+        Some(
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+                .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(image.image.0)
+                .subresource_range(get_subresource_range(image.parameters.format))
+                .build(),
+        )
+    }
+
+    fn create_pipeline_layout(
+        &mut self,
+        storage_buffer_count: u32,
+        storage_image_count: u32,
+        push_constant_size: u32,
+        factory: &mut DeviceFactory,
+    ) -> (vk::DescriptorSetLayout, vk::PipelineLayout) {
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = (0..storage_buffer_count + storage_image_count)
+            .map(|binding| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding)
+                    .descriptor_type(if binding < storage_buffer_count {
+                        vk::DescriptorType::STORAGE_BUFFER
+                    } else {
+                        vk::DescriptorType::STORAGE_IMAGE
+                    })
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::ALL)
+                    .build()
+            })
+            .collect();
+        let descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder()
+                .flags(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR)
+                .bindings(&bindings)
+                .build(),
+        );
+
+        let push_constant_ranges = if push_constant_size > 0 {
+            vec![vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::ALL)
+                .offset(0)
+                .size(push_constant_size)
+                .build()]
+        } else {
+            Vec::new()
+        };
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[descriptor_set_layout])
+                .push_constant_ranges(&push_constant_ranges)
+                .build(),
+        );
+        (descriptor_set_layout, pipeline_layout)
+    }
+}
+
+impl RenderHardwareInterface for VulkanRhi {
+    type Factory = DeviceFactory;
+    type CommandList = CommandBuffer;
+
+    fn create_buffer(&mut self, parameters: &RhiBufferParameters, factory: &mut DeviceFactory) -> RhiBuffer {
+        let mut usage = vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST;
+        if parameters.usage.index {
+            usage |= vk::BufferUsageFlags::INDEX_BUFFER;
+        }
+        if parameters.usage.uniform {
+            usage |= vk::BufferUsageFlags::UNIFORM_BUFFER;
+        }
+        if parameters.usage.storage {
+            usage |= vk::BufferUsageFlags::STORAGE_BUFFER;
+        }
+        if parameters.usage.indirect {
+            usage |= vk::BufferUsageFlags::INDIRECT_BUFFER;
+        }
+
+        let buffer = factory.allocate_buffer(
+            &vk::BufferCreateInfo::builder()
+                .size(parameters.size)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build(),
+            &if parameters.host_visible {
+                vk_mem::AllocationCreateInfo {
+                    usage: vk_mem::MemoryUsage::CpuToGpu,
+                    required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    ..Default::default()
+                }
+            } else {
+                vk_mem::AllocationCreateInfo {
+                    usage: vk_mem::MemoryUsage::GpuOnly,
+                    required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                    ..Default::default()
+                }
+            },
+        );
+        self.buffers.push(Some((buffer, *parameters)));
+        RhiBuffer::new(self.buffers.len() - 1)
+    }
+
+    fn destroy_buffer(&mut self, buffer: RhiBuffer, factory: &mut DeviceFactory) {
+        let (buffer, _) = self.buffers[buffer.index()].take().expect("buffer was destroyed twice");
+        factory.deallocate_buffer(&buffer);
+    }
+
+    fn write_buffer(&mut self, buffer: RhiBuffer, offset: u64, data: &[u8], factory: &mut DeviceFactory) {
+        let (buffer, parameters) = self.get_buffer_resource(buffer);
+        assert!(parameters.host_visible, "only host visible buffers can be written");
+        assert!(
+            offset + data.len() as u64 <= parameters.size,
+            "buffer write doesn't fit"
+        );
+
+        let memory = factory.map_allocation_memory(buffer);
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), memory.add(offset as usize), data.len());
+        }
+        factory.unmap_allocation_memory(buffer);
+    }
+
+    fn create_image(&mut self, parameters: &RhiImageParameters, factory: &mut DeviceFactory) -> RhiImage {
+        let format = get_format(parameters.format);
+        let mut usage =
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST;
+        if parameters.render_target {
+            usage |= if parameters.format.is_depth() {
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+            } else {
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+            };
+        }
+        if parameters.storage {
+            usage |= vk::ImageUsageFlags::STORAGE;
+        }
+
+        let image = factory.allocate_image(
+            &vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(format)
+                .extent(vk::Extent3D {
+                    width: parameters.width,
+                    height: parameters.height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ..Default::default()
+            },
+        );
+        let image_view = factory.create_image_view(
+            &vk::ImageViewCreateInfo::builder()
+                .image(image.0)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(format)
+                .components(vk::ComponentMapping::default())
+                .subresource_range(get_subresource_range(parameters.format))
+                .build(),
+        );
+
+        self.images.push(Some(VulkanRhiImage {
+            image,
+            image_view,
+            parameters: *parameters,
+            layout: vk::ImageLayout::UNDEFINED,
+        }));
+        RhiImage::new(self.images.len() - 1)
+    }
+
+    fn destroy_image(&mut self, image: RhiImage, factory: &mut DeviceFactory) {
+        let image = self.images[image.index()].take().expect("image was destroyed twice");
+        factory.destroy_image_view(image.image_view);
+        factory.deallocate_image(&image.image);
+    }
+
+    fn create_graphics_pipeline(
+        &mut self,
+        parameters: &RhiGraphicsPipelineParameters,
+        factory: &mut DeviceFactory,
+    ) -> RhiPipeline {
+        let (descriptor_set_layout, pipeline_layout) = self.create_pipeline_layout(
+            parameters.storage_buffer_count,
+            parameters.storage_image_count,
+            parameters.push_constant_size,
+            factory,
+        );
+        let vertex_shader = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(parameters.vertex_shader)
+                .build(),
+        );
+        let fragment_shader = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(parameters.fragment_shader)
+                .build(),
+        );
+
+        let shader_entry_name = std::ffi::CString::new("main").unwrap();
+        let shader_stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vertex_shader)
+                .name(&shader_entry_name)
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(fragment_shader)
+                .name(&shader_entry_name)
+                .build(),
+        ];
+        let color_formats: Vec<vk::Format> = parameters
+            .color_formats
+            .iter()
+            .map(|format| get_format(*format))
+            .collect();
+        let color_blend_attachments: Vec<vk::PipelineColorBlendAttachmentState> = color_formats
+            .iter()
+            .map(|_| {
+                vk::PipelineColorBlendAttachmentState::builder()
+                    .color_write_mask(vk::ColorComponentFlags::all())
+                    .build()
+            })
+            .collect();
+        let pipeline_rendering_info = PipelineRenderingCreateInfoKHR {
+            color_attachment_count: color_formats.len() as _,
+            p_color_attachment_formats: color_formats.as_ptr(),
+            depth_attachment_format: match parameters.depth_format {
+                Some(format) => get_format(format),
+                None => vk::Format::UNDEFINED,
+            },
+            ..Default::default()
+        };
+
+        // Viewport and scissor cover the whole pass, geometry is fetched by the shaders
+        let mut create_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&shader_stages)
+            .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+            .input_assembly_state(
+                &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                    .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                    .build(),
+            )
+            .viewport_state(
+                &vk::PipelineViewportStateCreateInfo::builder()
+                    .viewport_count(1)
+                    .scissor_count(1)
+                    .build(),
+            )
+            .rasterization_state(
+                &vk::PipelineRasterizationStateCreateInfo::builder()
+                    .polygon_mode(vk::PolygonMode::FILL)
+                    .cull_mode(vk::CullModeFlags::NONE)
+                    .line_width(1.0)
+                    .build(),
+            )
+            .multisample_state(
+                &vk::PipelineMultisampleStateCreateInfo::builder()
+                    .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                    .build(),
+            )
+            .depth_stencil_state(
+                &vk::PipelineDepthStencilStateCreateInfo::builder()
+                    .depth_test_enable(parameters.depth_format.is_some())
+                    .depth_write_enable(parameters.depth_format.is_some())
+                    .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+                    .build(),
+            )
+            .color_blend_state(
+                &vk::PipelineColorBlendStateCreateInfo::builder()
+                    .attachments(&color_blend_attachments)
+                    .build(),
+            )
+            .dynamic_state(
+                &vk::PipelineDynamicStateCreateInfo::builder()
+                    .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                    .build(),
+            )
+            .layout(pipeline_layout)
+            .build();
+        create_info.p_next = &pipeline_rendering_info as *const PipelineRenderingCreateInfoKHR as _;
+        let pipeline = factory.create_graphics_pipelines(vk::PipelineCache::null(), &[create_info])[0];
+
+        factory.destroy_shader_module(vertex_shader);
+        factory.destroy_shader_module(fragment_shader);
+
+        self.pipelines.push(Some(VulkanRhiPipeline {
+            pipeline,
+            pipeline_layout,
+            descriptor_set_layout,
+            bind_point: vk::PipelineBindPoint::GRAPHICS,
+            storage_buffer_count: parameters.storage_buffer_count,
+        }));
+        RhiPipeline::new(self.pipelines.len() - 1)
+    }
+
+    fn create_compute_pipeline(
+        &mut self,
+        parameters: &RhiComputePipelineParameters,
+        factory: &mut DeviceFactory,
+    ) -> RhiPipeline {
+        let (descriptor_set_layout, pipeline_layout) = self.create_pipeline_layout(
+            parameters.storage_buffer_count,
+            parameters.storage_image_count,
+            parameters.push_constant_size,
+            factory,
+        );
+        let compute_shader = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(parameters.compute_shader)
+                .build(),
+        );
+
+        let shader_entry_name = std::ffi::CString::new("main").unwrap();
+        let pipeline = factory.create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::ComputePipelineCreateInfo::builder()
+                .stage(
+                    vk::PipelineShaderStageCreateInfo::builder()
+                        .stage(vk::ShaderStageFlags::COMPUTE)
+                        .module(compute_shader)
+                        .name(&shader_entry_name)
+                        .build(),
+                )
+                .layout(pipeline_layout)
+                .build()],
+        )[0];
+        factory.destroy_shader_module(compute_shader);
+
+        self.pipelines.push(Some(VulkanRhiPipeline {
+            pipeline,
+            pipeline_layout,
+            descriptor_set_layout,
+            bind_point: vk::PipelineBindPoint::COMPUTE,
+            storage_buffer_count: parameters.storage_buffer_count,
+        }));
+        RhiPipeline::new(self.pipelines.len() - 1)
+    }
+
+    fn destroy_pipeline(&mut self, pipeline: RhiPipeline, factory: &mut DeviceFactory) {
+        let pipeline = self.pipelines[pipeline.index()]
+            .take()
+            .expect("pipeline was destroyed twice");
+        factory.destroy_pipeline(pipeline.pipeline);
+        factory.destroy_pipeline_layout(pipeline.pipeline_layout);
+        factory.destroy_descriptor_set_layout(pipeline.descriptor_set_layout);
+    }
+
+    fn begin_pass(&mut self, parameters: &RhiPassParameters, command_list: &mut CommandBuffer) {
+        assert!(!self.active_pass, "passes can't be nested");
+        let mut image_barriers = Vec::with_capacity(parameters.color_targets.len() + 1);
+        for image in parameters.color_targets {
+            image_barriers.extend(self.make_layout_transition(*image, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL));
+        }
+        if let Some(image) = parameters.depth_target {
+            image_barriers
+                .extend(self.make_layout_transition(image, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL));
+        }
+        if !image_barriers.is_empty() {
+            command_list.pipeline_barrier(
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                None,
+                &[],
+                &[],
+                &image_barriers,
+            );
+        }
+
+        let color_attachments: Vec<RenderingAttachmentInfoKHR> = parameters
+            .color_targets
+            .iter()
+            .map(|image| RenderingAttachmentInfoKHR {
+                image_view: self.get_image_view(*image),
+                image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                load_op: match parameters.clear_color {
+                    Some(_) => vk::AttachmentLoadOp::CLEAR,
+                    None => vk::AttachmentLoadOp::LOAD,
+                },
+                store_op: vk::AttachmentStoreOp::STORE,
+                clear_value: vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: parameters.clear_color.unwrap_or_default(),
+                    },
+                },
+                ..Default::default()
+            })
+            .collect();
+        let depth_attachment = parameters.depth_target.map(|image| RenderingAttachmentInfoKHR {
+            image_view: self.get_image_view(image),
+            image_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            load_op: match parameters.clear_depth {
+                Some(_) => vk::AttachmentLoadOp::CLEAR,
+                None => vk::AttachmentLoadOp::LOAD,
+            },
+            store_op: vk::AttachmentStoreOp::STORE,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: parameters.clear_depth.unwrap_or_default(),
+                    stencil: 0,
+                },
+            },
+            ..Default::default()
+        });
+
+        let target = parameters
+            .color_targets
+            .first()
+            .or(parameters.depth_target.as_ref())
+            .expect("passes need at least one target");
+        let target = &self.get_image_resource(*target).parameters;
+        let extent = vk::Extent2D {
+            width: target.width,
+            height: target.height,
+        };
+        command_list.begin_rendering(&RenderingInfoKHR {
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            },
+            color_attachment_count: color_attachments.len() as _,
+            p_color_attachments: color_attachments.as_ptr(),
+            p_depth_attachment: match &depth_attachment {
+                Some(attachment) => attachment,
+                None => std::ptr::null(),
+            },
+            ..Default::default()
+        });
+        command_list.set_viewport(
+            0,
+            &[vk::Viewport {
+                x: 0.0,
+                y: 0.0,
+                width: extent.width as f32,
+                height: extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+        );
+        command_list.set_scissor(
+            0,
+            &[vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            }],
+        );
+        self.active_pass = true;
+    }
+
+    fn end_pass(&mut self, command_list: &mut CommandBuffer) {
+        assert!(self.active_pass, "no pass is active");
+        command_list.end_rendering();
+        self.active_pass = false;
+    }
+
+    fn barrier(&mut self, command_list: &mut CommandBuffer) {
+        assert!(!self.active_pass, "barriers can't be inside passes");
+        command_list.pipeline_barrier(
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+                .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+                .build()],
+            &[],
+            &[],
+        );
+    }
+
+    fn bind_pipeline(&mut self, pipeline: RhiPipeline, command_list: &mut CommandBuffer) {
+        let pipeline = self.get_pipeline_resource(pipeline);
+        command_list.bind_pipeline(pipeline.bind_point, pipeline.pipeline);
+    }
+
+    fn bind_resources(
+        &mut self,
+        pipeline: RhiPipeline,
+        storage_buffers: &[RhiBuffer],
+        storage_images: &[RhiImage],
+        command_list: &mut CommandBuffer,
+    ) {
+        // Storage images stay in the general layout, so transitions only happen on first use
+        let image_barriers: Vec<vk::ImageMemoryBarrier> = storage_images
+            .iter()
+            .filter_map(|image| self.make_layout_transition(*image, vk::ImageLayout::GENERAL))
+            .collect();
+        if !image_barriers.is_empty() {
+            assert!(
+                !self.active_pass,
+                "storage images have to be used outside of passes first"
+            );
+            command_list.pipeline_barrier(
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                None,
+                &[],
+                &[],
+                &image_barriers,
+            );
+        }
+
+        let buffer_infos: Vec<vk::DescriptorBufferInfo> = storage_buffers
+            .iter()
+            .map(|buffer| {
+                vk::DescriptorBufferInfo::builder()
+                    .buffer(self.get_buffer(*buffer))
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build()
+            })
+            .collect();
+        let image_infos: Vec<vk::DescriptorImageInfo> = storage_images
+            .iter()
+            .map(|image| {
+                vk::DescriptorImageInfo::builder()
+                    .image_view(self.get_image_view(*image))
+                    .image_layout(vk::ImageLayout::GENERAL)
+                    .build()
+            })
+            .collect();
+
+        let pipeline = self.get_pipeline_resource(pipeline);
+        let mut descriptor_writes = Vec::with_capacity(buffer_infos.len() + image_infos.len());
+        for (binding, buffer_info) in buffer_infos.iter().enumerate() {
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_binding(binding as _)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(std::slice::from_ref(buffer_info))
+                    .build(),
+            );
+        }
+        for (binding, image_info) in image_infos.iter().enumerate() {
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_binding(pipeline.storage_buffer_count + binding as u32)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(std::slice::from_ref(image_info))
+                    .build(),
+            );
+        }
+        command_list.push_descriptor_set(pipeline.bind_point, pipeline.pipeline_layout, 0, &descriptor_writes);
+    }
+
+    fn push_constants(&mut self, pipeline: RhiPipeline, data: &[u8], command_list: &mut CommandBuffer) {
+        let pipeline = self.get_pipeline_resource(pipeline);
+        command_list.push_constants(pipeline.pipeline_layout, vk::ShaderStageFlags::ALL, 0, data);
+    }
+
+    fn draw(&mut self, vertex_count: u32, instance_count: u32, command_list: &mut CommandBuffer) {
+        command_list.draw(vertex_count, instance_count, 0, 0);
+    }
+
+    // Indices are 32 bit, indirect commands are tightly packed vk::DrawIndexedIndirectCommand
+    fn draw_indexed_indirect(
+        &mut self,
+        index_buffer: RhiBuffer,
+        indirect_buffer: RhiBuffer,
+        offset: u64,
+        draw_count: u32,
+        command_list: &mut CommandBuffer,
+    ) {
+        command_list.bind_index_buffer(self.get_buffer(index_buffer), 0, vk::IndexType::UINT32);
+        command_list.draw_indexed_indirect(
+            self.get_buffer(indirect_buffer),
+            offset,
+            draw_count,
+            std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as _,
+        );
+    }
+
+    fn dispatch(&mut self, group_count: [u32; 3], command_list: &mut CommandBuffer) {
+        command_list.dispatch(group_count[0], group_count[1], group_count[2]);
+    }
+
+    fn dispatch_indirect(&mut self, buffer: RhiBuffer, offset: u64, command_list: &mut CommandBuffer) {
+        command_list.dispatch_indirect(self.get_buffer(buffer), offset);
+    }
+}
+
+fn get_format(format: RhiFormat) -> vk::Format {
+    match format {
+        RhiFormat::Rgba8Unorm => vk::Format::R8G8B8A8_UNORM,
+        RhiFormat::Rgba16Float => vk::Format::R16G16B16A16_SFLOAT,
+        RhiFormat::Rgba32Float => vk::Format::R32G32B32A32_SFLOAT,
+        RhiFormat::R11G11B10Float => vk::Format::B10G11R11_UFLOAT_PACK32,
+        RhiFormat::Depth32Float => vk::Format::D32_SFLOAT,
+    }
+}
+
+fn get_subresource_range(format: RhiFormat) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::builder()
+        .aspect_mask(if format.is_depth() {
+            vk::ImageAspectFlags::DEPTH
+        } else {
+            vk::ImageAspectFlags::COLOR
+        })
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build()
+}
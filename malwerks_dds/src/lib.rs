@@ -172,6 +172,11 @@ impl ScratchImage {
     }
 
     pub fn save_to_file(&self, path: &std::path::Path) {
+        self.try_save_to_file(path)
+            .unwrap_or_else(|error| panic!("failed to write {:?}: {:?}", path, error));
+    }
+
+    pub fn try_save_to_file(&self, path: &std::path::Path) -> std::io::Result<()> {
         let header = bytemuck::bytes_of(&self.dds_header);
 
         use std::io::Write;
@@ -179,11 +184,10 @@ impl ScratchImage {
             .create(true)
             .write(true)
             .truncate(true)
-            .open(path)
-            .expect("failed to open dds output file");
+            .open(path)?;
 
-        file.write_all(&header[..]).expect("failed to write dds header");
-        file.write_all(&self.dds_data[..]).expect("failed to write dds data");
+        file.write_all(&header[..])?;
+        file.write_all(&self.dds_data[..])
     }
 
     pub fn image_size(&self) -> (u32, u32, u32) {
@@ -198,6 +202,10 @@ impl ScratchImage {
         self.dds_header.dxt10.array_size
     }
 
+    pub fn dxgi_format(&self) -> u32 {
+        self.dds_header.dxt10.dxgi_format
+    }
+
     pub fn block_size(&self) -> u32 {
         block_size(self.dds_header.dxt10.dxgi_format)
     }
@@ -0,0 +1,42 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use ultraviolet as utv;
+
+// Instance culling only supports uniform scale
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: utv::Vec3,
+    pub rotation: utv::Rotor3,
+    pub scale: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: utv::Vec3::zero(),
+            rotation: utv::Rotor3::identity(),
+            scale: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    // Column major, same layout as bundle instance transforms
+    pub fn get_matrix(&self) -> [f32; 16] {
+        let matrix = utv::Similarity3::new(self.translation, self.rotation, self.scale).into_homogeneous_matrix();
+        let mut result = [0.0f32; 16];
+        result.copy_from_slice(matrix.as_slice());
+        result
+    }
+}
+
+// Instance slot in a render bundle that the entity drives, instance index is relative to the bucket
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MeshInstance {
+    pub bundle_name: String,
+    pub bucket: usize,
+    pub instance_index: u32,
+}
@@ -0,0 +1,129 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_render::*;
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::components::*;
+
+// Instances of removed entities are scaled down to a point, so they stop being visible
+pub(crate) const REMOVED_INSTANCE_TRANSFORM: [f32; 16] = [
+    0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+];
+
+// Receives instance transforms queued by RenderInstanceSync
+pub trait InstanceTransformSink {
+    fn update_instance_transform(
+        &mut self,
+        bundle_name: &str,
+        bucket: usize,
+        instance_index: u32,
+        transform: &[f32; 16],
+    );
+}
+
+impl InstanceTransformSink for PbrForwardLit {
+    fn update_instance_transform(
+        &mut self,
+        bundle_name: &str,
+        bucket: usize,
+        instance_index: u32,
+        transform: &[f32; 16],
+    ) {
+        PbrForwardLit::update_instance_transform(self, bundle_name, bucket, instance_index, transform);
+    }
+}
+
+// Mirrors entity components into render bundle instance transforms. Any ECS can drive it by passing
+// the result of a (entity, &MeshInstance, &Transform) query, entity is the ECS entity handle.
+pub struct RenderInstanceSync<E> {
+    submitted_instances: HashMap<E, (MeshInstance, Transform)>,
+}
+
+impl<E> Default for RenderInstanceSync<E>
+where
+    E: Copy + Eq + Hash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E> RenderInstanceSync<E>
+where
+    E: Copy + Eq + Hash,
+{
+    pub fn new() -> Self {
+        Self {
+            submitted_instances: HashMap::new(),
+        }
+    }
+
+    // Has to be called once per frame with all entities, only new and changed entities are uploaded.
+    // Entities that are missing from the query are treated as removed. Returns the number of queued updates.
+    pub fn update<'a, I, S>(&mut self, entities: I, sink: &mut S) -> usize
+    where
+        I: IntoIterator<Item = (E, &'a MeshInstance, &'a Transform)>,
+        S: InstanceTransformSink,
+    {
+        let mut update_count = 0;
+        let mut removed_instances = Vec::new();
+        let mut submitted_instances = HashMap::with_capacity(self.submitted_instances.len());
+        let mut submitted_mesh_instances = HashSet::with_capacity(self.submitted_instances.len());
+        for (entity, mesh_instance, transform) in entities {
+            let submitted_instance = match self.submitted_instances.remove(&entity) {
+                Some((submitted_mesh_instance, submitted_transform)) if submitted_mesh_instance == *mesh_instance => {
+                    if submitted_transform != *transform {
+                        queue_update(sink, mesh_instance, &transform.get_matrix());
+                        update_count += 1;
+                    }
+                    (submitted_mesh_instance, *transform)
+                }
+                previous_instance => {
+                    // Entity moved to another instance slot, the old one may be taken by another entity
+                    if let Some((submitted_mesh_instance, _)) = previous_instance {
+                        removed_instances.push(submitted_mesh_instance);
+                    }
+                    queue_update(sink, mesh_instance, &transform.get_matrix());
+                    update_count += 1;
+                    (mesh_instance.clone(), *transform)
+                }
+            };
+            submitted_mesh_instances.insert(submitted_instance.0.clone());
+            submitted_instances.insert(entity, submitted_instance);
+        }
+
+        removed_instances.extend(
+            self.submitted_instances
+                .drain()
+                .map(|(_, (mesh_instance, _))| mesh_instance),
+        );
+        for mesh_instance in removed_instances {
+            // Slots that are driven by another entity now are left alone, each free slot is cleared once
+            if submitted_mesh_instances.insert(mesh_instance.clone()) {
+                queue_update(sink, &mesh_instance, &REMOVED_INSTANCE_TRANSFORM);
+                update_count += 1;
+            }
+        }
+        self.submitted_instances = submitted_instances;
+        update_count
+    }
+
+    // Forgets all entities without touching their instances, has to be called when render bundles are reloaded
+    pub fn clear(&mut self) {
+        self.submitted_instances.clear();
+    }
+}
+
+fn queue_update<S: InstanceTransformSink>(sink: &mut S, mesh_instance: &MeshInstance, transform: &[f32; 16]) {
+    sink.update_instance_transform(
+        &mesh_instance.bundle_name,
+        mesh_instance.bucket,
+        mesh_instance.instance_index,
+        transform,
+    );
+}
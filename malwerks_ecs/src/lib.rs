@@ -0,0 +1,13 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod components;
+mod instance_sync;
+
+pub use components::*;
+pub use instance_sync::*;
+
+#[cfg(test)]
+mod test_instance_sync;
@@ -0,0 +1,147 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::components::*;
+use crate::instance_sync::*;
+
+use ultraviolet as utv;
+
+#[derive(Default)]
+struct RecordingSink {
+    updates: Vec<(MeshInstance, [f32; 16])>,
+}
+
+impl InstanceTransformSink for RecordingSink {
+    fn update_instance_transform(
+        &mut self,
+        bundle_name: &str,
+        bucket: usize,
+        instance_index: u32,
+        transform: &[f32; 16],
+    ) {
+        self.updates
+            .push((create_mesh_instance(bundle_name, bucket, instance_index), *transform));
+    }
+}
+
+fn create_mesh_instance(bundle_name: &str, bucket: usize, instance_index: u32) -> MeshInstance {
+    MeshInstance {
+        bundle_name: String::from(bundle_name),
+        bucket,
+        instance_index,
+    }
+}
+
+fn create_transform(x: f32) -> Transform {
+    Transform {
+        translation: utv::Vec3::new(x, 0.0, 0.0),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_instance_sync_changes() {
+    let mut instance_sync = RenderInstanceSync::new();
+    let mut sink = RecordingSink::default();
+
+    let instance_a = create_mesh_instance("test", 0, 0);
+    let instance_b = create_mesh_instance("test", 1, 3);
+    let transform_a = create_transform(1.0);
+    let transform_b = create_transform(2.0);
+
+    let update_count = instance_sync.update(
+        vec![(0, &instance_a, &transform_a), (1, &instance_b, &transform_b)],
+        &mut sink,
+    );
+    assert_eq!(update_count, 2);
+    assert_eq!(
+        sink.updates,
+        vec![
+            (instance_a.clone(), transform_a.get_matrix()),
+            (instance_b.clone(), transform_b.get_matrix())
+        ]
+    );
+
+    // Unchanged entities are not uploaded again
+    sink.updates.clear();
+    let update_count = instance_sync.update(
+        vec![(0, &instance_a, &transform_a), (1, &instance_b, &transform_b)],
+        &mut sink,
+    );
+    assert_eq!(update_count, 0);
+    assert!(sink.updates.is_empty());
+
+    let moved_transform_b = create_transform(3.0);
+    let update_count = instance_sync.update(
+        vec![(0, &instance_a, &transform_a), (1, &instance_b, &moved_transform_b)],
+        &mut sink,
+    );
+    assert_eq!(update_count, 1);
+    assert_eq!(sink.updates, vec![(instance_b.clone(), moved_transform_b.get_matrix())]);
+
+    // Missing entities are removed
+    sink.updates.clear();
+    let update_count = instance_sync.update(vec![(1, &instance_b, &moved_transform_b)], &mut sink);
+    assert_eq!(update_count, 1);
+    assert_eq!(sink.updates, vec![(instance_a, REMOVED_INSTANCE_TRANSFORM)]);
+
+    // Cleared state uploads everything again
+    sink.updates.clear();
+    instance_sync.clear();
+    let update_count = instance_sync.update(vec![(1, &instance_b, &moved_transform_b)], &mut sink);
+    assert_eq!(update_count, 1);
+    assert_eq!(sink.updates, vec![(instance_b, moved_transform_b.get_matrix())]);
+}
+
+#[test]
+fn test_instance_sync_slot_changes() {
+    let mut instance_sync = RenderInstanceSync::new();
+    let mut sink = RecordingSink::default();
+
+    let instance_a = create_mesh_instance("test", 0, 0);
+    let instance_b = create_mesh_instance("test", 0, 1);
+    let instance_c = create_mesh_instance("test", 0, 2);
+    let transform_a = create_transform(1.0);
+    let transform_b = create_transform(2.0);
+
+    instance_sync.update(
+        vec![(0, &instance_a, &transform_a), (1, &instance_b, &transform_b)],
+        &mut sink,
+    );
+
+    // Swapped slots are both written with new transforms, neither is removed
+    sink.updates.clear();
+    let update_count = instance_sync.update(
+        vec![(0, &instance_b, &transform_a), (1, &instance_a, &transform_b)],
+        &mut sink,
+    );
+    assert_eq!(update_count, 2);
+    assert!(sink
+        .updates
+        .iter()
+        .all(|(_, transform)| *transform != REMOVED_INSTANCE_TRANSFORM));
+
+    // Slot that is no longer driven by any entity is removed once
+    sink.updates.clear();
+    let update_count = instance_sync.update(
+        vec![(0, &instance_c, &transform_a), (1, &instance_a, &transform_b)],
+        &mut sink,
+    );
+    assert_eq!(update_count, 2);
+    assert_eq!(
+        sink.updates,
+        vec![
+            (instance_c, transform_a.get_matrix()),
+            (instance_b, REMOVED_INSTANCE_TRANSFORM)
+        ]
+    );
+}
+
+#[test]
+fn test_instance_sync_removed_transform() {
+    // Zero scale keeps the matrix homogeneous, so removed instances still end up at a valid point
+    let removed_point = utv::Mat4::from(REMOVED_INSTANCE_TRANSFORM) * utv::Vec4::new(5.0, 6.0, 7.0, 1.0);
+    assert_eq!(removed_point, utv::Vec4::new(0.0, 0.0, 0.0, 1.0));
+}
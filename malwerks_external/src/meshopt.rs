@@ -71,12 +71,14 @@ pub fn optimize_mesh(
     let final_vertex_buffer = DiskBuffer {
         stride: raw_vertex_stride as _,
         usage_flags: vk::BufferUsageFlags::VERTEX_BUFFER.as_raw(),
+        encoding: DiskBufferEncoding::MeshoptVertex,
         data: vertex_buffer,
     };
 
     let mut final_index_buffer = DiskBuffer {
         stride: raw_index_stride as _,
         usage_flags: vk::BufferUsageFlags::INDEX_BUFFER.as_raw(),
+        encoding: DiskBufferEncoding::MeshoptIndex,
         data: Vec::new(),
     };
     match raw_index_stride {
@@ -89,6 +91,89 @@ pub fn optimize_mesh(
     (final_vertex_buffer, final_index_buffer)
 }
 
+// Simplified triangle list over the same vertices with 32 bit indices, used as a proxy by the occluder pass.
+// Returns None if the mesh can't be reduced, occluders are drawn with the full mesh in that case.
+pub fn build_occluder_mesh(
+    vertex_buffer: &DiskBuffer,
+    index_buffer: &DiskBuffer,
+    target_ratio: f32,
+    target_error: f32,
+) -> Option<DiskBuffer> {
+    let u32_index_data = match index_buffer.stride {
+        1 => make_wide_index_buffer::<u8>(&index_buffer.data),
+        2 => make_wide_index_buffer::<u16>(&index_buffer.data),
+        4 => make_wide_index_buffer::<u32>(&index_buffer.data),
+        _ => panic!("unsupported index stride"),
+    };
+
+    let positions = get_vertex_positions(vertex_buffer);
+    let occluder_index_data = simplify_mesh(&positions, &u32_index_data, target_ratio, target_error);
+    if occluder_index_data.is_empty() || occluder_index_data.len() >= u32_index_data.len() {
+        return None;
+    }
+
+    let mut occluder_buffer = DiskBuffer {
+        stride: std::mem::size_of::<u32>() as _,
+        usage_flags: vk::BufferUsageFlags::INDEX_BUFFER.as_raw(),
+        encoding: DiskBufferEncoding::MeshoptIndex,
+        data: Vec::new(),
+    };
+    copy_to_buffer::<u32>(&occluder_index_data, &mut occluder_buffer);
+    Some(occluder_buffer)
+}
+
+// Positions are the first 3 floats of every vertex
+pub fn get_vertex_positions(vertex_buffer: &DiskBuffer) -> Vec<[f32; 3]> {
+    let get_value = |value: &[u8]| f32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+    vertex_buffer
+        .data
+        .chunks_exact(vertex_buffer.stride as usize)
+        .map(|vertex| {
+            [
+                get_value(&vertex[0..4]),
+                get_value(&vertex[4..8]),
+                get_value(&vertex[8..12]),
+            ]
+        })
+        .collect()
+}
+
+// Simplified triangle list over the same positions, simplification stops early if it would exceed target_error
+pub fn simplify_mesh(positions: &[[f32; 3]], indices: &[u32], target_ratio: f32, target_error: f32) -> Vec<u32> {
+    let target_index_count = ((indices.len() / 3) as f32 * target_ratio) as usize * 3;
+    let mut simplified_indices = vec![0u32; indices.len()];
+    let simplified_index_count = unsafe {
+        meshopt::ffi::meshopt_simplify(
+            simplified_indices.as_mut_ptr(),
+            indices.as_ptr(),
+            indices.len(),
+            positions.as_ptr() as *const f32,
+            positions.len(),
+            std::mem::size_of::<[f32; 3]>(),
+            target_index_count.max(3),
+            target_error,
+        )
+    };
+    simplified_indices.truncate(simplified_index_count);
+    simplified_indices
+}
+
+// Indices of a subset of positions that keeps the overall shape of the point cloud
+pub fn simplify_points(positions: &[[f32; 3]], target_count: usize) -> Vec<u32> {
+    let mut simplified_points = vec![0u32; positions.len()];
+    let simplified_point_count = unsafe {
+        meshopt::ffi::meshopt_simplifyPoints(
+            simplified_points.as_mut_ptr(),
+            positions.as_ptr() as *const f32,
+            positions.len(),
+            std::mem::size_of::<[f32; 3]>(),
+            target_count,
+        )
+    };
+    simplified_points.truncate(simplified_point_count);
+    simplified_points
+}
+
 pub struct MeshCluster {
     pub vertex_count: u16,
     pub index_count: u16,
@@ -99,10 +184,23 @@ pub struct BoundingCone {
     pub cone_axis: [f32; 4],
 }
 
+// Bounds for frustum and Hi-Z tests, in the same space as vertex positions
+pub struct BoundingVolume {
+    pub sphere: [f32; 4], // center, radius
+    pub aabb_min: [f32; 4],
+    pub aabb_max: [f32; 4],
+}
+
+// One entry per cluster in both arrays
+pub struct ClusterBounds {
+    pub cones: Vec<BoundingCone>,
+    pub volumes: Vec<BoundingVolume>,
+}
+
 pub fn build_mesh_clusters(
     vertex_buffer: &DiskBuffer,
     index_buffer: &DiskBuffer,
-) -> (DiskBuffer, (i32, DiskBuffer), Vec<MeshCluster>, Vec<BoundingCone>) {
+) -> (DiskBuffer, (i32, DiskBuffer), Vec<MeshCluster>, ClusterBounds) {
     let vertex_stride = vertex_buffer.stride as usize;
     let vertex_count = vertex_buffer.data.len() / vertex_stride;
     let u32_index_data = match index_buffer.stride {
@@ -115,6 +213,7 @@ pub fn build_mesh_clusters(
     let meshlets = meshopt::clusterize::build_meshlets(&u32_index_data, vertex_count, 64, 126);
     let mut mesh_clusters = Vec::with_capacity(meshlets.len());
     let mut mesh_bounds = Vec::with_capacity(meshlets.len());
+    let mut mesh_volumes = Vec::with_capacity(meshlets.len());
 
     let mut final_vertex_count = 0usize;
     let mut final_index_count = 0usize;
@@ -129,10 +228,18 @@ pub fn build_mesh_clusters(
 
     let mut final_vertex_offset = 0;
     for meshlet in &meshlets {
+        // Positions are the first 3 floats of every vertex, same as meshopt_computeMeshletBounds expects
+        let mut aabb_min = [f32::MAX, f32::MAX, f32::MAX, 0.0];
+        let mut aabb_max = [f32::MIN, f32::MIN, f32::MIN, 0.0];
         for local_vertex_index in 0..meshlet.vertex_count {
             let vertex_id = meshlet.vertices[local_vertex_index as usize] as usize;
             let source_vertex_offset = vertex_id * vertex_stride;
             let source_vertex_slice = &vertex_buffer.data[source_vertex_offset..source_vertex_offset + vertex_stride];
+            for (axis, value) in source_vertex_slice[..12].chunks_exact(4).enumerate() {
+                let value = f32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+                aabb_min[axis] = aabb_min[axis].min(value);
+                aabb_max[axis] = aabb_max[axis].max(value);
+            }
             let target_vertex_slice = &mut final_vertex_data[final_vertex_offset..final_vertex_offset + vertex_stride];
             target_vertex_slice.copy_from_slice(source_vertex_slice);
             final_vertex_offset += vertex_stride;
@@ -175,18 +282,26 @@ pub fn build_mesh_clusters(
                 bounds.cone_cutoff,
             ],
         });
+
+        mesh_volumes.push(BoundingVolume {
+            sphere: [bounds.center[0], bounds.center[1], bounds.center[2], bounds.radius],
+            aabb_min,
+            aabb_max,
+        });
     }
     assert_eq!(final_vertex_offset, final_vertex_data.len());
 
     let final_vertex_buffer = DiskBuffer {
         stride: vertex_stride as _,
         usage_flags: vk::BufferUsageFlags::VERTEX_BUFFER.as_raw(),
+        encoding: DiskBufferEncoding::MeshoptVertex,
         data: final_vertex_data,
     };
 
     let mut final_index_buffer = DiskBuffer {
         stride: std::mem::size_of::<u16>() as _,
         usage_flags: vk::BufferUsageFlags::INDEX_BUFFER.as_raw(),
+        encoding: DiskBufferEncoding::MeshoptIndex,
         data: Vec::new(),
     };
     convert_to_narrow_index_buffer::<u16>(&temp_index_data, &mut final_index_buffer);
@@ -195,7 +310,10 @@ pub fn build_mesh_clusters(
         final_vertex_buffer,
         (vk::IndexType::UINT16.as_raw(), final_index_buffer),
         mesh_clusters,
-        mesh_bounds,
+        ClusterBounds {
+            cones: mesh_bounds,
+            volumes: mesh_volumes,
+        },
     )
 }
 
@@ -20,14 +20,55 @@ pub enum ImageUsage {
     EnvironmentBrdf,
 }
 
+impl ImageUsage {
+    // Only color textures are authored gamma encoded, they get _SRGB formats and are decoded when sampled
+    pub fn get_color_space(self) -> DiskColorSpace {
+        match self {
+            ImageUsage::SrgbColor | ImageUsage::EnvironmentSkybox => DiskColorSpace::Srgb,
+            _ => DiskColorSpace::Linear,
+        }
+    }
+}
+
 pub fn compress_image(
     image_usage: ImageUsage,
     output_path: &std::path::Path,
     image_path: &std::path::Path,
+) -> DiskImage {
+    convert_image(image_usage, false, output_path, image_path)
+}
+
+// Stores only the top mip without block compression, the rest of the chain is generated on the GPU when loading
+pub fn convert_image_with_runtime_mipmaps(
+    image_usage: ImageUsage,
+    output_path: &std::path::Path,
+    image_path: &std::path::Path,
+) -> DiskImage {
+    assert!(
+        matches!(
+            image_usage,
+            ImageUsage::SrgbColor
+                | ImageUsage::MetallicRoughnessMap
+                | ImageUsage::NormalMap
+                | ImageUsage::AmbientOcclusionMap
+        ),
+        "runtime mipmaps are not supported for {:?}",
+        image_usage
+    );
+    convert_image(image_usage, true, output_path, image_path)
+}
+
+fn convert_image(
+    image_usage: ImageUsage,
+    runtime_mipmaps: bool,
+    output_path: &std::path::Path,
+    image_path: &std::path::Path,
 ) -> DiskImage {
     std::fs::create_dir_all(output_path).expect("failed to create output folder for texconv");
 
-    let dds_path = output_path.join(image_path.with_extension("dds").file_name().unwrap());
+    // Both variants can be cached side by side
+    let dds_extension = if runtime_mipmaps { "top_mip.dds" } else { "dds" };
+    let dds_path = output_path.join(image_path.with_extension(dds_extension).file_name().unwrap());
     assert_ne!(dds_path, image_path); // make sure we're not writing compressed output to the source texture
 
     log::info!("texconv {:?} {:?} -> {:?}", image_usage, image_path, dds_path);
@@ -48,7 +89,28 @@ pub fn compress_image(
     };
 
     let mut texconv_args = vec!["-nologo", "-dx10", "-y", "-o", output_path.to_str().unwrap()];
+    if runtime_mipmaps {
+        texconv_args.push("-sx");
+        texconv_args.push(".top_mip");
+    }
     let (image_format, expected_block_size, is_cube_map) = match image_usage {
+        // Blits can't write block compressed formats
+        ImageUsage::SrgbColor if runtime_mipmaps => {
+            texconv_args.push("-srgb");
+            texconv_args.push("-f");
+            texconv_args.push("R8G8B8A8_UNORM_SRGB");
+            texconv_args.push("-m");
+            texconv_args.push("1");
+            (vk::Format::R8G8B8A8_SRGB, 16, false)
+        }
+        _ if runtime_mipmaps => {
+            texconv_args.push("-f");
+            texconv_args.push("R8G8B8A8_UNORM");
+            texconv_args.push("-m");
+            texconv_args.push("1");
+            (vk::Format::R8G8B8A8_UNORM, 16, false)
+        }
+
         ImageUsage::SrgbColor => {
             texconv_args.push("-srgb");
             texconv_args.push("-f");
@@ -151,6 +213,8 @@ pub fn compress_image(
         image_type: image_type.as_raw(),
         view_type: view_type.as_raw(),
         format: image_format.as_raw(),
+        color_space: image_usage.get_color_space(),
+        generate_mipmaps: runtime_mipmaps,
         pixels: scratch_image.as_slice().to_vec(),
     }
 }
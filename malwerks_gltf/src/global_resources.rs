@@ -147,6 +147,23 @@ fn import_global_shaders(global_resources: &mut DiskGlobalResources, base_path:
     compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
     compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
     compile_options.set_warnings_as_errors();
+    let include_folder = base_path.join("..").join("..").join("malwerks_shaders");
+    compile_options.set_include_callback(
+        move |requested_source_path, _directive_type, _contained_within_path, _recursion_depth| {
+            let include_path = include_folder.join(requested_source_path);
+            match std::fs::read_to_string(&include_path) {
+                Ok(included_source) => Ok(shaderc::ResolvedInclude {
+                    resolved_name: include_path.to_string_lossy().into_owned(),
+                    content: included_source,
+                }),
+                Err(e) => Err(format!(
+                    "failed to open GLSL include file {}: {}",
+                    include_path.display(),
+                    e
+                )),
+            }
+        },
+    );
 
     let mut compute_stage_options = compile_options.clone().expect("failed to clone compute options");
     compute_stage_options.add_macro_definition("COMPUTE_STAGE", None);
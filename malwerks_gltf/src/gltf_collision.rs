@@ -0,0 +1,180 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+use malwerks_external::*;
+
+use ash::vk;
+use ultraviolet as utv;
+
+use crate::gltf_import_parameters::*;
+
+// Exports one collision shape per render mesh, meshes with flat convex hulls fall back to triangle meshes
+pub fn export_collision(
+    meshes: &[DiskRenderMesh],
+    buffers: &[DiskBuffer],
+    import_parameters: &GltfImportParameters,
+) -> Vec<DiskCollision> {
+    let shape = match import_parameters.collision_shape {
+        Some(shape) => shape,
+        None => return Vec::new(),
+    };
+
+    let mut collision = Vec::with_capacity(meshes.len());
+    for (mesh_id, mesh) in meshes.iter().enumerate() {
+        let (positions, indices) = get_mesh_triangles(mesh, buffers);
+        let (shape, indices) = match shape {
+            DiskCollisionShape::ConvexHull => {
+                let points = if positions.len() > import_parameters.collision_hull_points {
+                    simplify_points(&positions, import_parameters.collision_hull_points)
+                } else {
+                    (0..positions.len() as u32).collect()
+                };
+                match build_convex_hull(&positions, &points) {
+                    Some(hull_indices) => (DiskCollisionShape::ConvexHull, hull_indices),
+                    None => {
+                        log::warn!("mesh {} has a flat convex hull, exporting triangles instead", mesh_id);
+                        (DiskCollisionShape::TriangleMesh, indices)
+                    }
+                }
+            }
+            DiskCollisionShape::TriangleMesh if import_parameters.collision_ratio < 1.0 => (
+                DiskCollisionShape::TriangleMesh,
+                simplify_mesh(
+                    &positions,
+                    &indices,
+                    import_parameters.collision_ratio,
+                    import_parameters.collision_error,
+                ),
+            ),
+            DiskCollisionShape::TriangleMesh => (DiskCollisionShape::TriangleMesh, indices),
+        };
+
+        // Only referenced positions are kept
+        let mut position_remap = vec![None; positions.len()];
+        let mut collision_positions = Vec::new();
+        let collision_indices = indices
+            .iter()
+            .map(|index| {
+                *position_remap[*index as usize].get_or_insert_with(|| {
+                    collision_positions.push(positions[*index as usize]);
+                    collision_positions.len() as u32 - 1
+                })
+            })
+            .collect();
+
+        collision.push(DiskCollision {
+            mesh: MeshHandle::new(mesh_id),
+            shape,
+            positions: collision_positions,
+            indices: collision_indices,
+        });
+    }
+
+    log::info!("exported {} collision shapes", collision.len());
+    collision
+}
+
+fn get_mesh_triangles(mesh: &DiskRenderMesh, buffers: &[DiskBuffer]) -> (Vec<[f32; 3]>, Vec<u32>) {
+    let positions = get_vertex_positions(&buffers[mesh.vertex_buffer.index()]);
+    let index_data = &buffers[mesh.index_buffer.1.index()].data;
+    let indices = if mesh.index_buffer.0 == vk::IndexType::UINT32.as_raw() {
+        index_data[mesh.first_index * 4..][..mesh.index_count * 4]
+            .chunks_exact(4)
+            .map(|index| u32::from_le_bytes([index[0], index[1], index[2], index[3]]))
+            .collect::<Vec<_>>()
+    } else {
+        index_data[mesh.first_index * 2..][..mesh.index_count * 2]
+            .chunks_exact(2)
+            .map(|index| u16::from_le_bytes([index[0], index[1]]) as u32)
+            .collect::<Vec<_>>()
+    };
+    let indices = indices
+        .into_iter()
+        .map(|index| index + mesh.vertex_offset as u32)
+        .collect();
+    (positions, indices)
+}
+
+// Incremental convex hull of a subset of positions, triangles are counter clockwise when seen from outside.
+// Returns None if the points are coplanar.
+fn build_convex_hull(positions: &[[f32; 3]], points: &[u32]) -> Option<Vec<u32>> {
+    let get_point = |index: u32| utv::Vec3::from(positions[index as usize]);
+    let get_distance = |face: &[u32; 3], point: utv::Vec3| {
+        let a = get_point(face[0]);
+        let normal = (get_point(face[1]) - a).cross(get_point(face[2]) - a);
+        normal.dot(point - a) / normal.mag().max(f32::MIN_POSITIVE)
+    };
+    if points.len() < 4 {
+        return None;
+    }
+
+    let (bounds_min, bounds_max) = points.iter().fold(
+        (utv::Vec3::broadcast(f32::MAX), utv::Vec3::broadcast(f32::MIN)),
+        |(bounds_min, bounds_max), point| {
+            (
+                bounds_min.min_by_component(get_point(*point)),
+                bounds_max.max_by_component(get_point(*point)),
+            )
+        },
+    );
+    let epsilon = (bounds_max - bounds_min).mag() * 1e-5;
+
+    // Initial tetrahedron from extreme points
+    let p0 = *points
+        .iter()
+        .min_by(|a, b| get_point(**a).x.partial_cmp(&get_point(**b).x).unwrap())
+        .unwrap();
+    let farthest = |distance: &dyn Fn(utv::Vec3) -> f32| {
+        *points
+            .iter()
+            .max_by(|a, b| distance(get_point(**a)).partial_cmp(&distance(get_point(**b))).unwrap())
+            .unwrap()
+    };
+    let p1 = farthest(&|point| (point - get_point(p0)).mag());
+    let axis = (get_point(p1) - get_point(p0)).normalized();
+    let p2 = farthest(&|point| (point - get_point(p0)).cross(axis).mag());
+    let p3 = farthest(&|point| get_distance(&[p0, p1, p2], point).abs());
+    let base_extent = (get_point(p1) - get_point(p0)).mag();
+    if base_extent <= epsilon || get_distance(&[p0, p1, p2], get_point(p3)).abs() <= epsilon {
+        return None;
+    }
+
+    let mut faces = Vec::new();
+    for (mut face, opposite) in [
+        ([p0, p1, p2], p3),
+        ([p0, p1, p3], p2),
+        ([p0, p2, p3], p1),
+        ([p1, p2, p3], p0),
+    ] {
+        if get_distance(&face, get_point(opposite)) > 0.0 {
+            face.swap(1, 2);
+        }
+        faces.push(face);
+    }
+
+    // Faces that see the point are replaced with a fan from their boundary to the point
+    for &point in points {
+        let (visible_faces, hidden_faces): (Vec<[u32; 3]>, Vec<[u32; 3]>) = faces
+            .iter()
+            .partition(|face| get_distance(face, get_point(point)) > epsilon);
+        if visible_faces.is_empty() {
+            continue;
+        }
+
+        let visible_edges: Vec<(u32, u32)> = visible_faces
+            .iter()
+            .flat_map(|face| vec![(face[0], face[1]), (face[1], face[2]), (face[2], face[0])])
+            .collect();
+        faces = hidden_faces;
+        for &(a, b) in &visible_edges {
+            if !visible_edges.contains(&(b, a)) {
+                faces.push([a, b, point]);
+            }
+        }
+    }
+
+    Some(faces.iter().flat_map(|face| face.iter().copied()).collect())
+}
@@ -13,6 +13,7 @@ pub fn import_images(
     temp_path: &std::path::Path,
     materials: gltf::iter::Materials,
     images: gltf::iter::Images,
+    runtime_mipmaps: bool,
 ) -> Vec<DiskImage> {
     macro_rules! update_image_usage {
         ($image_usage: ident, $texture: expr, $usage: expr) => {
@@ -66,12 +67,19 @@ pub fn import_images(
         };
 
         log::info!("importing image: {:?} as {:?}", &image_path, image_usage);
-        out_images.push(compress_image(image_usage, temp_path, &image_path));
+        if runtime_mipmaps {
+            out_images.push(convert_image_with_runtime_mipmaps(image_usage, temp_path, &image_path));
+        } else {
+            out_images.push(compress_image(image_usage, temp_path, &image_path));
+        }
     }
 
     out_images
 }
 
+// Clamped to the device limit when samplers are created
+const DEFAULT_MAX_ANISOTROPY: f32 = 16.0;
+
 pub fn import_samplers(samplers: gltf::iter::Samplers) -> Vec<DiskSampler> {
     let mut out_samplers = Vec::with_capacity(samplers.len());
     if samplers.len() == 0 {
@@ -83,6 +91,7 @@ pub fn import_samplers(samplers: gltf::iter::Samplers) -> Vec<DiskSampler> {
             address_mode_u: vk::SamplerAddressMode::REPEAT.as_raw(),
             address_mode_v: vk::SamplerAddressMode::REPEAT.as_raw(),
             address_mode_w: vk::SamplerAddressMode::REPEAT.as_raw(),
+            max_anisotropy: DEFAULT_MAX_ANISOTROPY,
         });
     } else {
         for sampler in samplers {
@@ -129,6 +138,12 @@ pub fn import_samplers(samplers: gltf::iter::Samplers) -> Vec<DiskSampler> {
                 address_mode_u: convert_wrap_mode(sampler.wrap_s()).as_raw(),
                 address_mode_v: convert_wrap_mode(sampler.wrap_t()).as_raw(),
                 address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE.as_raw(),
+
+                // Only trilinear filtering gets anisotropy, other filters are usually chosen on purpose
+                max_anisotropy: match sampler.min_filter() {
+                    Some(gltf::texture::MinFilter::LinearMipmapLinear) | None => DEFAULT_MAX_ANISOTROPY,
+                    Some(_) => 1.0,
+                },
             };
             out_samplers.push(disk_sampler);
         }
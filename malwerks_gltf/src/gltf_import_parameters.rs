@@ -0,0 +1,80 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Selects which vertex attributes are imported, positions are always kept.
+// Vertex colors and custom attributes are imported with Interpolated semantic as VS_color<n> and VS_<name>.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct GltfImportParameters {
+    pub keep_normals: bool,
+    pub keep_tangents: bool,
+    pub uv_channel_count: u32,          // TEXCOORD_n is kept if n < uv_channel_count
+    pub color_channel_count: u32,       // COLOR_n is kept if n < color_channel_count
+    pub custom_attributes: Vec<String>, // application specific attribute names without the leading underscore
+    pub material_definition: Option<std::path::PathBuf>, // relative to the glTF file, gltf_pbr_material.json if not set
+    pub runtime_mipmaps: bool,          // only the top mip of material textures is stored, uncompressed
+    pub atlas_max_image_size: u32,      // smaller material textures are packed into atlases, 0 disables atlasing
+    pub atlas_size: u32,
+    pub pack_mesh_geometry: bool, // all meshes share one vertex and one index buffer
+    pub occluder_ratio: f32,      // fraction of triangles that generated occluder meshes aim for, 0 disables them
+    pub occluder_error: f32,      // simplification error relative to the mesh extent
+    pub collision_shape: Option<malwerks_bundles::DiskCollisionShape>, // exported per mesh, None disables collision
+    pub collision_ratio: f32,     // fraction of triangles kept in collision triangle meshes
+    pub collision_error: f32,     // simplification error relative to the mesh extent
+    pub collision_hull_points: usize, // convex hulls are built from at most this many points
+}
+
+impl Default for GltfImportParameters {
+    fn default() -> Self {
+        Self {
+            keep_normals: true,
+            keep_tangents: true,
+            uv_channel_count: 8,
+            color_channel_count: 0,
+            custom_attributes: Vec::new(),
+            material_definition: None,
+            runtime_mipmaps: false,
+            atlas_max_image_size: 0,
+            atlas_size: 2048,
+            pack_mesh_geometry: false,
+            occluder_ratio: 0.1,
+            occluder_error: 0.02,
+            collision_shape: None,
+            collision_ratio: 0.25,
+            collision_error: 0.01,
+            collision_hull_points: 64,
+        }
+    }
+}
+
+impl GltfImportParameters {
+    // Parameters are read from "<name>.import.json" next to the glTF file, defaults are used if it doesn't exist
+    pub fn from_gltf_file(input_file: &std::path::Path) -> Self {
+        let parameters_file = input_file.with_extension("import.json");
+        if !parameters_file.exists() {
+            return Self::default();
+        }
+
+        log::info!("loading import parameters: {:?}", &parameters_file);
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .open(&parameters_file)
+            .expect("failed to open import parameters file");
+        serde_json::from_reader(file).expect("failed to parse import parameters file")
+    }
+
+    pub fn keeps_attribute(&self, semantic: &gltf::mesh::Semantic) -> bool {
+        match semantic {
+            gltf::mesh::Semantic::Positions => true,
+            gltf::mesh::Semantic::Normals => self.keep_normals,
+            gltf::mesh::Semantic::Tangents => self.keep_tangents,
+            gltf::mesh::Semantic::TexCoords(channel) => *channel < self.uv_channel_count,
+            gltf::mesh::Semantic::Colors(channel) => *channel < self.color_channel_count,
+            gltf::mesh::Semantic::Extras(name) => self.custom_attributes.contains(name),
+            gltf::mesh::Semantic::Joints(_) => false,
+            gltf::mesh::Semantic::Weights(_) => false,
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+
+// Material extras, e.g. "extras": { "uv_animation": { "scroll": [0.5, 0.0], "flipbook_size": [4, 4] } }
+#[derive(serde::Deserialize)]
+struct MaterialExtras {
+    uv_animation: Option<UvAnimation>,
+}
+
+#[derive(serde::Deserialize)]
+struct UvAnimation {
+    #[serde(default)]
+    scroll: [f32; 2],
+    #[serde(default = "default_flipbook_size")]
+    flipbook_size: [u32; 2],
+    frame_count: Option<u32>, // all cells of the flipbook by default
+    #[serde(default)]
+    frame_rate: f32,
+}
+
+fn default_flipbook_size() -> [u32; 2] {
+    [1, 1]
+}
+
+fn get_uv_animation(material: &gltf::Material) -> Option<UvAnimation> {
+    let extras = material.extras().as_ref()?;
+    let extras: MaterialExtras = match serde_json::from_str(extras.get()) {
+        Ok(extras) => extras,
+        Err(error) => {
+            log::warn!("ignoring extras of material {:?}: {}", material.name(), error);
+            return None;
+        }
+    };
+    extras.uv_animation
+}
+
+pub fn has_material_animations(materials: gltf::iter::Materials) -> bool {
+    materials
+        .into_iter()
+        .any(|material| get_uv_animation(&material).is_some())
+}
+
+// Material instances map directly to glTF materials
+pub fn import_material_animations(materials: gltf::iter::Materials, parameter_id: usize) -> Vec<DiskMaterialAnimation> {
+    let mut material_animations = Vec::new();
+    for material in materials {
+        if let Some(uv_animation) = get_uv_animation(&material) {
+            let [columns, rows] = uv_animation.flipbook_size;
+            assert!(columns > 0 && rows > 0, "flipbook size has to be at least 1x1");
+            material_animations.push(DiskMaterialAnimation {
+                material_instance: MaterialInstanceHandle::new(material.index().unwrap_or(0)),
+                parameter_id,
+                uv_scroll: uv_animation.scroll,
+                flipbook_size: uv_animation.flipbook_size,
+                flipbook_frame_count: uv_animation.frame_count.unwrap_or(columns * rows),
+                flipbook_frame_rate: uv_animation.frame_rate,
+            });
+        }
+    }
+    material_animations
+}
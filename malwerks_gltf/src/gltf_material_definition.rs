@@ -0,0 +1,183 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Parameters are packed as vec4s into push constants after the view projection matrix
+pub const MAX_MATERIAL_PARAMETERS: usize = 4;
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaterialTextureSource {
+    BaseColor,
+    MetallicRoughness,
+    Normal,
+    Occlusion,
+    Emissive,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaterialValueSource {
+    BaseColorFactor, // 4 components
+    MetallicFactor,
+    RoughnessFactor,
+    AlphaCutoff,
+    EmissiveFactor, // 3 components
+    NormalScale,
+    OcclusionStrength,
+    Zero,
+    One,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct MaterialTextureSlot {
+    pub name: String, // sampler name in the shader
+    pub source: MaterialTextureSource,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct MaterialParameter {
+    pub name: String,                         // vec4 name in the shader
+    pub components: Vec<MaterialValueSource>, // concatenated and padded with zeroes
+}
+
+// Describes how glTF materials map to a material shader, the default one is gltf_pbr_material.json.
+// Texture slots become image bindings, parameters become the material instance push constants
+// and options are passed to the shader as macro definitions.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct MaterialDefinition {
+    pub texture_slots: Vec<MaterialTextureSlot>,
+    pub parameters: Vec<MaterialParameter>,
+    pub options: Vec<(String, String)>, // name, value
+}
+
+impl Default for MaterialDefinition {
+    fn default() -> Self {
+        serde_json::from_str(include_str!("../../malwerks_shaders/gltf_pbr_material.json"))
+            .expect("failed to parse default material definition")
+    }
+}
+
+impl MaterialDefinition {
+    pub fn from_file(definition_file: &std::path::Path) -> Self {
+        log::info!("loading material definition: {:?}", definition_file);
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .open(definition_file)
+            .expect("failed to open material definition file");
+        let definition: Self = serde_json::from_reader(file).expect("failed to parse material definition file");
+        assert!(definition.parameters.len() <= MAX_MATERIAL_PARAMETERS);
+        definition
+    }
+
+    // Only textures that are present in the material are bound, in texture slot order
+    pub fn get_textures<'a>(&self, material: &gltf::Material<'a>) -> Vec<(&str, gltf::Texture<'a>, u32)> {
+        let pbr_metallic_roughness = material.pbr_metallic_roughness();
+        self.texture_slots
+            .iter()
+            .filter_map(|slot| {
+                let texture = match slot.source {
+                    MaterialTextureSource::BaseColor => pbr_metallic_roughness
+                        .base_color_texture()
+                        .map(|info| (info.texture(), info.tex_coord())),
+                    MaterialTextureSource::MetallicRoughness => pbr_metallic_roughness
+                        .metallic_roughness_texture()
+                        .map(|info| (info.texture(), info.tex_coord())),
+                    MaterialTextureSource::Normal => {
+                        material.normal_texture().map(|info| (info.texture(), info.tex_coord()))
+                    }
+                    MaterialTextureSource::Occlusion => material
+                        .occlusion_texture()
+                        .map(|info| (info.texture(), info.tex_coord())),
+                    MaterialTextureSource::Emissive => material
+                        .emissive_texture()
+                        .map(|info| (info.texture(), info.tex_coord())),
+                };
+                texture.map(|(texture, tex_coord)| (slot.name.as_str(), texture, tex_coord))
+            })
+            .collect()
+    }
+
+    // Atlased material instances overwrite it with their UV scale and offset, returns the parameter index
+    pub fn add_texture_atlas_parameter(&mut self) -> usize {
+        assert!(
+            self.parameters.len() < MAX_MATERIAL_PARAMETERS,
+            "material definition has no room for the texture atlas parameter"
+        );
+        self.parameters.push(MaterialParameter {
+            name: String::from("atlas_scale_offset"),
+            components: vec![
+                MaterialValueSource::One,
+                MaterialValueSource::One,
+                MaterialValueSource::Zero,
+                MaterialValueSource::Zero,
+            ],
+        });
+        self.options.push((String::from("TEXTURE_ATLAS"), String::from("1")));
+        self.parameters.len() - 1
+    }
+
+    // Animated material instances overwrite it with their UV scale and offset every frame, returns the parameter index
+    pub fn add_uv_animation_parameter(&mut self) -> usize {
+        assert!(
+            self.parameters.len() < MAX_MATERIAL_PARAMETERS,
+            "material definition has no room for the UV animation parameter"
+        );
+        self.parameters.push(MaterialParameter {
+            name: String::from("uv_animation_scale_offset"),
+            components: vec![
+                MaterialValueSource::One,
+                MaterialValueSource::One,
+                MaterialValueSource::Zero,
+                MaterialValueSource::Zero,
+            ],
+        });
+        self.options.push((String::from("UV_ANIMATION"), String::from("1")));
+        self.parameters.len() - 1
+    }
+
+    pub fn get_parameter_names(&self) -> Vec<String> {
+        self.parameters.iter().map(|parameter| parameter.name.clone()).collect()
+    }
+
+    // Always returns MAX_MATERIAL_PARAMETERS vec4s, unused ones are zero
+    pub fn pack_parameters(&self, material: &gltf::Material) -> Vec<u8> {
+        let pbr_metallic_roughness = material.pbr_metallic_roughness();
+        let mut packed_data = [[0.0f32; 4]; MAX_MATERIAL_PARAMETERS];
+        for (parameter, packed_parameter) in self.parameters.iter().zip(packed_data.iter_mut()) {
+            let mut values = Vec::with_capacity(4);
+            for component in &parameter.components {
+                match component {
+                    MaterialValueSource::BaseColorFactor => {
+                        values.extend_from_slice(&pbr_metallic_roughness.base_color_factor())
+                    }
+                    MaterialValueSource::MetallicFactor => values.push(pbr_metallic_roughness.metallic_factor()),
+                    MaterialValueSource::RoughnessFactor => values.push(pbr_metallic_roughness.roughness_factor()),
+                    MaterialValueSource::AlphaCutoff => values.push(material.alpha_cutoff()),
+                    MaterialValueSource::EmissiveFactor => values.extend_from_slice(&material.emissive_factor()),
+                    MaterialValueSource::NormalScale => {
+                        values.push(material.normal_texture().map_or(1.0, |info| info.scale()))
+                    }
+                    MaterialValueSource::OcclusionStrength => {
+                        values.push(material.occlusion_texture().map_or(1.0, |info| info.strength()))
+                    }
+                    MaterialValueSource::Zero => values.push(0.0),
+                    MaterialValueSource::One => values.push(1.0),
+                }
+            }
+            assert!(
+                values.len() <= 4,
+                "material parameter {} has more than 4 components",
+                &parameter.name
+            );
+            packed_parameter[0..values.len()].copy_from_slice(&values);
+        }
+
+        packed_data
+            .iter()
+            .flat_map(|parameter| parameter.iter())
+            .flat_map(|value| value.to_le_bytes().to_vec())
+            .collect()
+    }
+}
@@ -7,6 +7,8 @@ use malwerks_bundles::*;
 
 use ash::vk;
 
+use crate::gltf_material_definition::*;
+
 pub struct Attribute<'a> {
     pub semantic: gltf::mesh::Semantic,
     pub semantic_name: String,
@@ -19,44 +21,37 @@ pub struct Attribute<'a> {
     pub stride: usize,
     pub offset: usize,
     pub data: &'a [u8],
+    pub data_stride: usize, // distance between elements in data, differs from stride for interleaved buffers
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn generate_material<'a>(
     material_id: usize,
     vertex_stride: usize,
     attributes: &[Attribute<'a>],
     materials: gltf::iter::Materials,
     material_layouts: &[DiskMaterialLayout],
+    material_definition: &MaterialDefinition,
     in_attribute_cache: &mut Vec<&'a [Attribute<'a>]>,
     in_materials: &mut Vec<DiskMaterial>,
 ) -> usize {
-    macro_rules! texture_prelude {
-        ($images: ident, $texture: expr, $texture_name: expr) => {
-            if let Some(image) = $texture {
-                $images.push((String::from($texture_name), format!("VS_uv{}", image.tex_coord())));
-            }
-        };
-    }
-
-    let mut images = Vec::with_capacity(5);
     let material = materials.clone().nth(material_id).expect("failed to find material id");
-    let pbr_metallic_roughness = material.pbr_metallic_roughness();
-
-    texture_prelude!(images, pbr_metallic_roughness.base_color_texture(), "BaseColorTexture");
-    texture_prelude!(
-        images,
-        pbr_metallic_roughness.metallic_roughness_texture(),
-        "MetallicRoughnessTexture"
-    );
-    texture_prelude!(images, material.normal_texture(), "NormalTexture");
-    texture_prelude!(images, material.occlusion_texture(), "OcclusionTexture");
-    texture_prelude!(images, material.emissive_texture(), "EmissiveTexture");
+    let images: Vec<(String, String)> = material_definition
+        .get_textures(&material)
+        .iter()
+        .map(|(name, _, tex_coord)| (name.to_string(), format!("VS_uv{}", tex_coord)))
+        .collect();
 
     let fragment_alpha_test = match material.alpha_mode() {
         gltf::json::material::AlphaMode::Opaque => false,
         gltf::json::material::AlphaMode::Mask => true,
         gltf::json::material::AlphaMode::Blend => false,
     };
+    let fragment_alpha_blend = match material.alpha_mode() {
+        gltf::json::material::AlphaMode::Opaque => false,
+        gltf::json::material::AlphaMode::Mask => false,
+        gltf::json::material::AlphaMode::Blend => true,
+    };
     let fragment_cull_flags = if material.double_sided() {
         vk::CullModeFlags::NONE.as_raw()
     } else {
@@ -85,10 +80,12 @@ pub fn generate_material<'a>(
     } else {
         let id = in_materials.len();
         in_materials.push(DiskMaterial {
-            material_layout: material_layouts
-                .iter()
-                .position(|item| item.image_count == images.len())
-                .expect("failed to find material layout"),
+            material_layout: MaterialLayoutHandle::new(
+                material_layouts
+                    .iter()
+                    .position(|item| item.image_count == images.len())
+                    .expect("failed to find material layout"),
+            ),
             vertex_stride: vertex_stride as _,
             vertex_format: attributes
                 .iter()
@@ -99,6 +96,8 @@ pub fn generate_material<'a>(
                         gltf::mesh::Semantic::Normals => DiskVertexSemantic::Normal,
                         gltf::mesh::Semantic::Tangents => DiskVertexSemantic::Tangent,
                         gltf::mesh::Semantic::TexCoords(_) => DiskVertexSemantic::Interpolated,
+                        gltf::mesh::Semantic::Colors(_) => DiskVertexSemantic::Interpolated,
+                        gltf::mesh::Semantic::Extras(_) => DiskVertexSemantic::Interpolated,
 
                         _ => unimplemented!("unsupported attribute semantic"),
                     },
@@ -109,10 +108,12 @@ pub fn generate_material<'a>(
                 .collect(),
 
             fragment_alpha_test,
+            fragment_alpha_blend,
             fragment_cull_flags,
 
             shader_image_mapping: images,
-            shader_macro_definitions: Vec::new(),
+            shader_macro_definitions: material_definition.options.clone(),
+            shader_parameters: material_definition.get_parameter_names(),
         });
 
         id
@@ -8,22 +8,38 @@ use malwerks_external::*;
 
 use ash::vk;
 
+use crate::gltf_import_parameters::*;
+use crate::gltf_material_definition::*;
 use crate::gltf_materials::*;
 use crate::gltf_shared::*;
 
+// Everything about the glTF file that mesh import depends on besides its meshes
+pub struct MeshImportContext<'a> {
+    pub base_path: &'a std::path::Path,
+    pub material_layouts: &'a [DiskMaterialLayout],
+    pub import_parameters: &'a GltfImportParameters,
+    pub material_definition: &'a MaterialDefinition,
+}
+
 pub fn import_meshes(
-    base_path: &std::path::Path,
+    context: &MeshImportContext,
     buffers: gltf::iter::Buffers,
     _views: gltf::iter::Views,
     meshes: gltf::iter::Meshes,
     materials: gltf::iter::Materials,
-    material_layouts: &[DiskMaterialLayout],
 ) -> (
     Vec<DiskBuffer>,
     Vec<DiskRenderMesh>,
     Vec<DiskMaterial>,
     Vec<PrimitiveRemap>,
 ) {
+    let &MeshImportContext {
+        base_path,
+        material_layouts,
+        import_parameters,
+        material_definition,
+    } = context;
+
     let mut out_buffers = Vec::with_capacity(meshes.len() * 2);
     let mut out_meshes = Vec::with_capacity(meshes.len());
     let mut out_materials = Vec::with_capacity(meshes.len()); // Allocate for the worst case
@@ -53,6 +69,7 @@ pub fn import_meshes(
     }
 
     let mut attribute_cache = Vec::with_capacity(meshes.len());
+    let mut primitive_cache = std::collections::HashMap::new();
     for mesh in meshes {
         log::info!(
             "loading and optimizing mesh {:?} with {:?} primitives",
@@ -67,7 +84,31 @@ pub fn import_meshes(
                 None => panic!("primitive material is not defined"),
             };
 
-            let mut sorted_attributes: Vec<gltf::mesh::Attribute> = primitive.attributes().collect();
+            // Primitives that share accessors and material are imported once, so their nodes end up instanced
+            let primitive_key = {
+                let mut attribute_accessors: Vec<usize> =
+                    primitive.attributes().map(|attribute| attribute.1.index()).collect();
+                attribute_accessors.sort_unstable();
+                (
+                    attribute_accessors,
+                    primitive.indices().map(|indices| indices.index()),
+                    material_id,
+                )
+            };
+            if let Some((real_mesh_id, real_material_id)) = primitive_cache.get(&primitive_key) {
+                log::info!(
+                    "mesh {:?} reuses primitive of mesh {}",
+                    mesh.name().unwrap_or_default(),
+                    real_mesh_id
+                );
+                per_primitive_remap.push((*real_mesh_id, *real_material_id, material_id));
+                continue;
+            }
+
+            let mut sorted_attributes: Vec<gltf::mesh::Attribute> = primitive
+                .attributes()
+                .filter(|attr| import_parameters.keeps_attribute(&attr.0))
+                .collect();
             let position_attribute = sorted_attributes
                 .iter()
                 .position(|attr| attr.0 == gltf::mesh::Semantic::Positions)
@@ -76,17 +117,19 @@ pub fn import_meshes(
                 sorted_attributes.swap(0, position_attribute);
             }
 
+            let mut next_attribute = 1;
             if let Some(normal_attribute) = sorted_attributes
                 .iter()
                 .position(|attr| attr.0 == gltf::mesh::Semantic::Normals)
             {
-                sorted_attributes.swap(1, normal_attribute);
+                sorted_attributes.swap(next_attribute, normal_attribute);
+                next_attribute += 1;
             }
             if let Some(tangent_attribute) = sorted_attributes
                 .iter()
                 .position(|attr| attr.0 == gltf::mesh::Semantic::Tangents)
             {
-                sorted_attributes.swap(2, tangent_attribute);
+                sorted_attributes.swap(next_attribute, tangent_attribute);
             }
 
             let mut vertex_format = Vec::with_capacity(primitive.attributes().len());
@@ -96,12 +139,13 @@ pub fn import_meshes(
             for attribute in sorted_attributes {
                 let accessor: gltf::accessor::Accessor = attribute.1;
                 let view = accessor.view().expect("no buffer view for attribute");
-                let offset = view.offset();
-                let length = view.length();
+                let offset = view.offset() + accessor.offset();
+                let length = view.length() - accessor.offset();
                 let location = attributes.len();
 
                 let data = &temp_buffers[view.buffer().index()][offset..offset + length];
                 let (stride, format, type_name) = convert_to_format(&accessor);
+                let data_stride = view.stride().unwrap_or(stride);
 
                 attributes.push(Attribute {
                     semantic: attribute.0.clone(),
@@ -110,6 +154,8 @@ pub fn import_meshes(
                         gltf::mesh::Semantic::Normals => String::from("normal"),
                         gltf::mesh::Semantic::Tangents => String::from("tangent"),
                         gltf::mesh::Semantic::TexCoords(idx) => format!("uv{}", idx),
+                        gltf::mesh::Semantic::Colors(idx) => format!("color{}", idx),
+                        gltf::mesh::Semantic::Extras(ref name) => name.clone(),
 
                         _ => unimplemented!("unsupported attribute semantic"),
                     },
@@ -122,6 +168,7 @@ pub fn import_meshes(
                     stride,
                     offset: attribute_offset,
                     data,
+                    data_stride,
                 });
 
                 attribute_offset += stride;
@@ -141,6 +188,7 @@ pub fn import_meshes(
                 &attributes,
                 materials.clone(),
                 material_layouts,
+                material_definition,
                 &mut attribute_cache,
                 &mut out_materials,
             );
@@ -151,7 +199,7 @@ pub fn import_meshes(
                 let mut vertex_offset = vertex_id * vertex_stride;
                 for attribute in &attributes {
                     assert_eq!(attribute.count, vertex_count);
-                    let attribute_offset = vertex_id * attribute.stride;
+                    let attribute_offset = vertex_id * attribute.data_stride;
 
                     let src_slice = &attribute.data[attribute_offset..attribute_offset + attribute.stride];
                     let dst_slice = &mut vertex_data[vertex_offset..vertex_offset + attribute.stride];
@@ -203,16 +251,42 @@ pub fn import_meshes(
                 index_count,
             );
 
+            let occluder_buffer = if import_parameters.occluder_ratio > 0.0 {
+                build_occluder_mesh(
+                    &vertex_buffer,
+                    &index_buffer,
+                    import_parameters.occluder_ratio,
+                    import_parameters.occluder_error,
+                )
+            } else {
+                None
+            };
+
             let vertex_buffer_id = out_buffers.len();
             out_buffers.push(vertex_buffer);
             out_buffers.push(index_buffer);
+            let occluder = occluder_buffer.map(|occluder_buffer| {
+                let occluder_index_count = occluder_buffer.data.len() / 4;
+                log::info!(
+                    "mesh {:?} occluder: indices: {} -> {}",
+                    mesh.name().unwrap_or_default(),
+                    index_count,
+                    occluder_index_count,
+                );
+                out_buffers.push(occluder_buffer);
+                (BufferHandle::new(vertex_buffer_id + 2), occluder_index_count)
+            });
 
             let disk_mesh = DiskRenderMesh {
-                vertex_buffer: vertex_buffer_id,
-                index_buffer: (index_format.as_raw(), vertex_buffer_id + 1),
+                vertex_buffer: BufferHandle::new(vertex_buffer_id),
+                index_buffer: (index_format.as_raw(), BufferHandle::new(vertex_buffer_id + 1)),
                 index_count,
+                first_index: 0,
+                vertex_offset: 0,
+                occluder,
             };
             per_primitive_remap.push((real_mesh_id, real_material_id, material_id));
+            primitive_cache.insert(primitive_key, (real_mesh_id, real_material_id));
             out_meshes.push(disk_mesh);
         }
         primitive_remap_table.push(PrimitiveRemap {
@@ -225,6 +299,20 @@ pub fn import_meshes(
 }
 
 fn convert_to_format(accessor: &gltf::accessor::Accessor) -> (usize, vk::Format, &'static str) {
+    // Vertex colors and quantized texture coordinates
+    if accessor.normalized() {
+        return match (accessor.dimensions(), accessor.data_type()) {
+            (gltf::accessor::Dimensions::Vec2, gltf::accessor::DataType::U8) => (2, vk::Format::R8G8_UNORM, "vec2"),
+            (gltf::accessor::Dimensions::Vec2, gltf::accessor::DataType::U16) => (4, vk::Format::R16G16_UNORM, "vec2"),
+            (gltf::accessor::Dimensions::Vec4, gltf::accessor::DataType::U8) => (4, vk::Format::R8G8B8A8_UNORM, "vec4"),
+            (gltf::accessor::Dimensions::Vec4, gltf::accessor::DataType::U16) => {
+                (8, vk::Format::R16G16B16A16_UNORM, "vec4")
+            }
+
+            _ => panic!("unsupported normalized vertex element type"),
+        };
+    }
+
     match accessor.dimensions() {
         gltf::accessor::Dimensions::Scalar => match accessor.data_type() {
             gltf::accessor::DataType::U8 => (1, vk::Format::R8_UINT, "uint8_t"),
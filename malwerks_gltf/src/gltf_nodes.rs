@@ -14,13 +14,35 @@ pub fn import_nodes(
     primitive_remap: Vec<PrimitiveRemap>,
     nodes: gltf::iter::Nodes,
     in_buffers: &mut Vec<DiskBuffer>,
-) -> Vec<DiskRenderBucket> {
+) -> (Vec<DiskRenderBucket>, Vec<DiskSceneNode>) {
     use std::collections::HashMap;
 
     struct InstanceData {
         transforms: Vec<[f32; 16]>,
+        node_ids: Vec<usize>,
     };
 
+    let mut scene_nodes: Vec<DiskSceneNode> = nodes
+        .clone()
+        .map(|node| {
+            let mut local_transform = [0.0; 16];
+            for (column_id, column) in node.transform().matrix().iter().enumerate() {
+                local_transform[column_id * 4..column_id * 4 + 4].copy_from_slice(column);
+            }
+            DiskSceneNode {
+                name: node.name().unwrap_or("").to_string(),
+                parent: None,
+                local_transform,
+                instances: Vec::new(),
+            }
+        })
+        .collect();
+    for node in nodes.clone() {
+        for child in node.children() {
+            scene_nodes[child.index()].parent = Some(node.index());
+        }
+    }
+
     let mut buckets = HashMap::<usize, HashMap<(usize, usize), InstanceData>>::new();
     for node in nodes {
         if let Some(mesh) = node.mesh() {
@@ -48,12 +70,14 @@ pub fn import_nodes(
                     Some(bucket) => match bucket.get_mut(&(*mesh_index, *material_instance_id)) {
                         Some(instance) => {
                             instance.transforms.push(instance_data);
+                            instance.node_ids.push(node.index());
                         }
                         None => {
                             bucket.insert(
                                 (*mesh_index, *material_instance_id),
                                 InstanceData {
                                     transforms: vec![instance_data],
+                                    node_ids: vec![node.index()],
                                 },
                             );
                         }
@@ -64,6 +88,7 @@ pub fn import_nodes(
                             (*mesh_index, *material_instance_id),
                             InstanceData {
                                 transforms: vec![instance_data],
+                                node_ids: vec![node.index()],
                             },
                         );
                         buckets.insert(*material_id, new_value);
@@ -73,9 +98,10 @@ pub fn import_nodes(
         }
     }
 
-    buckets
+    let buckets = buckets
         .into_iter()
-        .map(|(material, instances)| {
+        .enumerate()
+        .map(|(bucket_id, (material, instances))| {
             let mut total_instance_count = 0usize;
             let mut total_draw_count = 0usize;
             for ((_, _), instance) in &instances {
@@ -99,36 +125,69 @@ pub fn import_nodes(
             for instance in instances.values() {
                 for instance_id in 0..instance.transforms.len() {
                     dst_instance_transforms[current_transform] = instance.transforms[instance_id];
+                    scene_nodes[instance.node_ids[instance_id]]
+                        .instances
+                        .push((bucket_id, current_transform));
                     current_transform += 1;
                 }
             }
 
-            let instance_transform_buffer = in_buffers.len();
+            let instance_transform_buffer = BufferHandle::new(in_buffers.len());
             {
                 let stride = std::mem::size_of::<[f32; 16]>() as u64;
                 let usage_flags = vk::BufferUsageFlags::STORAGE_BUFFER.as_raw();
                 in_buffers.push(DiskBuffer {
                     stride,
                     usage_flags,
+                    encoding: DiskBufferEncoding::Raw,
                     data: instance_transform_data,
                 });
             }
 
             DiskRenderBucket {
-                material,
+                material: MaterialHandle::new(material),
                 instances: instances
                     .into_iter()
                     .map(|((mesh, material_instance), instance_data)| DiskRenderInstance {
-                        mesh,
-                        material_instance,
+                        mesh: MeshHandle::new(mesh),
+                        material_instance: MaterialInstanceHandle::new(material_instance),
 
                         total_instance_count: instance_data.transforms.len(),
                         total_draw_count: instance_data.transforms.len(),
+                        sort_key: 0,
                     })
                     .collect(),
 
                 instance_transform_buffer,
+                sort_key: 0,
             }
         })
-        .collect()
+        .collect::<Vec<_>>();
+
+    // Every DiskRenderInstance is a single instanced draw
+    let mut total_instance_count = 0;
+    let mut total_draw_count = 0;
+    for (bucket_id, bucket) in buckets.iter().enumerate() {
+        let bucket_instance_count: usize = bucket
+            .instances
+            .iter()
+            .map(|instance| instance.total_instance_count)
+            .sum();
+        log::info!(
+            "bucket {} (material {}): {} instances in {} draws",
+            bucket_id,
+            bucket.material.index(),
+            bucket_instance_count,
+            bucket.instances.len()
+        );
+        total_instance_count += bucket_instance_count;
+        total_draw_count += bucket.instances.len();
+    }
+    log::info!(
+        "{} mesh instances merged into {} instanced draws",
+        total_instance_count,
+        total_draw_count
+    );
+
+    (buckets, scene_nodes)
 }
@@ -0,0 +1,309 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+
+use ash::vk;
+
+// All textures of an atlased material instance share one rect, so every texture slot gets its own atlas page
+// and pages of the same set have identical layouts.
+struct AtlasEntry {
+    images: Vec<usize>,
+    size: u32,
+    position: (u32, u32),
+}
+
+struct AtlasPageSet {
+    entries: Vec<AtlasEntry>,
+    free_cells: Vec<(u32, u32, u32)>, // x, y, size
+}
+
+impl AtlasPageSet {
+    fn new(atlas_size: u32) -> Self {
+        Self {
+            entries: Vec::new(),
+            free_cells: vec![(0, 0, atlas_size)],
+        }
+    }
+
+    // Cells are split in quadrants until they match, so every entry stays aligned to its own size
+    fn allocate(&mut self, size: u32) -> Option<(u32, u32)> {
+        let cell_id = self
+            .free_cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.2 >= size)
+            .min_by_key(|(_, cell)| cell.2)
+            .map(|(cell_id, _)| cell_id)?;
+
+        let (x, y, mut cell_size) = self.free_cells.swap_remove(cell_id);
+        while cell_size > size {
+            cell_size /= 2;
+            self.free_cells.push((x + cell_size, y, cell_size));
+            self.free_cells.push((x, y + cell_size, cell_size));
+            self.free_cells.push((x + cell_size, y + cell_size, cell_size));
+        }
+        Some((x, y))
+    }
+
+    // Mips stop where the smallest entry would be less than a block wide
+    fn get_mipmap_count(&self) -> usize {
+        let min_size = self.entries.iter().map(|entry| entry.size).min().unwrap();
+        (min_size.trailing_zeros() - 1) as usize
+    }
+}
+
+// Packs square block compressed images up to max_image_size into atlas pages and makes material instances
+// that use them sample the pages with the UV scale and offset stored in their `atlas_parameter` vec4.
+// Only instances with repeating samplers are atlased, wrapping is done in the shader.
+pub fn pack_texture_atlases(
+    atlas_size: u32,
+    max_image_size: u32,
+    atlas_parameter: usize,
+    images: &mut Vec<DiskImage>,
+    samplers: &[DiskSampler],
+    material_instances: &mut [DiskMaterialInstance],
+) {
+    assert!(
+        atlas_size.is_power_of_two(),
+        "texture atlas size has to be a power of two"
+    );
+
+    // Instances with identical textures become a single atlas entry
+    let mut instance_images: Vec<Option<Vec<usize>>> = material_instances
+        .iter()
+        .map(|material_instance| {
+            let image_ids: Vec<usize> = material_instance.images.iter().map(|image| image.0.index()).collect();
+            let size = images[*image_ids.first()?].width;
+            let is_atlased = material_instance.images.iter().all(|(image, sampler)| {
+                let image = &images[image.index()];
+                let sampler = &samplers[sampler.index()];
+                is_atlas_candidate(image, max_image_size)
+                    && image.width == size
+                    && sampler.address_mode_u == vk::SamplerAddressMode::REPEAT.as_raw()
+                    && sampler.address_mode_v == vk::SamplerAddressMode::REPEAT.as_raw()
+            });
+            if is_atlased && size < atlas_size {
+                Some(image_ids)
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    // Images can't be moved to an atlas if they are also used in another combination or by a regular instance
+    let mut image_combinations = vec![Vec::<&Vec<usize>>::new(); images.len()];
+    let mut image_users = vec![true; images.len()];
+    for (material_instance, image_ids) in material_instances.iter().zip(&instance_images) {
+        match image_ids {
+            Some(image_ids) => {
+                for image_id in image_ids {
+                    if !image_combinations[*image_id].contains(&image_ids) {
+                        image_combinations[*image_id].push(image_ids);
+                    }
+                }
+            }
+            None => {
+                for image in &material_instance.images {
+                    image_users[image.0.index()] = false;
+                }
+            }
+        }
+    }
+    let is_exclusive: Vec<bool> = image_combinations
+        .iter()
+        .zip(&image_users)
+        .map(|(combinations, &only_atlased)| only_atlased && combinations.len() == 1)
+        .collect();
+    for image_ids in instance_images.iter_mut() {
+        if let Some(ids) = image_ids {
+            if !ids.iter().all(|&image_id| is_exclusive[image_id]) {
+                *image_ids = None;
+            }
+        }
+    }
+
+    let mut entries: Vec<Vec<usize>> = Vec::new();
+    for image_ids in instance_images.iter().flatten() {
+        if !entries.contains(image_ids) {
+            entries.push(image_ids.clone());
+        }
+    }
+    entries.sort_by_key(|image_ids| std::cmp::Reverse(images[image_ids[0]].width));
+
+    // Entries with the same formats per slot share page sets
+    let mut page_sets: Vec<(Vec<_>, Vec<AtlasPageSet>)> = Vec::new();
+    for image_ids in entries {
+        let signature: Vec<_> = image_ids
+            .iter()
+            .map(|&image_id| {
+                let image = &images[image_id];
+                (image.format, image.block_size, image.color_space)
+            })
+            .collect();
+        let size = images[image_ids[0]].width;
+        let sets = match page_sets
+            .iter()
+            .position(|(set_signature, _)| *set_signature == signature)
+        {
+            Some(set_id) => &mut page_sets[set_id].1,
+            None => {
+                page_sets.push((signature, Vec::new()));
+                &mut page_sets.last_mut().unwrap().1
+            }
+        };
+
+        match sets
+            .iter_mut()
+            .find_map(|set| set.allocate(size).map(|position| (set, position)))
+        {
+            Some((set, position)) => set.entries.push(AtlasEntry {
+                images: image_ids,
+                size,
+                position,
+            }),
+            None => {
+                let mut set = AtlasPageSet::new(atlas_size);
+                let position = set.allocate(size).expect("atlased image is larger than the atlas");
+                set.entries.push(AtlasEntry {
+                    images: image_ids,
+                    size,
+                    position,
+                });
+                sets.push(set);
+            }
+        }
+    }
+
+    // Page images are appended after the remaining images
+    let mut atlased_images = vec![None; images.len()]; // page image id, atlas scale and offset
+    let mut page_images = Vec::new();
+    let page_base_id = images.len();
+    for set in page_sets.iter().flat_map(|(_, sets)| sets.iter()) {
+        if set.entries.len() < 2 {
+            continue;
+        }
+
+        let mipmap_count = set.get_mipmap_count();
+        for slot_id in 0..set.entries[0].images.len() {
+            let page_image_id = page_base_id + page_images.len();
+            page_images.push(build_page_image(atlas_size, mipmap_count, slot_id, set, images));
+            for entry in &set.entries {
+                let scale = entry.size as f32 / atlas_size as f32;
+                let offset = (
+                    entry.position.0 as f32 / atlas_size as f32,
+                    entry.position.1 as f32 / atlas_size as f32,
+                );
+                atlased_images[entry.images[slot_id]] = Some((page_image_id, [scale, scale, offset.0, offset.1]));
+            }
+        }
+    }
+    if page_images.is_empty() {
+        return;
+    }
+
+    for material_instance in material_instances.iter_mut() {
+        let mut scale_offset = None;
+        for image in material_instance.images.iter_mut() {
+            if let Some((page_image_id, image_scale_offset)) = atlased_images[image.0.index()] {
+                image.0 = ImageHandle::new(page_image_id);
+                scale_offset = Some(image_scale_offset);
+            }
+        }
+        if let Some(scale_offset) = scale_offset {
+            let parameter_data = &mut material_instance.material_instance_data[atlas_parameter * 16..][..16];
+            for (value, packed_value) in scale_offset.iter().zip(parameter_data.chunks_exact_mut(4)) {
+                packed_value.copy_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+
+    // Atlased images are no longer referenced
+    let atlased_image_count = atlased_images.iter().filter(|image| image.is_some()).count();
+    let page_image_count = page_images.len();
+    let mut image_remap = vec![0; images.len() + page_image_count];
+    let mut remaining_images = Vec::with_capacity(images.len() + page_image_count - atlased_image_count);
+    for (image_id, image) in images.drain(..).chain(page_images).enumerate() {
+        if image_id < atlased_images.len() && atlased_images[image_id].is_some() {
+            continue;
+        }
+        image_remap[image_id] = remaining_images.len();
+        remaining_images.push(image);
+    }
+    *images = remaining_images;
+    for material_instance in material_instances.iter_mut() {
+        for image in material_instance.images.iter_mut() {
+            image.0 = ImageHandle::new(image_remap[image.0.index()]);
+        }
+    }
+
+    log::info!(
+        "packed {} images into {} atlas pages",
+        atlased_image_count,
+        page_image_count
+    );
+}
+
+// Entries are block aligned in every mip, so compressed blocks are copied as is
+fn build_page_image(
+    atlas_size: u32,
+    mipmap_count: usize,
+    slot_id: usize,
+    set: &AtlasPageSet,
+    images: &[DiskImage],
+) -> DiskImage {
+    let first_image = &images[set.entries[0].images[slot_id]];
+    let block_size = first_image.block_size;
+    let get_mip_size = |size: u32, mip: usize| block_size * ((size >> mip) / 4) as usize * ((size >> mip) / 4) as usize;
+
+    let page_size: usize = (0..mipmap_count).map(|mip| get_mip_size(atlas_size, mip)).sum();
+    let mut pixels = vec![0u8; page_size];
+    let mut page_mip_offset = 0;
+    for mip in 0..mipmap_count {
+        let page_row_pitch = block_size * ((atlas_size >> mip) / 4) as usize;
+        for entry in &set.entries {
+            let image = &images[entry.images[slot_id]];
+            let image_mip_offset: usize = (0..mip).map(|image_mip| get_mip_size(entry.size, image_mip)).sum();
+            let block_count = ((entry.size >> mip) / 4) as usize;
+            let block_x = ((entry.position.0 >> mip) / 4) as usize;
+            let block_y = ((entry.position.1 >> mip) / 4) as usize;
+            for row in 0..block_count {
+                let src_offset = image_mip_offset + row * block_count * block_size;
+                let dst_offset = page_mip_offset + (block_y + row) * page_row_pitch + block_x * block_size;
+                pixels[dst_offset..dst_offset + block_count * block_size]
+                    .copy_from_slice(&image.pixels[src_offset..src_offset + block_count * block_size]);
+            }
+        }
+        page_mip_offset += get_mip_size(atlas_size, mip);
+    }
+
+    DiskImage {
+        width: atlas_size,
+        height: atlas_size,
+        depth: 1,
+        block_size,
+        mipmap_count,
+        layer_count: 1,
+        image_type: first_image.image_type,
+        view_type: first_image.view_type,
+        format: first_image.format,
+        color_space: first_image.color_space,
+        generate_mipmaps: false,
+        pixels,
+    }
+}
+
+fn is_atlas_candidate(image: &DiskImage, max_image_size: u32) -> bool {
+    let block_compressed_formats = vk::Format::BC1_RGB_UNORM_BLOCK.as_raw()..=vk::Format::BC7_SRGB_BLOCK.as_raw();
+    image.view_type == vk::ImageViewType::TYPE_2D.as_raw()
+        && image.width == image.height
+        && image.width.is_power_of_two()
+        && image.width >= 4
+        && image.width <= max_image_size
+        && image.layer_count == 1
+        && !image.generate_mipmaps
+        && image.mipmap_count + 1 >= image.width.trailing_zeros() as usize
+        && block_compressed_formats.contains(&image.format)
+}
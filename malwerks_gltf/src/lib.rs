@@ -3,17 +3,28 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
 // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+mod gltf_collision;
 mod gltf_images;
+mod gltf_import_parameters;
+mod gltf_material_animations;
+mod gltf_material_definition;
 mod gltf_material_instances;
 mod gltf_materials;
 mod gltf_meshes;
 mod gltf_nodes;
 mod gltf_shared;
+mod gltf_texture_atlas;
 
+pub use gltf_import_parameters::*;
+pub use gltf_material_definition::*;
+
+use gltf_collision::*;
 use gltf_images::*;
+use gltf_material_animations::*;
 use gltf_material_instances::*;
 use gltf_meshes::*;
 use gltf_nodes::*;
+use gltf_texture_atlas::*;
 
 pub fn import_gltf_bundle(
     input_file: &std::path::Path,
@@ -24,20 +35,60 @@ pub fn import_gltf_bundle(
         .parent()
         .expect("failed to get file base path");
 
-    let (material_layouts, material_instances) = import_material_instances(gltf.materials());
+    let import_parameters = GltfImportParameters::from_gltf_file(input_file);
+    let mut material_definition = match &import_parameters.material_definition {
+        Some(definition_file) => MaterialDefinition::from_file(&base_path.join(definition_file)),
+        None => MaterialDefinition::default(),
+    };
+    let atlas_parameter = if import_parameters.atlas_max_image_size > 0 {
+        Some(material_definition.add_texture_atlas_parameter())
+    } else {
+        None
+    };
+    let uv_animation_parameter = if has_material_animations(gltf.materials()) {
+        Some(material_definition.add_uv_animation_parameter())
+    } else {
+        None
+    };
+    let (material_layouts, mut material_instances) = import_material_instances(gltf.materials(), &material_definition);
+    let material_animations = match uv_animation_parameter {
+        Some(parameter_id) => import_material_animations(gltf.materials(), parameter_id),
+        None => Vec::new(),
+    };
     let (mut buffers, meshes, materials, primitive_remap_table) = import_meshes(
-        &base_path,
+        &MeshImportContext {
+            base_path,
+            material_layouts: &material_layouts,
+            import_parameters: &import_parameters,
+            material_definition: &material_definition,
+        },
         gltf.buffers(),
         gltf.views(),
         gltf.meshes(),
         gltf.materials(),
-        &material_layouts,
     );
-    let buckets = import_nodes(primitive_remap_table, gltf.nodes(), &mut buffers);
-    let images = import_images(&base_path, temp_folder, gltf.materials(), gltf.images());
+    let collision = export_collision(&meshes, &buffers, &import_parameters);
+    let (buckets, scene_nodes) = import_nodes(primitive_remap_table, gltf.nodes(), &mut buffers);
+    let mut images = import_images(
+        &base_path,
+        temp_folder,
+        gltf.materials(),
+        gltf.images(),
+        import_parameters.runtime_mipmaps,
+    );
     let samplers = import_samplers(gltf.samplers());
+    if let Some(atlas_parameter) = atlas_parameter {
+        pack_texture_atlases(
+            import_parameters.atlas_size,
+            import_parameters.atlas_max_image_size,
+            atlas_parameter,
+            &mut images,
+            &samplers,
+            &mut material_instances,
+        );
+    }
 
-    malwerks_bundles::DiskResourceBundle {
+    let mut bundle = malwerks_bundles::DiskResourceBundle {
         buffers,
         meshes,
         images,
@@ -46,5 +97,14 @@ pub fn import_gltf_bundle(
         material_instances,
         materials,
         buckets,
+        scene_nodes,
+        collision,
+        material_animations,
+    };
+    // Bucket order of the node import is random, texture atlases change texture sets of material instances
+    bundle.sort_render_buckets();
+    if import_parameters.pack_mesh_geometry {
+        bundle.pack_mesh_geometry();
     }
+    bundle
 }
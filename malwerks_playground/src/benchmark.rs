@@ -0,0 +1,205 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_render::*;
+
+use ultraviolet as utv;
+
+// Camera advances by a fixed step every frame, so every run renders exactly the same frames
+const BENCHMARK_TIME_STEP: f32 = 1.0 / 60.0;
+
+// Closed loop in world space, one lap takes the whole benchmark duration
+const CAMERA_PATH: [[f32; 3]; 8] = [
+    [-10.0, 2.0, 0.0],
+    [-5.0, 3.0, 3.0],
+    [0.0, 2.0, 4.0],
+    [5.0, 3.0, 3.0],
+    [10.0, 2.0, 0.0],
+    [5.0, 3.0, -3.0],
+    [0.0, 2.0, -4.0],
+    [-5.0, 3.0, -3.0],
+];
+
+struct BenchmarkFrame {
+    path_time: f32,
+    cpu_frame_time: f32,
+    gpu_frame_time: f32,
+    gpu_pass_times: Vec<(&'static str, f32)>,
+    render_statistics: RenderStatistics,
+}
+
+pub struct Benchmark {
+    frame_count: usize,
+    report_file: std::path::PathBuf,
+    frames: Vec<BenchmarkFrame>,
+}
+
+impl Benchmark {
+    pub fn new(duration: f32, report_file: &std::path::Path) -> Self {
+        let frame_count = ((duration / BENCHMARK_TIME_STEP) as usize).max(1);
+        Self {
+            frame_count,
+            report_file: report_file.to_path_buf(),
+            frames: Vec::with_capacity(frame_count),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frames.len() >= self.frame_count
+    }
+
+    pub fn update_camera(&self, camera: &mut Camera) {
+        let path_time = self.get_path_time();
+        let position = sample_camera_path(path_time);
+        let direction = sample_camera_path(path_time + 0.05) - position;
+
+        // Camera stores the negated world position, orientation only has yaw to avoid rolling
+        camera.position = -position;
+        camera.orientation = utv::rotor::Rotor3::from_rotation_xz((-direction.x).atan2(-direction.z));
+    }
+
+    // GPU timings are from the oldest buffered frame and lag behind the CPU timings
+    pub fn record_frame(
+        &mut self,
+        cpu_frame_time: f32,
+        gpu_pass_timings: &[(&'static str, [u64; 2])],
+        render_statistics: &RenderStatistics,
+    ) {
+        let gpu_frame_time = if gpu_pass_timings.is_empty() {
+            0.0
+        } else {
+            let gpu_start_ns = gpu_pass_timings.iter().map(|(_, timings)| timings[0]).min().unwrap();
+            let gpu_stop_ns = gpu_pass_timings.iter().map(|(_, timings)| timings[1]).max().unwrap();
+            gpu_stop_ns.saturating_sub(gpu_start_ns) as f32 / 1_000_000.0
+        };
+
+        self.frames.push(BenchmarkFrame {
+            path_time: self.get_path_time(),
+            cpu_frame_time,
+            gpu_frame_time,
+            gpu_pass_times: gpu_pass_timings
+                .iter()
+                .map(|(name, timings)| (*name, timings[1].saturating_sub(timings[0]) as f32 / 1_000_000.0))
+                .collect(),
+            render_statistics: *render_statistics,
+        });
+    }
+
+    // Report is written as JSON if the file has a .json extension and as CSV otherwise
+    pub fn write_report(&self) {
+        if self.frames.is_empty() {
+            return;
+        }
+
+        let frame_count = self.frames.len() as f32;
+        let average_cpu_frame_time = self.frames.iter().map(|frame| frame.cpu_frame_time).sum::<f32>() / frame_count;
+        let average_gpu_frame_time = self.frames.iter().map(|frame| frame.gpu_frame_time).sum::<f32>() / frame_count;
+        let max_cpu_frame_time = self.frames.iter().map(|frame| frame.cpu_frame_time).fold(0.0, f32::max);
+        let max_gpu_frame_time = self.frames.iter().map(|frame| frame.gpu_frame_time).fold(0.0, f32::max);
+        log::info!(
+            "benchmark finished after {} frames: CPU {:.3}ms average, {:.3}ms max, GPU {:.3}ms average, {:.3}ms max",
+            self.frames.len(),
+            average_cpu_frame_time,
+            max_cpu_frame_time,
+            average_gpu_frame_time,
+            max_gpu_frame_time,
+        );
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.report_file)
+            .expect("failed to open benchmark report file for writing");
+        let mut writer = std::io::BufWriter::new(file);
+
+        let is_json = self
+            .report_file
+            .extension()
+            .is_some_and(|extension| extension == "json");
+        if is_json {
+            let frames: Vec<serde_json::Value> = self
+                .frames
+                .iter()
+                .map(|frame| {
+                    let gpu_pass_times: serde_json::Map<String, serde_json::Value> = frame
+                        .gpu_pass_times
+                        .iter()
+                        .map(|(name, time)| (name.to_string(), serde_json::json!(time)))
+                        .collect();
+                    serde_json::json!({
+                        "path_time": frame.path_time,
+                        "cpu_frame_time_ms": frame.cpu_frame_time,
+                        "gpu_frame_time_ms": frame.gpu_frame_time,
+                        "gpu_pass_times_ms": gpu_pass_times,
+                        "draw_calls": frame.render_statistics.draw_call_count,
+                        "instances": frame.render_statistics.instance_count,
+                        "triangles": frame.render_statistics.triangle_count,
+                    })
+                })
+                .collect();
+            serde_json::to_writer_pretty(
+                writer,
+                &serde_json::json!({
+                    "average_cpu_frame_time_ms": average_cpu_frame_time,
+                    "average_gpu_frame_time_ms": average_gpu_frame_time,
+                    "max_cpu_frame_time_ms": max_cpu_frame_time,
+                    "max_gpu_frame_time_ms": max_gpu_frame_time,
+                    "frames": frames,
+                }),
+            )
+            .expect("failed to write benchmark report");
+        } else {
+            use std::io::Write;
+
+            writeln!(
+                writer,
+                "frame,path_time,cpu_frame_time_ms,gpu_frame_time_ms,draw_calls,instances,triangles"
+            )
+            .expect("failed to write benchmark report");
+            for (frame_index, frame) in self.frames.iter().enumerate() {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{}",
+                    frame_index,
+                    frame.path_time,
+                    frame.cpu_frame_time,
+                    frame.gpu_frame_time,
+                    frame.render_statistics.draw_call_count,
+                    frame.render_statistics.instance_count,
+                    frame.render_statistics.triangle_count,
+                )
+                .expect("failed to write benchmark report");
+            }
+        }
+        log::info!("benchmark report written to {:?}", &self.report_file);
+    }
+
+    fn get_path_time(&self) -> f32 {
+        self.frames.len() as f32 / self.frame_count as f32
+    }
+}
+
+// Uniform Catmull-Rom spline through the control points, time wraps around at 1.0
+fn sample_camera_path(time: f32) -> utv::vec::Vec3 {
+    let point_count = CAMERA_PATH.len();
+    let position = time.rem_euclid(1.0) * point_count as f32;
+    let segment = position as usize % point_count;
+    let t = position.fract();
+
+    let point = |offset: usize| {
+        let p = CAMERA_PATH[(segment + offset) % point_count];
+        utv::vec::Vec3::new(p[0], p[1], p[2])
+    };
+    let p0 = point(point_count - 1);
+    let p1 = point(0);
+    let p2 = point(1);
+    let p3 = point(2);
+
+    let t2 = t * t;
+    let t3 = t2 * t;
+    ((p1 * 2.0) + (p2 - p0) * t + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2 + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
@@ -0,0 +1,367 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_render::*;
+
+use crate::Game;
+
+const MAX_CONSOLE_MESSAGES: usize = 1024;
+
+// Commands are queued by the console window and stdin, they run after the frame is presented
+pub struct Console {
+    messages: Vec<(String, bool)>, // text, is error
+    input: imgui::ImString,
+    auto_scroll: bool,
+    has_new_messages: bool,
+    pending_commands: Vec<String>,
+    stdin_commands: Option<std::sync::mpsc::Receiver<String>>,
+}
+
+impl Console {
+    pub fn new(read_stdin: bool) -> Self {
+        // Reader thread is detached, it blocks on stdin until the process exits
+        let stdin_commands = if read_stdin {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            std::thread::Builder::new()
+                .name("console_stdin".to_string())
+                .spawn(move || {
+                    use std::io::BufRead;
+                    for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+                        if sender.send(line).is_err() {
+                            break;
+                        }
+                    }
+                })
+                .expect("failed to start stdin thread");
+            Some(receiver)
+        } else {
+            None
+        };
+
+        Self {
+            messages: Vec::new(),
+            input: imgui::ImString::with_capacity(256),
+            auto_scroll: true,
+            has_new_messages: false,
+            pending_commands: Vec::new(),
+            stdin_commands,
+        }
+    }
+
+    pub fn has_pending_commands(&mut self) -> bool {
+        self.receive_stdin_commands();
+        !self.pending_commands.is_empty()
+    }
+
+    pub fn take_pending_commands(&mut self) -> Vec<String> {
+        self.receive_stdin_commands();
+        std::mem::take(&mut self.pending_commands)
+    }
+
+    fn receive_stdin_commands(&mut self) {
+        if let Some(stdin_commands) = &self.stdin_commands {
+            self.pending_commands.extend(stdin_commands.try_iter());
+        }
+    }
+
+    // Output is logged as well, so commands from stdin can be followed without the window
+    pub fn add_output(&mut self, command_line: &str, result: &CommandResult) {
+        self.add_message(format!("> {}", command_line), false);
+        match result {
+            Ok(output) => {
+                for line in output.lines() {
+                    log::info!("{}", line);
+                    self.add_message(line.to_string(), false);
+                }
+            }
+            Err(error) => {
+                log::error!("{}", error);
+                self.add_message(error.clone(), true);
+            }
+        }
+    }
+
+    pub fn show<'a>(&mut self, ui: &imgui::Ui<'a>) {
+        use imgui::*;
+
+        let messages = &mut self.messages;
+        let input = &mut self.input;
+        let auto_scroll = &mut self.auto_scroll;
+        let has_new_messages = std::mem::take(&mut self.has_new_messages);
+        let pending_commands = &mut self.pending_commands;
+
+        Window::new(im_str!("Console"))
+            .size([600.0, 300.0], Condition::FirstUseEver)
+            .collapsed(true, Condition::FirstUseEver)
+            .build(ui, || {
+                if ui.button(im_str!("Clear"), [0.0, 0.0]) {
+                    messages.clear();
+                }
+                ui.same_line(0.0);
+                ui.checkbox(im_str!("Auto scroll"), auto_scroll);
+                ui.separator();
+
+                let footer_height = ui.frame_height_with_spacing();
+                ChildWindow::new("console_messages")
+                    .size([0.0, -footer_height])
+                    .horizontal_scrollbar(true)
+                    .build(ui, || {
+                        for (message, is_error) in messages.iter() {
+                            if *is_error {
+                                ui.text_colored([1.0, 0.4, 0.4, 1.0], message);
+                            } else {
+                                ui.text(message);
+                            }
+                        }
+                        if has_new_messages && *auto_scroll {
+                            ui.set_scroll_here_y();
+                        }
+                    });
+
+                if InputText::new(ui, im_str!("Command"), input)
+                    .enter_returns_true(true)
+                    .build()
+                {
+                    pending_commands.push(input.to_string());
+                    input.clear();
+                    ui.set_keyboard_focus_here(FocusedWidget::Previous);
+                }
+            });
+    }
+
+    fn add_message(&mut self, message: String, is_error: bool) {
+        self.messages.push((message, is_error));
+        if self.messages.len() > MAX_CONSOLE_MESSAGES {
+            self.messages.drain(..self.messages.len() - MAX_CONSOLE_MESSAGES);
+        }
+        self.has_new_messages = true;
+    }
+}
+
+pub fn register_playground_commands(commands: &mut CommandRegistry<Game>) {
+    commands.register(
+        "load_bundle",
+        "<gltf file>, loads a glTF file relative to the assets folder",
+        |game, arguments| {
+            let gltf_file = std::path::Path::new(get_argument(arguments, 0)?);
+            let assets_folder = game.command_line.assets_folder.clone();
+            if !assets_folder.join(gltf_file).exists() {
+                return Err(format!("{:?} doesn't exist", assets_folder.join(gltf_file)));
+            }
+            let bundle_name = gltf_file.to_str().unwrap();
+            if game
+                .pbr_forward_lit
+                .get_render_bundles()
+                .iter()
+                .any(|(name, _, _, _)| name == bundle_name)
+            {
+                return Err(format!("bundle \"{}\" is already loaded", bundle_name));
+            }
+
+            let bundle_file = gltf_file.with_extension("resource_bundle");
+            game.pbr_forward_lit.add_render_bundle(
+                bundle_name,
+                &mut game.bundle_loader,
+                &assets_folder.join(gltf_file),
+                &assets_folder.join(bundle_file.file_name().unwrap()),
+                &assets_folder
+                    .join("..")
+                    .join("malwerks_shaders")
+                    .join("gltf_pbr_material.glsl"),
+                &game.device,
+                &mut game.factory,
+                &mut game.queue,
+            );
+            Ok(format!("loaded \"{}\"", bundle_name))
+        },
+    );
+    commands.register("unload_bundle", "<name>, removes a loaded bundle", |game, arguments| {
+        let bundle_name = get_argument(arguments, 0)?;
+        if !game
+            .pbr_forward_lit
+            .get_render_bundles()
+            .iter()
+            .any(|(name, _, _, _)| name == bundle_name)
+        {
+            return Err(format!("bundle \"{}\" is not loaded", bundle_name));
+        }
+        game.pbr_forward_lit
+            .remove_render_bundle(bundle_name, &mut game.bundle_loader);
+        Ok(format!("unloaded \"{}\"", bundle_name))
+    });
+    commands.register(
+        "reload_shaders",
+        "compiles changed shaders of loaded bundles",
+        |game, _| {
+            game.reload_shaders()?;
+            Ok("reloaded shaders".to_string())
+        },
+    );
+    commands.register("list_bundles", "lists loaded bundles", |game, _| {
+        Ok(game
+            .pbr_forward_lit
+            .get_render_bundles()
+            .iter()
+            .map(|(name, _, _, _)| name.clone())
+            .collect::<Vec<String>>()
+            .join("\n"))
+    });
+
+    commands.register(
+        "set_camera",
+        "<x> <y> <z> [<s> <xy> <xz> <yz>], sets camera position and optionally orientation as a rotor",
+        |game, arguments| {
+            let values = arguments
+                .iter()
+                .map(|argument| argument.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|error| format!("{}", error))?;
+            let mut camera_state = game.camera_state.lock().unwrap();
+            let camera = camera_state.get_camera_mut();
+            match values.len() {
+                3 | 7 => camera.position = ultraviolet::vec::Vec3::new(values[0], values[1], values[2]),
+                _ => return Err("set_camera expects 3 or 7 values".to_string()),
+            }
+            if values.len() == 7 {
+                camera.orientation = ultraviolet::rotor::Rotor3::new(
+                    values[3],
+                    ultraviolet::bivec::Bivec3::new(values[4], values[5], values[6]),
+                )
+                .normalized();
+            }
+            Ok(format!("{:?} {:?}", camera.position, camera.orientation))
+        },
+    );
+    commands.register("get_camera", "prints camera position and orientation", |game, _| {
+        let camera_state = game.camera_state.lock().unwrap();
+        let camera = camera_state.get_camera();
+        Ok(format!("{:?} {:?}", camera.position, camera.orientation))
+    });
+
+    commands.register(
+        "debug",
+        "<anti_aliasing|color_space|nan_detection|overdraw_heatmap|reference_mode|screen_space_reflections|shader_clock_heatmap> <on|off>, toggles debug modes",
+        |game, arguments| {
+            let mode = get_argument(arguments, 0)?;
+            let enable = match get_argument(arguments, 1)? {
+                "on" => true,
+                "off" => false,
+                value => return Err(format!("expected on or off, got \"{}\"", value)),
+            };
+            let pbr_forward_lit = &mut game.pbr_forward_lit;
+            let screen_space_reflections = ScreenSpaceReflectionParameters::default();
+            let shader_clock_heatmap = ShaderClockHeatmapParameters::default();
+            match mode {
+                "anti_aliasing" => pbr_forward_lit.debug_enable_anti_aliasing(enable),
+                "color_space" => pbr_forward_lit.debug_highlight_color_space_mistakes(enable),
+                "nan_detection" if pbr_forward_lit.is_nan_detection_available() => {
+                    pbr_forward_lit.set_nan_detection(if enable {
+                        Some(&NanDetectionParameters { tint_output: true })
+                    } else {
+                        None
+                    })
+                }
+                "nan_detection" => return Err("NaN detection is only available in debug builds".to_string()),
+                "overdraw_heatmap" => pbr_forward_lit.set_overdraw_heatmap(if enable { Some(0.5) } else { None }),
+                "reference_mode" if pbr_forward_lit.is_reference_mode_available() => {
+                    pbr_forward_lit.set_reference_mode(enable)
+                }
+                "reference_mode" => return Err("reference mode requires --enable_ray_tracing".to_string()),
+                "screen_space_reflections" => pbr_forward_lit.set_screen_space_reflections(if enable {
+                    Some(&screen_space_reflections)
+                } else {
+                    None
+                }),
+                "shader_clock_heatmap" if pbr_forward_lit.is_shader_clock_heatmap_available() => {
+                    pbr_forward_lit.set_shader_clock_heatmap(if enable {
+                        Some(&shader_clock_heatmap)
+                    } else {
+                        None
+                    })
+                }
+                "shader_clock_heatmap" => return Err("shader clock heatmap requires --enable_shader_clock".to_string()),
+                _ => return Err(format!("unknown debug mode \"{}\"", mode)),
+            }
+            Ok(format!("{} {}", mode, if enable { "enabled" } else { "disabled" }))
+        },
+    );
+
+    commands.register("stats", "prints render statistics of the last frame", |game, _| {
+        let statistics = game.pbr_forward_lit.get_render_statistics();
+        let mut output = format!(
+            "GPU time: {:.2}ms, resolution scale: {:.2}\ndraw calls: {}, instances: {}, triangles: {}",
+            game.pbr_forward_lit.get_gpu_frame_time(),
+            game.pbr_forward_lit.get_resolution_scale(),
+            statistics.draw_call_count,
+            statistics.instance_count,
+            statistics.triangle_count
+        );
+        if let (Some(_), Some(result)) = (
+            game.pbr_forward_lit.get_nan_detection(),
+            game.pbr_forward_lit.get_nan_detection_result(),
+        ) {
+            output += &format!(
+                "\nnon-finite pixels: {}, non-finite buffer values: {}",
+                result.pixel_count, result.value_count
+            );
+        }
+        for (pass_name, statistics) in &game.gpu_pass_statistics {
+            output += &format!(
+                "\n{}: {} vertex, {} fragment, {} compute invocations",
+                pass_name,
+                statistics.vertex_shader_invocations,
+                statistics.fragment_shader_invocations,
+                statistics.compute_shader_invocations
+            );
+        }
+        Ok(output)
+    });
+
+    commands.register(
+        "screenshot",
+        "[<dds file>], saves HDR color before tone mapping, relative to the assets folder",
+        |game, arguments| {
+            let screenshot_file = game
+                .command_line
+                .assets_folder
+                .join(arguments.first().copied().unwrap_or("screenshot.dds"));
+            game.queue.wait_idle();
+            game.device.wait_idle();
+            let screenshot = game.pbr_forward_lit.capture_color(
+                game.bundle_loader.get_command_buffer_mut(),
+                &mut game.factory,
+                &mut game.queue,
+            );
+            screenshot.save_to_file(&screenshot_file);
+            Ok(format!("screenshot saved to {:?}", screenshot_file))
+        },
+    );
+    commands.register(
+        "dump_frame_graph",
+        "[<dot|json file>], saves passes and dependencies of the last frame, relative to the assets folder",
+        |game, arguments| {
+            let dump_file = game
+                .command_line
+                .assets_folder
+                .join(arguments.first().copied().unwrap_or("frame_graph.dot"));
+            match dump_file.extension().and_then(|extension| extension.to_str()) {
+                Some("dot") | Some("json") => {}
+                _ => return Err(format!("{:?} has to be a .dot or .json file", dump_file)),
+            }
+
+            game.pbr_forward_lit
+                .dump_frame_graph(&[("surface", game.surface_pass.get_render_layer())])
+                .save_to_file(&dump_file);
+            Ok(format!("frame graph saved to {:?}", dump_file))
+        },
+    );
+}
+
+fn get_argument<'a>(arguments: &[&'a str], index: usize) -> Result<&'a str, String> {
+    arguments
+        .get(index)
+        .copied()
+        .ok_or_else(|| format!("missing argument {}", index + 1))
+}
@@ -7,12 +7,16 @@ use malwerks_render::*;
 use malwerks_vk::*;
 
 use crate::camera_state::*;
+use crate::display_settings::*;
+use crate::profiler_export::*;
+use crate::settings::*;
 
 pub fn show_debug_window<'a>(
     ui: &imgui::Ui<'a>,
     _window: &winit::window::Window,
     gilrs: &gilrs::Gilrs,
     camera_state: &mut CameraState,
+    profiler_export: &mut ProfilerExport,
     average_frame_time: f32,
     average_fps: f32,
 ) {
@@ -36,6 +40,15 @@ pub fn show_debug_window<'a>(
                 if ui.button(im_str!("Toggle profiler"), [0.0, 0.0]) {
                     puffin::set_scopes_on(!puffin::are_scopes_on());
                 }
+                ui.same_line(0.0);
+                if profiler_export.is_capturing() {
+                    ui.text(im_str!(
+                        "Capturing frame {}",
+                        profiler_export.get_captured_frame_count()
+                    ));
+                } else if ui.button(im_str!("Export Chrome trace"), [0.0, 0.0]) {
+                    profiler_export.start_capture();
+                }
             }
 
             // camera
@@ -61,8 +74,9 @@ pub fn show_debug_window<'a>(
             // input
             if CollapsingHeader::new(im_str!("Input")).default_open(true).build(ui) {
                 ui.text_wrapped(im_str!(
-                    "WASD for camera movement, right mouse click + drag to rotate, Space/LeftControl to move up/down"
+                    "WASD for camera movement, right mouse click + drag to rotate, Space/LeftControl to move up/down, left click to select scene nodes"
                 ));
+                ui.text_wrapped(im_str!("F5 reloads shaders, F10 toggles vsync, F11 toggles borderless fullscreen"));
                 if gilrs.gamepads().count() > 0 {
                     ui.text_wrapped(im_str!(
                         "Right stick for camera movement, left stick to rotate, RB/LB to move up/down"
@@ -76,6 +90,118 @@ pub fn show_debug_window<'a>(
         });
 }
 
+// Changes are applied by the playground before the next frame, saving is explicit
+pub fn show_settings_window<'a>(
+    ui: &imgui::Ui<'a>,
+    settings: &mut Settings,
+    settings_file: &std::path::Path,
+    max_sampler_anisotropy: f32,
+) {
+    use imgui::*;
+
+    Window::new(im_str!("Settings")).always_auto_resize(true).build(ui, || {
+        ui.text(im_str!("Graphics"));
+        ui.checkbox(im_str!("Anti aliasing"), &mut settings.anti_aliasing);
+        ui.checkbox(
+            im_str!("Highlight color space mistakes"),
+            &mut settings.highlight_color_space_mistakes,
+        );
+
+        let mut anisotropy_override = settings.max_anisotropy.is_some();
+        if ui.checkbox(im_str!("Override anisotropy"), &mut anisotropy_override) {
+            settings.max_anisotropy = if anisotropy_override {
+                Some(max_sampler_anisotropy)
+            } else {
+                None
+            };
+        }
+        if let Some(max_anisotropy) = &mut settings.max_anisotropy {
+            Slider::new(im_str!("Max anisotropy"))
+                .range(1.0..=max_sampler_anisotropy)
+                .build(ui, max_anisotropy);
+        }
+
+        Slider::new(im_str!("Texture LOD bias"))
+            .range(-4.0..=4.0)
+            .build(ui, &mut settings.texture_lod_bias);
+        let mut clamp_texture_resolution = settings.max_texture_resolution.is_some();
+        if ui.checkbox(im_str!("Clamp texture resolution"), &mut clamp_texture_resolution) {
+            settings.max_texture_resolution = if clamp_texture_resolution { Some(512) } else { None };
+        }
+        if let Some(max_texture_resolution) = &mut settings.max_texture_resolution {
+            Slider::new(im_str!("Max texture resolution"))
+                .range(1..=4096)
+                .build(ui, max_texture_resolution);
+            *max_texture_resolution = max_texture_resolution.next_power_of_two();
+        }
+
+        let mut adaptive_resolution = settings.adaptive_resolution.is_some();
+        if ui.checkbox(im_str!("Adaptive resolution"), &mut adaptive_resolution) {
+            settings.adaptive_resolution = if adaptive_resolution { Some(8.0) } else { None };
+        }
+        if let Some(target_frame_time) = &mut settings.adaptive_resolution {
+            Slider::new(im_str!("Target GPU time (ms)"))
+                .range(1.0..=33.0)
+                .build(ui, target_frame_time);
+        } else {
+            Slider::new(im_str!("Resolution scale"))
+                .range(0.5..=1.0)
+                .build(ui, &mut settings.resolution_scale);
+        }
+        ui.separator();
+
+        ui.text(im_str!("Display"));
+        ui.checkbox(im_str!("Vsync"), &mut settings.display.vsync);
+        ui.radio_button(
+            im_str!("Windowed"),
+            &mut settings.display.window_mode,
+            WindowMode::Windowed,
+        );
+        ui.same_line(0.0);
+        ui.radio_button(
+            im_str!("Borderless"),
+            &mut settings.display.window_mode,
+            WindowMode::Borderless,
+        );
+        ui.same_line(0.0);
+        ui.radio_button(
+            im_str!("Exclusive"),
+            &mut settings.display.window_mode,
+            WindowMode::Exclusive,
+        );
+
+        let mut ui_scale_override = settings.ui_scale.is_some();
+        if ui.checkbox(im_str!("Override UI scale"), &mut ui_scale_override) {
+            settings.ui_scale = if ui_scale_override { Some(1.0) } else { None };
+        }
+        if let Some(ui_scale) = &mut settings.ui_scale {
+            // Font atlas is rebuilt on every change, so the scale is only applied once the slider is released
+            static mut UI_SCALE_EDIT: Option<f32> = None;
+            let mut scale = unsafe { UI_SCALE_EDIT }.unwrap_or(*ui_scale);
+            Slider::new(im_str!("UI scale")).range(0.5..=4.0).build(ui, &mut scale);
+            if ui.is_item_active() {
+                unsafe { UI_SCALE_EDIT = Some(scale) };
+            } else {
+                unsafe { UI_SCALE_EDIT = None };
+                *ui_scale = scale;
+            }
+        }
+        ui.separator();
+
+        if ui.button(im_str!("Save"), [0.0, 0.0]) {
+            settings.save(settings_file);
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Load"), [0.0, 0.0]) {
+            *settings = Settings::load(settings_file);
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Reset to defaults"), [0.0, 0.0]) {
+            *settings = Settings::default();
+        }
+    });
+}
+
 pub fn show_pbr_forward_lit_window<'a>(
     ui: &imgui::Ui<'a>,
     assets_folder: &std::path::Path,
@@ -92,19 +218,258 @@ pub fn show_pbr_forward_lit_window<'a>(
     Window::new(im_str!("PbrForwardLit"))
         .always_auto_resize(true)
         .build(ui, || {
-            ui.text(im_str!("Settings"));
-            static mut ANTI_ALIASING: bool = true;
-            if ui.checkbox(im_str!("Anti aliasing"), unsafe { &mut ANTI_ALIASING }) {
-                pbr_forward_lit.debug_enable_anti_aliasing(unsafe { ANTI_ALIASING });
+            ui.text(ImString::from(format!(
+                "Resolution scale: {:.2}, GPU time: {:.2}ms",
+                pbr_forward_lit.get_resolution_scale(),
+                pbr_forward_lit.get_gpu_frame_time()
+            )));
+            ui.separator();
+
+            let mut screen_space_reflections = pbr_forward_lit.get_screen_space_reflections().is_some();
+            if ui.checkbox(im_str!("Screen space reflections"), &mut screen_space_reflections) {
+                let default_parameters = ScreenSpaceReflectionParameters::default();
+                pbr_forward_lit.set_screen_space_reflections(if screen_space_reflections {
+                    Some(&default_parameters)
+                } else {
+                    None
+                });
+            }
+            if let Some(parameters) = pbr_forward_lit.get_screen_space_reflections() {
+                let mut parameters = *parameters;
+                let mut parameters_changed = false;
+                parameters_changed |= Slider::new(im_str!("Reflection max distance"))
+                    .range(1.0..=256.0)
+                    .build(ui, &mut parameters.max_distance);
+                parameters_changed |= Slider::new(im_str!("Reflection thickness"))
+                    .range(0.01..=4.0)
+                    .build(ui, &mut parameters.thickness);
+                parameters_changed |= Slider::new(im_str!("Reflection max roughness"))
+                    .range(0.05..=1.0)
+                    .build(ui, &mut parameters.max_roughness);
+                parameters_changed |= Slider::new(im_str!("Reflection max iterations"))
+                    .range(8..=256)
+                    .build(ui, &mut parameters.max_iterations);
+                if parameters_changed {
+                    pbr_forward_lit.set_screen_space_reflections(Some(&parameters));
+                }
+            }
+
+            let mut water_surface = pbr_forward_lit.get_water_surface().is_some();
+            if ui.checkbox(im_str!("Water plane"), &mut water_surface) {
+                let default_parameters = WaterSurfaceParameters::default();
+                pbr_forward_lit.set_water_surface(if water_surface { Some(&default_parameters) } else { None });
+            }
+            if let Some(parameters) = pbr_forward_lit.get_water_surface() {
+                let mut parameters = *parameters;
+                let mut parameters_changed = false;
+                parameters_changed |= Slider::new(im_str!("Water height"))
+                    .range(-10.0..=10.0)
+                    .build(ui, &mut parameters.position[1]);
+                parameters_changed |= Slider::new(im_str!("Water size"))
+                    .range(4.0..=256.0)
+                    .build(ui, &mut parameters.size);
+                parameters_changed |= Slider::new(im_str!("Wave amplitude"))
+                    .range(0.0..=1.0)
+                    .build(ui, &mut parameters.amplitude);
+                parameters_changed |= Slider::new(im_str!("Wavelength"))
+                    .range(0.5..=32.0)
+                    .build(ui, &mut parameters.wavelength);
+                parameters_changed |= Slider::new(im_str!("Wave steepness"))
+                    .range(0.0..=1.0)
+                    .build(ui, &mut parameters.steepness);
+                parameters_changed |= Slider::new(im_str!("Wave speed"))
+                    .range(0.0..=4.0)
+                    .build(ui, &mut parameters.speed);
+                parameters_changed |= Slider::new(im_str!("Wave direction"))
+                    .range(-std::f32::consts::PI..=std::f32::consts::PI)
+                    .build(ui, &mut parameters.direction);
+                if parameters_changed {
+                    pbr_forward_lit.set_water_surface(Some(&parameters));
+                }
+            }
+
+            let mut volumetric_fog = pbr_forward_lit.get_volumetric_fog().is_some();
+            if ui.checkbox(im_str!("Volumetric fog"), &mut volumetric_fog) {
+                let default_parameters = VolumetricFogParameters::default();
+                pbr_forward_lit.set_volumetric_fog(if volumetric_fog {
+                    Some(&default_parameters)
+                } else {
+                    None
+                });
+            }
+            if let Some(parameters) = pbr_forward_lit.get_volumetric_fog() {
+                let mut parameters = *parameters;
+                let mut parameters_changed = false;
+                parameters_changed |= Slider::new(im_str!("Fog density"))
+                    .range(0.0..=0.2)
+                    .build(ui, &mut parameters.density);
+                parameters_changed |= Slider::new(im_str!("Fog height"))
+                    .range(-10.0..=50.0)
+                    .build(ui, &mut parameters.height);
+                parameters_changed |= Slider::new(im_str!("Fog height falloff"))
+                    .range(0.0..=2.0)
+                    .build(ui, &mut parameters.height_falloff);
+                parameters_changed |= Slider::new(im_str!("Fog anisotropy"))
+                    .range(-0.9..=0.9)
+                    .build(ui, &mut parameters.anisotropy);
+                parameters_changed |= Slider::new(im_str!("Fog max distance"))
+                    .range(16.0..=512.0)
+                    .build(ui, &mut parameters.max_distance);
+                if parameters_changed {
+                    pbr_forward_lit.set_volumetric_fog(Some(&parameters));
+                }
+            }
+
+            let mut dithering = pbr_forward_lit.get_dithering().is_some();
+            if ui.checkbox(im_str!("Output dithering"), &mut dithering) {
+                let default_parameters = DitheringParameters::default();
+                pbr_forward_lit.set_dithering(if dithering { Some(&default_parameters) } else { None });
+            }
+            if let Some(parameters) = pbr_forward_lit.get_dithering() {
+                let mut parameters = *parameters;
+                if Slider::new(im_str!("Dither strength"))
+                    .range(0.0..=4.0)
+                    .build(ui, &mut parameters.strength)
+                {
+                    pbr_forward_lit.set_dithering(Some(&parameters));
+                }
+            }
+
+            let mut overdraw_heatmap = pbr_forward_lit.get_overdraw_heatmap().is_some();
+            if ui.checkbox(im_str!("Overdraw heatmap"), &mut overdraw_heatmap) {
+                pbr_forward_lit.set_overdraw_heatmap(if overdraw_heatmap { Some(0.75) } else { None });
+            }
+            if let Some(mut opacity) = pbr_forward_lit.get_overdraw_heatmap() {
+                if Slider::new(im_str!("Heatmap opacity"))
+                    .range(0.0..=1.0)
+                    .build(ui, &mut opacity)
+                {
+                    pbr_forward_lit.set_overdraw_heatmap(Some(opacity));
+                }
+            }
+            if pbr_forward_lit.is_shader_clock_heatmap_available() {
+                let mut shader_clock_heatmap = pbr_forward_lit.get_shader_clock_heatmap().is_some();
+                if ui.checkbox(im_str!("Fragment cost heatmap"), &mut shader_clock_heatmap) {
+                    let default_parameters = ShaderClockHeatmapParameters::default();
+                    pbr_forward_lit.set_shader_clock_heatmap(if shader_clock_heatmap {
+                        Some(&default_parameters)
+                    } else {
+                        None
+                    });
+                }
+                if let Some(parameters) = pbr_forward_lit.get_shader_clock_heatmap() {
+                    let mut parameters = *parameters;
+                    if Slider::new(im_str!("Max clock ticks"))
+                        .range(1000.0..=10000000.0)
+                        .flags(SliderFlags::LOGARITHMIC)
+                        .build(ui, &mut parameters.max_cost)
+                    {
+                        pbr_forward_lit.set_shader_clock_heatmap(Some(&parameters));
+                    }
+                }
+            }
+
+            if pbr_forward_lit.is_reference_mode_available() {
+                let mut reference_mode = pbr_forward_lit.get_reference_mode();
+                if ui.checkbox(im_str!("Reference path tracer"), &mut reference_mode) {
+                    pbr_forward_lit.set_reference_mode(reference_mode);
+                }
+                if let Some(sample_count) = pbr_forward_lit.get_reference_sample_count() {
+                    ui.text(ImString::from(format!("{} samples", sample_count)));
+                }
+            }
+
+            let mut depth_view = pbr_forward_lit.get_depth_view().is_some();
+            if ui.checkbox(im_str!("Depth view"), &mut depth_view) {
+                let default_parameters = DepthViewParameters::default();
+                pbr_forward_lit.set_depth_view(if depth_view { Some(&default_parameters) } else { None });
+            }
+            if let Some(parameters) = pbr_forward_lit.get_depth_view() {
+                let mut parameters = *parameters;
+                let mut parameters_changed = false;
+                // Depth pyramid is built by screen space reflections
+                if let Some(level_count) = pbr_forward_lit.get_hi_z_level_count() {
+                    let mut hi_z_level = match parameters.source {
+                        DepthViewSource::SceneDepth => -1,
+                        DepthViewSource::HiZ(level) => level as i32,
+                    };
+                    if Slider::new(im_str!("Hi-Z level"))
+                        .range(-1..=level_count as i32 - 1)
+                        .build(ui, &mut hi_z_level)
+                    {
+                        parameters.source = if hi_z_level < 0 {
+                            DepthViewSource::SceneDepth
+                        } else {
+                            DepthViewSource::HiZ(hi_z_level as u32)
+                        };
+                        parameters_changed = true;
+                    }
+                }
+                parameters_changed |= Slider::new(im_str!("Max distance"))
+                    .range(1.0..=10000.0)
+                    .flags(SliderFlags::LOGARITHMIC)
+                    .build(ui, &mut parameters.max_distance);
+                if parameters_changed {
+                    pbr_forward_lit.set_depth_view(Some(&parameters));
+                }
+                if ui.button(im_str!("Export linear depth"), [0.0, 0.0]) {
+                    queue.wait_idle();
+                    let depth_image = pbr_forward_lit.capture_linear_depth(
+                        parameters.source,
+                        bundle_loader.get_command_buffer_mut(),
+                        factory,
+                        queue,
+                    );
+                    let depth_file = assets_folder.join("linear_depth.dds");
+                    depth_image.save_to_file(&depth_file);
+                    log::info!("linear depth saved to {:?}", depth_file);
+                }
+            }
+            if pbr_forward_lit.is_nan_detection_available() {
+                let mut nan_detection = pbr_forward_lit.get_nan_detection().is_some();
+                if ui.checkbox(im_str!("NaN detection"), &mut nan_detection) {
+                    let default_parameters = NanDetectionParameters::default();
+                    pbr_forward_lit.set_nan_detection(if nan_detection { Some(&default_parameters) } else { None });
+                }
+                if let Some(parameters) = pbr_forward_lit.get_nan_detection() {
+                    let mut parameters = *parameters;
+                    if ui.checkbox(im_str!("Tint NaN pixels"), &mut parameters.tint_output) {
+                        pbr_forward_lit.set_nan_detection(Some(&parameters));
+                    }
+                    if let Some(result) = pbr_forward_lit.get_nan_detection_result() {
+                        ui.text(ImString::from(format!(
+                            "{} pixels, {} buffer values",
+                            result.pixel_count, result.value_count
+                        )));
+                    }
+                }
+            }
+            if pbr_forward_lit.is_stereo_view_available() {
+                let mut stereo_view = pbr_forward_lit.get_stereo_view().is_some();
+                if ui.checkbox(im_str!("Stereo view"), &mut stereo_view) {
+                    pbr_forward_lit.set_stereo_view(if stereo_view { Some(0.064) } else { None });
+                }
+                if let Some(mut eye_separation) = pbr_forward_lit.get_stereo_view() {
+                    if Slider::new(im_str!("Eye separation"))
+                        .range(0.0..=1.0)
+                        .build(ui, &mut eye_separation)
+                    {
+                        pbr_forward_lit.set_stereo_view(Some(eye_separation));
+                    }
+                }
             }
             ui.separator();
             ui.text(im_str!("Test bundles"));
 
+            // Checkboxes follow the loaded bundles, they change along with the scene tab
             macro_rules! bundle_checkbox {
                 ($gltf_path: expr, $bundle_path: expr) => {{
-                    static mut BUNDLE_FLAG: bool = false;
-                    if ui.checkbox(im_str!($gltf_path), unsafe { &mut BUNDLE_FLAG }) {
-                        if unsafe { BUNDLE_FLAG } {
+                    let mut is_loaded = pbr_forward_lit
+                        .get_render_bundles()
+                        .iter()
+                        .any(|(bundle_name, _, _, _)| bundle_name == $gltf_path);
+                    if ui.checkbox(im_str!($gltf_path), &mut is_loaded) {
+                        if is_loaded {
                             pbr_forward_lit.add_render_bundle(
                                 $gltf_path,
                                 bundle_loader,
@@ -181,3 +546,223 @@ pub fn show_pbr_forward_lit_window<'a>(
             }
         });
 }
+
+// Cached files are only read when bundles are loaded, clearing them doesn't affect loaded bundles
+pub fn show_asset_cache_window<'a>(ui: &imgui::Ui<'a>, bundle_loader: &mut BundleLoader) {
+    use imgui::*;
+
+    const CACHE_KINDS: [AssetCacheKind; 4] = [
+        AssetCacheKind::ShaderCache,
+        AssetCacheKind::PipelineCache,
+        AssetCacheKind::Thumbnails,
+        AssetCacheKind::ImportManifest,
+    ];
+    let megabytes = |size: u64| size as f64 / (1024.0 * 1024.0);
+
+    Window::new(im_str!("Asset cache"))
+        .always_auto_resize(true)
+        .collapsed(true, Condition::FirstUseEver)
+        .build(ui, || {
+            let asset_cache = bundle_loader.get_asset_cache();
+            ui.text(ImString::from(format!(
+                "{:.2}MB of {:.0}MB in {:?}",
+                megabytes(asset_cache.get_total_size()),
+                megabytes(asset_cache.get_size_budget()),
+                asset_cache.get_cache_folder()
+            )));
+            let mut cleared_bundle = None;
+            let clear_all = ui.button(im_str!("Clear all"), [0.0, 0.0]);
+            ui.separator();
+
+            for (bundle_name, bundle_size) in asset_cache.get_bundle_sizes() {
+                ui.text(ImString::from(format!(
+                    "{}: {:.2}MB",
+                    bundle_name,
+                    megabytes(bundle_size)
+                )));
+                if ui.is_item_hovered() {
+                    let mut tooltip = CACHE_KINDS
+                        .iter()
+                        .map(|kind| {
+                            format!(
+                                "{}: {:.2}MB",
+                                kind.get_name(),
+                                megabytes(asset_cache.get_bundle_kind_size(&bundle_name, *kind))
+                            )
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n");
+                    if let Some(import_manifest) = bundle_loader.get_import_manifest(&bundle_name) {
+                        tooltip += &format!(
+                            "\nimported from {:?}\n{} seconds since UNIX epoch",
+                            import_manifest.gltf_file, import_manifest.import_time
+                        );
+                    }
+                    ui.tooltip_text(tooltip);
+                }
+                ui.same_line(0.0);
+                if ui.button(&ImString::from(format!("Clear##{}", bundle_name)), [0.0, 0.0]) {
+                    cleared_bundle = Some(bundle_name);
+                }
+            }
+
+            let asset_cache = bundle_loader.get_asset_cache_mut();
+            if let Some(bundle_name) = cleared_bundle {
+                asset_cache.clear_bundle(&bundle_name);
+            }
+            if clear_all {
+                asset_cache.clear_all();
+            }
+        });
+}
+
+// Shows debugPrintfEXT output, messages are kept until cleared
+pub fn show_shader_console_window<'a>(ui: &imgui::Ui<'a>, device: &Device) {
+    use imgui::*;
+
+    if !device.is_shader_debug_printf_enabled() {
+        return;
+    }
+
+    const MAX_CONSOLE_MESSAGES: usize = 1024;
+    static mut CONSOLE_MESSAGES: Vec<String> = Vec::new();
+    static mut AUTO_SCROLL: bool = true;
+
+    let messages = unsafe { &mut CONSOLE_MESSAGES };
+    let new_messages = take_shader_debug_messages();
+    let has_new_messages = !new_messages.is_empty();
+    messages.extend(new_messages);
+    if messages.len() > MAX_CONSOLE_MESSAGES {
+        messages.drain(..messages.len() - MAX_CONSOLE_MESSAGES);
+    }
+
+    Window::new(im_str!("Shader console"))
+        .size([600.0, 300.0], Condition::FirstUseEver)
+        .build(ui, || {
+            if ui.button(im_str!("Clear"), [0.0, 0.0]) {
+                messages.clear();
+            }
+            ui.same_line(0.0);
+            ui.checkbox(im_str!("Auto scroll"), unsafe { &mut AUTO_SCROLL });
+            ui.separator();
+
+            ChildWindow::new("shader_console_messages")
+                .horizontal_scrollbar(true)
+                .build(ui, || {
+                    for message in messages.iter() {
+                        ui.text(message);
+                    }
+                    if has_new_messages && unsafe { AUTO_SCROLL } {
+                        ui.set_scroll_here_y();
+                    }
+                });
+        });
+}
+
+// Splits "file:line: error: message" lines of the shaderc log, other lines are shown as is
+fn parse_shader_error_line(line: &str) -> Option<(&str, &str, &str)> {
+    let position = line.find(": error:").or_else(|| line.find(": warning:"))?;
+    let (location, message) = line.split_at(position);
+    let (file, line_number) = location.rsplit_once(':')?;
+    line_number.parse::<u32>().ok()?;
+    Some((file, line_number, message[1..].trim()))
+}
+
+// Shows the log of the last failed shader reload, previous pipelines keep rendering until it is fixed
+// Invocation counts of every pass, the forward pass includes its compute work before the render pass
+pub fn show_pipeline_statistics_window<'a>(
+    ui: &imgui::Ui<'a>,
+    device: &Device,
+    gpu_pass_statistics: &[(&'static str, PipelineStatistics)],
+) {
+    use imgui::*;
+
+    puffin::profile_function!();
+    Window::new(im_str!("Pipeline statistics"))
+        .always_auto_resize(true)
+        .collapsed(true, Condition::FirstUseEver)
+        .build(ui, || {
+            if !device.is_pipeline_statistics_enabled() {
+                ui.text_wrapped(im_str!(
+                    "Pipeline statistics are disabled, run with --enable_pipeline_statistics"
+                ));
+                return;
+            }
+
+            ui.columns(4, im_str!("pipeline_statistics"), true);
+            for header in &[
+                im_str!("Pass"),
+                im_str!("Vertex"),
+                im_str!("Fragment"),
+                im_str!("Compute"),
+            ] {
+                ui.text(header);
+                ui.next_column();
+            }
+            ui.separator();
+
+            let show_row = |pass_name: &str, statistics: &PipelineStatistics| {
+                ui.text(pass_name);
+                ui.next_column();
+                ui.text(format!("{}", statistics.vertex_shader_invocations));
+                ui.next_column();
+                ui.text(format!("{}", statistics.fragment_shader_invocations));
+                ui.next_column();
+                ui.text(format!("{}", statistics.compute_shader_invocations));
+                ui.next_column();
+            };
+            let mut total_statistics = PipelineStatistics::default();
+            for (pass_name, statistics) in gpu_pass_statistics {
+                show_row(pass_name, statistics);
+                total_statistics += *statistics;
+            }
+            ui.separator();
+            show_row("total", &total_statistics);
+            ui.columns(1, im_str!("pipeline_statistics"), false);
+        });
+}
+
+pub fn show_shader_error_overlay<'a>(ui: &imgui::Ui<'a>, shader_errors: &mut Option<String>) {
+    use imgui::*;
+
+    let errors = match shader_errors {
+        Some(errors) => errors,
+        None => return,
+    };
+
+    let mut dismissed = false;
+    Window::new(im_str!("Shader errors"))
+        .size([700.0, 300.0], Condition::FirstUseEver)
+        .position([20.0, 20.0], Condition::FirstUseEver)
+        .build(ui, || {
+            ui.text_wrapped(im_str!(
+                "Shader compilation failed, previous shaders are still in use. Fix the errors and press F5 to reload."
+            ));
+            if ui.button(im_str!("Dismiss"), [0.0, 0.0]) {
+                dismissed = true;
+            }
+            ui.separator();
+
+            ChildWindow::new("shader_error_messages")
+                .horizontal_scrollbar(true)
+                .build(ui, || {
+                    for line in errors.lines() {
+                        match parse_shader_error_line(line) {
+                            Some((file, line_number, message)) => {
+                                ui.text(ImString::from(format!("{}:{}", file, line_number)));
+                                let color = if message.starts_with("warning") {
+                                    [1.0, 0.8, 0.3, 1.0]
+                                } else {
+                                    [1.0, 0.4, 0.4, 1.0]
+                                };
+                                ui.text_colored(color, ImString::from(format!("    {}", message)));
+                            }
+                            None => ui.text(line),
+                        }
+                    }
+                });
+        });
+    if dismissed {
+        *shader_errors = None;
+    }
+}
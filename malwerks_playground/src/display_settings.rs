@@ -0,0 +1,61 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum WindowMode {
+    Windowed,
+    Borderless,
+    Exclusive,
+}
+
+impl WindowMode {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "borderless" => WindowMode::Borderless,
+            "exclusive" => WindowMode::Exclusive,
+            _ => WindowMode::Windowed,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DisplaySettings {
+    pub vsync: bool,
+    pub window_mode: WindowMode,
+}
+
+// Exclusive fullscreen uses the video mode of the monitor with the highest refresh rate,
+// it falls back to borderless fullscreen if the platform doesn't report any video modes
+pub fn apply_window_mode(window: &winit::window::Window, window_mode: WindowMode) {
+    use winit::window::Fullscreen;
+
+    let fullscreen = match window_mode {
+        WindowMode::Windowed => None,
+        WindowMode::Borderless => Some(Fullscreen::Borderless(window.current_monitor())),
+        WindowMode::Exclusive => {
+            let monitor = window.current_monitor().expect("current_monitor() failed");
+            let monitor_size = monitor.size();
+            let video_mode = monitor
+                .video_modes()
+                .filter(|video_mode| video_mode.size() == monitor_size)
+                .max_by_key(|video_mode| (video_mode.refresh_rate(), video_mode.bit_depth()));
+            match video_mode {
+                Some(video_mode) => {
+                    log::info!(
+                        "exclusive fullscreen video mode: {:?} {}Hz",
+                        video_mode.size(),
+                        video_mode.refresh_rate()
+                    );
+                    Some(Fullscreen::Exclusive(video_mode))
+                }
+                None => {
+                    log::warn!("exclusive fullscreen is not supported, using borderless fullscreen");
+                    Some(Fullscreen::Borderless(Some(monitor)))
+                }
+            }
+        }
+    };
+    window.set_fullscreen(fullscreen);
+}
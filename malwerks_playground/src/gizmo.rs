@@ -0,0 +1,309 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_render::*;
+use ultraviolet as utv;
+
+// Screen space sizes in pixels
+const GIZMO_SIZE: f32 = 100.0;
+const GIZMO_PICK_RADIUS: f32 = 8.0;
+const NODE_PICK_RADIUS: f32 = 16.0;
+const ROTATION_CIRCLE_SEGMENTS: usize = 32;
+
+const AXIS_COLORS: [[f32; 4]; 3] = [[1.0, 0.2, 0.2, 1.0], [0.2, 1.0, 0.2, 1.0], [0.3, 0.3, 1.0, 1.0]];
+const ACTIVE_AXIS_COLOR: [f32; 4] = [1.0, 1.0, 0.2, 1.0];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+struct GizmoDrag {
+    axis: usize,
+    start_mouse_position: [f32; 2],
+    start_local_transform: [f32; 16],
+}
+
+// Gizmo axes are the world axes through the node origin, edits are written back to the scene node of the bundle
+pub struct Gizmo {
+    mode: GizmoMode,
+    selection: Option<(String, usize)>, // bundle name, scene node
+    drag: Option<GizmoDrag>,
+}
+
+impl Gizmo {
+    pub fn new() -> Self {
+        Self {
+            mode: GizmoMode::Translate,
+            selection: None,
+            drag: None,
+        }
+    }
+
+    pub fn show<'a>(&mut self, ui: &imgui::Ui<'a>, camera: &Camera, pbr_forward_lit: &mut PbrForwardLit) {
+        puffin::profile_function!();
+        self.show_window(ui, pbr_forward_lit);
+
+        let (view_projection, _) = camera.calculate_view_projection([0.0, 0.0]);
+        let display_size = ui.io().display_size;
+        let project = |position: utv::vec::Vec3| {
+            let clip_position = view_projection * position.into_homogeneous_point();
+            if clip_position.w > CAMERA_Z_NEAR {
+                Some((
+                    [
+                        (0.5 + 0.5 * clip_position.x / clip_position.w) * display_size[0],
+                        (0.5 + 0.5 * clip_position.y / clip_position.w) * display_size[1],
+                    ],
+                    clip_position.w,
+                ))
+            } else {
+                None
+            }
+        };
+
+        // Selection may refer to a bundle that has been removed since the last frame
+        let selected_node = match &self.selection {
+            Some((bundle_name, node_id)) => pbr_forward_lit
+                .get_render_bundles()
+                .iter()
+                .find(|(name, _, _, _)| name == bundle_name)
+                .filter(|(_, bundle, _, _)| *node_id < bundle.borrow().scene_nodes.len())
+                .map(|(_, bundle, _, _)| {
+                    let bundle = bundle.borrow();
+                    let parent_transform = match bundle.scene_nodes[*node_id].parent {
+                        Some(parent) => bundle.get_scene_node_world_transform(parent),
+                        None => *utv::mat::Mat4::identity().as_array(),
+                    };
+                    (
+                        utv::mat::Mat4::from(parent_transform),
+                        bundle.scene_nodes[*node_id].local_transform,
+                    )
+                }),
+            None => None,
+        };
+        if selected_node.is_none() {
+            self.selection = None;
+            self.drag = None;
+        }
+
+        let mouse_position = ui.io().mouse_pos;
+        let mouse_captured = ui.io().want_capture_mouse && self.drag.is_none();
+        let draw_list = ui.get_background_draw_list();
+
+        let mut hovered_axis = None;
+        if let Some((parent_transform, local_transform)) = selected_node {
+            let world_transform = parent_transform * utv::mat::Mat4::from(local_transform);
+            let origin = world_transform.cols[3].xyz();
+            if let Some((screen_origin, depth)) = project(origin) {
+                // Handles keep the same size on screen
+                let handle_length = GIZMO_SIZE * depth / display_size[1];
+                for axis in 0..3 {
+                    let mut axis_direction = utv::vec::Vec3::zero();
+                    axis_direction[axis] = 1.0;
+
+                    let handle_points: Vec<[f32; 2]> = match self.mode {
+                        GizmoMode::Translate | GizmoMode::Scale => vec![
+                            screen_origin,
+                            project(origin + axis_direction * handle_length)
+                                .map(|(position, _)| position)
+                                .unwrap_or(screen_origin),
+                        ],
+                        GizmoMode::Rotate => {
+                            let mut tangent = utv::vec::Vec3::zero();
+                            tangent[(axis + 1) % 3] = 1.0;
+                            let bitangent = axis_direction.cross(tangent);
+                            (0..=ROTATION_CIRCLE_SEGMENTS)
+                                .filter_map(|segment| {
+                                    let angle =
+                                        segment as f32 * 2.0 * std::f32::consts::PI / ROTATION_CIRCLE_SEGMENTS as f32;
+                                    project(origin + (tangent * angle.cos() + bitangent * angle.sin()) * handle_length)
+                                        .map(|(position, _)| position)
+                                })
+                                .collect()
+                        }
+                    };
+
+                    let is_hovered = handle_points
+                        .windows(2)
+                        .any(|segment| distance_to_segment(mouse_position, segment[0], segment[1]) < GIZMO_PICK_RADIUS);
+                    if is_hovered && hovered_axis.is_none() {
+                        hovered_axis = Some(axis);
+                    }
+
+                    let is_active = match &self.drag {
+                        Some(drag) => drag.axis == axis,
+                        None => is_hovered && hovered_axis == Some(axis),
+                    };
+                    let color = if is_active {
+                        ACTIVE_AXIS_COLOR
+                    } else {
+                        AXIS_COLORS[axis]
+                    };
+                    for segment in handle_points.windows(2) {
+                        draw_list.add_line(segment[0], segment[1], color).thickness(2.0).build();
+                    }
+                    if self.mode == GizmoMode::Scale {
+                        if let Some(end_point) = handle_points.last() {
+                            draw_list.add_circle(*end_point, 4.0, color).filled(true).build();
+                        }
+                    }
+                }
+
+                if let Some(drag) = &self.drag {
+                    let mut axis_direction = utv::vec::Vec3::zero();
+                    axis_direction[drag.axis] = 1.0;
+                    let mouse_delta = [
+                        mouse_position[0] - drag.start_mouse_position[0],
+                        mouse_position[1] - drag.start_mouse_position[1],
+                    ];
+
+                    // Mouse movement along the projected axis maps to world units of the handle
+                    let screen_axis = project(origin + axis_direction * handle_length)
+                        .map(|(position, _)| [position[0] - screen_origin[0], position[1] - screen_origin[1]])
+                        .unwrap_or([0.0, 0.0]);
+                    let screen_axis_length_squared = screen_axis[0] * screen_axis[0] + screen_axis[1] * screen_axis[1];
+                    let axis_amount = if screen_axis_length_squared > 1.0 {
+                        (mouse_delta[0] * screen_axis[0] + mouse_delta[1] * screen_axis[1]) / screen_axis_length_squared
+                    } else {
+                        0.0
+                    };
+
+                    let start_world_transform = parent_transform * utv::mat::Mat4::from(drag.start_local_transform);
+                    let start_origin = start_world_transform.cols[3].xyz();
+                    let world_edit = match self.mode {
+                        GizmoMode::Translate => {
+                            utv::mat::Mat4::from_translation(axis_direction * axis_amount * handle_length)
+                        }
+                        GizmoMode::Rotate => {
+                            // Positive rotation is clockwise on screen when the axis points away from the camera
+                            let start_angle = (drag.start_mouse_position[1] - screen_origin[1])
+                                .atan2(drag.start_mouse_position[0] - screen_origin[0]);
+                            let angle =
+                                (mouse_position[1] - screen_origin[1]).atan2(mouse_position[0] - screen_origin[0]);
+                            let camera_position = -camera.position;
+                            let sign = if axis_direction.dot(start_origin - camera_position) > 0.0 {
+                                1.0
+                            } else {
+                                -1.0
+                            };
+                            let rotation = match drag.axis {
+                                0 => utv::mat::Mat4::from_rotation_x(sign * (angle - start_angle)),
+                                1 => utv::mat::Mat4::from_rotation_y(sign * (angle - start_angle)),
+                                _ => utv::mat::Mat4::from_rotation_z(sign * (angle - start_angle)),
+                            };
+                            utv::mat::Mat4::from_translation(start_origin)
+                                * rotation
+                                * utv::mat::Mat4::from_translation(-start_origin)
+                        }
+                        GizmoMode::Scale => {
+                            let mut scale = utv::vec::Vec3::one();
+                            scale[drag.axis] = (1.0 + axis_amount).max(0.01);
+                            utv::mat::Mat4::from_translation(start_origin)
+                                * utv::mat::Mat4::from_nonuniform_scale(scale)
+                                * utv::mat::Mat4::from_translation(-start_origin)
+                        }
+                    };
+
+                    // World space edit is moved into the parent space of the node
+                    let new_local_transform = parent_transform.inversed()
+                        * world_edit
+                        * parent_transform
+                        * utv::mat::Mat4::from(drag.start_local_transform);
+                    if *new_local_transform.as_array() != local_transform {
+                        let (bundle_name, node_id) = self.selection.as_ref().unwrap();
+                        pbr_forward_lit.set_scene_node_transform(bundle_name, *node_id, new_local_transform.as_array());
+                    }
+                }
+            }
+
+            if !ui.is_mouse_down(imgui::MouseButton::Left) {
+                self.drag = None;
+            } else if self.drag.is_none() && !mouse_captured && ui.is_mouse_clicked(imgui::MouseButton::Left) {
+                if let Some(axis) = hovered_axis {
+                    self.drag = Some(GizmoDrag {
+                        axis,
+                        start_mouse_position: mouse_position,
+                        start_local_transform: local_transform,
+                    });
+                    return;
+                }
+            }
+        }
+
+        // Nodes are picked by their origin, the closest one to the camera wins
+        if !mouse_captured && self.drag.is_none() && ui.is_mouse_clicked(imgui::MouseButton::Left) {
+            let mut picked_node = None;
+            let mut picked_depth = f32::INFINITY;
+            for (bundle_name, bundle, _, _) in pbr_forward_lit.get_render_bundles() {
+                let bundle = bundle.borrow();
+                for node_id in 0..bundle.scene_nodes.len() {
+                    let world_transform = utv::mat::Mat4::from(bundle.get_scene_node_world_transform(node_id));
+                    if let Some((screen_position, depth)) = project(world_transform.cols[3].xyz()) {
+                        let distance = [
+                            screen_position[0] - mouse_position[0],
+                            screen_position[1] - mouse_position[1],
+                        ];
+                        if distance[0] * distance[0] + distance[1] * distance[1] < NODE_PICK_RADIUS * NODE_PICK_RADIUS
+                            && depth < picked_depth
+                        {
+                            picked_node = Some((bundle_name.clone(), node_id));
+                            picked_depth = depth;
+                        }
+                    }
+                }
+            }
+            self.selection = picked_node;
+        }
+    }
+
+    fn show_window<'a>(&mut self, ui: &imgui::Ui<'a>, pbr_forward_lit: &PbrForwardLit) {
+        use imgui::*;
+
+        Window::new(im_str!("Scene editor"))
+            .always_auto_resize(true)
+            .build(ui, || {
+                ui.radio_button(im_str!("Translate"), &mut self.mode, GizmoMode::Translate);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("Rotate"), &mut self.mode, GizmoMode::Rotate);
+                ui.same_line(0.0);
+                ui.radio_button(im_str!("Scale"), &mut self.mode, GizmoMode::Scale);
+                ui.text_wrapped(im_str!(
+                    "Left click selects the node closest to the mouse cursor, drag gizmo axes to edit it"
+                ));
+
+                for (bundle_name, bundle, _, _) in pbr_forward_lit.get_render_bundles() {
+                    let bundle = bundle.borrow();
+                    if bundle.scene_nodes.is_empty() {
+                        continue;
+                    }
+                    if CollapsingHeader::new(&ImString::from(format!("Nodes of {}", bundle_name))).build(ui) {
+                        for (node_id, node) in bundle.scene_nodes.iter().enumerate() {
+                            let is_selected = self.selection.as_ref() == Some(&(bundle_name.clone(), node_id));
+                            let label =
+                                ImString::from(format!("{} ({})##{}{}", node.name, node_id, bundle_name, node_id));
+                            if Selectable::new(&label).selected(is_selected).build(ui) {
+                                self.selection = Some((bundle_name.clone(), node_id));
+                                self.drag = None;
+                            }
+                        }
+                    }
+                }
+            });
+    }
+}
+
+fn distance_to_segment(point: [f32; 2], a: [f32; 2], b: [f32; 2]) -> f32 {
+    let segment = [b[0] - a[0], b[1] - a[1]];
+    let segment_length_squared = segment[0] * segment[0] + segment[1] * segment[1];
+    let t = if segment_length_squared > 0.0 {
+        (((point[0] - a[0]) * segment[0] + (point[1] - a[1]) * segment[1]) / segment_length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let closest = [a[0] + segment[0] * t - point[0], a[1] + segment[1] * t - point[1]];
+    (closest[0] * closest[0] + closest[1] * closest[1]).sqrt()
+}
@@ -5,7 +5,7 @@
 
 use std::collections::HashMap;
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum InputActionType {
     CameraMove,
     CameraStrafe,
@@ -14,7 +14,7 @@ pub enum InputActionType {
     CameraRotateY,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct InputAction {
     pub action_type: InputActionType,
     pub action_value: f32,
@@ -0,0 +1,102 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::input_map::InputAction;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RecordedFrame {
+    frame: u64,
+    actions: Vec<InputAction>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RecordedInput {
+    frame_count: u64,
+    frames: Vec<RecordedFrame>, // only frames with actions, sorted by frame
+}
+
+// Input actions keyed by simulation frame, replays are only deterministic with a fixed time step
+pub struct InputRecording {
+    input_file: std::path::PathBuf,
+    recorded_input: RecordedInput,
+    replay: bool,
+    next_frame: usize,
+}
+
+impl InputRecording {
+    pub fn record(input_file: &std::path::Path) -> Self {
+        Self {
+            input_file: input_file.to_path_buf(),
+            recorded_input: RecordedInput {
+                frame_count: 0,
+                frames: Vec::new(),
+            },
+            replay: false,
+            next_frame: 0,
+        }
+    }
+
+    pub fn replay(input_file: &std::path::Path) -> Self {
+        let file = std::fs::File::open(input_file).expect("failed to open input recording");
+        let recorded_input: RecordedInput =
+            serde_json::from_reader(std::io::BufReader::new(file)).expect("failed to parse input recording");
+        log::info!(
+            "replaying {} frames of input from {:?}",
+            recorded_input.frame_count,
+            input_file
+        );
+
+        Self {
+            input_file: input_file.to_path_buf(),
+            recorded_input,
+            replay: true,
+            next_frame: 0,
+        }
+    }
+
+    // Live actions are recorded, or replaced by the recorded ones when replaying
+    pub fn process_frame(&mut self, frame: u64, actions: Vec<InputAction>) -> Vec<InputAction> {
+        if self.replay {
+            let frames = &self.recorded_input.frames;
+            if self.next_frame < frames.len() && frames[self.next_frame].frame == frame {
+                self.next_frame += 1;
+                frames[self.next_frame - 1].actions.clone()
+            } else {
+                Vec::new()
+            }
+        } else {
+            self.recorded_input.frame_count = frame + 1;
+            if !actions.is_empty() {
+                self.recorded_input.frames.push(RecordedFrame {
+                    frame,
+                    actions: actions.clone(),
+                });
+            }
+            actions
+        }
+    }
+
+    pub fn is_replay_finished(&self, frame: u64) -> bool {
+        self.replay && frame >= self.recorded_input.frame_count
+    }
+
+    pub fn save(&self) {
+        if self.replay {
+            return;
+        }
+        match std::fs::File::create(&self.input_file) {
+            Ok(file) => {
+                serde_json::to_writer(std::io::BufWriter::new(file), &self.recorded_input)
+                    .expect("failed to write input recording");
+                log::info!(
+                    "{} frames of input saved to {:?}",
+                    self.recorded_input.frame_count,
+                    &self.input_file
+                );
+            }
+            Err(error) => log::error!("failed to create {:?}: {:?}", &self.input_file, error),
+        }
+    }
+}
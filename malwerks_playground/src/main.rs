@@ -3,16 +3,30 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
 // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+mod benchmark;
 mod camera_state;
+mod console;
 mod debug_ui;
+mod display_settings;
+mod gizmo;
 mod imgui_winit;
 mod input_map;
+mod input_recording;
+mod material_browser;
+mod profiler_export;
+mod scene_tabs;
+mod settings;
+mod simulation_thread;
 
 mod surface_pass;
 mod surface_winit;
+mod ui_scale;
 
 use malwerks_render::*;
 use malwerks_vk::*;
+use malwerks_xr::*;
+
+use std::sync::{Arc, Mutex};
 
 #[derive(Debug, structopt::StructOpt)]
 #[structopt(name = "malwerks_playground", about = "Playground application")]
@@ -49,8 +63,242 @@ struct CommandLineOptions {
     )]
     force_compile_shaders: bool,
 
+    #[structopt(
+        long = "pack_mesh_geometry",
+        help = "Packs geometry of all meshes in a bundle into one vertex and one index buffer when loading"
+    )]
+    pack_mesh_geometry: bool,
+
+    #[structopt(
+        long = "vertex_pulling",
+        help = "Fetches vertex attributes from storage buffers in vertex shaders instead of using vertex input"
+    )]
+    vertex_pulling: bool,
+
+    #[structopt(
+        long = "compress_environment_probes",
+        help = "Compresses environment probes convolved on the GPU to BC6H, needs texconv"
+    )]
+    compress_environment_probes: bool,
+
+    #[structopt(
+        long = "shader_debug_printf",
+        help = "Enables debugPrintfEXT in shaders and shows the messages in the shader console, requires validation"
+    )]
+    shader_debug_printf: bool,
+
+    #[structopt(
+        long = "stress",
+        default_value = "0",
+        help = "Replaces loaded scenes with a grid of this many instances of one mesh, used for performance testing"
+    )]
+    stress_instance_count: usize,
+
+    #[structopt(
+        long = "stress_mesh",
+        default_value = "0",
+        help = "Mesh of the loaded scene that is instanced by --stress"
+    )]
+    stress_mesh: usize,
+
+    #[structopt(
+        long = "track_resources",
+        help = "Logs creation and destruction of GPU resources and reports the ones that leak on shutdown"
+    )]
+    track_resources: bool,
+
     #[structopt(long = "no_anti_aliasing", help = "Disables anti-aliasing filters completely")]
     no_anti_aliasing: bool,
+
+    #[structopt(
+        long = "console_stdin",
+        help = "Executes console commands read from stdin, one command per line"
+    )]
+    console_stdin: bool,
+
+    #[structopt(
+        long = "enable_dynamic_rendering",
+        help = "Uses VK_KHR_dynamic_rendering when supported, falls back to render passes otherwise"
+    )]
+    enable_dynamic_rendering: bool,
+
+    #[structopt(
+        long = "enable_push_descriptors",
+        help = "Uses VK_KHR_push_descriptor for per-instance bindings when supported"
+    )]
+    enable_push_descriptors: bool,
+
+    #[structopt(
+        long = "enable_conditional_rendering",
+        help = "Uses VK_EXT_conditional_rendering to skip per-instance draws of culled instances when supported"
+    )]
+    enable_conditional_rendering: bool,
+
+    #[structopt(
+        long = "enable_buffer_device_address",
+        help = "Uses VK_KHR_buffer_device_address to read vertex data through GPU pointers when supported"
+    )]
+    enable_buffer_device_address: bool,
+
+    #[structopt(
+        long = "disable_resizable_bar",
+        help = "Keeps per frame buffers in host visible memory even if resizable BAR is available"
+    )]
+    disable_resizable_bar: bool,
+
+    #[structopt(
+        long = "enable_multiview",
+        help = "Uses VK_KHR_multiview to render an additional stereo view of the scene when supported"
+    )]
+    enable_multiview: bool,
+
+    #[structopt(
+        long = "enable_pipeline_statistics",
+        help = "Collects vertex, fragment and compute shader invocation counts of every pass when supported"
+    )]
+    enable_pipeline_statistics: bool,
+
+    #[structopt(
+        long = "enable_shader_clock",
+        help = "Uses VK_KHR_shader_clock to show the fragment cost heatmap of the forward pass when supported"
+    )]
+    enable_shader_clock: bool,
+
+    #[structopt(
+        long = "enable_graphics_pipeline_library",
+        help = "Uses VK_EXT_graphics_pipeline_library to link material pipelines from shared partial pipelines when supported"
+    )]
+    enable_graphics_pipeline_library: bool,
+
+    #[structopt(
+        long = "enable_ray_tracing",
+        help = "Requires VK_NV_ray_tracing and enables the reference path tracer"
+    )]
+    enable_ray_tracing: bool,
+
+    #[structopt(
+        long = "enable_xr",
+        help = "Presents the stereo view to an OpenXR headset, falls back to the window only when there is no runtime"
+    )]
+    enable_xr: bool,
+
+    #[structopt(
+        long = "recording_threads",
+        default_value = "0",
+        help = "Number of worker threads recording the forward pass, zero records everything on the render thread"
+    )]
+    num_recording_threads: usize,
+
+    #[structopt(
+        long = "buffered_frames",
+        default_value = "3",
+        help = "Number of frames in flight, has to fit into swapchain image count limits"
+    )]
+    num_buffered_frames: usize,
+
+    #[structopt(
+        long = "transparency",
+        default_value = "weighted_blended",
+        possible_values = &["disabled", "weighted_blended", "linked_lists"],
+        help = "Order-independent transparency mode for alpha blended materials"
+    )]
+    transparency_mode: String,
+
+    #[structopt(
+        long = "hdr_format",
+        default_value = "r11g11b10",
+        possible_values = &["r11g11b10", "rgba16f"],
+        help = "Format of the HDR color target"
+    )]
+    hdr_format: String,
+
+    #[structopt(
+        long = "vsync",
+        help = "Waits for vertical blank when presenting, overrides the settings file, can be toggled with F10"
+    )]
+    vsync: bool,
+
+    #[structopt(
+        long = "window_mode",
+        possible_values = &["windowed", "borderless", "exclusive"],
+        help = "Initial window mode, overrides the settings file, F11 toggles between windowed and borderless fullscreen"
+    )]
+    window_mode: Option<String>,
+
+    #[structopt(
+        long = "benchmark",
+        help = "Flies the camera along a fixed path through the benchmark scene and exits when done"
+    )]
+    benchmark: bool,
+
+    #[structopt(
+        long = "benchmark_scene",
+        default_value = "sponza/Sponza.gltf",
+        help = "glTF file loaded in benchmark mode, relative to the assets folder"
+    )]
+    benchmark_scene: std::path::PathBuf,
+
+    #[structopt(
+        long = "benchmark_duration",
+        default_value = "30",
+        help = "Benchmark length in seconds of simulated time at 60 frames per second"
+    )]
+    benchmark_duration: f32,
+
+    #[structopt(
+        long = "benchmark_report",
+        default_value = "./benchmark_report.csv",
+        help = "Per-frame benchmark report, written as JSON if the file has a .json extension",
+        parse(from_os_str)
+    )]
+    benchmark_report: std::path::PathBuf,
+
+    #[structopt(
+        long = "tiled_capture",
+        help = "Renders a WIDTHxHEIGHT capture in window sized tiles, one per frame, saves it and exits"
+    )]
+    tiled_capture: Option<String>,
+
+    #[structopt(
+        long = "tiled_capture_output",
+        default_value = "./tiled_capture.dds",
+        help = "File the tiled capture is saved to, contains the HDR image before tone mapping",
+        parse(from_os_str)
+    )]
+    tiled_capture_output: std::path::PathBuf,
+
+    #[structopt(
+        long = "fixed_time_step",
+        help = "Advances the simulation by this many seconds every frame instead of real time and starts from the default camera, for deterministic runs"
+    )]
+    fixed_time_step: Option<f32>,
+
+    #[structopt(
+        long = "record_input",
+        help = "Records input actions of every frame to a JSON file",
+        parse(from_os_str)
+    )]
+    record_input: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long = "replay_input",
+        help = "Replays recorded input actions instead of live input and exits when done, use with --fixed_time_step",
+        parse(from_os_str)
+    )]
+    replay_input: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long = "render_on_demand",
+        help = "Renders only when the camera moves, the UI is used or a console command runs, animations are paused while idle"
+    )]
+    render_on_demand: bool,
+
+    #[structopt(
+        long = "asset_cache_budget",
+        default_value = "1024",
+        help = "Size of shader caches, pipeline caches and thumbnails kept in the temporary folder, in megabytes"
+    )]
+    asset_cache_budget: u64,
 }
 
 struct Game {
@@ -60,95 +308,169 @@ struct Game {
 
     surface: surface_winit::SurfaceWinit,
     surface_pass: surface_pass::SurfacePass,
+    surface_size: winit::dpi::PhysicalSize<u32>, // window size the renderer was created with
+    settings: settings::Settings,
+    requested_settings: settings::Settings, // applied before the next frame
 
     imgui: imgui::Context,
     imgui_platform: imgui_winit::WinitPlatform,
     imgui_renderer: ImguiRenderer,
+    imgui_base_style: imgui::Style, // ImGui style before UI scaling
+    ui_scale: f32,
+    scale_factor: f64, // reported by the window, changes when it moves to another monitor
     profiler_ui: puffin_imgui::ProfilerUi,
+    profiler_export: profiler_export::ProfilerExport,
 
     bundle_loader: BundleLoader,
     pbr_forward_lit: PbrForwardLit,
+    gizmo: gizmo::Gizmo,
+    material_browser: material_browser::MaterialBrowser,
+    scene_tabs: scene_tabs::SceneTabs,
+    console: console::Console,
+    console_commands: CommandRegistry<Game>,
 
     frame_time: std::time::Instant,
     input_map: input_map::InputMap,
-    camera_state: camera_state::CameraState,
+    camera_state: Arc<Mutex<camera_state::CameraState>>,
+    simulation_thread: simulation_thread::SimulationThread,
+    pending_actions: Vec<input_map::InputAction>,
+    input_recording: Option<input_recording::InputRecording>,
+    simulation_frame: u64,
+    simulation_time: f32, // seconds of simulated time, drives animations with a fixed time step
+    benchmark: Option<benchmark::Benchmark>,
+    tiled_capture: Option<TiledCapture>,
+    redraw_tracker: Option<RedrawTracker>, // only used in render on demand mode
+    shader_errors: Option<String>,         // log of the last failed shader reload
+    gpu_pass_statistics: Vec<(&'static str, PipelineStatistics)>, // last available, empty if disabled
+
+    xr_context: Option<XrContext>,
+    xr_session: Option<XrSession>,
 
     command_line: CommandLineOptions,
 }
 
 impl Drop for Game {
     fn drop(&mut self) {
-        self.queue.wait_idle();
-        self.device.wait_idle();
-
-        self.imgui_renderer.destroy(&mut self.factory);
-
-        self.pbr_forward_lit.destroy(&mut self.factory);
-        self.bundle_loader.destroy(&mut self.factory);
-
-        self.surface_pass.destroy(&mut self.factory);
-        self.surface.destroy(&mut self.factory);
-
-        self.queue.wait_idle();
-        self.device.wait_idle();
+        self.simulation_thread.destroy();
+        if let Some(benchmark) = &self.benchmark {
+            benchmark.write_report();
+        }
+        if let Some(input_recording) = &self.input_recording {
+            input_recording.save();
+        }
+        if let Some(tiled_capture) = self.tiled_capture.take() {
+            if tiled_capture.is_finished() {
+                let output_image = tiled_capture.finish(self.camera_state.lock().unwrap().get_camera_mut());
+                output_image.save_to_file(&self.command_line.tiled_capture_output);
+                log::info!("tiled capture saved to {:?}", &self.command_line.tiled_capture_output);
+            }
+        }
+        self.destroy_device_resources();
+        if let Some(xr_context) = &mut self.xr_context {
+            xr_context.destroy();
+        }
     }
 }
 
 impl Game {
-    fn new(window: &winit::window::Window, base_path: &std::path::Path, command_line: CommandLineOptions) -> Self {
-        let instance_extensions = ash_window::enumerate_required_extensions(window).expect("no window extensions");
-        let device_extensions = [ash::extensions::khr::Swapchain::name()];
-
-        let mut device = Device::new(
-            &instance_extensions,
-            &device_extensions,
-            |entry: &ash::Entry, instance: &ash::Instance| {
-                surface_winit::create_surface(entry, instance, window).expect("failed to create KHR surface")
-            },
-            DeviceOptions {
-                enable_validation: command_line.enable_validation,
-                // enable_ray_tracing_nv: true,
-                ..Default::default()
-            },
-        );
+    fn new(
+        window: &winit::window::Window,
+        base_path: &std::path::Path,
+        command_line: CommandLineOptions,
+        settings: settings::Settings,
+    ) -> Self {
+        let xr_context = if command_line.enable_xr {
+            XrContext::new("malwerks_playground")
+        } else {
+            None
+        };
+        let mut device = create_device(window, &command_line, xr_context.as_ref());
         let mut queue = device.get_graphics_queue();
         let mut factory = device.create_factory();
 
-        let surface = surface_winit::SurfaceWinit::new(&device);
+        let surface = surface_winit::SurfaceWinit::new(&device, settings.display.vsync);
         let surface_pass = surface_pass::SurfacePass::new(&surface, &device, &mut factory);
         let surface_size = window.inner_size();
 
         log::info!("surface size: {:?}", surface_size);
 
-        let mut bundle_loader = BundleLoader::new(
-            &BundleLoaderParameters {
-                bundle_compression_level: command_line.compression_level,
-                temporary_folder: &command_line.assets_folder.join("temporary_folder"),
-                base_path,
-                shader_bundle_path: &command_line.assets_folder.join("common_shaders.bundle"),
-                pbr_resource_folder: &command_line.assets_folder.join("pbr_resources"),
-                force_import_bundles: command_line.force_import_bundles,
-                force_compile_shaders: command_line.force_compile_shaders,
-            },
+        let mut bundle_loader = create_bundle_loader(base_path, &command_line, &device, &mut factory, &mut queue);
+        let mut pbr_forward_lit = create_pbr_forward_lit(
+            window,
+            &command_line,
+            &surface_pass,
+            &bundle_loader,
             &device,
             &mut factory,
-            &mut queue,
         );
+        settings.apply_renderer_settings(None, &mut pbr_forward_lit, &device, &mut factory);
+
+        let benchmark = if command_line.benchmark {
+            let scene_name = command_line
+                .benchmark_scene
+                .to_str()
+                .expect("failed to convert benchmark scene path to str");
+            let bundle_file = command_line.benchmark_scene.with_extension("resource_bundle");
+            pbr_forward_lit.add_render_bundle(
+                scene_name,
+                &mut bundle_loader,
+                &command_line.assets_folder.join(&command_line.benchmark_scene),
+                &command_line
+                    .assets_folder
+                    .join(bundle_file.file_name().expect("benchmark scene is not a file")),
+                &base_path.join("malwerks_shaders").join("gltf_pbr_material.glsl"),
+                &device,
+                &mut factory,
+                &mut queue,
+            );
+            Some(benchmark::Benchmark::new(
+                command_line.benchmark_duration,
+                &command_line.benchmark_report,
+            ))
+        } else {
+            None
+        };
 
-        let pbr_forward_lit = PbrForwardLit::new(
-            &PbrForwardLitParameters {
-                render_width: surface_size.width,
-                render_height: surface_size.height,
-                target_layer: Some(surface_pass.get_render_layer()),
-                bundle_loader: &bundle_loader,
-                enable_anti_aliasing: !command_line.no_anti_aliasing,
-            },
-            &device,
-            &mut factory,
-        );
+        // Tiles are rendered at full resolution without temporal jitter, so they line up when stitched
+        let tiled_capture = command_line.tiled_capture.as_ref().map(|capture_size| {
+            let (output_width, output_height) =
+                parse_capture_size(capture_size).expect("tiled capture size has to be WIDTHxHEIGHT");
+            pbr_forward_lit.debug_enable_anti_aliasing(false);
+            pbr_forward_lit.set_adaptive_resolution(None);
+            pbr_forward_lit.set_resolution_scale(1.0);
+            TiledCapture::new(&TiledCaptureParameters {
+                output_width,
+                output_height,
+                tile_width: surface_size.width,
+                tile_height: surface_size.height,
+                hdr_color_format: pbr_forward_lit.get_hdr_color_format(),
+            })
+        });
+
+        // Headset is rendered without temporal jitter, each eye has its own projection
+        let xr_session = xr_context.as_ref().map(|xr_context| {
+            pbr_forward_lit.debug_enable_anti_aliasing(false);
+            pbr_forward_lit.set_adaptive_resolution(None);
+            pbr_forward_lit.set_resolution_scale(1.0);
+            pbr_forward_lit.set_stereo_view(Some(0.0));
+            XrSession::new(xr_context, &device, &mut factory)
+        });
+
+        let mut console_commands = CommandRegistry::new();
+        console::register_playground_commands(&mut console_commands);
 
         let mut imgui = imgui::Context::create();
         let mut imgui_platform = imgui_winit::WinitPlatform::init(&mut imgui);
+
+        // UI is scaled by the application, ImGui coordinates are physical pixels
+        imgui_platform.attach_window(imgui.io_mut(), &window, imgui_winit::HiDpiMode::Locked(1.0));
+        imgui.io_mut().config_flags |= imgui::ConfigFlags::NO_MOUSE_CURSOR_CHANGE;
+        imgui.set_ini_filename(Some(base_path.join("target").join("imgui.ini")));
+
+        let imgui_base_style = *imgui.style();
+        let scale_factor = window.scale_factor();
+        let ui_scale = ui_scale::get_ui_scale(settings.ui_scale, scale_factor);
+        ui_scale::apply_ui_scale(&mut imgui, &imgui_base_style, ui_scale);
         let imgui_renderer = bundle_loader.create_imgui_renderer(
             &mut imgui,
             surface_pass.get_render_layer(),
@@ -156,20 +478,12 @@ impl Game {
             &mut factory,
             &mut queue,
         );
-
-        let dpi = 1.0f32; //window.scale_factor() as f32;
-        imgui_platform.attach_window(imgui.io_mut(), &window, imgui_winit::HiDpiMode::Locked(dpi as f64));
-        imgui.io_mut().font_global_scale = dpi;
-        imgui.io_mut().config_flags |= imgui::ConfigFlags::NO_MOUSE_CURSOR_CHANGE;
-        imgui.fonts().add_font(&[imgui::FontSource::TtfData {
-            data: include_bytes!("../../assets/fonts/Roboto-Regular.ttf"),
-            size_pixels: 13.0 * dpi,
-            config: None,
-        }]);
-        imgui.set_ini_filename(Some(base_path.join("target").join("imgui.ini")));
+        let material_browser =
+            material_browser::MaterialBrowser::new(&bundle_loader, &device, &mut factory, &mut queue);
 
         puffin::set_scopes_on(true);
         let profiler_ui = puffin_imgui::ProfilerUi::default();
+        let profiler_export = profiler_export::ProfilerExport::new(&command_line.assets_folder.join("profiles"));
 
         let input_map = {
             use input_map::*;
@@ -200,56 +514,497 @@ impl Game {
             input_map
         };
 
+        let camera_cache_file = command_line
+            .assets_folder
+            .join("temporary_folder")
+            .join("camera_state.bin");
+
+        // Benchmark camera position is not saved, deterministic runs start from the default camera
+        let camera_state = Arc::new(Mutex::new(camera_state::CameraState::new(
+            if benchmark.is_none() && command_line.fixed_time_step.is_none() {
+                Some(&camera_cache_file)
+            } else {
+                None
+            },
+            Viewport {
+                x: 0,
+                y: 0,
+                width: surface_size.width,
+                height: surface_size.height,
+            },
+        )));
+        let simulation_thread = start_simulation_thread(&camera_state, &device);
+        let scene_tabs = scene_tabs::SceneTabs::new(camera_state.lock().unwrap().get_camera(), &settings);
+
+        // Deterministic runs advance by one time step per frame, they have to render every frame
+        let redraw_tracker = if command_line.render_on_demand && command_line.fixed_time_step.is_none() {
+            Some(RedrawTracker::new(DEFAULT_SETTLE_FRAME_COUNT))
+        } else {
+            None
+        };
+
         Self {
             device,
             factory,
             queue,
             surface,
             surface_pass,
+            surface_size,
+            settings,
+            requested_settings: settings,
             imgui,
             imgui_platform,
             imgui_renderer,
+            imgui_base_style,
+            ui_scale,
+            scale_factor,
             profiler_ui,
+            profiler_export,
             bundle_loader,
             pbr_forward_lit,
+            gizmo: gizmo::Gizmo::new(),
+            material_browser,
+            scene_tabs,
+            console: console::Console::new(command_line.console_stdin),
+            console_commands,
             frame_time: std::time::Instant::now(),
             input_map,
-            camera_state: camera_state::CameraState::new(
-                Some(
-                    &command_line
-                        .assets_folder
-                        .join("temporary_folder")
-                        .join("camera_state.bin"),
-                ),
-                Viewport {
-                    x: 0,
-                    y: 0,
-                    width: surface_size.width,
-                    height: surface_size.height,
-                },
-            ),
+            camera_state,
+            simulation_thread,
+            pending_actions: Vec::new(),
+            input_recording: match (&command_line.record_input, &command_line.replay_input) {
+                (_, Some(replay_file)) => Some(input_recording::InputRecording::replay(replay_file)),
+                (Some(record_file), None) => Some(input_recording::InputRecording::record(record_file)),
+                (None, None) => None,
+            },
+            simulation_frame: 0,
+            simulation_time: 0.0,
+            benchmark,
+            tiled_capture,
+            redraw_tracker,
+            shader_errors: None,
+            gpu_pass_statistics: Vec::new(),
+            xr_context,
+            xr_session,
             command_line,
         }
     }
 
+    fn destroy_device_resources(&mut self) {
+        self.queue.wait_idle();
+        self.device.wait_idle();
+
+        self.imgui_renderer.destroy(&mut self.factory);
+        self.material_browser.destroy(&mut self.factory);
+
+        if let (Some(xr_context), Some(xr_session)) = (&self.xr_context, &mut self.xr_session) {
+            xr_session.destroy(xr_context, &mut self.factory);
+        }
+        self.pbr_forward_lit.destroy(&mut self.factory);
+        self.bundle_loader.destroy(&mut self.factory);
+
+        self.surface_pass.destroy(&mut self.factory);
+        self.surface.destroy(&mut self.factory);
+
+        self.queue.wait_idle();
+        self.device.wait_idle();
+
+        self.factory.destroy();
+        self.device.destroy();
+    }
+
+    fn is_device_lost(&self) -> bool {
+        self.device.is_device_lost() || self.surface.is_lost()
+    }
+
+    // Everything that lives on the device is created again, loaded bundles are reloaded from their cached files.
+    // Renderer options that are not part of the settings are reset to defaults.
+    fn recover_lost_device(&mut self, window: &winit::window::Window, base_path: &std::path::Path) {
+        log::error!("device lost, recreating device resources");
+        match write_diagnostic_report(&self.command_line.assets_folder.join("crash_dumps"), "device lost") {
+            Ok(report_folder) => log::error!("diagnostic report written to {:?}", report_folder),
+            Err(error) => log::error!("failed to write diagnostic report: {:?}", error),
+        }
+
+        let render_bundles = self.get_loaded_render_bundles();
+        self.scene_tabs.release_resident_bundles();
+        self.destroy_device_resources();
+
+        self.device = create_device(window, &self.command_line, self.xr_context.as_ref());
+        self.queue = self.device.get_graphics_queue();
+        self.factory = self.device.create_factory();
+
+        // Frame indices start over with the new device
+        self.simulation_thread.destroy();
+        self.simulation_thread = start_simulation_thread(&self.camera_state, &self.device);
+
+        self.surface = surface_winit::SurfaceWinit::new(&self.device, self.settings.display.vsync);
+        self.surface_pass = surface_pass::SurfacePass::new(&self.surface, &self.device, &mut self.factory);
+
+        self.bundle_loader = create_bundle_loader(
+            base_path,
+            &self.command_line,
+            &self.device,
+            &mut self.factory,
+            &mut self.queue,
+        );
+        self.pbr_forward_lit = create_pbr_forward_lit(
+            window,
+            &self.command_line,
+            &self.surface_pass,
+            &self.bundle_loader,
+            &self.device,
+            &mut self.factory,
+        );
+        self.settings
+            .apply_renderer_settings(None, &mut self.pbr_forward_lit, &self.device, &mut self.factory);
+        self.restore_render_bundles(&render_bundles);
+        if let Some(xr_context) = &self.xr_context {
+            self.pbr_forward_lit.debug_enable_anti_aliasing(false);
+            self.pbr_forward_lit.set_adaptive_resolution(None);
+            self.pbr_forward_lit.set_resolution_scale(1.0);
+            self.pbr_forward_lit.set_stereo_view(Some(0.0));
+            self.xr_session = Some(XrSession::new(xr_context, &self.device, &mut self.factory));
+        }
+
+        self.imgui_renderer = self.bundle_loader.create_imgui_renderer(
+            &mut self.imgui,
+            self.surface_pass.get_render_layer(),
+            &mut self.device,
+            &mut self.factory,
+            &mut self.queue,
+        );
+        self.material_browser = material_browser::MaterialBrowser::new(
+            &self.bundle_loader,
+            &self.device,
+            &mut self.factory,
+            &mut self.queue,
+        );
+        self.frame_time = std::time::Instant::now();
+    }
+
+    fn get_loaded_render_bundles(&self) -> Vec<(String, RenderBundleFiles)> {
+        self.pbr_forward_lit
+            .get_render_bundles()
+            .iter()
+            .map(|(bundle_name, _, _, _)| bundle_name.clone())
+            .zip(self.pbr_forward_lit.get_render_bundle_files().iter().cloned())
+            .collect()
+    }
+
+    fn restore_render_bundles(&mut self, render_bundles: &[(String, RenderBundleFiles)]) {
+        for (bundle_name, render_bundle_files) in render_bundles {
+            self.pbr_forward_lit.add_render_bundle(
+                bundle_name,
+                &mut self.bundle_loader,
+                &render_bundle_files.gltf_file,
+                &render_bundle_files.bundle_file,
+                &render_bundle_files.shader_file,
+                &self.device,
+                &mut self.factory,
+                &mut self.queue,
+            );
+        }
+    }
+
+    // Tabs selected in the last frame are switched to before settings are applied, they may change settings
+    fn update_scene_tabs(&mut self) {
+        if let Some(tab_index) = self.scene_tabs.take_requested_tab() {
+            let tab_index = if tab_index == self.scene_tabs.get_tab_count() {
+                let camera = self.camera_state.lock().unwrap().get_camera().clone();
+                self.scene_tabs.add_tab(&camera, &self.requested_settings)
+            } else {
+                tab_index
+            };
+            if tab_index != self.scene_tabs.get_active_tab() {
+                self.switch_scene_tab(tab_index);
+            }
+        }
+        self.scene_tabs.remove_closed_tab();
+    }
+
+    // Render bundles of the active tab are removed and the selected tab's are added. Resource bundles that are
+    // still referenced, by the selected tab or by inactive tabs kept loaded, are not imported again.
+    fn switch_scene_tab(&mut self, tab_index: usize) {
+        let render_bundles = self.get_loaded_render_bundles();
+        let resident_bundles = if self.scene_tabs.keeps_inactive_resident() {
+            self.pbr_forward_lit
+                .get_render_bundles()
+                .iter()
+                .map(|(_, resource_bundle, _, _)| resource_bundle.clone())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        for (bundle_name, _) in &render_bundles {
+            self.pbr_forward_lit
+                .remove_render_bundle(bundle_name, &mut self.bundle_loader);
+        }
+
+        let camera = self.camera_state.lock().unwrap().get_camera().clone();
+        let tab = self.scene_tabs.switch_tab(
+            tab_index,
+            render_bundles,
+            resident_bundles,
+            &camera,
+            &self.requested_settings,
+        );
+        let render_bundles = std::mem::take(&mut tab.render_bundles);
+        let resident_bundles = std::mem::take(&mut tab.resident_bundles);
+        let (camera_position, camera_orientation, tab_settings) =
+            (tab.camera_position, tab.camera_orientation, tab.settings);
+
+        // Resident bundles are found by the bundle loader, the tab's references are dropped once they are added
+        self.restore_render_bundles(&render_bundles);
+        drop(resident_bundles);
+
+        let mut camera_state = self.camera_state.lock().unwrap();
+        let camera = camera_state.get_camera_mut();
+        camera.position = camera_position;
+        camera.orientation = camera_orientation;
+        drop(camera_state);
+
+        // Display and UI options are shared by all tabs
+        self.requested_settings = settings::Settings {
+            display: self.requested_settings.display,
+            ui_scale: self.requested_settings.ui_scale,
+            ..tab_settings
+        };
+        self.invalidate();
+    }
+
+    fn is_swapchain_out_of_date(&self) -> bool {
+        self.surface.is_out_of_date()
+    }
+
+    // Surface pass is created again along with the swapchain, everything else renders into it as before
+    fn recreate_swapchain(&mut self) {
+        log::info!("recreating swapchain");
+        self.queue.wait_idle();
+        self.device.wait_idle();
+
+        self.surface_pass.destroy(&mut self.factory);
+        self.surface.destroy(&mut self.factory);
+
+        self.surface = surface_winit::SurfaceWinit::new(&self.device, self.settings.display.vsync);
+        self.surface_pass = surface_pass::SurfacePass::new(&self.surface, &self.device, &mut self.factory);
+    }
+
+    // Font atlas is rebuilt when the UI scale changes, it has to be uploaded with a new ImGui renderer
+    fn apply_ui_scale(&mut self) {
+        let ui_scale = ui_scale::get_ui_scale(self.settings.ui_scale, self.scale_factor);
+        if ui_scale == self.ui_scale {
+            return;
+        }
+
+        self.queue.wait_idle();
+        self.device.wait_idle();
+        self.imgui_renderer.destroy(&mut self.factory);
+
+        ui_scale::apply_ui_scale(&mut self.imgui, &self.imgui_base_style, ui_scale);
+        self.imgui_renderer = self.bundle_loader.create_imgui_renderer(
+            &mut self.imgui,
+            self.surface_pass.get_render_layer(),
+            &mut self.device,
+            &mut self.factory,
+            &mut self.queue,
+        );
+        self.material_browser.reset_textures();
+        self.ui_scale = ui_scale;
+    }
+
+    // Window mode changes take effect right away, the window size follows later and the renderer
+    // is created again once it does. Vsync only needs a new swapchain.
+    fn apply_settings(&mut self, window: &winit::window::Window) {
+        let requested_settings = self.requested_settings;
+        if requested_settings.display.window_mode != self.settings.display.window_mode {
+            log::info!("window mode: {:?}", requested_settings.display.window_mode);
+            display_settings::apply_window_mode(window, requested_settings.display.window_mode);
+        }
+        let vsync_changed = requested_settings.display.vsync != self.settings.display.vsync;
+        requested_settings.apply_renderer_settings(
+            Some(&self.settings),
+            &mut self.pbr_forward_lit,
+            &self.device,
+            &mut self.factory,
+        );
+        self.settings = requested_settings;
+
+        let window_size = window.inner_size();
+        if window_size != self.surface_size && window_size.width > 0 && window_size.height > 0 {
+            self.resize(window);
+        } else if vsync_changed {
+            self.recreate_swapchain();
+        }
+        self.apply_ui_scale();
+    }
+
+    // Render targets depend on the window size, so the renderer is created again and loaded bundles are
+    // reloaded from their cached files. Renderer options that are not part of the settings are reset to defaults.
+    fn resize(&mut self, window: &winit::window::Window) {
+        let window_size = window.inner_size();
+        log::info!("resizing from {:?} to {:?}", self.surface_size, window_size);
+
+        let render_bundles = self.get_loaded_render_bundles();
+        self.queue.wait_idle();
+        self.device.wait_idle();
+        self.pbr_forward_lit.destroy(&mut self.factory);
+
+        self.recreate_swapchain();
+        self.pbr_forward_lit = create_pbr_forward_lit(
+            window,
+            &self.command_line,
+            &self.surface_pass,
+            &self.bundle_loader,
+            &self.device,
+            &mut self.factory,
+        );
+        self.settings
+            .apply_renderer_settings(None, &mut self.pbr_forward_lit, &self.device, &mut self.factory);
+        self.restore_render_bundles(&render_bundles);
+        if self.xr_context.is_some() {
+            self.pbr_forward_lit.debug_enable_anti_aliasing(false);
+            self.pbr_forward_lit.set_adaptive_resolution(None);
+            self.pbr_forward_lit.set_resolution_scale(1.0);
+            self.pbr_forward_lit.set_stereo_view(Some(0.0));
+        }
+
+        self.camera_state
+            .lock()
+            .unwrap()
+            .get_camera_mut()
+            .set_viewport(Viewport {
+                x: 0,
+                y: 0,
+                width: window_size.width,
+                height: window_size.height,
+            });
+        self.surface_size = window_size;
+        self.frame_time = std::time::Instant::now();
+    }
+
+    fn is_paused(&self) -> bool {
+        self.pbr_forward_lit.is_paused()
+    }
+
+    // Nothing is rendered while paused, the swapchain is recreated on resume because the surface might have changed
+    fn set_paused(&mut self, paused: bool) {
+        if paused == self.is_paused() {
+            return;
+        }
+
+        if paused {
+            log::info!("rendering paused");
+            self.pbr_forward_lit.pause();
+        } else {
+            log::info!("rendering resumed");
+            self.recreate_swapchain();
+            self.pbr_forward_lit.resume();
+            self.frame_time = std::time::Instant::now();
+        }
+    }
+
+    fn needs_redraw(&self) -> bool {
+        match &self.redraw_tracker {
+            Some(redraw_tracker) => redraw_tracker.needs_redraw(),
+            None => true,
+        }
+    }
+
+    fn invalidate(&mut self) {
+        if let Some(redraw_tracker) = &mut self.redraw_tracker {
+            redraw_tracker.invalidate();
+        }
+    }
+
+    // Errors are kept until the overlay is dismissed or the next reload succeeds
+    fn reload_shaders(&mut self) -> Result<(), String> {
+        let result = self
+            .pbr_forward_lit
+            .reload_shaders(&mut self.bundle_loader, &self.device, &mut self.factory);
+        self.shader_errors = result.clone().err();
+        self.invalidate();
+        result
+    }
+
+    // Time doesn't advance while idle, animations continue where they stopped
+    fn skip_frame(&mut self) {
+        self.frame_time = std::time::Instant::now();
+    }
+
     fn handle_event<T>(&mut self, window: &winit::window::Window, event: &winit::event::Event<T>) {
+        use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+        if let Event::WindowEvent { .. } = event {
+            self.invalidate();
+        }
+
         let io = self.imgui.io_mut();
         self.imgui_platform.handle_event(io, window, event);
         self.input_map
             .handle_event(io.want_capture_keyboard, io.want_capture_mouse, window, event);
+
+        if let Event::WindowEvent {
+            event: WindowEvent::ScaleFactorChanged { scale_factor, .. },
+            ..
+        } = event
+        {
+            self.scale_factor = *scale_factor;
+        }
+        if let Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(keycode),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                },
+            ..
+        } = event
+        {
+            if io.want_capture_keyboard {
+                return;
+            }
+            if *keycode == VirtualKeyCode::F5 {
+                let _ = self.reload_shaders();
+                return;
+            }
+            let requested_settings = &mut self.requested_settings.display;
+            match keycode {
+                VirtualKeyCode::F10 => requested_settings.vsync = !requested_settings.vsync,
+                VirtualKeyCode::F11 => {
+                    requested_settings.window_mode = match requested_settings.window_mode {
+                        display_settings::WindowMode::Windowed => display_settings::WindowMode::Borderless,
+                        _ => display_settings::WindowMode::Windowed,
+                    }
+                }
+                _ => {}
+            }
+        }
     }
 
     fn handle_gamepad_event(&mut self, event: &gilrs::Event) {
+        self.invalidate();
         self.input_map.handle_gamepad_event(event);
     }
 
     fn process_events(&mut self) {
+        if self.console.has_pending_commands() {
+            self.invalidate();
+        }
         self.input_map.process_events();
-        self.camera_state.handle_action_queue(self.input_map.get_action_queue());
+        self.pending_actions
+            .extend_from_slice(self.input_map.get_action_queue());
+        if let (Some(xr_context), Some(xr_session)) = (&self.xr_context, &mut self.xr_session) {
+            xr_session.poll_events(xr_context);
+        }
     }
 
     fn render_and_present(&mut self, window: &winit::window::Window, gilrs: &gilrs::Gilrs) {
         (*puffin::GlobalProfiler::lock()).new_frame();
+        self.profiler_export.update();
 
         let frame_context = self.device.begin_frame();
         {
@@ -269,26 +1024,111 @@ impl Game {
             self.surface.acquire_next_image(u64::max_value(), image_ready_semaphore)
         };
 
+        // Lost device is recovered before the next frame
+        let image_index = match image_index {
+            Some(image_index) if !self.device.is_device_lost() => image_index,
+            _ => return,
+        };
+
+        // Queries of this frame are reused by the render world pass
+        let cpu_frame_start = std::time::Instant::now();
+        let gpu_pass_timings = if puffin::are_scopes_on() || self.benchmark.is_some() {
+            self.pbr_forward_lit
+                .try_get_gpu_pass_timings(&frame_context, &mut self.factory)
+        } else {
+            Vec::new()
+        };
+        profiler_export::report_gpu_pass_timings(&gpu_pass_timings);
+        let gpu_pass_statistics = self
+            .pbr_forward_lit
+            .try_get_gpu_pass_statistics(&frame_context, &mut self.factory);
+        if !gpu_pass_statistics.is_empty() {
+            self.gpu_pass_statistics = gpu_pass_statistics;
+        }
+
         {
             puffin::profile_scope!("render");
 
             let time_now = std::time::Instant::now();
-            let time_delta = (time_now - self.frame_time).as_secs_f32();
+            let real_time_delta = (time_now - self.frame_time).as_secs_f32();
+            let time_delta = self.command_line.fixed_time_step.unwrap_or(real_time_delta);
             self.frame_time = time_now;
+            self.simulation_time += time_delta;
 
             {
                 puffin::profile_scope!("render_world");
 
+                // Next frame is simulated while this one is recorded
+                frame_context.wait_for_stage(FrameStage::Simulate);
+                let camera = {
+                    let mut camera_state = self.camera_state.lock().unwrap();
+                    if let Some(tiled_capture) = &self.tiled_capture {
+                        tiled_capture.prepare_camera(camera_state.get_camera_mut());
+                    } else if let Some(benchmark) = &self.benchmark {
+                        benchmark.update_camera(camera_state.get_camera_mut());
+                    }
+                    camera_state.get_camera().clone()
+                };
+                if let Some(redraw_tracker) = &mut self.redraw_tracker {
+                    redraw_tracker.update_camera(&camera);
+                }
+                let actions = std::mem::take(&mut self.pending_actions);
+                let actions = match &mut self.input_recording {
+                    Some(input_recording) => input_recording.process_frame(self.simulation_frame, actions),
+                    None => actions,
+                };
+                self.simulation_thread.simulate(
+                    frame_context.frame_index() + 1,
+                    time_delta,
+                    actions,
+                    self.tiled_capture.is_none() && self.benchmark.is_none(),
+                );
+                self.simulation_frame += 1;
+                if self.command_line.fixed_time_step.is_some() {
+                    self.pbr_forward_lit.set_animation_time(Some(self.simulation_time));
+                }
+
                 // render world
-                self.camera_state.update(time_delta);
+
+                // Headset pose is applied on top of the playground camera
+                let xr_frame = match (&self.xr_context, &mut self.xr_session) {
+                    (Some(xr_context), Some(xr_session)) => xr_session.begin_frame(xr_context),
+                    _ => None,
+                };
+                if let Some(xr_frame) = xr_frame.as_ref().filter(|xr_frame| xr_frame.should_render()) {
+                    self.pbr_forward_lit
+                        .set_stereo_view_projections(Some(xr_frame.calculate_view_projections(&camera)));
+                }
+
                 self.pbr_forward_lit.render(
-                    self.camera_state.get_camera(),
+                    &camera,
                     &frame_context,
                     &mut self.device,
                     &mut self.factory,
                     &mut self.queue,
                 );
 
+                if let (Some(xr_context), Some(xr_session), Some(xr_frame)) =
+                    (&self.xr_context, &mut self.xr_session, xr_frame)
+                {
+                    puffin::profile_scope!("xr_end_frame");
+                    let render_area = vk::Rect2D {
+                        offset: vk::Offset2D { x: 0, y: 0 },
+                        extent: vk::Extent2D {
+                            width: window.inner_size().width,
+                            height: window.inner_size().height,
+                        },
+                    };
+                    xr_session.end_frame(
+                        xr_context,
+                        xr_frame,
+                        self.pbr_forward_lit.get_stereo_layer(),
+                        render_area,
+                        &self.device,
+                        &mut self.queue,
+                    );
+                }
+
                 // process backbuffer pass and post processing
                 let screen_area = {
                     let surface_extent = self.surface.get_surface_extent();
@@ -307,7 +1147,7 @@ impl Game {
                     .add_wait_condition(image_ready_semaphore, vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
                 surface_layer.begin_render_pass(&frame_context, screen_area);
                 self.pbr_forward_lit
-                    .post_process(self.camera_state.get_camera(), &frame_context, surface_layer);
+                    .post_process(&camera, &frame_context, surface_layer);
             }
 
             // process imgui
@@ -318,6 +1158,14 @@ impl Game {
                 let average_delta = io.framerate;
 
                 self.imgui_platform.prepare_frame(io, window).unwrap();
+                self.material_browser.update(
+                    &self.pbr_forward_lit,
+                    &mut self.bundle_loader,
+                    &mut self.imgui_renderer,
+                    &self.device,
+                    &mut self.factory,
+                    &mut self.queue,
+                );
 
                 let ui = self.imgui.frame();
                 self.imgui_platform.prepare_render(&ui, window);
@@ -326,7 +1174,8 @@ impl Game {
                         &ui,
                         &window,
                         &gilrs,
-                        &mut self.camera_state,
+                        &mut self.camera_state.lock().unwrap(),
+                        &mut self.profiler_export,
                         1000.0 / average_delta,
                         average_delta,
                     );
@@ -339,6 +1188,24 @@ impl Game {
                         &mut self.factory,
                         &mut self.queue,
                     );
+                    self.gizmo.show(
+                        &ui,
+                        &self.camera_state.lock().unwrap().get_camera().clone(),
+                        &mut self.pbr_forward_lit,
+                    );
+                    self.console.show(&ui);
+                    debug_ui::show_shader_console_window(&ui, &self.device);
+                    debug_ui::show_asset_cache_window(&ui, &mut self.bundle_loader);
+                    debug_ui::show_shader_error_overlay(&ui, &mut self.shader_errors);
+                    debug_ui::show_pipeline_statistics_window(&ui, &self.device, &self.gpu_pass_statistics);
+                    self.material_browser.show(&ui, self.ui_scale, &self.pbr_forward_lit);
+                    self.scene_tabs.show(&ui, self.ui_scale, &self.pbr_forward_lit);
+                    debug_ui::show_settings_window(
+                        &ui,
+                        &mut self.requested_settings,
+                        &self.command_line.assets_folder.join("settings.json"),
+                        self.factory.get_max_sampler_anisotropy(),
+                    );
 
                     let _profiler_window_open = self.profiler_ui.window(&ui);
                     //let mut demo_window_open = true;
@@ -362,6 +1229,7 @@ impl Game {
             // self.pbr_forward_lit.copy_images(command_buffer);
 
             surface_layer.submit_commands(&frame_context, &mut self.queue);
+            frame_context.signal_stage(FrameStage::Record);
             self.surface.present(
                 &mut self.queue,
                 surface_layer.get_signal_semaphore(&frame_context),
@@ -369,7 +1237,182 @@ impl Game {
             );
             self.device.end_frame(frame_context);
         }
+        if let Some(redraw_tracker) = &mut self.redraw_tracker {
+            redraw_tracker.frame_rendered();
+        }
+
+        if let Some(benchmark) = &mut self.benchmark {
+            benchmark.record_frame(
+                cpu_frame_start.elapsed().as_secs_f32() * 1000.0,
+                &gpu_pass_timings,
+                self.pbr_forward_lit.get_render_statistics(),
+            );
+        }
+
+        // Tile is read back once the whole frame is done, the surface pass reads the same image
+        if let Some(tiled_capture) = &mut self.tiled_capture {
+            puffin::profile_scope!("tiled_capture");
+            self.queue.wait_idle();
+            self.device.wait_idle();
+            tiled_capture.capture_tile(
+                &self.pbr_forward_lit,
+                self.bundle_loader.get_command_buffer_mut(),
+                &mut self.factory,
+                &mut self.queue,
+            );
+        }
+
+        self.execute_console_commands();
+    }
+
+    // Commands may wait for the device, they run once the frame is submitted
+    fn execute_console_commands(&mut self) {
+        let command_lines = self.console.take_pending_commands();
+        if command_lines.is_empty() {
+            return;
+        }
+
+        let mut console_commands = std::mem::take(&mut self.console_commands);
+        for command_line in command_lines {
+            let result = console_commands.execute(self, &command_line);
+            self.console.add_output(&command_line, &result);
+        }
+        self.console_commands = console_commands;
+    }
+
+    fn is_benchmark_finished(&self) -> bool {
+        self.benchmark.as_ref().is_some_and(|benchmark| benchmark.is_finished())
+    }
+
+    fn is_input_replay_finished(&self) -> bool {
+        self.input_recording
+            .as_ref()
+            .is_some_and(|input_recording| input_recording.is_replay_finished(self.simulation_frame))
+    }
+
+    fn is_xr_exit_requested(&self) -> bool {
+        self.xr_session
+            .as_ref()
+            .is_some_and(|xr_session| xr_session.is_exit_requested())
     }
+
+    fn is_tiled_capture_finished(&self) -> bool {
+        self.tiled_capture
+            .as_ref()
+            .is_some_and(|tiled_capture| tiled_capture.is_finished())
+    }
+}
+
+fn parse_capture_size(capture_size: &str) -> Option<(u32, u32)> {
+    let mut dimensions = capture_size.split('x').map(|dimension| dimension.trim().parse::<u32>());
+    match (dimensions.next(), dimensions.next(), dimensions.next()) {
+        (Some(Ok(width)), Some(Ok(height)), None) if width > 0 && height > 0 => Some((width, height)),
+        _ => None,
+    }
+}
+
+fn create_device(
+    window: &winit::window::Window,
+    command_line: &CommandLineOptions,
+    xr_context: Option<&XrContext>,
+) -> Device {
+    let device_extensions = [ash::extensions::khr::Swapchain::name()];
+    let device_options = DeviceOptions {
+        enable_validation: command_line.enable_validation,
+        enable_dynamic_rendering: command_line.enable_dynamic_rendering,
+        enable_push_descriptors: command_line.enable_push_descriptors,
+        enable_conditional_rendering: command_line.enable_conditional_rendering,
+        enable_buffer_device_address: command_line.enable_buffer_device_address,
+        enable_multiview: command_line.enable_multiview,
+        enable_pipeline_statistics: command_line.enable_pipeline_statistics,
+        enable_shader_clock: command_line.enable_shader_clock,
+        enable_graphics_pipeline_library: command_line.enable_graphics_pipeline_library,
+        enable_shader_debug_printf: command_line.shader_debug_printf,
+        enable_resource_tracking: command_line.track_resources,
+        disable_resizable_bar: command_line.disable_resizable_bar,
+        num_buffered_frames: command_line.num_buffered_frames,
+        enable_ray_tracing_nv: command_line.enable_ray_tracing,
+        enable_render_target_export: true, // tiled capture and console screenshots
+        ..Default::default()
+    };
+
+    match xr_context {
+        Some(xr_context) => xr_context.create_device(window, &device_extensions, device_options),
+        None => Device::from_surface_provider(window, &device_extensions, device_options),
+    }
+}
+
+// First frame of the device is simulated right away, every frame after that is simulated by the one before it
+fn start_simulation_thread(
+    camera_state: &Arc<Mutex<camera_state::CameraState>>,
+    device: &Device,
+) -> simulation_thread::SimulationThread {
+    let simulation_thread =
+        simulation_thread::SimulationThread::new(camera_state.clone(), device.get_frame_stage_sync());
+    simulation_thread.simulate(device.get_frame_index(), 0.0, Vec::new(), false);
+    simulation_thread
+}
+
+fn create_bundle_loader(
+    base_path: &std::path::Path,
+    command_line: &CommandLineOptions,
+    device: &Device,
+    factory: &mut DeviceFactory,
+    queue: &mut DeviceQueue,
+) -> BundleLoader {
+    BundleLoader::new(
+        &BundleLoaderParameters {
+            bundle_compression_level: command_line.compression_level,
+            temporary_folder: &command_line.assets_folder.join("temporary_folder"),
+            base_path,
+            shader_bundle_path: &command_line.assets_folder.join("common_shaders.bundle"),
+            pbr_resource_folder: &command_line.assets_folder.join("pbr_resources"),
+            force_import_bundles: command_line.force_import_bundles,
+            force_compile_shaders: command_line.force_compile_shaders,
+            pack_mesh_geometry: command_line.pack_mesh_geometry,
+            vertex_pulling: command_line.vertex_pulling,
+            compress_environment_probes: command_line.compress_environment_probes,
+            shader_debug_printf: device.is_shader_debug_printf_enabled(),
+            stress_instance_count: command_line.stress_instance_count,
+            stress_mesh: command_line.stress_mesh,
+            asset_cache_size_budget: command_line.asset_cache_budget << 20,
+        },
+        device,
+        factory,
+        queue,
+    )
+}
+
+fn create_pbr_forward_lit(
+    window: &winit::window::Window,
+    command_line: &CommandLineOptions,
+    surface_pass: &surface_pass::SurfacePass,
+    bundle_loader: &BundleLoader,
+    device: &Device,
+    factory: &mut DeviceFactory,
+) -> PbrForwardLit {
+    let surface_size = window.inner_size();
+    PbrForwardLit::new(
+        &PbrForwardLitParameters {
+            render_width: surface_size.width,
+            render_height: surface_size.height,
+            target_layer: Some(surface_pass.get_render_layer()),
+            bundle_loader,
+            enable_anti_aliasing: !command_line.no_anti_aliasing,
+            transparency_mode: match command_line.transparency_mode.as_str() {
+                "disabled" => TransparencyMode::Disabled,
+                "linked_lists" => TransparencyMode::LinkedLists,
+                _ => TransparencyMode::WeightedBlended,
+            },
+            hdr_color_format: match command_line.hdr_format.as_str() {
+                "rgba16f" => HdrColorFormat::R16G16B16A16Float,
+                _ => HdrColorFormat::R11G11B10Float,
+            },
+            num_recording_threads: command_line.num_recording_threads,
+        },
+        device,
+        factory,
+    )
 }
 
 fn main() {
@@ -389,6 +1432,19 @@ fn main() {
     };
     log::info!("command line: {:?}", &command_line);
 
+    // Panics and device loss leave a diagnostic report behind
+    {
+        let crash_dump_folder = command_line.assets_folder.join("crash_dumps");
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |panic_info| {
+            default_hook(panic_info);
+            match write_diagnostic_report(&crash_dump_folder, &panic_info.to_string()) {
+                Ok(report_folder) => log::error!("diagnostic report written to {:?}", report_folder),
+                Err(error) => log::error!("failed to write diagnostic report: {:?}", error),
+            }
+        }));
+    }
+
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::WindowBuilder::new()
         .with_title("Málwerks")
@@ -403,19 +1459,41 @@ fn main() {
     window.set_inner_size(window_size);
     log::info!("monitor size: {:?}, window size: {:?}", monitor_size, window_size);
 
+    // Command line options take precedence over saved settings
+    let mut settings = settings::Settings::load(&command_line.assets_folder.join("settings.json"));
+    settings.display.vsync |= command_line.vsync;
+    if command_line.fixed_time_step.is_some() {
+        // Resolution must not follow GPU timings and presenting must not wait for vertical blank
+        settings.adaptive_resolution = None;
+        settings.display.vsync = false;
+    }
+    if let Some(window_mode) = &command_line.window_mode {
+        settings.display.window_mode = display_settings::WindowMode::from_name(window_mode);
+    }
+    if settings.display.window_mode != display_settings::WindowMode::Windowed {
+        display_settings::apply_window_mode(&window, settings.display.window_mode);
+    }
+
     let mut gilrs = gilrs::Gilrs::new().expect("failed to initialize gamepad input");
     for (_id, gamepad) in gilrs.gamepads() {
         log::info!("gamepad detected: {} {:?}", gamepad.name(), gamepad.power_info());
     }
 
-    let mut game = Game::new(&window, &base_path, command_line);
+    let mut game = Game::new(&window, &base_path, command_line, settings);
 
     // run events loop
     event_loop.run(move |event, _, control_flow| {
         use winit::event::{Event, WindowEvent};
         use winit::event_loop::ControlFlow;
 
-        *control_flow = ControlFlow::Poll;
+        // Event loop sleeps while rendering is paused, idle render on demand mode wakes up to poll gamepads
+        *control_flow = if game.is_paused() {
+            ControlFlow::Wait
+        } else if !game.needs_redraw() {
+            ControlFlow::WaitUntil(std::time::Instant::now() + std::time::Duration::from_millis(100))
+        } else {
+            ControlFlow::Poll
+        };
 
         game.handle_event(&window, &event);
         match event {
@@ -425,23 +1503,52 @@ fn main() {
                 }
 
                 game.process_events();
-                window.request_redraw();
+                if !game.is_paused() {
+                    if game.needs_redraw() {
+                        window.request_redraw();
+                    } else {
+                        game.skip_frame();
+                    }
+                }
             }
 
-            Event::RedrawRequested(_) => {
+            Event::RedrawRequested(_) if !game.is_paused() => {
+                if game.is_device_lost() {
+                    game.recover_lost_device(&window, &base_path);
+                } else if game.is_swapchain_out_of_date() {
+                    game.recreate_swapchain();
+                }
+                game.update_scene_tabs();
+                game.apply_settings(&window);
                 game.render_and_present(&window, &gilrs);
+                if game.is_benchmark_finished()
+                    || game.is_tiled_capture_finished()
+                    || game.is_input_replay_finished()
+                    || game.is_xr_exit_requested()
+                {
+                    *control_flow = ControlFlow::Exit;
+                }
             }
 
             Event::LoopDestroyed => {
                 // Nothing right now
             }
 
+            // application lifecycle
+            Event::Suspended => {
+                game.set_paused(true);
+            }
+            Event::Resumed => {
+                game.set_paused(false);
+            }
+
             // user input
             Event::WindowEvent {
-                event: WindowEvent::Resized(_size),
+                event: WindowEvent::Resized(size),
                 ..
             } => {
-                // Nothing right now
+                // Window is only resized by window mode changes, zero size means that it was minimized
+                game.set_paused(size.width == 0 || size.height == 0);
             }
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
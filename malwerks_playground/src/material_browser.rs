@@ -0,0 +1,246 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_render::*;
+use malwerks_vk::*;
+
+const THUMBNAIL_DISPLAY_SIZE: f32 = 96.0; // before UI scale
+const THUMBNAIL_CACHE_FILE_NAME: &str = "material_thumbnails.bin";
+
+// Thumbnails of the selected bundle are rendered in `update()` the next frame after it is selected, the window
+// only shows what is already cached. Thumbnails are not rendered again when the environment probe changes,
+// rendered ones are kept in the asset cache and loaded from there the next time.
+pub struct MaterialBrowser {
+    material_preview: MaterialPreview,
+    texture_ids: Vec<(String, Vec<imgui::TextureId>)>, // bundle name, one ImGui texture per thumbnail
+    selected_bundle: Option<String>,
+    refresh_requested: bool,
+}
+
+impl MaterialBrowser {
+    pub fn new(
+        bundle_loader: &BundleLoader,
+        device: &Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) -> Self {
+        Self {
+            material_preview: bundle_loader.create_material_preview(device, factory, queue),
+            texture_ids: Vec::new(),
+            selected_bundle: None,
+            refresh_requested: false,
+        }
+    }
+
+    // ImGui textures are owned by the ImGui renderer and are destroyed along with it
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        self.material_preview.destroy(factory);
+        self.texture_ids.clear();
+    }
+
+    // Has to be called when the ImGui renderer is created again, textures are registered with the new one
+    pub fn reset_textures(&mut self) {
+        self.texture_ids.clear();
+    }
+
+    pub fn update(
+        &mut self,
+        pbr_forward_lit: &PbrForwardLit,
+        bundle_loader: &mut BundleLoader,
+        imgui_renderer: &mut ImguiRenderer,
+        device: &Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) {
+        puffin::profile_function!();
+        let render_bundles = pbr_forward_lit.get_render_bundles();
+        let render_bundle_files = pbr_forward_lit.get_render_bundle_files();
+
+        // Thumbnails may still be drawn by frames in flight, removing them waits for the GPU
+        let refresh_bundle = self.selected_bundle.clone().filter(|_| self.refresh_requested);
+        let stale_bundles: Vec<String> = self
+            .material_preview
+            .get_thumbnail_bundle_names()
+            .into_iter()
+            .filter(|bundle_name| {
+                Some(bundle_name) == refresh_bundle.as_ref()
+                    || !render_bundles.iter().any(|(name, _, _, _)| name == bundle_name)
+            })
+            .collect();
+        self.refresh_requested = false;
+        if !stale_bundles.is_empty() {
+            queue.wait_idle();
+        }
+        if let Some(refresh_bundle) = &refresh_bundle {
+            if let Some(index) = render_bundles.iter().position(|(name, _, _, _)| name == refresh_bundle) {
+                bundle_loader.get_asset_cache_mut().remove(
+                    &get_asset_cache_bundle_name(&render_bundle_files[index].bundle_file),
+                    AssetCacheKind::Thumbnails,
+                    THUMBNAIL_CACHE_FILE_NAME,
+                );
+            }
+        }
+        for bundle_name in &stale_bundles {
+            if let Some(index) = self.texture_ids.iter().position(|(name, _)| name == bundle_name) {
+                let (_, texture_ids) = self.texture_ids.remove(index);
+                for texture_id in texture_ids {
+                    imgui_renderer.unregister_texture(texture_id, factory);
+                }
+            }
+            self.material_preview.remove_thumbnails(bundle_name, factory);
+        }
+
+        if let Some(selected_bundle) = &self.selected_bundle {
+            match render_bundles
+                .iter()
+                .position(|(name, _, _, _)| name == selected_bundle)
+            {
+                Some(index) => {
+                    let (bundle_name, resource_bundle, _, _) = &render_bundles[index];
+                    if self.material_preview.get_thumbnails(bundle_name).is_none() {
+                        let cache_bundle_name = get_asset_cache_bundle_name(&render_bundle_files[index].bundle_file);
+                        let asset_cache = bundle_loader.get_asset_cache_mut();
+                        let is_loaded = match asset_cache.read(
+                            &cache_bundle_name,
+                            AssetCacheKind::Thumbnails,
+                            THUMBNAIL_CACHE_FILE_NAME,
+                        ) {
+                            Some(thumbnail_data) => self.material_preview.load_thumbnails(
+                                bundle_name,
+                                &thumbnail_data,
+                                device,
+                                factory,
+                                queue,
+                            ),
+                            None => false,
+                        };
+                        if !is_loaded {
+                            self.material_preview.render_thumbnails(
+                                bundle_name,
+                                &resource_bundle.borrow(),
+                                &bundle_loader.get_pbr_resource_bundle().borrow(),
+                                device,
+                                factory,
+                                queue,
+                            );
+                            if let Some(thumbnail_data) =
+                                self.material_preview
+                                    .save_thumbnails(bundle_name, device, factory, queue)
+                            {
+                                bundle_loader.get_asset_cache_mut().write(
+                                    &cache_bundle_name,
+                                    AssetCacheKind::Thumbnails,
+                                    THUMBNAIL_CACHE_FILE_NAME,
+                                    &thumbnail_data,
+                                );
+                            }
+                        }
+                    }
+                }
+                None => self.selected_bundle = None,
+            }
+        }
+
+        for bundle_name in self.material_preview.get_thumbnail_bundle_names() {
+            if self.texture_ids.iter().any(|(name, _)| *name == bundle_name) {
+                continue;
+            }
+            let texture_ids = self
+                .material_preview
+                .get_thumbnails(&bundle_name)
+                .expect("thumbnails of the bundle are missing")
+                .iter()
+                .map(|thumbnail| imgui_renderer.register_texture(thumbnail.image_view, factory))
+                .collect();
+            self.texture_ids.push((bundle_name, texture_ids));
+        }
+    }
+
+    pub fn show<'a>(&mut self, ui: &imgui::Ui<'a>, ui_scale: f32, pbr_forward_lit: &PbrForwardLit) {
+        use imgui::*;
+
+        puffin::profile_function!();
+        Window::new(im_str!("Material browser"))
+            .size([480.0 * ui_scale, 420.0 * ui_scale], Condition::FirstUseEver)
+            .collapsed(true, Condition::FirstUseEver)
+            .build(ui, || {
+                for (bundle_name, _, _, _) in pbr_forward_lit.get_render_bundles() {
+                    let is_selected = self.selected_bundle.as_ref() == Some(bundle_name);
+                    if Selectable::new(&ImString::new(bundle_name))
+                        .selected(is_selected)
+                        .build(ui)
+                    {
+                        self.selected_bundle = Some(bundle_name.clone());
+                    }
+                }
+                let selected_bundle = match &self.selected_bundle {
+                    Some(selected_bundle) => selected_bundle,
+                    None => {
+                        ui.text_wrapped(im_str!("Select a bundle to show its materials"));
+                        return;
+                    }
+                };
+
+                if ui.button(im_str!("Refresh thumbnails"), [0.0, 0.0]) {
+                    self.refresh_requested = true;
+                }
+                ui.separator();
+
+                let render_bundle = pbr_forward_lit
+                    .get_render_bundles()
+                    .iter()
+                    .find(|(name, _, _, _)| name == selected_bundle);
+                let texture_ids = self.texture_ids.iter().find(|(name, _)| name == selected_bundle);
+                let thumbnails = self.material_preview.get_thumbnails(selected_bundle);
+                let (resource_bundle, texture_ids, thumbnails) = match (render_bundle, texture_ids, thumbnails) {
+                    (Some((_, resource_bundle, _, _)), Some((_, texture_ids)), Some(thumbnails)) => {
+                        (resource_bundle.borrow(), texture_ids, thumbnails)
+                    }
+                    _ => {
+                        ui.text(im_str!("Rendering thumbnails..."));
+                        return;
+                    }
+                };
+                if thumbnails.is_empty() {
+                    ui.text(im_str!("Bundle has no materials"));
+                    return;
+                }
+
+                let thumbnail_size = THUMBNAIL_DISPLAY_SIZE * ui_scale;
+                let spacing = ui.clone_style().item_spacing[0];
+                let column_count =
+                    (((ui.content_region_avail()[0] + spacing) / (thumbnail_size + spacing)) as usize).max(1);
+                for (thumbnail_id, (thumbnail, texture_id)) in thumbnails.iter().zip(texture_ids).enumerate() {
+                    if thumbnail_id % column_count != 0 {
+                        ui.same_line(0.0);
+                    }
+                    Image::new(*texture_id, [thumbnail_size, thumbnail_size]).build(ui);
+                    if ui.is_item_hovered() {
+                        let material = &resource_bundle.materials[thumbnail.material_id];
+                        let blending = if material.fragment_alpha_blend {
+                            "alpha blended"
+                        } else if material.fragment_alpha_test {
+                            "alpha tested"
+                        } else {
+                            "opaque"
+                        };
+                        let textures = material
+                            .shader_image_mapping
+                            .iter()
+                            .map(|(image_name, _)| image_name.as_str())
+                            .collect::<Vec<&str>>()
+                            .join(", ");
+                        ui.tooltip_text(format!(
+                            "material instance {}\nmaterial {}, {}\ntextures: {}",
+                            thumbnail.material_instance_id,
+                            thumbnail.material_id,
+                            blending,
+                            if textures.is_empty() { "none" } else { &textures }
+                        ));
+                    }
+                }
+            });
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+const CAPTURED_FRAME_COUNT: usize = 120;
+
+// Collects puffin frames and writes them as chrome://tracing JSON
+pub struct ProfilerExport {
+    output_folder: std::path::PathBuf,
+    captured_frames: Vec<puffin::FullProfileData>,
+    is_capturing: bool,
+}
+
+impl ProfilerExport {
+    pub fn new(output_folder: &std::path::Path) -> Self {
+        Self {
+            output_folder: output_folder.to_path_buf(),
+            captured_frames: Vec::with_capacity(CAPTURED_FRAME_COUNT),
+            is_capturing: false,
+        }
+    }
+
+    pub fn start_capture(&mut self) {
+        puffin::set_scopes_on(true);
+        self.captured_frames.clear();
+        self.is_capturing = true;
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.is_capturing
+    }
+
+    pub fn get_captured_frame_count(&self) -> usize {
+        self.captured_frames.len()
+    }
+
+    // Has to be called right after the global profiler starts a new frame
+    pub fn update(&mut self) {
+        if !self.is_capturing {
+            return;
+        }
+
+        self.captured_frames
+            .push(puffin::GlobalProfiler::lock().past_frame().clone());
+        if self.captured_frames.len() == CAPTURED_FRAME_COUNT {
+            let seconds = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .expect("system time is before unix epoch")
+                .as_secs();
+            let trace_file = self.output_folder.join(format!("profile_{}.json", seconds));
+            write_chrome_trace(&self.captured_frames, &trace_file);
+            log::info!("profiling data written to {:?}", &trace_file);
+
+            self.captured_frames.clear();
+            self.is_capturing = false;
+        }
+    }
+}
+
+// GPU clock is not calibrated against the CPU clock, passes are shifted to start with the current frame
+pub fn report_gpu_pass_timings(gpu_pass_timings: &[(&str, [u64; 2])]) {
+    if !puffin::are_scopes_on() || gpu_pass_timings.is_empty() {
+        return;
+    }
+
+    let gpu_start_ns = gpu_pass_timings.iter().map(|(_, timings)| timings[0]).min().unwrap();
+    let gpu_stop_ns = gpu_pass_timings.iter().map(|(_, timings)| timings[1]).max().unwrap();
+    let frame_start_ns = puffin::now_ns();
+    let to_cpu_ns = |gpu_ns: u64| frame_start_ns + (gpu_ns - gpu_start_ns) as puffin::NanoSecond;
+
+    let mut stream = puffin::Stream::default();
+    let frame_scope = stream.begin_scope(frame_start_ns, "gpu_frame", "", "");
+    for (name, timings) in gpu_pass_timings {
+        let pass_scope = stream.begin_scope(to_cpu_ns(timings[0]), name, "", "");
+        stream.end_scope(pass_scope, to_cpu_ns(timings[1].max(timings[0])));
+    }
+    stream.end_scope(frame_scope, to_cpu_ns(gpu_stop_ns));
+
+    puffin::GlobalProfiler::lock().report(
+        puffin::ThreadInfo {
+            start_time_ns: None,
+            name: String::from("GPU"),
+        },
+        stream,
+    );
+}
+
+fn write_chrome_trace(frames: &[puffin::FullProfileData], trace_file: &std::path::Path) {
+    let mut thread_names: Vec<String> = Vec::new();
+    let mut trace_events = Vec::new();
+    for frame in frames {
+        for (thread_info, stream) in &frame.0 {
+            let thread_id = match thread_names.iter().position(|name| *name == thread_info.name) {
+                Some(thread_id) => thread_id,
+                None => {
+                    thread_names.push(thread_info.name.clone());
+                    thread_names.len() - 1
+                }
+            };
+
+            match puffin::Reader::from_start(stream).read_top_scopes() {
+                Ok(scopes) => collect_trace_events(stream, &scopes, thread_id, &mut trace_events),
+                Err(error) => log::warn!("skipping invalid profiling stream: {:?}", error),
+            }
+        }
+    }
+    for (thread_id, thread_name) in thread_names.iter().enumerate() {
+        trace_events.push(serde_json::json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": 0,
+            "tid": thread_id,
+            "args": { "name": thread_name },
+        }));
+    }
+
+    std::fs::create_dir_all(trace_file.parent().expect("trace file has no parent folder"))
+        .expect("failed to create profiling output folder");
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(trace_file)
+        .expect("failed to open trace file for writing");
+    serde_json::to_writer(
+        std::io::BufWriter::new(file),
+        &serde_json::json!({
+            "traceEvents": trace_events,
+            "displayTimeUnit": "ms",
+        }),
+    )
+    .expect("failed to write trace file");
+}
+
+// Chrome trace timestamps are in microseconds
+fn collect_trace_events(
+    stream: &puffin::Stream,
+    scopes: &[puffin::Scope],
+    thread_id: usize,
+    trace_events: &mut Vec<serde_json::Value>,
+) {
+    for scope in scopes {
+        trace_events.push(serde_json::json!({
+            "name": scope.record.id,
+            "ph": "X",
+            "ts": scope.record.start_ns as f64 / 1000.0,
+            "dur": scope.record.duration_ns as f64 / 1000.0,
+            "pid": 0,
+            "tid": thread_id,
+            "args": {
+                "location": scope.record.location,
+                "data": scope.record.data,
+            },
+        }));
+
+        let children =
+            puffin::Reader::with_offset(stream, scope.child_begin_position).and_then(|reader| reader.read_top_scopes());
+        match children {
+            Ok(children) => collect_trace_events(stream, &children, thread_id, trace_events),
+            Err(error) => log::warn!("skipping invalid profiling scope: {:?}", error),
+        }
+    }
+}
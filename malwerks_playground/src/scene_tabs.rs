@@ -0,0 +1,195 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_render::*;
+
+use crate::settings::*;
+
+// Scene of one tab, it is only up to date for inactive tabs. The active tab's scene lives in PbrForwardLit,
+// the camera state and the requested settings and is stored back when another tab is selected.
+pub struct SceneTab {
+    pub id: usize, // stays the same when other tabs are closed
+    pub name: String,
+    pub render_bundles: Vec<(String, RenderBundleFiles)>,
+    pub resident_bundles: Vec<ResourceBundleReference>, // keeps resource bundles loaded while the tab is inactive
+    pub camera_position: ultraviolet::vec::Vec3,
+    pub camera_orientation: ultraviolet::rotor::Rotor3,
+    pub settings: Settings,
+}
+
+// Switching tabs is requested by the UI and done by the playground before the next frame. ImGui decides which
+// tab is selected, the active tab follows it. A closed active tab is hidden until ImGui selects another one.
+pub struct SceneTabs {
+    tabs: Vec<SceneTab>,
+    active_tab: usize,
+    next_tab_id: usize,
+    requested_tab: Option<usize>,
+    closed_tab: Option<usize>,
+    keep_inactive_resident: bool,
+}
+
+impl SceneTabs {
+    pub fn new(camera: &Camera, settings: &Settings) -> Self {
+        let mut scene_tabs = Self {
+            tabs: Vec::new(),
+            active_tab: 0,
+            next_tab_id: 1,
+            requested_tab: None,
+            closed_tab: None,
+            keep_inactive_resident: true,
+        };
+        scene_tabs.add_tab(camera, settings);
+        scene_tabs
+    }
+
+    // New tabs start empty with the camera and settings of the active one
+    pub fn add_tab(&mut self, camera: &Camera, settings: &Settings) -> usize {
+        self.tabs.push(SceneTab {
+            id: self.next_tab_id,
+            name: format!("Scene {}", self.next_tab_id),
+            render_bundles: Vec::new(),
+            resident_bundles: Vec::new(),
+            camera_position: camera.position,
+            camera_orientation: camera.orientation,
+            settings: *settings,
+        });
+        self.next_tab_id += 1;
+        self.tabs.len() - 1
+    }
+
+    // The active tab is only removed after another one is selected
+    pub fn remove_closed_tab(&mut self) {
+        if let Some(tab_index) = self.closed_tab {
+            if tab_index != self.active_tab {
+                log::info!("closing scene tab \"{}\"", self.tabs[tab_index].name);
+                self.tabs.remove(tab_index);
+                if self.active_tab > tab_index {
+                    self.active_tab -= 1;
+                }
+                self.closed_tab = None;
+            }
+        }
+    }
+
+    pub fn get_tab_count(&self) -> usize {
+        self.tabs.len()
+    }
+
+    pub fn get_active_tab(&self) -> usize {
+        self.active_tab
+    }
+
+    pub fn keeps_inactive_resident(&self) -> bool {
+        self.keep_inactive_resident
+    }
+
+    // Stores the scene of the active tab and makes another tab active, its scene is returned to be restored
+    pub fn switch_tab(
+        &mut self,
+        tab_index: usize,
+        render_bundles: Vec<(String, RenderBundleFiles)>,
+        resident_bundles: Vec<ResourceBundleReference>,
+        camera: &Camera,
+        settings: &Settings,
+    ) -> &mut SceneTab {
+        log::info!(
+            "switching from scene tab \"{}\" to \"{}\"",
+            self.tabs[self.active_tab].name,
+            self.tabs[tab_index].name
+        );
+        let active_tab = &mut self.tabs[self.active_tab];
+        active_tab.render_bundles = render_bundles;
+        active_tab.resident_bundles = resident_bundles;
+        active_tab.camera_position = camera.position;
+        active_tab.camera_orientation = camera.orientation;
+        active_tab.settings = *settings;
+
+        self.active_tab = tab_index;
+        &mut self.tabs[tab_index]
+    }
+
+    // Resource bundles of inactive tabs are loaded again when they are selected
+    pub fn release_resident_bundles(&mut self) {
+        for tab in &mut self.tabs {
+            tab.resident_bundles.clear();
+        }
+    }
+
+    pub fn take_requested_tab(&mut self) -> Option<usize> {
+        self.requested_tab.take()
+    }
+
+    pub fn show<'a>(&mut self, ui: &imgui::Ui<'a>, ui_scale: f32, pbr_forward_lit: &PbrForwardLit) {
+        use imgui::*;
+
+        puffin::profile_function!();
+        Window::new(im_str!("Scenes"))
+            .size([360.0 * ui_scale, 0.0], Condition::FirstUseEver)
+            .build(ui, || {
+                // Last tab can't be closed
+                let can_close = self.tabs.len() > 1 && self.closed_tab.is_none();
+                let tabs = &self.tabs;
+                let active_tab = self.active_tab;
+                let mut requested_tab = self.requested_tab;
+                let mut closed_tab = self.closed_tab;
+                TabBar::new(im_str!("Scene tabs"))
+                    .flags(TabBarFlags::AUTO_SELECT_NEW_TABS)
+                    .build(ui, || {
+                        for (tab_index, tab) in tabs.iter().enumerate() {
+                            if closed_tab == Some(tab_index) {
+                                continue;
+                            }
+                            let mut opened = true;
+                            let label = ImString::from(format!("{}##{}", tab.name, tab.id));
+                            let tab_item = if can_close {
+                                TabItem::new(&label).opened(&mut opened)
+                            } else {
+                                TabItem::new(&label)
+                            };
+                            tab_item.build(ui, || {
+                                if tab_index != active_tab && requested_tab.is_none() {
+                                    requested_tab = Some(tab_index);
+                                }
+                            });
+                            if !opened {
+                                closed_tab = Some(tab_index);
+                            }
+                        }
+                    });
+                self.requested_tab = requested_tab;
+                self.closed_tab = closed_tab;
+
+                if ui.button(im_str!("New scene"), [0.0, 0.0]) {
+                    self.requested_tab = Some(self.tabs.len());
+                }
+                ui.same_line(0.0);
+                if ui.checkbox(im_str!("Keep inactive scenes loaded"), &mut self.keep_inactive_resident)
+                    && !self.keep_inactive_resident
+                {
+                    self.release_resident_bundles();
+                }
+
+                ui.text(ImString::from(format!(
+                    "{}: {} render bundles, active",
+                    self.tabs[self.active_tab].name,
+                    pbr_forward_lit.get_render_bundles().len()
+                )));
+                for (tab_index, tab) in self.tabs.iter().enumerate() {
+                    if tab_index != self.active_tab {
+                        ui.text(ImString::from(format!(
+                            "{}: {} render bundles, {}",
+                            tab.name,
+                            tab.render_bundles.len(),
+                            if tab.resident_bundles.is_empty() {
+                                "unloaded"
+                            } else {
+                                "loaded"
+                            }
+                        )));
+                    }
+                }
+            });
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_render::*;
+use malwerks_vk::*;
+
+use crate::display_settings::*;
+
+// Options that are kept between runs, missing fields get their default values
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub anti_aliasing: bool,
+    pub highlight_color_space_mistakes: bool,
+    pub max_anisotropy: Option<f32>, // overrides the authored anisotropy of material samplers
+    pub texture_lod_bias: f32,
+    pub max_texture_resolution: Option<u32>, // material textures above it sample smaller mips only
+    pub resolution_scale: f32,
+    pub adaptive_resolution: Option<f32>, // target GPU frame time in milliseconds
+    pub display: DisplaySettings,
+    pub ui_scale: Option<f32>, // overrides the window scale factor
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            anti_aliasing: true,
+            highlight_color_space_mistakes: false,
+            max_anisotropy: None,
+            texture_lod_bias: 0.0,
+            max_texture_resolution: None,
+            resolution_scale: 1.0,
+            adaptive_resolution: None,
+            display: DisplaySettings {
+                vsync: false,
+                window_mode: WindowMode::Windowed,
+            },
+            ui_scale: None,
+        }
+    }
+}
+
+impl Settings {
+    // Missing or broken settings files fall back to defaults
+    pub fn load(settings_file: &std::path::Path) -> Self {
+        match std::fs::File::open(settings_file) {
+            Ok(file) => serde_json::from_reader(std::io::BufReader::new(file)).unwrap_or_else(|error| {
+                log::warn!("failed to parse {:?}: {:?}", settings_file, error);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, settings_file: &std::path::Path) {
+        match std::fs::File::create(settings_file) {
+            Ok(file) => match serde_json::to_writer_pretty(std::io::BufWriter::new(file), self) {
+                Ok(_) => log::info!("settings saved to {:?}", settings_file),
+                Err(error) => log::error!("failed to write {:?}: {:?}", settings_file, error),
+            },
+            Err(error) => log::error!("failed to create {:?}: {:?}", settings_file, error),
+        }
+    }
+
+    // Only options that differ from the applied settings are changed, everything is applied without them.
+    // Display and UI settings are applied by the playground itself.
+    pub fn apply_renderer_settings(
+        &self,
+        applied_settings: Option<&Settings>,
+        pbr_forward_lit: &mut PbrForwardLit,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) {
+        if applied_settings.is_none_or(|applied| applied.anti_aliasing != self.anti_aliasing) {
+            pbr_forward_lit.debug_enable_anti_aliasing(self.anti_aliasing);
+        }
+        if applied_settings
+            .is_none_or(|applied| applied.highlight_color_space_mistakes != self.highlight_color_space_mistakes)
+        {
+            pbr_forward_lit.debug_highlight_color_space_mistakes(self.highlight_color_space_mistakes);
+        }
+        if applied_settings.is_none_or(|applied| applied.max_anisotropy != self.max_anisotropy) {
+            pbr_forward_lit.set_sampler_anisotropy_override(self.max_anisotropy, device, factory);
+        }
+        if applied_settings.is_none_or(|applied| {
+            applied.texture_lod_bias != self.texture_lod_bias
+                || applied.max_texture_resolution != self.max_texture_resolution
+        }) {
+            pbr_forward_lit.set_texture_lod_override(
+                self.texture_lod_bias,
+                self.max_texture_resolution,
+                device,
+                factory,
+            );
+        }
+        if applied_settings.is_none_or(|applied| {
+            applied.adaptive_resolution != self.adaptive_resolution || applied.resolution_scale != self.resolution_scale
+        }) {
+            pbr_forward_lit.set_adaptive_resolution(self.adaptive_resolution);
+            if self.adaptive_resolution.is_none() {
+                pbr_forward_lit.set_resolution_scale(self.resolution_scale);
+            }
+        }
+    }
+}
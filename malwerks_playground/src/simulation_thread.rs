@@ -0,0 +1,73 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_vk::*;
+
+use std::sync::{mpsc, Arc, Mutex};
+
+use crate::camera_state::CameraState;
+use crate::input_map::InputAction;
+
+struct SimulationStep {
+    frame_index: u64,
+    time_delta: f32,
+    actions: Vec<InputAction>,
+    update_camera: bool, // camera is driven by the benchmark or tiled capture otherwise
+}
+
+// Simulates the next frame while the render thread records the current one.
+// Render thread waits for the simulate stage of its frame and renders a snapshot of the camera.
+pub struct SimulationThread {
+    sender: Option<mpsc::Sender<SimulationStep>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl SimulationThread {
+    pub fn new(camera_state: Arc<Mutex<CameraState>>, stage_sync: Arc<FrameStageSync>) -> Self {
+        let (sender, receiver) = mpsc::channel::<SimulationStep>();
+        let thread = std::thread::Builder::new()
+            .name("malwerks_simulation".to_string())
+            .spawn(move || {
+                while let Ok(step) = receiver.recv() {
+                    puffin::profile_scope!("simulate");
+                    {
+                        let mut camera_state = camera_state.lock().unwrap();
+                        camera_state.handle_action_queue(&step.actions);
+                        if step.update_camera {
+                            camera_state.update(step.time_delta);
+                        }
+                    }
+                    stage_sync.signal(FrameStage::Simulate, step.frame_index);
+                }
+            })
+            .expect("failed to start simulation thread");
+
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    // Remaining steps are simulated before the thread exits
+    pub fn destroy(&mut self) {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("simulation thread panicked");
+        }
+    }
+
+    pub fn simulate(&self, frame_index: u64, time_delta: f32, actions: Vec<InputAction>, update_camera: bool) {
+        self.sender
+            .as_ref()
+            .expect("simulate() called after destroy()")
+            .send(SimulationStep {
+                frame_index,
+                time_delta,
+                actions,
+                update_camera,
+            })
+            .expect("simulation thread has exited");
+    }
+}
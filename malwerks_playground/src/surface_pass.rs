@@ -82,7 +82,8 @@ impl SurfacePass {
                 .build(),
         );
 
-        let framebuffer = FrameLocal::new(|frame_index| {
+        let num_buffered_frames = device.get_num_buffered_frames();
+        let framebuffer = FrameLocal::new(num_buffered_frames, |frame_index| {
             factory.create_framebuffer(
                 &vk::FramebufferCreateInfo::builder()
                     .flags(Default::default())
@@ -95,7 +96,9 @@ impl SurfacePass {
             )
         });
         let clear_values = vec![vk::ClearValue::default()];
-        let image_ready_semaphore = FrameLocal::new(|_| factory.create_semaphore(&vk::SemaphoreCreateInfo::default()));
+        let image_ready_semaphore = FrameLocal::new(num_buffered_frames, |_| {
+            factory.create_semaphore(&vk::SemaphoreCreateInfo::default())
+        });
 
         Self {
             render_layer: RenderLayer::from_existing_render_pass(
@@ -103,6 +106,7 @@ impl SurfacePass {
                 factory,
                 render_pass,
                 framebuffer,
+                vec![surface.get_surface_format()],
                 clear_values,
             ),
             _images: swapchain_images,
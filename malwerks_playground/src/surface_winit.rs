@@ -3,17 +3,19 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
 // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use malwerks_core::is_srgb_format;
 use malwerks_vk::*;
 
-use ash::version::*;
-
 pub struct SurfaceWinit {
     internal_surface: InternalSurface,
     internal_swapchain: InternalSwapchain,
+    lost: bool,
+    out_of_date: bool,
 }
 
 impl SurfaceWinit {
-    pub fn new(device: &Device) -> Self {
+    // Without vsync presentation prefers modes that don't wait for vertical blank, FIFO is the fallback
+    pub fn new(device: &Device, vsync: bool) -> Self {
         let surface_loader = device.get_surface_loader().as_ref().unwrap();
         let surface_khr = device.get_surface_khr();
 
@@ -39,22 +41,28 @@ impl SurfaceWinit {
                 .next()
                 .expect("Unable to find fallback surface format");
 
-            // Try to find SRGB format first
+            // Tone map and ImGui write gamma encoded values, so UNORM formats with the sRGB color space
+            // are preferred. Any other sRGB color space format works too, tone map decodes its output for
+            // _SRGB targets, ImGui colors will be slightly off though.
+            let is_unorm_format = |format: &vk::SurfaceFormatKHR| {
+                format.format == vk::Format::B8G8R8A8_UNORM || format.format == vk::Format::R8G8B8A8_UNORM
+            };
+            let is_srgb_color_space =
+                |format: &vk::SurfaceFormatKHR| format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR;
             surface_formats
                 .iter()
                 .cloned()
-                .map(|format| match format.color_space {
-                    vk::ColorSpaceKHR::SRGB_NONLINEAR => format,
-
-                    _ => fallback_format,
-                })
-                .next()
+                .find(|format| is_unorm_format(format) && is_srgb_color_space(format))
+                .or_else(|| surface_formats.iter().cloned().find(is_srgb_color_space))
                 .unwrap_or(fallback_format)
         };
+        if is_srgb_format(surface_format.format) {
+            log::warn!("no UNORM surface format available, using {:?}", surface_format.format);
+        }
 
         log::info!("{:?}", surface_format);
 
-        // Validate surface caps
+        // Buffered frame count is validated against surface caps during device creation
         let surface_caps = unsafe {
             surface_loader
                 .get_physical_device_surface_capabilities(device.get_physical_device(), surface_khr)
@@ -62,8 +70,7 @@ impl SurfaceWinit {
         };
 
         //let image_count = surface_caps.min_image_count + 1;
-        let image_count = NUM_BUFFERED_GPU_FRAMES as u32;
-        assert!(image_count >= surface_caps.min_image_count && image_count < surface_caps.max_image_count);
+        let image_count = device.get_num_buffered_frames() as u32;
 
         let pre_transform = if surface_caps
             .supported_transforms
@@ -82,12 +89,18 @@ impl SurfaceWinit {
                     .unwrap()
             };
 
-            present_modes
+            let preferred_modes: &[vk::PresentModeKHR] = if vsync {
+                &[vk::PresentModeKHR::FIFO]
+            } else {
+                &[vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::IMMEDIATE]
+            };
+            preferred_modes
                 .iter()
                 .cloned()
-                .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
+                .find(|mode| present_modes.contains(mode))
                 .unwrap_or(vk::PresentModeKHR::FIFO)
         };
+        log::info!("{:?}", present_mode);
 
         let swapchain_loader = ash::extensions::khr::Swapchain::new(device.get_instance(), device.get_device());
 
@@ -130,6 +143,8 @@ impl SurfaceWinit {
         Self {
             internal_surface,
             internal_swapchain,
+            lost: false,
+            out_of_date: false,
         }
     }
 
@@ -141,15 +156,32 @@ impl SurfaceWinit {
         }
     }
 
-    pub fn acquire_next_image(&mut self, timeout: u64, image_ready_semaphore: vk::Semaphore) -> u32 {
+    // Returns None if the surface or the device got lost or the swapchain has to be created again
+    pub fn acquire_next_image(&mut self, timeout: u64, image_ready_semaphore: vk::Semaphore) -> Option<u32> {
         let swapchain = &self.internal_swapchain.swapchain;
-        let (image_index, _) = unsafe {
-            self.internal_swapchain
-                .loader
-                .acquire_next_image(*swapchain, timeout, image_ready_semaphore, vk::Fence::null())
-                .expect("acquire_next_image() failed")
+        let result = unsafe {
+            self.internal_swapchain.loader.acquire_next_image(
+                *swapchain,
+                timeout,
+                image_ready_semaphore,
+                vk::Fence::null(),
+            )
         };
-        image_index
+        match result {
+            Ok((image_index, suboptimal)) => {
+                self.out_of_date |= suboptimal;
+                Some(image_index)
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.out_of_date = true;
+                None
+            }
+            Err(err @ vk::Result::ERROR_DEVICE_LOST) | Err(err @ vk::Result::ERROR_SURFACE_LOST_KHR) => {
+                self.mark_lost("acquire_next_image()", err);
+                None
+            }
+            Err(err) => panic!("acquire_next_image() failed: {:?}", err),
+        }
     }
 
     pub fn present(&mut self, queue: &mut DeviceQueue, frame_ready_semaphore: vk::Semaphore, image_index: u32) {
@@ -161,14 +193,36 @@ impl SurfaceWinit {
             //.results(results: &'a mut [Result])
             .build();
 
-        unsafe {
+        let result = unsafe {
             self.internal_swapchain
                 .loader
                 .queue_present(queue.clone().into(), &present_info)
-                .expect("queue_present() failed");
+        };
+        match result {
+            Ok(suboptimal) => self.out_of_date |= suboptimal,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.out_of_date = true,
+            Err(err @ vk::Result::ERROR_DEVICE_LOST) | Err(err @ vk::Result::ERROR_SURFACE_LOST_KHR) => {
+                self.mark_lost("queue_present()", err)
+            }
+            Err(err) => panic!("queue_present() failed: {:?}", err),
         }
     }
 
+    // Lost surface can't be used anymore, it has to be created again along with the device
+    pub fn is_lost(&self) -> bool {
+        self.lost
+    }
+
+    // Swapchain doesn't match the surface anymore, e.g. after the window was restored
+    pub fn is_out_of_date(&self) -> bool {
+        self.out_of_date
+    }
+
+    fn mark_lost(&mut self, function_name: &str, err: vk::Result) {
+        log::error!("{} failed: {:?}", function_name, err);
+        self.lost = true;
+    }
+
     pub fn get_surface_format(&self) -> vk::Format {
         self.internal_surface.format.format
     }
@@ -199,16 +253,3 @@ struct InternalSwapchain {
     swapchain: vk::SwapchainKHR,
     present_mode: vk::PresentModeKHR,
 }
-
-pub fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
-    entry: &E,
-    instance: &I,
-    window: &winit::window::Window,
-) -> Result<(Option<ash::extensions::khr::Surface>, vk::SurfaceKHR), vk::Result> {
-    unsafe {
-        let surface_loader = ash::extensions::khr::Surface::new(entry, instance);
-        let surface = ash_window::create_surface(entry, instance, window, None)?;
-
-        Ok((Some(surface_loader), surface))
-    }
-}
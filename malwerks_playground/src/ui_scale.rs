@@ -0,0 +1,28 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+const FONT_SIZE: f32 = 13.0;
+
+// UI scale override from the settings replaces the window scale factor
+pub fn get_ui_scale(ui_scale_override: Option<f32>, scale_factor: f64) -> f32 {
+    ui_scale_override.unwrap_or(scale_factor as f32).clamp(0.5, 4.0)
+}
+
+// ImGui works in physical pixels, so the font atlas is rebuilt at the scaled size to stay sharp and style sizes
+// are scaled from the unscaled base style. Font texture has to be uploaded again afterwards.
+pub fn apply_ui_scale(imgui: &mut imgui::Context, base_style: &imgui::Style, ui_scale: f32) {
+    log::info!("ui scale: {}", ui_scale);
+
+    *imgui.style_mut() = *base_style;
+    imgui.style_mut().scale_all_sizes(ui_scale);
+
+    let mut fonts = imgui.fonts();
+    fonts.clear();
+    fonts.add_font(&[imgui::FontSource::TtfData {
+        data: include_bytes!("../../assets/fonts/Roboto-Regular.ttf"),
+        size_pixels: (FONT_SIZE * ui_scale).round(),
+        config: None,
+    }]);
+}
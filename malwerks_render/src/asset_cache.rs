@@ -0,0 +1,354 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::StableHasher;
+
+pub const DEFAULT_ASSET_CACHE_SIZE_BUDGET: u64 = 1024 * 1024 * 1024;
+const MANIFEST_FILE_NAME: &str = "asset_cache.json";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum AssetCacheKind {
+    Thumbnails,
+    ShaderCache,
+    PipelineCache,
+    ImportManifest,
+}
+
+impl AssetCacheKind {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            AssetCacheKind::Thumbnails => "thumbnails",
+            AssetCacheKind::ShaderCache => "shader_cache",
+            AssetCacheKind::PipelineCache => "pipeline_cache",
+            AssetCacheKind::ImportManifest => "import_manifest",
+        }
+    }
+}
+
+// Written when a bundle is imported from glTF, describes where the cached bundle came from
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AssetImportManifest {
+    pub gltf_file: std::path::PathBuf,
+    pub bundle_file: std::path::PathBuf,
+    pub import_time: u64, // seconds since UNIX epoch
+    pub compression_level: u32,
+    pub pack_mesh_geometry: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct AssetCacheEntry {
+    bundle_name: String,
+    kind: AssetCacheKind,
+    file_name: String,
+    size: u64,
+    last_access: u64, // value of `access_counter` when the entry was last used
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct AssetCacheManifest {
+    access_counter: u64,
+    entries: Vec<AssetCacheEntry>,
+}
+
+// Derived data that can be produced again from the source assets, stored per bundle under the cache folder.
+// Least recently used entries are evicted when the total size exceeds the budget, the most recently used
+// one is always kept. Entries and their access order are kept in a manifest file between runs.
+pub struct AssetCache {
+    cache_folder: std::path::PathBuf,
+    size_budget: u64,
+    access_counter: u64,
+    entries: Vec<AssetCacheEntry>,
+    manifest_dirty: bool, // access order changed since the manifest was last written
+}
+
+impl AssetCache {
+    // Entries without a file are dropped, files without an entry are left alone
+    pub fn new(cache_folder: &std::path::Path, size_budget: u64) -> Self {
+        std::fs::create_dir_all(cache_folder).expect("failed to create asset cache folder");
+
+        let manifest_file = cache_folder.join(MANIFEST_FILE_NAME);
+        let manifest: AssetCacheManifest = match std::fs::File::open(&manifest_file) {
+            Ok(file) => serde_json::from_reader(std::io::BufReader::new(file)).unwrap_or_else(|error| {
+                log::warn!("failed to parse {:?}: {:?}", manifest_file, error);
+                AssetCacheManifest::default()
+            }),
+            Err(_) => AssetCacheManifest::default(),
+        };
+
+        let mut asset_cache = Self {
+            cache_folder: cache_folder.to_path_buf(),
+            size_budget,
+            access_counter: manifest.access_counter,
+            entries: Vec::with_capacity(manifest.entries.len()),
+            manifest_dirty: false,
+        };
+        for entry in manifest.entries {
+            if asset_cache
+                .get_entry_path(&entry.bundle_name, entry.kind, &entry.file_name)
+                .is_file()
+            {
+                asset_cache.entries.push(entry);
+            }
+        }
+        asset_cache.evict();
+        asset_cache.save_manifest();
+        asset_cache
+    }
+
+    pub fn get_cache_folder(&self) -> &std::path::Path {
+        &self.cache_folder
+    }
+
+    pub fn get_size_budget(&self) -> u64 {
+        self.size_budget
+    }
+
+    pub fn set_size_budget(&mut self, size_budget: u64) {
+        self.size_budget = size_budget;
+        self.evict();
+        self.save_manifest();
+    }
+
+    pub fn get_total_size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    // Sorted by bundle name
+    pub fn get_bundle_sizes(&self) -> Vec<(String, u64)> {
+        let mut bundle_sizes: Vec<(String, u64)> = Vec::new();
+        for entry in &self.entries {
+            match bundle_sizes.iter_mut().find(|(name, _)| *name == entry.bundle_name) {
+                Some((_, size)) => *size += entry.size,
+                None => bundle_sizes.push((entry.bundle_name.clone(), entry.size)),
+            }
+        }
+        bundle_sizes.sort_by(|a, b| a.0.cmp(&b.0));
+        bundle_sizes
+    }
+
+    pub fn get_bundle_kind_size(&self, bundle_name: &str, kind: AssetCacheKind) -> u64 {
+        self.entries
+            .iter()
+            .filter(|entry| entry.bundle_name == bundle_name && entry.kind == kind)
+            .map(|entry| entry.size)
+            .sum()
+    }
+
+    // The file may not exist yet, it has to be added with `insert()` after it is written
+    pub fn get_entry_path(&self, bundle_name: &str, kind: AssetCacheKind, file_name: &str) -> std::path::PathBuf {
+        self.cache_folder
+            .join(bundle_name)
+            .join(kind.get_name())
+            .join(file_name)
+    }
+
+    pub fn contains(&self, bundle_name: &str, kind: AssetCacheKind, file_name: &str) -> bool {
+        self.find_entry(bundle_name, kind, file_name).is_some()
+    }
+
+    // Marks the entry as used, nothing happens if it is not cached.
+    // The manifest is written on the next insert, eviction or when the cache is dropped.
+    pub fn touch(&mut self, bundle_name: &str, kind: AssetCacheKind, file_name: &str) {
+        if let Some(index) = self.find_entry(bundle_name, kind, file_name) {
+            self.access_counter += 1;
+            self.entries[index].last_access = self.access_counter;
+            self.manifest_dirty = true;
+        }
+    }
+
+    // Adds a file that was written to `get_entry_path()` or updates its size if it is cached already.
+    // Files that can't be queried are not cached.
+    pub fn insert(&mut self, bundle_name: &str, kind: AssetCacheKind, file_name: &str) {
+        let entry_path = self.get_entry_path(bundle_name, kind, file_name);
+        let size = match std::fs::metadata(&entry_path) {
+            Ok(metadata) => metadata.len(),
+            Err(error) => {
+                log::error!("failed to query {:?}: {:?}", entry_path, error);
+                if let Some(index) = self.find_entry(bundle_name, kind, file_name) {
+                    self.entries.swap_remove(index);
+                    self.save_manifest();
+                }
+                return;
+            }
+        };
+
+        self.access_counter += 1;
+        let last_access = self.access_counter;
+        match self.find_entry(bundle_name, kind, file_name) {
+            Some(index) => {
+                self.entries[index].size = size;
+                self.entries[index].last_access = last_access;
+            }
+            None => self.entries.push(AssetCacheEntry {
+                bundle_name: bundle_name.to_string(),
+                kind,
+                file_name: file_name.to_string(),
+                size,
+                last_access,
+            }),
+        }
+        self.evict();
+        self.save_manifest();
+    }
+
+    pub fn read(&mut self, bundle_name: &str, kind: AssetCacheKind, file_name: &str) -> Option<Vec<u8>> {
+        self.find_entry(bundle_name, kind, file_name)?;
+        match std::fs::read(self.get_entry_path(bundle_name, kind, file_name)) {
+            Ok(data) => {
+                self.touch(bundle_name, kind, file_name);
+                Some(data)
+            }
+            Err(error) => {
+                log::warn!("failed to read cached {}/{}: {:?}", bundle_name, file_name, error);
+                self.remove(bundle_name, kind, file_name);
+                None
+            }
+        }
+    }
+
+    // Failures are logged and leave the entry uncached, the data can always be produced again
+    pub fn write(&mut self, bundle_name: &str, kind: AssetCacheKind, file_name: &str, data: &[u8]) {
+        let entry_path = self.get_entry_path(bundle_name, kind, file_name);
+        let result =
+            std::fs::create_dir_all(entry_path.parent().unwrap()).and_then(|_| std::fs::write(&entry_path, data));
+        if let Err(error) = result {
+            log::error!("failed to write {:?}: {:?}", entry_path, error);
+            self.remove(bundle_name, kind, file_name);
+            return;
+        }
+        self.insert(bundle_name, kind, file_name);
+    }
+
+    pub fn remove(&mut self, bundle_name: &str, kind: AssetCacheKind, file_name: &str) {
+        if let Some(index) = self.find_entry(bundle_name, kind, file_name) {
+            self.remove_entry(index);
+            self.save_manifest();
+        }
+    }
+
+    pub fn clear_bundle(&mut self, bundle_name: &str) {
+        log::info!("clearing asset cache of \"{}\"", bundle_name);
+        self.retain_entries(|entry| entry.bundle_name != bundle_name);
+    }
+
+    pub fn clear_bundle_kind(&mut self, bundle_name: &str, kind: AssetCacheKind) {
+        self.retain_entries(|entry| entry.bundle_name != bundle_name || entry.kind != kind);
+    }
+
+    pub fn clear_all(&mut self) {
+        log::info!("clearing asset cache");
+        self.retain_entries(|_| false);
+    }
+
+    fn find_entry(&self, bundle_name: &str, kind: AssetCacheKind, file_name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.bundle_name == bundle_name && entry.kind == kind && entry.file_name == file_name)
+    }
+
+    fn retain_entries<F>(&mut self, mut func: F)
+    where
+        F: FnMut(&AssetCacheEntry) -> bool,
+    {
+        let mut index = 0;
+        while index != self.entries.len() {
+            if func(&self.entries[index]) {
+                index += 1;
+            } else {
+                self.remove_entry(index);
+            }
+        }
+        self.save_manifest();
+    }
+
+    fn remove_entry(&mut self, index: usize) {
+        let entry = self.entries.swap_remove(index);
+        let entry_path = self.get_entry_path(&entry.bundle_name, entry.kind, &entry.file_name);
+        if let Err(error) = std::fs::remove_file(&entry_path) {
+            log::warn!("failed to remove {:?}: {:?}", entry_path, error);
+        }
+    }
+
+    fn evict(&mut self) {
+        let mut total_size = self.get_total_size();
+        while total_size > self.size_budget && self.entries.len() > 1 {
+            let (index, _) = self
+                .entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, entry)| entry.last_access)
+                .unwrap();
+            log::info!(
+                "evicting {}/{}/{} from asset cache",
+                self.entries[index].bundle_name,
+                self.entries[index].kind.get_name(),
+                self.entries[index].file_name
+            );
+            total_size -= self.entries[index].size;
+            self.remove_entry(index);
+        }
+    }
+
+    fn save_manifest(&mut self) {
+        self.manifest_dirty = false;
+
+        let manifest_file = self.cache_folder.join(MANIFEST_FILE_NAME);
+        let result = serde_json::to_vec_pretty(&AssetCacheManifest {
+            access_counter: self.access_counter,
+            entries: self.entries.clone(),
+        })
+        .map_err(std::io::Error::from)
+        .and_then(|data| std::fs::write(&manifest_file, data));
+        if let Err(error) = result {
+            log::error!("failed to write {:?}: {:?}", manifest_file, error);
+        }
+    }
+}
+
+impl Drop for AssetCache {
+    fn drop(&mut self) {
+        if self.manifest_dirty {
+            self.save_manifest();
+        }
+    }
+}
+
+// Bundles with the same file name in different folders are told apart by a hash of the canonical path
+pub fn get_asset_cache_bundle_name(bundle_file: &std::path::Path) -> String {
+    let file_stem = bundle_file
+        .file_stem()
+        .and_then(|file_stem| file_stem.to_str())
+        .expect("bundle file has no name");
+
+    let mut hasher = StableHasher::new();
+    hasher.write_str(&canonicalize_bundle_file(bundle_file).to_string_lossy());
+    format!("{}_{:016x}", file_stem, hasher.finish())
+}
+
+// The bundle file may not be written yet, so the closest existing folder is canonicalized
+// and the rest of the path is appended to it. This keeps the name the same before and after import.
+fn canonicalize_bundle_file(bundle_file: &std::path::Path) -> std::path::PathBuf {
+    let mut existing_path = bundle_file;
+    let mut missing_components = Vec::new();
+    loop {
+        if let Ok(canonical_path) = std::fs::canonicalize(existing_path) {
+            return missing_components
+                .iter()
+                .rev()
+                .fold(canonical_path, |path, component| path.join(component));
+        }
+        match (existing_path.parent(), existing_path.file_name()) {
+            (Some(parent), Some(file_name)) => {
+                missing_components.push(file_name);
+                existing_path = if parent.as_os_str().is_empty() {
+                    std::path::Path::new(".")
+                } else {
+                    parent
+                };
+            }
+            _ => return bundle_file.to_path_buf(),
+        }
+    }
+}
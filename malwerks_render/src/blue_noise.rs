@@ -0,0 +1,190 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+use malwerks_vk::*;
+
+// Has to be a power of two, shaders wrap texel coordinates with a mask
+pub const BLUE_NOISE_SIZE: usize = 32;
+pub const BLUE_NOISE_LAYER_COUNT: usize = 16;
+
+// Energy filter of the void and cluster method, layers are the time axis of spatiotemporal blue noise
+const SPATIAL_SIGMA: f32 = 1.9;
+const TEMPORAL_SIGMA: f32 = 1.9;
+const FILTER_RADIUS: usize = 6;
+
+// Every layer is blue noise on its own and every texel is blue noise over the layers.
+// Layers are stored one after another in an R8 image, shaders are expected to use texelFetch.
+pub fn bake_blue_noise(size: usize, layer_count: usize) -> DiskImage {
+    let ranks = generate_blue_noise_ranks(size, layer_count);
+    let layer_size = size * size;
+    let pixels = ranks
+        .iter()
+        .map(|rank| ((*rank as usize * 256) / layer_size) as u8)
+        .collect();
+
+    DiskImage {
+        width: size as _,
+        height: size as _,
+        depth: 1,
+        block_size: 1,
+        mipmap_count: 1,
+        layer_count,
+        image_type: vk::ImageType::TYPE_2D.as_raw(),
+        view_type: vk::ImageViewType::TYPE_2D_ARRAY.as_raw(),
+        format: vk::Format::R8_UNORM.as_raw(),
+        color_space: DiskColorSpace::Linear,
+        generate_mipmaps: false,
+        pixels,
+    }
+}
+
+// Void and cluster ranks of a toroidal volume, ranks of every layer are a permutation of 0..size * size
+pub fn generate_blue_noise_ranks(size: usize, layer_count: usize) -> Vec<u32> {
+    assert!(size.is_power_of_two(), "blue noise size has to be a power of two");
+    assert!(layer_count > 0, "blue noise needs at least one layer");
+
+    let mut volume = EnergyVolume::new(size, layer_count);
+    let texel_count = volume.energy.len();
+
+    // Deterministic initial pattern, the same noise is generated on every import
+    let mut random_state = 0x9e37_79b9u32;
+    let initial_count = (texel_count / 10).max(1);
+    let mut placed_count = 0;
+    while placed_count < initial_count {
+        random_state ^= random_state << 13;
+        random_state ^= random_state >> 17;
+        random_state ^= random_state << 5;
+        let texel = random_state as usize % texel_count;
+        if !volume.occupied[texel] {
+            volume.insert(texel);
+            placed_count += 1;
+        }
+    }
+
+    // Points are moved from the tightest clusters to the largest voids until the pattern is stable
+    for _ in 0..texel_count {
+        let cluster = volume.find_tightest_cluster();
+        volume.remove(cluster);
+        let void = volume.find_largest_void();
+        volume.insert(void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    // Initial points are ranked by removing them, the remaining texels by filling the largest voids
+    let mut ranks = vec![0u32; texel_count];
+    let mut removal_volume = volume.clone();
+    for rank in (0..initial_count).rev() {
+        let cluster = removal_volume.find_tightest_cluster();
+        removal_volume.remove(cluster);
+        ranks[cluster] = rank as _;
+    }
+    for rank in initial_count..texel_count {
+        let void = volume.find_largest_void();
+        volume.insert(void);
+        ranks[void] = rank as _;
+    }
+
+    // Ranks are made unique within every layer, so that every layer covers the whole value range
+    let layer_size = size * size;
+    for layer in ranks.chunks_mut(layer_size) {
+        let mut order: Vec<usize> = (0..layer_size).collect();
+        order.sort_by_key(|texel| layer[*texel]);
+        for (layer_rank, texel) in order.into_iter().enumerate() {
+            layer[texel] = layer_rank as _;
+        }
+    }
+    ranks
+}
+
+#[derive(Clone)]
+struct EnergyVolume {
+    size: usize,
+    layer_count: usize,
+    energy: Vec<f32>,
+    occupied: Vec<bool>,
+    filter: Vec<(isize, isize, isize, f32)>,
+}
+
+impl EnergyVolume {
+    fn new(size: usize, layer_count: usize) -> Self {
+        // Texels only repel each other within the same layer or at the same position in different layers.
+        // Filter is cut off before it wraps around and reaches the same texel twice.
+        let spatial_radius = FILTER_RADIUS.min((size - 1) / 2) as isize;
+        let temporal_radius = FILTER_RADIUS.min((layer_count - 1) / 2) as isize;
+        let mut filter = Vec::new();
+        for z in -temporal_radius..=temporal_radius {
+            for y in -spatial_radius..=spatial_radius {
+                for x in -spatial_radius..=spatial_radius {
+                    if z != 0 && (x != 0 || y != 0) {
+                        continue;
+                    }
+                    let spatial_distance = (x * x + y * y) as f32;
+                    let temporal_distance = (z * z) as f32;
+                    let weight = (-spatial_distance / (2.0 * SPATIAL_SIGMA * SPATIAL_SIGMA)
+                        - temporal_distance / (2.0 * TEMPORAL_SIGMA * TEMPORAL_SIGMA))
+                        .exp();
+                    filter.push((x, y, z, weight));
+                }
+            }
+        }
+
+        let texel_count = size * size * layer_count;
+        Self {
+            size,
+            layer_count,
+            energy: vec![0.0; texel_count],
+            occupied: vec![false; texel_count],
+            filter,
+        }
+    }
+
+    fn insert(&mut self, texel: usize) {
+        self.occupied[texel] = true;
+        self.splat(texel, 1.0);
+    }
+
+    fn remove(&mut self, texel: usize) {
+        self.occupied[texel] = false;
+        self.splat(texel, -1.0);
+    }
+
+    fn splat(&mut self, texel: usize, sign: f32) {
+        let size = self.size as isize;
+        let layer_count = self.layer_count as isize;
+        let x = texel as isize % size;
+        let y = (texel as isize / size) % size;
+        let z = texel as isize / (size * size);
+        for (offset_x, offset_y, offset_z, weight) in &self.filter {
+            let target_x = (x + offset_x) & (size - 1);
+            let target_y = (y + offset_y) & (size - 1);
+            let target_z = (z + offset_z).rem_euclid(layer_count);
+            let target = (target_z * size * size + target_y * size + target_x) as usize;
+            self.energy[target] += sign * weight;
+        }
+    }
+
+    fn find_tightest_cluster(&self) -> usize {
+        let mut result = (0, f32::MIN);
+        for (texel, energy) in self.energy.iter().enumerate() {
+            if self.occupied[texel] && *energy > result.1 {
+                result = (texel, *energy);
+            }
+        }
+        result.0
+    }
+
+    fn find_largest_void(&self) -> usize {
+        let mut result = (0, f32::MAX);
+        for (texel, energy) in self.energy.iter().enumerate() {
+            if !self.occupied[texel] && *energy < result.1 {
+                result = (texel, *energy);
+            }
+        }
+        result.0
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+
+const BRDF_LUT_SIZE: u32 = 256;
+const BRDF_LUT_SAMPLE_COUNT: u32 = 4096;
+const BRDF_LUT_GROUP_SIZE: u32 = 8;
+
+// Integrates the split-sum environment BRDF on the GPU and reads it back, replaces brdf.dds made by precompute_brdf
+pub fn compute_brdf_lut(
+    common_shaders: &DiskCommonShaders,
+    command_buffer: &mut CommandBuffer,
+    factory: &mut DeviceFactory,
+    queue: &mut DeviceQueue,
+) -> DiskImage {
+    let texel_size = 2 * std::mem::size_of::<f32>();
+    let lut_buffer_size = (BRDF_LUT_SIZE * BRDF_LUT_SIZE) as usize * texel_size;
+    let lut_buffer = factory.allocate_buffer(
+        &vk::BufferCreateInfo::builder()
+            .size(lut_buffer_size as _)
+            .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+            .build(),
+        &vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuToCpu,
+            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE,
+            ..Default::default()
+        },
+    );
+
+    let descriptor_set_layout = factory.create_descriptor_set_layout(
+        &vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&[vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build()])
+            .build(),
+    );
+    let descriptor_pool = factory.create_descriptor_pool(
+        &vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&[vk::DescriptorPoolSize::builder()
+                .ty(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .build()])
+            .build(),
+    );
+    let descriptor_set = factory.allocate_descriptor_sets(
+        &vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&[descriptor_set_layout])
+            .build(),
+    )[0];
+    factory.update_descriptor_sets(
+        &[vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&[vk::DescriptorBufferInfo::builder()
+                .buffer(lut_buffer.0)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build()])
+            .build()],
+        &[],
+    );
+
+    let compute_module = factory.create_shader_module(
+        &vk::ShaderModuleCreateInfo::builder()
+            .code(&common_shaders.brdf_lut_compute_stage)
+            .build(),
+    );
+    let pipeline_layout = factory.create_pipeline_layout(
+        &vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&[descriptor_set_layout])
+            .push_constant_ranges(&[vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .offset(0)
+                .size(std::mem::size_of::<[u32; 3]>() as _)
+                .build()])
+            .build(),
+    );
+    let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+    let pipeline = factory.create_compute_pipelines(
+        vk::PipelineCache::null(),
+        &[vk::ComputePipelineCreateInfo::builder()
+            .stage(
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .name(&entry_name)
+                    .module(compute_module)
+                    .stage(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+            )
+            .layout(pipeline_layout)
+            .build()],
+    )[0];
+
+    command_buffer.reset();
+    command_buffer.begin(
+        &vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build(),
+    );
+    command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, pipeline);
+    command_buffer.bind_descriptor_sets(
+        vk::PipelineBindPoint::COMPUTE,
+        pipeline_layout,
+        0,
+        &[descriptor_set],
+        &[],
+    );
+    command_buffer.push_constants(
+        pipeline_layout,
+        vk::ShaderStageFlags::COMPUTE,
+        0,
+        &[BRDF_LUT_SIZE, BRDF_LUT_SIZE, BRDF_LUT_SAMPLE_COUNT],
+    );
+    let group_count = BRDF_LUT_SIZE.div_ceil(BRDF_LUT_GROUP_SIZE);
+    command_buffer.dispatch(group_count, group_count, 1);
+    command_buffer.pipeline_barrier(
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::HOST,
+        None,
+        &[vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .build()],
+        &[],
+        &[],
+    );
+    command_buffer.end();
+
+    queue.submit(
+        &[vk::SubmitInfo::builder()
+            .command_buffers(&[(*command_buffer).into()])
+            .build()],
+        vk::Fence::null(),
+    );
+    queue.wait_idle();
+
+    let mut pixels = vec![0u8; lut_buffer_size];
+    let lut_memory = factory.map_allocation_memory(&lut_buffer);
+    unsafe {
+        std::ptr::copy_nonoverlapping(lut_memory, pixels.as_mut_ptr(), pixels.len());
+    }
+    factory.unmap_allocation_memory(&lut_buffer);
+
+    factory.destroy_pipeline(pipeline);
+    factory.destroy_pipeline_layout(pipeline_layout);
+    factory.destroy_shader_module(compute_module);
+    factory.destroy_descriptor_pool(descriptor_pool);
+    factory.destroy_descriptor_set_layout(descriptor_set_layout);
+    factory.deallocate_buffer(&lut_buffer);
+
+    DiskImage {
+        width: BRDF_LUT_SIZE,
+        height: BRDF_LUT_SIZE,
+        depth: 1,
+        block_size: texel_size,
+        mipmap_count: 1,
+        layer_count: 1,
+        image_type: vk::ImageType::TYPE_2D.as_raw(),
+        view_type: vk::ImageViewType::TYPE_2D.as_raw(),
+        format: vk::Format::R32G32_SFLOAT.as_raw(),
+        color_space: DiskColorSpace::Linear,
+        generate_mipmaps: false,
+        pixels,
+    }
+}
@@ -11,11 +11,20 @@ use malwerks_bundles::*;
 use malwerks_external::*;
 use malwerks_gltf::*;
 
+use crate::asset_cache::*;
+use crate::blue_noise::*;
+use crate::brdf_lut::*;
 use crate::common_shaders::*;
+use crate::environment_convolution::*;
+use crate::equirectangular_environment::*;
+use crate::ies_profile::*;
 use crate::material_shaders::*;
 use crate::pbr_resource_bundle::*;
 
 use crate::imgui_renderer::*;
+use crate::material_preview::*;
+
+const IMPORT_MANIFEST_FILE_NAME: &str = "import_manifest.json";
 
 pub type ResourceBundleReference = std::rc::Rc<std::cell::RefCell<ResourceBundle>>;
 pub type PbrResourceBundleReference = std::rc::Rc<std::cell::RefCell<PbrResourceBundle>>;
@@ -53,6 +62,43 @@ pub struct BundleLoaderParameters<'a> {
     pub pbr_resource_folder: &'a std::path::Path,
     pub force_import_bundles: bool,
     pub force_compile_shaders: bool,
+    pub pack_mesh_geometry: bool, // applied when loading bundles that were imported without it
+    pub vertex_pulling: bool,
+    pub compress_environment_probes: bool, // BC6H for IEM and PMREM convolved on the GPU, needs texconv
+    pub shader_debug_printf: bool,         // compiles shaders with SHADER_DEBUG_PRINTF, needs device support
+    pub stress_instance_count: usize,      // replaces loaded scenes with a grid of stress_mesh instances, 0 disables it
+    pub stress_mesh: usize,
+    pub asset_cache_size_budget: u64, // bytes of shader caches, pipeline caches and thumbnails
+}
+
+// Options shared by every scene import, taken from BundleLoaderParameters
+struct BundleImportOptions {
+    temporary_folder: std::path::PathBuf,
+    compression_level: u32,
+    force_import: bool,
+    pack_mesh_geometry: bool,
+    stress_scene: Option<(MeshHandle, usize)>,
+}
+
+impl BundleImportOptions {
+    fn new(parameters: &BundleLoaderParameters) -> Self {
+        let stress_scene = if parameters.stress_instance_count > 0 {
+            Some((
+                MeshHandle::new(parameters.stress_mesh),
+                parameters.stress_instance_count,
+            ))
+        } else {
+            None
+        };
+
+        Self {
+            temporary_folder: parameters.temporary_folder.to_path_buf(),
+            compression_level: parameters.bundle_compression_level,
+            force_import: parameters.force_import_bundles,
+            pack_mesh_geometry: parameters.pack_mesh_geometry,
+            stress_scene,
+        }
+    }
 }
 
 pub struct BundleLoader {
@@ -64,11 +110,13 @@ pub struct BundleLoader {
     resource_bundles: Vec<InternalBundleReference>,
 
     bundle_remove_queue: Vec<(isize, QueuedBundle)>,
+    num_buffered_frames: usize,
+    asset_cache: AssetCache,
 
     base_path: std::path::PathBuf,
-    temporary_folder: std::path::PathBuf,
-    compression_level: u32,
-    force_import_bundles: bool,
+    import_options: BundleImportOptions,
+    vertex_pulling: bool,
+    shader_debug_printf: bool,
 }
 
 impl BundleLoader {
@@ -97,12 +145,17 @@ impl BundleLoader {
             parameters.shader_bundle_path,
             parameters.bundle_compression_level,
             parameters.force_compile_shaders,
+            parameters.shader_debug_printf,
         );
         let pbr_resource_bundle = std::rc::Rc::new(std::cell::RefCell::new(import_pbr_resource_bundle(
-            &parameters.temporary_folder.join("pbr_resource_bundle"),
-            parameters.pbr_resource_folder,
-            parameters.bundle_compression_level,
-            parameters.force_import_bundles,
+            &PbrResourceImportParameters {
+                temporary_path: &parameters.temporary_folder.join("pbr_resource_bundle"),
+                input_path: parameters.pbr_resource_folder,
+                compression_level: parameters.bundle_compression_level,
+                force_import: parameters.force_import_bundles,
+                compress_probes: parameters.compress_environment_probes,
+            },
+            &common_shaders,
             &mut command_buffers[0],
             device,
             factory,
@@ -110,11 +163,16 @@ impl BundleLoader {
         )));
         let resource_bundles = Vec::new();
         let bundle_remove_queue = Vec::new();
+        let num_buffered_frames = factory.get_num_buffered_frames();
+        let asset_cache = AssetCache::new(
+            &parameters.temporary_folder.join("asset_cache"),
+            parameters.asset_cache_size_budget,
+        );
 
         let base_path = parameters.base_path.to_path_buf();
-        let temporary_folder = parameters.temporary_folder.to_path_buf();
-        let compression_level = parameters.bundle_compression_level;
-        let force_import_bundles = parameters.force_import_bundles;
+        let import_options = BundleImportOptions::new(parameters);
+        let vertex_pulling = parameters.vertex_pulling;
+        let shader_debug_printf = parameters.shader_debug_printf;
 
         Self {
             command_pool,
@@ -123,10 +181,12 @@ impl BundleLoader {
             pbr_resource_bundle,
             resource_bundles,
             bundle_remove_queue,
+            num_buffered_frames,
+            asset_cache,
             base_path,
-            temporary_folder,
-            compression_level,
-            force_import_bundles,
+            import_options,
+            vertex_pulling,
+            shader_debug_printf,
         }
     }
 
@@ -153,6 +213,14 @@ impl BundleLoader {
         &mut self.command_buffers[0]
     }
 
+    pub fn get_asset_cache(&self) -> &AssetCache {
+        &self.asset_cache
+    }
+
+    pub fn get_asset_cache_mut(&mut self) -> &mut AssetCache {
+        &mut self.asset_cache
+    }
+
     pub fn get_common_shaders(&self) -> &DiskCommonShaders {
         &self.common_shaders
     }
@@ -160,6 +228,14 @@ impl BundleLoader {
     pub fn get_pbr_resource_bundle(&self) -> PbrResourceBundleReference {
         self.pbr_resource_bundle.clone()
     }
+
+    pub fn uses_vertex_pulling(&self) -> bool {
+        self.vertex_pulling
+    }
+
+    pub fn uses_shader_debug_printf(&self) -> bool {
+        self.shader_debug_printf
+    }
 }
 
 impl BundleLoader {
@@ -180,21 +256,29 @@ impl BundleLoader {
         {
             bundle_index
         } else {
+            // Everything derived from the previous import is stale
+            let is_imported = self.import_options.force_import || !bundle_file.exists();
+            let bundle_name = get_asset_cache_bundle_name(bundle_file);
+            if is_imported {
+                self.asset_cache.clear_bundle(&bundle_name);
+            }
+
             let bundle_index = self.resource_bundles.len();
             self.resource_bundles.push(InternalBundleReference {
                 bundle_file: bundle_file.to_path_buf(),
                 bundle: std::rc::Rc::new(std::cell::RefCell::new(import_bundle(
-                    &self.temporary_folder.join(bundle_file),
                     gltf_file,
                     bundle_file,
-                    self.compression_level,
-                    self.force_import_bundles,
+                    &self.import_options,
                     &mut self.command_buffers[0],
                     device,
                     factory,
                     queue,
                 ))),
             });
+            if is_imported {
+                self.write_import_manifest(&bundle_name, gltf_file, bundle_file);
+            }
             bundle_index
         };
 
@@ -202,7 +286,7 @@ impl BundleLoader {
     }
 
     pub fn queue_destroy_bundle(&mut self, bundle: QueuedBundle) {
-        self.bundle_remove_queue.push((NUM_BUFFERED_GPU_FRAMES as _, bundle));
+        self.bundle_remove_queue.push((self.num_buffered_frames as _, bundle));
     }
 
     pub fn begin_frame(&mut self, _frame_context: &FrameContext, factory: &mut DeviceFactory) {
@@ -232,39 +316,137 @@ impl BundleLoader {
         }
     }
 
+    // Cached shaders are compiled again if the shader folder has changed since, the cache is only
+    // replaced when compilation succeeds and errors are returned with the compiler log
     pub fn compile_shader_module_bundle(
-        &self,
+        &mut self,
         resource_bundle: &ResourceBundleReference,
         bundle_file: &std::path::Path,
         shader_file: &std::path::Path,
+        macro_definitions: &[(&str, &str)],
+        alpha_blend_macro_definitions: Option<&[(&str, &str)]>,
         factory: &mut DeviceFactory,
-    ) -> ShaderModuleBundle {
+    ) -> Result<ShaderModuleBundle, String> {
         let resource_bundle = resource_bundle.borrow();
-        let disk_shader_stage = if !bundle_file.exists() {
+
+        // Shader variants are cached separately
+        let mut bundle_file = bundle_file.to_path_buf();
+        if self.vertex_pulling {
+            bundle_file = append_bundle_extension(&bundle_file, "vertex_pulling");
+            if resource_bundle.uses_buffer_device_address() {
+                bundle_file = append_bundle_extension(&bundle_file, "buffer_device_address");
+            }
+        }
+        let mut macro_definitions = macro_definitions.to_vec();
+        if self.shader_debug_printf {
+            bundle_file = append_bundle_extension(&bundle_file, "debug_printf");
+            macro_definitions.push(("SHADER_DEBUG_PRINTF", "1"));
+        }
+        let bundle_name = get_asset_cache_bundle_name(&bundle_file);
+        let cache_file_name = bundle_file.file_name().unwrap().to_str().unwrap().to_string();
+        let cache_file = self
+            .asset_cache
+            .get_entry_path(&bundle_name, AssetCacheKind::ShaderCache, &cache_file_name);
+        let is_cached = self
+            .asset_cache
+            .contains(&bundle_name, AssetCacheKind::ShaderCache, &cache_file_name);
+        let disk_shader_stage = if !is_cached || is_shader_bundle_outdated(&cache_file, shader_file) {
             let bundle = compile_material_shaders(
                 &resource_bundle,
                 shader_file,
-                &self.temporary_folder.join(shader_file.file_name().unwrap()),
-            );
+                &self
+                    .import_options
+                    .temporary_folder
+                    .join(shader_file.file_name().unwrap()),
+                &macro_definitions,
+                alpha_blend_macro_definitions,
+                self.vertex_pulling,
+            )?;
+            std::fs::create_dir_all(cache_file.parent().unwrap()).expect("failed to create shader cache folder");
             let file = std::fs::OpenOptions::new()
                 .create(true)
                 .write(true)
                 .truncate(true)
-                .open(bundle_file)
+                .open(&cache_file)
                 .expect("failed to open shader stage bundle file for writing");
             bundle
-                .serialize_into(file, self.compression_level)
+                .serialize_into(file, self.import_options.compression_level)
                 .expect("failed to serialize shader bundle");
+            self.asset_cache
+                .insert(&bundle_name, AssetCacheKind::ShaderCache, &cache_file_name);
             bundle
         } else {
             let file = std::fs::OpenOptions::new()
                 .read(true)
-                .open(bundle_file)
+                .open(&cache_file)
                 .expect("failed to open shader stage bundle for reading");
+            self.asset_cache
+                .touch(&bundle_name, AssetCacheKind::ShaderCache, &cache_file_name);
             DiskShaderStageBundle::deserialize_from(file).expect("failed to deserialize shader stage bundle")
         };
 
-        ShaderModuleBundle::new(&disk_shader_stage, factory)
+        Ok(ShaderModuleBundle::new(&disk_shader_stage, factory))
+    }
+
+    // Initial data of a pipeline cache, empty if nothing is cached. Data of another device or driver version
+    // is ignored by the driver.
+    pub fn load_pipeline_cache_data(&mut self, bundle_file: &std::path::Path, pipeline_name: &str) -> Vec<u8> {
+        self.asset_cache
+            .read(
+                &get_asset_cache_bundle_name(bundle_file),
+                AssetCacheKind::PipelineCache,
+                &format!("{}.pipeline_cache", pipeline_name),
+            )
+            .unwrap_or_default()
+    }
+
+    pub fn store_pipeline_cache_data(
+        &mut self,
+        bundle_file: &std::path::Path,
+        pipeline_name: &str,
+        pipeline_bundle: &PipelineBundle,
+        factory: &mut DeviceFactory,
+    ) {
+        let pipeline_cache_data = factory.get_pipeline_cache_data(pipeline_bundle.pipeline_cache);
+        self.asset_cache.write(
+            &get_asset_cache_bundle_name(bundle_file),
+            AssetCacheKind::PipelineCache,
+            &format!("{}.pipeline_cache", pipeline_name),
+            &pipeline_cache_data,
+        );
+    }
+
+    pub fn get_import_manifest(&self, bundle_name: &str) -> Option<AssetImportManifest> {
+        if !self
+            .asset_cache
+            .contains(bundle_name, AssetCacheKind::ImportManifest, IMPORT_MANIFEST_FILE_NAME)
+        {
+            return None;
+        }
+        let manifest_file =
+            self.asset_cache
+                .get_entry_path(bundle_name, AssetCacheKind::ImportManifest, IMPORT_MANIFEST_FILE_NAME);
+        let file = std::fs::File::open(manifest_file).ok()?;
+        serde_json::from_reader(std::io::BufReader::new(file)).ok()
+    }
+
+    fn write_import_manifest(&mut self, bundle_name: &str, gltf_file: &std::path::Path, bundle_file: &std::path::Path) {
+        let import_manifest = AssetImportManifest {
+            gltf_file: gltf_file.to_path_buf(),
+            bundle_file: bundle_file.to_path_buf(),
+            import_time: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+            compression_level: self.import_options.compression_level,
+            pack_mesh_geometry: self.import_options.pack_mesh_geometry,
+        };
+        self.asset_cache.write(
+            bundle_name,
+            AssetCacheKind::ImportManifest,
+            IMPORT_MANIFEST_FILE_NAME,
+            &serde_json::to_vec_pretty(&import_manifest).expect("failed to serialize import manifest"),
+        );
     }
 
     pub fn create_pipeline_bundle<F>(&self, resource_bundle: &ResourceBundleReference, mut func: F) -> PipelineBundle
@@ -297,6 +479,15 @@ impl BundleLoader {
             queue,
         )
     }
+
+    pub fn create_material_preview(
+        &self,
+        device: &Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) -> MaterialPreview {
+        MaterialPreview::new(&self.common_shaders, device, factory, queue)
+    }
 }
 
 struct InternalBundleReference {
@@ -304,47 +495,68 @@ struct InternalBundleReference {
     bundle: ResourceBundleReference,
 }
 
-fn import_pbr_resource_bundle(
-    temporary_path: &std::path::Path,
-    input_path: &std::path::Path,
+struct PbrResourceImportParameters<'a> {
+    temporary_path: &'a std::path::Path,
+    input_path: &'a std::path::Path,
     compression_level: u32,
     force_import: bool,
+    compress_probes: bool, // BC6H for convolved probes, needs texconv
+}
+
+fn import_pbr_resource_bundle(
+    parameters: &PbrResourceImportParameters,
+    common_shaders: &DiskCommonShaders,
     command_buffer: &mut CommandBuffer,
-    _device: &Device,
+    device: &Device,
     factory: &mut DeviceFactory,
     queue: &mut DeviceQueue,
 ) -> PbrResourceBundle {
+    let &PbrResourceImportParameters {
+        temporary_path,
+        input_path,
+        compression_level,
+        force_import,
+        compress_probes,
+    } = parameters;
+
     let bundle_file = input_path.with_extension("bundle");
     let disk_bundle = if force_import || !bundle_file.exists() {
-        let precomputed_brdf_image = compress_image(
-            ImageUsage::EnvironmentBrdf,
-            temporary_path,
-            &input_path.join("brdf.dds"),
-        );
+        // Shipped brdf.dds still takes priority, otherwise the table is computed once and stored in the bundle
+        let brdf_file = input_path.join("brdf.dds");
+        let precomputed_brdf_image = if brdf_file.exists() {
+            compress_image(ImageUsage::EnvironmentBrdf, temporary_path, &brdf_file)
+        } else {
+            log::info!("{:?} not found, computing BRDF table", &brdf_file);
+            compute_brdf_lut(common_shaders, command_buffer, factory, queue)
+        };
 
-        let probe_image = compress_image(
-            ImageUsage::EnvironmentSkybox,
-            temporary_path,
-            &input_path.join("probe_image.dds"),
-        );
-        let iem_image = compress_image(
-            ImageUsage::EnvironmentIem,
-            temporary_path,
-            &input_path.join("probe_iem.dds"),
-        );
-        let pmrem_image = compress_image(
-            ImageUsage::EnvironmentPmrem,
-            temporary_path,
-            &input_path.join("probe_pmrem.dds"),
+        // Global probe comes first, local probes follow it
+        let mut probe_sources = vec![EnvironmentProbeSource {
+            temporary_path: temporary_path.to_path_buf(),
+            probe_path: input_path.to_path_buf(),
+            probe_box: None,
+        }];
+        probe_sources.extend(import_local_probe_sources(temporary_path, input_path));
+        let mut environment_probes = import_environment_probes(
+            &probe_sources,
+            compress_probes,
+            common_shaders,
+            command_buffer,
+            device,
+            factory,
+            queue,
         );
+        let environment_probe = environment_probes.remove(0);
 
         let bundle = DiskPbrResourceBundle {
             precomputed_brdf_image,
-            environment_probe: DiskEnvironmentProbe {
-                probe_image,
-                iem_image,
-                pmrem_image,
-            },
+            environment_probe,
+            local_probes: environment_probes,
+            irradiance_volume: import_irradiance_volume(input_path),
+            ies_profiles: import_ies_profiles(input_path),
+            ltc_tables: import_ltc_tables(input_path),
+            area_light_textures: import_area_light_textures(temporary_path, input_path),
+            blue_noise_image: bake_blue_noise(BLUE_NOISE_SIZE, BLUE_NOISE_LAYER_COUNT),
         };
 
         let file = std::fs::OpenOptions::new()
@@ -369,22 +581,292 @@ fn import_pbr_resource_bundle(
     PbrResourceBundle::new(&disk_bundle, command_buffer, factory, queue)
 }
 
-fn import_bundle(
+#[derive(serde::Deserialize)]
+struct LocalProbeDescription {
+    name: String,
+    position: [f32; 3],
+    box_min: [f32; 3],
+    box_max: [f32; 3],
+    blend_distance: f32,
+}
+
+// Irradiance volume is baked by bake_irradiance_volume tool and is optional
+fn import_irradiance_volume(input_path: &std::path::Path) -> Option<DiskIrradianceVolume> {
+    let volume_file = input_path.join("irradiance_volume.bin");
+    if !volume_file.exists() {
+        return None;
+    }
+
+    log::info!("importing irradiance volume {:?}", &volume_file);
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .open(&volume_file)
+        .expect("failed to open irradiance volume file");
+    Some(
+        DiskIrradianceVolume::deserialize_from(std::io::BufReader::new(file))
+            .expect("failed to deserialize irradiance volume"),
+    )
+}
+
+// IES profiles are listed in ies_profiles.json, light profile indices follow the list order
+fn import_ies_profiles(input_path: &std::path::Path) -> Option<DiskImage> {
+    let description_file = input_path.join("ies_profiles.json");
+    if !description_file.exists() {
+        return None;
+    }
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .open(&description_file)
+        .expect("failed to open IES profile description file");
+    let profile_names: Vec<String> =
+        serde_json::from_reader(file).expect("failed to parse IES profile description file");
+    if profile_names.is_empty() {
+        return None;
+    }
+
+    let profiles: Vec<IesProfile> = profile_names
+        .iter()
+        .map(|profile_name| {
+            log::info!("importing IES profile \"{}\"", profile_name);
+            let profile_text =
+                std::fs::read_to_string(input_path.join(profile_name)).expect("failed to read IES profile");
+            match IesProfile::parse(&profile_text) {
+                Ok(profile) => profile,
+                Err(error) => panic!("failed to parse IES profile \"{}\": {:?}", profile_name, error),
+            }
+        })
+        .collect();
+    Some(bake_ies_profiles(&profiles))
+}
+
+// LTC tables are precomputed by precompute_ltc tool and are optional
+fn import_ltc_tables(input_path: &std::path::Path) -> Option<DiskLtcTables> {
+    let tables_file = input_path.join("ltc_tables.bin");
+    if !tables_file.exists() {
+        return None;
+    }
+
+    log::info!("importing LTC tables {:?}", &tables_file);
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .open(&tables_file)
+        .expect("failed to open LTC tables file");
+    Some(DiskLtcTables::deserialize_from(std::io::BufReader::new(file)).expect("failed to deserialize LTC tables"))
+}
+
+// Area light textures are listed in area_light_textures.json, light texture indices follow the list order
+fn import_area_light_textures(temporary_path: &std::path::Path, input_path: &std::path::Path) -> Vec<DiskImage> {
+    let description_file = input_path.join("area_light_textures.json");
+    if !description_file.exists() {
+        return Vec::new();
+    }
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .open(&description_file)
+        .expect("failed to open area light texture description file");
+    let texture_names: Vec<String> =
+        serde_json::from_reader(file).expect("failed to parse area light texture description file");
+
+    texture_names
+        .iter()
+        .map(|texture_name| {
+            log::info!("importing area light texture \"{}\"", texture_name);
+            compress_image(ImageUsage::SrgbColor, temporary_path, &input_path.join(texture_name))
+        })
+        .collect()
+}
+
+struct EnvironmentProbeSource {
+    temporary_path: std::path::PathBuf,
+    probe_path: std::path::PathBuf,
+    probe_box: Option<DiskProbeBox>,
+}
+
+// Probes without probe_iem.dds and probe_pmrem.dds are convolved from probe_image.dds on the GPU,
+// probe_image.hdr panorama can be used instead of a cube map and is always convolved.
+// Convolutions overlap with loading of the next probes and compression of the finished ones.
+fn import_environment_probes(
+    sources: &[EnvironmentProbeSource],
+    compress_probes: bool,
+    common_shaders: &DiskCommonShaders,
+    command_buffer: &mut CommandBuffer,
+    device: &Device,
+    factory: &mut DeviceFactory,
+    queue: &mut DeviceQueue,
+) -> Vec<DiskEnvironmentProbe> {
+    let mut probes = Vec::with_capacity(sources.len());
+    let mut pending_sources = Vec::new();
+    for (source_id, source) in sources.iter().enumerate() {
+        let cube_file = source.probe_path.join("probe_image.dds");
+        let iem_file = source.probe_path.join("probe_iem.dds");
+        let pmrem_file = source.probe_path.join("probe_pmrem.dds");
+        if cube_file.exists() && iem_file.exists() && pmrem_file.exists() {
+            probes.push(Some(DiskEnvironmentProbe {
+                probe_image: compress_image(ImageUsage::EnvironmentSkybox, &source.temporary_path, &cube_file),
+                iem_image: compress_image(ImageUsage::EnvironmentIem, &source.temporary_path, &iem_file),
+                pmrem_image: compress_image(ImageUsage::EnvironmentPmrem, &source.temporary_path, &pmrem_file),
+                probe_box: source.probe_box,
+            }));
+        } else {
+            probes.push(None);
+            pending_sources.push(source_id);
+        }
+    }
+
+    if !pending_sources.is_empty() {
+        let mut convolution = EnvironmentConvolution::new(common_shaders, device, factory);
+        convolution.convolve_environment_probes(
+            pending_sources.len(),
+            |pending_id, factory, queue| {
+                let source = &sources[pending_sources[pending_id]];
+                log::info!("convolving environment probe {:?}", &source.probe_path);
+                load_environment_probe_image(source, common_shaders, command_buffer, factory, queue)
+            },
+            |pending_id, probe_image, iem_image, pmrem_image| {
+                let source_id = pending_sources[pending_id];
+                let temporary_path = &sources[source_id].temporary_path;
+                probes[source_id] = Some(DiskEnvironmentProbe {
+                    probe_image,
+                    iem_image: compress_convolved_probe_image(
+                        compress_probes,
+                        iem_image,
+                        ImageUsage::EnvironmentIem,
+                        temporary_path,
+                    ),
+                    pmrem_image: compress_convolved_probe_image(
+                        compress_probes,
+                        pmrem_image,
+                        ImageUsage::EnvironmentPmrem,
+                        temporary_path,
+                    ),
+                    probe_box: sources[source_id].probe_box,
+                });
+            },
+            device,
+            factory,
+            queue,
+        );
+        convolution.destroy(factory);
+    }
+
+    probes
+        .into_iter()
+        .map(|probe| probe.expect("environment probe wasn't imported"))
+        .collect()
+}
+
+fn load_environment_probe_image(
+    source: &EnvironmentProbeSource,
+    common_shaders: &DiskCommonShaders,
+    command_buffer: &mut CommandBuffer,
+    factory: &mut DeviceFactory,
+    queue: &mut DeviceQueue,
+) -> DiskImage {
+    let cube_file = source.probe_path.join("probe_image.dds");
+    let panorama_file = ["hdr", "exr"]
+        .iter()
+        .map(|extension| source.probe_path.join("probe_image").with_extension(extension))
+        .find(|panorama_file| panorama_file.exists() && !cube_file.exists());
+    match panorama_file {
+        Some(panorama_file) => {
+            log::info!("converting environment panorama {:?}", &panorama_file);
+            let (width, height, pixels) = load_equirectangular_environment(&panorama_file);
+            convert_equirectangular_environment(width, height, &pixels, common_shaders, command_buffer, factory, queue)
+        }
+        None => compress_image(ImageUsage::EnvironmentSkybox, &source.temporary_path, &cube_file),
+    }
+}
+
+// Convolved images are RGBA16F, compression goes through texconv like authored probe images do
+fn compress_convolved_probe_image(
+    compress_probes: bool,
+    image: DiskImage,
+    image_usage: ImageUsage,
+    temporary_path: &std::path::Path,
+) -> DiskImage {
+    if !compress_probes {
+        return image;
+    }
+
+    // Probes stay uncompressed if texconv input can't be written
+    let uncompressed_path = temporary_path.join("uncompressed");
+    let image_file = uncompressed_path.join(match image_usage {
+        ImageUsage::EnvironmentIem => "probe_iem.dds",
+        _ => "probe_pmrem.dds",
+    });
+    let result = std::fs::create_dir_all(&uncompressed_path)
+        .map_err(|error| format!("failed to create {:?}: {:?}", uncompressed_path, error))
+        .and_then(|_| save_cube_image(&image, &image_file));
+    match result {
+        Ok(_) => compress_image(image_usage, temporary_path, &image_file),
+        Err(error) => {
+            log::error!("{:?} probe is not compressed, {}", image_usage, error);
+            image
+        }
+    }
+}
+
+// Local probes are listed in local_probes.json, each one has a folder with probe images next to it
+fn import_local_probe_sources(
     temporary_path: &std::path::Path,
+    input_path: &std::path::Path,
+) -> Vec<EnvironmentProbeSource> {
+    let description_file = input_path.join("local_probes.json");
+    if !description_file.exists() {
+        return Vec::new();
+    }
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .open(&description_file)
+        .expect("failed to open local probe description file");
+    let mut descriptions: Vec<LocalProbeDescription> =
+        serde_json::from_reader(file).expect("failed to parse local probe description file");
+
+    // Smaller probes are usually nested inside larger ones and take priority
+    let box_volume = |description: &LocalProbeDescription| {
+        (0..3)
+            .map(|axis| (description.box_max[axis] - description.box_min[axis]).abs())
+            .product::<f32>()
+    };
+    descriptions.sort_by(|a, b| box_volume(a).partial_cmp(&box_volume(b)).unwrap());
+
+    descriptions
+        .iter()
+        .map(|description| {
+            log::info!("importing local probe \"{}\"", &description.name);
+            EnvironmentProbeSource {
+                temporary_path: temporary_path.join(&description.name),
+                probe_path: input_path.join(&description.name),
+                probe_box: Some(DiskProbeBox {
+                    position: description.position,
+                    box_min: description.box_min,
+                    box_max: description.box_max,
+                    blend_distance: description.blend_distance,
+                }),
+            }
+        })
+        .collect()
+}
+
+fn import_bundle(
     gltf_file: &std::path::Path,
     bundle_file: &std::path::Path,
-    compression_level: u32,
-    force_import: bool,
+    options: &BundleImportOptions,
     command_buffer: &mut CommandBuffer,
-    _device: &Device,
+    device: &Device,
     factory: &mut DeviceFactory,
     queue: &mut DeviceQueue,
 ) -> ResourceBundle {
-    let disk_resource_bundle = if force_import || !bundle_file.exists() {
+    let mut disk_resource_bundle = if options.force_import || !bundle_file.exists() {
+        let temporary_path = options.temporary_folder.join(bundle_file);
         let bundle = import_gltf_bundle(gltf_file, &temporary_path.join(gltf_file));
         // if clusterize_meshes {
         //     clusterize_bundle_in_place(&mut bundle);
         // }
+        validate_bundle(&bundle, gltf_file);
 
         let file = std::fs::OpenOptions::new()
             .create(true)
@@ -393,7 +875,7 @@ fn import_bundle(
             .open(bundle_file)
             .expect("failed to open bundle file for writing");
         bundle
-            .serialize_into(file, compression_level)
+            .serialize_into(file, options.compression_level)
             .expect("failed to serialize resource bundle");
         bundle
     } else {
@@ -401,10 +883,36 @@ fn import_bundle(
             .read(true)
             .open(bundle_file)
             .expect("failed to open resource bundle file for reading");
-        DiskResourceBundle::deserialize_from(file).expect("failed to deserialize resource bundle")
+        let bundle = DiskResourceBundle::deserialize_from(file).expect("failed to deserialize resource bundle");
+        validate_bundle(&bundle, bundle_file);
+        bundle
     };
+    // Stress scenes are generated after loading, only the original scene is cached
+    if let Some((mesh, instance_count)) = options.stress_scene {
+        disk_resource_bundle.generate_stress_scene(mesh, instance_count);
+    }
+    if options.pack_mesh_geometry {
+        disk_resource_bundle.pack_mesh_geometry();
+    }
+
+    let mut resource_bundle = ResourceBundle::from_disk(&disk_resource_bundle, command_buffer, factory, queue);
+    if device.is_conditional_rendering_enabled() {
+        resource_bundle.initialize_draw_predicates(command_buffer, factory, queue);
+    }
+    resource_bundle
+}
 
-    ResourceBundle::from_disk(&disk_resource_bundle, command_buffer, factory, queue)
+fn validate_bundle(bundle: &DiskResourceBundle, source_file: &std::path::Path) {
+    if let Err(errors) = bundle.validate() {
+        for error in &errors {
+            log::error!("{:?}: {}", source_file, error);
+        }
+        panic!(
+            "resource bundle {:?} failed validation with {} errors",
+            source_file,
+            errors.len()
+        );
+    }
 }
 
 // fn clusterize_bundle_in_place(bundle: &mut DiskResourceBundle) {
@@ -412,7 +920,7 @@ fn import_bundle(
 //         let vertex_buffer = &bundle.buffers[mesh.vertex_buffer];
 //         let index_buffer = &bundle.buffers[mesh.index_buffer.1];
 //
-//         let (new_vertex_buffer, new_index_buffer, mesh_clusters, bounding_cones) =
+//         let (new_vertex_buffer, new_index_buffer, mesh_clusters, cluster_bounds) =
 //             build_mesh_clusters(&vertex_buffer, &index_buffer);
 //
 //         mesh.index_buffer.0 = new_index_buffer.0;
@@ -421,19 +929,52 @@ fn import_bundle(
 //     }
 // }
 
+fn is_shader_bundle_outdated(bundle_file: &std::path::Path, shader_file: &std::path::Path) -> bool {
+    let bundle_time = match std::fs::metadata(bundle_file).and_then(|metadata| metadata.modified()) {
+        Ok(bundle_time) => bundle_time,
+        Err(_) => return true,
+    };
+    let shader_folder = shader_file.parent().expect("shader path has no parent folder");
+    match std::fs::read_dir(shader_folder) {
+        Ok(entries) => entries
+            .filter_map(|entry| {
+                entry
+                    .and_then(|entry| entry.metadata())
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+            })
+            .any(|shader_time| shader_time > bundle_time),
+        Err(_) => false,
+    }
+}
+
+fn append_bundle_extension(bundle_file: &std::path::Path, suffix: &str) -> std::path::PathBuf {
+    let extension = bundle_file
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("");
+    bundle_file.with_extension(format!("{}_{}", extension, suffix))
+}
+
 fn import_common_shaders(
     base_path: &std::path::Path,
     shader_bundle_path: &std::path::Path,
     compression_level: u32,
     force_compile: bool,
+    shader_debug_printf: bool,
 ) -> DiskCommonShaders {
+    let shader_bundle_path = if shader_debug_printf {
+        append_bundle_extension(shader_bundle_path, "debug_printf")
+    } else {
+        shader_bundle_path.to_path_buf()
+    };
     let disk_common_shaders = if force_compile || !shader_bundle_path.exists() {
-        let bundle = compile_common_shaders(base_path);
+        let bundle = compile_common_shaders(base_path, shader_debug_printf);
         let file = std::fs::OpenOptions::new()
             .create(true)
             .write(true)
             .truncate(true)
-            .open(shader_bundle_path)
+            .open(&shader_bundle_path)
             .expect("failed to open common shader bundle file for writing");
         bundle
             .serialize_into(file, compression_level)
@@ -442,14 +983,14 @@ fn import_common_shaders(
     } else {
         let file = std::fs::OpenOptions::new()
             .read(true)
-            .open(shader_bundle_path)
+            .open(&shader_bundle_path)
             .expect("failed to open common shader bundle file for reading");
         DiskCommonShaders::deserialize_from(file).expect("failed to deserialize common shader bundle")
     };
     disk_common_shaders
 }
 
-fn compile_common_shaders(base_path: &std::path::Path) -> DiskCommonShaders {
+fn compile_common_shaders(base_path: &std::path::Path, shader_debug_printf: bool) -> DiskCommonShaders {
     let base_shader_path = base_path.join("malwerks_shaders");
 
     let apex_culling_glsl =
@@ -458,6 +999,19 @@ fn compile_common_shaders(base_path: &std::path::Path) -> DiskCommonShaders {
         .expect("failed to open occlusion_culling.glsl");
     let count_to_dispatch_glsl = std::fs::read_to_string(base_shader_path.join("count_to_dispatch.glsl"))
         .expect("failed to open count_to_dispatch.glsl");
+    let instance_transform_update_glsl =
+        std::fs::read_to_string(base_shader_path.join("instance_transform_update.glsl"))
+            .expect("failed to open instance_transform_update.glsl");
+    let light_clustering_glsl = std::fs::read_to_string(base_shader_path.join("light_clustering.glsl"))
+        .expect("failed to open light_clustering.glsl");
+    let brdf_lut_glsl =
+        std::fs::read_to_string(base_shader_path.join("brdf_lut.glsl")).expect("failed to open brdf_lut.glsl");
+    let environment_convolution_glsl = std::fs::read_to_string(base_shader_path.join("environment_convolution.glsl"))
+        .expect("failed to open environment_convolution.glsl");
+    let equirectangular_to_cube_glsl = std::fs::read_to_string(base_shader_path.join("equirectangular_to_cube.glsl"))
+        .expect("failed to open equirectangular_to_cube.glsl");
+    let material_preview_glsl = std::fs::read_to_string(base_shader_path.join("material_preview.glsl"))
+        .expect("failed to open material_preview.glsl");
 
     let empty_fragment_glsl = "#version 460 core\nvoid main() {}\n";
 
@@ -467,6 +1021,9 @@ fn compile_common_shaders(base_path: &std::path::Path) -> DiskCommonShaders {
     let occluder_resolve_glsl = std::fs::read_to_string(base_shader_path.join("occluder_resolve.glsl"))
         .expect("failed to open occluder_resolve.glsl");
 
+    let full_screen_glsl =
+        std::fs::read_to_string(base_shader_path.join("full_screen.glsl")).expect("failed to open full_screen.glsl");
+
     let tone_map_glsl =
         std::fs::read_to_string(base_shader_path.join("tone_map.glsl")).expect("failed to open tone_map.glsl");
 
@@ -476,6 +1033,15 @@ fn compile_common_shaders(base_path: &std::path::Path) -> DiskCommonShaders {
     compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
     compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
     compile_options.set_warnings_as_errors();
+    if shader_debug_printf {
+        compile_options.add_macro_definition("SHADER_DEBUG_PRINTF", Some("1"));
+    }
+    let include_folder = base_shader_path.clone();
+    compile_options.set_include_callback(
+        move |requested_source_path, _directive_type, _contained_within_path, _recursion_depth| {
+            resolve_shader_include(&include_folder, requested_source_path)
+        },
+    );
 
     let mut compute_stage_options = compile_options.clone().expect("failed to clone compute options");
     compute_stage_options.add_macro_definition("COMPUTE_STAGE", None);
@@ -517,6 +1083,78 @@ fn compile_common_shaders(base_path: &std::path::Path) -> DiskCommonShaders {
             .expect("failed to compile compute shader")
             .as_binary(),
     );
+    let instance_transform_update_compute_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &instance_transform_update_glsl,
+                shaderc::ShaderKind::Compute,
+                "instance_transform_update.glsl",
+                "main",
+                Some(&compute_stage_options),
+            )
+            .expect("failed to compile compute shader")
+            .as_binary(),
+    );
+    let light_clustering_compute_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &light_clustering_glsl,
+                shaderc::ShaderKind::Compute,
+                "light_clustering.glsl",
+                "main",
+                Some(&compute_stage_options),
+            )
+            .expect("failed to compile compute shader")
+            .as_binary(),
+    );
+    let brdf_lut_compute_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &brdf_lut_glsl,
+                shaderc::ShaderKind::Compute,
+                "brdf_lut.glsl",
+                "main",
+                Some(&compute_stage_options),
+            )
+            .expect("failed to compile compute shader")
+            .as_binary(),
+    );
+    let environment_convolution_compute_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &environment_convolution_glsl,
+                shaderc::ShaderKind::Compute,
+                "environment_convolution.glsl",
+                "main",
+                Some(&compute_stage_options),
+            )
+            .expect("failed to compile compute shader")
+            .as_binary(),
+    );
+    let equirectangular_to_cube_compute_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &equirectangular_to_cube_glsl,
+                shaderc::ShaderKind::Compute,
+                "equirectangular_to_cube.glsl",
+                "main",
+                Some(&compute_stage_options),
+            )
+            .expect("failed to compile compute shader")
+            .as_binary(),
+    );
+    let material_preview_compute_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &material_preview_glsl,
+                shaderc::ShaderKind::Compute,
+                "material_preview.glsl",
+                "main",
+                Some(&compute_stage_options),
+            )
+            .expect("failed to compile compute shader")
+            .as_binary(),
+    );
 
     let mut vertex_stage_options = compile_options.clone().expect("failed to clone vertex options");
     vertex_stage_options.add_macro_definition("VERTEX_STAGE", None);
@@ -587,12 +1225,12 @@ fn compile_common_shaders(base_path: &std::path::Path) -> DiskCommonShaders {
             .as_binary(),
     );
 
-    let tone_map_vertex_stage = Vec::from(
+    let full_screen_vertex_stage = Vec::from(
         compiler
             .compile_into_spirv(
-                &tone_map_glsl,
+                &full_screen_glsl,
                 shaderc::ShaderKind::Vertex,
-                "tone_map.glsl",
+                "full_screen.glsl",
                 "main",
                 Some(&vertex_stage_options),
             )
@@ -638,28 +1276,99 @@ fn compile_common_shaders(base_path: &std::path::Path) -> DiskCommonShaders {
     );
 
     let (skybox_vertex_stage, skybox_fragment_stage) = compile_environment_probe_shaders(base_path);
-    let (anti_aliasing_vertex_stage, anti_aliasing_fragment_stage) = compile_anti_aliasing_shaders(base_path);
+    let anti_aliasing_fragment_stage = compile_anti_aliasing_shaders(base_path);
+    let (half_resolution_vertex_stage, depth_downsample_fragment_stage, half_resolution_composite_fragment_stage) =
+        compile_half_resolution_shaders(base_path);
+    let (
+        volumetric_fog_scattering_compute_stage,
+        volumetric_fog_integration_compute_stage,
+        volumetric_fog_vertex_stage,
+        volumetric_fog_fragment_stage,
+    ) = compile_volumetric_fog_shaders(base_path);
+    let (
+        hi_z_downsample_compute_stage,
+        reflection_trace_compute_stage,
+        reflection_composite_vertex_stage,
+        reflection_composite_fragment_stage,
+    ) = compile_screen_space_reflection_shaders(base_path);
+    let (water_surface_vertex_stage, water_surface_fragment_stage) = compile_water_surface_shaders(base_path);
+    let (
+        transparency_composite_vertex_stage,
+        weighted_blended_composite_fragment_stage,
+        linked_lists_composite_fragment_stage,
+    ) = compile_order_independent_transparency_shaders(base_path);
+    let (overdraw_heatmap_vertex_stage, overdraw_heatmap_fragment_stage) = compile_overdraw_heatmap_shaders(base_path);
+    let (depth_view_vertex_stage, depth_view_fragment_stage) = compile_depth_view_shaders(base_path);
+    let (
+        nan_detection_scan_image_compute_stage,
+        nan_detection_scan_buffer_compute_stage,
+        nan_detection_tint_fragment_stage,
+    ) = compile_nan_detection_shaders(base_path);
+    let shader_clock_heatmap_fragment_stage = compile_shader_clock_heatmap_shaders(base_path);
+    let (
+        path_tracer_instance_compute_stage,
+        path_tracer_ray_gen_stage,
+        path_tracer_closest_hit_stage,
+        path_tracer_any_hit_stage,
+        path_tracer_miss_stage,
+        path_tracer_shadow_miss_stage,
+    ) = compile_path_tracer_shaders(base_path);
     DiskCommonShaders {
         apex_culling_compute_stage,
         occlusion_culling_compute_stage,
         count_to_dispatch_compute_stage,
+        instance_transform_update_compute_stage,
+        light_clustering_compute_stage,
+        brdf_lut_compute_stage,
+        environment_convolution_compute_stage,
+        equirectangular_to_cube_compute_stage,
+        material_preview_compute_stage,
         empty_fragment_stage,
         occluder_material_vertex_stage,
         occluder_material_fragment_stage,
         occluder_resolve_vertex_stage,
         occluder_resolve_fragment_stage,
+        full_screen_vertex_stage,
         skybox_vertex_stage,
         skybox_fragment_stage,
-        anti_aliasing_vertex_stage,
         anti_aliasing_fragment_stage,
-        tone_map_vertex_stage,
+        half_resolution_vertex_stage,
+        depth_downsample_fragment_stage,
+        half_resolution_composite_fragment_stage,
+        volumetric_fog_scattering_compute_stage,
+        volumetric_fog_integration_compute_stage,
+        volumetric_fog_vertex_stage,
+        volumetric_fog_fragment_stage,
+        hi_z_downsample_compute_stage,
+        reflection_trace_compute_stage,
+        reflection_composite_vertex_stage,
+        reflection_composite_fragment_stage,
+        water_surface_vertex_stage,
+        water_surface_fragment_stage,
+        transparency_composite_vertex_stage,
+        weighted_blended_composite_fragment_stage,
+        linked_lists_composite_fragment_stage,
+        overdraw_heatmap_vertex_stage,
+        overdraw_heatmap_fragment_stage,
+        depth_view_vertex_stage,
+        depth_view_fragment_stage,
+        nan_detection_scan_image_compute_stage,
+        nan_detection_scan_buffer_compute_stage,
+        nan_detection_tint_fragment_stage,
+        shader_clock_heatmap_fragment_stage,
+        path_tracer_instance_compute_stage,
+        path_tracer_ray_gen_stage,
+        path_tracer_closest_hit_stage,
+        path_tracer_any_hit_stage,
+        path_tracer_miss_stage,
+        path_tracer_shadow_miss_stage,
         tone_map_fragment_stage,
         imgui_vertex_stage,
         imgui_fragment_stage,
     }
 }
 
-fn compile_anti_aliasing_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<u32>) {
+fn compile_anti_aliasing_shaders(base_path: &std::path::Path) -> Vec<u32> {
     let anti_aliasing_glsl = std::fs::read_to_string(base_path.join("malwerks_shaders").join("anti_aliasing.glsl"))
         .expect("failed to open anti_aliasing.glsl");
 
@@ -668,30 +1377,74 @@ fn compile_anti_aliasing_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<
     compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
     compile_options.set_warnings_as_errors();
 
+    let mut compiler = shaderc::Compiler::new().expect("failed to initialize GLSL compiler");
+    Vec::from(
+        compiler
+            .compile_into_spirv(
+                &anti_aliasing_glsl,
+                shaderc::ShaderKind::Fragment,
+                "anti_aliasing.glsl",
+                "main",
+                Some(&compile_options),
+            )
+            .expect("failed to compile fragment shader")
+            .as_binary(),
+    )
+}
+
+fn compile_half_resolution_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<u32>, Vec<u32>) {
+    let half_resolution_glsl = std::fs::read_to_string(
+        base_path
+            .join("malwerks_shaders")
+            .join("half_resolution_composite.glsl"),
+    )
+    .expect("failed to open half_resolution_composite.glsl");
+
+    let mut compile_options = shaderc::CompileOptions::new().expect("failed to initialize GLSL compiler options");
+    compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
+    compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    compile_options.set_warnings_as_errors();
+
     let mut vertex_stage_options = compile_options.clone().expect("failed to clone vertex options");
     vertex_stage_options.add_macro_definition("VERTEX_STAGE", None);
     let mut fragment_stage_options = compile_options.clone().expect("failed to clone fragment options");
     fragment_stage_options.add_macro_definition("FRAGMENT_STAGE", None);
+    let mut depth_downsample_options = fragment_stage_options
+        .clone()
+        .expect("failed to clone fragment options");
+    depth_downsample_options.add_macro_definition("DEPTH_DOWNSAMPLE", None);
 
     let mut compiler = shaderc::Compiler::new().expect("failed to initialize GLSL compiler");
-    let skybox_vertex_stage = Vec::from(
+    let vertex_stage = Vec::from(
         compiler
             .compile_into_spirv(
-                &anti_aliasing_glsl,
+                &half_resolution_glsl,
                 shaderc::ShaderKind::Vertex,
-                "anti_aliasing.glsl",
+                "half_resolution_composite.glsl",
                 "main",
                 Some(&vertex_stage_options),
             )
             .expect("failed to compile vertex shader")
             .as_binary(),
     );
-    let skybox_fragment_stage = Vec::from(
+    let depth_downsample_fragment_stage = Vec::from(
         compiler
             .compile_into_spirv(
-                &anti_aliasing_glsl,
+                &half_resolution_glsl,
                 shaderc::ShaderKind::Fragment,
-                "anti_aliasing.glsl",
+                "half_resolution_composite.glsl",
+                "main",
+                Some(&depth_downsample_options),
+            )
+            .expect("failed to compile fragment shader")
+            .as_binary(),
+    );
+    let composite_fragment_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &half_resolution_glsl,
+                shaderc::ShaderKind::Fragment,
+                "half_resolution_composite.glsl",
                 "main",
                 Some(&fragment_stage_options),
             )
@@ -699,12 +1452,274 @@ fn compile_anti_aliasing_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<
             .as_binary(),
     );
 
-    (skybox_vertex_stage, skybox_fragment_stage)
+    (vertex_stage, depth_downsample_fragment_stage, composite_fragment_stage)
 }
 
-fn compile_environment_probe_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<u32>) {
-    let skybox_glsl = std::fs::read_to_string(base_path.join("malwerks_shaders").join("environment_probe.glsl"))
-        .expect("failed to open environment_probe.glsl");
+fn compile_volumetric_fog_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<u32>, Vec<u32>, Vec<u32>) {
+    let volumetric_fog_glsl = std::fs::read_to_string(base_path.join("malwerks_shaders").join("volumetric_fog.glsl"))
+        .expect("failed to open volumetric_fog.glsl");
+
+    let mut compile_options = shaderc::CompileOptions::new().expect("failed to initialize GLSL compiler options");
+    compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
+    compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    compile_options.set_warnings_as_errors();
+
+    let mut compute_stage_options = compile_options.clone().expect("failed to clone compute options");
+    compute_stage_options.add_macro_definition("COMPUTE_STAGE", None);
+    let mut scattering_options = compute_stage_options.clone().expect("failed to clone compute options");
+    scattering_options.add_macro_definition("FOG_SCATTERING", None);
+    let mut integration_options = compute_stage_options.clone().expect("failed to clone compute options");
+    integration_options.add_macro_definition("FOG_INTEGRATION", None);
+    let mut vertex_stage_options = compile_options.clone().expect("failed to clone vertex options");
+    vertex_stage_options.add_macro_definition("VERTEX_STAGE", None);
+    let mut fragment_stage_options = compile_options.clone().expect("failed to clone fragment options");
+    fragment_stage_options.add_macro_definition("FRAGMENT_STAGE", None);
+
+    let mut compiler = shaderc::Compiler::new().expect("failed to initialize GLSL compiler");
+    let scattering_compute_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &volumetric_fog_glsl,
+                shaderc::ShaderKind::Compute,
+                "volumetric_fog.glsl",
+                "main",
+                Some(&scattering_options),
+            )
+            .expect("failed to compile compute shader")
+            .as_binary(),
+    );
+    let integration_compute_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &volumetric_fog_glsl,
+                shaderc::ShaderKind::Compute,
+                "volumetric_fog.glsl",
+                "main",
+                Some(&integration_options),
+            )
+            .expect("failed to compile compute shader")
+            .as_binary(),
+    );
+    let vertex_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &volumetric_fog_glsl,
+                shaderc::ShaderKind::Vertex,
+                "volumetric_fog.glsl",
+                "main",
+                Some(&vertex_stage_options),
+            )
+            .expect("failed to compile vertex shader")
+            .as_binary(),
+    );
+    let fragment_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &volumetric_fog_glsl,
+                shaderc::ShaderKind::Fragment,
+                "volumetric_fog.glsl",
+                "main",
+                Some(&fragment_stage_options),
+            )
+            .expect("failed to compile fragment shader")
+            .as_binary(),
+    );
+
+    (
+        scattering_compute_stage,
+        integration_compute_stage,
+        vertex_stage,
+        fragment_stage,
+    )
+}
+
+fn compile_screen_space_reflection_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<u32>, Vec<u32>, Vec<u32>) {
+    let screen_space_reflections_glsl =
+        std::fs::read_to_string(base_path.join("malwerks_shaders").join("screen_space_reflections.glsl"))
+            .expect("failed to open screen_space_reflections.glsl");
+
+    let mut compile_options = shaderc::CompileOptions::new().expect("failed to initialize GLSL compiler options");
+    compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
+    compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    compile_options.set_warnings_as_errors();
+
+    let mut compute_stage_options = compile_options.clone().expect("failed to clone compute options");
+    compute_stage_options.add_macro_definition("COMPUTE_STAGE", None);
+    let mut hi_z_options = compute_stage_options.clone().expect("failed to clone compute options");
+    hi_z_options.add_macro_definition("HI_Z_DOWNSAMPLE", None);
+    let mut trace_options = compute_stage_options.clone().expect("failed to clone compute options");
+    trace_options.add_macro_definition("REFLECTION_TRACE", None);
+    let mut vertex_stage_options = compile_options.clone().expect("failed to clone vertex options");
+    vertex_stage_options.add_macro_definition("VERTEX_STAGE", None);
+    let mut fragment_stage_options = compile_options.clone().expect("failed to clone fragment options");
+    fragment_stage_options.add_macro_definition("FRAGMENT_STAGE", None);
+
+    let mut compiler = shaderc::Compiler::new().expect("failed to initialize GLSL compiler");
+    let hi_z_compute_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &screen_space_reflections_glsl,
+                shaderc::ShaderKind::Compute,
+                "screen_space_reflections.glsl",
+                "main",
+                Some(&hi_z_options),
+            )
+            .expect("failed to compile compute shader")
+            .as_binary(),
+    );
+    let trace_compute_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &screen_space_reflections_glsl,
+                shaderc::ShaderKind::Compute,
+                "screen_space_reflections.glsl",
+                "main",
+                Some(&trace_options),
+            )
+            .expect("failed to compile compute shader")
+            .as_binary(),
+    );
+    let vertex_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &screen_space_reflections_glsl,
+                shaderc::ShaderKind::Vertex,
+                "screen_space_reflections.glsl",
+                "main",
+                Some(&vertex_stage_options),
+            )
+            .expect("failed to compile vertex shader")
+            .as_binary(),
+    );
+    let fragment_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &screen_space_reflections_glsl,
+                shaderc::ShaderKind::Fragment,
+                "screen_space_reflections.glsl",
+                "main",
+                Some(&fragment_stage_options),
+            )
+            .expect("failed to compile fragment shader")
+            .as_binary(),
+    );
+
+    (hi_z_compute_stage, trace_compute_stage, vertex_stage, fragment_stage)
+}
+
+fn compile_water_surface_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<u32>) {
+    let water_surface_glsl = std::fs::read_to_string(base_path.join("malwerks_shaders").join("water_surface.glsl"))
+        .expect("failed to open water_surface.glsl");
+
+    let mut compile_options = shaderc::CompileOptions::new().expect("failed to initialize GLSL compiler options");
+    compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
+    compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    compile_options.set_warnings_as_errors();
+
+    let mut vertex_stage_options = compile_options.clone().expect("failed to clone vertex options");
+    vertex_stage_options.add_macro_definition("VERTEX_STAGE", None);
+    let mut fragment_stage_options = compile_options.clone().expect("failed to clone fragment options");
+    fragment_stage_options.add_macro_definition("FRAGMENT_STAGE", None);
+
+    let mut compiler = shaderc::Compiler::new().expect("failed to initialize GLSL compiler");
+    let vertex_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &water_surface_glsl,
+                shaderc::ShaderKind::Vertex,
+                "water_surface.glsl",
+                "main",
+                Some(&vertex_stage_options),
+            )
+            .expect("failed to compile vertex shader")
+            .as_binary(),
+    );
+    let fragment_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &water_surface_glsl,
+                shaderc::ShaderKind::Fragment,
+                "water_surface.glsl",
+                "main",
+                Some(&fragment_stage_options),
+            )
+            .expect("failed to compile fragment shader")
+            .as_binary(),
+    );
+
+    (vertex_stage, fragment_stage)
+}
+
+fn compile_order_independent_transparency_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<u32>, Vec<u32>) {
+    let transparency_glsl = std::fs::read_to_string(
+        base_path
+            .join("malwerks_shaders")
+            .join("order_independent_transparency.glsl"),
+    )
+    .expect("failed to open order_independent_transparency.glsl");
+
+    let mut compile_options = shaderc::CompileOptions::new().expect("failed to initialize GLSL compiler options");
+    compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
+    compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    compile_options.set_warnings_as_errors();
+
+    let mut vertex_stage_options = compile_options.clone().expect("failed to clone vertex options");
+    vertex_stage_options.add_macro_definition("VERTEX_STAGE", None);
+    let mut weighted_blended_options = compile_options.clone().expect("failed to clone fragment options");
+    weighted_blended_options.add_macro_definition("FRAGMENT_STAGE", None);
+    weighted_blended_options.add_macro_definition("WEIGHTED_BLENDED_COMPOSITE", None);
+    let mut linked_lists_options = compile_options.clone().expect("failed to clone fragment options");
+    linked_lists_options.add_macro_definition("FRAGMENT_STAGE", None);
+    linked_lists_options.add_macro_definition("LINKED_LISTS_COMPOSITE", None);
+
+    let mut compiler = shaderc::Compiler::new().expect("failed to initialize GLSL compiler");
+    let vertex_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &transparency_glsl,
+                shaderc::ShaderKind::Vertex,
+                "order_independent_transparency.glsl",
+                "main",
+                Some(&vertex_stage_options),
+            )
+            .expect("failed to compile vertex shader")
+            .as_binary(),
+    );
+    let weighted_blended_fragment_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &transparency_glsl,
+                shaderc::ShaderKind::Fragment,
+                "order_independent_transparency.glsl",
+                "main",
+                Some(&weighted_blended_options),
+            )
+            .expect("failed to compile fragment shader")
+            .as_binary(),
+    );
+    let linked_lists_fragment_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &transparency_glsl,
+                shaderc::ShaderKind::Fragment,
+                "order_independent_transparency.glsl",
+                "main",
+                Some(&linked_lists_options),
+            )
+            .expect("failed to compile fragment shader")
+            .as_binary(),
+    );
+
+    (
+        vertex_stage,
+        weighted_blended_fragment_stage,
+        linked_lists_fragment_stage,
+    )
+}
+
+fn compile_environment_probe_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<u32>) {
+    let skybox_glsl = std::fs::read_to_string(base_path.join("malwerks_shaders").join("environment_probe.glsl"))
+        .expect("failed to open environment_probe.glsl");
 
     let mut compile_options = shaderc::CompileOptions::new().expect("failed to initialize GLSL compiler options");
     compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
@@ -751,3 +1766,212 @@ fn compile_environment_probe_shaders(base_path: &std::path::Path) -> (Vec<u32>,
 
     (skybox_vertex_stage, skybox_fragment_stage)
 }
+
+fn compile_overdraw_heatmap_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<u32>) {
+    let overdraw_heatmap_glsl =
+        std::fs::read_to_string(base_path.join("malwerks_shaders").join("overdraw_heatmap.glsl"))
+            .expect("failed to open overdraw_heatmap.glsl");
+
+    let mut compile_options = shaderc::CompileOptions::new().expect("failed to initialize GLSL compiler options");
+    compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
+    compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    compile_options.set_warnings_as_errors();
+
+    let mut vertex_stage_options = compile_options.clone().expect("failed to clone vertex options");
+    vertex_stage_options.add_macro_definition("VERTEX_STAGE", None);
+    let mut fragment_stage_options = compile_options.clone().expect("failed to clone fragment options");
+    fragment_stage_options.add_macro_definition("FRAGMENT_STAGE", None);
+
+    let mut compiler = shaderc::Compiler::new().expect("failed to initialize GLSL compiler");
+    let vertex_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &overdraw_heatmap_glsl,
+                shaderc::ShaderKind::Vertex,
+                "overdraw_heatmap.glsl",
+                "main",
+                Some(&vertex_stage_options),
+            )
+            .expect("failed to compile vertex shader")
+            .as_binary(),
+    );
+    let fragment_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &overdraw_heatmap_glsl,
+                shaderc::ShaderKind::Fragment,
+                "overdraw_heatmap.glsl",
+                "main",
+                Some(&fragment_stage_options),
+            )
+            .expect("failed to compile fragment shader")
+            .as_binary(),
+    );
+
+    (vertex_stage, fragment_stage)
+}
+
+fn compile_nan_detection_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<u32>, Vec<u32>) {
+    let nan_detection_glsl = std::fs::read_to_string(base_path.join("malwerks_shaders").join("nan_detection.glsl"))
+        .expect("failed to open nan_detection.glsl");
+
+    let mut compile_options = shaderc::CompileOptions::new().expect("failed to initialize GLSL compiler options");
+    compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
+    compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    compile_options.set_warnings_as_errors();
+
+    let mut scan_image_stage_options = compile_options.clone().expect("failed to clone compute options");
+    scan_image_stage_options.add_macro_definition("SCAN_IMAGE_STAGE", None);
+    let mut scan_buffer_stage_options = compile_options.clone().expect("failed to clone compute options");
+    scan_buffer_stage_options.add_macro_definition("SCAN_BUFFER_STAGE", None);
+    let mut fragment_stage_options = compile_options.clone().expect("failed to clone fragment options");
+    fragment_stage_options.add_macro_definition("FRAGMENT_STAGE", None);
+
+    let mut compiler = shaderc::Compiler::new().expect("failed to initialize GLSL compiler");
+    let scan_image_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &nan_detection_glsl,
+                shaderc::ShaderKind::Compute,
+                "nan_detection.glsl",
+                "main",
+                Some(&scan_image_stage_options),
+            )
+            .expect("failed to compile compute shader")
+            .as_binary(),
+    );
+    let scan_buffer_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &nan_detection_glsl,
+                shaderc::ShaderKind::Compute,
+                "nan_detection.glsl",
+                "main",
+                Some(&scan_buffer_stage_options),
+            )
+            .expect("failed to compile compute shader")
+            .as_binary(),
+    );
+    let fragment_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &nan_detection_glsl,
+                shaderc::ShaderKind::Fragment,
+                "nan_detection.glsl",
+                "main",
+                Some(&fragment_stage_options),
+            )
+            .expect("failed to compile fragment shader")
+            .as_binary(),
+    );
+
+    (scan_image_stage, scan_buffer_stage, fragment_stage)
+}
+
+fn compile_shader_clock_heatmap_shaders(base_path: &std::path::Path) -> Vec<u32> {
+    let shader_clock_heatmap_glsl =
+        std::fs::read_to_string(base_path.join("malwerks_shaders").join("shader_clock_heatmap.glsl"))
+            .expect("failed to open shader_clock_heatmap.glsl");
+
+    let mut compile_options = shaderc::CompileOptions::new().expect("failed to initialize GLSL compiler options");
+    compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
+    compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    compile_options.set_warnings_as_errors();
+
+    let mut compiler = shaderc::Compiler::new().expect("failed to initialize GLSL compiler");
+    Vec::from(
+        compiler
+            .compile_into_spirv(
+                &shader_clock_heatmap_glsl,
+                shaderc::ShaderKind::Fragment,
+                "shader_clock_heatmap.glsl",
+                "main",
+                Some(&compile_options),
+            )
+            .expect("failed to compile fragment shader")
+            .as_binary(),
+    )
+}
+
+fn compile_depth_view_shaders(base_path: &std::path::Path) -> (Vec<u32>, Vec<u32>) {
+    let depth_view_glsl = std::fs::read_to_string(base_path.join("malwerks_shaders").join("depth_view.glsl"))
+        .expect("failed to open depth_view.glsl");
+
+    let mut compile_options = shaderc::CompileOptions::new().expect("failed to initialize GLSL compiler options");
+    compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
+    compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    compile_options.set_warnings_as_errors();
+
+    let mut vertex_stage_options = compile_options.clone().expect("failed to clone vertex options");
+    vertex_stage_options.add_macro_definition("VERTEX_STAGE", None);
+    let mut fragment_stage_options = compile_options.clone().expect("failed to clone fragment options");
+    fragment_stage_options.add_macro_definition("FRAGMENT_STAGE", None);
+
+    let mut compiler = shaderc::Compiler::new().expect("failed to initialize GLSL compiler");
+    let vertex_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &depth_view_glsl,
+                shaderc::ShaderKind::Vertex,
+                "depth_view.glsl",
+                "main",
+                Some(&vertex_stage_options),
+            )
+            .expect("failed to compile vertex shader")
+            .as_binary(),
+    );
+    let fragment_stage = Vec::from(
+        compiler
+            .compile_into_spirv(
+                &depth_view_glsl,
+                shaderc::ShaderKind::Fragment,
+                "depth_view.glsl",
+                "main",
+                Some(&fragment_stage_options),
+            )
+            .expect("failed to compile fragment shader")
+            .as_binary(),
+    );
+
+    (vertex_stage, fragment_stage)
+}
+
+#[allow(clippy::type_complexity)]
+fn compile_path_tracer_shaders(
+    base_path: &std::path::Path,
+) -> (Vec<u32>, Vec<u32>, Vec<u32>, Vec<u32>, Vec<u32>, Vec<u32>) {
+    let path_tracer_glsl = std::fs::read_to_string(base_path.join("malwerks_shaders").join("path_tracer.glsl"))
+        .expect("failed to open path_tracer.glsl");
+
+    let mut compile_options = shaderc::CompileOptions::new().expect("failed to initialize GLSL compiler options");
+    compile_options.set_source_language(shaderc::SourceLanguage::GLSL);
+    compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    compile_options.set_warnings_as_errors();
+
+    let mut compiler = shaderc::Compiler::new().expect("failed to initialize GLSL compiler");
+    let mut compile_stage = |stage_macro: &str, shader_kind: shaderc::ShaderKind| {
+        let mut stage_options = compile_options.clone().expect("failed to clone stage options");
+        stage_options.add_macro_definition(stage_macro, None);
+        Vec::from(
+            compiler
+                .compile_into_spirv(
+                    &path_tracer_glsl,
+                    shader_kind,
+                    "path_tracer.glsl",
+                    "main",
+                    Some(&stage_options),
+                )
+                .expect("failed to compile path tracer shader")
+                .as_binary(),
+        )
+    };
+
+    (
+        compile_stage("COMPUTE_STAGE", shaderc::ShaderKind::Compute),
+        compile_stage("RAY_GEN_STAGE", shaderc::ShaderKind::RayGeneration),
+        compile_stage("RAY_CLOSEST_HIT_STAGE", shaderc::ShaderKind::ClosestHit),
+        compile_stage("RAY_ANY_HIT_STAGE", shaderc::ShaderKind::AnyHit),
+        compile_stage("RAY_MISS_STAGE", shaderc::ShaderKind::Miss),
+        compile_stage("SHADOW_RAY_MISS_STAGE", shaderc::ShaderKind::Miss),
+    )
+}
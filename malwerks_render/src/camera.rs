@@ -5,7 +5,10 @@
 
 use ultraviolet as utv;
 
-#[derive(Debug)]
+// All projections use reversed infinite Z with this near plane
+pub const CAMERA_Z_NEAR: f32 = 0.1;
+
+#[derive(Debug, Copy, Clone)]
 pub struct Viewport {
     pub x: i32,
     pub y: i32,
@@ -13,6 +16,27 @@ pub struct Viewport {
     pub height: u32,
 }
 
+// Part of a larger image rendered with an off-center projection, in pixels of the full image
+#[derive(Debug, Copy, Clone)]
+pub struct ProjectionTile {
+    pub full_width: u32,
+    pub full_height: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Per-eye field of view reported by head mounted displays, angles are in radians and left/down are negative
+#[derive(Debug, Copy, Clone)]
+pub struct EyeFieldOfView {
+    pub angle_left: f32,
+    pub angle_right: f32,
+    pub angle_up: f32,
+    pub angle_down: f32,
+}
+
+#[derive(Clone)]
 pub struct Camera {
     pub position: utv::vec::Vec3,
     pub orientation: utv::rotor::Rotor3,
@@ -20,13 +44,12 @@ pub struct Camera {
     viewport: Viewport,
     field_of_view: f32,
     aspect_ratio: f32,
+    projection_tile: Option<ProjectionTile>,
 }
 
 impl Camera {
     pub fn new(field_of_view: f32, viewport: Viewport) -> Self {
-        let width = (viewport.width - (viewport.x as u32)) as f32;
-        let height = (viewport.height - (viewport.y as u32)) as f32;
-        let aspect_ratio = width / height;
+        let aspect_ratio = calculate_aspect_ratio(&viewport);
 
         Self {
             position: utv::vec::Vec3::new(0.0, 0.0, 0.0),
@@ -35,6 +58,7 @@ impl Camera {
             viewport,
             field_of_view,
             aspect_ratio,
+            projection_tile: None,
         }
     }
 
@@ -47,6 +71,21 @@ impl Camera {
         &self.viewport
     }
 
+    // Aspect ratio follows the new viewport, e.g. after the window switched to fullscreen
+    pub fn set_viewport(&mut self, viewport: Viewport) {
+        self.aspect_ratio = calculate_aspect_ratio(&viewport);
+        self.viewport = viewport;
+    }
+
+    // Tile projection keeps the aspect ratio of the full image and only shows the tile area of it
+    pub fn set_projection_tile(&mut self, projection_tile: Option<ProjectionTile>) {
+        self.projection_tile = projection_tile;
+    }
+
+    pub fn get_projection_tile(&self) -> Option<ProjectionTile> {
+        self.projection_tile
+    }
+
     pub fn move_by(&mut self, amount: utv::vec::Vec3) {
         self.position += self.orientation.reversed() * amount;
     }
@@ -58,15 +97,113 @@ impl Camera {
     }
 
     pub fn calculate_view_projection(&self, subsample_offset: [f32; 2]) -> (utv::mat::Mat4, utv::mat::Mat4) {
-        let mut projection =
-            utv::projection::perspective_reversed_infinite_z_vk(to_radians(self.field_of_view), self.aspect_ratio, 0.1);
-        let view = self.orientation.into_matrix().into_homogeneous() * utv::mat::Mat4::from_translation(self.position);
-        let view_projection = projection * view;
+        let (projection, subsample_projection) = self.calculate_projection(subsample_offset);
+        let view = self.calculate_view();
+
+        (projection * view, subsample_projection * view)
+    }
 
-        projection[2][0] += subsample_offset[0] / (((self.viewport.width as i32) - self.viewport.x) as f32);
-        projection[2][1] += subsample_offset[1] / (((self.viewport.height as i32) - self.viewport.y) as f32);
+    // View 0 is the left eye, both eyes are offset from the camera position along its right axis.
+    // Stereo views are not jittered, they are not resolved temporally.
+    pub fn calculate_stereo_view_projections(&self, eye_separation: f32) -> [utv::mat::Mat4; 2] {
+        let (projection, _) = self.calculate_projection([0.0, 0.0]);
+        let view = self.calculate_view();
+        let half_separation = 0.5 * eye_separation;
+
+        [
+            projection * utv::mat::Mat4::from_translation(utv::vec::Vec3::new(half_separation, 0.0, 0.0)) * view,
+            projection * utv::mat::Mat4::from_translation(utv::vec::Vec3::new(-half_separation, 0.0, 0.0)) * view,
+        ]
+    }
+
+    // Eye transform is from eye to camera view space, projection is asymmetric and uses the same depth range
+    pub fn calculate_eye_view_projection(
+        &self,
+        eye_transform: &utv::mat::Mat4,
+        field_of_view: &EyeFieldOfView,
+    ) -> utv::mat::Mat4 {
+        calculate_eye_projection(field_of_view, CAMERA_Z_NEAR) * eye_transform.inversed() * self.calculate_view()
+    }
+
+    fn calculate_view(&self) -> utv::mat::Mat4 {
+        self.orientation.into_matrix().into_homogeneous() * utv::mat::Mat4::from_translation(self.position)
+    }
+
+    fn calculate_projection(&self, subsample_offset: [f32; 2]) -> (utv::mat::Mat4, utv::mat::Mat4) {
+        let aspect_ratio = match &self.projection_tile {
+            Some(tile) => tile.full_width as f32 / tile.full_height as f32,
+            None => self.aspect_ratio,
+        };
+        let mut projection = utv::projection::perspective_reversed_infinite_z_vk(
+            to_radians(self.field_of_view),
+            aspect_ratio,
+            CAMERA_Z_NEAR,
+        );
+        if let Some(tile) = &self.projection_tile {
+            apply_projection_tile(&mut projection, tile);
+        }
+
+        let mut subsample_projection = projection;
+        subsample_projection[2][0] += subsample_offset[0] / (((self.viewport.width as i32) - self.viewport.x) as f32);
+        subsample_projection[2][1] += subsample_offset[1] / (((self.viewport.height as i32) - self.viewport.y) as f32);
+
+        (projection, subsample_projection)
+    }
+}
+
+// Reversed infinite Z projection with Vulkan clip space, same as perspective_reversed_infinite_z_vk()
+// but with separate left, right, up and down extents
+fn calculate_eye_projection(field_of_view: &EyeFieldOfView, z_near: f32) -> utv::mat::Mat4 {
+    let tan_left = field_of_view.angle_left.tan();
+    let tan_right = field_of_view.angle_right.tan();
+    let tan_up = field_of_view.angle_up.tan();
+    let tan_down = field_of_view.angle_down.tan();
+    let tan_width = tan_right - tan_left;
+    let tan_height = tan_up - tan_down;
+
+    utv::mat::Mat4::new(
+        utv::vec::Vec4::new(2.0 / tan_width, 0.0, 0.0, 0.0),
+        utv::vec::Vec4::new(0.0, -2.0 / tan_height, 0.0, 0.0),
+        utv::vec::Vec4::new(
+            (tan_right + tan_left) / tan_width,
+            -(tan_up + tan_down) / tan_height,
+            0.0,
+            -1.0,
+        ),
+        utv::vec::Vec4::new(0.0, 0.0, z_near, 0.0),
+    )
+}
+
+// Scales and offsets clip space so that the tile area of the full image covers the whole viewport
+fn apply_projection_tile(projection: &mut utv::mat::Mat4, tile: &ProjectionTile) {
+    let min_x = 2.0 * tile.x as f32 / tile.full_width as f32 - 1.0;
+    let max_x = 2.0 * (tile.x + tile.width) as f32 / tile.full_width as f32 - 1.0;
+    let min_y = 2.0 * tile.y as f32 / tile.full_height as f32 - 1.0;
+    let max_y = 2.0 * (tile.y + tile.height) as f32 / tile.full_height as f32 - 1.0;
+
+    let scale_x = 2.0 / (max_x - min_x);
+    let scale_y = 2.0 / (max_y - min_y);
+    let center_x = 0.5 * (min_x + max_x);
+    let center_y = 0.5 * (min_y + max_y);
+    for column in 0..4 {
+        let w = projection[column][3];
+        projection[column][0] = scale_x * (projection[column][0] - center_x * w);
+        projection[column][1] = scale_y * (projection[column][1] - center_y * w);
+    }
+}
+
+fn calculate_aspect_ratio(viewport: &Viewport) -> f32 {
+    let width = (viewport.width - (viewport.x as u32)) as f32;
+    let height = (viewport.height - (viewport.y as u32)) as f32;
+    width / height
+}
 
-        (view_projection, projection * view)
+// View space distance of a depth buffer value, zero depth is infinitely far away
+pub fn linearize_depth(depth: f32) -> f32 {
+    if depth > 0.0 {
+        CAMERA_Z_NEAR / depth
+    } else {
+        f32::INFINITY
     }
 }
 
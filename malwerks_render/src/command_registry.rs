@@ -0,0 +1,112 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Output of a command is printed to the console, errors are highlighted
+pub type CommandResult = Result<String, String>;
+pub type CommandHandler<T> = Box<dyn FnMut(&mut T, &[&str]) -> CommandResult>;
+
+struct Command<T> {
+    name: String,
+    help: String,
+    handler: CommandHandler<T>,
+}
+
+// Runtime commands operating on an application defined context, "help" lists all registered commands
+pub struct CommandRegistry<T> {
+    commands: Vec<Command<T>>,
+}
+
+impl<T> Default for CommandRegistry<T> {
+    fn default() -> Self {
+        Self { commands: Vec::new() }
+    }
+}
+
+impl<T> CommandRegistry<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Command with the same name is replaced
+    pub fn register<F>(&mut self, name: &str, help: &str, handler: F)
+    where
+        F: FnMut(&mut T, &[&str]) -> CommandResult + 'static,
+    {
+        assert!(
+            !name.is_empty() && !name.contains(char::is_whitespace),
+            "command name must be a single word"
+        );
+        self.unregister(name);
+        self.commands.push(Command {
+            name: name.to_string(),
+            help: help.to_string(),
+            handler: Box::new(handler),
+        });
+    }
+
+    pub fn unregister(&mut self, name: &str) {
+        self.commands.retain(|command| command.name != name);
+    }
+
+    // Registered commands in registration order, (name, help)
+    pub fn get_commands(&self) -> Vec<(&str, &str)> {
+        self.commands
+            .iter()
+            .map(|command| (command.name.as_str(), command.help.as_str()))
+            .collect()
+    }
+
+    // Arguments are separated by whitespace, double quotes keep whitespace inside of an argument
+    pub fn execute(&mut self, context: &mut T, command_line: &str) -> CommandResult {
+        let arguments = split_command_line(command_line)?;
+        let (name, arguments) = match arguments.split_first() {
+            Some((name, arguments)) => (name.as_str(), arguments),
+            None => return Ok(String::new()),
+        };
+        let arguments: Vec<&str> = arguments.iter().map(|argument| argument.as_str()).collect();
+
+        if name == "help" {
+            return Ok(self
+                .commands
+                .iter()
+                .map(|command| format!("{} - {}", command.name, command.help))
+                .collect::<Vec<String>>()
+                .join("\n"));
+        }
+
+        match self.commands.iter_mut().find(|command| command.name == name) {
+            Some(command) => (command.handler)(context, &arguments),
+            None => Err(format!(
+                "unknown command \"{}\", type \"help\" for the list of commands",
+                name
+            )),
+        }
+    }
+}
+
+pub(crate) fn split_command_line(command_line: &str) -> Result<Vec<String>, String> {
+    let mut arguments = Vec::new();
+    let mut current_argument: Option<String> = None;
+    let mut in_quotes = false;
+    for character in command_line.chars() {
+        match character {
+            '"' => {
+                in_quotes = !in_quotes;
+                current_argument.get_or_insert_with(String::new);
+            }
+            character if character.is_whitespace() && !in_quotes => {
+                if let Some(argument) = current_argument.take() {
+                    arguments.push(argument);
+                }
+            }
+            character => current_argument.get_or_insert_with(String::new).push(character),
+        }
+    }
+    if in_quotes {
+        return Err("missing closing quote".to_string());
+    }
+    arguments.extend(current_argument);
+    Ok(arguments)
+}
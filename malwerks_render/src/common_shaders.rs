@@ -8,6 +8,12 @@ pub struct DiskCommonShaders {
     pub apex_culling_compute_stage: Vec<u32>,
     pub occlusion_culling_compute_stage: Vec<u32>,
     pub count_to_dispatch_compute_stage: Vec<u32>,
+    pub instance_transform_update_compute_stage: Vec<u32>,
+    pub light_clustering_compute_stage: Vec<u32>,
+    pub brdf_lut_compute_stage: Vec<u32>,
+    pub environment_convolution_compute_stage: Vec<u32>,
+    pub equirectangular_to_cube_compute_stage: Vec<u32>,
+    pub material_preview_compute_stage: Vec<u32>,
 
     pub empty_fragment_stage: Vec<u32>,
 
@@ -17,13 +23,53 @@ pub struct DiskCommonShaders {
     pub occluder_resolve_vertex_stage: Vec<u32>,
     pub occluder_resolve_fragment_stage: Vec<u32>,
 
+    pub full_screen_vertex_stage: Vec<u32>, // see FullScreenPass
+
     pub skybox_vertex_stage: Vec<u32>,
     pub skybox_fragment_stage: Vec<u32>,
 
-    pub anti_aliasing_vertex_stage: Vec<u32>,
     pub anti_aliasing_fragment_stage: Vec<u32>,
 
-    pub tone_map_vertex_stage: Vec<u32>,
+    pub half_resolution_vertex_stage: Vec<u32>,
+    pub depth_downsample_fragment_stage: Vec<u32>,
+    pub half_resolution_composite_fragment_stage: Vec<u32>,
+
+    pub volumetric_fog_scattering_compute_stage: Vec<u32>,
+    pub volumetric_fog_integration_compute_stage: Vec<u32>,
+    pub volumetric_fog_vertex_stage: Vec<u32>,
+    pub volumetric_fog_fragment_stage: Vec<u32>,
+
+    pub hi_z_downsample_compute_stage: Vec<u32>,
+    pub reflection_trace_compute_stage: Vec<u32>,
+    pub reflection_composite_vertex_stage: Vec<u32>,
+    pub reflection_composite_fragment_stage: Vec<u32>,
+
+    pub water_surface_vertex_stage: Vec<u32>,
+    pub water_surface_fragment_stage: Vec<u32>,
+
+    pub transparency_composite_vertex_stage: Vec<u32>,
+    pub weighted_blended_composite_fragment_stage: Vec<u32>,
+    pub linked_lists_composite_fragment_stage: Vec<u32>,
+
+    pub overdraw_heatmap_vertex_stage: Vec<u32>,
+    pub overdraw_heatmap_fragment_stage: Vec<u32>,
+
+    pub depth_view_vertex_stage: Vec<u32>,
+    pub depth_view_fragment_stage: Vec<u32>,
+
+    pub nan_detection_scan_image_compute_stage: Vec<u32>,
+    pub nan_detection_scan_buffer_compute_stage: Vec<u32>,
+    pub nan_detection_tint_fragment_stage: Vec<u32>,
+
+    pub shader_clock_heatmap_fragment_stage: Vec<u32>,
+
+    pub path_tracer_instance_compute_stage: Vec<u32>,
+    pub path_tracer_ray_gen_stage: Vec<u32>,
+    pub path_tracer_closest_hit_stage: Vec<u32>,
+    pub path_tracer_any_hit_stage: Vec<u32>,
+    pub path_tracer_miss_stage: Vec<u32>,
+    pub path_tracer_shadow_miss_stage: Vec<u32>,
+
     pub tone_map_fragment_stage: Vec<u32>,
 
     pub imgui_vertex_stage: Vec<u32>,
@@ -0,0 +1,286 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::camera::*;
+use crate::common_shaders::*;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DepthViewSource {
+    SceneDepth,
+    HiZ(u32), // mip level of the screen space reflection depth pyramid
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DepthViewParameters {
+    pub source: DepthViewSource,
+    pub max_distance: f32, // distance that is displayed as black, in world units
+}
+
+impl Default for DepthViewParameters {
+    fn default() -> Self {
+        Self {
+            source: DepthViewSource::SceneDepth,
+            max_distance: 1000.0,
+        }
+    }
+}
+
+// Replaces the final image with linearized depth of the scene or one level of the depth pyramid
+pub struct DepthView {
+    point_sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    depth_descriptor_set: vk::DescriptorSet,
+    hi_z_descriptor_set: vk::DescriptorSet,
+
+    vert_module: vk::ShaderModule,
+    frag_module: vk::ShaderModule,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl DepthView {
+    // Depth image is expected in SHADER_READ_ONLY_OPTIMAL and the depth pyramid in GENERAL layout
+    pub fn new(
+        common_shaders: &DiskCommonShaders,
+        target_layer: &RenderLayer,
+        depth_image_view: vk::ImageView,
+        hi_z_image_view: vk::ImageView,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        let point_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build(),
+        );
+
+        let descriptor_pool = factory.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(2)
+                .pool_sizes(&[vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(2)
+                    .build()]),
+        );
+        let descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()]),
+        );
+        let descriptor_sets = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&[descriptor_set_layout, descriptor_set_layout])
+                .build(),
+        );
+        let (depth_descriptor_set, hi_z_descriptor_set) = (descriptor_sets[0], descriptor_sets[1]);
+        factory.update_descriptor_sets(
+            &[
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(depth_descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .sampler(point_sampler)
+                        .image_view(depth_image_view)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .build()])
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(hi_z_descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .sampler(point_sampler)
+                        .image_view(hi_z_image_view)
+                        .image_layout(vk::ImageLayout::GENERAL)
+                        .build()])
+                    .build(),
+            ],
+            &[],
+        );
+
+        let vert_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.depth_view_vertex_stage)
+                .build(),
+        );
+        let frag_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.depth_view_fragment_stage)
+                .build(),
+        );
+
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[descriptor_set_layout])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .offset(0)
+                    .size(32)
+                    .build()])
+                .build(),
+        );
+
+        // Depth view overwrites the final image after tone mapping
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let vertex_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(vert_module)
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let fragment_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(frag_module)
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let pipeline = factory.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[target_layer.make_pipeline_create_info(
+                vk::GraphicsPipelineCreateInfo::builder()
+                    .stages(&[vertex_stage, fragment_stage])
+                    .vertex_input_state(
+                        &vk::PipelineVertexInputStateCreateInfo::builder()
+                            .vertex_binding_descriptions(&[])
+                            .build(),
+                    )
+                    .input_assembly_state(
+                        &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                            .primitive_restart_enable(false)
+                            .build(),
+                    )
+                    .tessellation_state(&Default::default())
+                    .viewport_state(
+                        &vk::PipelineViewportStateCreateInfo::builder()
+                            .viewport_count(1)
+                            .scissor_count(1)
+                            .build(),
+                    )
+                    .rasterization_state(
+                        &vk::PipelineRasterizationStateCreateInfo::builder()
+                            .cull_mode(vk::CullModeFlags::NONE)
+                            .line_width(1.0)
+                            .build(),
+                    )
+                    .multisample_state(
+                        &vk::PipelineMultisampleStateCreateInfo::builder()
+                            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                            .build(),
+                    )
+                    .depth_stencil_state(&Default::default())
+                    .color_blend_state(
+                        &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                            vk::PipelineColorBlendAttachmentState::builder()
+                                .blend_enable(false)
+                                .color_write_mask(
+                                    vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B
+                                        | vk::ColorComponentFlags::A,
+                                )
+                                .build(),
+                        ]),
+                    )
+                    .dynamic_state(
+                        &vk::PipelineDynamicStateCreateInfo::builder()
+                            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                            .build(),
+                    )
+                    .layout(pipeline_layout)
+                    .subpass(0)
+                    .base_pipeline_handle(vk::Pipeline::null())
+                    .base_pipeline_index(0)
+                    .build(),
+            )],
+        )[0];
+
+        Self {
+            point_sampler,
+            descriptor_pool,
+            descriptor_set_layout,
+            depth_descriptor_set,
+            hi_z_descriptor_set,
+            vert_module,
+            frag_module,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        factory.destroy_sampler(self.point_sampler);
+        factory.destroy_descriptor_pool(self.descriptor_pool);
+        factory.destroy_descriptor_set_layout(self.descriptor_set_layout);
+        factory.destroy_shader_module(self.vert_module);
+        factory.destroy_shader_module(self.frag_module);
+        factory.destroy_pipeline_layout(self.pipeline_layout);
+        factory.destroy_pipeline(self.pipeline);
+    }
+
+    // Output area is the whole viewport, render scale maps it to the rendered area.
+    // Scene depth is displayed if there is no depth pyramid level.
+    pub fn render(
+        &mut self,
+        output_area: vk::Rect2D,
+        render_scale: f32,
+        max_distance: f32,
+        hi_z_level: Option<u32>,
+        frame_context: &FrameContext,
+        target_layer: &mut RenderLayer,
+    ) {
+        let command_buffer = target_layer.get_command_buffer(frame_context);
+        let (descriptor_set, level) = match hi_z_level {
+            Some(level) => (self.hi_z_descriptor_set, level),
+            None => (self.depth_descriptor_set, 0),
+        };
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &[descriptor_set],
+            &[],
+        );
+        command_buffer.set_viewport(
+            0,
+            &[vk::Viewport {
+                x: output_area.offset.x as _,
+                y: output_area.offset.y as _,
+                width: output_area.extent.width as _,
+                height: output_area.extent.height as _,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+        );
+        command_buffer.set_scissor(0, &[output_area]);
+        command_buffer.push_constants(
+            self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &[
+                output_area.offset.x as f32,
+                output_area.offset.y as f32,
+                render_scale,
+                CAMERA_Z_NEAR,
+                max_distance.max(CAMERA_Z_NEAR),
+                level as f32,
+                0.0,
+                0.0,
+            ],
+        );
+        command_buffer.draw(3, 1, 0, 0);
+    }
+}
@@ -0,0 +1,534 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+use malwerks_core::*;
+use malwerks_dds::*;
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+
+const IEM_SIZE: u32 = 32;
+const IEM_SAMPLE_COUNT: u32 = 1024;
+
+// Mip roughness has to match the PMREM lookup in gltf_pbr_material.glsl, which uses roughness * 10 as LOD
+const PMREM_SIZE: u32 = 512;
+const PMREM_MIP_COUNT: usize = 10;
+const PMREM_ROUGHNESS_PER_MIP: f32 = 0.1;
+const PMREM_SAMPLE_COUNT: u32 = 512;
+
+const CONVOLUTION_GROUP_SIZE: u32 = 8;
+const CONVOLUTION_MODE_IEM: u32 = 0;
+const CONVOLUTION_MODE_PMREM: u32 = 1;
+
+pub const CUBE_TEXEL_SIZE: usize = 4 * std::mem::size_of::<u16>(); // R16G16B16A16_SFLOAT
+
+// Probes that are convolved at the same time, each one has its own source image and readback buffer
+const MAX_CONVOLUTIONS_IN_FLIGHT: usize = 2;
+
+struct ConvolutionSource {
+    image: HeapAllocatedResource<vk::Image>,
+    image_view: vk::ImageView,
+    upload_buffer: HeapAllocatedResource<vk::Buffer>,
+}
+
+struct ConvolutionSlot {
+    command_buffer: CommandBuffer,
+    fence: vk::Fence,
+    descriptor_set: vk::DescriptorSet,
+    output_buffer: HeapAllocatedResource<vk::Buffer>,
+    source: Option<ConvolutionSource>,
+}
+
+// Filters skybox cube maps into IEM and PMREM images on the GPU, replaces externally convolved probe_iem.dds
+// and probe_pmrem.dds. Several probes are in flight at once and are waited on with fences, so the CPU loads
+// the next probes and processes finished ones while the GPU is busy. Results are read back and returned in
+// the same layout as imported images.
+pub struct EnvironmentConvolution {
+    command_pool: vk::CommandPool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    source_sampler: vk::Sampler,
+    compute_module: vk::ShaderModule,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    passes: Vec<ConvolutionConstants>,
+    pmrem_offset: usize,
+    output_buffer_size: usize,
+    slots: Vec<ConvolutionSlot>,
+}
+
+impl EnvironmentConvolution {
+    pub fn new(common_shaders: &DiskCommonShaders, device: &Device, factory: &mut DeviceFactory) -> Self {
+        let iem_face_stride = get_face_texel_count(IEM_SIZE, 1);
+        let pmrem_face_stride = get_face_texel_count(PMREM_SIZE, PMREM_MIP_COUNT);
+        let pmrem_offset = 6 * iem_face_stride;
+        let output_buffer_size = (pmrem_offset + 6 * pmrem_face_stride) * CUBE_TEXEL_SIZE;
+
+        let mut passes = vec![ConvolutionConstants {
+            convolution_mode: CONVOLUTION_MODE_IEM,
+            face_size: IEM_SIZE,
+            sample_count: IEM_SAMPLE_COUNT,
+            roughness: 0.0,
+            output_offset: 0,
+            output_face_stride: iem_face_stride as _,
+        }];
+        let mut mip_offset = pmrem_offset;
+        for mip in 0..PMREM_MIP_COUNT {
+            passes.push(ConvolutionConstants {
+                convolution_mode: CONVOLUTION_MODE_PMREM,
+                face_size: (PMREM_SIZE >> mip).max(1),
+                sample_count: PMREM_SAMPLE_COUNT,
+                roughness: mip as f32 * PMREM_ROUGHNESS_PER_MIP,
+                output_offset: mip_offset as _,
+                output_face_stride: pmrem_face_stride as _,
+            });
+            mip_offset += get_mip_texel_count(PMREM_SIZE, mip);
+        }
+
+        let command_pool = factory.create_command_pool(
+            &vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(device.get_graphics_queue_index())
+                .build(),
+        );
+        let command_buffers = factory.allocate_command_buffers(
+            &vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(MAX_CONVOLUTIONS_IN_FLIGHT as _)
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .build(),
+        );
+
+        let source_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .min_lod(0.0)
+                .max_lod(f32::MAX)
+                .build(),
+        );
+        let descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&[
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                ])
+                .build(),
+        );
+        let descriptor_pool = factory.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(MAX_CONVOLUTIONS_IN_FLIGHT as _)
+                .pool_sizes(&[
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(MAX_CONVOLUTIONS_IN_FLIGHT as _)
+                        .build(),
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(MAX_CONVOLUTIONS_IN_FLIGHT as _)
+                        .build(),
+                ])
+                .build(),
+        );
+        let descriptor_sets = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&[descriptor_set_layout; MAX_CONVOLUTIONS_IN_FLIGHT])
+                .build(),
+        );
+
+        let compute_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.environment_convolution_compute_stage)
+                .build(),
+        );
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[descriptor_set_layout])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .offset(0)
+                    .size(std::mem::size_of::<ConvolutionConstants>() as _)
+                    .build()])
+                .build(),
+        );
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let pipeline = factory.create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::ComputePipelineCreateInfo::builder()
+                .stage(
+                    vk::PipelineShaderStageCreateInfo::builder()
+                        .name(&entry_name)
+                        .module(compute_module)
+                        .stage(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                )
+                .layout(pipeline_layout)
+                .build()],
+        )[0];
+
+        let slots = command_buffers
+            .into_iter()
+            .zip(descriptor_sets)
+            .map(|(command_buffer, descriptor_set)| {
+                let output_buffer = factory.allocate_buffer(
+                    &vk::BufferCreateInfo::builder()
+                        .size(output_buffer_size as _)
+                        .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+                        .build(),
+                    &vk_mem::AllocationCreateInfo {
+                        usage: vk_mem::MemoryUsage::GpuToCpu,
+                        required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE,
+                        ..Default::default()
+                    },
+                );
+                factory.update_descriptor_sets(
+                    &[vk::WriteDescriptorSet::builder()
+                        .dst_set(descriptor_set)
+                        .dst_binding(1)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .buffer_info(&[vk::DescriptorBufferInfo::builder()
+                            .buffer(output_buffer.0)
+                            .offset(0)
+                            .range(vk::WHOLE_SIZE)
+                            .build()])
+                        .build()],
+                    &[],
+                );
+                ConvolutionSlot {
+                    command_buffer,
+                    fence: factory.create_fence(&vk::FenceCreateInfo::default()),
+                    descriptor_set,
+                    output_buffer,
+                    source: None,
+                }
+            })
+            .collect();
+
+        Self {
+            command_pool,
+            descriptor_set_layout,
+            descriptor_pool,
+            source_sampler,
+            compute_module,
+            pipeline_layout,
+            pipeline,
+            passes,
+            pmrem_offset,
+            output_buffer_size,
+            slots,
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        for slot in &self.slots {
+            assert!(
+                slot.source.is_none(),
+                "environment probe convolution is still in flight"
+            );
+            factory.destroy_fence(slot.fence);
+            factory.deallocate_buffer(&slot.output_buffer);
+        }
+        let command_buffers: Vec<CommandBuffer> = self.slots.iter().map(|slot| slot.command_buffer).collect();
+        factory.free_command_buffers(self.command_pool, &command_buffers);
+        factory.destroy_command_pool(self.command_pool);
+        factory.destroy_pipeline(self.pipeline);
+        factory.destroy_pipeline_layout(self.pipeline_layout);
+        factory.destroy_shader_module(self.compute_module);
+        factory.destroy_descriptor_pool(self.descriptor_pool);
+        factory.destroy_descriptor_set_layout(self.descriptor_set_layout);
+        factory.destroy_sampler(self.source_sampler);
+    }
+
+    // Probe images are loaded on demand right before they are submitted, finished probes are passed to
+    // `on_convolved` in the same order together with their IEM and PMREM images
+    pub fn convolve_environment_probes<L, F>(
+        &mut self,
+        probe_count: usize,
+        mut load_probe_image: L,
+        mut on_convolved: F,
+        device: &Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) where
+        L: FnMut(usize, &mut DeviceFactory, &mut DeviceQueue) -> DiskImage,
+        F: FnMut(usize, DiskImage, DiskImage, DiskImage),
+    {
+        let mut in_flight = std::collections::VecDeque::with_capacity(self.slots.len());
+        for probe_id in 0..probe_count {
+            let probe_image = load_probe_image(probe_id, factory, queue);
+            if in_flight.len() == self.slots.len() {
+                let (finished_id, finished_image) = in_flight.pop_front().unwrap();
+                let (iem_image, pmrem_image) = self.finish_convolution(finished_id % self.slots.len(), device, factory);
+                on_convolved(finished_id, finished_image, iem_image, pmrem_image);
+            }
+            self.begin_convolution(probe_id % self.slots.len(), &probe_image, factory, queue);
+            in_flight.push_back((probe_id, probe_image));
+        }
+        for (finished_id, finished_image) in in_flight {
+            let (iem_image, pmrem_image) = self.finish_convolution(finished_id % self.slots.len(), device, factory);
+            on_convolved(finished_id, finished_image, iem_image, pmrem_image);
+        }
+    }
+
+    fn begin_convolution(
+        &mut self,
+        slot_id: usize,
+        probe_image: &DiskImage,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) {
+        assert_eq!(
+            vk::ImageViewType::from_raw(probe_image.view_type),
+            vk::ImageViewType::CUBE,
+            "environment probe image has to be a cube map"
+        );
+
+        let slot = &mut self.slots[slot_id];
+        assert!(slot.source.is_none(), "convolution slot is still in flight");
+
+        let image = factory.allocate_image(
+            &vk::ImageCreateInfo::builder()
+                .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE)
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(vk::Format::from_raw(probe_image.format))
+                .extent(vk::Extent3D {
+                    width: probe_image.width,
+                    height: probe_image.height,
+                    depth: 1,
+                })
+                .mip_levels(probe_image.mipmap_count as _)
+                .array_layers(probe_image.layer_count as _)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ..Default::default()
+            },
+        );
+        let image_view = factory.create_image_view(
+            &vk::ImageViewCreateInfo::builder()
+                .image(image.0)
+                .view_type(vk::ImageViewType::CUBE)
+                .format(vk::Format::from_raw(probe_image.format))
+                .components(vk::ComponentMapping::default())
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(probe_image.mipmap_count as _)
+                        .base_array_layer(0)
+                        .layer_count(6)
+                        .build(),
+                )
+                .build(),
+        );
+        factory.update_descriptor_sets(
+            &[vk::WriteDescriptorSet::builder()
+                .dst_set(slot.descriptor_set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&[vk::DescriptorImageInfo::builder()
+                    .sampler(self.source_sampler)
+                    .image_view(image_view)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .build()])
+                .build()],
+            &[],
+        );
+
+        let command_buffer = &mut slot.command_buffer;
+        command_buffer.reset();
+        command_buffer.begin(
+            &vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build(),
+        );
+        let upload_buffer = upload_image_memory(
+            &image,
+            (probe_image.width, probe_image.height, 1),
+            (
+                probe_image.block_size,
+                probe_image.mipmap_count,
+                probe_image.layer_count,
+            ),
+            &probe_image.pixels,
+            command_buffer,
+            factory,
+        );
+        // Upload is made visible to fragment shaders, convolution reads it in compute
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build()],
+            &[],
+            &[],
+        );
+
+        // Passes write separate parts of the output buffer and don't need barriers between them
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, self.pipeline);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+            0,
+            &[slot.descriptor_set],
+            &[],
+        );
+        for constants in &self.passes {
+            command_buffer.push_constants(self.pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, &[*constants]);
+            let group_count = constants.face_size.div_ceil(CONVOLUTION_GROUP_SIZE);
+            command_buffer.dispatch(group_count, group_count, 6);
+        }
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::HOST,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .build()],
+            &[],
+            &[],
+        );
+        command_buffer.end();
+
+        queue.submit(
+            &[vk::SubmitInfo::builder()
+                .command_buffers(&[(*command_buffer).into()])
+                .build()],
+            slot.fence,
+        );
+        slot.source = Some(ConvolutionSource {
+            image,
+            image_view,
+            upload_buffer,
+        });
+    }
+
+    fn finish_convolution(
+        &mut self,
+        slot_id: usize,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) -> (DiskImage, DiskImage) {
+        let slot = &mut self.slots[slot_id];
+        let source = slot.source.take().expect("convolution slot is not in flight");
+        device.wait_for_fences(&[slot.fence], true, u64::MAX);
+        device.reset_fences(&[slot.fence]);
+
+        let mut pixels = vec![0u8; self.output_buffer_size];
+        let output_memory = factory.map_allocation_memory(&slot.output_buffer);
+        unsafe {
+            std::ptr::copy_nonoverlapping(output_memory, pixels.as_mut_ptr(), pixels.len());
+        }
+        factory.unmap_allocation_memory(&slot.output_buffer);
+
+        factory.destroy_image_view(source.image_view);
+        factory.deallocate_image(&source.image);
+        factory.deallocate_buffer(&source.upload_buffer);
+
+        let pmrem_pixels = pixels.split_off(self.pmrem_offset * CUBE_TEXEL_SIZE);
+        (
+            create_cube_image(IEM_SIZE, 1, pixels),
+            create_cube_image(PMREM_SIZE, PMREM_MIP_COUNT, pmrem_pixels),
+        )
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct ConvolutionConstants {
+    convolution_mode: u32,
+    face_size: u32,
+    sample_count: u32,
+    roughness: f32,
+    output_offset: u32,
+    output_face_stride: u32,
+}
+
+// Mips are padded to whole 4x4 blocks, same as upload_image_memory() expects
+pub fn get_mip_texel_count(size: u32, mip: usize) -> usize {
+    let block_count = ((size >> mip).max(1) as usize).div_ceil(4);
+    16 * block_count * block_count
+}
+
+pub fn get_face_texel_count(size: u32, mip_count: usize) -> usize {
+    (0..mip_count).map(|mip| get_mip_texel_count(size, mip)).sum()
+}
+
+pub fn create_cube_image(size: u32, mip_count: usize, pixels: Vec<u8>) -> DiskImage {
+    DiskImage {
+        width: size,
+        height: size,
+        depth: 1,
+        block_size: 16 * CUBE_TEXEL_SIZE,
+        mipmap_count: mip_count,
+        layer_count: 6,
+        image_type: vk::ImageType::TYPE_2D.as_raw(),
+        view_type: vk::ImageViewType::CUBE.as_raw(),
+        format: vk::Format::R16G16B16A16_SFLOAT.as_raw(),
+        color_space: DiskColorSpace::Linear,
+        generate_mipmaps: false,
+        pixels,
+    }
+}
+
+// Writes a cube image made by create_cube_image() as DDS, mips in DDS are tightly packed
+pub fn save_cube_image(image: &DiskImage, path: &std::path::Path) -> Result<(), String> {
+    if image.format != vk::Format::R16G16B16A16_SFLOAT.as_raw() {
+        return Err(format!("unsupported cube image format {}", image.format));
+    }
+
+    let mut scratch_image = ScratchImage::new(
+        image.width,
+        image.height,
+        1,
+        image.mipmap_count as _,
+        1,
+        DXGI_FORMAT_R16G16B16A16_FLOAT,
+        true,
+    );
+    let dds_pixels = scratch_image.as_slice_mut();
+    let mut dds_offset = 0;
+    let mut image_offset = 0;
+    for _ in 0..image.layer_count {
+        for mip in 0..image.mipmap_count {
+            let mip_size = (image.width >> mip).max(1) as usize;
+            let mip_data_size = mip_size * mip_size * CUBE_TEXEL_SIZE;
+            dds_pixels[dds_offset..dds_offset + mip_data_size]
+                .copy_from_slice(&image.pixels[image_offset..image_offset + mip_data_size]);
+            dds_offset += mip_data_size;
+            image_offset += get_mip_texel_count(image.width, mip) * CUBE_TEXEL_SIZE;
+        }
+    }
+    assert_eq!(dds_offset, dds_pixels.len());
+    scratch_image
+        .try_save_to_file(path)
+        .map_err(|error| format!("failed to write {:?}: {:?}", path, error))
+}
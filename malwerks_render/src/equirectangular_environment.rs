@@ -0,0 +1,325 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+use crate::environment_convolution::*;
+
+const MIN_CUBE_SIZE: u32 = 64;
+const MAX_CUBE_SIZE: u32 = 1024;
+
+const CONVERSION_GROUP_SIZE: u32 = 8;
+const CONVERSION_MODE_PANORAMA: u32 = 0;
+const CONVERSION_MODE_DOWNSAMPLE: u32 = 1;
+
+// Loads a Radiance .hdr panorama as RGBA32F texels
+pub fn load_equirectangular_environment(path: &std::path::Path) -> (u32, u32, Vec<f32>) {
+    let is_exr = path
+        .extension()
+        .map(|extension| extension.eq_ignore_ascii_case("exr"))
+        .unwrap_or(false);
+    assert!(
+        !is_exr,
+        "OpenEXR environments are not supported, convert {:?} to .hdr",
+        path
+    );
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .open(path)
+        .expect("failed to open environment file");
+    let decoder =
+        image::codecs::hdr::HdrDecoder::new(std::io::BufReader::new(file)).expect("failed to read environment header");
+    let metadata = decoder.metadata();
+    let texels = decoder.read_image_hdr().expect("failed to decode environment");
+
+    let mut pixels = Vec::with_capacity(texels.len() * 4);
+    for texel in texels {
+        pixels.extend_from_slice(&[texel[0], texel[1], texel[2], 1.0]);
+    }
+    (metadata.width, metadata.height, pixels)
+}
+
+// Resamples an equirectangular panorama into an RGBA16F cube map with a full mip chain, so that it can be
+// used as probe_image.dds and convolved like one. Face size follows the panorama resolution.
+pub fn convert_equirectangular_environment(
+    width: u32,
+    height: u32,
+    pixels: &[f32],
+    common_shaders: &DiskCommonShaders,
+    command_buffer: &mut CommandBuffer,
+    factory: &mut DeviceFactory,
+    queue: &mut DeviceQueue,
+) -> DiskImage {
+    assert_eq!(pixels.len(), (width * height * 4) as usize);
+    let cube_size = (width / 4).next_power_of_two().clamp(MIN_CUBE_SIZE, MAX_CUBE_SIZE);
+    let cube_mip_count = get_full_mipmap_count((cube_size, cube_size, 1));
+
+    let panorama_texel_size = 4 * std::mem::size_of::<f32>();
+    let panorama_image = factory.allocate_image(
+        &vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build(),
+        &vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ..Default::default()
+        },
+    );
+    {
+        let pixel_bytes =
+            unsafe { std::slice::from_raw_parts(pixels.as_ptr() as *const u8, std::mem::size_of_val(pixels)) };
+        let mut upload_batch = UploadBatch::new(command_buffer);
+        upload_batch.upload_image_memory(
+            &panorama_image,
+            (width, height, 1),
+            (16 * panorama_texel_size, 1, 1),
+            pixel_bytes,
+            factory,
+        );
+        upload_batch.flush(factory, queue);
+    }
+    let panorama_image_view = factory.create_image_view(
+        &vk::ImageViewCreateInfo::builder()
+            .image(panorama_image.0)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .components(vk::ComponentMapping::default())
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(0)
+                    .level_count(1)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .build(),
+    );
+    let panorama_sampler = factory.create_sampler(
+        &vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::REPEAT)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .min_lod(0.0)
+            .max_lod(0.0)
+            .build(),
+    );
+
+    let face_stride = get_face_texel_count(cube_size, cube_mip_count);
+    let output_buffer_size = 6 * face_stride * CUBE_TEXEL_SIZE;
+    let output_buffer = factory.allocate_buffer(
+        &vk::BufferCreateInfo::builder()
+            .size(output_buffer_size as _)
+            .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+            .build(),
+        &vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuToCpu,
+            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE,
+            ..Default::default()
+        },
+    );
+
+    let descriptor_set_layout = factory.create_descriptor_set_layout(
+        &vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&[
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+            ])
+            .build(),
+    );
+    let descriptor_pool = factory.create_descriptor_pool(
+        &vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&[
+                vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .build(),
+                vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(1)
+                    .build(),
+            ])
+            .build(),
+    );
+    let descriptor_set = factory.allocate_descriptor_sets(
+        &vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&[descriptor_set_layout])
+            .build(),
+    )[0];
+    factory.update_descriptor_sets(
+        &[
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&[vk::DescriptorImageInfo::builder()
+                    .sampler(panorama_sampler)
+                    .image_view(panorama_image_view)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .build()])
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&[vk::DescriptorBufferInfo::builder()
+                    .buffer(output_buffer.0)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build()])
+                .build(),
+        ],
+        &[],
+    );
+
+    let compute_module = factory.create_shader_module(
+        &vk::ShaderModuleCreateInfo::builder()
+            .code(&common_shaders.equirectangular_to_cube_compute_stage)
+            .build(),
+    );
+    let pipeline_layout = factory.create_pipeline_layout(
+        &vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&[descriptor_set_layout])
+            .push_constant_ranges(&[vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .offset(0)
+                .size(std::mem::size_of::<ConversionConstants>() as _)
+                .build()])
+            .build(),
+    );
+    let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+    let pipeline = factory.create_compute_pipelines(
+        vk::PipelineCache::null(),
+        &[vk::ComputePipelineCreateInfo::builder()
+            .stage(
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .name(&entry_name)
+                    .module(compute_module)
+                    .stage(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+            )
+            .layout(pipeline_layout)
+            .build()],
+    )[0];
+
+    command_buffer.reset();
+    command_buffer.begin(
+        &vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build(),
+    );
+    command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, pipeline);
+    command_buffer.bind_descriptor_sets(
+        vk::PipelineBindPoint::COMPUTE,
+        pipeline_layout,
+        0,
+        &[descriptor_set],
+        &[],
+    );
+
+    // Every mip reads the one before it
+    let mut mip_offset = 0;
+    for mip in 0..cube_mip_count {
+        let constants = ConversionConstants {
+            conversion_mode: if mip == 0 {
+                CONVERSION_MODE_PANORAMA
+            } else {
+                CONVERSION_MODE_DOWNSAMPLE
+            },
+            face_size: (cube_size >> mip).max(1),
+            input_offset: if mip == 0 {
+                0
+            } else {
+                (mip_offset - get_mip_texel_count(cube_size, mip - 1)) as _
+            },
+            output_offset: mip_offset as _,
+            face_stride: face_stride as _,
+        };
+        command_buffer.push_constants(pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, &[constants]);
+        let group_count = constants.face_size.div_ceil(CONVERSION_GROUP_SIZE);
+        command_buffer.dispatch(group_count, group_count, 6);
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::HOST,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::HOST_READ)
+                .build()],
+            &[],
+            &[],
+        );
+        mip_offset += get_mip_texel_count(cube_size, mip);
+    }
+    command_buffer.end();
+
+    queue.submit(
+        &[vk::SubmitInfo::builder()
+            .command_buffers(&[(*command_buffer).into()])
+            .build()],
+        vk::Fence::null(),
+    );
+    queue.wait_idle();
+
+    let mut cube_pixels = vec![0u8; output_buffer_size];
+    let output_memory = factory.map_allocation_memory(&output_buffer);
+    unsafe {
+        std::ptr::copy_nonoverlapping(output_memory, cube_pixels.as_mut_ptr(), cube_pixels.len());
+    }
+    factory.unmap_allocation_memory(&output_buffer);
+
+    factory.destroy_pipeline(pipeline);
+    factory.destroy_pipeline_layout(pipeline_layout);
+    factory.destroy_shader_module(compute_module);
+    factory.destroy_descriptor_pool(descriptor_pool);
+    factory.destroy_descriptor_set_layout(descriptor_set_layout);
+    factory.deallocate_buffer(&output_buffer);
+    factory.destroy_sampler(panorama_sampler);
+    factory.destroy_image_view(panorama_image_view);
+    factory.deallocate_image(&panorama_image);
+
+    create_cube_image(cube_size, cube_mip_count, cube_pixels)
+}
+
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct ConversionConstants {
+    conversion_mode: u32,
+    face_size: u32,
+    input_offset: u32,
+    output_offset: u32,
+    face_stride: u32,
+}
@@ -0,0 +1,181 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::vk::Handle;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FrameGraphAttachment {
+    pub name: String, // "color0", "color1", ..., "depth"
+    pub format: String,
+    pub image: u64, // raw image handle, passes rendering to the same image share it
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FrameGraphPass {
+    pub name: String,
+    pub view_mask: u32,
+    pub dynamic_rendering: bool,
+    pub attachments: Vec<FrameGraphAttachment>,
+}
+
+// Semaphore wait of the target pass, source is None for semaphores that aren't signaled by a dumped pass
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FrameGraphDependency {
+    pub source_pass: Option<String>,
+    pub target_pass: String,
+    pub wait_stage_mask: String,
+}
+
+// Pass and dependency structure of the last rendered frame for offline inspection. Passes are expected in
+// submission order, dependencies are taken from their last submission.
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FrameGraphDump {
+    pub passes: Vec<FrameGraphPass>,
+    pub dependencies: Vec<FrameGraphDependency>,
+}
+
+impl FrameGraphDump {
+    pub fn new(passes: &[(&str, &RenderLayer)]) -> Self {
+        let mut frame_graph_dump = Self::default();
+        for (pass_name, layer) in passes {
+            let mut attachments = Vec::with_capacity(layer.get_render_image_count() + 1);
+            for image_id in 0..layer.get_render_image_count() {
+                attachments.push(FrameGraphAttachment {
+                    name: format!("color{}", image_id),
+                    format: format!("{:?}", layer.get_color_format(image_id)),
+                    image: layer.get_render_image(image_id).0.as_raw(),
+                });
+            }
+            if let (Some((depth_image, _)), Some(depth_format)) = (layer.get_depth_image(), layer.get_depth_format()) {
+                attachments.push(FrameGraphAttachment {
+                    name: "depth".to_string(),
+                    format: format!("{:?}", depth_format),
+                    image: depth_image.as_raw(),
+                });
+            }
+            frame_graph_dump.passes.push(FrameGraphPass {
+                name: pass_name.to_string(),
+                view_mask: layer.get_view_mask(),
+                dynamic_rendering: layer.get_pipeline_rendering_info().is_some(),
+                attachments,
+            });
+
+            for (semaphore, wait_stage_mask) in layer.get_submitted_waits() {
+                let source_pass = passes
+                    .iter()
+                    .find(|(_, source_layer)| source_layer.is_signal_semaphore(*semaphore))
+                    .map(|(source_name, _)| source_name.to_string());
+                frame_graph_dump.dependencies.push(FrameGraphDependency {
+                    source_pass,
+                    target_pass: pass_name.to_string(),
+                    wait_stage_mask: format!("{:?}", wait_stage_mask),
+                });
+            }
+        }
+        frame_graph_dump
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("failed to serialize frame graph")
+    }
+
+    // Semaphore waits are solid edges, images written by an earlier pass are dashed edges
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph frame_graph {\n    rankdir=LR;\n    node [shape=record];\n");
+        let find_pass = |pass_name: &str| {
+            self.passes
+                .iter()
+                .position(|pass| pass.name == pass_name)
+                .expect("dependency of a pass that isn't dumped")
+        };
+
+        for (pass_id, pass) in self.passes.iter().enumerate() {
+            let mut label = escape_dot_record(&pass.name);
+            if pass.view_mask != 0 {
+                label += &format!("\\nview mask {:#x}", pass.view_mask);
+            }
+            if pass.dynamic_rendering {
+                label += "\\ndynamic rendering";
+            }
+            for attachment in &pass.attachments {
+                label += &format!(
+                    "|{}: {}",
+                    escape_dot_record(&attachment.name),
+                    escape_dot_record(&attachment.format)
+                );
+            }
+            dot += &format!("    pass_{} [label=\"{{{}}}\"];\n", pass_id, label);
+        }
+        if self
+            .dependencies
+            .iter()
+            .any(|dependency| dependency.source_pass.is_none())
+        {
+            dot += "    external [shape=ellipse];\n";
+        }
+
+        for dependency in &self.dependencies {
+            let source_node = match &dependency.source_pass {
+                Some(source_pass) => format!("pass_{}", find_pass(source_pass)),
+                None => "external".to_string(),
+            };
+            dot += &format!(
+                "    {} -> pass_{} [label=\"{}\"];\n",
+                source_node,
+                find_pass(&dependency.target_pass),
+                escape_dot_string(&dependency.wait_stage_mask)
+            );
+        }
+
+        for (pass_id, pass) in self.passes.iter().enumerate() {
+            for attachment in &pass.attachments {
+                let source_pass_id = self.passes[..pass_id].iter().rposition(|source_pass| {
+                    source_pass
+                        .attachments
+                        .iter()
+                        .any(|source_attachment| source_attachment.image == attachment.image)
+                });
+                if let Some(source_pass_id) = source_pass_id {
+                    dot += &format!(
+                        "    pass_{} -> pass_{} [style=dashed, label=\"{}\"];\n",
+                        source_pass_id,
+                        pass_id,
+                        escape_dot_string(&attachment.name)
+                    );
+                }
+            }
+        }
+
+        dot += "}\n";
+        dot
+    }
+
+    // Format is picked by the extension, ".dot" or ".json"
+    pub fn save_to_file(&self, path: &std::path::Path) {
+        let contents = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("dot") => self.to_dot(),
+            Some("json") => self.to_json(),
+            _ => panic!("unsupported frame graph dump format: {:?}", path),
+        };
+        std::fs::write(path, contents).unwrap_or_else(|error| panic!("failed to write {:?}: {:?}", path, error));
+    }
+}
+
+fn escape_dot_string(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Record labels treat braces, bars and angle brackets as structure
+fn escape_dot_record(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        if "{}|<>\"\\".contains(character) {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
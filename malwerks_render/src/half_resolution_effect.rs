@@ -0,0 +1,22 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_vk::*;
+
+// Expensive transparent effects (particles, volumetrics) are rendered at half resolution and
+// composited over the full resolution HDR image afterwards. Target is cleared to (0, 0, 0, 1),
+// effects are expected to output premultiplied color and accumulate transmittance in alpha.
+// Depth attachment contains conservatively downsampled scene depth and must not be written.
+pub trait HalfResolutionEffect {
+    fn destroy(&mut self, factory: &mut DeviceFactory);
+
+    fn render(
+        &mut self,
+        command_buffer: &mut CommandBuffer,
+        frame_context: &FrameContext,
+        frame_data_descriptor_set: vk::DescriptorSet,
+        frame_data_offset: u32, // dynamic offset of the frame data uniform buffer
+    );
+}
@@ -0,0 +1,631 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+use crate::half_resolution_effect::*;
+
+const HALF_COLOR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const HALF_DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+// The source layer provides full resolution color and depth, the color image is also the composite target
+pub struct HalfResolutionPassParameters<'a> {
+    pub source_layer: &'a RenderLayer,
+    pub source_color_image: usize,
+    pub source_color_format: vk::Format,
+    pub render_width: u32,
+    pub render_height: u32,
+    pub transient_images: &'a TransientImagePool,
+    pub first_transient_image: usize, // half resolution color, depth follows it
+}
+
+pub struct HalfResolutionRenderParameters<'a> {
+    pub source_layer: &'a RenderLayer,
+    pub dependency_layer: &'a RenderLayer,
+    pub source_color_image: usize,
+    pub screen_area: vk::Rect2D,
+    pub frame_data_descriptor_set: vk::DescriptorSet,
+    pub frame_data_offset: u32,
+}
+
+pub struct HalfResolutionPass {
+    half_layer: RenderLayer,
+    composite_layer: RenderLayer,
+
+    point_sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_set: vk::DescriptorSet,
+
+    vert_module: vk::ShaderModule,
+    downsample_frag_module: vk::ShaderModule,
+    composite_frag_module: vk::ShaderModule,
+
+    pipeline_layout: vk::PipelineLayout,
+    downsample_pipeline: vk::Pipeline,
+    composite_pipeline: vk::Pipeline,
+}
+
+impl HalfResolutionPass {
+    // Half resolution color and depth are only used while the pass is rendered
+    pub fn get_transient_image_parameters(
+        render_width: u32,
+        render_height: u32,
+        pass: u32,
+    ) -> Vec<TransientImageParameters> {
+        let make_parameters = |image_format, image_usage, aspect_mask| TransientImageParameters {
+            width: render_width.div_ceil(2),
+            height: render_height.div_ceil(2),
+            image_format,
+            image_usage,
+            aspect_mask,
+            first_pass: pass,
+            last_pass: pass,
+        };
+        vec![
+            make_parameters(
+                HALF_COLOR_FORMAT,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                vk::ImageAspectFlags::COLOR,
+            ),
+            make_parameters(
+                HALF_DEPTH_FORMAT,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                vk::ImageAspectFlags::DEPTH,
+            ),
+        ]
+    }
+
+    pub fn new(
+        parameters: &HalfResolutionPassParameters,
+        common_shaders: &DiskCommonShaders,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        let source_layer = parameters.source_layer;
+        let render_width = parameters.render_width;
+        let render_height = parameters.render_height;
+        let first_transient_image = parameters.first_transient_image;
+
+        let half_layer = RenderLayer::from_transient_images(
+            device,
+            factory,
+            &RenderLayerParameters {
+                render_image_parameters: &[RenderImageParameters {
+                    image_format: HALF_COLOR_FORMAT,
+                    image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                    image_clear_value: vk::ClearValue {
+                        color: vk::ClearColorValue {
+                            float32: [0.0, 0.0, 0.0, 1.0],
+                        },
+                    },
+                }],
+                depth_image_parameters: Some(RenderImageParameters {
+                    image_format: HALF_DEPTH_FORMAT,
+                    image_usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                    image_clear_value: vk::ClearValue::default(),
+                }),
+                render_pass_parameters: &[RenderPassParameters {
+                    flags: vk::SubpassDescriptionFlags::default(),
+                    pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+                    input_attachments: None,
+                    color_attachments: Some(&[vk::AttachmentReference::builder()
+                        .attachment(0)
+                        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .build()]),
+                    resolve_attachments: None,
+                    depth_stencil_attachment: Some(
+                        &vk::AttachmentReference::builder()
+                            .attachment(1)
+                            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                            .build(),
+                    ),
+                    preserve_attachments: None,
+                }],
+                render_pass_dependencies: None,
+                view_mask: 0,
+            },
+            &TransientLayerImages {
+                width: render_width.div_ceil(2),
+                height: render_height.div_ceil(2),
+                transient_images: parameters.transient_images,
+                image_indices: &[first_transient_image],
+                depth_image_index: Some(first_transient_image + 1),
+            },
+        );
+        let composite_layer = RenderLayer::from_shared_images(
+            device,
+            factory,
+            source_layer,
+            render_width,
+            render_height,
+            &[SharedImageParameters {
+                image_index: parameters.source_color_image,
+                image_format: parameters.source_color_format,
+                initial_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }],
+        );
+
+        let vert_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.half_resolution_vertex_stage)
+                .build(),
+        );
+        let downsample_frag_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.depth_downsample_fragment_stage)
+                .build(),
+        );
+        let composite_frag_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.half_resolution_composite_fragment_stage)
+                .build(),
+        );
+
+        let point_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .min_lod(0.0)
+                .max_lod(f32::MAX)
+                .build(),
+        );
+
+        let descriptor_pool = factory.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder().max_sets(1).pool_sizes(&[
+                vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::SAMPLER)
+                    .descriptor_count(1)
+                    .build(),
+                vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                    .descriptor_count(3)
+                    .build(),
+            ]),
+        );
+        let descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(1)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(2)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(3)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+            ]),
+        );
+        let descriptor_set = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&[descriptor_set_layout])
+                .build(),
+        )[0];
+
+        // Depth downsample only reads the source depth, half resolution images are
+        // statically unused by it and can stay bound while being rendered to
+        let source_depth_image = source_layer
+            .get_depth_image()
+            .expect("Depth image is required for half resolution effects")
+            .1;
+        let half_depth_image = half_layer.get_depth_image().unwrap().1;
+        factory.update_descriptor_sets(
+            &[
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::builder().sampler(point_sampler).build()])
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .image_view(source_depth_image)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .build()])
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(2)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .image_view(half_layer.get_render_image(0).1)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .build()])
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(3)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .image_view(half_depth_image)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .build()])
+                    .build(),
+            ],
+            &[],
+        );
+
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[descriptor_set_layout])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .offset(0)
+                    .size(std::mem::size_of::<[i32; 4]>() as _)
+                    .build()])
+                .build(),
+        );
+
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let vertex_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(vert_module)
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let downsample_fragment_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(downsample_frag_module)
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let composite_fragment_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(composite_frag_module)
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+
+        let downsample_pipeline = factory.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[half_layer.make_pipeline_create_info(
+                vk::GraphicsPipelineCreateInfo::builder()
+                    .stages(&[vertex_stage, downsample_fragment_stage])
+                    .vertex_input_state(
+                        &vk::PipelineVertexInputStateCreateInfo::builder()
+                            .vertex_binding_descriptions(&[])
+                            .build(),
+                    )
+                    .input_assembly_state(
+                        &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                            .primitive_restart_enable(false)
+                            .build(),
+                    )
+                    .tessellation_state(&Default::default())
+                    .viewport_state(
+                        &vk::PipelineViewportStateCreateInfo::builder()
+                            .viewport_count(1)
+                            .scissor_count(1)
+                            .build(),
+                    )
+                    .rasterization_state(
+                        &vk::PipelineRasterizationStateCreateInfo::builder()
+                            .line_width(1.0)
+                            .build(),
+                    )
+                    .multisample_state(
+                        &vk::PipelineMultisampleStateCreateInfo::builder()
+                            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                            .build(),
+                    )
+                    .depth_stencil_state(
+                        &vk::PipelineDepthStencilStateCreateInfo::builder()
+                            .flags(Default::default())
+                            .depth_test_enable(true)
+                            .depth_write_enable(true)
+                            .depth_compare_op(vk::CompareOp::ALWAYS)
+                            .stencil_test_enable(false)
+                            .build(),
+                    )
+                    .color_blend_state(
+                        &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                            vk::PipelineColorBlendAttachmentState::builder()
+                                .blend_enable(false)
+                                .color_write_mask(vk::ColorComponentFlags::empty())
+                                .build(),
+                        ]),
+                    )
+                    .dynamic_state(
+                        &vk::PipelineDynamicStateCreateInfo::builder()
+                            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                            .build(),
+                    )
+                    .layout(pipeline_layout)
+                    .subpass(0)
+                    .base_pipeline_handle(vk::Pipeline::null())
+                    .base_pipeline_index(0)
+                    .build(),
+            )],
+        )[0];
+
+        // Composite color is premultiplied and alpha contains transmittance of the effects
+        let composite_pipeline = factory.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[composite_layer.make_pipeline_create_info(
+                vk::GraphicsPipelineCreateInfo::builder()
+                    .stages(&[vertex_stage, composite_fragment_stage])
+                    .vertex_input_state(
+                        &vk::PipelineVertexInputStateCreateInfo::builder()
+                            .vertex_binding_descriptions(&[])
+                            .build(),
+                    )
+                    .input_assembly_state(
+                        &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                            .primitive_restart_enable(false)
+                            .build(),
+                    )
+                    .tessellation_state(&Default::default())
+                    .viewport_state(
+                        &vk::PipelineViewportStateCreateInfo::builder()
+                            .viewport_count(1)
+                            .scissor_count(1)
+                            .build(),
+                    )
+                    .rasterization_state(
+                        &vk::PipelineRasterizationStateCreateInfo::builder()
+                            .line_width(1.0)
+                            .build(),
+                    )
+                    .multisample_state(
+                        &vk::PipelineMultisampleStateCreateInfo::builder()
+                            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                            .build(),
+                    )
+                    .depth_stencil_state(&Default::default())
+                    .color_blend_state(
+                        &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                            vk::PipelineColorBlendAttachmentState::builder()
+                                .blend_enable(true)
+                                .src_color_blend_factor(vk::BlendFactor::ONE)
+                                .dst_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                                .color_blend_op(vk::BlendOp::ADD)
+                                .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+                                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                                .alpha_blend_op(vk::BlendOp::ADD)
+                                .color_write_mask(get_color_write_mask(parameters.source_color_format))
+                                .build(),
+                        ]),
+                    )
+                    .dynamic_state(
+                        &vk::PipelineDynamicStateCreateInfo::builder()
+                            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                            .build(),
+                    )
+                    .layout(pipeline_layout)
+                    .subpass(0)
+                    .base_pipeline_handle(vk::Pipeline::null())
+                    .base_pipeline_index(0)
+                    .build(),
+            )],
+        )[0];
+
+        Self {
+            half_layer,
+            composite_layer,
+            point_sampler,
+            descriptor_pool,
+            descriptor_set_layout,
+            descriptor_set,
+            vert_module,
+            downsample_frag_module,
+            composite_frag_module,
+            pipeline_layout,
+            downsample_pipeline,
+            composite_pipeline,
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        self.half_layer.destroy(factory);
+        self.composite_layer.destroy(factory);
+        factory.destroy_sampler(self.point_sampler);
+        factory.destroy_descriptor_pool(self.descriptor_pool);
+        factory.destroy_descriptor_set_layout(self.descriptor_set_layout);
+        factory.destroy_shader_module(self.vert_module);
+        factory.destroy_shader_module(self.downsample_frag_module);
+        factory.destroy_shader_module(self.composite_frag_module);
+        factory.destroy_pipeline_layout(self.pipeline_layout);
+        factory.destroy_pipeline(self.downsample_pipeline);
+        factory.destroy_pipeline(self.composite_pipeline);
+    }
+
+    pub fn get_half_resolution_layer(&self) -> &RenderLayer {
+        &self.half_layer
+    }
+
+    // Signals when the source color image contains composited effects
+    pub fn get_composite_layer(&self) -> &RenderLayer {
+        &self.composite_layer
+    }
+
+    // Source color and depth are expected to be in SHADER_READ_ONLY_OPTIMAL layout,
+    // effects are rendered in order on top of each other after the dependency layer
+    pub fn render(
+        &mut self,
+        effects: &mut [&mut dyn HalfResolutionEffect],
+        parameters: &HalfResolutionRenderParameters,
+        frame_context: &FrameContext,
+        device: &mut Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) {
+        puffin::profile_function!();
+
+        let screen_area = parameters.screen_area;
+        let half_area = vk::Rect2D {
+            offset: vk::Offset2D {
+                x: screen_area.offset.x / 2,
+                y: screen_area.offset.y / 2,
+            },
+            extent: vk::Extent2D {
+                width: screen_area.extent.width.div_ceil(2),
+                height: screen_area.extent.height.div_ceil(2),
+            },
+        };
+        let half_color_image = self.half_layer.get_render_image(0).0;
+        let half_depth_image = self.half_layer.get_depth_image().unwrap().0;
+
+        self.half_layer.add_dependency(
+            frame_context,
+            parameters.dependency_layer,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        );
+        self.half_layer.acquire_frame(frame_context, device, factory);
+        self.half_layer.begin_render_pass(frame_context, half_area);
+        {
+            let command_buffer = self.half_layer.get_command_buffer(frame_context);
+            command_buffer.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, self.downsample_pipeline);
+            command_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            command_buffer.draw(3, 1, 0, 0);
+
+            for effect in effects.iter_mut() {
+                effect.render(
+                    command_buffer,
+                    frame_context,
+                    parameters.frame_data_descriptor_set,
+                    parameters.frame_data_offset,
+                );
+            }
+        }
+        self.half_layer.end_render_pass(frame_context);
+
+        let command_buffer = self.half_layer.get_command_buffer(frame_context);
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::ALL_GRAPHICS,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[],
+            &[],
+            &[
+                vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                    .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .src_queue_family_index(!0)
+                    .dst_queue_family_index(!0)
+                    .image(half_color_image)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .base_mip_level(0)
+                            .level_count(1)
+                            .base_array_layer(0)
+                            .layer_count(1)
+                            .build(),
+                    )
+                    .build(),
+                vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                    .old_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .src_queue_family_index(!0)
+                    .dst_queue_family_index(!0)
+                    .image(half_depth_image)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::builder()
+                            .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                            .base_mip_level(0)
+                            .level_count(1)
+                            .base_array_layer(0)
+                            .layer_count(1)
+                            .build(),
+                    )
+                    .build(),
+            ],
+        );
+        self.half_layer.submit_commands(frame_context, queue);
+
+        let source_image = parameters
+            .source_layer
+            .get_render_image(parameters.source_color_image)
+            .0;
+        self.composite_layer
+            .add_dependency(frame_context, &self.half_layer, vk::PipelineStageFlags::FRAGMENT_SHADER);
+        self.composite_layer.acquire_frame(frame_context, device, factory);
+        self.composite_layer.begin_render_pass(frame_context, screen_area);
+        {
+            let command_buffer = self.composite_layer.get_command_buffer(frame_context);
+            command_buffer.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, self.composite_pipeline);
+            command_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            command_buffer.push_constants(
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &[
+                    half_area.offset.x,
+                    half_area.offset.y,
+                    half_area.offset.x + half_area.extent.width as i32 - 1,
+                    half_area.offset.y + half_area.extent.height as i32 - 1,
+                ],
+            );
+            command_buffer.draw(3, 1, 0, 0);
+        }
+        self.composite_layer.end_render_pass(frame_context);
+
+        let command_buffer = self.composite_layer.get_command_buffer(frame_context);
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[],
+            &[],
+            &[vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(source_image)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(1)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build()],
+        );
+        self.composite_layer.submit_commands(frame_context, queue);
+    }
+}
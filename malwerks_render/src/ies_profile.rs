@@ -0,0 +1,199 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+use malwerks_vk::*;
+
+// Has to match gltf_pbr_material.glsl, vertical angles go along X and horizontal angles along Y.
+// Both axes include their end angles, vertical from 0 to 180 and horizontal from 0 to 360 degrees.
+pub const IES_PROFILE_WIDTH: u32 = 64;
+pub const IES_PROFILE_HEIGHT: u32 = 16;
+
+#[derive(Debug)]
+pub enum IesError {
+    InvalidHeader,
+    InvalidData,
+}
+
+// Photometric web of an IESNA LM-63 file, only type C photometry is supported.
+// Vertical angle 0 points along the light direction, angles are in degrees.
+#[derive(Debug)]
+pub struct IesProfile {
+    vertical_angles: Vec<f32>,
+    horizontal_angles: Vec<f32>,
+    candela_values: Vec<f32>, // all vertical angles for every horizontal angle
+}
+
+impl IesProfile {
+    pub fn parse(text: &str) -> Result<Self, IesError> {
+        // Keywords come before the TILT line, lamp tilt data is skipped
+        let mut lines = text.lines();
+        let tilt = loop {
+            match lines.next() {
+                Some(line) if line.trim_start().starts_with("TILT=") => break line.trim()[5..].to_string(),
+                Some(_) => continue,
+                None => return Err(IesError::InvalidHeader),
+            }
+        };
+
+        let mut values = lines.flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','));
+        let mut next_value = || -> Result<f32, IesError> {
+            loop {
+                match values.next() {
+                    Some("") => continue,
+                    Some(value) => return value.parse::<f32>().map_err(|_| IesError::InvalidData),
+                    None => return Err(IesError::InvalidData),
+                }
+            }
+        };
+
+        if tilt == "INCLUDE" {
+            next_value()?; // lamp to luminaire geometry
+            let tilt_angle_count = next_value()? as usize;
+            for _ in 0..2 * tilt_angle_count {
+                next_value()?;
+            }
+        }
+
+        let _lamp_count = next_value()?;
+        let _lumens_per_lamp = next_value()?;
+        let candela_multiplier = next_value()?;
+        let vertical_angle_count = next_value()? as usize;
+        let horizontal_angle_count = next_value()? as usize;
+        let photometric_type = next_value()? as u32;
+        for _ in 0..4 {
+            next_value()?; // units type and luminous opening dimensions
+        }
+        let ballast_factor = next_value()?;
+        next_value()?; // future use
+        next_value()?; // input watts
+
+        if photometric_type != 1 || vertical_angle_count == 0 || horizontal_angle_count == 0 {
+            return Err(IesError::InvalidHeader);
+        }
+
+        let mut read_values = |count: usize, scale: f32| -> Result<Vec<f32>, IesError> {
+            (0..count).map(|_| next_value().map(|value| value * scale)).collect()
+        };
+        let vertical_angles = read_values(vertical_angle_count, 1.0)?;
+        let horizontal_angles = read_values(horizontal_angle_count, 1.0)?;
+        let candela_values = read_values(
+            vertical_angle_count * horizontal_angle_count,
+            candela_multiplier * ballast_factor,
+        )?;
+
+        let is_sorted = |angles: &[f32]| angles.windows(2).all(|pair| pair[0] < pair[1]);
+        if !is_sorted(&vertical_angles) || !is_sorted(&horizontal_angles) {
+            return Err(IesError::InvalidData);
+        }
+
+        Ok(Self {
+            vertical_angles,
+            horizontal_angles,
+            candela_values,
+        })
+    }
+
+    pub fn get_max_candela(&self) -> f32 {
+        self.candela_values.iter().cloned().fold(0.0, f32::max)
+    }
+
+    // Horizontal angles missing from the file are mirrored according to the symmetry of the profile
+    pub fn sample(&self, vertical_angle: f32, horizontal_angle: f32) -> f32 {
+        let last_horizontal_angle = *self.horizontal_angles.last().unwrap();
+        let horizontal_angle = horizontal_angle.rem_euclid(360.0);
+        let horizontal_angle = if last_horizontal_angle <= 0.0 {
+            0.0
+        } else if last_horizontal_angle <= 90.0 {
+            let angle = horizontal_angle % 180.0;
+            if angle > 90.0 {
+                180.0 - angle
+            } else {
+                angle
+            }
+        } else if last_horizontal_angle <= 180.0 && horizontal_angle > 180.0 {
+            360.0 - horizontal_angle
+        } else {
+            horizontal_angle
+        };
+
+        // Luminaires that only emit into one hemisphere don't list the other one
+        if vertical_angle < self.vertical_angles[0] || vertical_angle > *self.vertical_angles.last().unwrap() {
+            return 0.0;
+        }
+
+        let (horizontal_index, horizontal_weight) = find_interval(&self.horizontal_angles, horizontal_angle);
+        let (vertical_index, vertical_weight) = find_interval(&self.vertical_angles, vertical_angle);
+
+        let vertical_count = self.vertical_angles.len();
+        let fetch = |horizontal_index: usize, vertical_index: usize| {
+            let horizontal_index = horizontal_index.min(self.horizontal_angles.len() - 1);
+            let vertical_index = vertical_index.min(vertical_count - 1);
+            self.candela_values[horizontal_index * vertical_count + vertical_index]
+        };
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        lerp(
+            lerp(
+                fetch(horizontal_index, vertical_index),
+                fetch(horizontal_index, vertical_index + 1),
+                vertical_weight,
+            ),
+            lerp(
+                fetch(horizontal_index + 1, vertical_index),
+                fetch(horizontal_index + 1, vertical_index + 1),
+                vertical_weight,
+            ),
+            horizontal_weight,
+        )
+    }
+}
+
+// Every profile becomes a layer of a single channel image normalized to its peak intensity
+pub fn bake_ies_profiles(profiles: &[IesProfile]) -> DiskImage {
+    assert!(!profiles.is_empty(), "at least one IES profile is required");
+
+    let texel_size = std::mem::size_of::<f32>();
+    let mut pixels =
+        Vec::with_capacity(profiles.len() * (IES_PROFILE_WIDTH * IES_PROFILE_HEIGHT) as usize * texel_size);
+    for profile in profiles {
+        let normalization = 1.0 / profile.get_max_candela().max(1e-6);
+        for y in 0..IES_PROFILE_HEIGHT {
+            let horizontal_angle = 360.0 * y as f32 / (IES_PROFILE_HEIGHT - 1) as f32;
+            for x in 0..IES_PROFILE_WIDTH {
+                let vertical_angle = 180.0 * x as f32 / (IES_PROFILE_WIDTH - 1) as f32;
+                let intensity = profile.sample(vertical_angle, horizontal_angle) * normalization;
+                pixels.extend_from_slice(&intensity.to_le_bytes());
+            }
+        }
+    }
+
+    DiskImage {
+        width: IES_PROFILE_WIDTH,
+        height: IES_PROFILE_HEIGHT,
+        depth: 1,
+        block_size: texel_size,
+        mipmap_count: 1,
+        layer_count: profiles.len(),
+        image_type: vk::ImageType::TYPE_2D.as_raw(),
+        view_type: vk::ImageViewType::TYPE_2D_ARRAY.as_raw(),
+        format: vk::Format::R32_SFLOAT.as_raw(),
+        color_space: DiskColorSpace::Linear,
+        generate_mipmaps: false,
+        pixels,
+    }
+}
+
+// Index of the angle before the given one and the interpolation weight towards the next angle
+fn find_interval(angles: &[f32], angle: f32) -> (usize, f32) {
+    let next_index = angles.iter().position(|&next_angle| next_angle > angle);
+    match next_index {
+        None => (angles.len() - 1, 0.0),
+        Some(0) => (0, 0.0),
+        Some(next_index) => {
+            let index = next_index - 1;
+            (index, (angle - angles[index]) / (angles[next_index] - angles[index]))
+        }
+    }
+}
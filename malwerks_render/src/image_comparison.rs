@@ -0,0 +1,187 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+use malwerks_dds::*;
+
+// SSIM is computed on luma in non-overlapping windows of this size
+const SSIM_WINDOW_SIZE: u32 = 8;
+
+// Images with a PSNR or SSIM below the thresholds are considered different
+#[derive(Debug, Copy, Clone)]
+pub struct ImageTolerance {
+    pub min_psnr: f32, // in dB
+    pub min_ssim: f32, // from -1 to 1
+}
+
+impl Default for ImageTolerance {
+    fn default() -> Self {
+        Self {
+            min_psnr: 40.0,
+            min_ssim: 0.99,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ImageComparison {
+    pub psnr: f32, // infinite for identical images
+    pub ssim: f32,
+}
+
+impl ImageComparison {
+    pub fn is_within(&self, tolerance: &ImageTolerance) -> bool {
+        self.psnr >= tolerance.min_psnr && self.ssim >= tolerance.min_ssim
+    }
+}
+
+// Stable across runs and platforms, identical hashes mean bit-identical pixels
+pub fn hash_image_pixels(pixels: &[u8]) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write(pixels);
+    hasher.finish()
+}
+
+// Captured R11G11B10_FLOAT or R16G16B16A16_FLOAT color is clamped and encoded as sRGB,
+// golden images don't depend on the tone mapper
+pub fn convert_capture_to_rgb8(scratch_image: &ScratchImage) -> image::RgbImage {
+    let (width, height, _) = scratch_image.image_size();
+    let decode_pixel: Box<dyn Fn(usize) -> [f32; 3]> = match scratch_image.dxgi_format() {
+        DXGI_FORMAT_R11G11B10_FLOAT => {
+            let packed_pixels = scratch_image.as_typed_slice::<u32>();
+            Box::new(move |pixel| decode_r11g11b10(packed_pixels[pixel]))
+        }
+        DXGI_FORMAT_R16G16B16A16_FLOAT => {
+            let half_pixels = scratch_image.as_typed_slice::<[u16; 4]>();
+            Box::new(move |pixel| {
+                let [r, g, b, _] = half_pixels[pixel];
+                [decode_half(r), decode_half(g), decode_half(b)]
+            })
+        }
+        dxgi_format => panic!("unsupported capture format {}", dxgi_format),
+    };
+    image::RgbImage::from_fn(width, height, |x, y| {
+        let color = decode_pixel((y * width + x) as usize);
+        image::Rgb([encode_srgb(color[0]), encode_srgb(color[1]), encode_srgb(color[2])])
+    })
+}
+
+pub fn compare_images(test_image: &image::RgbImage, reference_image: &image::RgbImage) -> ImageComparison {
+    assert_eq!(
+        test_image.dimensions(),
+        reference_image.dimensions(),
+        "compared images have different sizes"
+    );
+    let (width, height) = test_image.dimensions();
+    ImageComparison {
+        psnr: calculate_psnr(test_image.as_raw(), reference_image.as_raw()),
+        ssim: calculate_ssim(test_image.as_raw(), reference_image.as_raw(), width, height),
+    }
+}
+
+pub(crate) fn calculate_psnr(test_pixels: &[u8], reference_pixels: &[u8]) -> f32 {
+    assert_eq!(test_pixels.len(), reference_pixels.len());
+    let squared_error: f64 = test_pixels
+        .iter()
+        .zip(reference_pixels)
+        .map(|(test, reference)| (*test as f64 - *reference as f64).powi(2))
+        .sum();
+    if squared_error == 0.0 {
+        return f32::INFINITY;
+    }
+    let mean_squared_error = squared_error / test_pixels.len() as f64;
+    (10.0 * (255.0 * 255.0 / mean_squared_error).log10()) as f32
+}
+
+// Pixels are RGB8, partial windows at the right and bottom edges are skipped
+pub(crate) fn calculate_ssim(test_pixels: &[u8], reference_pixels: &[u8], width: u32, height: u32) -> f32 {
+    assert_eq!(test_pixels.len(), (width * height * 3) as usize);
+    assert_eq!(reference_pixels.len(), test_pixels.len());
+
+    let luma = |pixels: &[u8], x: u32, y: u32| {
+        let offset = ((y * width + x) * 3) as usize;
+        0.299 * pixels[offset] as f64 + 0.587 * pixels[offset + 1] as f64 + 0.114 * pixels[offset + 2] as f64
+    };
+    let c1 = (0.01 * 255.0f64).powi(2);
+    let c2 = (0.03 * 255.0f64).powi(2);
+
+    let mut ssim_sum = 0.0;
+    let mut window_count = 0;
+    for window_y in 0..height / SSIM_WINDOW_SIZE {
+        for window_x in 0..width / SSIM_WINDOW_SIZE {
+            let samples: Vec<(f64, f64)> = (0..SSIM_WINDOW_SIZE * SSIM_WINDOW_SIZE)
+                .map(|i| {
+                    let x = window_x * SSIM_WINDOW_SIZE + i % SSIM_WINDOW_SIZE;
+                    let y = window_y * SSIM_WINDOW_SIZE + i / SSIM_WINDOW_SIZE;
+                    (luma(test_pixels, x, y), luma(reference_pixels, x, y))
+                })
+                .collect();
+
+            let sample_count = samples.len() as f64;
+            let mean_test = samples.iter().map(|(test, _)| test).sum::<f64>() / sample_count;
+            let mean_reference = samples.iter().map(|(_, reference)| reference).sum::<f64>() / sample_count;
+            let (mut variance_test, mut variance_reference, mut covariance) = (0.0, 0.0, 0.0);
+            for (test, reference) in &samples {
+                variance_test += (test - mean_test).powi(2);
+                variance_reference += (reference - mean_reference).powi(2);
+                covariance += (test - mean_test) * (reference - mean_reference);
+            }
+            variance_test /= sample_count - 1.0;
+            variance_reference /= sample_count - 1.0;
+            covariance /= sample_count - 1.0;
+
+            ssim_sum += ((2.0 * mean_test * mean_reference + c1) * (2.0 * covariance + c2))
+                / ((mean_test * mean_test + mean_reference * mean_reference + c1)
+                    * (variance_test + variance_reference + c2));
+            window_count += 1;
+        }
+    }
+
+    if window_count == 0 {
+        1.0
+    } else {
+        (ssim_sum / window_count as f64) as f32
+    }
+}
+
+// Red and green have 6 mantissa bits, blue has 5, all channels have a 5 bit exponent and no sign
+pub(crate) fn decode_r11g11b10(packed: u32) -> [f32; 3] {
+    let decode = |bits: u32, mantissa_bits: u32| {
+        let exponent = (bits >> mantissa_bits) as i32;
+        let mantissa = (bits & ((1 << mantissa_bits) - 1)) as f32 / (1 << mantissa_bits) as f32;
+        match exponent {
+            0 => mantissa * 2.0f32.powi(-14),
+            31 => f32::MAX,
+            _ => (1.0 + mantissa) * 2.0f32.powi(exponent - 15),
+        }
+    };
+    [
+        decode(packed & 0x7ff, 6),
+        decode((packed >> 11) & 0x7ff, 6),
+        decode(packed >> 22, 5),
+    ]
+}
+
+// NaNs and infinities are decoded as f32::MAX, they are clamped like any other out of range value
+pub(crate) fn decode_half(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32 / 1024.0;
+    sign * match exponent {
+        0 => mantissa * 2.0f32.powi(-14),
+        31 => f32::MAX,
+        _ => (1.0 + mantissa) * 2.0f32.powi(exponent - 15),
+    }
+}
+
+fn encode_srgb(linear: f32) -> u8 {
+    let linear = linear.clamp(0.0, 1.0);
+    let srgb = if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0 + 0.5) as u8
+}
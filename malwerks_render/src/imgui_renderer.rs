@@ -8,6 +8,10 @@ use malwerks_vk::*;
 
 use crate::common_shaders::*;
 
+// Font atlas is always the first texture, registered textures follow
+const FONT_TEXTURE_ID: usize = 0;
+const MAX_IMGUI_TEXTURES: u32 = 256;
+
 pub struct ImguiRenderer {
     font_image: HeapAllocatedResource<vk::Image>,
     font_view: vk::ImageView,
@@ -15,7 +19,8 @@ pub struct ImguiRenderer {
 
     descriptor_pool: vk::DescriptorPool,
     descriptor_set_layout: vk::DescriptorSetLayout,
-    descriptor_set: vk::DescriptorSet,
+    texture_descriptor_pool: vk::DescriptorPool,
+    texture_descriptor_sets: Vec<vk::DescriptorSet>, // null for free texture IDs
 
     vert_module: vk::ShaderModule,
     frag_module: vk::ShaderModule,
@@ -32,6 +37,7 @@ impl ImguiRenderer {
         factory.destroy_image_view(self.font_view);
         factory.destroy_sampler(self.font_sampler);
         factory.destroy_descriptor_pool(self.descriptor_pool);
+        factory.destroy_descriptor_pool(self.texture_descriptor_pool);
         factory.destroy_descriptor_set_layout(self.descriptor_set_layout);
         factory.destroy_shader_module(self.vert_module);
         factory.destroy_shader_module(self.frag_module);
@@ -71,6 +77,7 @@ impl ImguiRenderer {
             .stage(vk::ShaderStageFlags::FRAGMENT);
 
         let font_image = Self::create_font_texture(imgui, factory, command_buffer, queue);
+        imgui.fonts().tex_id = imgui::TextureId::from(FONT_TEXTURE_ID);
         let font_sampler = factory.create_sampler(
             &vk::SamplerCreateInfo::builder()
                 .mag_filter(vk::Filter::LINEAR)
@@ -151,6 +158,21 @@ impl ImguiRenderer {
             ],
             &[],
         );
+        let texture_descriptor_pool = factory.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder()
+                .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+                .max_sets(MAX_IMGUI_TEXTURES)
+                .pool_sizes(&[
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::SAMPLER)
+                        .descriptor_count(MAX_IMGUI_TEXTURES)
+                        .build(),
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::SAMPLED_IMAGE)
+                        .descriptor_count(MAX_IMGUI_TEXTURES)
+                        .build(),
+                ]),
+        );
 
         let pipeline_layout = factory.create_pipeline_layout(
             &vk::PipelineLayoutCreateInfo::builder()
@@ -164,96 +186,97 @@ impl ImguiRenderer {
         );
         let pipeline = factory.create_graphics_pipelines(
             vk::PipelineCache::null(),
-            &[vk::GraphicsPipelineCreateInfo::builder()
-                .stages(&[imgui_vert.build(), imgui_frag.build()])
-                .vertex_input_state(
-                    &vk::PipelineVertexInputStateCreateInfo::builder()
-                        .vertex_binding_descriptions(&[vk::VertexInputBindingDescription::builder()
-                            .binding(0)
-                            .stride(std::mem::size_of::<imgui::DrawVert>() as _)
-                            .input_rate(vk::VertexInputRate::VERTEX)
-                            .build()])
-                        .vertex_attribute_descriptions(&[
-                            vk::VertexInputAttributeDescription::builder()
-                                .location(0)
-                                .binding(0)
-                                .format(vk::Format::R32G32_SFLOAT)
-                                .offset(0)
-                                .build(),
-                            vk::VertexInputAttributeDescription::builder()
-                                .location(1)
-                                .binding(0)
-                                .format(vk::Format::R32G32_SFLOAT)
-                                .offset(8)
-                                .build(),
-                            vk::VertexInputAttributeDescription::builder()
-                                .location(2)
+            &[target_layer.make_pipeline_create_info(
+                vk::GraphicsPipelineCreateInfo::builder()
+                    .stages(&[imgui_vert.build(), imgui_frag.build()])
+                    .vertex_input_state(
+                        &vk::PipelineVertexInputStateCreateInfo::builder()
+                            .vertex_binding_descriptions(&[vk::VertexInputBindingDescription::builder()
                                 .binding(0)
-                                .format(vk::Format::R32_UINT)
-                                .offset(16)
+                                .stride(std::mem::size_of::<imgui::DrawVert>() as _)
+                                .input_rate(vk::VertexInputRate::VERTEX)
+                                .build()])
+                            .vertex_attribute_descriptions(&[
+                                vk::VertexInputAttributeDescription::builder()
+                                    .location(0)
+                                    .binding(0)
+                                    .format(vk::Format::R32G32_SFLOAT)
+                                    .offset(0)
+                                    .build(),
+                                vk::VertexInputAttributeDescription::builder()
+                                    .location(1)
+                                    .binding(0)
+                                    .format(vk::Format::R32G32_SFLOAT)
+                                    .offset(8)
+                                    .build(),
+                                vk::VertexInputAttributeDescription::builder()
+                                    .location(2)
+                                    .binding(0)
+                                    .format(vk::Format::R32_UINT)
+                                    .offset(16)
+                                    .build(),
+                            ]),
+                    )
+                    .input_assembly_state(
+                        &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                            .primitive_restart_enable(false)
+                            .build(),
+                    )
+                    .tessellation_state(&Default::default())
+                    .viewport_state(
+                        &vk::PipelineViewportStateCreateInfo::builder()
+                            .viewport_count(1)
+                            .scissor_count(1)
+                            .build(),
+                    )
+                    .rasterization_state(
+                        &vk::PipelineRasterizationStateCreateInfo::builder()
+                            .line_width(1.0)
+                            .build(),
+                    )
+                    .multisample_state(
+                        &vk::PipelineMultisampleStateCreateInfo::builder()
+                            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                            .build(),
+                    )
+                    .depth_stencil_state(
+                        &vk::PipelineDepthStencilStateCreateInfo::builder()
+                            .depth_test_enable(false)
+                            .depth_write_enable(false)
+                            .stencil_test_enable(false)
+                            .build(),
+                    )
+                    .color_blend_state(
+                        &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                            vk::PipelineColorBlendAttachmentState::builder()
+                                .blend_enable(true)
+                                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                                .color_blend_op(vk::BlendOp::ADD)
+                                .src_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                                .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+                                .alpha_blend_op(vk::BlendOp::ADD)
+                                .color_write_mask(
+                                    vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B
+                                        | vk::ColorComponentFlags::A,
+                                )
                                 .build(),
                         ]),
-                )
-                .input_assembly_state(
-                    &vk::PipelineInputAssemblyStateCreateInfo::builder()
-                        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-                        .primitive_restart_enable(false)
-                        .build(),
-                )
-                .tessellation_state(&Default::default())
-                .viewport_state(
-                    &vk::PipelineViewportStateCreateInfo::builder()
-                        .viewport_count(1)
-                        .scissor_count(1)
-                        .build(),
-                )
-                .rasterization_state(
-                    &vk::PipelineRasterizationStateCreateInfo::builder()
-                        .line_width(1.0)
-                        .build(),
-                )
-                .multisample_state(
-                    &vk::PipelineMultisampleStateCreateInfo::builder()
-                        .rasterization_samples(vk::SampleCountFlags::TYPE_1)
-                        .build(),
-                )
-                .depth_stencil_state(
-                    &vk::PipelineDepthStencilStateCreateInfo::builder()
-                        .depth_test_enable(false)
-                        .depth_write_enable(false)
-                        .stencil_test_enable(false)
-                        .build(),
-                )
-                .color_blend_state(
-                    &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
-                        vk::PipelineColorBlendAttachmentState::builder()
-                            .blend_enable(true)
-                            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-                            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                            .color_blend_op(vk::BlendOp::ADD)
-                            .src_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-                            .alpha_blend_op(vk::BlendOp::ADD)
-                            .color_write_mask(
-                                vk::ColorComponentFlags::R
-                                    | vk::ColorComponentFlags::G
-                                    | vk::ColorComponentFlags::B
-                                    | vk::ColorComponentFlags::A,
-                            )
+                    )
+                    .dynamic_state(
+                        &vk::PipelineDynamicStateCreateInfo::builder()
+                            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
                             .build(),
-                    ]),
-                )
-                .dynamic_state(
-                    &vk::PipelineDynamicStateCreateInfo::builder()
-                        .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
-                        .build(),
-                )
-                .layout(pipeline_layout)
-                .render_pass(target_layer.get_render_pass())
-                .subpass(0)
-                .base_pipeline_handle(vk::Pipeline::null())
-                .base_pipeline_index(0)
-                .build()],
+                    )
+                    .layout(pipeline_layout)
+                    .subpass(0)
+                    .base_pipeline_handle(vk::Pipeline::null())
+                    .base_pipeline_index(0)
+                    .build(),
+            )],
         )[0];
 
         Self {
@@ -263,7 +286,8 @@ impl ImguiRenderer {
 
             descriptor_pool,
             descriptor_set_layout,
-            descriptor_set,
+            texture_descriptor_pool,
+            texture_descriptor_sets: vec![descriptor_set],
 
             vert_module,
             frag_module,
@@ -271,10 +295,67 @@ impl ImguiRenderer {
             pipeline_layout,
             pipeline,
 
-            buffer_set: BufferSet::new(),
+            buffer_set: BufferSet::new(factory.get_num_buffered_frames()),
         }
     }
 
+    // Image is sampled with the font sampler and has to stay in SHADER_READ_ONLY_OPTIMAL while it is registered
+    pub fn register_texture(&mut self, image_view: vk::ImageView, factory: &mut DeviceFactory) -> imgui::TextureId {
+        let descriptor_set = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.texture_descriptor_pool)
+                .set_layouts(&[self.descriptor_set_layout])
+                .build(),
+        )[0];
+        factory.update_descriptor_sets(
+            &[
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::builder().sampler(self.font_sampler).build()])
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .image_view(image_view)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .build()])
+                    .build(),
+            ],
+            &[],
+        );
+
+        let texture_id = match self
+            .texture_descriptor_sets
+            .iter()
+            .position(|texture_descriptor_set| *texture_descriptor_set == vk::DescriptorSet::null())
+        {
+            Some(texture_id) => {
+                self.texture_descriptor_sets[texture_id] = descriptor_set;
+                texture_id
+            }
+            None => {
+                self.texture_descriptor_sets.push(descriptor_set);
+                self.texture_descriptor_sets.len() - 1
+            }
+        };
+        imgui::TextureId::from(texture_id)
+    }
+
+    // Texture must not be referenced by frames that are still in flight
+    pub fn unregister_texture(&mut self, texture_id: imgui::TextureId, factory: &mut DeviceFactory) {
+        assert_ne!(texture_id.id(), FONT_TEXTURE_ID, "font texture can't be unregistered");
+        let descriptor_set = std::mem::replace(
+            &mut self.texture_descriptor_sets[texture_id.id()],
+            vk::DescriptorSet::null(),
+        );
+        assert_ne!(descriptor_set, vk::DescriptorSet::null(), "texture is not registered");
+        factory.free_descriptor_sets(self.texture_descriptor_pool, &[descriptor_set]);
+    }
+
     pub fn draw(
         &mut self,
         frame_context: &FrameContext,
@@ -300,13 +381,6 @@ impl ImguiRenderer {
 
         command_buffer.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, self.pipeline);
         command_buffer.push_constants(self.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, &matrix);
-        command_buffer.bind_descriptor_sets(
-            vk::PipelineBindPoint::GRAPHICS,
-            self.pipeline_layout,
-            0,
-            &[self.descriptor_set],
-            &[],
-        );
         command_buffer.set_viewport(
             0,
             &[vk::Viewport {
@@ -320,6 +394,7 @@ impl ImguiRenderer {
         );
 
         self.buffer_set.acquire_frame(frame_context, factory);
+        let mut bound_texture_id = None;
         for draw_list in draw_data.draw_lists() {
             puffin::profile_scope!("imgui_draw_list");
 
@@ -348,12 +423,17 @@ impl ImguiRenderer {
                             (cmd_params.clip_rect[3] - clip_offset[1]) * clip_scale[1],
                         ];
 
-                        //let texture_id = cmd_params.texture_id.into();
-                        //let tex = self
-                        //    .textures
-                        //    .get(texture_id)
-                        //    .ok_or_else(|| RendererError::BadTexture(texture_id))?;
-                        //render_pass.set_bind_group(1, &tex.bind_group, &[]);
+                        let texture_id = cmd_params.texture_id.id();
+                        if bound_texture_id != Some(texture_id) {
+                            command_buffer.bind_descriptor_sets(
+                                vk::PipelineBindPoint::GRAPHICS,
+                                self.pipeline_layout,
+                                0,
+                                &[self.texture_descriptor_sets[texture_id]],
+                                &[],
+                            );
+                            bound_texture_id = Some(texture_id);
+                        }
 
                         let scissors = vk::Rect2D {
                             offset: vk::Offset2D {
@@ -434,10 +514,10 @@ struct BufferSet {
 }
 
 impl BufferSet {
-    pub fn new() -> Self {
+    pub fn new(num_buffered_frames: usize) -> Self {
         Self {
-            vertex_buffers: FrameLocal::new(|_| Vec::new()),
-            index_buffers: FrameLocal::new(|_| Vec::new()),
+            vertex_buffers: FrameLocal::new(num_buffered_frames, |_| Vec::new()),
+            index_buffers: FrameLocal::new(num_buffered_frames, |_| Vec::new()),
         }
     }
 
@@ -479,16 +559,12 @@ impl BufferSet {
     ) -> vk::Buffer {
         puffin::profile_function!();
 
-        let buffer = factory.allocate_buffer(
+        let buffer = factory.allocate_dynamic_buffer(
             &vk::BufferCreateInfo::builder()
                 .size((data.len() * std::mem::size_of::<T>()) as _)
                 .usage(usage)
                 .build(),
-            &vk_mem::AllocationCreateInfo {
-                usage: vk_mem::MemoryUsage::CpuToGpu,
-                required_flags: vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
-                ..Default::default()
-            },
+            vk_mem::AllocationCreateFlags::NONE,
         );
 
         let memory = factory.map_allocation_memory(&buffer);
@@ -0,0 +1,278 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+
+const MAX_INSTANCE_TRANSFORM_UPDATES: usize = 16384;
+const MAX_INSTANCE_TRANSFORM_BATCHES: usize = 256;
+const UPDATE_GROUP_SIZE: usize = 64;
+
+pub struct InstanceTransformUpdate {
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_update_template: vk::DescriptorUpdateTemplate,
+    descriptor_pool: FrameLocal<vk::DescriptorPool>,
+    update_buffer: FrameLocal<HeapAllocatedResource<vk::Buffer>>,
+
+    compute_module: vk::ShaderModule,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+
+    pending_updates: std::collections::HashMap<(vk::Buffer, u32), [f32; 16]>,
+}
+
+impl InstanceTransformUpdate {
+    pub fn new(common_shaders: &DiskCommonShaders, factory: &mut DeviceFactory) -> Self {
+        let num_buffered_frames = factory.get_num_buffered_frames();
+        let update_buffer = FrameLocal::new(num_buffered_frames, |_| {
+            factory.allocate_dynamic_buffer(
+                &vk::BufferCreateInfo::builder()
+                    .size((MAX_INSTANCE_TRANSFORM_UPDATES * std::mem::size_of::<InstanceTransformUpdateData>()) as _)
+                    .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+                    .build(),
+                vk_mem::AllocationCreateFlags::NONE,
+            )
+        });
+
+        let descriptor_pool = FrameLocal::new(num_buffered_frames, |_| {
+            factory.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::builder()
+                    .max_sets(MAX_INSTANCE_TRANSFORM_BATCHES as _)
+                    .pool_sizes(&[vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(2 * MAX_INSTANCE_TRANSFORM_BATCHES as u32)
+                        .build()])
+                    .build(),
+            )
+        });
+        let descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&[
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                ])
+                .build(),
+        );
+
+        // Sets are written for every batch each frame, update and target buffer infos follow each other
+        let descriptor_update_template = factory.create_descriptor_update_template(
+            &vk::DescriptorUpdateTemplateCreateInfo::builder()
+                .descriptor_update_entries(&[vk::DescriptorUpdateTemplateEntry::builder()
+                    .dst_binding(0)
+                    .dst_array_element(0)
+                    .descriptor_count(2)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .offset(0)
+                    .stride(std::mem::size_of::<vk::DescriptorBufferInfo>())
+                    .build()])
+                .template_type(vk::DescriptorUpdateTemplateType::DESCRIPTOR_SET)
+                .descriptor_set_layout(descriptor_set_layout)
+                .build(),
+        );
+
+        let compute_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.instance_transform_update_compute_stage)
+                .build(),
+        );
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[descriptor_set_layout])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .offset(0)
+                    .size(std::mem::size_of::<[u32; 2]>() as _)
+                    .build()])
+                .build(),
+        );
+
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let pipeline = factory.create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::ComputePipelineCreateInfo::builder()
+                .stage(
+                    vk::PipelineShaderStageCreateInfo::builder()
+                        .name(&entry_name)
+                        .module(compute_module)
+                        .stage(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                )
+                .layout(pipeline_layout)
+                .build()],
+        )[0];
+
+        Self {
+            descriptor_set_layout,
+            descriptor_update_template,
+            descriptor_pool,
+            update_buffer,
+            compute_module,
+            pipeline_layout,
+            pipeline,
+            pending_updates: Default::default(),
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        self.descriptor_pool
+            .destroy(|descriptor_pool| factory.destroy_descriptor_pool(*descriptor_pool));
+        self.update_buffer.destroy(|buffer| factory.deallocate_buffer(buffer));
+        factory.destroy_descriptor_set_layout(self.descriptor_set_layout);
+        factory.destroy_descriptor_update_template(self.descriptor_update_template);
+        factory.destroy_shader_module(self.compute_module);
+        factory.destroy_pipeline_layout(self.pipeline_layout);
+        factory.destroy_pipeline(self.pipeline);
+    }
+
+    // Instance index is relative to the start of the target transform buffer,
+    // updating the same instance twice in a frame keeps the latest transform
+    pub fn queue_update(&mut self, target_buffer: vk::Buffer, instance_index: u32, transform: &[f32; 16]) {
+        let key = (target_buffer, instance_index);
+        if self.pending_updates.len() < MAX_INSTANCE_TRANSFORM_UPDATES || self.pending_updates.contains_key(&key) {
+            self.pending_updates.insert(key, *transform);
+        } else {
+            log::warn!("instance transform update queue is full, dropping update");
+        }
+    }
+
+    pub fn dispatch(
+        &mut self,
+        command_buffer: &mut CommandBuffer,
+        frame_context: &FrameContext,
+        factory: &mut DeviceFactory,
+    ) {
+        if self.pending_updates.is_empty() {
+            return;
+        }
+        puffin::profile_function!();
+
+        // Group updates by target buffer so that each buffer gets a single dispatch
+        let mut sorted_updates: Vec<(vk::Buffer, u32, [f32; 16])> = self
+            .pending_updates
+            .drain()
+            .map(|((target_buffer, instance_index), transform)| (target_buffer, instance_index, transform))
+            .collect();
+        sorted_updates.sort_by_key(|(target_buffer, instance_index, _)| (*target_buffer, *instance_index));
+        let update_data: Vec<InstanceTransformUpdateData> = sorted_updates
+            .iter()
+            .map(|(_, instance_index, transform)| InstanceTransformUpdateData {
+                transform: *transform,
+                instance_index: [*instance_index, 0, 0, 0],
+            })
+            .collect();
+
+        let update_buffer = self.update_buffer.get(frame_context);
+        let update_memory = factory.map_allocation_memory(update_buffer);
+        copy_to_mapped_memory(&update_data, update_memory);
+        factory.unmap_allocation_memory(update_buffer);
+
+        let mut batches = Vec::new();
+        let mut batch_start = 0;
+        while batch_start < sorted_updates.len() {
+            let target_buffer = sorted_updates[batch_start].0;
+            let mut batch_end = batch_start + 1;
+            while batch_end < sorted_updates.len() && sorted_updates[batch_end].0 == target_buffer {
+                batch_end += 1;
+            }
+            batches.push((target_buffer, batch_start, batch_end - batch_start));
+            batch_start = batch_end;
+        }
+        assert!(
+            batches.len() <= MAX_INSTANCE_TRANSFORM_BATCHES,
+            "too many instance transform buffers updated in a single frame"
+        );
+
+        let descriptor_pool = *self.descriptor_pool.get(frame_context);
+        factory.reset_descriptor_pool(descriptor_pool);
+        let temp_set_layouts = vec![self.descriptor_set_layout; batches.len()];
+        let descriptor_sets = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&temp_set_layouts)
+                .build(),
+        );
+
+        for ((target_buffer, _, _), descriptor_set) in batches.iter().zip(&descriptor_sets) {
+            let buffer_infos = [
+                vk::DescriptorBufferInfo::builder()
+                    .buffer(update_buffer.0)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build(),
+                vk::DescriptorBufferInfo::builder()
+                    .buffer(*target_buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build(),
+            ];
+            unsafe {
+                factory.update_descriptor_set_with_template(
+                    *descriptor_set,
+                    self.descriptor_update_template,
+                    &buffer_infos,
+                );
+            }
+        }
+
+        // Previous frames may still be reading the transforms in vertex shaders
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::VERTEX_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            None,
+            &[],
+            &[],
+            &[],
+        );
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, self.pipeline);
+        for (batch_id, (_, batch_offset, batch_size)) in batches.iter().enumerate() {
+            command_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[descriptor_sets[batch_id]],
+                &[],
+            );
+            command_buffer.push_constants(
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &[*batch_offset as u32, *batch_size as u32],
+            );
+            command_buffer.dispatch(batch_size.div_ceil(UPDATE_GROUP_SIZE) as _, 1, 1);
+        }
+
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::VERTEX_SHADER,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build()],
+            &[],
+            &[],
+        );
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct InstanceTransformUpdateData {
+    transform: [f32; 16],
+    instance_index: [u32; 4],
+}
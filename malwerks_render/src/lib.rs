@@ -3,23 +3,87 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
 // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+mod asset_cache;
 mod bundle_loader;
 mod camera;
+mod command_registry;
+mod frame_graph_dump;
+mod half_resolution_effect;
+mod image_comparison;
 mod imgui_renderer;
+mod material_preview;
 mod pbr_forward_lit;
+mod redraw_tracker;
+mod render_target_capture;
+mod tiled_capture;
+mod upscaler;
 
 mod anti_aliasing;
+mod blue_noise;
+mod brdf_lut;
 mod common_shaders;
+mod depth_view;
+mod environment_convolution;
+mod equirectangular_environment;
+mod half_resolution_pass;
+mod ies_profile;
+mod instance_transform_update;
+mod light_clustering;
 mod material_shaders;
+mod nan_detection;
+mod order_independent_transparency;
+mod overdraw_heatmap;
+mod path_tracer;
 mod pbr_resource_bundle;
+mod screen_space_reflections;
+mod shader_clock_heatmap;
 mod shared_frame_data;
 mod sky_box;
+mod stereo_view;
 mod tone_map;
+mod volumetric_fog;
+mod water_surface;
 
+pub use asset_cache::*;
 pub use bundle_loader::*;
 pub use camera::*;
+pub use command_registry::*;
+pub use depth_view::{DepthViewParameters, DepthViewSource};
+pub use frame_graph_dump::*;
+pub use half_resolution_effect::*;
+pub use image_comparison::*;
 pub use imgui_renderer::*;
+pub use light_clustering::{PunctualLight, PunctualLightType, MAX_PUNCTUAL_LIGHTS};
+pub use material_preview::*;
+pub use nan_detection::{NanDetectionParameters, NanDetectionResult};
+pub use order_independent_transparency::TransparencyMode;
+pub use path_tracer::DeformedVertexBuffer;
 pub use pbr_forward_lit::*;
+pub use redraw_tracker::*;
+pub use render_target_capture::*;
+pub use screen_space_reflections::ScreenSpaceReflectionParameters;
+pub use shader_clock_heatmap::ShaderClockHeatmapParameters;
+pub use tiled_capture::*;
+pub use tone_map::DitheringParameters;
+pub use upscaler::*;
+pub use volumetric_fog::VolumetricFogParameters;
+pub use water_surface::WaterSurfaceParameters;
 
+#[cfg(test)]
+mod test_asset_cache;
+#[cfg(test)]
+mod test_blue_noise;
+#[cfg(test)]
+mod test_command_registry;
+#[cfg(test)]
+mod test_frame_graph_dump;
+#[cfg(test)]
+mod test_ies_profile;
+#[cfg(test)]
+mod test_image_comparison;
+#[cfg(test)]
+mod test_nan_detection;
 #[cfg(test)]
 mod test_pbr_forward_lit;
+#[cfg(test)]
+mod test_redraw_tracker;
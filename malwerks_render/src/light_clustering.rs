@@ -0,0 +1,209 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+use crate::shared_frame_data::*;
+
+// Has to match light_clustering.glsl and gltf_pbr_material.glsl
+pub const CLUSTER_GRID_SIZE: [u32; 3] = [16, 9, 24];
+pub const MAX_LIGHTS_PER_CLUSTER: usize = 32;
+pub const MAX_PUNCTUAL_LIGHTS: usize = 1024;
+const CLUSTER_GROUP_SIZE: u32 = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum PunctualLightType {
+    Point {
+        ies_profile: Option<u32>, // layer of the baked IES profiles, oriented along the light direction
+    },
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+        ies_profile: Option<u32>,
+    }, // radians from the spot direction
+    RectArea {
+        width: f32,
+        height: f32,
+        right: [f32; 3], // width axis, the light emits along its direction
+        two_sided: bool,
+        texture: Option<u32>, // index into area light textures of the PBR resource bundle
+    },
+}
+
+// Lights only affect surfaces within their range and closer than the last depth slice of the cluster grid
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PunctualLight {
+    pub light_type: PunctualLightType,
+    pub position: [f32; 3],
+    pub direction: [f32; 3], // unused by point lights without IES profiles
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: f32,
+}
+
+impl PunctualLight {
+    pub(crate) fn to_light_data(self) -> PunctualLightData {
+        assert!(self.range > 0.0, "punctual light range has to be positive");
+
+        let direction = ultraviolet::vec::Vec3::from(self.direction);
+        let direction = if direction.mag_sq() > 0.0 {
+            direction.normalized()
+        } else {
+            direction
+        };
+        let texture_index = |index: Option<u32>| match index {
+            Some(index) => index as f32,
+            None => -1.0,
+        };
+
+        // Same angular attenuation as KHR_lights_punctual
+        let mut light_data = PunctualLightData::default();
+        let (light_type, parameters) = match self.light_type {
+            PunctualLightType::Point { ies_profile } => (0.0, [0.0, 1.0, texture_index(ies_profile), 0.0]),
+            PunctualLightType::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+                ies_profile,
+            } => {
+                let spot_scale = 1.0 / (inner_cone_angle.cos() - outer_cone_angle.cos()).max(1e-4);
+                let spot_offset = -outer_cone_angle.cos() * spot_scale;
+                (1.0, [spot_scale, spot_offset, texture_index(ies_profile), 0.0])
+            }
+            PunctualLightType::RectArea {
+                width,
+                height,
+                right,
+                two_sided,
+                texture,
+            } => {
+                let right = ultraviolet::vec::Vec3::from(right);
+                let right = (right - direction * right.dot(direction)).normalized();
+                let up = direction.cross(right);
+                let half_width = right * width * 0.5;
+                let half_height = up * height * 0.5;
+                light_data.area_half_width_axis = [half_width.x, half_width.y, half_width.z, 0.0];
+                light_data.area_half_height_axis = [half_height.x, half_height.y, half_height.z, 0.0];
+
+                let light_type = if two_sided { 3.0 } else { 2.0 };
+                (light_type, [0.0, 1.0, texture_index(texture), 0.0])
+            }
+        };
+        light_data.position_range = [self.position[0], self.position[1], self.position[2], self.range];
+        light_data.color_intensity = [self.color[0], self.color[1], self.color[2], self.intensity];
+        light_data.direction_type = [direction.x, direction.y, direction.z, light_type];
+        light_data.parameters = parameters;
+        light_data
+    }
+}
+
+// Matches PunctualLight struct in light_clustering.glsl
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+pub(crate) struct PunctualLightData {
+    position_range: [f32; 4],
+    color_intensity: [f32; 4],
+    direction_type: [f32; 4],
+    parameters: [f32; 4],
+    area_half_width_axis: [f32; 4],
+    area_half_height_axis: [f32; 4],
+}
+
+// Light counts of all clusters followed by fixed size light index lists
+pub(crate) fn get_cluster_light_buffer_size() -> usize {
+    let cluster_count = (CLUSTER_GRID_SIZE[0] * CLUSTER_GRID_SIZE[1] * CLUSTER_GRID_SIZE[2]) as usize;
+    cluster_count * (1 + MAX_LIGHTS_PER_CLUSTER) * std::mem::size_of::<u32>()
+}
+
+pub struct LightClustering {
+    compute_module: vk::ShaderModule,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl LightClustering {
+    pub fn new(
+        common_shaders: &DiskCommonShaders,
+        shared_frame_data: &SharedFrameData,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        let compute_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.light_clustering_compute_stage)
+                .build(),
+        );
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[shared_frame_data.descriptor_set_layout])
+                .build(),
+        );
+
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let pipeline = factory.create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::ComputePipelineCreateInfo::builder()
+                .stage(
+                    vk::PipelineShaderStageCreateInfo::builder()
+                        .name(&entry_name)
+                        .module(compute_module)
+                        .stage(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                )
+                .layout(pipeline_layout)
+                .build()],
+        )[0];
+
+        Self {
+            compute_module,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        factory.destroy_shader_module(self.compute_module);
+        factory.destroy_pipeline_layout(self.pipeline_layout);
+        factory.destroy_pipeline(self.pipeline);
+    }
+
+    // Cluster light lists are per buffered frame, previous frames can't be reading them anymore
+    pub fn dispatch(
+        &mut self,
+        command_buffer: &mut CommandBuffer,
+        frame_context: &FrameContext,
+        shared_frame_data: &SharedFrameData,
+    ) {
+        if shared_frame_data.get_punctual_lights().is_empty() {
+            return;
+        }
+        puffin::profile_function!();
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, self.pipeline);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+            0,
+            &[*shared_frame_data.get_frame_data_descriptor_set(frame_context)],
+            &[shared_frame_data.get_frame_data_offset(frame_context)],
+        );
+        command_buffer.dispatch(
+            CLUSTER_GRID_SIZE[0].div_ceil(CLUSTER_GROUP_SIZE),
+            CLUSTER_GRID_SIZE[1].div_ceil(CLUSTER_GROUP_SIZE),
+            CLUSTER_GRID_SIZE[2].div_ceil(CLUSTER_GROUP_SIZE),
+        );
+
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build()],
+            &[],
+            &[],
+        );
+    }
+}
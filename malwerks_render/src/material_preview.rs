@@ -0,0 +1,853 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+use crate::path_tracer::get_material_parameter;
+use crate::pbr_resource_bundle::*;
+
+pub const MATERIAL_THUMBNAIL_SIZE: u32 = 128;
+const MATERIAL_PREVIEW_GROUP_SIZE: u32 = 8;
+const THUMBNAIL_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM; // sRGB encoded by the shader
+
+// Texture slots of gltf_pbr_material.json, in the order of MaterialTextures in material_preview.glsl
+const MATERIAL_TEXTURE_SLOTS: [&str; 5] = [
+    "BaseColorTexture",
+    "MetallicRoughnessTexture",
+    "NormalTexture",
+    "OcclusionTexture",
+    "EmissiveTexture",
+];
+
+// Thumbnail images stay in SHADER_READ_ONLY_OPTIMAL and can be registered as ImGui textures
+pub struct MaterialThumbnail {
+    pub material_instance_id: usize,
+    pub material_id: usize,
+    pub image: HeapAllocatedResource<vk::Image>,
+    pub image_view: vk::ImageView,
+}
+
+// Renders every material instance of a bundle onto a sphere under the environment probe. Thumbnails are
+// rendered at once on request and cached per bundle until they are removed.
+pub struct MaterialPreview {
+    command_pool: vk::CommandPool,
+    command_buffer: CommandBuffer,
+    fence: vk::Fence,
+    fallback_image: HeapAllocatedResource<vk::Image>, // bound to texture slots the material doesn't use
+    fallback_image_view: vk::ImageView,
+    fallback_sampler: vk::Sampler,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    compute_module: vk::ShaderModule,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    thumbnails: Vec<(String, Vec<MaterialThumbnail>)>, // bundle name, thumbnails
+}
+
+impl MaterialPreview {
+    pub fn new(
+        common_shaders: &DiskCommonShaders,
+        device: &Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) -> Self {
+        let command_pool = factory.create_command_pool(
+            &vk::CommandPoolCreateInfo::builder()
+                .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                .queue_family_index(device.get_graphics_queue_index())
+                .build(),
+        );
+        let command_buffer = factory.allocate_command_buffers(
+            &vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(1)
+                .command_pool(command_pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .build(),
+        )[0];
+        let fence = factory.create_fence(&vk::FenceCreateInfo::default());
+
+        let fallback_image = allocate_thumbnail_image(1, factory);
+        let fallback_image_view = create_thumbnail_image_view(fallback_image.0, factory);
+        let fallback_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build(),
+        );
+
+        let descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&[
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(1)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(2)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(3)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(MATERIAL_TEXTURE_SLOTS.len() as _)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(4)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                ])
+                .build(),
+        );
+
+        let compute_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.material_preview_compute_stage)
+                .build(),
+        );
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[descriptor_set_layout])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .offset(0)
+                    .size(std::mem::size_of::<MaterialPreviewConstants>() as _)
+                    .build()])
+                .build(),
+        );
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let pipeline = factory.create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::ComputePipelineCreateInfo::builder()
+                .stage(
+                    vk::PipelineShaderStageCreateInfo::builder()
+                        .name(&entry_name)
+                        .module(compute_module)
+                        .stage(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                )
+                .layout(pipeline_layout)
+                .build()],
+        )[0];
+
+        let mut material_preview = Self {
+            command_pool,
+            command_buffer,
+            fence,
+            fallback_image,
+            fallback_image_view,
+            fallback_sampler,
+            descriptor_set_layout,
+            compute_module,
+            pipeline_layout,
+            pipeline,
+            thumbnails: Vec::new(),
+        };
+        material_preview.clear_fallback_image(device, queue);
+        material_preview
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        for (_, thumbnails) in self.thumbnails.drain(..) {
+            destroy_thumbnails(&thumbnails, factory);
+        }
+        factory.free_command_buffers(self.command_pool, &[self.command_buffer]);
+        factory.destroy_command_pool(self.command_pool);
+        factory.destroy_fence(self.fence);
+        factory.destroy_image_view(self.fallback_image_view);
+        factory.deallocate_image(&self.fallback_image);
+        factory.destroy_sampler(self.fallback_sampler);
+        factory.destroy_pipeline(self.pipeline);
+        factory.destroy_pipeline_layout(self.pipeline_layout);
+        factory.destroy_shader_module(self.compute_module);
+        factory.destroy_descriptor_set_layout(self.descriptor_set_layout);
+    }
+
+    pub fn get_thumbnails(&self, bundle_name: &str) -> Option<&[MaterialThumbnail]> {
+        self.thumbnails
+            .iter()
+            .find(|(name, _)| name == bundle_name)
+            .map(|(_, thumbnails)| &thumbnails[..])
+    }
+
+    pub fn get_thumbnail_bundle_names(&self) -> Vec<String> {
+        self.thumbnails.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    // Thumbnails must not be used by frames that are still in flight
+    pub fn remove_thumbnails(&mut self, bundle_name: &str, factory: &mut DeviceFactory) {
+        if let Some(index) = self.thumbnails.iter().position(|(name, _)| name == bundle_name) {
+            let (_, thumbnails) = self.thumbnails.remove(index);
+            destroy_thumbnails(&thumbnails, factory);
+        }
+    }
+
+    // Material instances that no render instance uses are skipped, their parameters are only known per instance.
+    // Rendering waits for the GPU, existing thumbnails of the bundle are replaced.
+    pub fn render_thumbnails(
+        &mut self,
+        bundle_name: &str,
+        resource_bundle: &ResourceBundle,
+        pbr_resource_bundle: &PbrResourceBundle,
+        device: &Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) {
+        puffin::profile_function!();
+        self.remove_thumbnails(bundle_name, factory);
+
+        let mut material_instances: Vec<(usize, usize, &[u8; 64])> = Vec::new();
+        for bucket in &resource_bundle.buckets {
+            for instance in &bucket.instances {
+                let material_instance_id = instance.material_instance.index();
+                if !material_instances.iter().any(|(id, _, _)| *id == material_instance_id) {
+                    material_instances.push((
+                        material_instance_id,
+                        bucket.material.index(),
+                        &instance.material_instance_data,
+                    ));
+                }
+            }
+        }
+        material_instances.sort_by_key(|(material_instance_id, _, _)| *material_instance_id);
+        if material_instances.is_empty() {
+            self.thumbnails.push((bundle_name.to_string(), Vec::new()));
+            return;
+        }
+        log::info!(
+            "rendering {} material thumbnails of \"{}\"",
+            material_instances.len(),
+            bundle_name
+        );
+
+        let thumbnail_count = material_instances.len() as u32;
+        let descriptor_pool = factory.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(thumbnail_count)
+                .pool_sizes(&[
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(thumbnail_count * (3 + MATERIAL_TEXTURE_SLOTS.len() as u32))
+                        .build(),
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::STORAGE_IMAGE)
+                        .descriptor_count(thumbnail_count)
+                        .build(),
+                ])
+                .build(),
+        );
+        let descriptor_sets = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&vec![self.descriptor_set_layout; material_instances.len()])
+                .build(),
+        );
+
+        let environment_image_infos = [
+            pbr_resource_bundle.get_precomputed_brdf_image_view(),
+            pbr_resource_bundle.get_iem_image_view(),
+            pbr_resource_bundle.get_pmrem_image_view(),
+        ]
+        .iter()
+        .map(|image_view| {
+            vk::DescriptorImageInfo::builder()
+                .sampler(pbr_resource_bundle.linear_sampler)
+                .image_view(*image_view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+        let mut thumbnails = Vec::with_capacity(material_instances.len());
+        let mut constants = Vec::with_capacity(material_instances.len());
+        for (&(material_instance_id, material_id, instance_data), &descriptor_set) in
+            material_instances.iter().zip(descriptor_sets.iter())
+        {
+            let material = &resource_bundle.materials[material_id];
+            let material_images = &resource_bundle.material_instance_images[material_instance_id];
+
+            let mut texture_mask = 0;
+            let mut texture_infos = [vk::DescriptorImageInfo::builder()
+                .sampler(self.fallback_sampler)
+                .image_view(self.fallback_image_view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build(); MATERIAL_TEXTURE_SLOTS.len()];
+            for (slot_id, slot_name) in MATERIAL_TEXTURE_SLOTS.iter().enumerate() {
+                let image_id = material
+                    .shader_image_mapping
+                    .iter()
+                    .position(|(image_name, _)| image_name == slot_name);
+                if let Some(image_id) = image_id {
+                    let (image, sampler) = material_images[image_id];
+                    texture_infos[slot_id].image_view = resource_bundle.image_views[image.index()];
+                    texture_infos[slot_id].sampler = resource_bundle.samplers[sampler.index()];
+                    texture_mask |= 1 << slot_id;
+                }
+            }
+            constants.push(MaterialPreviewConstants {
+                base_color_factor: get_material_parameter(material, instance_data, "base_color_factor")
+                    .unwrap_or([1.0; 4]),
+                metallic_roughness_discard_unused: get_material_parameter(
+                    material,
+                    instance_data,
+                    "metallic_roughness_discard_unused",
+                )
+                .unwrap_or([1.0, 1.0, 0.5, 0.0]),
+                emissive_rgb_unused: get_material_parameter(material, instance_data, "emissive_rgb_unused")
+                    .unwrap_or([0.0; 4]),
+                texture_mask_size_alpha_test_unused: [
+                    texture_mask,
+                    MATERIAL_THUMBNAIL_SIZE,
+                    material.fragment_alpha_test as _,
+                    0,
+                ],
+            });
+
+            let image = allocate_thumbnail_image(MATERIAL_THUMBNAIL_SIZE, factory);
+            let image_view = create_thumbnail_image_view(image.0, factory);
+            factory.update_descriptor_sets(
+                &[
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(descriptor_set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&environment_image_infos[0..1])
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(descriptor_set)
+                        .dst_binding(1)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&environment_image_infos[1..2])
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(descriptor_set)
+                        .dst_binding(2)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&environment_image_infos[2..3])
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(descriptor_set)
+                        .dst_binding(3)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&texture_infos)
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(descriptor_set)
+                        .dst_binding(4)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&[vk::DescriptorImageInfo::builder()
+                            .image_view(image_view)
+                            .image_layout(vk::ImageLayout::GENERAL)
+                            .build()])
+                        .build(),
+                ],
+                &[],
+            );
+            thumbnails.push(MaterialThumbnail {
+                material_instance_id,
+                material_id,
+                image,
+                image_view,
+            });
+        }
+
+        let command_buffer = &mut self.command_buffer;
+        command_buffer.reset();
+        command_buffer.begin(
+            &vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build(),
+        );
+        let write_barriers: Vec<vk::ImageMemoryBarrier> = thumbnails
+            .iter()
+            .map(|thumbnail| {
+                get_thumbnail_barrier(
+                    thumbnail.image.0,
+                    (vk::AccessFlags::empty(), vk::AccessFlags::SHADER_WRITE),
+                    (vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL),
+                )
+            })
+            .collect();
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            None,
+            &[],
+            &[],
+            &write_barriers,
+        );
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, self.pipeline);
+        let group_count = MATERIAL_THUMBNAIL_SIZE.div_ceil(MATERIAL_PREVIEW_GROUP_SIZE);
+        for (descriptor_set, constants) in descriptor_sets.iter().zip(constants.iter()) {
+            command_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[*descriptor_set],
+                &[],
+            );
+            command_buffer.push_constants(self.pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, &[*constants]);
+            command_buffer.dispatch(group_count, group_count, 1);
+        }
+
+        let read_barriers: Vec<vk::ImageMemoryBarrier> = thumbnails
+            .iter()
+            .map(|thumbnail| {
+                get_thumbnail_barrier(
+                    thumbnail.image.0,
+                    (vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::SHADER_READ),
+                    (vk::ImageLayout::GENERAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                )
+            })
+            .collect();
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[],
+            &[],
+            &read_barriers,
+        );
+        command_buffer.end();
+        self.submit_and_wait(device, queue);
+
+        factory.destroy_descriptor_pool(descriptor_pool);
+        self.thumbnails.push((bundle_name.to_string(), thumbnails));
+    }
+
+    // Copies thumbnails of the bundle back to the CPU, they can be restored with `load_thumbnails()`
+    pub fn save_thumbnails(
+        &mut self,
+        bundle_name: &str,
+        device: &Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) -> Option<Vec<u8>> {
+        puffin::profile_function!();
+        let index = self.thumbnails.iter().position(|(name, _)| name == bundle_name)?;
+        let thumbnail_count = self.thumbnails[index].1.len();
+        let temp_buffer = allocate_thumbnail_buffer(
+            thumbnail_count,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::CpuOnly,
+            factory,
+        );
+
+        let command_buffer = &mut self.command_buffer;
+        command_buffer.reset();
+        command_buffer.begin(
+            &vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build(),
+        );
+        let thumbnails = &self.thumbnails[index].1;
+        let copy_barriers: Vec<vk::ImageMemoryBarrier> = thumbnails
+            .iter()
+            .map(|thumbnail| {
+                get_thumbnail_barrier(
+                    thumbnail.image.0,
+                    (vk::AccessFlags::SHADER_READ, vk::AccessFlags::TRANSFER_READ),
+                    (
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    ),
+                )
+            })
+            .collect();
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
+            None,
+            &[],
+            &[],
+            &copy_barriers,
+        );
+        for (thumbnail_id, thumbnail) in thumbnails.iter().enumerate() {
+            command_buffer.copy_image_to_buffer(
+                thumbnail.image.0,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                temp_buffer.0,
+                &[get_thumbnail_copy_region(thumbnail_id)],
+            );
+        }
+        let read_barriers: Vec<vk::ImageMemoryBarrier> = thumbnails
+            .iter()
+            .map(|thumbnail| {
+                get_thumbnail_barrier(
+                    thumbnail.image.0,
+                    (vk::AccessFlags::TRANSFER_READ, vk::AccessFlags::SHADER_READ),
+                    (
+                        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                )
+            })
+            .collect();
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[],
+            &[],
+            &read_barriers,
+        );
+        command_buffer.end();
+        self.submit_and_wait(device, queue);
+
+        let mut pixels = vec![0u8; thumbnail_count * get_thumbnail_byte_size()];
+        let temp_memory = factory.map_allocation_memory(&temp_buffer);
+        unsafe {
+            std::ptr::copy_nonoverlapping(temp_memory, pixels.as_mut_ptr(), pixels.len());
+        }
+        factory.unmap_allocation_memory(&temp_buffer);
+        factory.deallocate_buffer(&temp_buffer);
+
+        let disk_thumbnails = DiskMaterialThumbnails {
+            thumbnail_size: MATERIAL_THUMBNAIL_SIZE,
+            thumbnails: self.thumbnails[index]
+                .1
+                .iter()
+                .map(|thumbnail| (thumbnail.material_instance_id, thumbnail.material_id))
+                .collect(),
+            pixels,
+        };
+        Some(bincode::serialize(&disk_thumbnails).expect("failed to serialize material thumbnails"))
+    }
+
+    // Replaces thumbnails of the bundle with the saved ones, nothing is changed if the data can't be used
+    pub fn load_thumbnails(
+        &mut self,
+        bundle_name: &str,
+        thumbnail_data: &[u8],
+        device: &Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) -> bool {
+        puffin::profile_function!();
+        let disk_thumbnails: DiskMaterialThumbnails = match bincode::deserialize(thumbnail_data) {
+            Ok(disk_thumbnails) => disk_thumbnails,
+            Err(error) => {
+                log::warn!(
+                    "failed to deserialize material thumbnails of \"{}\": {:?}",
+                    bundle_name,
+                    error
+                );
+                return false;
+            }
+        };
+        let thumbnail_count = disk_thumbnails.thumbnails.len();
+        if disk_thumbnails.thumbnail_size != MATERIAL_THUMBNAIL_SIZE
+            || disk_thumbnails.pixels.len() != thumbnail_count * get_thumbnail_byte_size()
+        {
+            return false;
+        }
+        self.remove_thumbnails(bundle_name, factory);
+        if thumbnail_count == 0 {
+            self.thumbnails.push((bundle_name.to_string(), Vec::new()));
+            return true;
+        }
+
+        let temp_buffer = allocate_thumbnail_buffer(
+            thumbnail_count,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk_mem::MemoryUsage::CpuToGpu,
+            factory,
+        );
+        let temp_memory = factory.map_allocation_memory(&temp_buffer);
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                disk_thumbnails.pixels.as_ptr(),
+                temp_memory,
+                disk_thumbnails.pixels.len(),
+            );
+        }
+        factory.unmap_allocation_memory(&temp_buffer);
+
+        let thumbnails: Vec<MaterialThumbnail> = disk_thumbnails
+            .thumbnails
+            .iter()
+            .map(|&(material_instance_id, material_id)| {
+                let image = allocate_thumbnail_image(MATERIAL_THUMBNAIL_SIZE, factory);
+                let image_view = create_thumbnail_image_view(image.0, factory);
+                MaterialThumbnail {
+                    material_instance_id,
+                    material_id,
+                    image,
+                    image_view,
+                }
+            })
+            .collect();
+
+        let command_buffer = &mut self.command_buffer;
+        command_buffer.reset();
+        command_buffer.begin(
+            &vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build(),
+        );
+        let copy_barriers: Vec<vk::ImageMemoryBarrier> = thumbnails
+            .iter()
+            .map(|thumbnail| {
+                get_thumbnail_barrier(
+                    thumbnail.image.0,
+                    (vk::AccessFlags::empty(), vk::AccessFlags::TRANSFER_WRITE),
+                    (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+                )
+            })
+            .collect();
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            None,
+            &[],
+            &[],
+            &copy_barriers,
+        );
+        for (thumbnail_id, thumbnail) in thumbnails.iter().enumerate() {
+            command_buffer.copy_buffer_to_image(
+                temp_buffer.0,
+                thumbnail.image.0,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[get_thumbnail_copy_region(thumbnail_id)],
+            );
+        }
+        let read_barriers: Vec<vk::ImageMemoryBarrier> = thumbnails
+            .iter()
+            .map(|thumbnail| {
+                get_thumbnail_barrier(
+                    thumbnail.image.0,
+                    (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ),
+                    (
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                )
+            })
+            .collect();
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[],
+            &[],
+            &read_barriers,
+        );
+        command_buffer.end();
+        self.submit_and_wait(device, queue);
+
+        factory.deallocate_buffer(&temp_buffer);
+        self.thumbnails.push((bundle_name.to_string(), thumbnails));
+        true
+    }
+
+    fn clear_fallback_image(&mut self, device: &Device, queue: &mut DeviceQueue) {
+        let command_buffer = &mut self.command_buffer;
+        command_buffer.begin(
+            &vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                .build(),
+        );
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::PipelineStageFlags::TRANSFER,
+            None,
+            &[],
+            &[],
+            &[get_thumbnail_barrier(
+                self.fallback_image.0,
+                (vk::AccessFlags::empty(), vk::AccessFlags::TRANSFER_WRITE),
+                (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL),
+            )],
+        );
+        command_buffer.clear_color_image(
+            self.fallback_image.0,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &vk::ClearColorValue {
+                float32: [1.0, 1.0, 1.0, 1.0],
+            },
+            &[get_thumbnail_subresource_range()],
+        );
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            None,
+            &[],
+            &[],
+            &[get_thumbnail_barrier(
+                self.fallback_image.0,
+                (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ),
+                (
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ),
+            )],
+        );
+        command_buffer.end();
+        self.submit_and_wait(device, queue);
+    }
+
+    fn submit_and_wait(&mut self, device: &Device, queue: &mut DeviceQueue) {
+        queue.submit(
+            &[vk::SubmitInfo::builder()
+                .command_buffers(&[self.command_buffer.into()])
+                .build()],
+            self.fence,
+        );
+        device.wait_for_fences(&[self.fence], true, u64::MAX);
+        device.reset_fences(&[self.fence]);
+    }
+}
+
+// Matches PC_MaterialPreview in material_preview.glsl
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct MaterialPreviewConstants {
+    base_color_factor: [f32; 4],
+    metallic_roughness_discard_unused: [f32; 4],
+    emissive_rgb_unused: [f32; 4],
+    texture_mask_size_alpha_test_unused: [u32; 4],
+}
+
+// Pixels of all thumbnails, tightly packed in the order of `thumbnails`
+#[derive(serde::Serialize, serde::Deserialize)]
+struct DiskMaterialThumbnails {
+    thumbnail_size: u32,
+    thumbnails: Vec<(usize, usize)>, // material instance id, material id
+    pixels: Vec<u8>,
+}
+
+fn get_thumbnail_byte_size() -> usize {
+    (MATERIAL_THUMBNAIL_SIZE * MATERIAL_THUMBNAIL_SIZE * 4) as usize
+}
+
+fn get_thumbnail_copy_region(thumbnail_id: usize) -> vk::BufferImageCopy {
+    vk::BufferImageCopy::builder()
+        .buffer_offset((thumbnail_id * get_thumbnail_byte_size()) as _)
+        .image_subresource(
+            vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build(),
+        )
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D {
+            width: MATERIAL_THUMBNAIL_SIZE,
+            height: MATERIAL_THUMBNAIL_SIZE,
+            depth: 1,
+        })
+        .build()
+}
+
+fn allocate_thumbnail_buffer(
+    thumbnail_count: usize,
+    usage: vk::BufferUsageFlags,
+    memory_usage: vk_mem::MemoryUsage,
+    factory: &mut DeviceFactory,
+) -> HeapAllocatedResource<vk::Buffer> {
+    factory.allocate_buffer(
+        &vk::BufferCreateInfo::builder()
+            .size((thumbnail_count.max(1) * get_thumbnail_byte_size()) as _)
+            .usage(usage)
+            .build(),
+        &vk_mem::AllocationCreateInfo {
+            usage: memory_usage,
+            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE,
+            ..Default::default()
+        },
+    )
+}
+
+fn allocate_thumbnail_image(size: u32, factory: &mut DeviceFactory) -> HeapAllocatedResource<vk::Image> {
+    factory.allocate_image(
+        &vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(THUMBNAIL_FORMAT)
+            .extent(vk::Extent3D {
+                width: size,
+                height: size,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(
+                vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+            )
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build(),
+        &vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ..Default::default()
+        },
+    )
+}
+
+fn create_thumbnail_image_view(image: vk::Image, factory: &mut DeviceFactory) -> vk::ImageView {
+    factory.create_image_view(
+        &vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(THUMBNAIL_FORMAT)
+            .components(vk::ComponentMapping::default())
+            .subresource_range(get_thumbnail_subresource_range()),
+    )
+}
+
+fn destroy_thumbnails(thumbnails: &[MaterialThumbnail], factory: &mut DeviceFactory) {
+    for thumbnail in thumbnails {
+        factory.destroy_image_view(thumbnail.image_view);
+        factory.deallocate_image(&thumbnail.image);
+    }
+}
+
+fn get_thumbnail_subresource_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build()
+}
+
+fn get_thumbnail_barrier(
+    image: vk::Image,
+    (src_access_mask, dst_access_mask): (vk::AccessFlags, vk::AccessFlags),
+    (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier::builder()
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(!0)
+        .dst_queue_family_index(!0)
+        .image(image)
+        .subresource_range(get_thumbnail_subresource_range())
+        .build()
+}
@@ -8,11 +8,20 @@ use malwerks_bundles::*;
 use malwerks_core::*;
 use malwerks_vk::*;
 
+// Macro definitions are added to every stage of every material.
+// Alpha blended materials are compiled with ALPHA_BLEND and the provided macros,
+// they are compiled as opaque if no macros are provided.
+// With vertex pulling attributes are loaded from the vertex buffer bound as a storage buffer,
+// or through its address in ObjectData if the bundle has buffer addresses.
+// Compilation errors are returned with the compiler log, so that shader edits can be retried
 pub fn compile_material_shaders(
     source_bundle: &ResourceBundle,
     shader_path: &std::path::Path,
     temp_folder: &std::path::Path,
-) -> DiskShaderStageBundle {
+    macro_definitions: &[(&str, &str)],
+    alpha_blend_macro_definitions: Option<&[(&str, &str)]>,
+    vertex_pulling: bool,
+) -> Result<DiskShaderStageBundle, String> {
     std::fs::create_dir_all(temp_folder).expect("failed to create temp folder for shaders");
     log::info!(
         "compiling {} \"{}\" shaders",
@@ -24,7 +33,8 @@ pub fn compile_material_shaders(
             .expect("failed to convert shader path to str")
     );
 
-    let shader_code = std::fs::read_to_string(shader_path).expect("failed to open shader file");
+    let shader_code = std::fs::read_to_string(shader_path)
+        .map_err(|error| format!("failed to open shader file {}: {}", shader_path.display(), error))?;
 
     let mut compiler = shaderc::Compiler::new().expect("failed to initialize GLSL compiler");
     let mut compile_options = shaderc::CompileOptions::new().expect("failed to initialize GLSL compiler options");
@@ -32,10 +42,21 @@ pub fn compile_material_shaders(
     compile_options.set_optimization_level(shaderc::OptimizationLevel::Performance);
     compile_options.set_warnings_as_errors();
 
+    let shader_folder = shader_path
+        .parent()
+        .expect("shader path has no parent folder")
+        .to_path_buf();
+    let vertex_data_address = vertex_pulling && source_bundle.uses_buffer_device_address();
     let mut shader_stages = Vec::with_capacity(source_bundle.materials.len());
     for (material_id, material) in source_bundle.materials.iter().enumerate() {
-        let attribute_fetch_code = generate_attribute_fetch_code(&material.vertex_format);
+        let attribute_fetch_code = generate_attribute_fetch_code(
+            &material.vertex_format,
+            material.vertex_stride,
+            vertex_pulling,
+            vertex_data_address,
+        );
         let image_mapping_code = generate_image_mapping_code(&material.shader_image_mapping);
+        let material_parameters_code = generate_material_parameters_code(&material.shader_parameters);
 
         std::fs::write(
             temp_folder.join(&format!("attribute_fetch_{}.glsl", material_id)),
@@ -47,7 +68,13 @@ pub fn compile_material_shaders(
             &image_mapping_code,
         )
         .expect("failed to write generated image mapping shader");
+        std::fs::write(
+            temp_folder.join(&format!("material_parameters_{}.glsl", material_id)),
+            &material_parameters_code,
+        )
+        .expect("failed to write generated material parameters shader");
 
+        let shader_folder = shader_folder.clone();
         compile_options.set_include_callback(
             move |requested_source_path, _directive_type, _contained_within_path, _recursion_depth| {
                 if requested_source_path == "generated://attribute_fetch.glsl" {
@@ -60,18 +87,13 @@ pub fn compile_material_shaders(
                         resolved_name: String::from("image_mapping.glsl"),
                         content: image_mapping_code.clone(),
                     })
+                } else if requested_source_path == "generated://material_parameters.glsl" {
+                    Ok(shaderc::ResolvedInclude {
+                        resolved_name: String::from("material_parameters.glsl"),
+                        content: material_parameters_code.clone(),
+                    })
                 } else {
-                    match std::fs::read_to_string(&requested_source_path) {
-                        Ok(included_source) => Ok(shaderc::ResolvedInclude {
-                            resolved_name: String::from(requested_source_path),
-                            content: included_source,
-                        }),
-
-                        Err(e) => Err(format!(
-                            "failed to open GLSL include file {}: {}",
-                            &requested_source_path, e
-                        )),
-                    }
+                    resolve_shader_include(&shader_folder, requested_source_path)
                 }
             },
         );
@@ -80,6 +102,22 @@ pub fn compile_material_shaders(
         vertex_stage_options.add_macro_definition("VERTEX_STAGE", None);
         let mut fragment_stage_options = compile_options.clone().expect("failed to clone fragment options");
         fragment_stage_options.add_macro_definition("FRAGMENT_STAGE", None);
+        for (name, value) in &material.shader_macro_definitions {
+            vertex_stage_options.add_macro_definition(name, Some(value));
+            fragment_stage_options.add_macro_definition(name, Some(value));
+        }
+        for (name, value) in macro_definitions {
+            vertex_stage_options.add_macro_definition(name, Some(value));
+            fragment_stage_options.add_macro_definition(name, Some(value));
+        }
+        if let Some(alpha_blend_macro_definitions) = alpha_blend_macro_definitions {
+            if material.fragment_alpha_blend {
+                fragment_stage_options.add_macro_definition("ALPHA_BLEND", None);
+                for (name, value) in alpha_blend_macro_definitions {
+                    fragment_stage_options.add_macro_definition(name, Some(value));
+                }
+            }
+        }
 
         let vertex_stage = compiler
             .compile_into_spirv(
@@ -89,7 +127,7 @@ pub fn compile_material_shaders(
                 "main",
                 Some(&vertex_stage_options),
             )
-            .expect("failed to compile vertex shader");
+            .map_err(|error| error.to_string())?;
         let fragment_stage = compiler
             .compile_into_spirv(
                 &shader_code,
@@ -98,7 +136,7 @@ pub fn compile_material_shaders(
                 "main",
                 Some(&fragment_stage_options),
             )
-            .expect("failed to compile fragment shader");
+            .map_err(|error| error.to_string())?;
 
         shader_stages.push(DiskShaderStages::Material(DiskMaterialStages {
             vertex_stage: vertex_stage.as_binary().into(),
@@ -109,30 +147,100 @@ pub fn compile_material_shaders(
         }));
     }
 
-    DiskShaderStageBundle { shader_stages }
+    Ok(DiskShaderStageBundle { shader_stages })
+}
+
+// Shared headers are looked up next to the shader that includes them
+pub fn resolve_shader_include(
+    shader_folder: &std::path::Path,
+    requested_source_path: &str,
+) -> Result<shaderc::ResolvedInclude, String> {
+    let include_path = shader_folder.join(requested_source_path);
+    match std::fs::read_to_string(&include_path) {
+        Ok(included_source) => Ok(shaderc::ResolvedInclude {
+            resolved_name: include_path.to_string_lossy().into_owned(),
+            content: included_source,
+        }),
+
+        Err(e) => Err(format!(
+            "failed to open GLSL include file {}: {}",
+            include_path.display(),
+            e
+        )),
+    }
 }
 
-fn generate_attribute_fetch_code(vertex_format: &[VertexAttribute]) -> String {
+fn generate_attribute_fetch_code(
+    vertex_format: &[VertexAttribute],
+    vertex_stride: u32,
+    vertex_pulling: bool,
+    vertex_data_address: bool,
+) -> String {
     let mut shader_code = String::from("// Autogenerated vertex attribute fetch code\n");
+    if vertex_data_address {
+        shader_code.push_str("#extension GL_EXT_buffer_reference : require\n");
+        shader_code.push_str("#extension GL_EXT_buffer_reference_uvec2 : require\n");
+    }
     for attribute in vertex_format {
         shader_code.push_str(&format!("#define HAS_VS_{0} 1\n", attribute.attribute_name));
     }
 
+    // Object data is passed to the fragment stage after the last attribute:
+    // object ID, material instance, transform index and LOD
+    let object_data_location = vertex_format
+        .iter()
+        .map(|attribute| attribute.attribute_location + 1)
+        .max()
+        .unwrap_or(0);
+    shader_code.push_str("#define HAS_VS_object_data 1\n");
+
     shader_code.push_str("#ifdef VERTEX_STAGE\n");
     for attribute in vertex_format {
         let type_name = get_attribute_type_name(attribute.attribute_format);
+        let input_qualifier = if vertex_pulling {
+            String::new()
+        } else {
+            format!("layout (location = {}) in ", attribute.attribute_location)
+        };
         shader_code.push_str(&format!(
-            "layout (location = {0}) in {1} IN_{2};\nlayout (location = {0}) out {1} VS_{2};\n",
-            attribute.attribute_location, type_name, attribute.attribute_name,
+            "{4}{1} IN_{2};\nlayout (location = {0}) {3}out {1} VS_{2};\n",
+            attribute.attribute_location,
+            type_name,
+            attribute.attribute_name,
+            get_interpolation_qualifier(type_name),
+            input_qualifier,
         ));
     }
     shader_code.push_str("layout (std430, set = 1, binding = 0) restrict readonly buffer InstanceDataBuffer {\n");
     shader_code.push_str("    mat4 WorldTransforms[];\n");
     shader_code.push_str("};\n");
+    shader_code.push_str("struct ObjectDataEntry {\n");
+    shader_code.push_str("    uvec4 object_data;\n");
+    shader_code.push_str("    uvec2 vertex_data_address;\n");
+    shader_code.push_str("    uvec2 reserved;\n");
+    shader_code.push_str("};\n");
+    shader_code.push_str("layout (std430, set = 1, binding = 1) restrict readonly buffer ObjectDataBuffer {\n");
+    shader_code.push_str("    ObjectDataEntry ObjectData[];\n");
+    shader_code.push_str("};\n");
+    if vertex_pulling {
+        shader_code.push_str(&generate_vertex_pulling_code(
+            vertex_format,
+            vertex_stride,
+            vertex_data_address,
+        ));
+    }
+    shader_code.push_str(&format!(
+        "layout (location = {}) flat out uvec4 VS_object_data;\n",
+        object_data_location
+    ));
     shader_code.push_str("vec3 transform_direction(vec3 v, mat3 m)\n");
     shader_code.push_str("{ return normalize(m * (v / vec3(dot(m[0], m[0]), dot(m[1], m[1]), dot(m[2], m[2])))); }\n");
     shader_code.push_str("vec4 fetch_vertex_attributes() {\n");
+    if vertex_pulling {
+        shader_code.push_str("    pull_vertex_attributes();\n");
+    }
     shader_code.push_str("    mat4 world_transform = WorldTransforms[gl_InstanceIndex];\n");
+    shader_code.push_str("    VS_object_data = ObjectData[gl_InstanceIndex].object_data;\n");
     for attribute in vertex_format {
         match attribute.attribute_semantic {
             DiskVertexSemantic::Position => shader_code.push_str(&format!(
@@ -153,6 +261,11 @@ fn generate_attribute_fetch_code(vertex_format: &[VertexAttribute]) -> String {
             DiskVertexSemantic::Interpolated => {
                 shader_code.push_str(&format!("    VS_{0} = IN_{0};\n", attribute.attribute_name))
             }
+
+            DiskVertexSemantic::SkyVisibility => shader_code.push_str(&format!(
+                "    VS_{0} = vec4(transform_direction(IN_{0}.xyz * 2.0 - 1.0, mat3(world_transform)), IN_{0}.w);\n",
+                attribute.attribute_name
+            )),
         }
     }
     shader_code.push_str("    return vec4(VS_position.xyz, 1.0);\n");
@@ -163,10 +276,116 @@ fn generate_attribute_fetch_code(vertex_format: &[VertexAttribute]) -> String {
     for attribute in vertex_format {
         let type_name = get_attribute_type_name(attribute.attribute_format);
         shader_code.push_str(&format!(
-            "layout (location = {0}) in {1} VS_{2};\n",
-            attribute.attribute_location, type_name, attribute.attribute_name,
+            "layout (location = {0}) {3}in {1} VS_{2};\n",
+            attribute.attribute_location,
+            type_name,
+            attribute.attribute_name,
+            get_interpolation_qualifier(type_name),
         ));
     }
+    shader_code.push_str(&format!(
+        "layout (location = {}) flat in uvec4 VS_object_data;\n",
+        object_data_location
+    ));
+    shader_code.push_str("#endif\n");
+
+    shader_code
+}
+
+// Vertex buffer is read as raw words, gl_VertexIndex already includes the vertex offset of the draw.
+// Attributes are tightly packed and can be unaligned, so words are stitched together when needed.
+// With buffer addresses the vertex buffer is a buffer reference, otherwise it's bound to the third binding
+fn generate_vertex_pulling_code(
+    vertex_format: &[VertexAttribute],
+    vertex_stride: u32,
+    vertex_data_address: bool,
+) -> String {
+    let mut shader_code = String::new();
+    if vertex_data_address {
+        shader_code.push_str(
+            "layout (buffer_reference, std430, buffer_reference_align = 4) restrict readonly buffer VertexDataReference {\n",
+        );
+    } else {
+        shader_code.push_str("layout (std430, set = 1, binding = 2) restrict readonly buffer VertexDataBuffer {\n");
+    }
+    shader_code.push_str("    uint VertexData[];\n");
+    shader_code.push_str("};\n");
+    shader_code.push_str("uint load_vertex_data(uint byte_offset, uint byte_count) {\n");
+    let vertex_data = if vertex_data_address {
+        shader_code.push_str(
+            "    VertexDataReference vertex_data = VertexDataReference(ObjectData[gl_InstanceIndex].vertex_data_address);\n",
+        );
+        "vertex_data.VertexData"
+    } else {
+        "VertexData"
+    };
+    shader_code.push_str("    uint word_id = byte_offset >> 2;\n");
+    shader_code.push_str("    uint shift = (byte_offset & 3) * 8;\n");
+    shader_code.push_str(&format!("    uint value = {}[word_id] >> shift;\n", vertex_data));
+    shader_code.push_str(&format!(
+        "    if (shift + byte_count * 8 > 32) value |= {}[word_id + 1] << (32 - shift);\n",
+        vertex_data
+    ));
+    shader_code.push_str("    return value;\n");
+    shader_code.push_str("}\n");
+    shader_code.push_str("void pull_vertex_attributes() {\n");
+    shader_code.push_str(&format!(
+        "    uint vertex_base = uint(gl_VertexIndex) * {};\n",
+        vertex_stride
+    ));
+    for attribute in vertex_format {
+        let offset = format!("vertex_base + {}", attribute.attribute_offset);
+        let load_words = |convert: &str, count: usize| -> Vec<String> {
+            (0..count)
+                .map(|word| format!("{}(load_vertex_data({} + {}, 4))", convert, offset, word * 4))
+                .collect()
+        };
+        let value = match attribute.attribute_format {
+            vk::Format::R32_SINT => load_words("int", 1).join(""),
+            vk::Format::R32G32_SINT => format!("ivec2({})", load_words("int", 2).join(", ")),
+            vk::Format::R32G32B32_SINT => format!("ivec3({})", load_words("int", 3).join(", ")),
+            vk::Format::R32G32B32A32_SINT => format!("ivec4({})", load_words("int", 4).join(", ")),
+
+            vk::Format::R32_UINT => load_words("", 1).join(""),
+            vk::Format::R32G32_UINT => format!("uvec2({})", load_words("", 2).join(", ")),
+            vk::Format::R32G32B32_UINT => format!("uvec3({})", load_words("", 3).join(", ")),
+            vk::Format::R32G32B32A32_UINT => format!("uvec4({})", load_words("", 4).join(", ")),
+
+            vk::Format::R32_SFLOAT => load_words("uintBitsToFloat", 1).join(""),
+            vk::Format::R32G32_SFLOAT => format!("vec2({})", load_words("uintBitsToFloat", 2).join(", ")),
+            vk::Format::R32G32B32_SFLOAT => format!("vec3({})", load_words("uintBitsToFloat", 3).join(", ")),
+            vk::Format::R32G32B32A32_SFLOAT => format!("vec4({})", load_words("uintBitsToFloat", 4).join(", ")),
+
+            vk::Format::R8G8_UNORM => format!("unpackUnorm4x8(load_vertex_data({}, 2)).xy", offset),
+            vk::Format::R16G16_UNORM => load_words("unpackUnorm2x16", 1).join(""),
+            vk::Format::R8G8B8A8_UNORM => load_words("unpackUnorm4x8", 1).join(""),
+            vk::Format::R16G16B16A16_UNORM => format!("vec4({})", load_words("unpackUnorm2x16", 2).join(", ")),
+
+            _ => unimplemented!(),
+        };
+        shader_code.push_str(&format!("    IN_{} = {};\n", attribute.attribute_name, value));
+    }
+    shader_code.push_str("}\n");
+
+    shader_code
+}
+
+// Material instance data is pushed after the view projection matrix
+fn generate_material_parameters_code(parameters: &[String]) -> String {
+    let mut shader_code = String::from("// Autogenerated material parameters code\n");
+
+    shader_code.push_str("#ifdef FRAGMENT_STAGE\n");
+    if !parameters.is_empty() {
+        shader_code.push_str("layout (push_constant) uniform PC_MaterialInstance {\n");
+        for (parameter_id, parameter) in parameters.iter().enumerate() {
+            shader_code.push_str(&format!(
+                "    layout (offset = {}) vec4 {};\n",
+                64 + parameter_id * 16,
+                parameter
+            ));
+        }
+        shader_code.push_str("};\n");
+    }
     shader_code.push_str("#endif\n");
 
     shader_code
@@ -189,6 +408,15 @@ fn generate_image_mapping_code(images: &[(String, String)]) -> String {
     shader_code
 }
 
+// Integer attributes can't be interpolated
+fn get_interpolation_qualifier(type_name: &str) -> &'static str {
+    if type_name.starts_with('i') || type_name.starts_with('u') {
+        "flat "
+    } else {
+        ""
+    }
+}
+
 fn get_attribute_type_name(attribute_format: vk::Format) -> &'static str {
     match attribute_format {
         vk::Format::R32_SINT => "int",
@@ -206,6 +434,11 @@ fn get_attribute_type_name(attribute_format: vk::Format) -> &'static str {
         vk::Format::R32G32B32_SFLOAT => "vec3",
         vk::Format::R32G32B32A32_SFLOAT => "vec4",
 
+        vk::Format::R8G8_UNORM => "vec2",
+        vk::Format::R16G16_UNORM => "vec2",
+        vk::Format::R8G8B8A8_UNORM => "vec4",
+        vk::Format::R16G16B16A16_UNORM => "vec4",
+
         _ => unimplemented!(),
     }
 }
@@ -0,0 +1,568 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+
+pub const MAX_NAN_DETECTION_BUFFERS: usize = 256;
+
+const SCAN_IMAGE_GROUP_SIZE: u32 = 8;
+const SCAN_BUFFER_GROUP_SIZE: u64 = 64;
+const RESULT_HEADER_SIZE: usize = 3; // pixel_count, first_pixel, value_count
+const NO_NON_FINITE_VALUE: u32 = !0;
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NanDetectionParameters {
+    pub tint_output: bool, // non-finite pixels are drawn magenta over the final image
+}
+
+// Storage buffer that is scanned as an array of floats
+pub struct NanDetectionBuffer {
+    pub name: String,
+    pub buffer: vk::Buffer,
+    pub size: u64,
+    pub stride: u32, // floats per element, the first non-finite element is reported
+}
+
+// Non-finite values of the last frame that has finished on the GPU
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NanDetectionResult {
+    pub pixel_count: u32,
+    pub first_pixel: Option<(u32, u32)>, // top left first, in render layer coordinates
+    pub value_count: u32,
+    pub first_value: Option<(String, u32)>, // buffer name and element index
+}
+
+// Scans the HDR color and storage buffers for NaN and Inf, results are read back when the frame is reused
+pub struct NanDetection {
+    mask_image: HeapAllocatedResource<vk::Image>,
+    mask_image_view: vk::ImageView,
+    point_sampler: vk::Sampler,
+    hdr_image_view: vk::ImageView,
+    result_buffer: FrameLocal<HeapAllocatedResource<vk::Buffer>>,
+    scanned_buffers: FrameLocal<Option<Vec<(String, u32)>>>, // name and stride, Some if a scan is in flight
+
+    descriptor_pool: FrameLocal<vk::DescriptorPool>,
+    descriptor_set_layouts: [vk::DescriptorSetLayout; 2], // image and results, scanned buffer
+    scan_image_module: vk::ShaderModule,
+    scan_buffer_module: vk::ShaderModule,
+    pipeline_layout: vk::PipelineLayout,
+    scan_image_pipeline: vk::Pipeline,
+    scan_buffer_pipeline: vk::Pipeline,
+
+    tint: FullScreenPass,
+    result: NanDetectionResult,
+}
+
+impl NanDetection {
+    pub fn new(
+        common_shaders: &DiskCommonShaders,
+        source_layer: &RenderLayer,
+        target_layer: &RenderLayer,
+        render_width: u32,
+        render_height: u32,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        let mask_image = factory.allocate_image(
+            &vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(vk::Format::R8_UNORM)
+                .extent(vk::Extent3D {
+                    width: render_width,
+                    height: render_height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ..Default::default()
+            },
+        );
+        let mask_image_view = factory.create_image_view(
+            &vk::ImageViewCreateInfo::builder()
+                .image(mask_image.0)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(vk::Format::R8_UNORM)
+                .components(vk::ComponentMapping::default())
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(1)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build(),
+        );
+        let point_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build(),
+        );
+
+        let num_buffered_frames = factory.get_num_buffered_frames();
+        let result_buffer = FrameLocal::new(num_buffered_frames, |_| {
+            factory.allocate_buffer(
+                &vk::BufferCreateInfo::builder()
+                    .size(((RESULT_HEADER_SIZE + MAX_NAN_DETECTION_BUFFERS) * std::mem::size_of::<u32>()) as _)
+                    .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+                    .build(),
+                &vk_mem::AllocationCreateInfo {
+                    usage: vk_mem::MemoryUsage::GpuToCpu,
+                    required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    ..Default::default()
+                },
+            )
+        });
+        let descriptor_pool = FrameLocal::new(num_buffered_frames, |_| {
+            factory.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::builder()
+                    .max_sets((MAX_NAN_DETECTION_BUFFERS + 1) as _)
+                    .pool_sizes(&[
+                        vk::DescriptorPoolSize::builder()
+                            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                            .descriptor_count(1)
+                            .build(),
+                        vk::DescriptorPoolSize::builder()
+                            .ty(vk::DescriptorType::STORAGE_IMAGE)
+                            .descriptor_count(1)
+                            .build(),
+                        vk::DescriptorPoolSize::builder()
+                            .ty(vk::DescriptorType::STORAGE_BUFFER)
+                            .descriptor_count((MAX_NAN_DETECTION_BUFFERS + 1) as _)
+                            .build(),
+                    ])
+                    .build(),
+            )
+        });
+        let descriptor_set_layouts = [
+            factory.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::builder()
+                    .bindings(&[
+                        vk::DescriptorSetLayoutBinding::builder()
+                            .binding(0)
+                            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                            .descriptor_count(1)
+                            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                            .build(),
+                        vk::DescriptorSetLayoutBinding::builder()
+                            .binding(1)
+                            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                            .descriptor_count(1)
+                            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                            .build(),
+                        vk::DescriptorSetLayoutBinding::builder()
+                            .binding(2)
+                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                            .descriptor_count(1)
+                            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                            .build(),
+                    ])
+                    .build(),
+            ),
+            factory.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::builder()
+                    .bindings(&[vk::DescriptorSetLayoutBinding::builder()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build()])
+                    .build(),
+            ),
+        ];
+
+        let scan_image_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.nan_detection_scan_image_compute_stage)
+                .build(),
+        );
+        let scan_buffer_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.nan_detection_scan_buffer_compute_stage)
+                .build(),
+        );
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&descriptor_set_layouts)
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .offset(0)
+                    .size(std::mem::size_of::<[u32; 4]>() as _)
+                    .build()])
+                .build(),
+        );
+
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let pipelines = factory.create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[
+                vk::ComputePipelineCreateInfo::builder()
+                    .stage(
+                        vk::PipelineShaderStageCreateInfo::builder()
+                            .name(&entry_name)
+                            .module(scan_image_module)
+                            .stage(vk::ShaderStageFlags::COMPUTE)
+                            .build(),
+                    )
+                    .layout(pipeline_layout)
+                    .build(),
+                vk::ComputePipelineCreateInfo::builder()
+                    .stage(
+                        vk::PipelineShaderStageCreateInfo::builder()
+                            .name(&entry_name)
+                            .module(scan_buffer_module)
+                            .stage(vk::ShaderStageFlags::COMPUTE)
+                            .build(),
+                    )
+                    .layout(pipeline_layout)
+                    .build(),
+            ],
+        );
+
+        let tint = FullScreenPass::new(
+            &FullScreenPassParameters {
+                vertex_stage: &common_shaders.full_screen_vertex_stage,
+                fragment_stage: &common_shaders.nan_detection_tint_fragment_stage,
+                input_images: &[&[mask_image_view]],
+                sampler_filter: vk::Filter::NEAREST,
+                additional_set_layouts: &[],
+                push_constants_size: std::mem::size_of::<[f32; 4]>() as _,
+            },
+            target_layer,
+            factory,
+        );
+
+        Self {
+            mask_image,
+            mask_image_view,
+            point_sampler,
+            hdr_image_view: source_layer.get_render_image(0).1,
+            result_buffer,
+            scanned_buffers: FrameLocal::new(num_buffered_frames, |_| None),
+            descriptor_pool,
+            descriptor_set_layouts,
+            scan_image_module,
+            scan_buffer_module,
+            pipeline_layout,
+            scan_image_pipeline: pipelines[0],
+            scan_buffer_pipeline: pipelines[1],
+            tint,
+            result: Default::default(),
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        factory.deallocate_image(&self.mask_image);
+        factory.destroy_image_view(self.mask_image_view);
+        factory.destroy_sampler(self.point_sampler);
+        self.result_buffer.destroy(|buffer| factory.deallocate_buffer(buffer));
+        self.descriptor_pool
+            .destroy(|descriptor_pool| factory.destroy_descriptor_pool(*descriptor_pool));
+        for descriptor_set_layout in &self.descriptor_set_layouts {
+            factory.destroy_descriptor_set_layout(*descriptor_set_layout);
+        }
+        factory.destroy_shader_module(self.scan_image_module);
+        factory.destroy_shader_module(self.scan_buffer_module);
+        factory.destroy_pipeline_layout(self.pipeline_layout);
+        factory.destroy_pipeline(self.scan_image_pipeline);
+        factory.destroy_pipeline(self.scan_buffer_pipeline);
+        self.tint.destroy(factory);
+    }
+
+    pub fn get_result(&self) -> &NanDetectionResult {
+        &self.result
+    }
+
+    // HDR color has to be in SHADER_READ_ONLY_OPTIMAL, buffers past MAX_NAN_DETECTION_BUFFERS are not scanned.
+    // Results of the previous scan in this frame are read first, the frame is expected to be finished on the GPU.
+    pub fn dispatch(
+        &mut self,
+        command_buffer: &mut CommandBuffer,
+        scan_area: vk::Rect2D,
+        scanned_buffers: &[NanDetectionBuffer],
+        frame_context: &FrameContext,
+        factory: &mut DeviceFactory,
+    ) {
+        puffin::profile_function!();
+
+        let scanned_buffers = &scanned_buffers[..scanned_buffers.len().min(MAX_NAN_DETECTION_BUFFERS)];
+        let result_buffer = self.result_buffer.get(frame_context);
+        let result_size = RESULT_HEADER_SIZE + MAX_NAN_DETECTION_BUFFERS;
+        let result_memory = factory.map_allocation_memory(result_buffer);
+        if let Some(previous_buffers) = self.scanned_buffers.get_mut(frame_context).take() {
+            let mut result_data = vec![0u32; result_size];
+            unsafe {
+                std::ptr::copy_nonoverlapping(result_memory as *const u32, result_data.as_mut_ptr(), result_size);
+            }
+            self.result = read_nan_detection_result(&result_data, &previous_buffers, &self.result);
+        }
+        let mut reset_data = vec![NO_NON_FINITE_VALUE; result_size];
+        reset_data[0] = 0;
+        reset_data[2] = 0;
+        copy_to_mapped_memory(&reset_data, result_memory);
+        factory.unmap_allocation_memory(result_buffer);
+        *self.scanned_buffers.get_mut(frame_context) = Some(
+            scanned_buffers
+                .iter()
+                .map(|scanned_buffer| (scanned_buffer.name.clone(), scanned_buffer.stride))
+                .collect(),
+        );
+
+        let descriptor_pool = *self.descriptor_pool.get(frame_context);
+        factory.reset_descriptor_pool(descriptor_pool);
+        let mut temp_set_layouts = vec![self.descriptor_set_layouts[1]; scanned_buffers.len() + 1];
+        temp_set_layouts[0] = self.descriptor_set_layouts[0];
+        let descriptor_sets = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&temp_set_layouts)
+                .build(),
+        );
+
+        let image_infos = [
+            vk::DescriptorImageInfo::builder()
+                .sampler(self.point_sampler)
+                .image_view(self.hdr_image_view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build(),
+            vk::DescriptorImageInfo::builder()
+                .image_view(self.mask_image_view)
+                .image_layout(vk::ImageLayout::GENERAL)
+                .build(),
+        ];
+        let mut buffer_infos = Vec::with_capacity(scanned_buffers.len() + 1);
+        buffer_infos.push(
+            vk::DescriptorBufferInfo::builder()
+                .buffer(result_buffer.0)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build(),
+        );
+        for scanned_buffer in scanned_buffers {
+            buffer_infos.push(
+                vk::DescriptorBufferInfo::builder()
+                    .buffer(scanned_buffer.buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build(),
+            );
+        }
+        let mut descriptor_writes = Vec::with_capacity(descriptor_sets.len() + 2);
+        descriptor_writes.push(
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_sets[0])
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_infos[0..1])
+                .build(),
+        );
+        descriptor_writes.push(
+            vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_sets[0])
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(&image_infos[1..2])
+                .build(),
+        );
+        for (set_id, (descriptor_set, buffer_info)) in descriptor_sets.iter().zip(&buffer_infos).enumerate() {
+            // Results are the last binding of the first set, scanned buffers have a set each
+            let binding = if set_id == 0 { 2 } else { 0 };
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*descriptor_set)
+                    .dst_binding(binding)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(std::slice::from_ref(buffer_info))
+                    .build(),
+            );
+        }
+        factory.update_descriptor_sets(&descriptor_writes, &[]);
+
+        // Mask is tinted by the previous frame, scanned buffers may have been written by compute passes
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build()],
+            &[],
+            &[vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_READ)
+                .dst_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::GENERAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(self.mask_image.0)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(1)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build()],
+        );
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, self.scan_image_pipeline);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+            0,
+            &descriptor_sets[0..1],
+            &[],
+        );
+        command_buffer.push_constants(
+            self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &[
+                scan_area.offset.x as u32,
+                scan_area.offset.y as u32,
+                scan_area.extent.width,
+                scan_area.extent.height,
+            ],
+        );
+        command_buffer.dispatch(
+            scan_area.extent.width.div_ceil(SCAN_IMAGE_GROUP_SIZE),
+            scan_area.extent.height.div_ceil(SCAN_IMAGE_GROUP_SIZE),
+            1,
+        );
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, self.scan_buffer_pipeline);
+        for (buffer_id, scanned_buffer) in scanned_buffers.iter().enumerate() {
+            command_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                1,
+                &[descriptor_sets[buffer_id + 1]],
+                &[],
+            );
+            command_buffer.push_constants(
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &[buffer_id as u32, 0, 0, 0],
+            );
+            let value_count = scanned_buffer.size / std::mem::size_of::<f32>() as u64;
+            command_buffer.dispatch(value_count.div_ceil(SCAN_BUFFER_GROUP_SIZE) as _, 1, 1);
+        }
+
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::HOST,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::HOST_READ)
+                .build()],
+            &[],
+            &[vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::GENERAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(self.mask_image.0)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(1)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build()],
+        );
+    }
+
+    // Output area is the whole viewport, render scale maps it to the scanned area
+    pub fn render_tint(
+        &mut self,
+        output_area: vk::Rect2D,
+        render_scale: f32,
+        frame_context: &FrameContext,
+        target_layer: &mut RenderLayer,
+    ) {
+        let command_buffer = target_layer.get_command_buffer(frame_context);
+        command_buffer.set_viewport(
+            0,
+            &[vk::Viewport {
+                x: output_area.offset.x as _,
+                y: output_area.offset.y as _,
+                width: output_area.extent.width as _,
+                height: output_area.extent.height as _,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+        );
+        command_buffer.set_scissor(0, &[output_area]);
+        self.tint
+            .push_constants(command_buffer, &[render_scale, render_scale, 0.0, 0.0]);
+        self.tint.render(command_buffer, 0, &[], &[]);
+    }
+}
+
+// Warnings are only logged when non-finite values show up, not for every frame they stay
+pub(crate) fn read_nan_detection_result(
+    result_data: &[u32],
+    scanned_buffers: &[(String, u32)],
+    previous_result: &NanDetectionResult,
+) -> NanDetectionResult {
+    let first_pixel = result_data[1];
+    let first_value = scanned_buffers
+        .iter()
+        .zip(&result_data[RESULT_HEADER_SIZE..])
+        .find(|(_, value_id)| **value_id != NO_NON_FINITE_VALUE)
+        .map(|((name, stride), value_id)| (name.clone(), value_id / stride));
+    let result = NanDetectionResult {
+        pixel_count: result_data[0],
+        first_pixel: if first_pixel != NO_NON_FINITE_VALUE {
+            Some((first_pixel & 0xFFFF, first_pixel >> 16))
+        } else {
+            None
+        },
+        value_count: result_data[2],
+        first_value,
+    };
+
+    if let (Some((x, y)), None) = (result.first_pixel, previous_result.first_pixel) {
+        log::warn!(
+            "{} non-finite pixels in the HDR color, first one at {}x{}",
+            result.pixel_count,
+            x,
+            y
+        );
+    }
+    if let (Some((name, element_id)), None) = (&result.first_value, &previous_result.first_value) {
+        log::warn!(
+            "{} non-finite values in storage buffers, first one in element {} of {}",
+            result.value_count,
+            element_id,
+            name
+        );
+    }
+    result
+}
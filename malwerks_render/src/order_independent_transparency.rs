@@ -0,0 +1,778 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+
+// Average number of transparent fragments per pixel the linked list node buffer can hold
+const FRAGMENT_NODES_PER_PIXEL: u64 = 4;
+const FRAGMENT_NODE_SIZE: u64 = 16;
+const FRAGMENT_NODE_HEADER_SIZE: u64 = 16;
+
+const ACCUMULATION_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const REVEALAGE_FORMAT: vk::Format = vk::Format::R16_SFLOAT;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TransparencyMode {
+    // Alpha blended materials are rendered as opaque
+    Disabled,
+    // Approximate, fixed memory cost
+    WeightedBlended,
+    // Exact up to 16 layers per pixel, memory scales with the render resolution
+    LinkedLists,
+}
+
+impl TransparencyMode {
+    // Macros for alpha blended material shaders, None if they are compiled as opaque
+    pub fn get_macro_definitions(&self) -> Option<&'static [(&'static str, &'static str)]> {
+        match self {
+            TransparencyMode::Disabled => None,
+            TransparencyMode::WeightedBlended => Some(&[("OIT_WEIGHTED_BLENDED", "1")]),
+            TransparencyMode::LinkedLists => Some(&[("OIT_LINKED_LISTS", "1")]),
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            TransparencyMode::Disabled => "disabled",
+            TransparencyMode::WeightedBlended => "weighted_blended",
+            TransparencyMode::LinkedLists => "linked_lists",
+        }
+    }
+}
+
+// The source color image is the composite target, weighted blended mode needs two transient images
+pub struct OrderIndependentTransparencyParameters<'a> {
+    pub mode: TransparencyMode,
+    pub source_layer: &'a RenderLayer,
+    pub source_color_image: usize,
+    pub source_color_format: vk::Format,
+    pub render_width: u32,
+    pub render_height: u32,
+    pub transient_images: &'a TransientImagePool,
+    pub first_transient_image: usize,
+}
+
+struct FragmentLists {
+    head_image: HeapAllocatedResource<vk::Image>,
+    head_image_view: vk::ImageView,
+    node_buffer: HeapAllocatedResource<vk::Buffer>,
+}
+
+pub struct OrderIndependentTransparency {
+    mode: TransparencyMode,
+    accumulation_layer: RenderLayer,
+    composite_layer: RenderLayer,
+    source_color_image: usize,
+    fragment_lists: Option<FragmentLists>,
+
+    point_sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    accumulation_descriptor_set_layout: vk::DescriptorSetLayout,
+    accumulation_descriptor_set: vk::DescriptorSet,
+    composite_descriptor_set_layout: vk::DescriptorSetLayout,
+    composite_descriptor_set: vk::DescriptorSet,
+
+    vert_module: vk::ShaderModule,
+    frag_module: vk::ShaderModule,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl OrderIndependentTransparency {
+    // Weighted blended accumulation targets are only used between accumulation and resolve
+    pub fn get_transient_image_parameters(
+        mode: TransparencyMode,
+        render_width: u32,
+        render_height: u32,
+        pass: u32,
+    ) -> Vec<TransientImageParameters> {
+        if mode != TransparencyMode::WeightedBlended {
+            return Vec::new();
+        }
+        [ACCUMULATION_FORMAT, REVEALAGE_FORMAT]
+            .iter()
+            .map(|image_format| TransientImageParameters {
+                width: render_width,
+                height: render_height,
+                image_format: *image_format,
+                image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                first_pass: pass,
+                last_pass: pass,
+            })
+            .collect()
+    }
+
+    pub fn new(
+        parameters: &OrderIndependentTransparencyParameters,
+        common_shaders: &DiskCommonShaders,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        let &OrderIndependentTransparencyParameters {
+            mode,
+            source_layer,
+            source_color_image,
+            source_color_format,
+            render_width,
+            render_height,
+            transient_images,
+            first_transient_image,
+        } = parameters;
+        assert!(mode != TransparencyMode::Disabled, "transparency mode is disabled");
+
+        // Weighted blended mode accumulates into its own color targets, linked lists are built without attachments
+        let accumulation_layer = match mode {
+            TransparencyMode::WeightedBlended => RenderLayer::from_transient_images(
+                device,
+                factory,
+                &RenderLayerParameters {
+                    render_image_parameters: &[
+                        RenderImageParameters {
+                            image_format: ACCUMULATION_FORMAT,
+                            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                            image_clear_value: vk::ClearValue::default(),
+                        },
+                        RenderImageParameters {
+                            image_format: REVEALAGE_FORMAT,
+                            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                            image_clear_value: vk::ClearValue {
+                                color: vk::ClearColorValue { float32: [1.0; 4] },
+                            },
+                        },
+                    ],
+                    depth_image_parameters: None,
+                    render_pass_parameters: &[RenderPassParameters {
+                        flags: vk::SubpassDescriptionFlags::default(),
+                        pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+                        input_attachments: None,
+                        color_attachments: Some(&[
+                            vk::AttachmentReference::builder()
+                                .attachment(0)
+                                .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                                .build(),
+                            vk::AttachmentReference::builder()
+                                .attachment(1)
+                                .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                                .build(),
+                        ]),
+                        resolve_attachments: None,
+                        depth_stencil_attachment: None,
+                        preserve_attachments: None,
+                    }],
+                    render_pass_dependencies: None,
+                    view_mask: 0,
+                },
+                &TransientLayerImages {
+                    width: render_width,
+                    height: render_height,
+                    transient_images,
+                    image_indices: &[first_transient_image, first_transient_image + 1],
+                    depth_image_index: None,
+                },
+            ),
+            _ => RenderLayer::new(
+                device,
+                factory,
+                render_width,
+                render_height,
+                &RenderLayerParameters {
+                    render_image_parameters: &[],
+                    depth_image_parameters: None,
+                    render_pass_parameters: &[RenderPassParameters {
+                        flags: vk::SubpassDescriptionFlags::default(),
+                        pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+                        input_attachments: None,
+                        color_attachments: None,
+                        resolve_attachments: None,
+                        depth_stencil_attachment: None,
+                        preserve_attachments: None,
+                    }],
+                    render_pass_dependencies: None,
+                    view_mask: 0,
+                },
+            ),
+        };
+        let composite_layer = RenderLayer::from_shared_images(
+            device,
+            factory,
+            source_layer,
+            render_width,
+            render_height,
+            &[SharedImageParameters {
+                image_index: source_color_image,
+                image_format: source_color_format,
+                initial_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }],
+        );
+
+        let fragment_lists = if mode == TransparencyMode::LinkedLists {
+            let head_image = factory.allocate_image(
+                &vk::ImageCreateInfo::builder()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(vk::Format::R32_UINT)
+                    .extent(vk::Extent3D {
+                        width: render_width,
+                        height: render_height,
+                        depth: 1,
+                    })
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::TRANSFER_DST)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .build(),
+                &vk_mem::AllocationCreateInfo {
+                    usage: vk_mem::MemoryUsage::GpuOnly,
+                    required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                    ..Default::default()
+                },
+            );
+            let head_image_view = factory.create_image_view(
+                &vk::ImageViewCreateInfo::builder()
+                    .image(head_image.0)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(vk::Format::R32_UINT)
+                    .components(vk::ComponentMapping::default())
+                    .subresource_range(
+                        vk::ImageSubresourceRange::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .base_mip_level(0)
+                            .level_count(1)
+                            .base_array_layer(0)
+                            .layer_count(1)
+                            .build(),
+                    )
+                    .build(),
+            );
+            let node_count = render_width as u64 * render_height as u64 * FRAGMENT_NODES_PER_PIXEL;
+            let node_buffer = factory.allocate_buffer(
+                &vk::BufferCreateInfo::builder()
+                    .size(FRAGMENT_NODE_HEADER_SIZE + node_count * FRAGMENT_NODE_SIZE)
+                    .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .build(),
+                &vk_mem::AllocationCreateInfo {
+                    usage: vk_mem::MemoryUsage::GpuOnly,
+                    required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                    ..Default::default()
+                },
+            );
+
+            Some(FragmentLists {
+                head_image,
+                head_image_view,
+                node_buffer,
+            })
+        } else {
+            None
+        };
+
+        let point_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build(),
+        );
+
+        let descriptor_pool = factory.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder().max_sets(2).pool_sizes(&[
+                vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(3)
+                    .build(),
+                vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(2)
+                    .build(),
+                vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(2)
+                    .build(),
+            ]),
+        );
+
+        // Accumulation set is bound to material pipelines, composite set to the composite pipeline
+        let make_binding = |binding: u32, descriptor_type: vk::DescriptorType| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(descriptor_type)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()
+        };
+        let (accumulation_bindings, composite_bindings) = if fragment_lists.is_some() {
+            (
+                vec![
+                    make_binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+                    make_binding(1, vk::DescriptorType::STORAGE_IMAGE),
+                    make_binding(2, vk::DescriptorType::STORAGE_BUFFER),
+                ],
+                vec![
+                    make_binding(0, vk::DescriptorType::STORAGE_IMAGE),
+                    make_binding(1, vk::DescriptorType::STORAGE_BUFFER),
+                ],
+            )
+        } else {
+            (
+                vec![make_binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)],
+                vec![
+                    make_binding(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+                    make_binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+                ],
+            )
+        };
+        let accumulation_descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&accumulation_bindings),
+        );
+        let composite_descriptor_set_layout = factory
+            .create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::builder().bindings(&composite_bindings));
+        let descriptor_sets = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&[accumulation_descriptor_set_layout, composite_descriptor_set_layout])
+                .build(),
+        );
+        let accumulation_descriptor_set = descriptor_sets[0];
+        let composite_descriptor_set = descriptor_sets[1];
+
+        let source_depth_image = source_layer
+            .get_depth_image()
+            .expect("Depth image is required for transparency")
+            .1;
+        let depth_image_info = [vk::DescriptorImageInfo::builder()
+            .sampler(point_sampler)
+            .image_view(source_depth_image)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+        let mut descriptor_writes = vec![vk::WriteDescriptorSet::builder()
+            .dst_set(accumulation_descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&depth_image_info)
+            .build()];
+
+        // Descriptor infos have to outlive the descriptor writes
+        let head_image_info;
+        let node_buffer_info;
+        let accumulation_image_infos;
+        if let Some(fragment_lists) = &fragment_lists {
+            head_image_info = [vk::DescriptorImageInfo::builder()
+                .image_view(fragment_lists.head_image_view)
+                .image_layout(vk::ImageLayout::GENERAL)
+                .build()];
+            node_buffer_info = [vk::DescriptorBufferInfo::builder()
+                .buffer(fragment_lists.node_buffer.0)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build()];
+            for (descriptor_set, first_binding) in &[(accumulation_descriptor_set, 1), (composite_descriptor_set, 0)] {
+                descriptor_writes.push(
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*descriptor_set)
+                        .dst_binding(*first_binding)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&head_image_info)
+                        .build(),
+                );
+                descriptor_writes.push(
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*descriptor_set)
+                        .dst_binding(*first_binding + 1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .buffer_info(&node_buffer_info)
+                        .build(),
+                );
+            }
+        } else {
+            accumulation_image_infos = [
+                vk::DescriptorImageInfo::builder()
+                    .sampler(point_sampler)
+                    .image_view(accumulation_layer.get_render_image(0).1)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .build(),
+                vk::DescriptorImageInfo::builder()
+                    .sampler(point_sampler)
+                    .image_view(accumulation_layer.get_render_image(1).1)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .build(),
+            ];
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(composite_descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&accumulation_image_infos)
+                    .build(),
+            );
+        }
+        factory.update_descriptor_sets(&descriptor_writes, &[]);
+
+        let vert_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.transparency_composite_vertex_stage)
+                .build(),
+        );
+        let frag_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(if fragment_lists.is_some() {
+                    &common_shaders.linked_lists_composite_fragment_stage
+                } else {
+                    &common_shaders.weighted_blended_composite_fragment_stage
+                })
+                .build(),
+        );
+
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[composite_descriptor_set_layout])
+                .build(),
+        );
+
+        // Composite outputs premultiplied color and transmittance
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let vertex_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(vert_module)
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let fragment_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(frag_module)
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let pipeline = factory.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[composite_layer.make_pipeline_create_info(
+                vk::GraphicsPipelineCreateInfo::builder()
+                    .stages(&[vertex_stage, fragment_stage])
+                    .vertex_input_state(
+                        &vk::PipelineVertexInputStateCreateInfo::builder()
+                            .vertex_binding_descriptions(&[])
+                            .build(),
+                    )
+                    .input_assembly_state(
+                        &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                            .primitive_restart_enable(false)
+                            .build(),
+                    )
+                    .tessellation_state(&Default::default())
+                    .viewport_state(
+                        &vk::PipelineViewportStateCreateInfo::builder()
+                            .viewport_count(1)
+                            .scissor_count(1)
+                            .build(),
+                    )
+                    .rasterization_state(
+                        &vk::PipelineRasterizationStateCreateInfo::builder()
+                            .cull_mode(vk::CullModeFlags::NONE)
+                            .line_width(1.0)
+                            .build(),
+                    )
+                    .multisample_state(
+                        &vk::PipelineMultisampleStateCreateInfo::builder()
+                            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                            .build(),
+                    )
+                    .depth_stencil_state(&Default::default())
+                    .color_blend_state(
+                        &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                            vk::PipelineColorBlendAttachmentState::builder()
+                                .blend_enable(true)
+                                .src_color_blend_factor(vk::BlendFactor::ONE)
+                                .dst_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                                .color_blend_op(vk::BlendOp::ADD)
+                                .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+                                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                                .alpha_blend_op(vk::BlendOp::ADD)
+                                .color_write_mask(get_color_write_mask(source_color_format))
+                                .build(),
+                        ]),
+                    )
+                    .dynamic_state(
+                        &vk::PipelineDynamicStateCreateInfo::builder()
+                            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                            .build(),
+                    )
+                    .layout(pipeline_layout)
+                    .subpass(0)
+                    .base_pipeline_handle(vk::Pipeline::null())
+                    .base_pipeline_index(0)
+                    .build(),
+            )],
+        )[0];
+
+        Self {
+            mode,
+            accumulation_layer,
+            composite_layer,
+            source_color_image,
+            fragment_lists,
+            point_sampler,
+            descriptor_pool,
+            accumulation_descriptor_set_layout,
+            accumulation_descriptor_set,
+            composite_descriptor_set_layout,
+            composite_descriptor_set,
+            vert_module,
+            frag_module,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        self.accumulation_layer.destroy(factory);
+        self.composite_layer.destroy(factory);
+        if let Some(fragment_lists) = &self.fragment_lists {
+            factory.deallocate_image(&fragment_lists.head_image);
+            factory.destroy_image_view(fragment_lists.head_image_view);
+            factory.deallocate_buffer(&fragment_lists.node_buffer);
+        }
+        factory.destroy_sampler(self.point_sampler);
+        factory.destroy_descriptor_pool(self.descriptor_pool);
+        factory.destroy_descriptor_set_layout(self.accumulation_descriptor_set_layout);
+        factory.destroy_descriptor_set_layout(self.composite_descriptor_set_layout);
+        factory.destroy_shader_module(self.vert_module);
+        factory.destroy_shader_module(self.frag_module);
+        factory.destroy_pipeline_layout(self.pipeline_layout);
+        factory.destroy_pipeline(self.pipeline);
+    }
+
+    pub fn get_mode(&self) -> TransparencyMode {
+        self.mode
+    }
+
+    // Material pipelines are created against this layer with these blend states
+    pub fn get_accumulation_layer(&self) -> &RenderLayer {
+        &self.accumulation_layer
+    }
+
+    pub fn get_accumulation_blend_attachments(&self) -> Vec<vk::PipelineColorBlendAttachmentState> {
+        if self.fragment_lists.is_some() {
+            return Vec::new();
+        }
+
+        // Weighted color is added up, revealage is multiplied by one minus alpha of every layer
+        vec![
+            vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::ONE)
+                .dst_color_blend_factor(vk::BlendFactor::ONE)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                .alpha_blend_op(vk::BlendOp::ADD)
+                .color_write_mask(
+                    vk::ColorComponentFlags::R
+                        | vk::ColorComponentFlags::G
+                        | vk::ColorComponentFlags::B
+                        | vk::ColorComponentFlags::A,
+                )
+                .build(),
+            vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::ZERO)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_COLOR)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .alpha_blend_op(vk::BlendOp::ADD)
+                .color_write_mask(vk::ColorComponentFlags::R)
+                .build(),
+        ]
+    }
+
+    pub fn get_accumulation_descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.accumulation_descriptor_set_layout
+    }
+
+    pub fn get_accumulation_descriptor_set(&self) -> vk::DescriptorSet {
+        self.accumulation_descriptor_set
+    }
+
+    // Signals when the source color image contains transparent geometry
+    pub fn get_composite_layer(&self) -> &RenderLayer {
+        &self.composite_layer
+    }
+
+    // Transparent geometry is recorded into the returned command buffer until resolve() is called,
+    // source depth is expected to be in SHADER_READ_ONLY_OPTIMAL layout
+    pub fn begin_accumulation(
+        &mut self,
+        dependency_layer: &RenderLayer,
+        screen_area: vk::Rect2D,
+        frame_context: &FrameContext,
+        device: &mut Device,
+        factory: &mut DeviceFactory,
+    ) -> &mut CommandBuffer {
+        puffin::profile_function!();
+
+        self.accumulation_layer.add_dependency(
+            frame_context,
+            dependency_layer,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        );
+        self.accumulation_layer.acquire_frame(frame_context, device, factory);
+        if let Some(fragment_lists) = &self.fragment_lists {
+            // All lists are emptied and the node counter is reset every frame
+            let command_buffer = self.accumulation_layer.get_command_buffer(frame_context);
+            let subresource_range = vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(0)
+                .level_count(1)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build();
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                None,
+                &[],
+                &[],
+                &[vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::GENERAL)
+                    .src_queue_family_index(!0)
+                    .dst_queue_family_index(!0)
+                    .image(fragment_lists.head_image.0)
+                    .subresource_range(subresource_range)
+                    .build()],
+            );
+            command_buffer.clear_color_image(
+                fragment_lists.head_image.0,
+                vk::ImageLayout::GENERAL,
+                &vk::ClearColorValue { uint32: [!0; 4] },
+                &[subresource_range],
+            );
+            command_buffer.fill_buffer(fragment_lists.node_buffer.0, 0, FRAGMENT_NODE_HEADER_SIZE, 0);
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                None,
+                &[vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                    .build()],
+                &[],
+                &[],
+            );
+        }
+
+        self.accumulation_layer.begin_render_pass(frame_context, screen_area);
+        self.accumulation_layer.get_command_buffer(frame_context)
+    }
+
+    pub fn resolve(
+        &mut self,
+        source_layer: &RenderLayer,
+        screen_area: vk::Rect2D,
+        frame_context: &FrameContext,
+        device: &mut Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) {
+        puffin::profile_function!();
+
+        let color_subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        self.accumulation_layer.end_render_pass(frame_context);
+        if self.fragment_lists.is_some() {
+            let command_buffer = self.accumulation_layer.get_command_buffer(frame_context);
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                None,
+                &[vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .build()],
+                &[],
+                &[],
+            );
+        } else {
+            let image_barriers: Vec<vk::ImageMemoryBarrier> = (0..self.accumulation_layer.get_render_image_count())
+                .map(|image_id| {
+                    vk::ImageMemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                        .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                        .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .src_queue_family_index(!0)
+                        .dst_queue_family_index(!0)
+                        .image(self.accumulation_layer.get_render_image(image_id).0)
+                        .subresource_range(color_subresource_range)
+                        .build()
+                })
+                .collect();
+            let command_buffer = self.accumulation_layer.get_command_buffer(frame_context);
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                None,
+                &[],
+                &[],
+                &image_barriers,
+            );
+        }
+        self.accumulation_layer.submit_commands(frame_context, queue);
+
+        self.composite_layer.add_dependency(
+            frame_context,
+            &self.accumulation_layer,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+        );
+        self.composite_layer.acquire_frame(frame_context, device, factory);
+        self.composite_layer.begin_render_pass(frame_context, screen_area);
+        {
+            let command_buffer = self.composite_layer.get_command_buffer(frame_context);
+            command_buffer.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            command_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.composite_descriptor_set],
+                &[],
+            );
+            command_buffer.draw(3, 1, 0, 0);
+        }
+        self.composite_layer.end_render_pass(frame_context);
+
+        let command_buffer = self.composite_layer.get_command_buffer(frame_context);
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[],
+            &[],
+            &[vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(source_layer.get_render_image(self.source_color_image).0)
+                .subresource_range(color_subresource_range)
+                .build()],
+        );
+        self.composite_layer.submit_commands(frame_context, queue);
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+
+pub struct OverdrawHeatmap {
+    overdraw_layer: RenderLayer,
+
+    point_sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_set: vk::DescriptorSet,
+
+    vert_module: vk::ShaderModule,
+    frag_module: vk::ShaderModule,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl OverdrawHeatmap {
+    pub fn new(
+        common_shaders: &DiskCommonShaders,
+        target_layer: &RenderLayer,
+        render_width: u32,
+        render_height: u32,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        let overdraw_layer = RenderLayer::new(
+            device,
+            factory,
+            render_width,
+            render_height,
+            &RenderLayerParameters {
+                render_image_parameters: &[RenderImageParameters {
+                    image_format: vk::Format::R16_SFLOAT,
+                    image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                    image_clear_value: vk::ClearValue::default(),
+                }],
+                depth_image_parameters: None,
+                render_pass_parameters: &[RenderPassParameters {
+                    flags: vk::SubpassDescriptionFlags::default(),
+                    pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+                    input_attachments: None,
+                    color_attachments: Some(&[vk::AttachmentReference::builder()
+                        .attachment(0)
+                        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .build()]),
+                    resolve_attachments: None,
+                    depth_stencil_attachment: None,
+                    preserve_attachments: None,
+                }],
+                render_pass_dependencies: None,
+                view_mask: 0,
+            },
+        );
+
+        let point_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build(),
+        );
+
+        let descriptor_pool = factory.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(1)
+                .pool_sizes(&[vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .build()]),
+        );
+        let descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()]),
+        );
+        let descriptor_set = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&[descriptor_set_layout])
+                .build(),
+        )[0];
+        factory.update_descriptor_sets(
+            &[vk::WriteDescriptorSet::builder()
+                .dst_set(descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&[vk::DescriptorImageInfo::builder()
+                    .sampler(point_sampler)
+                    .image_view(overdraw_layer.get_render_image(0).1)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .build()])
+                .build()],
+            &[],
+        );
+
+        let vert_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.overdraw_heatmap_vertex_stage)
+                .build(),
+        );
+        let frag_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.overdraw_heatmap_fragment_stage)
+                .build(),
+        );
+
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[descriptor_set_layout])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .offset(0)
+                    .size(16)
+                    .build()])
+                .build(),
+        );
+
+        // Heatmap is blended over the final image after tone mapping
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let vertex_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(vert_module)
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let fragment_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(frag_module)
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let pipeline = factory.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[target_layer.make_pipeline_create_info(
+                vk::GraphicsPipelineCreateInfo::builder()
+                    .stages(&[vertex_stage, fragment_stage])
+                    .vertex_input_state(
+                        &vk::PipelineVertexInputStateCreateInfo::builder()
+                            .vertex_binding_descriptions(&[])
+                            .build(),
+                    )
+                    .input_assembly_state(
+                        &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                            .primitive_restart_enable(false)
+                            .build(),
+                    )
+                    .tessellation_state(&Default::default())
+                    .viewport_state(
+                        &vk::PipelineViewportStateCreateInfo::builder()
+                            .viewport_count(1)
+                            .scissor_count(1)
+                            .build(),
+                    )
+                    .rasterization_state(
+                        &vk::PipelineRasterizationStateCreateInfo::builder()
+                            .cull_mode(vk::CullModeFlags::NONE)
+                            .line_width(1.0)
+                            .build(),
+                    )
+                    .multisample_state(
+                        &vk::PipelineMultisampleStateCreateInfo::builder()
+                            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                            .build(),
+                    )
+                    .depth_stencil_state(&Default::default())
+                    .color_blend_state(
+                        &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                            vk::PipelineColorBlendAttachmentState::builder()
+                                .blend_enable(true)
+                                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                                .color_blend_op(vk::BlendOp::ADD)
+                                .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+                                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                                .alpha_blend_op(vk::BlendOp::ADD)
+                                .color_write_mask(
+                                    vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B
+                                        | vk::ColorComponentFlags::A,
+                                )
+                                .build(),
+                        ]),
+                    )
+                    .dynamic_state(
+                        &vk::PipelineDynamicStateCreateInfo::builder()
+                            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                            .build(),
+                    )
+                    .layout(pipeline_layout)
+                    .subpass(0)
+                    .base_pipeline_handle(vk::Pipeline::null())
+                    .base_pipeline_index(0)
+                    .build(),
+            )],
+        )[0];
+
+        Self {
+            overdraw_layer,
+            point_sampler,
+            descriptor_pool,
+            descriptor_set_layout,
+            descriptor_set,
+            vert_module,
+            frag_module,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        self.overdraw_layer.destroy(factory);
+        factory.destroy_sampler(self.point_sampler);
+        factory.destroy_descriptor_pool(self.descriptor_pool);
+        factory.destroy_descriptor_set_layout(self.descriptor_set_layout);
+        factory.destroy_shader_module(self.vert_module);
+        factory.destroy_shader_module(self.frag_module);
+        factory.destroy_pipeline_layout(self.pipeline_layout);
+        factory.destroy_pipeline(self.pipeline);
+    }
+
+    // Material pipelines are created against this layer with these blend states
+    pub fn get_overdraw_layer(&self) -> &RenderLayer {
+        &self.overdraw_layer
+    }
+
+    pub fn get_overdraw_blend_attachments(&self) -> [vk::PipelineColorBlendAttachmentState; 1] {
+        [vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .color_write_mask(vk::ColorComponentFlags::R)
+            .build()]
+    }
+
+    // Scene geometry is recorded into the returned command buffer until end_counting() is called
+    pub fn begin_counting(
+        &mut self,
+        dependency_layer: &RenderLayer,
+        screen_area: vk::Rect2D,
+        frame_context: &FrameContext,
+        device: &mut Device,
+        factory: &mut DeviceFactory,
+    ) -> &mut CommandBuffer {
+        puffin::profile_function!();
+
+        self.overdraw_layer
+            .add_dependency(frame_context, dependency_layer, vk::PipelineStageFlags::VERTEX_SHADER);
+        self.overdraw_layer.acquire_frame(frame_context, device, factory);
+        self.overdraw_layer.begin_render_pass(frame_context, screen_area);
+        self.overdraw_layer.get_command_buffer(frame_context)
+    }
+
+    pub fn end_counting(&mut self, frame_context: &FrameContext, queue: &mut DeviceQueue) {
+        puffin::profile_function!();
+
+        self.overdraw_layer.end_render_pass(frame_context);
+        let overdraw_image = self.overdraw_layer.get_render_image(0).0;
+        let command_buffer = self.overdraw_layer.get_command_buffer(frame_context);
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[],
+            &[],
+            &[vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(overdraw_image)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(1)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build()],
+        );
+        self.overdraw_layer.submit_commands(frame_context, queue);
+    }
+
+    // Output area is the whole viewport, render scale maps it to the counted area
+    pub fn render(
+        &mut self,
+        output_area: vk::Rect2D,
+        render_scale: f32,
+        opacity: f32,
+        frame_context: &FrameContext,
+        target_layer: &mut RenderLayer,
+    ) {
+        let command_buffer = target_layer.get_command_buffer(frame_context);
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &[self.descriptor_set],
+            &[],
+        );
+        command_buffer.set_viewport(
+            0,
+            &[vk::Viewport {
+                x: output_area.offset.x as _,
+                y: output_area.offset.y as _,
+                width: output_area.extent.width as _,
+                height: output_area.extent.height as _,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+        );
+        command_buffer.set_scissor(0, &[output_area]);
+        command_buffer.push_constants(
+            self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &[
+                output_area.offset.x as f32,
+                output_area.offset.y as f32,
+                render_scale,
+                opacity,
+            ],
+        );
+        command_buffer.draw(3, 1, 0, 0);
+    }
+}
@@ -0,0 +1,1370 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::camera::*;
+use crate::common_shaders::*;
+use crate::instance_transform_update::*;
+use crate::light_clustering::*;
+use crate::pbr_resource_bundle::*;
+use crate::shared_frame_data::*;
+use crate::tone_map::*;
+
+const MAX_PATH_BOUNCES: u32 = 4;
+const INSTANCE_GROUP_SIZE: u32 = 64;
+const GEOMETRY_INSTANCE_SIZE: u64 = 64; // VkGeometryInstanceNV
+
+// Raygen, miss, shadow miss, hit and shadow hit groups
+const SHADER_GROUP_COUNT: u32 = 5;
+
+// Mesh vertices written every frame by another pass, e.g. skinning. Uses the vertex layout of the source mesh
+// starting with its first vertex and needs STORAGE_BUFFER and RAY_TRACING_NV usage.
+#[derive(Copy, Clone, PartialEq)]
+pub struct DeformedVertexBuffer {
+    pub buffer: vk::Buffer,
+    pub vertex_count: u32,
+}
+
+struct PathTracerBottomLevel {
+    acceleration_structure: vk::AccelerationStructureNV,
+    memory: HeapAllocatedMemory,
+    geometry: vk::GeometryNV,
+    flags: vk::BuildAccelerationStructureFlagsNV,
+    handle: u64,
+}
+
+// Transforms of one render instance become consecutive top level instances
+struct PathTracerInstanceBatch {
+    instance_set: usize,
+    bottom_level: usize,
+    first_transform: u32,
+    first_instance: u32,
+    instance_count: u32,
+    geometry_index: u32,
+    instance_flags: vk::GeometryInstanceFlagsNV,
+}
+
+struct PathTracerScene {
+    bottom_levels: Vec<PathTracerBottomLevel>,
+    bottom_levels_built: bool,
+    has_deformed_geometry: bool,
+    top_level: vk::AccelerationStructureNV,
+    top_level_memory: HeapAllocatedMemory,
+    instance_count: u32,
+    instance_batches: Vec<PathTracerInstanceBatch>,
+
+    instance_buffer: HeapAllocatedResource<vk::Buffer>,
+    geometry_buffer: HeapAllocatedResource<vk::Buffer>,
+    scratch_buffer: HeapAllocatedResource<vk::Buffer>,
+
+    descriptor_pool: vk::DescriptorPool,
+    buffer_set_layout: vk::DescriptorSetLayout,
+    texture_set_layout: vk::DescriptorSetLayout,
+    frame_sets: FrameLocal<vk::DescriptorSet>,
+    buffer_set: vk::DescriptorSet,
+    texture_set: vk::DescriptorSet,
+    instance_sets: Vec<vk::DescriptorSet>, // one per instance transform buffer
+
+    // Scene buffer and texture counts are part of the pipeline layout
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    shader_binding_table: ShaderBindingTable,
+}
+
+impl PathTracerScene {
+    fn destroy(&mut self, factory: &mut DeviceFactory) {
+        for bottom_level in &self.bottom_levels {
+            factory.destroy_acceleration_structure_nv(bottom_level.acceleration_structure);
+            factory.deallocate_heap_memory(&bottom_level.memory);
+        }
+        factory.destroy_acceleration_structure_nv(self.top_level);
+        factory.deallocate_heap_memory(&self.top_level_memory);
+        factory.deallocate_buffer(&self.instance_buffer);
+        factory.deallocate_buffer(&self.geometry_buffer);
+        factory.deallocate_buffer(&self.scratch_buffer);
+        factory.destroy_descriptor_pool(self.descriptor_pool);
+        factory.destroy_descriptor_set_layout(self.buffer_set_layout);
+        factory.destroy_descriptor_set_layout(self.texture_set_layout);
+        factory.destroy_pipeline_layout(self.pipeline_layout);
+        factory.destroy_pipeline(self.pipeline);
+        self.shader_binding_table.destroy(factory);
+    }
+}
+
+// Scene inputs of a frame, changes of the camera or the lights restart accumulation
+pub struct PathTracerRenderParameters<'a> {
+    pub camera: &'a Camera,
+    pub resource_bundles: &'a [&'a ResourceBundle],
+    pub pbr_resource_bundle: &'a PbrResourceBundle,
+    pub punctual_lights: &'a [PunctualLight],
+}
+
+// Progressive reference renderer, samples are accumulated until the camera, lights or the scene change
+pub struct PathTracer {
+    accumulation_layer: RenderLayer,
+    tone_map: ToneMap,
+
+    light_buffer: FrameLocal<HeapAllocatedResource<vk::Buffer>>,
+    frame_set_layout: vk::DescriptorSetLayout,
+    instance_set_layout: vk::DescriptorSetLayout,
+
+    instance_module: vk::ShaderModule,
+    instance_pipeline_layout: vk::PipelineLayout,
+    instance_pipeline: vk::Pipeline,
+
+    ray_gen_module: vk::ShaderModule,
+    closest_hit_module: vk::ShaderModule,
+    any_hit_module: vk::ShaderModule,
+    miss_module: vk::ShaderModule,
+    shadow_miss_module: vk::ShaderModule,
+    ray_tracing_properties: vk::PhysicalDeviceRayTracingPropertiesNV,
+
+    scene: Option<PathTracerScene>,
+    scene_dirty: bool,
+    // Keyed by the source vertex buffer and vertex offset of the mesh, bundle indices change on removal
+    deformed_vertex_buffers: std::collections::HashMap<(vk::Buffer, usize), DeformedVertexBuffer>,
+    sample_count: u32,
+    accumulated_view: ([f32; 16], [i32; 4]), // view projection and viewport of the accumulated samples
+    accumulated_lights: Vec<PunctualLight>,
+}
+
+impl PathTracer {
+    pub fn new(
+        common_shaders: &DiskCommonShaders,
+        shared_frame_data: &SharedFrameData,
+        target_layer: &RenderLayer,
+        render_width: u32,
+        render_height: u32,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        // Samples are averaged in full precision, the render pass is only there to satisfy the layer
+        let accumulation_layer = RenderLayer::new(
+            device,
+            factory,
+            render_width,
+            render_height,
+            &RenderLayerParameters {
+                render_image_parameters: &[RenderImageParameters {
+                    image_format: vk::Format::R32G32B32A32_SFLOAT,
+                    image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                        | vk::ImageUsageFlags::SAMPLED
+                        | vk::ImageUsageFlags::STORAGE,
+                    image_clear_value: vk::ClearValue::default(),
+                }],
+                depth_image_parameters: None,
+                render_pass_parameters: &[RenderPassParameters {
+                    flags: vk::SubpassDescriptionFlags::default(),
+                    pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+                    input_attachments: None,
+                    color_attachments: Some(&[vk::AttachmentReference::builder()
+                        .attachment(0)
+                        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .build()]),
+                    resolve_attachments: None,
+                    depth_stencil_attachment: None,
+                    preserve_attachments: None,
+                }],
+                render_pass_dependencies: None,
+                view_mask: 0,
+            },
+        );
+        let tone_map = ToneMap::new(
+            common_shaders,
+            shared_frame_data,
+            &[&accumulation_layer],
+            0,
+            target_layer,
+            factory,
+        );
+
+        let light_buffer = FrameLocal::new(factory.get_num_buffered_frames(), |_| {
+            factory.allocate_buffer(
+                &vk::BufferCreateInfo::builder()
+                    .size((MAX_PUNCTUAL_LIGHTS * std::mem::size_of::<PunctualLightData>()) as _)
+                    .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+                    .build(),
+                &vk_mem::AllocationCreateInfo {
+                    usage: vk_mem::MemoryUsage::CpuToGpu,
+                    ..Default::default()
+                },
+            )
+        });
+
+        let frame_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&[
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_NV)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::RAYGEN_NV)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::RAYGEN_NV)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(2)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::CLOSEST_HIT_NV | vk::ShaderStageFlags::ANY_HIT_NV)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(3)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::RAYGEN_NV)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(4)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::MISS_NV)
+                        .build(),
+                ])
+                .build(),
+        );
+        let instance_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(&[
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                ])
+                .build(),
+        );
+
+        let instance_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.path_tracer_instance_compute_stage)
+                .build(),
+        );
+        let instance_pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[instance_set_layout])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .offset(0)
+                    .size(std::mem::size_of::<[u32; 8]>() as _)
+                    .build()])
+                .build(),
+        );
+
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let instance_pipeline = factory.create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::ComputePipelineCreateInfo::builder()
+                .stage(
+                    vk::PipelineShaderStageCreateInfo::builder()
+                        .name(&entry_name)
+                        .module(instance_module)
+                        .stage(vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                )
+                .layout(instance_pipeline_layout)
+                .build()],
+        )[0];
+
+        let mut create_module =
+            |code: &[u32]| factory.create_shader_module(&vk::ShaderModuleCreateInfo::builder().code(code).build());
+        let ray_gen_module = create_module(&common_shaders.path_tracer_ray_gen_stage);
+        let closest_hit_module = create_module(&common_shaders.path_tracer_closest_hit_stage);
+        let any_hit_module = create_module(&common_shaders.path_tracer_any_hit_stage);
+        let miss_module = create_module(&common_shaders.path_tracer_miss_stage);
+        let shadow_miss_module = create_module(&common_shaders.path_tracer_shadow_miss_stage);
+
+        Self {
+            accumulation_layer,
+            tone_map,
+            light_buffer,
+            frame_set_layout,
+            instance_set_layout,
+            instance_module,
+            instance_pipeline_layout,
+            instance_pipeline,
+            ray_gen_module,
+            closest_hit_module,
+            any_hit_module,
+            miss_module,
+            shadow_miss_module,
+            ray_tracing_properties: device.get_ray_tracing_properties_nv(),
+            scene: None,
+            scene_dirty: true,
+            deformed_vertex_buffers: Default::default(),
+            sample_count: 0,
+            accumulated_view: Default::default(),
+            accumulated_lights: Vec::new(),
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        if let Some(scene) = &mut self.scene {
+            scene.destroy(factory);
+        }
+        self.accumulation_layer.destroy(factory);
+        self.tone_map.destroy(factory);
+        self.light_buffer.destroy(|buffer| factory.deallocate_buffer(buffer));
+        factory.destroy_descriptor_set_layout(self.frame_set_layout);
+        factory.destroy_descriptor_set_layout(self.instance_set_layout);
+        factory.destroy_shader_module(self.instance_module);
+        factory.destroy_pipeline_layout(self.instance_pipeline_layout);
+        factory.destroy_pipeline(self.instance_pipeline);
+        factory.destroy_shader_module(self.ray_gen_module);
+        factory.destroy_shader_module(self.closest_hit_module);
+        factory.destroy_shader_module(self.any_hit_module);
+        factory.destroy_shader_module(self.miss_module);
+        factory.destroy_shader_module(self.shadow_miss_module);
+    }
+
+    // Acceleration structures are rebuilt from the current bundles before the next frame
+    pub fn invalidate_scene(&mut self) {
+        self.scene_dirty = true;
+        self.sample_count = 0;
+    }
+
+    // Bottom level of the mesh is built from the deformed vertices and refit every frame, so that the traced
+    // geometry follows animated content. Shading attributes are read from the deformed vertices as well.
+    pub fn set_deformed_vertex_buffer(
+        &mut self,
+        resource_bundle: &ResourceBundle,
+        mesh: MeshHandle,
+        deformed_vertex_buffer: Option<DeformedVertexBuffer>,
+    ) {
+        let mesh = &resource_bundle.meshes[mesh.index()];
+        let key = (
+            resource_bundle.buffers[mesh.vertex_buffer.index()].0,
+            mesh.vertex_offset,
+        );
+        let previous = match deformed_vertex_buffer {
+            Some(deformed_vertex_buffer) => self.deformed_vertex_buffers.insert(key, deformed_vertex_buffer),
+            None => self.deformed_vertex_buffers.remove(&key),
+        };
+        if previous != deformed_vertex_buffer {
+            self.invalidate_scene();
+        }
+    }
+
+    // Has to be called before the bundle is destroyed, buffer handles may be reused afterwards
+    pub fn remove_deformed_vertex_buffers(&mut self, resource_bundle: &ResourceBundle) {
+        let vertex_buffers: Vec<vk::Buffer> = resource_bundle.buffers.iter().map(|buffer| buffer.0).collect();
+        self.deformed_vertex_buffers
+            .retain(|(vertex_buffer, _), _| !vertex_buffers.contains(vertex_buffer));
+    }
+
+    pub fn reset_accumulation(&mut self) {
+        self.sample_count = 0;
+    }
+
+    pub fn get_sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn get_accumulation_layer(&self) -> &RenderLayer {
+        &self.accumulation_layer
+    }
+
+    pub fn render(
+        &mut self,
+        parameters: &PathTracerRenderParameters,
+        instance_transform_update: &mut InstanceTransformUpdate,
+        frame_context: &FrameContext,
+        device: &mut Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) {
+        puffin::profile_function!();
+
+        let &PathTracerRenderParameters {
+            camera,
+            resource_bundles,
+            pbr_resource_bundle,
+            punctual_lights,
+        } = parameters;
+
+        if self.scene_dirty {
+            device.wait_idle();
+            if let Some(scene) = &mut self.scene {
+                scene.destroy(factory);
+            }
+            self.scene = Some(self.create_scene(resource_bundles, pbr_resource_bundle, factory));
+            self.scene_dirty = false;
+        }
+
+        let viewport = camera.get_viewport();
+        let (view_projection, _) = camera.calculate_view_projection([0.0, 0.0]);
+        let mut accumulated_view = (
+            [0.0; 16],
+            [viewport.x, viewport.y, viewport.width as _, viewport.height as _],
+        );
+        accumulated_view.0.copy_from_slice(view_projection.as_slice());
+        if accumulated_view != self.accumulated_view || punctual_lights != self.accumulated_lights.as_slice() {
+            self.accumulated_view = accumulated_view;
+            self.accumulated_lights = punctual_lights.to_vec();
+            self.sample_count = 0;
+        }
+
+        if !punctual_lights.is_empty() {
+            let light_data: Vec<PunctualLightData> = punctual_lights
+                .iter()
+                .map(|punctual_light| punctual_light.to_light_data())
+                .collect();
+            let light_buffer = self.light_buffer.get(frame_context);
+            let light_memory = factory.map_allocation_memory(light_buffer);
+            copy_to_mapped_memory(&light_data, light_memory);
+            factory.unmap_allocation_memory(light_buffer);
+        }
+
+        // Deformed geometry may change every frame, samples of the previous frames don't match it
+        let scene = self.scene.as_mut().unwrap();
+        if scene.has_deformed_geometry {
+            self.sample_count = 0;
+        }
+        let accumulation_image = self.accumulation_layer.get_render_image(0).0;
+        self.accumulation_layer.acquire_frame(frame_context, device, factory);
+        let command_buffer = self.accumulation_layer.get_command_buffer(frame_context);
+
+        // Previous frame may still be tracing against the instances and the top level
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::RAY_TRACING_SHADER_NV | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_NV,
+            None,
+            &[],
+            &[],
+            &[],
+        );
+        instance_transform_update.dispatch(command_buffer, frame_context, factory);
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build()],
+            &[],
+            &[],
+        );
+
+        // Bottom levels share the scratch buffer, so every build waits for the previous one
+        let acceleration_structure_barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(
+                vk::AccessFlags::ACCELERATION_STRUCTURE_READ_NV | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_NV,
+            )
+            .dst_access_mask(
+                vk::AccessFlags::ACCELERATION_STRUCTURE_READ_NV | vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_NV,
+            )
+            .build();
+        // Deformed bottom levels are refit in place after the initial build, vertices are written before the frame
+        let refit = scene.bottom_levels_built;
+        if refit && scene.has_deformed_geometry {
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::COMPUTE_SHADER
+                    | vk::PipelineStageFlags::VERTEX_SHADER
+                    | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_NV,
+                None,
+                &[vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_READ_NV)
+                    .build()],
+                &[],
+                &[],
+            );
+        }
+        for bottom_level in &scene.bottom_levels {
+            let deformed = bottom_level
+                .flags
+                .contains(vk::BuildAccelerationStructureFlagsNV::ALLOW_UPDATE);
+            if refit && !deformed {
+                continue;
+            }
+            let source = if refit {
+                bottom_level.acceleration_structure
+            } else {
+                vk::AccelerationStructureNV::null()
+            };
+            command_buffer.build_acceleration_structure_nv(
+                &vk::AccelerationStructureInfoNV::builder()
+                    .ty(vk::AccelerationStructureTypeNV::BOTTOM_LEVEL)
+                    .flags(bottom_level.flags)
+                    .geometries(std::slice::from_ref(&bottom_level.geometry))
+                    .build(),
+                vk::Buffer::null(),
+                0,
+                refit,
+                bottom_level.acceleration_structure,
+                source,
+                scene.scratch_buffer.0,
+                0,
+            );
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_NV,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_NV,
+                None,
+                &[acceleration_structure_barrier],
+                &[],
+                &[],
+            );
+        }
+        scene.bottom_levels_built = true;
+
+        // Top level is rebuilt every frame from the current instance transforms
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, self.instance_pipeline);
+        for batch in &scene.instance_batches {
+            let handle = scene.bottom_levels[batch.bottom_level].handle;
+            command_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                self.instance_pipeline_layout,
+                0,
+                &[scene.instance_sets[batch.instance_set]],
+                &[],
+            );
+            command_buffer.push_constants(
+                self.instance_pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &[
+                    handle as u32,
+                    (handle >> 32) as u32,
+                    batch.first_transform,
+                    batch.first_instance,
+                    batch.instance_count,
+                    batch.geometry_index,
+                    batch.instance_flags.as_raw(),
+                    0,
+                ],
+            );
+            command_buffer.dispatch(batch.instance_count.div_ceil(INSTANCE_GROUP_SIZE), 1, 1);
+        }
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_NV,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::ACCELERATION_STRUCTURE_READ_NV)
+                .build()],
+            &[],
+            &[],
+        );
+        command_buffer.build_acceleration_structure_nv(
+            &vk::AccelerationStructureInfoNV::builder()
+                .ty(vk::AccelerationStructureTypeNV::TOP_LEVEL)
+                .flags(vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE)
+                .instance_count(scene.instance_count)
+                .build(),
+            scene.instance_buffer.0,
+            0,
+            false,
+            scene.top_level,
+            vk::AccelerationStructureNV::null(),
+            scene.scratch_buffer.0,
+            0,
+        );
+
+        // Accumulated samples are only kept if nothing changed since the last frame
+        let old_layout = if self.sample_count == 0 {
+            vk::ImageLayout::UNDEFINED
+        } else {
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        };
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_NV | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::RAY_TRACING_SHADER_NV,
+            None,
+            &[acceleration_structure_barrier],
+            &[],
+            &[vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_READ)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                .old_layout(old_layout)
+                .new_layout(vk::ImageLayout::GENERAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(accumulation_image)
+                .subresource_range(subresource_range)
+                .build()],
+        );
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::RAY_TRACING_NV, scene.pipeline);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::RAY_TRACING_NV,
+            scene.pipeline_layout,
+            0,
+            &[
+                *scene.frame_sets.get(frame_context),
+                scene.buffer_set,
+                scene.texture_set,
+            ],
+            &[],
+        );
+        let camera_position = -camera.position;
+        let mut constants = PathTracerConstants::default();
+        constants
+            .inverse_view_projection
+            .copy_from_slice(view_projection.inversed().as_slice());
+        constants.camera_position = [camera_position.x, camera_position.y, camera_position.z, 1.0];
+        constants.viewport_offset = [viewport.x.max(0) as _, viewport.y.max(0) as _, 0, 0];
+        constants.sample_index_light_count_max_bounces =
+            [self.sample_count, punctual_lights.len() as _, MAX_PATH_BOUNCES, 0];
+        command_buffer.push_constants(scene.pipeline_layout, vk::ShaderStageFlags::RAYGEN_NV, 0, &[constants]);
+        scene
+            .shader_binding_table
+            .trace_rays(viewport.width, viewport.height, 1, command_buffer);
+
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::RAY_TRACING_SHADER_NV,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[],
+            &[],
+            &[vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::GENERAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(accumulation_image)
+                .subresource_range(subresource_range)
+                .build()],
+        );
+        self.accumulation_layer.submit_commands(frame_context, queue);
+        self.sample_count += 1;
+    }
+
+    // Samples were traced at full resolution into the top left corner of the viewport, the output is never dithered
+    pub fn post_process(
+        &mut self,
+        output_area: vk::Rect2D,
+        shared_frame_data: &SharedFrameData,
+        frame_context: &FrameContext,
+        target_layer: &mut RenderLayer,
+    ) {
+        self.tone_map
+            .render(output_area, 0, 1.0, shared_frame_data, frame_context, target_layer);
+    }
+
+    fn create_scene(
+        &self,
+        resource_bundles: &[&ResourceBundle],
+        pbr_resource_bundle: &PbrResourceBundle,
+        factory: &mut DeviceFactory,
+    ) -> PathTracerScene {
+        puffin::profile_function!();
+
+        let mut scene_buffers = Vec::new();
+        let mut scene_textures: Vec<(vk::ImageView, vk::Sampler)> = Vec::new();
+        let mut texture_ids = std::collections::HashMap::new();
+        let mut deformed_buffer_ids = std::collections::HashMap::new();
+        let mut bottom_levels = Vec::new();
+        let mut geometries = Vec::new();
+        let mut instance_batches = Vec::new();
+        let mut transform_buffers = Vec::new();
+        let mut instance_count = 0;
+
+        for resource_bundle in resource_bundles {
+            let first_buffer = scene_buffers.len() as u32;
+            scene_buffers.extend(resource_bundle.buffers.iter().map(|buffer| buffer.0));
+
+            let mut mesh_bottom_levels = std::collections::HashMap::new();
+            for bucket in &resource_bundle.buckets {
+                let material = &resource_bundle.materials[bucket.material.index()];
+                let find_attribute = |semantic: VertexSemantic| {
+                    material.vertex_format.iter().find(|attribute| {
+                        std::mem::discriminant(&attribute.attribute_semantic) == std::mem::discriminant(&semantic)
+                    })
+                };
+                let position = match find_attribute(VertexSemantic::Position) {
+                    Some(position) if position.attribute_format == vk::Format::R32G32B32_SFLOAT => position,
+                    _ => {
+                        log::warn!("path tracer skips a material without full precision positions");
+                        continue;
+                    }
+                };
+                let normal_offset = match find_attribute(VertexSemantic::Normal) {
+                    Some(normal) if normal.attribute_format == vk::Format::R32G32B32_SFLOAT => normal.attribute_offset,
+                    _ => !0,
+                };
+
+                // All textures are sampled with the UV channel of the first one
+                let uv = material.shader_image_mapping.first().and_then(|(_, uv_channel)| {
+                    let uv_channel = uv_channel.trim_start_matches("VS_");
+                    material
+                        .vertex_format
+                        .iter()
+                        .find(|attribute| attribute.attribute_name == uv_channel)
+                });
+                let (uv_offset, uv_format) = match uv.map(|uv| (uv.attribute_offset, uv.attribute_format)) {
+                    Some((offset, vk::Format::R32G32_SFLOAT)) => (offset, 0),
+                    Some((offset, vk::Format::R16G16_UNORM)) => (offset, 1),
+                    Some((offset, vk::Format::R8G8_UNORM)) => (offset, 2),
+                    _ => (!0, 0),
+                };
+
+                let instance_set = transform_buffers.len();
+                transform_buffers.push(resource_bundle.buffers[bucket.instance_transform_buffer.index()].0);
+
+                let mut first_transform = 0;
+                for instance in &bucket.instances {
+                    let transform_count = instance.total_instance_count as u32;
+                    first_transform += transform_count;
+
+                    let mesh = &resource_bundle.meshes[instance.mesh.index()];
+                    let index_size = match mesh.index_buffer.0 {
+                        vk::IndexType::UINT16 => 2,
+                        vk::IndexType::UINT32 => 4,
+                        _ => {
+                            log::warn!("path tracer skips a mesh with unsupported index type");
+                            continue;
+                        }
+                    };
+                    let vertex_buffer = resource_bundle.buffers[mesh.vertex_buffer.index()].0;
+                    let deformed_vertex_buffer = self.deformed_vertex_buffers.get(&(vertex_buffer, mesh.vertex_offset));
+                    let bottom_level = *mesh_bottom_levels.entry(instance.mesh).or_insert_with(|| {
+                        bottom_levels.push(create_bottom_level(
+                            resource_bundle,
+                            mesh,
+                            deformed_vertex_buffer,
+                            material.vertex_stride,
+                            position.attribute_offset,
+                            factory,
+                        ));
+                        bottom_levels.len() - 1
+                    });
+
+                    // Deformed vertices are traced and shaded instead of the source vertex buffer
+                    let (vertex_buffer_id, vertex_offset) = match deformed_vertex_buffer {
+                        Some(deformed_vertex_buffer) => {
+                            let buffer_id =
+                                *deformed_buffer_ids
+                                    .entry(deformed_vertex_buffer.buffer)
+                                    .or_insert_with(|| {
+                                        scene_buffers.push(deformed_vertex_buffer.buffer);
+                                        scene_buffers.len() as u32 - 1
+                                    });
+                            (buffer_id, 0)
+                        }
+                        None => (
+                            first_buffer + mesh.vertex_buffer.index() as u32,
+                            mesh.vertex_offset as u32,
+                        ),
+                    };
+
+                    let material_images = &resource_bundle.material_instance_images[instance.material_instance.index()];
+                    let mut get_texture = |slot_name: &str| {
+                        let image_id = material
+                            .shader_image_mapping
+                            .iter()
+                            .position(|(image_name, _)| image_name == slot_name);
+                        match image_id {
+                            Some(image_id) => {
+                                let (image, sampler) = material_images[image_id];
+                                let texture = (
+                                    resource_bundle.image_views[image.index()],
+                                    resource_bundle.samplers[sampler.index()],
+                                );
+                                *texture_ids.entry(texture).or_insert_with(|| {
+                                    scene_textures.push(texture);
+                                    scene_textures.len() as i32 - 1
+                                })
+                            }
+                            None => -1,
+                        }
+                    };
+                    let textures = [
+                        get_texture("BaseColorTexture"),
+                        get_texture("MetallicRoughnessTexture"),
+                        get_texture("EmissiveTexture"),
+                        -1,
+                    ];
+
+                    // Blended materials are traced as alpha tested
+                    let instance_data = &instance.material_instance_data;
+                    let mut metallic_roughness_alpha_cutoff =
+                        get_material_parameter(material, instance_data, "metallic_roughness_discard_unused")
+                            .unwrap_or([1.0, 1.0, 0.5, 0.0]);
+                    if material.fragment_alpha_blend {
+                        metallic_roughness_alpha_cutoff[2] = 0.5;
+                    }
+                    let alpha_test = material.fragment_alpha_test || material.fragment_alpha_blend;
+
+                    geometries.push(PathTracerGeometry {
+                        buffers_first_index_vertex_offset: [
+                            vertex_buffer_id,
+                            first_buffer + mesh.index_buffer.1.index() as u32,
+                            mesh.first_index as _,
+                            vertex_offset,
+                        ],
+                        stride_position_normal_uv: [
+                            material.vertex_stride,
+                            position.attribute_offset,
+                            normal_offset,
+                            uv_offset,
+                        ],
+                        uv_format_index_size_alpha_test: [uv_format, index_size, alpha_test as _, 0],
+                        textures,
+                        base_color_factor: get_material_parameter(material, instance_data, "base_color_factor")
+                            .unwrap_or([1.0; 4]),
+                        metallic_roughness_alpha_cutoff,
+                        emissive_factor: get_material_parameter(material, instance_data, "emissive_rgb_unused")
+                            .unwrap_or([0.0; 4]),
+                        atlas_scale_offset: get_material_parameter(material, instance_data, "atlas_scale_offset")
+                            .unwrap_or([1.0, 1.0, 0.0, 0.0]),
+                    });
+
+                    let opacity_flags = if alpha_test {
+                        vk::GeometryInstanceFlagsNV::FORCE_NO_OPAQUE
+                    } else {
+                        vk::GeometryInstanceFlagsNV::FORCE_OPAQUE
+                    };
+                    instance_batches.push(PathTracerInstanceBatch {
+                        instance_set,
+                        bottom_level,
+                        first_transform: first_transform - transform_count,
+                        first_instance: instance_count,
+                        instance_count: transform_count,
+                        geometry_index: geometries.len() as u32 - 1,
+                        instance_flags: vk::GeometryInstanceFlagsNV::TRIANGLE_CULL_DISABLE_NV | opacity_flags,
+                    });
+                    instance_count += transform_count;
+                }
+            }
+        }
+        let deformed_bottom_level_count = bottom_levels
+            .iter()
+            .filter(|bottom_level| {
+                bottom_level
+                    .flags
+                    .contains(vk::BuildAccelerationStructureFlagsNV::ALLOW_UPDATE)
+            })
+            .count();
+        log::info!(
+            "path tracer scene: {} bottom levels ({} deformed), {} instances, {} textures",
+            bottom_levels.len(),
+            deformed_bottom_level_count,
+            instance_count,
+            scene_textures.len()
+        );
+
+        let top_level_info = vk::AccelerationStructureInfoNV::builder()
+            .ty(vk::AccelerationStructureTypeNV::TOP_LEVEL)
+            .flags(vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE)
+            .instance_count(instance_count)
+            .build();
+        let top_level = factory
+            .create_acceleration_structure_nv(&vk::AccelerationStructureCreateInfoNV::builder().info(top_level_info));
+        let top_level_memory = allocate_acceleration_structure_memory(top_level, factory);
+
+        let build_scratch = vk::AccelerationStructureMemoryRequirementsTypeNV::BUILD_SCRATCH_NV;
+        let update_scratch = vk::AccelerationStructureMemoryRequirementsTypeNV::UPDATE_SCRATCH_NV;
+        let mut scratch_size = get_scratch_size(top_level, build_scratch, factory);
+        for bottom_level in &bottom_levels {
+            let acceleration_structure = bottom_level.acceleration_structure;
+            scratch_size = scratch_size.max(get_scratch_size(acceleration_structure, build_scratch, factory));
+            if bottom_level
+                .flags
+                .contains(vk::BuildAccelerationStructureFlagsNV::ALLOW_UPDATE)
+            {
+                scratch_size = scratch_size.max(get_scratch_size(acceleration_structure, update_scratch, factory));
+            }
+        }
+        let scratch_buffer = factory.allocate_buffer(
+            &vk::BufferCreateInfo::builder()
+                .size(scratch_size.max(1))
+                .usage(vk::BufferUsageFlags::RAY_TRACING_NV)
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                ..Default::default()
+            },
+        );
+        let instance_buffer = factory.allocate_buffer(
+            &vk::BufferCreateInfo::builder()
+                .size(instance_count.max(1) as u64 * GEOMETRY_INSTANCE_SIZE)
+                .usage(vk::BufferUsageFlags::RAY_TRACING_NV | vk::BufferUsageFlags::STORAGE_BUFFER)
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                ..Default::default()
+            },
+        );
+        let geometry_buffer = factory.allocate_buffer(
+            &vk::BufferCreateInfo::builder()
+                .size((geometries.len().max(1) * std::mem::size_of::<PathTracerGeometry>()) as _)
+                .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::CpuToGpu,
+                ..Default::default()
+            },
+        );
+        if !geometries.is_empty() {
+            let geometry_memory = factory.map_allocation_memory(&geometry_buffer);
+            copy_to_mapped_memory(&geometries, geometry_memory);
+            factory.unmap_allocation_memory(&geometry_buffer);
+        }
+
+        let num_buffered_frames = factory.get_num_buffered_frames();
+        let descriptor_pool = factory.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder()
+                .max_sets((num_buffered_frames + 2 + transform_buffers.len()) as _)
+                .pool_sizes(&[
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::ACCELERATION_STRUCTURE_NV)
+                        .descriptor_count(num_buffered_frames as _)
+                        .build(),
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::STORAGE_IMAGE)
+                        .descriptor_count(num_buffered_frames as _)
+                        .build(),
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(
+                            (num_buffered_frames * 2 + scene_buffers.len() + transform_buffers.len() * 2) as _,
+                        )
+                        .build(),
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count((num_buffered_frames + scene_textures.len()) as _)
+                        .build(),
+                ]),
+        );
+        let buffer_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(scene_buffers.len() as _)
+                .stage_flags(vk::ShaderStageFlags::CLOSEST_HIT_NV | vk::ShaderStageFlags::ANY_HIT_NV)
+                .build()]),
+        );
+        let texture_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(scene_textures.len() as _)
+                .stage_flags(vk::ShaderStageFlags::CLOSEST_HIT_NV | vk::ShaderStageFlags::ANY_HIT_NV)
+                .build()]),
+        );
+
+        let mut set_layouts = vec![self.frame_set_layout; num_buffered_frames];
+        set_layouts.push(buffer_set_layout);
+        set_layouts.push(texture_set_layout);
+        set_layouts.resize(set_layouts.len() + transform_buffers.len(), self.instance_set_layout);
+        let mut descriptor_sets = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&set_layouts)
+                .build(),
+        );
+        let instance_sets = descriptor_sets.split_off(num_buffered_frames + 2);
+        let texture_set = descriptor_sets.pop().unwrap();
+        let buffer_set = descriptor_sets.pop().unwrap();
+        let frame_sets = FrameLocal::new(num_buffered_frames, |frame| descriptor_sets[frame]);
+
+        let whole_buffer = |buffer: vk::Buffer| {
+            vk::DescriptorBufferInfo::builder()
+                .buffer(buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)
+                .build()
+        };
+        let top_levels = [top_level];
+        let mut top_level_writes: Vec<vk::WriteDescriptorSetAccelerationStructureNV> = (0..num_buffered_frames)
+            .map(|_| {
+                vk::WriteDescriptorSetAccelerationStructureNV::builder()
+                    .acceleration_structures(&top_levels)
+                    .build()
+            })
+            .collect();
+        let accumulation_infos = [vk::DescriptorImageInfo::builder()
+            .image_view(self.accumulation_layer.get_render_image(0).1)
+            .image_layout(vk::ImageLayout::GENERAL)
+            .build()];
+        let geometry_infos = [whole_buffer(geometry_buffer.0)];
+        let light_infos: Vec<[vk::DescriptorBufferInfo; 1]> = (0..num_buffered_frames)
+            .map(|frame| [whole_buffer(self.light_buffer.get_frame(frame).0)])
+            .collect();
+        let environment_infos = [vk::DescriptorImageInfo::builder()
+            .sampler(pbr_resource_bundle.linear_sampler)
+            .image_view(pbr_resource_bundle.get_probe_image_view())
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+        let scene_buffer_infos: Vec<vk::DescriptorBufferInfo> =
+            scene_buffers.iter().map(|buffer| whole_buffer(*buffer)).collect();
+        let scene_texture_infos: Vec<vk::DescriptorImageInfo> = scene_textures
+            .iter()
+            .map(|(image_view, sampler)| {
+                vk::DescriptorImageInfo::builder()
+                    .sampler(*sampler)
+                    .image_view(*image_view)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .build()
+            })
+            .collect();
+        let instance_infos: Vec<[vk::DescriptorBufferInfo; 2]> = transform_buffers
+            .iter()
+            .map(|transform_buffer| [whole_buffer(*transform_buffer), whole_buffer(instance_buffer.0)])
+            .collect();
+
+        let mut descriptor_writes = Vec::new();
+        for (frame, top_level_write) in top_level_writes.iter_mut().enumerate() {
+            let frame_set = *frame_sets.get_frame(frame);
+            // Acceleration structures are chained, the descriptor count has to be set explicitly
+            let mut write = vk::WriteDescriptorSet::builder()
+                .dst_set(frame_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_NV)
+                .push_next(top_level_write)
+                .build();
+            write.descriptor_count = 1;
+            descriptor_writes.push(write);
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(frame_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(&accumulation_infos)
+                    .build(),
+            );
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(frame_set)
+                    .dst_binding(2)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&geometry_infos)
+                    .build(),
+            );
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(frame_set)
+                    .dst_binding(3)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&light_infos[frame])
+                    .build(),
+            );
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(frame_set)
+                    .dst_binding(4)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&environment_infos)
+                    .build(),
+            );
+        }
+        if !scene_buffer_infos.is_empty() {
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(buffer_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(&scene_buffer_infos)
+                    .build(),
+            );
+        }
+        if !scene_texture_infos.is_empty() {
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(texture_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&scene_texture_infos)
+                    .build(),
+            );
+        }
+        for (instance_set, instance_info) in instance_sets.iter().zip(&instance_infos) {
+            descriptor_writes.push(
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*instance_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(instance_info)
+                    .build(),
+            );
+        }
+        factory.update_descriptor_sets(&descriptor_writes, &[]);
+
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[self.frame_set_layout, buffer_set_layout, texture_set_layout])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::RAYGEN_NV)
+                    .offset(0)
+                    .size(std::mem::size_of::<PathTracerConstants>() as _)
+                    .build()])
+                .build(),
+        );
+
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let make_stage = |module: vk::ShaderModule, stage: vk::ShaderStageFlags| {
+            vk::PipelineShaderStageCreateInfo::builder()
+                .name(&entry_name)
+                .module(module)
+                .stage(stage)
+                .build()
+        };
+        let stages = [
+            make_stage(self.ray_gen_module, vk::ShaderStageFlags::RAYGEN_NV),
+            make_stage(self.closest_hit_module, vk::ShaderStageFlags::CLOSEST_HIT_NV),
+            make_stage(self.any_hit_module, vk::ShaderStageFlags::ANY_HIT_NV),
+            make_stage(self.miss_module, vk::ShaderStageFlags::MISS_NV),
+            make_stage(self.shadow_miss_module, vk::ShaderStageFlags::MISS_NV),
+        ];
+        let make_group = |ty: vk::RayTracingShaderGroupTypeNV, general: u32, closest_hit: u32, any_hit: u32| {
+            vk::RayTracingShaderGroupCreateInfoNV::builder()
+                .ty(ty)
+                .general_shader(general)
+                .closest_hit_shader(closest_hit)
+                .any_hit_shader(any_hit)
+                .intersection_shader(vk::SHADER_UNUSED_NV)
+                .build()
+        };
+        let general_group = vk::RayTracingShaderGroupTypeNV::GENERAL_NV;
+        let hit_group = vk::RayTracingShaderGroupTypeNV::TRIANGLES_HIT_GROUP_NV;
+        let unused = vk::SHADER_UNUSED_NV;
+        let groups = [
+            make_group(general_group, 0, unused, unused),
+            make_group(general_group, 3, unused, unused),
+            make_group(general_group, 4, unused, unused),
+            make_group(hit_group, unused, 1, 2),
+            make_group(hit_group, unused, unused, 2),
+        ];
+        let pipeline = factory.create_ray_tracing_pipelines_nv(
+            vk::PipelineCache::null(),
+            &[vk::RayTracingPipelineCreateInfoNV::builder()
+                .stages(&stages)
+                .groups(&groups)
+                .max_recursion_depth(1)
+                .layout(pipeline_layout)
+                .build()],
+        )[0];
+
+        // Shadow rays use the second miss and hit records
+        let shader_binding_table = ShaderBindingTable::new(
+            &ShaderBindingTableParameters {
+                raygen_record: ShaderRecord { group: 0, data: &[] },
+                miss_records: &[
+                    ShaderRecord { group: 1, data: &[] },
+                    ShaderRecord { group: 2, data: &[] },
+                ],
+                hit_records: &[
+                    ShaderRecord { group: 3, data: &[] },
+                    ShaderRecord { group: 4, data: &[] },
+                ],
+                callable_records: &[],
+            },
+            pipeline,
+            SHADER_GROUP_COUNT,
+            &self.ray_tracing_properties,
+            factory,
+        );
+
+        PathTracerScene {
+            bottom_levels,
+            bottom_levels_built: false,
+            has_deformed_geometry: deformed_bottom_level_count > 0,
+            top_level,
+            top_level_memory,
+            instance_count,
+            instance_batches,
+            instance_buffer,
+            geometry_buffer,
+            scratch_buffer,
+            descriptor_pool,
+            buffer_set_layout,
+            texture_set_layout,
+            frame_sets,
+            buffer_set,
+            texture_set,
+            instance_sets,
+            pipeline_layout,
+            pipeline,
+            shader_binding_table,
+        }
+    }
+}
+
+// Vertex count is bounded by the size of the vertex buffer, meshes don't store it.
+// Deformed bottom levels prefer fast builds, they are refit every frame.
+fn create_bottom_level(
+    resource_bundle: &ResourceBundle,
+    mesh: &RenderMesh,
+    deformed_vertex_buffer: Option<&DeformedVertexBuffer>,
+    vertex_stride: u32,
+    position_offset: u32,
+    factory: &mut DeviceFactory,
+) -> PathTracerBottomLevel {
+    let index_buffer = &resource_bundle.buffers[mesh.index_buffer.1.index()];
+    let (vertex_data, vertex_offset, vertex_count, flags) = match deformed_vertex_buffer {
+        Some(deformed_vertex_buffer) => (
+            deformed_vertex_buffer.buffer,
+            0,
+            deformed_vertex_buffer.vertex_count,
+            vk::BuildAccelerationStructureFlagsNV::ALLOW_UPDATE
+                | vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_BUILD,
+        ),
+        None => {
+            let vertex_buffer = &resource_bundle.buffers[mesh.vertex_buffer.index()];
+            let vertex_offset = (mesh.vertex_offset * vertex_stride as usize) as u64;
+            (
+                vertex_buffer.0,
+                vertex_offset,
+                ((vertex_buffer.1.get_size() as u64 - vertex_offset) / vertex_stride as u64) as u32,
+                vk::BuildAccelerationStructureFlagsNV::PREFER_FAST_TRACE,
+            )
+        }
+    };
+    let index_size = if mesh.index_buffer.0 == vk::IndexType::UINT16 {
+        2
+    } else {
+        4
+    };
+
+    let geometry = vk::GeometryNV::builder()
+        .geometry_type(vk::GeometryTypeNV::TRIANGLES_NV)
+        .geometry(
+            vk::GeometryDataNV::builder()
+                .triangles(
+                    vk::GeometryTrianglesNV::builder()
+                        .vertex_data(vertex_data)
+                        .vertex_offset(vertex_offset + position_offset as u64)
+                        .vertex_count(vertex_count)
+                        .vertex_stride(vertex_stride as _)
+                        .vertex_format(vk::Format::R32G32B32_SFLOAT)
+                        .index_data(index_buffer.0)
+                        .index_offset((mesh.first_index * index_size) as _)
+                        .index_count(mesh.index_count as _)
+                        .index_type(mesh.index_buffer.0)
+                        .build(),
+                )
+                .build(),
+        )
+        .build();
+
+    let acceleration_structure = factory.create_acceleration_structure_nv(
+        &vk::AccelerationStructureCreateInfoNV::builder().info(
+            vk::AccelerationStructureInfoNV::builder()
+                .ty(vk::AccelerationStructureTypeNV::BOTTOM_LEVEL)
+                .flags(flags)
+                .geometries(std::slice::from_ref(&geometry))
+                .build(),
+        ),
+    );
+    let memory = allocate_acceleration_structure_memory(acceleration_structure, factory);
+    let handle = factory.get_acceleration_structure_handle_nv(acceleration_structure);
+
+    PathTracerBottomLevel {
+        acceleration_structure,
+        memory,
+        geometry,
+        flags,
+        handle,
+    }
+}
+
+fn allocate_acceleration_structure_memory(
+    acceleration_structure: vk::AccelerationStructureNV,
+    factory: &mut DeviceFactory,
+) -> HeapAllocatedMemory {
+    let requirements = factory.get_acceleration_structure_memory_requirements_nv(
+        &vk::AccelerationStructureMemoryRequirementsInfoNV::builder()
+            .ty(vk::AccelerationStructureMemoryRequirementsTypeNV::OBJECT_NV)
+            .acceleration_structure(acceleration_structure)
+            .build(),
+    );
+    let memory = factory.allocate_heap_memory(
+        &requirements.memory_requirements,
+        &vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            ..Default::default()
+        },
+    );
+    factory.bind_acceleration_structure_memory_nv(&[vk::BindAccelerationStructureMemoryInfoNV::builder()
+        .acceleration_structure(acceleration_structure)
+        .memory(memory.0.get_device_memory())
+        .memory_offset(memory.0.get_offset() as _)
+        .build()]);
+    memory
+}
+
+fn get_scratch_size(
+    acceleration_structure: vk::AccelerationStructureNV,
+    scratch_type: vk::AccelerationStructureMemoryRequirementsTypeNV,
+    factory: &mut DeviceFactory,
+) -> u64 {
+    factory
+        .get_acceleration_structure_memory_requirements_nv(
+            &vk::AccelerationStructureMemoryRequirementsInfoNV::builder()
+                .ty(scratch_type)
+                .acceleration_structure(acceleration_structure)
+                .build(),
+        )
+        .memory_requirements
+        .size
+}
+
+// Material parameters are vec4s in the order of `shader_parameters`
+pub(crate) fn get_material_parameter(
+    material: &RenderMaterial,
+    instance_data: &[u8; 64],
+    name: &str,
+) -> Option<[f32; 4]> {
+    let parameter_id = material
+        .shader_parameters
+        .iter()
+        .position(|parameter| parameter == name)?;
+    let parameter_data = instance_data.get(parameter_id * 16..(parameter_id + 1) * 16)?;
+    let mut parameter = [0.0; 4];
+    for (component, bytes) in parameter.iter_mut().zip(parameter_data.chunks_exact(4)) {
+        *component = f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    Some(parameter)
+}
+
+// Matches PC_PathTracer in path_tracer.glsl
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct PathTracerConstants {
+    inverse_view_projection: [f32; 16],
+    camera_position: [f32; 4],
+    viewport_offset: [u32; 4],
+    sample_index_light_count_max_bounces: [u32; 4],
+}
+
+// Matches Geometry struct in path_tracer.glsl
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct PathTracerGeometry {
+    buffers_first_index_vertex_offset: [u32; 4],
+    stride_position_normal_uv: [u32; 4],
+    uv_format_index_size_alpha_test: [u32; 4],
+    textures: [i32; 4],
+    base_color_factor: [f32; 4],
+    metallic_roughness_alpha_cutoff: [f32; 4],
+    emissive_factor: [f32; 4],
+    atlas_scale_offset: [f32; 4],
+}
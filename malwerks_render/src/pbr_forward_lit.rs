@@ -3,15 +3,42 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
 // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use malwerks_bundles::*;
 use malwerks_core::*;
+use malwerks_dds::*;
 use malwerks_vk::*;
 
 use crate::anti_aliasing::*;
 use crate::bundle_loader::*;
 use crate::camera::*;
+use crate::depth_view::*;
+use crate::frame_graph_dump::*;
+use crate::half_resolution_effect::*;
+use crate::half_resolution_pass::*;
+use crate::instance_transform_update::*;
+use crate::light_clustering::*;
+use crate::nan_detection::*;
+use crate::order_independent_transparency::*;
+use crate::overdraw_heatmap::*;
+use crate::path_tracer::*;
+use crate::pbr_resource_bundle::*;
+use crate::render_target_capture::*;
+use crate::screen_space_reflections::*;
+use crate::shader_clock_heatmap::*;
 use crate::shared_frame_data::*;
 use crate::sky_box::*;
+use crate::stereo_view::*;
 use crate::tone_map::*;
+use crate::upscaler::*;
+use crate::volumetric_fog::*;
+use crate::water_surface::*;
+
+const MIN_RESOLUTION_SCALE: f32 = 0.5;
+const RESOLUTION_SCALE_STEP: f32 = 0.05;
+
+// Order of the passes that use transient images, images of different passes share memory
+const TRANSPARENCY_PASS: u32 = 0;
+const HALF_RESOLUTION_PASS: u32 = 1;
 
 pub struct PbrForwardLitParameters<'a> {
     pub render_width: u32,
@@ -19,25 +46,156 @@ pub struct PbrForwardLitParameters<'a> {
     pub target_layer: Option<&'a RenderLayer>,
     pub bundle_loader: &'a BundleLoader,
     pub enable_anti_aliasing: bool,
+    pub transparency_mode: TransparencyMode,
+    pub hdr_color_format: HdrColorFormat,
+    pub num_recording_threads: usize, // forward pass is recorded on the render thread if zero
+}
+
+// Format of the HDR color target and everything derived from it (anti-aliasing history, water and SSR copies)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HdrColorFormat {
+    // Half the bandwidth, no alpha and no negative values, blue has one mantissa bit less than red and green
+    R11G11B10Float,
+    // Full half precision with alpha, signed values and NaNs are flushed by the tone mapper
+    R16G16B16A16Float,
+}
+
+impl HdrColorFormat {
+    pub fn get_vk_format(self) -> vk::Format {
+        match self {
+            HdrColorFormat::R11G11B10Float => vk::Format::B10G11R11_UFLOAT_PACK32,
+            HdrColorFormat::R16G16B16A16Float => vk::Format::R16G16B16A16_SFLOAT,
+        }
+    }
+
+    pub fn get_dxgi_format(self) -> u32 {
+        match self {
+            HdrColorFormat::R11G11B10Float => DXGI_FORMAT_R11G11B10_FLOAT,
+            HdrColorFormat::R16G16B16A16Float => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        }
+    }
+
+    pub fn get_pixel_size(self) -> usize {
+        match self {
+            HdrColorFormat::R11G11B10Float => std::mem::size_of::<u32>(),
+            HdrColorFormat::R16G16B16A16Float => 4 * std::mem::size_of::<u16>(),
+        }
+    }
+}
+
+// Files a render bundle was loaded from, enough to load it again on a new device
+#[derive(Debug, Clone)]
+pub struct RenderBundleFiles {
+    pub gltf_file: std::path::PathBuf,
+    pub bundle_file: std::path::PathBuf,
+    pub shader_file: std::path::PathBuf,
+}
+
+// Pipelines of one render bundle, optional ones are only created if their pass is available
+struct RenderBundlePipelines {
+    shader_module_bundle: ShaderModuleBundle,
+    pipeline_bundle: PipelineBundle,
+    transparent_pipeline_bundle: Option<PipelineBundle>,
+    overdraw_render_bundle: Option<(ShaderModuleBundle, PipelineBundle)>,
+    shader_clock_render_bundle: Option<(ShaderModuleBundle, PipelineBundle)>,
+    stereo_render_bundle: Option<(ShaderModuleBundle, PipelineBundle)>,
+}
+
+// Geometry submitted by the forward and transparent passes during the last rendered frame
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RenderStatistics {
+    pub draw_call_count: usize,
+    pub instance_count: usize,
+    pub triangle_count: usize,
+}
+
+impl std::ops::AddAssign for RenderStatistics {
+    fn add_assign(&mut self, other: Self) {
+        self.draw_call_count += other.draw_call_count;
+        self.instance_count += other.instance_count;
+        self.triangle_count += other.triangle_count;
+    }
 }
 
 pub struct PbrForwardLit {
     render_layer: RenderLayer,
+    render_size: (u32, u32),
+    hdr_color_format: HdrColorFormat,
     render_bundles: Vec<(String, ResourceBundleReference, ShaderModuleBundle, PipelineBundle)>,
+    render_bundle_files: Vec<RenderBundleFiles>, // maps to `render_bundles`
+    transparent_pipeline_bundles: Vec<PipelineBundle>, // maps to `render_bundles` if transparency is enabled
     pbr_resource_bundle: PbrResourceBundleReference,
 
     shared_frame_data: SharedFrameData,
     sky_box: SkyBox,
+    instance_transform_update: InstanceTransformUpdate,
+    light_clustering: LightClustering,
+    half_resolution_pass: HalfResolutionPass,
+    half_resolution_effects: Vec<Box<dyn HalfResolutionEffect>>,
+    volumetric_fog: VolumetricFog,
+    enable_volumetric_fog: bool,
+    screen_space_reflections: ScreenSpaceReflections,
+    enable_screen_space_reflections: bool,
+    water_surface: WaterSurface,
+    enable_water_surface: bool,
+    order_independent_transparency: Option<OrderIndependentTransparency>,
+    transient_images: TransientImagePool, // shared by passes that don't overlap
+    overdraw_heatmap: Option<OverdrawHeatmap>,
+    overdraw_render_bundles: Vec<(ShaderModuleBundle, PipelineBundle)>, // maps to `render_bundles` if the heatmap is available
+    overdraw_heatmap_opacity: Option<f32>,
+    shader_clock_heatmap: Option<ShaderClockHeatmap>,
+    shader_clock_render_bundles: Vec<(ShaderModuleBundle, PipelineBundle)>, // maps to `render_bundles` if the heatmap is available
+    shader_clock_heatmap_parameters: Option<ShaderClockHeatmapParameters>,
+    path_tracer: Option<PathTracer>,
+    reference_mode: bool,
+    depth_view: Option<DepthView>,
+    depth_view_parameters: Option<DepthViewParameters>,
+    nan_detection: Option<NanDetection>,
+    nan_detection_parameters: Option<NanDetectionParameters>,
+    stereo_view: Option<StereoView>,
+    stereo_render_bundles: Vec<(ShaderModuleBundle, PipelineBundle)>, // maps to `render_bundles` if multiview is available
+    stereo_eye_separation: Option<f32>,
+    parallel_recorder: Option<ParallelRecorder>,
 
-    anti_aliasing: Option<AntiAliasing>,
+    upscaler: Option<Box<dyn Upscaler>>,
     tone_map: Option<ToneMap>,
 
+    render_statistics: RenderStatistics,
+    timestamp_period: f32,
+    gpu_frame_time: f32,
+    resolution_scale: f32,
+    current_resolution_scale: f32,
+    adaptive_resolution_target: Option<f32>, // target frame time in milliseconds
+    paused: bool,
+
+    // Clock of material UV animations, the water surface keeps its own
+    animation_start_time: std::time::Instant,
+    animation_pause_time: Option<std::time::Instant>,
+    animation_time: Option<f32>, // replaces the wall clock
+
     debug_enable_anti_aliasing: bool,
+    debug_highlight_color_space_mistakes: bool,
 }
 
 impl PbrForwardLit {
     pub fn destroy(&mut self, factory: &mut DeviceFactory) {
-        for (_, _, shader_module_bundle, pipeline_bundle) in &mut self.render_bundles {
+        for (bundle_name, _, shader_module_bundle, pipeline_bundle) in &mut self.render_bundles {
+            unregister_loaded_bundle(bundle_name);
+            pipeline_bundle.destroy(factory);
+            shader_module_bundle.destroy(factory);
+        }
+        for pipeline_bundle in &mut self.transparent_pipeline_bundles {
+            pipeline_bundle.destroy(factory);
+        }
+        for (shader_module_bundle, pipeline_bundle) in &mut self.overdraw_render_bundles {
+            pipeline_bundle.destroy(factory);
+            shader_module_bundle.destroy(factory);
+        }
+        for (shader_module_bundle, pipeline_bundle) in &mut self.shader_clock_render_bundles {
+            pipeline_bundle.destroy(factory);
+            shader_module_bundle.destroy(factory);
+        }
+        for (shader_module_bundle, pipeline_bundle) in &mut self.stereo_render_bundles {
             pipeline_bundle.destroy(factory);
             shader_module_bundle.destroy(factory);
         }
@@ -45,56 +203,120 @@ impl PbrForwardLit {
         self.render_layer.destroy(factory);
         self.shared_frame_data.destroy(factory);
         self.sky_box.destroy(factory);
+        self.instance_transform_update.destroy(factory);
+        self.light_clustering.destroy(factory);
+        self.half_resolution_pass.destroy(factory);
+        for effect in &mut self.half_resolution_effects {
+            effect.destroy(factory);
+        }
+        self.volumetric_fog.destroy(factory);
+        self.screen_space_reflections.destroy(factory);
+        self.water_surface.destroy(factory);
+        if let Some(order_independent_transparency) = &mut self.order_independent_transparency {
+            order_independent_transparency.destroy(factory);
+        }
+        if let Some(overdraw_heatmap) = &mut self.overdraw_heatmap {
+            overdraw_heatmap.destroy(factory);
+        }
+        if let Some(shader_clock_heatmap) = &mut self.shader_clock_heatmap {
+            shader_clock_heatmap.destroy(factory);
+        }
+        if let Some(path_tracer) = &mut self.path_tracer {
+            path_tracer.destroy(factory);
+        }
+        if let Some(depth_view) = &mut self.depth_view {
+            depth_view.destroy(factory);
+        }
+        if let Some(nan_detection) = &mut self.nan_detection {
+            nan_detection.destroy(factory);
+        }
+        if let Some(stereo_view) = &mut self.stereo_view {
+            stereo_view.destroy(factory);
+        }
+        if let Some(parallel_recorder) = &mut self.parallel_recorder {
+            parallel_recorder.destroy(factory);
+        }
 
-        if let Some(anti_aliasing) = &mut self.anti_aliasing {
-            anti_aliasing.destroy(factory);
+        if let Some(upscaler) = &mut self.upscaler {
+            upscaler.destroy(factory);
         }
         if let Some(tone_map) = &mut self.tone_map {
             tone_map.destroy(factory);
         }
+        self.transient_images.destroy(factory);
     }
 
     pub fn new(parameters: &PbrForwardLitParameters, device: &Device, factory: &mut DeviceFactory) -> Self {
+        let hdr_format = parameters.hdr_color_format.get_vk_format();
         let render_layer = RenderLayer::new(
             device,
             factory,
             parameters.render_width,
             parameters.render_height,
             &RenderLayerParameters {
-                render_image_parameters: &[RenderImageParameters {
-                    image_format: vk::Format::B10G11R11_UFLOAT_PACK32,
-                    image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
-                    image_clear_value: vk::ClearValue::default(),
-                }],
+                render_image_parameters: &[
+                    RenderImageParameters {
+                        image_format: hdr_format,
+                        image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                            | vk::ImageUsageFlags::SAMPLED
+                            | vk::ImageUsageFlags::TRANSFER_SRC,
+                        image_clear_value: vk::ClearValue::default(),
+                    },
+                    // Normal and roughness
+                    RenderImageParameters {
+                        image_format: vk::Format::R16G16B16A16_SFLOAT,
+                        image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                        image_clear_value: vk::ClearValue::default(),
+                    },
+                    // Specular reflectance
+                    RenderImageParameters {
+                        image_format: vk::Format::R8G8B8A8_UNORM,
+                        image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                        image_clear_value: vk::ClearValue::default(),
+                    },
+                ],
                 depth_image_parameters: Some(RenderImageParameters {
                     image_format: vk::Format::D32_SFLOAT,
-                    image_usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                    image_usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                        | vk::ImageUsageFlags::SAMPLED
+                        | vk::ImageUsageFlags::TRANSFER_SRC,
                     image_clear_value: vk::ClearValue::default(),
                 }),
                 render_pass_parameters: &[RenderPassParameters {
                     flags: vk::SubpassDescriptionFlags::default(),
                     pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
                     input_attachments: None,
-                    color_attachments: Some(&[vk::AttachmentReference::builder()
-                        .attachment(0)
-                        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                        .build()]),
+                    color_attachments: Some(&[
+                        vk::AttachmentReference::builder()
+                            .attachment(0)
+                            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                            .build(),
+                        vk::AttachmentReference::builder()
+                            .attachment(1)
+                            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                            .build(),
+                        vk::AttachmentReference::builder()
+                            .attachment(2)
+                            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                            .build(),
+                    ]),
                     resolve_attachments: None,
                     depth_stencil_attachment: Some(
                         &vk::AttachmentReference::builder()
-                            .attachment(1)
+                            .attachment(3)
                             .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
                             .build(),
                     ),
                     preserve_attachments: None,
                 }],
                 render_pass_dependencies: None,
+                view_mask: 0,
             },
         );
         let render_bundles = Vec::new();
         let pbr_resource_bundle = parameters.bundle_loader.get_pbr_resource_bundle();
 
-        let shared_frame_data = SharedFrameData::new(factory);
+        let shared_frame_data = SharedFrameData::new(&pbr_resource_bundle.borrow(), factory);
         let sky_box = SkyBox::from_disk(
             parameters.bundle_loader.get_common_shaders(),
             &pbr_resource_bundle.borrow(),
@@ -102,58 +324,279 @@ impl PbrForwardLit {
             &render_layer,
             factory,
         );
+        let instance_transform_update =
+            InstanceTransformUpdate::new(parameters.bundle_loader.get_common_shaders(), factory);
+        let light_clustering = LightClustering::new(
+            parameters.bundle_loader.get_common_shaders(),
+            &shared_frame_data,
+            factory,
+        );
+        // Transparency accumulation and half resolution targets are only used within their own passes
+        let mut transient_image_parameters = HalfResolutionPass::get_transient_image_parameters(
+            parameters.render_width,
+            parameters.render_height,
+            HALF_RESOLUTION_PASS,
+        );
+        let first_transparency_image = transient_image_parameters.len();
+        transient_image_parameters.extend(OrderIndependentTransparency::get_transient_image_parameters(
+            parameters.transparency_mode,
+            parameters.render_width,
+            parameters.render_height,
+            TRANSPARENCY_PASS,
+        ));
+        let transient_images = TransientImagePool::new(&transient_image_parameters, device, factory);
+
+        let half_resolution_pass = HalfResolutionPass::new(
+            &HalfResolutionPassParameters {
+                source_layer: &render_layer,
+                source_color_image: 0,
+                source_color_format: hdr_format,
+                render_width: parameters.render_width,
+                render_height: parameters.render_height,
+                transient_images: &transient_images,
+                first_transient_image: 0,
+            },
+            parameters.bundle_loader.get_common_shaders(),
+            device,
+            factory,
+        );
+        let volumetric_fog = VolumetricFog::new(
+            parameters.bundle_loader.get_common_shaders(),
+            &shared_frame_data,
+            &pbr_resource_bundle.borrow(),
+            &render_layer,
+            half_resolution_pass.get_half_resolution_layer(),
+            factory,
+        );
+        let screen_space_reflections = ScreenSpaceReflections::new(
+            parameters.bundle_loader.get_common_shaders(),
+            &shared_frame_data,
+            &pbr_resource_bundle.borrow(),
+            &ScreenSpaceReflectionLayerParameters {
+                source_layer: &render_layer,
+                source_color_image: 0,
+                source_color_format: hdr_format,
+                normal_roughness_image: 1,
+                specular_reflectance_image: 2,
+                render_width: parameters.render_width,
+                render_height: parameters.render_height,
+            },
+            device,
+            factory,
+        );
+        let water_surface = WaterSurface::new(
+            parameters.bundle_loader.get_common_shaders(),
+            &shared_frame_data,
+            &pbr_resource_bundle.borrow(),
+            &WaterSurfaceLayerParameters {
+                source_layer: &render_layer,
+                source_color_image: 0,
+                source_color_format: hdr_format,
+                render_width: parameters.render_width,
+                render_height: parameters.render_height,
+            },
+            device,
+            factory,
+        );
+
+        let order_independent_transparency = if parameters.transparency_mode != TransparencyMode::Disabled {
+            Some(OrderIndependentTransparency::new(
+                &OrderIndependentTransparencyParameters {
+                    mode: parameters.transparency_mode,
+                    source_layer: &render_layer,
+                    source_color_image: 0,
+                    source_color_format: hdr_format,
+                    render_width: parameters.render_width,
+                    render_height: parameters.render_height,
+                    transient_images: &transient_images,
+                    first_transient_image: first_transparency_image,
+                },
+                parameters.bundle_loader.get_common_shaders(),
+                device,
+                factory,
+            ))
+        } else {
+            None
+        };
+
+        let parallel_recorder = if parameters.num_recording_threads > 0 {
+            Some(ParallelRecorder::new(parameters.num_recording_threads, device, factory))
+        } else {
+            None
+        };
+
+        // Heatmap is blended over the final image, it is only available when there is a target layer
+        let overdraw_heatmap = parameters.target_layer.map(|target_layer| {
+            OverdrawHeatmap::new(
+                parameters.bundle_loader.get_common_shaders(),
+                target_layer,
+                parameters.render_width,
+                parameters.render_height,
+                device,
+                factory,
+            )
+        });
+
+        // Fragment cost is measured with the subgroup clock, the heatmap replaces the final image
+        let shader_clock_heatmap = match parameters.target_layer {
+            Some(target_layer) if device.is_shader_clock_enabled() => Some(ShaderClockHeatmap::new(
+                parameters.bundle_loader.get_common_shaders(),
+                target_layer,
+                parameters.render_width,
+                parameters.render_height,
+                device,
+                factory,
+            )),
+            _ => None,
+        };
+
+        // Reference path tracer replaces the whole frame, it needs ray tracing and a target layer to present
+        let path_tracer = match parameters.target_layer {
+            Some(target_layer) if device.get_device_options().enable_ray_tracing_nv => Some(PathTracer::new(
+                parameters.bundle_loader.get_common_shaders(),
+                &shared_frame_data,
+                target_layer,
+                parameters.render_width,
+                parameters.render_height,
+                device,
+                factory,
+            )),
+            _ => None,
+        };
+
+        let depth_view = parameters.target_layer.map(|target_layer| {
+            DepthView::new(
+                parameters.bundle_loader.get_common_shaders(),
+                target_layer,
+                render_layer.get_depth_image().unwrap().1,
+                screen_space_reflections.get_hi_z_image_view(),
+                factory,
+            )
+        });
+
+        // Scanning every pixel and instance transform is only affordable in debug builds
+        let nan_detection = match parameters.target_layer {
+            Some(target_layer) if cfg!(debug_assertions) => Some(NanDetection::new(
+                parameters.bundle_loader.get_common_shaders(),
+                &render_layer,
+                target_layer,
+                parameters.render_width,
+                parameters.render_height,
+                factory,
+            )),
+            _ => None,
+        };
+
+        let stereo_view = if device.is_multiview_enabled() {
+            Some(StereoView::new(
+                parameters.render_width,
+                parameters.render_height,
+                hdr_format,
+                device,
+                factory,
+            ))
+        } else {
+            None
+        };
 
-        let anti_aliasing = if parameters.enable_anti_aliasing {
-            Some(AntiAliasing::new(
+        let upscaler: Option<Box<dyn Upscaler>> = if parameters.enable_anti_aliasing {
+            Some(Box::new(AntiAliasing::new(
                 parameters.bundle_loader.get_common_shaders(),
                 &shared_frame_data,
                 &render_layer,
                 0,
-                vk::Format::B10G11R11_UFLOAT_PACK32,
+                hdr_format,
                 parameters.render_width,
                 parameters.render_height,
                 device,
                 factory,
-            ))
+            )))
         } else {
             None
         };
 
+        // Output is dithered by default to hide banding of 8 bit targets
         let tone_map = if let Some(target_layer) = parameters.target_layer {
-            if let Some(anti_aliasing) = &anti_aliasing {
-                Some(ToneMap::new(
+            let mut tone_map = if let Some(upscaler) = &upscaler {
+                ToneMap::new(
                     parameters.bundle_loader.get_common_shaders(),
-                    &[
-                        anti_aliasing.get_current_render_layer(),
-                        anti_aliasing.get_previous_render_layer(),
-                    ],
+                    &shared_frame_data,
+                    &upscaler.get_output_layers(),
                     0,
                     target_layer,
                     factory,
-                ))
+                )
             } else {
-                Some(ToneMap::new(
+                ToneMap::new(
                     parameters.bundle_loader.get_common_shaders(),
+                    &shared_frame_data,
                     &[&render_layer],
                     0,
                     target_layer,
                     factory,
-                ))
-            }
+                )
+            };
+            tone_map.set_dithering(Some(&DitheringParameters::default()));
+            Some(tone_map)
         } else {
             None
         };
 
         Self {
             render_layer,
+            render_size: (parameters.render_width, parameters.render_height),
+            hdr_color_format: parameters.hdr_color_format,
             render_bundles,
+            render_bundle_files: Vec::new(),
+            transparent_pipeline_bundles: Vec::new(),
             pbr_resource_bundle,
             shared_frame_data,
             sky_box,
-            anti_aliasing,
+            instance_transform_update,
+            light_clustering,
+            half_resolution_pass,
+            half_resolution_effects: Vec::new(),
+            volumetric_fog,
+            enable_volumetric_fog: false,
+            screen_space_reflections,
+            enable_screen_space_reflections: false,
+            water_surface,
+            enable_water_surface: false,
+            order_independent_transparency,
+            transient_images,
+            overdraw_heatmap,
+            overdraw_render_bundles: Vec::new(),
+            overdraw_heatmap_opacity: None,
+            shader_clock_heatmap,
+            shader_clock_render_bundles: Vec::new(),
+            shader_clock_heatmap_parameters: None,
+            path_tracer,
+            reference_mode: false,
+            depth_view,
+            depth_view_parameters: None,
+            nan_detection,
+            nan_detection_parameters: None,
+            stereo_view,
+            stereo_render_bundles: Vec::new(),
+            stereo_eye_separation: None,
+            parallel_recorder,
+            upscaler,
             tone_map,
 
+            render_statistics: Default::default(),
+            timestamp_period: device.get_physical_device_properties().limits.timestamp_period,
+            gpu_frame_time: 0.0,
+            resolution_scale: 1.0,
+            current_resolution_scale: 1.0,
+            adaptive_resolution_target: None,
+            paused: false,
+
+            animation_start_time: std::time::Instant::now(),
+            animation_pause_time: None,
+            animation_time: None,
+
             debug_enable_anti_aliasing: parameters.enable_anti_aliasing,
+            debug_highlight_color_space_mistakes: false,
         }
     }
 
@@ -166,9 +609,37 @@ impl PbrForwardLit {
         queue: &mut DeviceQueue,
     ) {
         puffin::profile_function!();
+        assert!(!self.paused, "render() called while paused");
+
+        self.update_resolution_scale(frame_context, factory);
+        self.update_material_animations();
+
+        if let (Some(path_tracer), true) = (&mut self.path_tracer, self.reference_mode) {
+            let resource_bundles: Vec<_> = self
+                .render_bundles
+                .iter()
+                .map(|(_, resource_bundle, _, _)| resource_bundle.borrow())
+                .collect();
+            let resource_bundles: Vec<&ResourceBundle> = resource_bundles.iter().map(|bundle| &**bundle).collect();
+            path_tracer.render(
+                &PathTracerRenderParameters {
+                    camera,
+                    resource_bundles: &resource_bundles,
+                    pbr_resource_bundle: &self.pbr_resource_bundle.borrow(),
+                    punctual_lights: self.shared_frame_data.get_punctual_lights(),
+                },
+                &mut self.instance_transform_update,
+                frame_context,
+                device,
+                factory,
+                queue,
+            );
+            return;
+        }
 
+        // Scene is rendered into the top left corner of the render layer and upscaled afterwards
         let viewport = camera.get_viewport();
-        let screen_area = vk::Rect2D {
+        let output_area = vk::Rect2D {
             offset: vk::Offset2D {
                 x: viewport.x,
                 y: viewport.y,
@@ -178,97 +649,117 @@ impl PbrForwardLit {
                 height: viewport.height,
             },
         };
+        let screen_area = vk::Rect2D {
+            offset: output_area.offset,
+            extent: vk::Extent2D {
+                width: ((viewport.width as f32 * self.current_resolution_scale) as u32).max(1),
+                height: ((viewport.height as f32 * self.current_resolution_scale) as u32).max(1),
+            },
+        };
 
         if !self.debug_enable_anti_aliasing {
             self.shared_frame_data.reset_subsample_offset();
         }
-        self.shared_frame_data.update(frame_context, camera, factory);
+        self.shared_frame_data
+            .update(frame_context, camera, self.current_resolution_scale, factory);
 
-        let color_image = self.render_layer.get_render_image(0).0;
+        let color_images = [
+            self.render_layer.get_render_image(0).0,
+            self.render_layer.get_render_image(1).0,
+            self.render_layer.get_render_image(2).0,
+        ];
         let depth_image = self.render_layer.get_depth_image().unwrap().0;
 
         self.render_layer.acquire_frame(frame_context, device, factory);
-        self.render_layer.begin_render_pass(frame_context, screen_area);
-        {
-            let command_buffer = self.render_layer.get_command_buffer(frame_context);
-            command_buffer.set_viewport(
-                0,
-                &[vk::Viewport {
-                    x: screen_area.offset.x as _,
-                    y: screen_area.offset.y as _,
-                    width: screen_area.extent.width as _,
-                    height: screen_area.extent.height as _,
-                    min_depth: 0.0,
-                    max_depth: 1.0,
-                }],
+        self.instance_transform_update.dispatch(
+            self.render_layer.get_command_buffer(frame_context),
+            frame_context,
+            factory,
+        );
+        self.light_clustering.dispatch(
+            self.render_layer.get_command_buffer(frame_context),
+            frame_context,
+            &self.shared_frame_data,
+        );
+        if self.enable_volumetric_fog {
+            self.volumetric_fog.dispatch(
+                self.render_layer.get_command_buffer(frame_context),
+                camera,
+                *self.shared_frame_data.get_frame_data_descriptor_set(frame_context),
+                self.shared_frame_data.get_frame_data_offset(frame_context),
+                &self.pbr_resource_bundle.borrow(),
             );
-            command_buffer.set_scissor(0, &[screen_area]);
-
+        }
+        {
             let pbr_resource_bundle = self.pbr_resource_bundle.borrow();
-            for (_, resource_bundle, _, pipeline_bundle) in &self.render_bundles {
-                let resource_bundle = resource_bundle.borrow();
-
-                let mut render_instance_id = 0;
-                for bucket in &resource_bundle.buckets {
-                    puffin::profile_scope!("render bucket");
-
-                    let pipeline_layout = pipeline_bundle.pipeline_layouts[bucket.material];
-                    let pipeline = pipeline_bundle.pipelines[bucket.material];
-
-                    command_buffer.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, pipeline);
-                    command_buffer.push_constants(
-                        pipeline_layout,
-                        vk::ShaderStageFlags::VERTEX,
-                        0,
-                        self.shared_frame_data.get_subsample_view_projection().as_slice(),
+            self.render_statistics = Default::default();
+            if let Some(parallel_recorder) = &mut self.parallel_recorder {
+                let resource_bundles: Vec<_> = self
+                    .render_bundles
+                    .iter()
+                    .map(|(_, resource_bundle, _, _)| resource_bundle.borrow())
+                    .collect();
+                let mut jobs: Vec<ForwardPassJob> = Vec::new();
+                for (resource_bundle, (_, _, _, pipeline_bundle)) in resource_bundles.iter().zip(&self.render_bundles) {
+                    jobs.extend(
+                        split_into_bucket_ranges(resource_bundle, pipeline_bundle)
+                            .into_iter()
+                            .map(ForwardPassJob::Buckets),
                     );
+                }
+                jobs.push(ForwardPassJob::SkyBox);
 
-                    for instance in &bucket.instances {
-                        command_buffer.push_constants(
-                            pipeline_layout,
-                            vk::ShaderStageFlags::FRAGMENT,
-                            64,
-                            &instance.material_instance_data,
-                        );
-                        command_buffer.bind_descriptor_sets(
-                            vk::PipelineBindPoint::GRAPHICS,
-                            pipeline_layout,
-                            0,
-                            &[
-                                resource_bundle.descriptor_sets[instance.material_instance],
-                                pipeline_bundle.descriptor_sets[render_instance_id],
-                                *self.shared_frame_data.get_frame_data_descriptor_set(frame_context),
-                                pbr_resource_bundle.descriptor_sets[0],
-                            ],
-                            &[],
-                        );
-
-                        let mesh = &resource_bundle.meshes[instance.mesh];
-                        command_buffer.bind_vertex_buffers(0, &[resource_bundle.buffers[mesh.vertex_buffer].0], &[0]);
-                        command_buffer.bind_index_buffer(
-                            resource_bundle.buffers[mesh.index_buffer.1].0,
-                            0,
-                            mesh.index_buffer.0,
-                        );
-                        command_buffer.draw_indexed(mesh.index_count as _, instance.total_instance_count as _, 0, 0, 0);
-
-                        render_instance_id += 1;
-                    }
+                let shared_frame_data = &self.shared_frame_data;
+                let sky_box = &self.sky_box;
+                let pbr_resource_bundle: &PbrResourceBundle = &pbr_resource_bundle;
+                parallel_recorder.begin_render_pass(&mut self.render_layer, frame_context, screen_area, factory);
+                let job_statistics = parallel_recorder.record(
+                    &mut self.render_layer,
+                    frame_context,
+                    factory,
+                    &jobs,
+                    |command_buffer, job| match job {
+                        ForwardPassJob::Buckets(bucket_range) => render_bucket_range(
+                            command_buffer,
+                            bucket_range,
+                            shared_frame_data,
+                            pbr_resource_bundle,
+                            None,
+                            frame_context,
+                        ),
+                        ForwardPassJob::SkyBox => {
+                            sky_box.render(command_buffer, frame_context, shared_frame_data);
+                            RenderStatistics::default()
+                        }
+                    },
+                );
+                for statistics in job_statistics {
+                    self.render_statistics += statistics;
+                }
+                parallel_recorder.end_render_pass(&mut self.render_layer, frame_context);
+            } else {
+                self.render_layer.begin_render_pass(frame_context, screen_area);
+                let command_buffer = self.render_layer.get_command_buffer(frame_context);
+                for (_, resource_bundle, _, pipeline_bundle) in &self.render_bundles {
+                    self.render_statistics += render_buckets(
+                        command_buffer,
+                        &resource_bundle.borrow(),
+                        pipeline_bundle,
+                        &self.shared_frame_data,
+                        &pbr_resource_bundle,
+                        None,
+                        frame_context,
+                    );
                 }
-            }
 
-            self.sky_box
-                .render(command_buffer, frame_context, &self.shared_frame_data);
-            self.render_layer.end_render_pass(frame_context);
+                self.sky_box
+                    .render(command_buffer, frame_context, &self.shared_frame_data);
+                self.render_layer.end_render_pass(frame_context);
+            }
 
-            let command_buffer = self.render_layer.get_command_buffer(frame_context);
-            command_buffer.pipeline_barrier(
-                vk::PipelineStageFlags::ALL_GRAPHICS,
-                vk::PipelineStageFlags::FRAGMENT_SHADER,
-                None,
-                &[],
-                &[],
-                &[
+            let mut image_barriers: Vec<vk::ImageMemoryBarrier> = color_images
+                .iter()
+                .map(|image| {
                     vk::ImageMemoryBarrier::builder()
                         .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
                         .dst_access_mask(vk::AccessFlags::MEMORY_READ)
@@ -276,7 +767,7 @@ impl PbrForwardLit {
                         .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
                         .src_queue_family_index(!0)
                         .dst_queue_family_index(!0)
-                        .image(color_image)
+                        .image(*image)
                         .subresource_range(
                             vk::ImageSubresourceRange::builder()
                                 .aspect_mask(vk::ImageAspectFlags::COLOR)
@@ -286,91 +777,563 @@ impl PbrForwardLit {
                                 .layer_count(1)
                                 .build(),
                         )
-                        .build(),
-                    vk::ImageMemoryBarrier::builder()
-                        .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
-                        .dst_access_mask(vk::AccessFlags::MEMORY_READ)
-                        .old_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                        .src_queue_family_index(!0)
-                        .dst_queue_family_index(!0)
-                        .image(depth_image)
-                        .subresource_range(
-                            vk::ImageSubresourceRange::builder()
-                                .aspect_mask(vk::ImageAspectFlags::DEPTH)
-                                .base_mip_level(0)
-                                .level_count(1)
-                                .base_array_layer(0)
-                                .layer_count(1)
-                                .build(),
-                        )
-                        .build(),
-                ],
+                        .build()
+                })
+                .collect();
+            image_barriers.push(
+                vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                    .old_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .src_queue_family_index(!0)
+                    .dst_queue_family_index(!0)
+                    .image(depth_image)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::builder()
+                            .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                            .base_mip_level(0)
+                            .level_count(1)
+                            .base_array_layer(0)
+                            .layer_count(1)
+                            .build(),
+                    )
+                    .build(),
+            );
+
+            let command_buffer = self.render_layer.get_command_buffer(frame_context);
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::ALL_GRAPHICS,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                None,
+                &[],
+                &[],
+                &image_barriers,
             );
+            if self.enable_screen_space_reflections {
+                self.screen_space_reflections.dispatch(
+                    command_buffer,
+                    screen_area,
+                    *self.shared_frame_data.get_frame_data_descriptor_set(frame_context),
+                    self.shared_frame_data.get_frame_data_offset(frame_context),
+                    &pbr_resource_bundle,
+                );
+            }
+            if let (Some(nan_detection), Some(_)) = (&mut self.nan_detection, self.nan_detection_parameters) {
+                let mut scanned_buffers = Vec::new();
+                for (bundle_name, resource_bundle, _, _) in &self.render_bundles {
+                    let resource_bundle = resource_bundle.borrow();
+                    for (bucket_id, bucket) in resource_bundle.buckets.iter().enumerate() {
+                        let transform_buffer = &resource_bundle.buffers[bucket.instance_transform_buffer.index()];
+                        scanned_buffers.push(NanDetectionBuffer {
+                            name: format!("instance transforms of bucket {} in \"{}\"", bucket_id, bundle_name),
+                            buffer: transform_buffer.0,
+                            size: transform_buffer.1.get_size() as _,
+                            stride: 16,
+                        });
+                    }
+                }
+                nan_detection.dispatch(command_buffer, screen_area, &scanned_buffers, frame_context, factory);
+            }
         }
 
         self.render_layer.submit_commands(frame_context, queue);
 
-        if let Some(anti_aliasing) = &mut self.anti_aliasing {
-            anti_aliasing.get_current_render_layer_mut().add_dependency(
-                frame_context,
+        // Every pass after the forward pass waits for the previous one that wrote the scene color
+        let has_half_resolution_effects = self.has_half_resolution_effects();
+        let mut scene_color_layer = &self.render_layer;
+        if self.enable_screen_space_reflections {
+            self.screen_space_reflections.render(
                 &self.render_layer,
-                vk::PipelineStageFlags::FRAGMENT_SHADER,
-            );
-            anti_aliasing.render(
                 screen_area,
-                &self.shared_frame_data,
                 frame_context,
                 device,
                 factory,
                 queue,
             );
-
-            self.shared_frame_data.advance_subsample_offset();
+            scene_color_layer = self.screen_space_reflections.get_composite_layer();
         }
-    }
 
-    pub fn post_process(&mut self, camera: &Camera, frame_context: &FrameContext, target_layer: &mut RenderLayer) {
-        if let Some(tone_map) = &mut self.tone_map {
-            let viewport = camera.get_viewport();
-            let screen_area = vk::Rect2D {
-                offset: vk::Offset2D {
-                    x: viewport.x,
-                    y: viewport.y,
+        if self.enable_water_surface {
+            self.water_surface.render(
+                &WaterSurfaceRenderParameters {
+                    source_layer: &self.render_layer,
+                    dependency_layer: scene_color_layer,
+                    screen_area,
+                    shared_frame_data: &self.shared_frame_data,
+                    pbr_resource_bundle: &self.pbr_resource_bundle.borrow(),
                 },
-                extent: vk::Extent2D {
-                    width: viewport.width,
-                    height: viewport.height,
-                },
-            };
-            tone_map.render(screen_area, frame_context, target_layer);
+                frame_context,
+                device,
+                factory,
+                queue,
+            );
+            scene_color_layer = self.water_surface.get_water_layer();
         }
-    }
-}
 
-impl PbrForwardLit {
-    pub fn add_render_bundle(
-        &mut self,
-        bundle_name: &str,
-        bundle_loader: &mut BundleLoader,
-        gltf_file: &std::path::Path,
-        bundle_file: &std::path::Path,
-        shader_file: &std::path::Path,
-        device: &Device,
-        factory: &mut DeviceFactory,
-        queue: &mut DeviceQueue,
+        if let Some(order_independent_transparency) = &mut self.order_independent_transparency {
+            let transparency_descriptor_set = order_independent_transparency.get_accumulation_descriptor_set();
+            let command_buffer = order_independent_transparency.begin_accumulation(
+                scene_color_layer,
+                screen_area,
+                frame_context,
+                device,
+                factory,
+            );
+
+            let pbr_resource_bundle = self.pbr_resource_bundle.borrow();
+            for ((_, resource_bundle, _, _), pipeline_bundle) in
+                self.render_bundles.iter().zip(&self.transparent_pipeline_bundles)
+            {
+                self.render_statistics += render_buckets(
+                    command_buffer,
+                    &resource_bundle.borrow(),
+                    pipeline_bundle,
+                    &self.shared_frame_data,
+                    &pbr_resource_bundle,
+                    Some(transparency_descriptor_set),
+                    frame_context,
+                );
+            }
+
+            order_independent_transparency.resolve(
+                &self.render_layer,
+                screen_area,
+                frame_context,
+                device,
+                factory,
+                queue,
+            );
+            scene_color_layer = order_independent_transparency.get_composite_layer();
+        }
+
+        if has_half_resolution_effects {
+            let mut effects: Vec<&mut dyn HalfResolutionEffect> =
+                Vec::with_capacity(self.half_resolution_effects.len() + 1);
+            if self.enable_volumetric_fog {
+                effects.push(&mut self.volumetric_fog);
+            }
+            for effect in &mut self.half_resolution_effects {
+                effects.push(effect.as_mut());
+            }
+
+            self.half_resolution_pass.render(
+                &mut effects,
+                &HalfResolutionRenderParameters {
+                    source_layer: &self.render_layer,
+                    dependency_layer: scene_color_layer,
+                    source_color_image: 0,
+                    screen_area,
+                    frame_data_descriptor_set: *self.shared_frame_data.get_frame_data_descriptor_set(frame_context),
+                    frame_data_offset: self.shared_frame_data.get_frame_data_offset(frame_context),
+                },
+                frame_context,
+                device,
+                factory,
+                queue,
+            );
+            scene_color_layer = self.half_resolution_pass.get_composite_layer();
+        }
+
+        if let (Some(overdraw_heatmap), Some(_)) = (&mut self.overdraw_heatmap, self.overdraw_heatmap_opacity) {
+            let command_buffer =
+                overdraw_heatmap.begin_counting(scene_color_layer, screen_area, frame_context, device, factory);
+
+            let pbr_resource_bundle = self.pbr_resource_bundle.borrow();
+            for ((_, resource_bundle, _, _), (_, pipeline_bundle)) in
+                self.render_bundles.iter().zip(&self.overdraw_render_bundles)
+            {
+                render_buckets(
+                    command_buffer,
+                    &resource_bundle.borrow(),
+                    pipeline_bundle,
+                    &self.shared_frame_data,
+                    &pbr_resource_bundle,
+                    None,
+                    frame_context,
+                );
+            }
+
+            overdraw_heatmap.end_counting(frame_context, queue);
+            scene_color_layer = overdraw_heatmap.get_overdraw_layer();
+        }
+
+        if let (Some(shader_clock_heatmap), Some(_)) =
+            (&mut self.shader_clock_heatmap, self.shader_clock_heatmap_parameters)
+        {
+            let command_buffer =
+                shader_clock_heatmap.begin_measuring(scene_color_layer, screen_area, frame_context, device, factory);
+
+            let pbr_resource_bundle = self.pbr_resource_bundle.borrow();
+            for ((_, resource_bundle, _, _), (_, pipeline_bundle)) in
+                self.render_bundles.iter().zip(&self.shader_clock_render_bundles)
+            {
+                render_buckets(
+                    command_buffer,
+                    &resource_bundle.borrow(),
+                    pipeline_bundle,
+                    &self.shared_frame_data,
+                    &pbr_resource_bundle,
+                    None,
+                    frame_context,
+                );
+            }
+
+            shader_clock_heatmap.end_measuring(frame_context, queue);
+            scene_color_layer = shader_clock_heatmap.get_cost_layer();
+        }
+
+        // Stereo view only contains scene geometry, sky and post processing are not applied to it
+        if let (Some(stereo_view), Some(_)) = (&mut self.stereo_view, self.stereo_eye_separation) {
+            let command_buffer = stereo_view.begin(scene_color_layer, screen_area, frame_context, device, factory);
+
+            let pbr_resource_bundle = self.pbr_resource_bundle.borrow();
+            for ((_, resource_bundle, _, _), (_, pipeline_bundle)) in
+                self.render_bundles.iter().zip(&self.stereo_render_bundles)
+            {
+                render_buckets(
+                    command_buffer,
+                    &resource_bundle.borrow(),
+                    pipeline_bundle,
+                    &self.shared_frame_data,
+                    &pbr_resource_bundle,
+                    None,
+                    frame_context,
+                );
+            }
+
+            stereo_view.end(frame_context, queue);
+            scene_color_layer = stereo_view.get_stereo_layer();
+        }
+
+        if let Some(upscaler) = &mut self.upscaler {
+            upscaler.render(
+                &UpscalerInputs {
+                    source_layer: &self.render_layer,
+                    dependency_layer: scene_color_layer,
+                    source_color_image: 0,
+                    source_motion_vectors: None,
+                    frame_data_descriptor_set: *self.shared_frame_data.get_frame_data_descriptor_set(frame_context),
+                    frame_data_offset: self.shared_frame_data.get_frame_data_offset(frame_context),
+                    subsample_offset: self.shared_frame_data.get_subsample_offset(),
+                    render_area: screen_area,
+                    output_area,
+                },
+                frame_context,
+                device,
+                factory,
+                queue,
+            );
+
+            self.shared_frame_data.advance_subsample_offset();
+        }
+    }
+
+    pub fn post_process(&mut self, camera: &Camera, frame_context: &FrameContext, target_layer: &mut RenderLayer) {
+        let viewport = camera.get_viewport();
+        let screen_area = vk::Rect2D {
+            offset: vk::Offset2D {
+                x: viewport.x,
+                y: viewport.y,
+            },
+            extent: vk::Extent2D {
+                width: viewport.width,
+                height: viewport.height,
+            },
+        };
+        if let (Some(path_tracer), true) = (&mut self.path_tracer, self.reference_mode) {
+            path_tracer.post_process(screen_area, &self.shared_frame_data, frame_context, target_layer);
+            return;
+        }
+
+        if let Some(tone_map) = &mut self.tone_map {
+            // Upscalers always fill the whole output area
+            if let Some(upscaler) = &self.upscaler {
+                tone_map.render(
+                    screen_area,
+                    upscaler.get_output_index(),
+                    1.0,
+                    &self.shared_frame_data,
+                    frame_context,
+                    target_layer,
+                );
+            } else {
+                tone_map.render(
+                    screen_area,
+                    0,
+                    self.current_resolution_scale,
+                    &self.shared_frame_data,
+                    frame_context,
+                    target_layer,
+                );
+            }
+
+            if let (Some(overdraw_heatmap), Some(opacity)) = (&mut self.overdraw_heatmap, self.overdraw_heatmap_opacity)
+            {
+                overdraw_heatmap.render(
+                    screen_area,
+                    self.current_resolution_scale,
+                    opacity,
+                    frame_context,
+                    target_layer,
+                );
+            }
+
+            if let (Some(shader_clock_heatmap), Some(parameters)) =
+                (&mut self.shader_clock_heatmap, &self.shader_clock_heatmap_parameters)
+            {
+                shader_clock_heatmap.render(
+                    screen_area,
+                    self.current_resolution_scale,
+                    parameters,
+                    frame_context,
+                    target_layer,
+                );
+            }
+
+            let hi_z_level = self
+                .depth_view_parameters
+                .and_then(|parameters| self.get_hi_z_level(parameters.source));
+            if let (Some(depth_view), Some(parameters)) = (&mut self.depth_view, &self.depth_view_parameters) {
+                depth_view.render(
+                    screen_area,
+                    self.current_resolution_scale,
+                    parameters.max_distance,
+                    hi_z_level,
+                    frame_context,
+                    target_layer,
+                );
+            }
+
+            if let (Some(nan_detection), Some(NanDetectionParameters { tint_output: true })) =
+                (&mut self.nan_detection, self.nan_detection_parameters)
+            {
+                nan_detection.render_tint(screen_area, self.current_resolution_scale, frame_context, target_layer);
+            }
+        }
+    }
+}
+
+impl PbrForwardLit {
+    pub fn add_render_bundle(
+        &mut self,
+        bundle_name: &str,
+        bundle_loader: &mut BundleLoader,
+        gltf_file: &std::path::Path,
+        bundle_file: &std::path::Path,
+        shader_file: &std::path::Path,
+        device: &Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
     ) {
         log::info!("adding render bundle \"{}\"", bundle_name);
+        factory.push_resource_tag(bundle_name);
 
         let resource_bundle = bundle_loader.request_bundle(gltf_file, bundle_file, device, factory, queue);
-        let shader_module_bundle = bundle_loader.compile_shader_module_bundle(
-            &resource_bundle,
-            &bundle_file.with_extension("pbr_forward_lit"),
-            &shader_file,
+        let render_bundle_files = RenderBundleFiles {
+            gltf_file: gltf_file.to_path_buf(),
+            bundle_file: bundle_file.to_path_buf(),
+            shader_file: shader_file.to_path_buf(),
+        };
+        let pipelines = self
+            .create_render_bundle_pipelines(bundle_loader, &resource_bundle, &render_bundle_files, device, factory)
+            .unwrap_or_else(|error| panic!("failed to compile shaders of \"{}\":\n{}", bundle_name, error));
+
+        if let Some(transparent_pipeline_bundle) = pipelines.transparent_pipeline_bundle {
+            self.transparent_pipeline_bundles.push(transparent_pipeline_bundle);
+        }
+        if let Some(overdraw_render_bundle) = pipelines.overdraw_render_bundle {
+            self.overdraw_render_bundles.push(overdraw_render_bundle);
+        }
+        if let Some(shader_clock_render_bundle) = pipelines.shader_clock_render_bundle {
+            self.shader_clock_render_bundles.push(shader_clock_render_bundle);
+        }
+        if let Some(stereo_render_bundle) = pipelines.stereo_render_bundle {
+            self.stereo_render_bundles.push(stereo_render_bundle);
+        }
+
+        register_loaded_bundle(bundle_name);
+        self.render_bundle_files.push(render_bundle_files);
+        resource_bundle.borrow_mut().color_space_debug = self.debug_highlight_color_space_mistakes;
+        self.render_bundles.push((
+            bundle_name.to_string(),
+            resource_bundle,
+            pipelines.shader_module_bundle,
+            pipelines.pipeline_bundle,
+        ));
+        if let Some(path_tracer) = &mut self.path_tracer {
+            path_tracer.invalidate_scene();
+        }
+        factory.pop_resource_tag();
+    }
+
+    // Shaders of all loaded bundles are compiled again if they have changed. Bundles with errors keep their
+    // previous pipelines and the compiler logs are returned, so that shader edits can be fixed and reloaded.
+    pub fn reload_shaders(
+        &mut self,
+        bundle_loader: &mut BundleLoader,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for bundle_index in 0..self.render_bundles.len() {
+            let bundle_name = self.render_bundles[bundle_index].0.clone();
+            let resource_bundle = self.render_bundles[bundle_index].1.clone();
+            log::info!("reloading shaders of \"{}\"", bundle_name);
+
+            let pipelines = match self.create_render_bundle_pipelines(
+                bundle_loader,
+                &resource_bundle,
+                &self.render_bundle_files[bundle_index],
+                device,
+                factory,
+            ) {
+                Ok(pipelines) => pipelines,
+                Err(error) => {
+                    log::error!("failed to reload shaders of \"{}\":\n{}", bundle_name, error);
+                    errors.push(error);
+                    continue;
+                }
+            };
+
+            let render_bundle = &mut self.render_bundles[bundle_index];
+            let shader_module_bundle = std::mem::replace(&mut render_bundle.2, pipelines.shader_module_bundle);
+            let pipeline_bundle = std::mem::replace(&mut render_bundle.3, pipelines.pipeline_bundle);
+            bundle_loader.queue_destroy_bundle(QueuedBundle::Pipeline(pipeline_bundle));
+            bundle_loader.queue_destroy_bundle(QueuedBundle::ShaderModule(shader_module_bundle));
+            if let Some(transparent_pipeline_bundle) = pipelines.transparent_pipeline_bundle {
+                let transparent_pipeline_bundle = std::mem::replace(
+                    &mut self.transparent_pipeline_bundles[bundle_index],
+                    transparent_pipeline_bundle,
+                );
+                bundle_loader.queue_destroy_bundle(QueuedBundle::Pipeline(transparent_pipeline_bundle));
+            }
+            if let Some(overdraw_render_bundle) = pipelines.overdraw_render_bundle {
+                let (overdraw_shader_module_bundle, overdraw_pipeline_bundle) =
+                    std::mem::replace(&mut self.overdraw_render_bundles[bundle_index], overdraw_render_bundle);
+                bundle_loader.queue_destroy_bundle(QueuedBundle::Pipeline(overdraw_pipeline_bundle));
+                bundle_loader.queue_destroy_bundle(QueuedBundle::ShaderModule(overdraw_shader_module_bundle));
+            }
+            if let Some(shader_clock_render_bundle) = pipelines.shader_clock_render_bundle {
+                let (shader_clock_shader_module_bundle, shader_clock_pipeline_bundle) = std::mem::replace(
+                    &mut self.shader_clock_render_bundles[bundle_index],
+                    shader_clock_render_bundle,
+                );
+                bundle_loader.queue_destroy_bundle(QueuedBundle::Pipeline(shader_clock_pipeline_bundle));
+                bundle_loader.queue_destroy_bundle(QueuedBundle::ShaderModule(shader_clock_shader_module_bundle));
+            }
+            if let Some(stereo_render_bundle) = pipelines.stereo_render_bundle {
+                let (stereo_shader_module_bundle, stereo_pipeline_bundle) =
+                    std::mem::replace(&mut self.stereo_render_bundles[bundle_index], stereo_render_bundle);
+                bundle_loader.queue_destroy_bundle(QueuedBundle::Pipeline(stereo_pipeline_bundle));
+                bundle_loader.queue_destroy_bundle(QueuedBundle::ShaderModule(stereo_shader_module_bundle));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    // All shader variants are compiled before any pipeline is created, nothing is kept if one of them fails
+    fn create_render_bundle_pipelines(
+        &self,
+        bundle_loader: &mut BundleLoader,
+        resource_bundle: &ResourceBundleReference,
+        render_bundle_files: &RenderBundleFiles,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) -> Result<RenderBundlePipelines, String> {
+        let bundle_file = &render_bundle_files.bundle_file;
+        let shader_file = &render_bundle_files.shader_file;
+
+        // Alpha blended materials are compiled differently for every transparency mode
+        let transparency_mode = match &self.order_independent_transparency {
+            Some(order_independent_transparency) => order_independent_transparency.get_mode(),
+            None => TransparencyMode::Disabled,
+        };
+        let use_vertex_pulling = bundle_loader.uses_vertex_pulling();
+        let mut shader_module_bundle = bundle_loader.compile_shader_module_bundle(
+            resource_bundle,
+            &bundle_file.with_extension(format!("pbr_forward_lit_{}", transparency_mode.get_name())),
+            shader_file,
+            &[],
+            transparency_mode.get_macro_definitions(),
             factory,
-        );
+        )?;
+        // Overdraw material only fetches positions, all materials are counted including alpha blended ones
+        let mut overdraw_shader_module_bundle = match &self.overdraw_heatmap {
+            Some(_) => match bundle_loader.compile_shader_module_bundle(
+                resource_bundle,
+                &bundle_file.with_extension("pbr_forward_lit_overdraw"),
+                &bundle_loader
+                    .get_base_path()
+                    .join("malwerks_shaders")
+                    .join("overdraw_material.glsl"),
+                &[],
+                None,
+                factory,
+            ) {
+                Ok(overdraw_shader_module_bundle) => Some(overdraw_shader_module_bundle),
+                Err(error) => {
+                    shader_module_bundle.destroy(factory);
+                    return Err(error);
+                }
+            },
+            None => None,
+        };
+        // Cost is measured with alpha blended materials compiled as opaque, they are counted like the overdraw
+        let mut shader_clock_shader_module_bundle = match &self.shader_clock_heatmap {
+            Some(_) => match bundle_loader.compile_shader_module_bundle(
+                resource_bundle,
+                &bundle_file.with_extension("pbr_forward_lit_shader_clock"),
+                shader_file,
+                &[("SHADER_CLOCK", "1")],
+                None,
+                factory,
+            ) {
+                Ok(shader_clock_shader_module_bundle) => Some(shader_clock_shader_module_bundle),
+                Err(error) => {
+                    shader_module_bundle.destroy(factory);
+                    if let Some(overdraw_shader_module_bundle) = &mut overdraw_shader_module_bundle {
+                        overdraw_shader_module_bundle.destroy(factory);
+                    }
+                    return Err(error);
+                }
+            },
+            None => None,
+        };
+        // Stereo pipelines skip alpha blended materials, transparency is not resolved per view
+        let stereo_shader_module_bundle = match &self.stereo_view {
+            Some(_) => match bundle_loader.compile_shader_module_bundle(
+                resource_bundle,
+                &bundle_file.with_extension("pbr_forward_lit_stereo"),
+                shader_file,
+                &[("MULTIVIEW", "1")],
+                None,
+                factory,
+            ) {
+                Ok(stereo_shader_module_bundle) => Some(stereo_shader_module_bundle),
+                Err(error) => {
+                    shader_module_bundle.destroy(factory);
+                    if let Some(overdraw_shader_module_bundle) = &mut overdraw_shader_module_bundle {
+                        overdraw_shader_module_bundle.destroy(factory);
+                    }
+                    if let Some(shader_clock_shader_module_bundle) = &mut shader_clock_shader_module_bundle {
+                        shader_clock_shader_module_bundle.destroy(factory);
+                    }
+                    return Err(error);
+                }
+            },
+            None => None,
+        };
+
+        // Pipeline caches are stored once all pipelines of the variant are created
+        let pipeline_cache_data = bundle_loader.load_pipeline_cache_data(bundle_file, "forward");
         let pipeline_bundle =
-            bundle_loader.create_pipeline_bundle(&resource_bundle, |pbr_resource_bundle, resource_bundle| {
+            bundle_loader.create_pipeline_bundle(resource_bundle, |pbr_resource_bundle, resource_bundle| {
                 PipelineBundle::new(
                     &PipelineBundleParameters {
                         resource_bundle,
@@ -380,17 +1343,155 @@ impl PbrForwardLit {
                             self.shared_frame_data.descriptor_set_layout,
                             pbr_resource_bundle.descriptor_set_layout,
                         ],
+                        use_push_descriptors: device.is_push_descriptor_enabled(),
+                        use_vertex_pulling,
+                        blending: if self.order_independent_transparency.is_some() {
+                            PipelineBlending::SkipAlphaBlended
+                        } else {
+                            PipelineBlending::Opaque
+                        },
+                        pipeline_cache_data: &pipeline_cache_data,
+                        use_pipeline_libraries: device.is_graphics_pipeline_library_enabled(),
                     },
                     factory,
                 )
             });
+        bundle_loader.store_pipeline_cache_data(bundle_file, "forward", &pipeline_bundle, factory);
+        let transparent_pipeline_bundle = match &self.order_independent_transparency {
+            Some(order_independent_transparency) => {
+                let blend_attachments = order_independent_transparency.get_accumulation_blend_attachments();
+                let pipeline_cache_data = bundle_loader.load_pipeline_cache_data(bundle_file, "transparent");
+                let transparent_pipeline_bundle =
+                    bundle_loader.create_pipeline_bundle(resource_bundle, |pbr_resource_bundle, resource_bundle| {
+                        PipelineBundle::new(
+                            &PipelineBundleParameters {
+                                resource_bundle,
+                                shader_module_bundle: &shader_module_bundle,
+                                render_layer: order_independent_transparency.get_accumulation_layer(),
+                                descriptor_set_layouts: &[
+                                    self.shared_frame_data.descriptor_set_layout,
+                                    pbr_resource_bundle.descriptor_set_layout,
+                                    order_independent_transparency.get_accumulation_descriptor_set_layout(),
+                                ],
+                                use_push_descriptors: device.is_push_descriptor_enabled(),
+                                use_vertex_pulling,
+                                blending: PipelineBlending::AlphaBlendedOnly(&blend_attachments),
+                                pipeline_cache_data: &pipeline_cache_data,
+                                use_pipeline_libraries: device.is_graphics_pipeline_library_enabled(),
+                            },
+                            factory,
+                        )
+                    });
+                bundle_loader.store_pipeline_cache_data(
+                    bundle_file,
+                    "transparent",
+                    &transparent_pipeline_bundle,
+                    factory,
+                );
+                Some(transparent_pipeline_bundle)
+            }
+            None => None,
+        };
+        let overdraw_render_bundle = match (&self.overdraw_heatmap, overdraw_shader_module_bundle) {
+            (Some(overdraw_heatmap), Some(overdraw_shader_module_bundle)) => {
+                let blend_attachments = overdraw_heatmap.get_overdraw_blend_attachments();
+                let pipeline_cache_data = bundle_loader.load_pipeline_cache_data(bundle_file, "overdraw");
+                let overdraw_pipeline_bundle =
+                    bundle_loader.create_pipeline_bundle(resource_bundle, |pbr_resource_bundle, resource_bundle| {
+                        PipelineBundle::new(
+                            &PipelineBundleParameters {
+                                resource_bundle,
+                                shader_module_bundle: &overdraw_shader_module_bundle,
+                                render_layer: overdraw_heatmap.get_overdraw_layer(),
+                                descriptor_set_layouts: &[
+                                    self.shared_frame_data.descriptor_set_layout,
+                                    pbr_resource_bundle.descriptor_set_layout,
+                                ],
+                                use_push_descriptors: device.is_push_descriptor_enabled(),
+                                use_vertex_pulling,
+                                blending: PipelineBlending::AllBlended(&blend_attachments),
+                                pipeline_cache_data: &pipeline_cache_data,
+                                use_pipeline_libraries: device.is_graphics_pipeline_library_enabled(),
+                            },
+                            factory,
+                        )
+                    });
+                bundle_loader.store_pipeline_cache_data(bundle_file, "overdraw", &overdraw_pipeline_bundle, factory);
+                Some((overdraw_shader_module_bundle, overdraw_pipeline_bundle))
+            }
+            _ => None,
+        };
+        let shader_clock_render_bundle = match (&self.shader_clock_heatmap, shader_clock_shader_module_bundle) {
+            (Some(shader_clock_heatmap), Some(shader_clock_shader_module_bundle)) => {
+                let blend_attachments = shader_clock_heatmap.get_cost_blend_attachments();
+                let pipeline_cache_data = bundle_loader.load_pipeline_cache_data(bundle_file, "shader_clock");
+                let shader_clock_pipeline_bundle =
+                    bundle_loader.create_pipeline_bundle(resource_bundle, |pbr_resource_bundle, resource_bundle| {
+                        PipelineBundle::new(
+                            &PipelineBundleParameters {
+                                resource_bundle,
+                                shader_module_bundle: &shader_clock_shader_module_bundle,
+                                render_layer: shader_clock_heatmap.get_cost_layer(),
+                                descriptor_set_layouts: &[
+                                    self.shared_frame_data.descriptor_set_layout,
+                                    pbr_resource_bundle.descriptor_set_layout,
+                                ],
+                                use_push_descriptors: device.is_push_descriptor_enabled(),
+                                use_vertex_pulling,
+                                blending: PipelineBlending::AllBlended(&blend_attachments),
+                                pipeline_cache_data: &pipeline_cache_data,
+                                use_pipeline_libraries: device.is_graphics_pipeline_library_enabled(),
+                            },
+                            factory,
+                        )
+                    });
+                bundle_loader.store_pipeline_cache_data(
+                    bundle_file,
+                    "shader_clock",
+                    &shader_clock_pipeline_bundle,
+                    factory,
+                );
+                Some((shader_clock_shader_module_bundle, shader_clock_pipeline_bundle))
+            }
+            _ => None,
+        };
+        let stereo_render_bundle = match (&self.stereo_view, stereo_shader_module_bundle) {
+            (Some(stereo_view), Some(stereo_shader_module_bundle)) => {
+                let pipeline_cache_data = bundle_loader.load_pipeline_cache_data(bundle_file, "stereo");
+                let stereo_pipeline_bundle =
+                    bundle_loader.create_pipeline_bundle(resource_bundle, |pbr_resource_bundle, resource_bundle| {
+                        PipelineBundle::new(
+                            &PipelineBundleParameters {
+                                resource_bundle,
+                                shader_module_bundle: &stereo_shader_module_bundle,
+                                render_layer: stereo_view.get_stereo_layer(),
+                                descriptor_set_layouts: &[
+                                    self.shared_frame_data.descriptor_set_layout,
+                                    pbr_resource_bundle.descriptor_set_layout,
+                                ],
+                                use_push_descriptors: device.is_push_descriptor_enabled(),
+                                use_vertex_pulling,
+                                blending: PipelineBlending::SkipAlphaBlended,
+                                pipeline_cache_data: &pipeline_cache_data,
+                                use_pipeline_libraries: device.is_graphics_pipeline_library_enabled(),
+                            },
+                            factory,
+                        )
+                    });
+                bundle_loader.store_pipeline_cache_data(bundle_file, "stereo", &stereo_pipeline_bundle, factory);
+                Some((stereo_shader_module_bundle, stereo_pipeline_bundle))
+            }
+            _ => None,
+        };
 
-        self.render_bundles.push((
-            bundle_name.to_string(),
-            resource_bundle,
+        Ok(RenderBundlePipelines {
             shader_module_bundle,
             pipeline_bundle,
-        ));
+            transparent_pipeline_bundle,
+            overdraw_render_bundle,
+            shader_clock_render_bundle,
+            stereo_render_bundle,
+        })
     }
 
     pub fn remove_render_bundle(&mut self, bundle_name: &str, bundle_loader: &mut BundleLoader) {
@@ -398,23 +1499,584 @@ impl PbrForwardLit {
         while index != self.render_bundles.len() {
             if self.render_bundles[index].0 == bundle_name {
                 log::info!("removing render bundle \"{}\"", bundle_name);
+                unregister_loaded_bundle(bundle_name);
+                if let Some(path_tracer) = &mut self.path_tracer {
+                    path_tracer.remove_deformed_vertex_buffers(&self.render_bundles[index].1.borrow());
+                }
                 let (_, _, shader_module_bundle, pipeline_bundle) = self.render_bundles.swap_remove(index);
+                self.render_bundle_files.swap_remove(index);
+                if self.order_independent_transparency.is_some() {
+                    let transparent_pipeline_bundle = self.transparent_pipeline_bundles.swap_remove(index);
+                    bundle_loader.queue_destroy_bundle(QueuedBundle::Pipeline(transparent_pipeline_bundle));
+                }
+                if self.overdraw_heatmap.is_some() {
+                    let (overdraw_shader_module_bundle, overdraw_pipeline_bundle) =
+                        self.overdraw_render_bundles.swap_remove(index);
+                    bundle_loader.queue_destroy_bundle(QueuedBundle::Pipeline(overdraw_pipeline_bundle));
+                    bundle_loader.queue_destroy_bundle(QueuedBundle::ShaderModule(overdraw_shader_module_bundle));
+                }
+                if self.shader_clock_heatmap.is_some() {
+                    let (shader_clock_shader_module_bundle, shader_clock_pipeline_bundle) =
+                        self.shader_clock_render_bundles.swap_remove(index);
+                    bundle_loader.queue_destroy_bundle(QueuedBundle::Pipeline(shader_clock_pipeline_bundle));
+                    bundle_loader.queue_destroy_bundle(QueuedBundle::ShaderModule(shader_clock_shader_module_bundle));
+                }
+                if self.stereo_view.is_some() {
+                    let (stereo_shader_module_bundle, stereo_pipeline_bundle) =
+                        self.stereo_render_bundles.swap_remove(index);
+                    bundle_loader.queue_destroy_bundle(QueuedBundle::Pipeline(stereo_pipeline_bundle));
+                    bundle_loader.queue_destroy_bundle(QueuedBundle::ShaderModule(stereo_shader_module_bundle));
+                }
 
                 bundle_loader.queue_destroy_bundle(QueuedBundle::Pipeline(pipeline_bundle));
                 bundle_loader.queue_destroy_bundle(QueuedBundle::ShaderModule(shader_module_bundle));
+                if let Some(path_tracer) = &mut self.path_tracer {
+                    path_tracer.invalidate_scene();
+                }
             } else {
                 index += 1;
             }
         }
     }
 
+    // Queues a transform update for the next rendered frame, instance index is relative to the bucket
+    pub fn update_instance_transform(
+        &mut self,
+        bundle_name: &str,
+        bucket: usize,
+        instance_index: u32,
+        transform: &[f32; 16],
+    ) {
+        for (name, resource_bundle, _, _) in &self.render_bundles {
+            if name == bundle_name {
+                let resource_bundle = resource_bundle.borrow();
+                let render_bucket = match resource_bundle.buckets.get(bucket) {
+                    Some(render_bucket) if (instance_index as usize) < render_bucket.instances.len() => render_bucket,
+                    _ => {
+                        log::error!(
+                            "instance {} of bucket {} is out of range in \"{}\"",
+                            instance_index,
+                            bucket,
+                            bundle_name
+                        );
+                        return;
+                    }
+                };
+                let target_buffer = resource_bundle.buffers[render_bucket.instance_transform_buffer.index()].0;
+                self.instance_transform_update
+                    .queue_update(target_buffer, instance_index, transform);
+                if let Some(path_tracer) = &mut self.path_tracer {
+                    path_tracer.reset_accumulation();
+                }
+            }
+        }
+    }
+
+    // Reference mode traces the mesh with vertices written by another pass every frame, e.g. skinned vertices.
+    // Passing None goes back to the vertices of the bundle.
+    pub fn set_deformed_vertex_buffer(
+        &mut self,
+        bundle_name: &str,
+        mesh: MeshHandle,
+        deformed_vertex_buffer: Option<DeformedVertexBuffer>,
+    ) {
+        if let Some(path_tracer) = &mut self.path_tracer {
+            for (name, resource_bundle, _, _) in &self.render_bundles {
+                if name == bundle_name {
+                    path_tracer.set_deformed_vertex_buffer(&resource_bundle.borrow(), mesh, deformed_vertex_buffer);
+                }
+            }
+        }
+    }
+
+    // Moves the scene node with all its children, instances are updated with the next rendered frame
+    pub fn set_scene_node_transform(&mut self, bundle_name: &str, node_id: usize, local_transform: &[f32; 16]) {
+        let mut instance_transforms = Vec::new();
+        for (name, resource_bundle, _, _) in &self.render_bundles {
+            if name == bundle_name {
+                instance_transforms = resource_bundle
+                    .borrow_mut()
+                    .set_scene_node_transform(node_id, local_transform);
+            }
+        }
+        for (bucket, instance_index, transform) in instance_transforms {
+            self.update_instance_transform(bundle_name, bucket, instance_index as u32, &transform);
+        }
+    }
+
+    // Effects are created against the half resolution layer and the shared frame data descriptor set layout
+    pub fn add_half_resolution_effect<F>(&mut self, factory: &mut DeviceFactory, create_effect: F)
+    where
+        F: FnOnce(&RenderLayer, vk::DescriptorSetLayout, &mut DeviceFactory) -> Box<dyn HalfResolutionEffect>,
+    {
+        let effect = create_effect(
+            self.half_resolution_pass.get_half_resolution_layer(),
+            self.shared_frame_data.descriptor_set_layout,
+            factory,
+        );
+        self.half_resolution_effects.push(effect);
+    }
+
+    // Passing None disables volumetric fog
+    pub fn set_volumetric_fog(&mut self, parameters: Option<&VolumetricFogParameters>) {
+        if let Some(parameters) = parameters {
+            if !self.enable_volumetric_fog {
+                self.volumetric_fog.reset_history();
+            }
+            self.volumetric_fog.set_parameters(parameters);
+            self.enable_volumetric_fog = true;
+        } else {
+            self.enable_volumetric_fog = false;
+        }
+    }
+
+    pub fn get_volumetric_fog(&self) -> Option<&VolumetricFogParameters> {
+        if self.enable_volumetric_fog {
+            Some(self.volumetric_fog.get_parameters())
+        } else {
+            None
+        }
+    }
+
+    // Passing None disables output dithering, has no effect without a target layer
+    pub fn set_dithering(&mut self, parameters: Option<&DitheringParameters>) {
+        if let Some(tone_map) = &mut self.tone_map {
+            tone_map.set_dithering(parameters);
+        }
+    }
+
+    pub fn get_dithering(&self) -> Option<&DitheringParameters> {
+        self.tone_map.as_ref().and_then(|tone_map| tone_map.get_dithering())
+    }
+
+    // Passing None disables screen space reflections, environment probe is used for all reflections
+    pub fn set_screen_space_reflections(&mut self, parameters: Option<&ScreenSpaceReflectionParameters>) {
+        if let Some(parameters) = parameters {
+            self.screen_space_reflections.set_parameters(parameters);
+            self.enable_screen_space_reflections = true;
+        } else {
+            self.enable_screen_space_reflections = false;
+        }
+    }
+
+    pub fn get_screen_space_reflections(&self) -> Option<&ScreenSpaceReflectionParameters> {
+        if self.enable_screen_space_reflections {
+            Some(self.screen_space_reflections.get_parameters())
+        } else {
+            None
+        }
+    }
+
+    // Passing None hides the water surface
+    pub fn set_water_surface(&mut self, parameters: Option<&WaterSurfaceParameters>) {
+        if let Some(parameters) = parameters {
+            self.water_surface.set_parameters(parameters);
+            self.enable_water_surface = true;
+        } else {
+            self.enable_water_surface = false;
+        }
+    }
+
+    // Animated effects use this time in seconds instead of the wall clock, e.g. for deterministic runs
+    pub fn set_animation_time(&mut self, animation_time: Option<f32>) {
+        self.water_surface.set_animation_time(animation_time);
+        self.animation_time = animation_time;
+    }
+
+    // Lights are clustered every frame, an empty slice disables punctual lighting
+    pub fn set_punctual_lights(&mut self, punctual_lights: &[PunctualLight]) {
+        self.shared_frame_data.set_punctual_lights(punctual_lights);
+    }
+
+    pub fn get_punctual_lights(&self) -> &[PunctualLight] {
+        self.shared_frame_data.get_punctual_lights()
+    }
+
+    pub fn get_water_surface(&self) -> Option<&WaterSurfaceParameters> {
+        if self.enable_water_surface {
+            Some(self.water_surface.get_parameters())
+        } else {
+            None
+        }
+    }
+
+    // Passing None hides the heatmap, opacity is clamped to [0, 1].
+    // Does nothing if the renderer was created without a target layer.
+    pub fn set_overdraw_heatmap(&mut self, opacity: Option<f32>) {
+        if self.overdraw_heatmap.is_some() {
+            self.overdraw_heatmap_opacity = opacity.map(|opacity| opacity.clamp(0.0, 1.0));
+        }
+    }
+
+    pub fn get_overdraw_heatmap(&self) -> Option<f32> {
+        self.overdraw_heatmap_opacity
+    }
+
+    // Shader clock has to be enabled on the device and the renderer created with a target layer
+    pub fn is_shader_clock_heatmap_available(&self) -> bool {
+        self.shader_clock_heatmap.is_some()
+    }
+
+    // Passing None hides the heatmap, does nothing if the heatmap is not available
+    pub fn set_shader_clock_heatmap(&mut self, parameters: Option<&ShaderClockHeatmapParameters>) {
+        if self.shader_clock_heatmap.is_some() {
+            self.shader_clock_heatmap_parameters = parameters.copied();
+        }
+    }
+
+    pub fn get_shader_clock_heatmap(&self) -> Option<&ShaderClockHeatmapParameters> {
+        self.shader_clock_heatmap_parameters.as_ref()
+    }
+
+    // Replaces the frame with a progressively path traced image, samples accumulate while nothing changes.
+    // Does nothing if ray tracing is not enabled or the renderer was created without a target layer.
+    pub fn set_reference_mode(&mut self, enable: bool) {
+        if let Some(path_tracer) = &mut self.path_tracer {
+            path_tracer.reset_accumulation();
+            self.reference_mode = enable;
+        }
+    }
+
+    pub fn get_reference_mode(&self) -> bool {
+        self.reference_mode
+    }
+
+    pub fn is_reference_mode_available(&self) -> bool {
+        self.path_tracer.is_some()
+    }
+
+    pub fn get_reference_sample_count(&self) -> Option<u32> {
+        match &self.path_tracer {
+            Some(path_tracer) if self.reference_mode => Some(path_tracer.get_sample_count()),
+            _ => None,
+        }
+    }
+
+    // Passing None hides the depth view, depth pyramid levels fall back to scene depth
+    // while screen space reflections are disabled. Does nothing if the renderer was created without a target layer.
+    pub fn set_depth_view(&mut self, parameters: Option<&DepthViewParameters>) {
+        if self.depth_view.is_some() {
+            self.depth_view_parameters = parameters.copied();
+        }
+    }
+
+    pub fn get_depth_view(&self) -> Option<&DepthViewParameters> {
+        self.depth_view_parameters.as_ref()
+    }
+
+    // Only available in debug builds with a target layer, setting it does nothing otherwise
+    pub fn is_nan_detection_available(&self) -> bool {
+        self.nan_detection.is_some()
+    }
+
+    pub fn set_nan_detection(&mut self, parameters: Option<&NanDetectionParameters>) {
+        if self.nan_detection.is_some() {
+            self.nan_detection_parameters = parameters.copied();
+        }
+    }
+
+    pub fn get_nan_detection(&self) -> Option<&NanDetectionParameters> {
+        self.nan_detection_parameters.as_ref()
+    }
+
+    // Lags behind by the number of buffered frames, None if NaN detection is not available
+    pub fn get_nan_detection_result(&self) -> Option<&NanDetectionResult> {
+        self.nan_detection
+            .as_ref()
+            .map(|nan_detection| nan_detection.get_result())
+    }
+
+    pub fn get_hi_z_level_count(&self) -> Option<u32> {
+        self.screen_space_reflections
+            .get_hi_z_pyramid()
+            .map(|(_, mip_sizes)| mip_sizes.len() as u32)
+    }
+
+    // Linear view space distances as R32_FLOAT, frame resources must not be in use by the device anymore.
+    // Depth pyramid levels fall back to scene depth the same way as in the depth view.
+    pub fn capture_linear_depth(
+        &self,
+        source: DepthViewSource,
+        command_buffer: &mut CommandBuffer,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) -> ScratchImage {
+        match (
+            self.get_hi_z_level(source),
+            self.screen_space_reflections.get_hi_z_pyramid(),
+        ) {
+            (Some(level), Some((hi_z_image, mip_sizes))) => capture_linear_depth(
+                &LinearDepthCaptureParameters {
+                    image: hi_z_image,
+                    image_layout: vk::ImageLayout::GENERAL,
+                    image_aspect: vk::ImageAspectFlags::COLOR,
+                    mip_level: level,
+                    mip_extent: mip_sizes[level as usize],
+                },
+                command_buffer,
+                factory,
+                queue,
+            ),
+            _ => capture_linear_depth(
+                &LinearDepthCaptureParameters {
+                    image: self.render_layer.get_depth_image().unwrap().0,
+                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    image_aspect: vk::ImageAspectFlags::DEPTH,
+                    mip_level: 0,
+                    mip_extent: self.render_size,
+                },
+                command_buffer,
+                factory,
+                queue,
+            ),
+        }
+    }
+
+    pub fn get_hdr_color_format(&self) -> HdrColorFormat {
+        self.hdr_color_format
+    }
+
+    // Lit HDR color before anti-aliasing and tone mapping in the HDR color format, frame resources must not be in use
+    // by the device anymore. Requires render target export to be enabled on the device.
+    pub fn capture_color(
+        &self,
+        command_buffer: &mut CommandBuffer,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) -> ScratchImage {
+        capture_render_target(
+            None,
+            &RenderTargetCaptureParameters {
+                image: self.render_layer.get_image_resource(0),
+                image_extent: vk::Extent3D {
+                    width: self.render_size.0,
+                    height: self.render_size.1,
+                    depth: 1,
+                },
+                image_aspect: vk::ImageAspectFlags::COLOR,
+                image_dxgi_format: self.hdr_color_format.get_dxgi_format(),
+                num_mip_levels: 1,
+                num_array_layers: 1,
+            },
+            command_buffer,
+            factory,
+            queue,
+        )
+    }
+
+    fn get_hi_z_level(&self, source: DepthViewSource) -> Option<u32> {
+        match source {
+            DepthViewSource::HiZ(level) if self.enable_screen_space_reflections => self
+                .get_hi_z_level_count()
+                .map(|level_count| level.min(level_count - 1)),
+            _ => None,
+        }
+    }
+
+    // Eye separation is in world units, stereo view is only available when multiview is enabled on the device
+    pub fn set_stereo_view(&mut self, eye_separation: Option<f32>) {
+        if self.stereo_view.is_some() {
+            self.stereo_eye_separation = eye_separation.map(|eye_separation| eye_separation.max(0.0));
+            self.shared_frame_data
+                .set_stereo_eye_separation(self.stereo_eye_separation.unwrap_or_default());
+        }
+    }
+
+    pub fn get_stereo_view(&self) -> Option<f32> {
+        self.stereo_eye_separation
+    }
+
+    // Head mounted displays provide their own per-eye matrices, eye separation is ignored while they are set
+    pub fn set_stereo_view_projections(&mut self, view_projections: Option<[ultraviolet::mat::Mat4; 2]>) {
+        self.shared_frame_data.set_stereo_view_projections(view_projections);
+    }
+
+    pub fn is_stereo_view_available(&self) -> bool {
+        self.stereo_view.is_some()
+    }
+
+    // Array layer 0 is the left eye, 1 is the right eye
+    pub fn get_stereo_layer(&self) -> Option<&RenderLayer> {
+        match (&self.stereo_view, self.stereo_eye_separation) {
+            (Some(stereo_view), Some(_)) => Some(stereo_view.get_stereo_layer()),
+            _ => None,
+        }
+    }
+
+    fn has_half_resolution_effects(&self) -> bool {
+        self.enable_volumetric_fog || !self.half_resolution_effects.is_empty()
+    }
+
     pub fn get_render_bundles(&self) -> &[(String, ResourceBundleReference, ShaderModuleBundle, PipelineBundle)] {
         &self.render_bundles
     }
 
+    pub fn get_render_bundle_files(&self) -> &[RenderBundleFiles] {
+        &self.render_bundle_files
+    }
+
+    // Has to be called when the application stops rendering for a while, e.g. when the window is minimized.
+    // Animated effects are frozen until resume() and render() must not be called in between.
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.water_surface.pause();
+        if self.animation_pause_time.is_none() {
+            self.animation_pause_time = Some(std::time::Instant::now());
+        }
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.water_surface.resume();
+        if let Some(pause_time) = self.animation_pause_time.take() {
+            self.animation_start_time += pause_time.elapsed();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
     pub fn debug_enable_anti_aliasing(&mut self, enable: bool) {
         self.debug_enable_anti_aliasing = enable;
     }
+
+    // Textures stored in a format that doesn't match their color space are rendered magenta
+    pub fn debug_highlight_color_space_mistakes(&mut self, enable: bool) {
+        self.debug_highlight_color_space_mistakes = enable;
+        for (_, resource_bundle, _, _) in &self.render_bundles {
+            resource_bundle.borrow_mut().color_space_debug = enable;
+        }
+    }
+
+    // Replaces the built-in temporal upscaler, passing None renders without one.
+    // Waits for the device to become idle because the old upscaler might still be in use.
+    pub fn set_upscaler(
+        &mut self,
+        upscaler: Option<Box<dyn Upscaler>>,
+        bundle_loader: &BundleLoader,
+        target_layer: &RenderLayer,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) {
+        device.wait_idle();
+        if let Some(old_upscaler) = &mut self.upscaler {
+            old_upscaler.destroy(factory);
+        }
+        self.upscaler = upscaler;
+
+        if let Some(tone_map) = &mut self.tone_map {
+            tone_map.destroy(factory);
+            let dithering = tone_map.get_dithering().copied();
+            let common_shaders = bundle_loader.get_common_shaders();
+            let shared_frame_data = &self.shared_frame_data;
+            let mut tone_map = if let Some(upscaler) = &self.upscaler {
+                ToneMap::new(
+                    common_shaders,
+                    shared_frame_data,
+                    &upscaler.get_output_layers(),
+                    0,
+                    target_layer,
+                    factory,
+                )
+            } else {
+                ToneMap::new(
+                    common_shaders,
+                    shared_frame_data,
+                    &[&self.render_layer],
+                    0,
+                    target_layer,
+                    factory,
+                )
+            };
+            tone_map.set_dithering(dithering.as_ref());
+            self.tone_map = Some(tone_map);
+        }
+    }
+}
+
+impl PbrForwardLit {
+    // Material samplers of all render bundles are created again, None restores the authored anisotropy.
+    // Waits for the device to become idle because the old samplers might still be in use.
+    pub fn set_sampler_anisotropy_override(
+        &mut self,
+        max_anisotropy: Option<f32>,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) {
+        device.wait_idle();
+        factory.set_sampler_anisotropy_override(max_anisotropy);
+        for (_, resource_bundle, _, _) in &self.render_bundles {
+            resource_bundle.borrow_mut().recreate_samplers(factory);
+        }
+    }
+
+    // Debug control to emulate low end texture settings, the bias is added to all material samplers
+    // and textures larger than the resolution never sample their top mips
+    pub fn set_texture_lod_override(
+        &mut self,
+        lod_bias: f32,
+        max_texture_resolution: Option<u32>,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) {
+        device.wait_idle();
+        factory.set_texture_lod_override(lod_bias, max_texture_resolution);
+        for (_, resource_bundle, _, _) in &self.render_bundles {
+            resource_bundle.borrow_mut().recreate_samplers(factory);
+        }
+    }
+
+    // Passing None disables adaptive scaling and keeps the current scale
+    pub fn set_adaptive_resolution(&mut self, target_frame_time: Option<f32>) {
+        self.adaptive_resolution_target = target_frame_time;
+    }
+
+    pub fn set_resolution_scale(&mut self, resolution_scale: f32) {
+        self.resolution_scale = resolution_scale.clamp(MIN_RESOLUTION_SCALE, 1.0);
+    }
+
+    pub fn get_resolution_scale(&self) -> f32 {
+        self.resolution_scale
+    }
+
+    pub fn get_render_statistics(&self) -> &RenderStatistics {
+        &self.render_statistics
+    }
+
+    // Forward pass GPU time in milliseconds, only updated when adaptive resolution is enabled
+    pub fn get_gpu_frame_time(&self) -> f32 {
+        self.gpu_frame_time
+    }
+
+    // Animated material instance data is pushed as push constants, so it only has to be updated on the CPU
+    fn update_material_animations(&mut self) {
+        let time = self
+            .animation_time
+            .unwrap_or_else(|| self.animation_start_time.elapsed().as_secs_f32());
+        for (_, resource_bundle, _, _) in &self.render_bundles {
+            let mut resource_bundle = resource_bundle.borrow_mut();
+            if !resource_bundle.material_animations.is_empty() {
+                resource_bundle.update_material_animations(time);
+            }
+        }
+    }
+
+    fn update_resolution_scale(&mut self, frame_context: &FrameContext, factory: &mut DeviceFactory) {
+        if let Some(target_frame_time) = self.adaptive_resolution_target {
+            if let Some(timestamps) = self.render_layer.try_get_oldest_timestamp(frame_context, factory) {
+                let elapsed_ticks = timestamps[1].wrapping_sub(timestamps[0]);
+                self.gpu_frame_time = (elapsed_ticks as f64 * self.timestamp_period as f64 / 1_000_000.0) as f32;
+
+                if self.gpu_frame_time > target_frame_time * 1.05 {
+                    self.set_resolution_scale(self.resolution_scale - RESOLUTION_SCALE_STEP);
+                } else if self.gpu_frame_time < target_frame_time * 0.85 {
+                    self.set_resolution_scale(self.resolution_scale + RESOLUTION_SCALE_STEP);
+                }
+            }
+        }
+        self.current_resolution_scale = self.resolution_scale;
+    }
 }
 
 impl PbrForwardLit {
@@ -426,9 +2088,132 @@ impl PbrForwardLit {
         self.render_layer.try_get_oldest_timestamp(frame_context, factory)
     }
 
+    // GPU begin and end time in nanoseconds of every enabled pass in the oldest buffered frame.
+    // Has to be called before render() reuses the queries, upscaler passes are not included.
+    pub fn try_get_gpu_pass_timings(
+        &self,
+        frame_context: &FrameContext,
+        factory: &mut DeviceFactory,
+    ) -> Vec<(&'static str, [u64; 2])> {
+        let timestamp_period = self.timestamp_period as f64;
+        self.get_enabled_passes()
+            .iter()
+            .filter_map(|(name, layer)| {
+                layer
+                    .try_get_oldest_timestamp(frame_context, factory)
+                    .map(|timestamps| {
+                        (
+                            *name,
+                            [
+                                (timestamps[0] as f64 * timestamp_period) as u64,
+                                (timestamps[1] as f64 * timestamp_period) as u64,
+                            ],
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    // Same as try_get_gpu_pass_timings(), empty if the device has pipeline statistics disabled
+    pub fn try_get_gpu_pass_statistics(
+        &self,
+        frame_context: &FrameContext,
+        factory: &mut DeviceFactory,
+    ) -> Vec<(&'static str, PipelineStatistics)> {
+        self.get_enabled_passes()
+            .iter()
+            .filter_map(|(name, layer)| {
+                layer
+                    .try_get_oldest_pipeline_statistics(frame_context, factory)
+                    .map(|statistics| (*name, statistics))
+            })
+            .collect()
+    }
+
+    // Passes submitted by the last render() followed by target passes, post processing renders into those
+    pub fn dump_frame_graph(&self, target_passes: &[(&str, &RenderLayer)]) -> FrameGraphDump {
+        let mut passes: Vec<(&str, &RenderLayer)> = Vec::new();
+        if let (Some(path_tracer), true) = (&self.path_tracer, self.reference_mode) {
+            passes.push(("path_tracer", path_tracer.get_accumulation_layer()));
+        } else {
+            passes.extend(self.get_enabled_passes());
+            if let Some(upscaler) = &self.upscaler {
+                passes.push(("upscaler", upscaler.get_output_layers()[upscaler.get_output_index()]));
+            }
+        }
+        passes.extend_from_slice(target_passes);
+        FrameGraphDump::new(&passes)
+    }
+
+    fn get_enabled_passes(&self) -> Vec<(&'static str, &RenderLayer)> {
+        let mut layers = vec![("forward_lit", &self.render_layer)];
+        if self.enable_screen_space_reflections {
+            layers.push((
+                "screen_space_reflections",
+                self.screen_space_reflections.get_composite_layer(),
+            ));
+        }
+        if self.enable_water_surface {
+            layers.push(("water_surface", self.water_surface.get_water_layer()));
+        }
+        if let Some(order_independent_transparency) = &self.order_independent_transparency {
+            layers.push((
+                "transparency_accumulation",
+                order_independent_transparency.get_accumulation_layer(),
+            ));
+            layers.push((
+                "transparency_composite",
+                order_independent_transparency.get_composite_layer(),
+            ));
+        }
+        if self.has_half_resolution_effects() {
+            layers.push((
+                "half_resolution_effects",
+                self.half_resolution_pass.get_half_resolution_layer(),
+            ));
+            layers.push((
+                "half_resolution_composite",
+                self.half_resolution_pass.get_composite_layer(),
+            ));
+        }
+        if let (Some(overdraw_heatmap), Some(_)) = (&self.overdraw_heatmap, self.overdraw_heatmap_opacity) {
+            layers.push(("overdraw_heatmap", overdraw_heatmap.get_overdraw_layer()));
+        }
+        if let (Some(shader_clock_heatmap), Some(_)) =
+            (&self.shader_clock_heatmap, self.shader_clock_heatmap_parameters)
+        {
+            layers.push(("shader_clock_heatmap", shader_clock_heatmap.get_cost_layer()));
+        }
+        if let (Some(path_tracer), true) = (&self.path_tracer, self.reference_mode) {
+            layers.push(("path_tracer", path_tracer.get_accumulation_layer()));
+        }
+        if let Some(stereo_layer) = self.get_stereo_layer() {
+            layers.push(("stereo_view", stereo_layer));
+        }
+        layers
+    }
+
     pub fn get_render_layer(&self) -> &RenderLayer {
-        if let Some(anti_aliasing) = &self.anti_aliasing {
-            anti_aliasing.get_previous_render_layer()
+        if let (Some(path_tracer), true) = (&self.path_tracer, self.reference_mode) {
+            path_tracer.get_accumulation_layer()
+        } else if let Some(upscaler) = &self.upscaler {
+            upscaler.get_output_layers()[upscaler.get_output_index()]
+        } else if let Some(stereo_layer) = self.get_stereo_layer() {
+            stereo_layer
+        } else if let (Some(shader_clock_heatmap), Some(_)) =
+            (&self.shader_clock_heatmap, self.shader_clock_heatmap_parameters)
+        {
+            shader_clock_heatmap.get_cost_layer()
+        } else if let (Some(overdraw_heatmap), Some(_)) = (&self.overdraw_heatmap, self.overdraw_heatmap_opacity) {
+            overdraw_heatmap.get_overdraw_layer()
+        } else if self.has_half_resolution_effects() {
+            self.half_resolution_pass.get_composite_layer()
+        } else if let Some(order_independent_transparency) = &self.order_independent_transparency {
+            order_independent_transparency.get_composite_layer()
+        } else if self.enable_water_surface {
+            self.water_surface.get_water_layer()
+        } else if self.enable_screen_space_reflections {
+            self.screen_space_reflections.get_composite_layer()
         } else {
             &self.render_layer
         }
@@ -438,3 +2223,273 @@ impl PbrForwardLit {
         &mut self.render_layer
     }
 }
+
+// Buckets without a pipeline in the given bundle are skipped, transparency set is bound right after the PBR resources
+// Contiguous range of buckets of one render bundle, instance ids continue from the buckets before it
+struct BucketRange<'a> {
+    resource_bundle: &'a ResourceBundle,
+    pipeline_bundle: &'a PipelineBundle,
+    buckets: std::ops::Range<usize>,
+    first_render_instance_id: usize,
+}
+
+enum ForwardPassJob<'a> {
+    Buckets(BucketRange<'a>),
+    SkyBox,
+}
+
+// Draws recorded by a single job, small enough to spread a large scene over all recording threads
+const RECORD_JOB_DRAW_COUNT: usize = 256;
+
+fn split_into_bucket_ranges<'a>(
+    resource_bundle: &'a ResourceBundle,
+    pipeline_bundle: &'a PipelineBundle,
+) -> Vec<BucketRange<'a>> {
+    let mut bucket_ranges = Vec::new();
+    let mut first_bucket = 0;
+    let mut first_render_instance_id = 0;
+    let mut draw_count = 0;
+    for (bucket_id, bucket) in resource_bundle.buckets.iter().enumerate() {
+        draw_count += bucket.instances.len();
+        if draw_count >= RECORD_JOB_DRAW_COUNT || bucket_id + 1 == resource_bundle.buckets.len() {
+            bucket_ranges.push(BucketRange {
+                resource_bundle,
+                pipeline_bundle,
+                buckets: first_bucket..bucket_id + 1,
+                first_render_instance_id,
+            });
+            first_bucket = bucket_id + 1;
+            first_render_instance_id += draw_count;
+            draw_count = 0;
+        }
+    }
+    bucket_ranges
+}
+
+fn render_buckets(
+    command_buffer: &mut CommandBuffer,
+    resource_bundle: &ResourceBundle,
+    pipeline_bundle: &PipelineBundle,
+    shared_frame_data: &SharedFrameData,
+    pbr_resource_bundle: &PbrResourceBundle,
+    transparency_descriptor_set: Option<vk::DescriptorSet>,
+    frame_context: &FrameContext,
+) -> RenderStatistics {
+    render_bucket_range(
+        command_buffer,
+        &BucketRange {
+            resource_bundle,
+            pipeline_bundle,
+            buckets: 0..resource_bundle.buckets.len(),
+            first_render_instance_id: 0,
+        },
+        shared_frame_data,
+        pbr_resource_bundle,
+        transparency_descriptor_set,
+        frame_context,
+    )
+}
+
+fn render_bucket_range(
+    command_buffer: &mut CommandBuffer,
+    bucket_range: &BucketRange,
+    shared_frame_data: &SharedFrameData,
+    pbr_resource_bundle: &PbrResourceBundle,
+    transparency_descriptor_set: Option<vk::DescriptorSet>,
+    frame_context: &FrameContext,
+) -> RenderStatistics {
+    let resource_bundle = bucket_range.resource_bundle;
+    let pipeline_bundle = bucket_range.pipeline_bundle;
+    let frame_data_descriptor_set = *shared_frame_data.get_frame_data_descriptor_set(frame_context);
+    let frame_data_offsets = [shared_frame_data.get_frame_data_offset(frame_context)];
+    let extra_descriptor_set_count = 2 + transparency_descriptor_set.is_some() as usize;
+
+    let mut render_statistics = RenderStatistics::default();
+
+    let mut render_instance_id = bucket_range.first_render_instance_id;
+    let mut bound_mesh_buffers = None; // vertex buffer bindings survive pipeline changes
+    for (bucket_id, bucket) in resource_bundle.buckets[bucket_range.buckets.clone()]
+        .iter()
+        .enumerate()
+        .map(|(bucket_offset, bucket)| (bucket_range.buckets.start + bucket_offset, bucket))
+    {
+        puffin::profile_scope!("render bucket");
+
+        let pipeline_layout = pipeline_bundle.pipeline_layouts[bucket.material.index()];
+        let pipeline = pipeline_bundle.pipelines[bucket.material.index()];
+        if pipeline == vk::Pipeline::null() {
+            render_instance_id += bucket.instances.len();
+            continue;
+        }
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, pipeline);
+        command_buffer.push_constants(
+            pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            shared_frame_data.get_subsample_view_projection().as_slice(),
+        );
+
+        let extra_descriptor_sets = [
+            frame_data_descriptor_set,
+            pbr_resource_bundle.descriptor_sets[0],
+            transparency_descriptor_set.unwrap_or(vk::DescriptorSet::null()),
+        ];
+
+        // Packed geometry is drawn with one indirect call per run of instances with the same material bindings
+        if let (Some(draw_command_buffer), Some(draw_count_buffer)) =
+            (&resource_bundle.draw_command_buffer, &resource_bundle.draw_count_buffer)
+        {
+            if pipeline_bundle.uses_push_descriptors() {
+                pipeline_bundle.push_bucket_descriptor_set(command_buffer, pipeline_layout, 1, bucket_id);
+                command_buffer.bind_descriptor_sets(
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    2,
+                    &extra_descriptor_sets[0..extra_descriptor_set_count],
+                    &frame_data_offsets,
+                );
+            } else {
+                let descriptor_sets = [
+                    pipeline_bundle.bucket_descriptor_sets[bucket_id],
+                    extra_descriptor_sets[0],
+                    extra_descriptor_sets[1],
+                    extra_descriptor_sets[2],
+                ];
+                command_buffer.bind_descriptor_sets(
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    1,
+                    &descriptor_sets[0..1 + extra_descriptor_set_count],
+                    &frame_data_offsets,
+                );
+            }
+
+            for multi_draw in &bucket.multi_draws {
+                let instance = &bucket.instances[multi_draw.first_instance];
+                command_buffer.push_constants(
+                    pipeline_layout,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    64,
+                    &instance.material_instance_data,
+                );
+                command_buffer.bind_descriptor_sets(
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    0,
+                    &[resource_bundle.get_material_descriptor_set(instance.material_instance)],
+                    &[],
+                );
+
+                let mesh = &resource_bundle.meshes[instance.mesh.index()];
+                if bound_mesh_buffers != Some((mesh.vertex_buffer, mesh.index_buffer)) {
+                    command_buffer.bind_vertex_buffers(
+                        0,
+                        &[resource_bundle.buffers[mesh.vertex_buffer.index()].0],
+                        &[0],
+                    );
+                    command_buffer.bind_index_buffer(
+                        resource_bundle.buffers[mesh.index_buffer.1.index()].0,
+                        0,
+                        mesh.index_buffer.0,
+                    );
+                    bound_mesh_buffers = Some((mesh.vertex_buffer, mesh.index_buffer));
+                }
+                command_buffer.draw_indexed_indirect_count(
+                    draw_command_buffer.0,
+                    (multi_draw.first_draw_command * std::mem::size_of::<vk::DrawIndexedIndirectCommand>()) as _,
+                    draw_count_buffer.0,
+                    (multi_draw.draw_count_id * std::mem::size_of::<u32>()) as _,
+                    multi_draw.instance_count as _,
+                    std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as _,
+                );
+
+                render_statistics.draw_call_count += 1;
+                for instance in &bucket.instances[multi_draw.first_instance..][..multi_draw.instance_count] {
+                    let mesh = &resource_bundle.meshes[instance.mesh.index()];
+                    render_statistics.instance_count += instance.total_instance_count;
+                    render_statistics.triangle_count += mesh.index_count / 3 * instance.total_instance_count;
+                }
+            }
+            render_instance_id += bucket.instances.len();
+            continue;
+        }
+
+        for instance in &bucket.instances {
+            command_buffer.push_constants(
+                pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                64,
+                &instance.material_instance_data,
+            );
+            if pipeline_bundle.uses_push_descriptors() {
+                command_buffer.bind_descriptor_sets(
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    0,
+                    &[resource_bundle.get_material_descriptor_set(instance.material_instance)],
+                    &[],
+                );
+                pipeline_bundle.push_instance_descriptor_set(command_buffer, pipeline_layout, 1, render_instance_id);
+                command_buffer.bind_descriptor_sets(
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    2,
+                    &extra_descriptor_sets[0..extra_descriptor_set_count],
+                    &frame_data_offsets,
+                );
+            } else {
+                let descriptor_sets = [
+                    resource_bundle.get_material_descriptor_set(instance.material_instance),
+                    pipeline_bundle.descriptor_sets[render_instance_id],
+                    extra_descriptor_sets[0],
+                    extra_descriptor_sets[1],
+                    extra_descriptor_sets[2],
+                ];
+                command_buffer.bind_descriptor_sets(
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    0,
+                    &descriptor_sets[0..2 + extra_descriptor_set_count],
+                    &frame_data_offsets,
+                );
+            }
+
+            // Packed mesh geometry is only bound once
+            let mesh = &resource_bundle.meshes[instance.mesh.index()];
+            if bound_mesh_buffers != Some((mesh.vertex_buffer, mesh.index_buffer)) {
+                command_buffer.bind_vertex_buffers(0, &[resource_bundle.buffers[mesh.vertex_buffer.index()].0], &[0]);
+                command_buffer.bind_index_buffer(
+                    resource_bundle.buffers[mesh.index_buffer.1.index()].0,
+                    0,
+                    mesh.index_buffer.0,
+                );
+                bound_mesh_buffers = Some((mesh.vertex_buffer, mesh.index_buffer));
+            }
+            // Instances without surviving clusters are skipped on the GPU
+            if let Some(draw_predicate_buffer) = &resource_bundle.draw_predicate_buffer {
+                command_buffer.begin_conditional_rendering(
+                    draw_predicate_buffer.0,
+                    (render_instance_id * std::mem::size_of::<u32>()) as _,
+                    false,
+                );
+            }
+            command_buffer.draw_indexed(
+                mesh.index_count as _,
+                instance.total_instance_count as _,
+                mesh.first_index as _,
+                mesh.vertex_offset as _,
+                0,
+            );
+            if resource_bundle.draw_predicate_buffer.is_some() {
+                command_buffer.end_conditional_rendering();
+            }
+
+            render_statistics.draw_call_count += 1;
+            render_statistics.instance_count += instance.total_instance_count;
+            render_statistics.triangle_count += mesh.index_count / 3 * instance.total_instance_count;
+            render_instance_id += 1;
+        }
+    }
+    render_statistics
+}
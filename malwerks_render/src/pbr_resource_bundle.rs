@@ -7,17 +7,39 @@ use malwerks_bundles::*;
 use malwerks_core::*;
 use malwerks_vk::*;
 
+// Local probes beyond this count are ignored
+pub const MAX_LOCAL_PROBES: usize = 8;
+
+// Area lights can only reference textures below this index, has to match gltf_pbr_material.glsl
+pub const MAX_AREA_LIGHT_TEXTURES: usize = 8;
+
+// Reflections of local probes are parallax corrected against this box, everything is in world space
+#[derive(Debug, Copy, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DiskProbeBox {
+    pub position: [f32; 3], // capture position
+    pub box_min: [f32; 3],
+    pub box_max: [f32; 3],
+    pub blend_distance: f32, // probe fades out within this distance from the box sides
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct DiskEnvironmentProbe {
     pub probe_image: DiskImage,
     pub iem_image: DiskImage,
     pub pmrem_image: DiskImage,
+    pub probe_box: Option<DiskProbeBox>, // None means the probe is infinitely far away
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct DiskPbrResourceBundle {
     pub precomputed_brdf_image: DiskImage,
     pub environment_probe: DiskEnvironmentProbe,
+    pub local_probes: Vec<DiskEnvironmentProbe>, // sorted by priority, the first probe covering a point wins
+    pub irradiance_volume: Option<DiskIrradianceVolume>,
+    pub ies_profiles: Option<DiskImage>, // every layer is a baked IES profile
+    pub ltc_tables: Option<DiskLtcTables>,
+    pub area_light_textures: Vec<DiskImage>,
+    pub blue_noise_image: DiskImage, // spatiotemporal blue noise, every layer is a frame
 }
 
 impl DiskPbrResourceBundle {
@@ -47,10 +69,15 @@ pub struct PbrResourceBundle {
     pub image_views: Vec<vk::ImageView>,
 
     pub linear_sampler: vk::Sampler,
+    pub clamp_sampler: vk::Sampler,
+    pub local_probe_buffer: HeapAllocatedResource<vk::Buffer>,
 
     pub descriptor_pool: vk::DescriptorPool,
     pub descriptor_set_layout: vk::DescriptorSetLayout,
     pub descriptor_sets: Vec<vk::DescriptorSet>,
+
+    blue_noise_image_id: usize,
+    blue_noise_layer_count: usize,
 }
 
 impl PbrResourceBundle {
@@ -62,6 +89,8 @@ impl PbrResourceBundle {
             factory.destroy_image_view(*image_view);
         }
         factory.destroy_sampler(self.linear_sampler);
+        factory.destroy_sampler(self.clamp_sampler);
+        factory.deallocate_buffer(&self.local_probe_buffer);
         factory.destroy_descriptor_pool(self.descriptor_pool);
         factory.destroy_descriptor_set_layout(self.descriptor_set_layout);
     }
@@ -72,22 +101,119 @@ impl PbrResourceBundle {
         factory: &mut DeviceFactory,
         queue: &mut DeviceQueue,
     ) -> Self {
-        let mut images = Vec::with_capacity(4);
-        let mut image_views = Vec::with_capacity(4);
+        let local_probes = if disk_resources.local_probes.len() > MAX_LOCAL_PROBES {
+            log::warn!(
+                "{} local probes found, only {} are used",
+                disk_resources.local_probes.len(),
+                MAX_LOCAL_PROBES
+            );
+            &disk_resources.local_probes[0..MAX_LOCAL_PROBES]
+        } else {
+            &disk_resources.local_probes[..]
+        };
 
-        let mut upload_batch = UploadBatch::new(command_buffer);
-        for disk_image in &[
+        // Local probes only need their irradiance and radiance images
+        let mut disk_images = vec![
             &disk_resources.precomputed_brdf_image,
             &disk_resources.environment_probe.probe_image,
             &disk_resources.environment_probe.iem_image,
             &disk_resources.environment_probe.pmrem_image,
-        ] {
+        ];
+        for local_probe in local_probes {
+            disk_images.push(&local_probe.iem_image);
+            disk_images.push(&local_probe.pmrem_image);
+        }
+
+        // Without a baked volume global irradiance is not attenuated anywhere
+        let default_irradiance_volume;
+        let irradiance_volume = match &disk_resources.irradiance_volume {
+            Some(irradiance_volume) => irradiance_volume,
+            None => {
+                default_irradiance_volume = create_default_irradiance_volume();
+                &default_irradiance_volume
+            }
+        };
+        let irradiance_volume_image_id = disk_images.len();
+        disk_images.push(&irradiance_volume.ambient_cube_image);
+
+        // Lights without IES profiles or textures never sample them, defaults only fill the bindings
+        let default_ies_profiles;
+        let ies_profiles = match &disk_resources.ies_profiles {
+            Some(ies_profiles) => ies_profiles,
+            None => {
+                default_ies_profiles =
+                    create_default_image(vk::ImageViewType::TYPE_2D_ARRAY, vk::Format::R32_SFLOAT, &[1.0]);
+                &default_ies_profiles
+            }
+        };
+        let ies_profiles_image_id = disk_images.len();
+        disk_images.push(ies_profiles);
+
+        // Identity transforms turn LTC into a plain clamped cosine
+        let default_ltc_tables;
+        let ltc_tables = match &disk_resources.ltc_tables {
+            Some(ltc_tables) => ltc_tables,
+            None => {
+                default_ltc_tables = DiskLtcTables {
+                    ltc_matrix_image: create_default_image(
+                        vk::ImageViewType::TYPE_2D,
+                        vk::Format::R32G32B32A32_SFLOAT,
+                        &[1.0, 0.0, 0.0, 1.0],
+                    ),
+                    ltc_amplitude_image: create_default_image(
+                        vk::ImageViewType::TYPE_2D,
+                        vk::Format::R32G32B32A32_SFLOAT,
+                        &[1.0, 0.0, 0.0, 0.0],
+                    ),
+                };
+                &default_ltc_tables
+            }
+        };
+        let ltc_tables_image_id = disk_images.len();
+        disk_images.push(&ltc_tables.ltc_matrix_image);
+        disk_images.push(&ltc_tables.ltc_amplitude_image);
+
+        let area_light_textures = if disk_resources.area_light_textures.len() > MAX_AREA_LIGHT_TEXTURES {
+            log::warn!(
+                "{} area light textures found, only {} are used",
+                disk_resources.area_light_textures.len(),
+                MAX_AREA_LIGHT_TEXTURES
+            );
+            &disk_resources.area_light_textures[0..MAX_AREA_LIGHT_TEXTURES]
+        } else {
+            &disk_resources.area_light_textures[..]
+        };
+        let default_area_light_texture = create_default_image(
+            vk::ImageViewType::TYPE_2D,
+            vk::Format::R32G32B32A32_SFLOAT,
+            &[1.0, 1.0, 1.0, 1.0],
+        );
+        let area_light_textures_image_id = disk_images.len();
+        disk_images.push(&default_area_light_texture);
+        for area_light_texture in area_light_textures {
+            disk_images.push(area_light_texture);
+        }
+
+        // Blue noise isn't part of the material descriptor set, shared frame data binds it
+        let blue_noise_image_id = disk_images.len();
+        disk_images.push(&disk_resources.blue_noise_image);
+
+        let mut images = Vec::with_capacity(disk_images.len());
+        let mut image_views = Vec::with_capacity(disk_images.len());
+
+        let mut upload_batch = UploadBatch::new(command_buffer);
+        for disk_image in &disk_images {
             let image_view_type = vk::ImageViewType::from_raw(disk_image.view_type);
             let image_flags = match image_view_type {
                 vk::ImageViewType::CUBE => vk::ImageCreateFlags::CUBE_COMPATIBLE,
                 vk::ImageViewType::CUBE_ARRAY => vk::ImageCreateFlags::CUBE_COMPATIBLE,
 
-                vk::ImageViewType::TYPE_2D_ARRAY => vk::ImageCreateFlags::TYPE_2D_ARRAY_COMPATIBLE,
+                // 2D array views of 3D images need a compatible image, regular 2D arrays don't
+                vk::ImageViewType::TYPE_2D_ARRAY
+                    if vk::ImageType::from_raw(disk_image.image_type) == vk::ImageType::TYPE_3D =>
+                {
+                    vk::ImageCreateFlags::TYPE_2D_ARRAY_COMPATIBLE
+                }
 
                 _ => vk::ImageCreateFlags::default(),
             };
@@ -153,18 +279,67 @@ impl PbrResourceBundle {
                 .min_filter(vk::Filter::LINEAR)
                 .address_mode_u(vk::SamplerAddressMode::REPEAT)
                 .address_mode_v(vk::SamplerAddressMode::REPEAT)
+                .anisotropy_enable(true)
+                .max_anisotropy(factory.get_max_sampler_anisotropy())
                 .min_lod(0.0)
                 .max_lod(std::f32::MAX)
                 .build(),
         );
+        let clamp_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .min_lod(0.0)
+                .max_lod(f32::MAX)
+                .build(),
+        );
+
+        let local_probe_buffer = factory.allocate_buffer(
+            &vk::BufferCreateInfo::builder()
+                .size(std::mem::size_of::<LocalProbeData>() as _)
+                .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::CpuToGpu,
+                ..Default::default()
+            },
+        );
+        {
+            let mut local_probe_data = LocalProbeData::default();
+            for (probe_id, local_probe) in local_probes.iter().enumerate() {
+                let probe_box = local_probe.probe_box.expect("local probe doesn't have a box");
+                let probe_data = &mut local_probe_data.probes[probe_id];
+                probe_data.position_blend_distance[0..3].copy_from_slice(&probe_box.position);
+                probe_data.position_blend_distance[3] = probe_box.blend_distance;
+                probe_data.box_min[0..3].copy_from_slice(&probe_box.box_min);
+                probe_data.box_max[0..3].copy_from_slice(&probe_box.box_max);
+            }
+            local_probe_data.probe_count[0] = local_probes.len() as _;
+            local_probe_data.irradiance_volume_min[0..3].copy_from_slice(&irradiance_volume.volume_min);
+            local_probe_data.irradiance_volume_max[0..3].copy_from_slice(&irradiance_volume.volume_max);
+
+            let local_probe_memory = factory.map_allocation_memory(&local_probe_buffer);
+            copy_to_mapped_memory(&[local_probe_data], local_probe_memory);
+            factory.unmap_allocation_memory(&local_probe_buffer);
+        }
 
         let descriptor_pool = factory.create_descriptor_pool(
             &vk::DescriptorPoolCreateInfo::builder()
                 .max_sets(1)
-                .pool_sizes(&[vk::DescriptorPoolSize::builder()
-                    .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                    .descriptor_count(4)
-                    .build()])
+                .pool_sizes(&[
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(8 + 2 * MAX_LOCAL_PROBES as u32 + MAX_AREA_LIGHT_TEXTURES as u32)
+                        .build(),
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::UNIFORM_BUFFER)
+                        .descriptor_count(1)
+                        .build(),
+                ])
                 .build(),
         );
         let descriptor_set_layout = factory.create_descriptor_set_layout(
@@ -173,25 +348,73 @@ impl PbrResourceBundle {
                     .binding(0)
                     .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
                     .descriptor_count(1)
-                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
                     .build(),
                 vk::DescriptorSetLayoutBinding::builder()
                     .binding(1)
                     .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
                     .descriptor_count(1)
-                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
                     .build(),
                 vk::DescriptorSetLayoutBinding::builder()
                     .binding(2)
                     .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
                     .descriptor_count(1)
-                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
                     .build(),
                 vk::DescriptorSetLayoutBinding::builder()
                     .binding(3)
                     .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
                     .descriptor_count(1)
-                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(4)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(MAX_LOCAL_PROBES as _)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(5)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(MAX_LOCAL_PROBES as _)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(6)
+                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(7)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(8)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(9)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(10)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(11)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(MAX_AREA_LIGHT_TEXTURES as _)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
                     .build(),
             ]),
         );
@@ -204,9 +427,9 @@ impl PbrResourceBundle {
                 .build(),
         );
 
-        let mut temp_writes = [vk::WriteDescriptorSet::default(); 4];
+        let mut temp_writes = [vk::WriteDescriptorSet::default(); 12];
         let mut temp_image_infos = [vk::DescriptorImageInfo::default(); 4];
-        for (image_id, image_view) in image_views.iter().enumerate() {
+        for (image_id, image_view) in image_views[0..4].iter().enumerate() {
             temp_image_infos[image_id] = vk::DescriptorImageInfo::builder()
                 .image_view(*image_view)
                 .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
@@ -219,19 +442,187 @@ impl PbrResourceBundle {
                 .image_info(&temp_image_infos[image_id..image_id + 1])
                 .build();
         }
+
+        // Unused local probe slots point to the global probe
+        let mut temp_local_iem_infos = [temp_image_infos[2]; MAX_LOCAL_PROBES];
+        let mut temp_local_pmrem_infos = [temp_image_infos[3]; MAX_LOCAL_PROBES];
+        for probe_id in 0..local_probes.len() {
+            temp_local_iem_infos[probe_id].image_view = image_views[4 + probe_id * 2];
+            temp_local_pmrem_infos[probe_id].image_view = image_views[5 + probe_id * 2];
+        }
+        temp_writes[4] = vk::WriteDescriptorSet::builder()
+            .dst_binding(4)
+            .dst_set(descriptor_sets[0])
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&temp_local_iem_infos)
+            .build();
+        temp_writes[5] = vk::WriteDescriptorSet::builder()
+            .dst_binding(5)
+            .dst_set(descriptor_sets[0])
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&temp_local_pmrem_infos)
+            .build();
+        let temp_buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(local_probe_buffer.0)
+            .offset(0)
+            .range(std::mem::size_of::<LocalProbeData>() as _)
+            .build()];
+        temp_writes[6] = vk::WriteDescriptorSet::builder()
+            .dst_binding(6)
+            .dst_set(descriptor_sets[0])
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .buffer_info(&temp_buffer_info)
+            .build();
+        let temp_irradiance_volume_info = [vk::DescriptorImageInfo::builder()
+            .image_view(image_views[irradiance_volume_image_id])
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .sampler(linear_sampler)
+            .build()];
+        temp_writes[7] = vk::WriteDescriptorSet::builder()
+            .dst_binding(7)
+            .dst_set(descriptor_sets[0])
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&temp_irradiance_volume_info)
+            .build();
+
+        // Lookup tables are sampled at the texel centers and must not wrap around
+        let light_image_ids = [ies_profiles_image_id, ltc_tables_image_id, ltc_tables_image_id + 1];
+        let mut temp_light_infos = [temp_irradiance_volume_info[0]; 3];
+        for (info, image_id) in temp_light_infos.iter_mut().zip(light_image_ids.iter()) {
+            info.image_view = image_views[*image_id];
+            info.sampler = clamp_sampler;
+        }
+        for (info_id, info) in temp_light_infos.iter().enumerate() {
+            temp_writes[8 + info_id] = vk::WriteDescriptorSet::builder()
+                .dst_binding(8 + info_id as u32)
+                .dst_set(descriptor_sets[0])
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(std::slice::from_ref(info))
+                .build();
+        }
+
+        // Unused area light texture slots point to a white texture
+        let mut temp_area_light_infos = [temp_light_infos[0]; MAX_AREA_LIGHT_TEXTURES];
+        for (texture_id, info) in temp_area_light_infos.iter_mut().enumerate() {
+            if texture_id < area_light_textures.len() {
+                info.image_view = image_views[area_light_textures_image_id + 1 + texture_id];
+            } else {
+                info.image_view = image_views[area_light_textures_image_id];
+            }
+        }
+        temp_writes[11] = vk::WriteDescriptorSet::builder()
+            .dst_binding(11)
+            .dst_set(descriptor_sets[0])
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&temp_area_light_infos)
+            .build();
         factory.update_descriptor_sets(&temp_writes, &[]);
 
         Self {
             images,
             image_views,
             linear_sampler,
+            clamp_sampler,
+            local_probe_buffer,
             descriptor_pool,
             descriptor_set_layout,
             descriptor_sets,
+            blue_noise_image_id,
+            blue_noise_layer_count: disk_resources.blue_noise_image.layer_count,
         }
     }
 
+    pub fn get_precomputed_brdf_image_view(&self) -> vk::ImageView {
+        self.image_views[0]
+    }
+
     pub fn get_probe_image_view(&self) -> vk::ImageView {
         self.image_views[1]
     }
+
+    pub fn get_iem_image_view(&self) -> vk::ImageView {
+        self.image_views[2]
+    }
+
+    pub fn get_pmrem_image_view(&self) -> vk::ImageView {
+        self.image_views[3]
+    }
+
+    pub fn get_blue_noise_image_view(&self) -> vk::ImageView {
+        self.image_views[self.blue_noise_image_id]
+    }
+
+    pub fn get_blue_noise_layer_count(&self) -> usize {
+        self.blue_noise_layer_count
+    }
+}
+
+// Matches LocalProbes uniform block in gltf_pbr_material.glsl
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct LocalProbe {
+    position_blend_distance: [f32; 4],
+    box_min: [f32; 4],
+    box_max: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct LocalProbeData {
+    probes: [LocalProbe; MAX_LOCAL_PROBES],
+    probe_count: [u32; 4],
+    irradiance_volume_min: [f32; 4],
+    irradiance_volume_max: [f32; 4],
+}
+
+// Single probe that passes the global irradiance through unchanged
+fn create_default_irradiance_volume() -> DiskIrradianceVolume {
+    let texel_size = 4 * std::mem::size_of::<f32>();
+    let mut pixels = Vec::with_capacity(6 * texel_size);
+    for _ in 0..6 * 4 {
+        pixels.extend_from_slice(&1.0f32.to_le_bytes());
+    }
+
+    DiskIrradianceVolume {
+        volume_min: [-1.0; 3],
+        volume_max: [1.0; 3],
+        probe_counts: [1; 3],
+        ambient_cube_image: DiskImage {
+            width: 6,
+            height: 1,
+            depth: 1,
+            block_size: texel_size,
+            mipmap_count: 1,
+            layer_count: 1,
+            image_type: vk::ImageType::TYPE_3D.as_raw(),
+            view_type: vk::ImageViewType::TYPE_3D.as_raw(),
+            format: vk::Format::R32G32B32A32_SFLOAT.as_raw(),
+            color_space: DiskColorSpace::Linear,
+            generate_mipmaps: false,
+            pixels,
+        },
+    }
+}
+
+// Single texel image used when the bundle doesn't provide one
+fn create_default_image(view_type: vk::ImageViewType, format: vk::Format, texel: &[f32]) -> DiskImage {
+    let mut pixels = Vec::with_capacity(std::mem::size_of_val(texel));
+    for component in texel {
+        pixels.extend_from_slice(&component.to_le_bytes());
+    }
+
+    DiskImage {
+        width: 1,
+        height: 1,
+        depth: 1,
+        block_size: pixels.len(),
+        mipmap_count: 1,
+        layer_count: 1,
+        image_type: vk::ImageType::TYPE_2D.as_raw(),
+        view_type: view_type.as_raw(),
+        format: format.as_raw(),
+        color_space: DiskColorSpace::Linear,
+        generate_mipmaps: false,
+        pixels,
+    }
 }
@@ -0,0 +1,48 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::camera::*;
+
+// Temporal anti-aliasing cycles through 8 subsample offsets, the image is stable after that
+pub const DEFAULT_SETTLE_FRAME_COUNT: usize = 8;
+
+// Decides when a frame has to be rendered in render on demand mode. Every change keeps rendering
+// for a few more frames, so that temporal effects converge before the renderer goes idle.
+pub struct RedrawTracker {
+    settle_frame_count: usize,
+    remaining_frame_count: usize,
+    last_view_projection: Option<ultraviolet::mat::Mat4>,
+}
+
+impl RedrawTracker {
+    pub fn new(settle_frame_count: usize) -> Self {
+        Self {
+            settle_frame_count: settle_frame_count.max(1),
+            remaining_frame_count: settle_frame_count.max(1),
+            last_view_projection: None,
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.remaining_frame_count = self.settle_frame_count;
+    }
+
+    pub fn needs_redraw(&self) -> bool {
+        self.remaining_frame_count > 0
+    }
+
+    // Camera is compared without the subsample offset, jitter alone doesn't count as movement
+    pub fn update_camera(&mut self, camera: &Camera) {
+        let (view_projection, _) = camera.calculate_view_projection([0.0, 0.0]);
+        if self.last_view_projection != Some(view_projection) {
+            self.last_view_projection = Some(view_projection);
+            self.invalidate();
+        }
+    }
+
+    pub fn frame_rendered(&mut self) {
+        self.remaining_frame_count = self.remaining_frame_count.saturating_sub(1);
+    }
+}
@@ -0,0 +1,295 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_dds::*;
+use malwerks_vk::*;
+
+// Shader readable image in SHADER_READ_ONLY_OPTIMAL layout, mip and layer counts describe the whole image
+pub struct RenderTargetCaptureParameters<'a> {
+    pub image: &'a HeapAllocatedResource<vk::Image>,
+    pub image_extent: vk::Extent3D,
+    pub image_aspect: vk::ImageAspectFlags,
+    pub image_dxgi_format: u32,
+    pub num_mip_levels: usize,
+    pub num_array_layers: usize,
+}
+
+// Copies the first mip and layer of a shader readable image into a CPU side image and waits for the copy
+// to finish. Without a wait semaphore the image must not be in use by the device anymore.
+pub fn capture_render_target(
+    wait_semaphore: Option<(vk::Semaphore, vk::PipelineStageFlags)>,
+    parameters: &RenderTargetCaptureParameters,
+    command_buffer: &mut CommandBuffer,
+    factory: &mut DeviceFactory,
+    queue: &mut DeviceQueue,
+) -> ScratchImage {
+    let &RenderTargetCaptureParameters {
+        image,
+        image_extent,
+        image_aspect,
+        image_dxgi_format,
+        num_mip_levels,
+        num_array_layers,
+    } = parameters;
+
+    command_buffer.reset();
+    command_buffer.begin(
+        &vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build(),
+    );
+
+    let temp_buffer = factory.allocate_buffer(
+        &vk::BufferCreateInfo::builder()
+            .size(image.1.get_size() as _)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .build(),
+        &vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::CpuOnly,
+            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE,
+            ..Default::default()
+        },
+    );
+
+    command_buffer.pipeline_barrier(
+        vk::PipelineStageFlags::HOST,
+        vk::PipelineStageFlags::TRANSFER,
+        None,
+        &[],
+        &[],
+        &[vk::ImageMemoryBarrier::builder()
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(!0)
+            .dst_queue_family_index(!0)
+            .image(image.0)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(image_aspect)
+                    .base_mip_level(0)
+                    .level_count(num_mip_levels as _)
+                    .base_array_layer(0)
+                    .layer_count(num_array_layers as _)
+                    .build(),
+            )
+            .build()],
+    );
+    command_buffer.copy_image_to_buffer(
+        image.0,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        temp_buffer.0,
+        &[vk::BufferImageCopy::builder()
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(image_aspect)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(image_extent)
+            .buffer_offset(0)
+            .build()],
+    );
+    command_buffer.pipeline_barrier(
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        None,
+        &[],
+        &[],
+        &[vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(!0)
+            .dst_queue_family_index(!0)
+            .image(image.0)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(image_aspect)
+                    .base_mip_level(0)
+                    .level_count(num_mip_levels as _)
+                    .base_array_layer(0)
+                    .layer_count(num_array_layers as _)
+                    .build(),
+            )
+            .build()],
+    );
+
+    command_buffer.end();
+    let (wait_semaphores, wait_dst_stage_mask) = match wait_semaphore {
+        Some((semaphore, stage_mask)) => (vec![semaphore], vec![stage_mask]),
+        None => (Vec::new(), Vec::new()),
+    };
+    queue.submit(
+        &[vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_dst_stage_mask)
+            .command_buffers(&[(*command_buffer).into()])
+            .build()],
+        vk::Fence::null(),
+    );
+    queue.wait_idle();
+
+    let mut scratch_image = ScratchImage::new(
+        image_extent.width,
+        image_extent.height,
+        image_extent.depth,
+        num_mip_levels as _,
+        num_array_layers as _,
+        image_dxgi_format,
+        false,
+    );
+
+    let temp_memory = factory.map_allocation_memory(&temp_buffer);
+    unsafe {
+        assert_eq!(scratch_image.as_slice().len(), temp_buffer.1.get_size());
+
+        let dst_slice = scratch_image.as_slice_mut();
+        std::ptr::copy_nonoverlapping(temp_memory, dst_slice.as_mut_ptr(), dst_slice.len());
+    }
+    factory.unmap_allocation_memory(&temp_buffer);
+
+    factory.deallocate_buffer(&temp_buffer);
+
+    scratch_image
+}
+
+// Image layout is restored after the copy
+pub struct LinearDepthCaptureParameters {
+    pub image: vk::Image,
+    pub image_layout: vk::ImageLayout,
+    pub image_aspect: vk::ImageAspectFlags,
+    pub mip_level: u32,
+    pub mip_extent: (u32, u32),
+}
+
+// Copies one mip of a 32 bit float depth image and converts it to linear view space distance,
+// infinitely far pixels are stored as f32::MAX. The image must not be in use by the device anymore.
+pub fn capture_linear_depth(
+    parameters: &LinearDepthCaptureParameters,
+    command_buffer: &mut CommandBuffer,
+    factory: &mut DeviceFactory,
+    queue: &mut DeviceQueue,
+) -> ScratchImage {
+    let &LinearDepthCaptureParameters {
+        image,
+        image_layout,
+        image_aspect,
+        mip_level,
+        mip_extent,
+    } = parameters;
+
+    command_buffer.reset();
+    command_buffer.begin(
+        &vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build(),
+    );
+
+    let pixel_count = (mip_extent.0 * mip_extent.1) as usize;
+    let temp_buffer = factory.allocate_buffer(
+        &vk::BufferCreateInfo::builder()
+            .size((pixel_count * std::mem::size_of::<f32>()) as _)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .build(),
+        &vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::CpuOnly,
+            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE,
+            ..Default::default()
+        },
+    );
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(image_aspect)
+        .base_mip_level(mip_level)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build();
+    command_buffer.pipeline_barrier(
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        vk::PipelineStageFlags::TRANSFER,
+        None,
+        &[],
+        &[],
+        &[vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(image_layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(!0)
+            .dst_queue_family_index(!0)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build()],
+    );
+    command_buffer.copy_image_to_buffer(
+        image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        temp_buffer.0,
+        &[vk::BufferImageCopy::builder()
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(image_aspect)
+                    .mip_level(mip_level)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: mip_extent.0,
+                height: mip_extent.1,
+                depth: 1,
+            })
+            .buffer_offset(0)
+            .build()],
+    );
+    command_buffer.pipeline_barrier(
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::ALL_COMMANDS,
+        None,
+        &[],
+        &[],
+        &[vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(image_layout)
+            .src_queue_family_index(!0)
+            .dst_queue_family_index(!0)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build()],
+    );
+
+    command_buffer.end();
+    queue.submit(
+        &[vk::SubmitInfo::builder()
+            .command_buffers(&[(*command_buffer).into()])
+            .build()],
+        vk::Fence::null(),
+    );
+    queue.wait_idle();
+
+    let mut scratch_image = ScratchImage::new(mip_extent.0, mip_extent.1, 1, 1, 1, DXGI_FORMAT_R32_FLOAT, false);
+
+    let temp_memory = factory.map_allocation_memory(&temp_buffer);
+    unsafe {
+        let depth = std::slice::from_raw_parts(temp_memory as *const f32, pixel_count);
+        for (pixel, depth) in scratch_image.as_slice_mut().chunks_exact_mut(4).zip(depth) {
+            pixel.copy_from_slice(&crate::linearize_depth(*depth).min(f32::MAX).to_le_bytes());
+        }
+    }
+    factory.unmap_allocation_memory(&temp_buffer);
+
+    factory.deallocate_buffer(&temp_buffer);
+
+    scratch_image
+}
@@ -0,0 +1,890 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+use crate::pbr_resource_bundle::*;
+use crate::shared_frame_data::*;
+
+const REFLECTION_GROUP_SIZE: u32 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ScreenSpaceReflectionParameters {
+    pub max_distance: f32,
+    pub thickness: f32,
+    pub max_roughness: f32,
+    pub max_iterations: u32,
+}
+
+impl Default for ScreenSpaceReflectionParameters {
+    fn default() -> Self {
+        Self {
+            max_distance: 64.0,
+            thickness: 0.5,
+            max_roughness: 0.7,
+            max_iterations: 64,
+        }
+    }
+}
+
+// Normal roughness and specular reflectance are read from the source layer,
+// the source color image is also the composite target
+pub struct ScreenSpaceReflectionLayerParameters<'a> {
+    pub source_layer: &'a RenderLayer,
+    pub source_color_image: usize,
+    pub source_color_format: vk::Format,
+    pub normal_roughness_image: usize,
+    pub specular_reflectance_image: usize,
+    pub render_width: u32,
+    pub render_height: u32,
+}
+
+pub struct ScreenSpaceReflections {
+    parameters: ScreenSpaceReflectionParameters,
+    composite_layer: RenderLayer,
+    source_color_image: usize,
+
+    hi_z_image: HeapAllocatedResource<vk::Image>,
+    hi_z_image_view: vk::ImageView,
+    hi_z_mip_views: Vec<vk::ImageView>,
+    hi_z_mip_sizes: Vec<(u32, u32)>,
+    reflection_image: HeapAllocatedResource<vk::Image>,
+    reflection_image_view: vk::ImageView,
+
+    point_sampler: vk::Sampler,
+    linear_sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    hi_z_descriptor_set_layout: vk::DescriptorSetLayout,
+    hi_z_descriptor_sets: Vec<vk::DescriptorSet>,
+    trace_descriptor_set_layout: vk::DescriptorSetLayout,
+    trace_descriptor_set: vk::DescriptorSet,
+    composite_descriptor_set_layout: vk::DescriptorSetLayout,
+    composite_descriptor_set: vk::DescriptorSet,
+
+    hi_z_module: vk::ShaderModule,
+    trace_module: vk::ShaderModule,
+    vert_module: vk::ShaderModule,
+    frag_module: vk::ShaderModule,
+
+    hi_z_pipeline_layout: vk::PipelineLayout,
+    hi_z_pipeline: vk::Pipeline,
+    trace_pipeline_layout: vk::PipelineLayout,
+    trace_pipeline: vk::Pipeline,
+    composite_pipeline_layout: vk::PipelineLayout,
+    composite_pipeline: vk::Pipeline,
+
+    layouts_initialized: bool,
+}
+
+impl ScreenSpaceReflections {
+    pub fn new(
+        common_shaders: &DiskCommonShaders,
+        shared_frame_data: &SharedFrameData,
+        pbr_resource_bundle: &PbrResourceBundle,
+        layer_parameters: &ScreenSpaceReflectionLayerParameters,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        let &ScreenSpaceReflectionLayerParameters {
+            source_layer,
+            source_color_image,
+            source_color_format,
+            normal_roughness_image,
+            specular_reflectance_image,
+            render_width,
+            render_height,
+        } = layer_parameters;
+
+        let composite_layer = RenderLayer::from_shared_images(
+            device,
+            factory,
+            source_layer,
+            render_width,
+            render_height,
+            &[SharedImageParameters {
+                image_index: source_color_image,
+                image_format: source_color_format,
+                initial_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }],
+        );
+
+        // Full mip chain of the closest scene depth
+        let hi_z_mip_count = 32 - render_width.max(render_height).leading_zeros();
+        let hi_z_image = factory.allocate_image(
+            &vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(vk::Format::R32_SFLOAT)
+                .extent(vk::Extent3D {
+                    width: render_width,
+                    height: render_height,
+                    depth: 1,
+                })
+                .mip_levels(hi_z_mip_count)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ..Default::default()
+            },
+        );
+        let hi_z_image_view = create_image_view(factory, hi_z_image.0, vk::Format::R32_SFLOAT, 0, hi_z_mip_count);
+        let mut hi_z_mip_views = Vec::with_capacity(hi_z_mip_count as _);
+        let mut hi_z_mip_sizes = Vec::with_capacity(hi_z_mip_count as _);
+        for level in 0..hi_z_mip_count {
+            hi_z_mip_views.push(create_image_view(
+                factory,
+                hi_z_image.0,
+                vk::Format::R32_SFLOAT,
+                level,
+                1,
+            ));
+            hi_z_mip_sizes.push(((render_width >> level).max(1), (render_height >> level).max(1)));
+        }
+
+        let reflection_image = factory.allocate_image(
+            &vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(vk::Format::R16G16B16A16_SFLOAT)
+                .extent(vk::Extent3D {
+                    width: render_width,
+                    height: render_height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ..Default::default()
+            },
+        );
+        let reflection_image_view =
+            create_image_view(factory, reflection_image.0, vk::Format::R16G16B16A16_SFLOAT, 0, 1);
+
+        let point_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .min_lod(0.0)
+                .max_lod(f32::MAX)
+                .build(),
+        );
+        let linear_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build(),
+        );
+
+        let descriptor_pool = factory.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(hi_z_mip_count + 2)
+                .pool_sizes(&[
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::STORAGE_IMAGE)
+                        .descriptor_count(hi_z_mip_count + 1)
+                        .build(),
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(hi_z_mip_count + 7)
+                        .build(),
+                ]),
+        );
+        let hi_z_descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(1)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+            ]),
+        );
+        let mut trace_bindings = Vec::with_capacity(6);
+        for binding in 0..5 {
+            trace_bindings.push(
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+            );
+        }
+        trace_bindings.push(
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(5)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                .build(),
+        );
+        let trace_descriptor_set_layout = factory
+            .create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::builder().bindings(&trace_bindings));
+        let composite_descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+            ]),
+        );
+
+        let mut set_layouts = vec![hi_z_descriptor_set_layout; hi_z_mip_count as _];
+        set_layouts.push(trace_descriptor_set_layout);
+        set_layouts.push(composite_descriptor_set_layout);
+        let mut descriptor_sets = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&set_layouts)
+                .build(),
+        );
+        let composite_descriptor_set = descriptor_sets.pop().unwrap();
+        let trace_descriptor_set = descriptor_sets.pop().unwrap();
+        let hi_z_descriptor_sets = descriptor_sets;
+
+        // First mip reads the scene depth, every other mip reads the previous one
+        let source_depth_image = source_layer
+            .get_depth_image()
+            .expect("Depth image is required for screen space reflections")
+            .1;
+        for (level, descriptor_set) in hi_z_descriptor_sets.iter().enumerate() {
+            let (source_image_view, source_image_layout) = if level == 0 {
+                (source_depth_image, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            } else {
+                (hi_z_mip_views[level - 1], vk::ImageLayout::GENERAL)
+            };
+            factory.update_descriptor_sets(
+                &[
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*descriptor_set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&[vk::DescriptorImageInfo::builder()
+                            .sampler(point_sampler)
+                            .image_view(source_image_view)
+                            .image_layout(source_image_layout)
+                            .build()])
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*descriptor_set)
+                        .dst_binding(1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&[vk::DescriptorImageInfo::builder()
+                            .image_view(hi_z_mip_views[level])
+                            .image_layout(vk::ImageLayout::GENERAL)
+                            .build()])
+                        .build(),
+                ],
+                &[],
+            );
+        }
+
+        let trace_images = [
+            (
+                linear_sampler,
+                source_layer.get_render_image(source_color_image).1,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            (
+                point_sampler,
+                source_depth_image,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            (
+                point_sampler,
+                source_layer.get_render_image(normal_roughness_image).1,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            (
+                point_sampler,
+                source_layer.get_render_image(specular_reflectance_image).1,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            (point_sampler, hi_z_image_view, vk::ImageLayout::GENERAL),
+        ];
+        for (binding, (sampler, image_view, image_layout)) in trace_images.iter().enumerate() {
+            factory.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::builder()
+                    .dst_set(trace_descriptor_set)
+                    .dst_binding(binding as _)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .sampler(*sampler)
+                        .image_view(*image_view)
+                        .image_layout(*image_layout)
+                        .build()])
+                    .build()],
+                &[],
+            );
+        }
+        factory.update_descriptor_sets(
+            &[
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(trace_descriptor_set)
+                    .dst_binding(5)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .image_view(reflection_image_view)
+                        .image_layout(vk::ImageLayout::GENERAL)
+                        .build()])
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(composite_descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .sampler(point_sampler)
+                        .image_view(reflection_image_view)
+                        .image_layout(vk::ImageLayout::GENERAL)
+                        .build()])
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(composite_descriptor_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .sampler(point_sampler)
+                        .image_view(source_layer.get_render_image(normal_roughness_image).1)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .build()])
+                    .build(),
+            ],
+            &[],
+        );
+
+        let hi_z_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.hi_z_downsample_compute_stage)
+                .build(),
+        );
+        let trace_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.reflection_trace_compute_stage)
+                .build(),
+        );
+        let vert_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.reflection_composite_vertex_stage)
+                .build(),
+        );
+        let frag_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.reflection_composite_fragment_stage)
+                .build(),
+        );
+
+        let hi_z_pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[hi_z_descriptor_set_layout])
+                .build(),
+        );
+        let trace_pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[
+                    shared_frame_data.descriptor_set_layout,
+                    pbr_resource_bundle.descriptor_set_layout,
+                    trace_descriptor_set_layout,
+                ])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .offset(0)
+                    .size(std::mem::size_of::<ScreenSpaceReflectionConstants>() as _)
+                    .build()])
+                .build(),
+        );
+        let composite_pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[composite_descriptor_set_layout])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .offset(0)
+                    .size(std::mem::size_of::<ScreenSpaceReflectionConstants>() as _)
+                    .build()])
+                .build(),
+        );
+
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let compute_pipelines = factory.create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[
+                vk::ComputePipelineCreateInfo::builder()
+                    .stage(
+                        vk::PipelineShaderStageCreateInfo::builder()
+                            .name(&entry_name)
+                            .module(hi_z_module)
+                            .stage(vk::ShaderStageFlags::COMPUTE)
+                            .build(),
+                    )
+                    .layout(hi_z_pipeline_layout)
+                    .build(),
+                vk::ComputePipelineCreateInfo::builder()
+                    .stage(
+                        vk::PipelineShaderStageCreateInfo::builder()
+                            .name(&entry_name)
+                            .module(trace_module)
+                            .stage(vk::ShaderStageFlags::COMPUTE)
+                            .build(),
+                    )
+                    .layout(trace_pipeline_layout)
+                    .build(),
+            ],
+        );
+
+        // Traced reflections are signed and added on top of the scene color
+        let vertex_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(vert_module)
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let fragment_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(frag_module)
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let composite_pipeline = factory.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[composite_layer.make_pipeline_create_info(
+                vk::GraphicsPipelineCreateInfo::builder()
+                    .stages(&[vertex_stage, fragment_stage])
+                    .vertex_input_state(
+                        &vk::PipelineVertexInputStateCreateInfo::builder()
+                            .vertex_binding_descriptions(&[])
+                            .build(),
+                    )
+                    .input_assembly_state(
+                        &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                            .primitive_restart_enable(false)
+                            .build(),
+                    )
+                    .tessellation_state(&Default::default())
+                    .viewport_state(
+                        &vk::PipelineViewportStateCreateInfo::builder()
+                            .viewport_count(1)
+                            .scissor_count(1)
+                            .build(),
+                    )
+                    .rasterization_state(
+                        &vk::PipelineRasterizationStateCreateInfo::builder()
+                            .line_width(1.0)
+                            .build(),
+                    )
+                    .multisample_state(
+                        &vk::PipelineMultisampleStateCreateInfo::builder()
+                            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                            .build(),
+                    )
+                    .depth_stencil_state(&Default::default())
+                    .color_blend_state(
+                        &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                            vk::PipelineColorBlendAttachmentState::builder()
+                                .blend_enable(true)
+                                .src_color_blend_factor(vk::BlendFactor::ONE)
+                                .dst_color_blend_factor(vk::BlendFactor::ONE)
+                                .color_blend_op(vk::BlendOp::ADD)
+                                .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+                                .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                                .alpha_blend_op(vk::BlendOp::ADD)
+                                .color_write_mask(
+                                    vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B
+                                        | vk::ColorComponentFlags::A,
+                                )
+                                .build(),
+                        ]),
+                    )
+                    .dynamic_state(
+                        &vk::PipelineDynamicStateCreateInfo::builder()
+                            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                            .build(),
+                    )
+                    .layout(composite_pipeline_layout)
+                    .subpass(0)
+                    .base_pipeline_handle(vk::Pipeline::null())
+                    .base_pipeline_index(0)
+                    .build(),
+            )],
+        )[0];
+
+        Self {
+            parameters: Default::default(),
+            composite_layer,
+            source_color_image,
+            hi_z_image,
+            hi_z_image_view,
+            hi_z_mip_views,
+            hi_z_mip_sizes,
+            reflection_image,
+            reflection_image_view,
+            point_sampler,
+            linear_sampler,
+            descriptor_pool,
+            hi_z_descriptor_set_layout,
+            hi_z_descriptor_sets,
+            trace_descriptor_set_layout,
+            trace_descriptor_set,
+            composite_descriptor_set_layout,
+            composite_descriptor_set,
+            hi_z_module,
+            trace_module,
+            vert_module,
+            frag_module,
+            hi_z_pipeline_layout,
+            hi_z_pipeline: compute_pipelines[0],
+            trace_pipeline_layout,
+            trace_pipeline: compute_pipelines[1],
+            composite_pipeline_layout,
+            composite_pipeline,
+            layouts_initialized: false,
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        self.composite_layer.destroy(factory);
+        factory.deallocate_image(&self.hi_z_image);
+        factory.destroy_image_view(self.hi_z_image_view);
+        for image_view in &self.hi_z_mip_views {
+            factory.destroy_image_view(*image_view);
+        }
+        factory.deallocate_image(&self.reflection_image);
+        factory.destroy_image_view(self.reflection_image_view);
+        factory.destroy_sampler(self.point_sampler);
+        factory.destroy_sampler(self.linear_sampler);
+        factory.destroy_descriptor_pool(self.descriptor_pool);
+        factory.destroy_descriptor_set_layout(self.hi_z_descriptor_set_layout);
+        factory.destroy_descriptor_set_layout(self.trace_descriptor_set_layout);
+        factory.destroy_descriptor_set_layout(self.composite_descriptor_set_layout);
+        factory.destroy_shader_module(self.hi_z_module);
+        factory.destroy_shader_module(self.trace_module);
+        factory.destroy_shader_module(self.vert_module);
+        factory.destroy_shader_module(self.frag_module);
+        factory.destroy_pipeline_layout(self.hi_z_pipeline_layout);
+        factory.destroy_pipeline(self.hi_z_pipeline);
+        factory.destroy_pipeline_layout(self.trace_pipeline_layout);
+        factory.destroy_pipeline(self.trace_pipeline);
+        factory.destroy_pipeline_layout(self.composite_pipeline_layout);
+        factory.destroy_pipeline(self.composite_pipeline);
+    }
+
+    pub fn set_parameters(&mut self, parameters: &ScreenSpaceReflectionParameters) {
+        self.parameters = *parameters;
+    }
+
+    pub fn get_parameters(&self) -> &ScreenSpaceReflectionParameters {
+        &self.parameters
+    }
+
+    pub fn get_hi_z_image_view(&self) -> vk::ImageView {
+        self.hi_z_image_view
+    }
+
+    // Depth pyramid image in GENERAL layout and its mip sizes.
+    // Pyramid contents are undefined before the first dispatch.
+    pub fn get_hi_z_pyramid(&self) -> Option<(vk::Image, &[(u32, u32)])> {
+        if self.layouts_initialized {
+            Some((self.hi_z_image.0, &self.hi_z_mip_sizes))
+        } else {
+            None
+        }
+    }
+
+    // Signals when the source color image contains composited reflections
+    pub fn get_composite_layer(&self) -> &RenderLayer {
+        &self.composite_layer
+    }
+
+    // Builds the depth pyramid and traces reflections, has to be recorded outside of a render pass.
+    // Source color, depth, normal and specular images are expected to be in SHADER_READ_ONLY_OPTIMAL layout.
+    pub fn dispatch(
+        &mut self,
+        command_buffer: &mut CommandBuffer,
+        screen_area: vk::Rect2D,
+        frame_data_descriptor_set: vk::DescriptorSet,
+        frame_data_offset: u32,
+        pbr_resource_bundle: &PbrResourceBundle,
+    ) {
+        puffin::profile_function!();
+
+        if !self.layouts_initialized {
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                None,
+                &[],
+                &[],
+                &[
+                    vk::ImageMemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::default())
+                        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(vk::ImageLayout::GENERAL)
+                        .src_queue_family_index(!0)
+                        .dst_queue_family_index(!0)
+                        .image(self.hi_z_image.0)
+                        .subresource_range(
+                            vk::ImageSubresourceRange::builder()
+                                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                .base_mip_level(0)
+                                .level_count(self.hi_z_mip_views.len() as _)
+                                .base_array_layer(0)
+                                .layer_count(1)
+                                .build(),
+                        )
+                        .build(),
+                    vk::ImageMemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::default())
+                        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(vk::ImageLayout::GENERAL)
+                        .src_queue_family_index(!0)
+                        .dst_queue_family_index(!0)
+                        .image(self.reflection_image.0)
+                        .subresource_range(
+                            vk::ImageSubresourceRange::builder()
+                                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                .base_mip_level(0)
+                                .level_count(1)
+                                .base_array_layer(0)
+                                .layer_count(1)
+                                .build(),
+                        )
+                        .build(),
+                ],
+            );
+            self.layouts_initialized = true;
+        }
+
+        // Previous frame might still be compositing the reflection image
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            None,
+            &[],
+            &[],
+            &[],
+        );
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, self.hi_z_pipeline);
+        for (level, descriptor_set) in self.hi_z_descriptor_sets.iter().enumerate() {
+            let (width, height) = self.hi_z_mip_sizes[level];
+            command_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                self.hi_z_pipeline_layout,
+                0,
+                &[*descriptor_set],
+                &[],
+            );
+            command_buffer.dispatch(
+                width.div_ceil(REFLECTION_GROUP_SIZE),
+                height.div_ceil(REFLECTION_GROUP_SIZE),
+                1,
+            );
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                None,
+                &[vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .build()],
+                &[],
+                &[],
+            );
+        }
+
+        let constants = self.make_constants(screen_area);
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, self.trace_pipeline);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            self.trace_pipeline_layout,
+            0,
+            &[
+                frame_data_descriptor_set,
+                pbr_resource_bundle.descriptor_sets[0],
+                self.trace_descriptor_set,
+            ],
+            &[frame_data_offset],
+        );
+        command_buffer.push_constants(
+            self.trace_pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &[constants],
+        );
+        command_buffer.dispatch(
+            screen_area.extent.width.div_ceil(REFLECTION_GROUP_SIZE),
+            screen_area.extent.height.div_ceil(REFLECTION_GROUP_SIZE),
+            1,
+        );
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build()],
+            &[],
+            &[],
+        );
+    }
+
+    // Filters traced reflections based on roughness and adds them to the source color image
+    pub fn render(
+        &mut self,
+        source_layer: &RenderLayer,
+        screen_area: vk::Rect2D,
+        frame_context: &FrameContext,
+        device: &mut Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) {
+        puffin::profile_function!();
+
+        let constants = self.make_constants(screen_area);
+        let source_image = source_layer.get_render_image(self.source_color_image).0;
+        self.composite_layer
+            .add_dependency(frame_context, source_layer, vk::PipelineStageFlags::FRAGMENT_SHADER);
+        self.composite_layer.acquire_frame(frame_context, device, factory);
+        self.composite_layer.begin_render_pass(frame_context, screen_area);
+        {
+            let command_buffer = self.composite_layer.get_command_buffer(frame_context);
+            command_buffer.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, self.composite_pipeline);
+            command_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::GRAPHICS,
+                self.composite_pipeline_layout,
+                0,
+                &[self.composite_descriptor_set],
+                &[],
+            );
+            command_buffer.push_constants(
+                self.composite_pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &[constants],
+            );
+            command_buffer.draw(3, 1, 0, 0);
+        }
+        self.composite_layer.end_render_pass(frame_context);
+
+        let command_buffer = self.composite_layer.get_command_buffer(frame_context);
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[],
+            &[],
+            &[vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(source_image)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(1)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build()],
+        );
+        self.composite_layer.submit_commands(frame_context, queue);
+    }
+
+    fn make_constants(&self, screen_area: vk::Rect2D) -> ScreenSpaceReflectionConstants {
+        ScreenSpaceReflectionConstants {
+            max_distance_thickness_max_roughness_unused: [
+                self.parameters.max_distance.max(1.0),
+                self.parameters.thickness.max(0.0),
+                self.parameters.max_roughness.clamp(0.01, 1.0),
+                0.0,
+            ],
+            render_area: [
+                screen_area.offset.x,
+                screen_area.offset.y,
+                screen_area.extent.width as _,
+                screen_area.extent.height as _,
+            ],
+            max_level_max_iterations_unused: [
+                self.hi_z_mip_views.len() as i32 - 1,
+                self.parameters.max_iterations as _,
+                0,
+                0,
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct ScreenSpaceReflectionConstants {
+    max_distance_thickness_max_roughness_unused: [f32; 4],
+    render_area: [i32; 4],
+    max_level_max_iterations_unused: [i32; 4],
+}
+
+fn create_image_view(
+    factory: &mut DeviceFactory,
+    image: vk::Image,
+    format: vk::Format,
+    base_mip_level: u32,
+    level_count: u32,
+) -> vk::ImageView {
+    factory.create_image_view(
+        &vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .components(vk::ComponentMapping::default())
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(base_mip_level)
+                    .level_count(level_count)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .build(),
+    )
+}
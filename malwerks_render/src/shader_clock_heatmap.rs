@@ -0,0 +1,191 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+
+#[derive(Debug, Copy, Clone)]
+pub struct ShaderClockHeatmapParameters {
+    pub max_cost: f32, // subgroup clock ticks per pixel that are displayed as red
+}
+
+impl Default for ShaderClockHeatmapParameters {
+    fn default() -> Self {
+        Self { max_cost: 100000.0 }
+    }
+}
+
+// Replaces the final image with the time spent shading every pixel of the forward pass.
+// Materials are rendered again with SHADER_CLOCK and the clock deltas of all their fragments are added up,
+// so hidden surfaces contribute just like they do in the overdraw heatmap.
+pub struct ShaderClockHeatmap {
+    cost_layer: RenderLayer,
+    heatmap: FullScreenPass,
+}
+
+impl ShaderClockHeatmap {
+    pub fn new(
+        common_shaders: &DiskCommonShaders,
+        target_layer: &RenderLayer,
+        render_width: u32,
+        render_height: u32,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        assert!(
+            device.is_shader_clock_enabled(),
+            "shader clock heatmap requires shader clock to be enabled"
+        );
+
+        // Clock ticks of all shaded fragments are added up in full precision
+        let cost_layer = RenderLayer::new(
+            device,
+            factory,
+            render_width,
+            render_height,
+            &RenderLayerParameters {
+                render_image_parameters: &[RenderImageParameters {
+                    image_format: vk::Format::R32_SFLOAT,
+                    image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                    image_clear_value: vk::ClearValue::default(),
+                }],
+                depth_image_parameters: None,
+                render_pass_parameters: &[RenderPassParameters {
+                    flags: vk::SubpassDescriptionFlags::default(),
+                    pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+                    input_attachments: None,
+                    color_attachments: Some(&[vk::AttachmentReference::builder()
+                        .attachment(0)
+                        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .build()]),
+                    resolve_attachments: None,
+                    depth_stencil_attachment: None,
+                    preserve_attachments: None,
+                }],
+                render_pass_dependencies: None,
+                view_mask: 0,
+            },
+        );
+
+        let heatmap = FullScreenPass::new(
+            &FullScreenPassParameters {
+                vertex_stage: &common_shaders.full_screen_vertex_stage,
+                fragment_stage: &common_shaders.shader_clock_heatmap_fragment_stage,
+                input_images: &[&[cost_layer.get_render_image(0).1]],
+                sampler_filter: vk::Filter::NEAREST,
+                additional_set_layouts: &[],
+                push_constants_size: 16,
+            },
+            target_layer,
+            factory,
+        );
+
+        Self { cost_layer, heatmap }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        self.cost_layer.destroy(factory);
+        self.heatmap.destroy(factory);
+    }
+
+    // Material pipelines are created against this layer with these blend states
+    pub fn get_cost_layer(&self) -> &RenderLayer {
+        &self.cost_layer
+    }
+
+    pub fn get_cost_blend_attachments(&self) -> [vk::PipelineColorBlendAttachmentState; 1] {
+        [vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .color_write_mask(vk::ColorComponentFlags::R)
+            .build()]
+    }
+
+    // Scene geometry is recorded into the returned command buffer until end_measuring() is called
+    pub fn begin_measuring(
+        &mut self,
+        dependency_layer: &RenderLayer,
+        screen_area: vk::Rect2D,
+        frame_context: &FrameContext,
+        device: &mut Device,
+        factory: &mut DeviceFactory,
+    ) -> &mut CommandBuffer {
+        puffin::profile_function!();
+
+        self.cost_layer
+            .add_dependency(frame_context, dependency_layer, vk::PipelineStageFlags::VERTEX_SHADER);
+        self.cost_layer.acquire_frame(frame_context, device, factory);
+        self.cost_layer.begin_render_pass(frame_context, screen_area);
+        self.cost_layer.get_command_buffer(frame_context)
+    }
+
+    pub fn end_measuring(&mut self, frame_context: &FrameContext, queue: &mut DeviceQueue) {
+        puffin::profile_function!();
+
+        self.cost_layer.end_render_pass(frame_context);
+        let cost_image = self.cost_layer.get_render_image(0).0;
+        let command_buffer = self.cost_layer.get_command_buffer(frame_context);
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[],
+            &[],
+            &[vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(cost_image)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(1)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build()],
+        );
+        self.cost_layer.submit_commands(frame_context, queue);
+    }
+
+    // Output area is the whole viewport, render scale maps it to the measured area
+    pub fn render(
+        &mut self,
+        output_area: vk::Rect2D,
+        render_scale: f32,
+        parameters: &ShaderClockHeatmapParameters,
+        frame_context: &FrameContext,
+        target_layer: &mut RenderLayer,
+    ) {
+        let command_buffer = target_layer.get_command_buffer(frame_context);
+        command_buffer.set_viewport(
+            0,
+            &[vk::Viewport {
+                x: output_area.offset.x as _,
+                y: output_area.offset.y as _,
+                width: output_area.extent.width as _,
+                height: output_area.extent.height as _,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+        );
+        command_buffer.set_scissor(0, &[output_area]);
+        self.heatmap
+            .push_constants(command_buffer, &[render_scale, render_scale, parameters.max_cost, 0.0]);
+        self.heatmap.render(command_buffer, 0, &[], &[]);
+    }
+}
@@ -7,16 +7,30 @@ use malwerks_core::*;
 use malwerks_vk::*;
 
 use crate::camera::*;
+use crate::light_clustering::*;
+use crate::pbr_resource_bundle::*;
+
+// Dynamic uniform buffer offsets must be multiples of minUniformBufferOffsetAlignment, which is at most 256
+const FRAME_DATA_ALIGNMENT: usize = 256;
 
 pub struct SharedFrameData {
     pub descriptor_pool: vk::DescriptorPool,
     pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub descriptor_update_template: vk::DescriptorUpdateTemplate,
 
     frame_data_descriptor_set: FrameLocal<vk::DescriptorSet>,
-    frame_data_buffer: FrameLocal<HeapAllocatedResource<vk::Buffer>>,
+    frame_data_buffer: HeapAllocatedResource<vk::Buffer>, // one slot per buffered frame, persistently mapped
+    frame_data_stride: usize,
+    punctual_light_buffer: FrameLocal<HeapAllocatedResource<vk::Buffer>>,
+    cluster_light_buffer: FrameLocal<HeapAllocatedResource<vk::Buffer>>,
+    punctual_lights: Vec<PunctualLight>,
+    blue_noise_sampler: vk::Sampler,
+    blue_noise_layer_count: usize,
 
     view_subsample_offset: [f32; 2],
     view_subsample_index: usize,
+    stereo_eye_separation: f32,
+    stereo_view_projections: Option<[ultraviolet::mat::Mat4; 2]>,
 
     previous_view_projection: ultraviolet::mat::Mat4,
     view_projection: ultraviolet::mat::Mat4,
@@ -24,12 +38,22 @@ pub struct SharedFrameData {
 }
 
 impl SharedFrameData {
-    pub fn new(factory: &mut DeviceFactory) -> Self {
-        let frame_data_buffer = FrameLocal::new(|_| {
+    pub fn new(pbr_resource_bundle: &PbrResourceBundle, factory: &mut DeviceFactory) -> Self {
+        let num_buffered_frames = factory.get_num_buffered_frames();
+        // Frame slots are selected with a dynamic offset at bind time, descriptors are never updated after creation
+        let frame_data_stride = get_frame_data_stride();
+        let frame_data_buffer = factory.allocate_dynamic_buffer(
+            &vk::BufferCreateInfo::builder()
+                .size((num_buffered_frames * frame_data_stride) as _)
+                .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+                .build(),
+            vk_mem::AllocationCreateFlags::MAPPED,
+        );
+        let punctual_light_buffer = FrameLocal::new(num_buffered_frames, |_| {
             factory.allocate_buffer(
                 &vk::BufferCreateInfo::builder()
-                    .size(std::mem::size_of::<PerFrameData>() as _)
-                    .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+                    .size((MAX_PUNCTUAL_LIGHTS * std::mem::size_of::<PunctualLightData>()) as _)
+                    .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
                     .build(),
                 &vk_mem::AllocationCreateInfo {
                     usage: vk_mem::MemoryUsage::CpuToGpu,
@@ -37,29 +61,76 @@ impl SharedFrameData {
                 },
             )
         });
+        // Cluster light lists are filled by the light clustering pass every frame
+        let cluster_light_buffer = FrameLocal::new(num_buffered_frames, |_| {
+            factory.allocate_buffer(
+                &vk::BufferCreateInfo::builder()
+                    .size(get_cluster_light_buffer_size() as _)
+                    .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+                    .build(),
+                &vk_mem::AllocationCreateInfo {
+                    usage: vk_mem::MemoryUsage::GpuOnly,
+                    ..Default::default()
+                },
+            )
+        });
 
         let descriptor_pool = factory.create_descriptor_pool(
             &vk::DescriptorPoolCreateInfo::builder()
-                .max_sets(NUM_BUFFERED_GPU_FRAMES as _)
-                .pool_sizes(&[vk::DescriptorPoolSize::builder()
-                    .ty(vk::DescriptorType::UNIFORM_BUFFER)
-                    .descriptor_count(1)
-                    .build()])
+                .max_sets(num_buffered_frames as _)
+                .pool_sizes(&[
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                        .descriptor_count(num_buffered_frames as _)
+                        .build(),
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(2 * num_buffered_frames as u32)
+                        .build(),
+                    vk::DescriptorPoolSize::builder()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(num_buffered_frames as _)
+                        .build(),
+                ])
                 .build(),
         );
         let descriptor_set_layout = factory.create_descriptor_set_layout(
             &vk::DescriptorSetLayoutCreateInfo::builder()
-                .bindings(&[vk::DescriptorSetLayoutBinding::builder()
-                    .binding(0)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .descriptor_count(1)
-                    .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
-                    .build()])
+                .bindings(&[
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                        .descriptor_count(1)
+                        .stage_flags(
+                            vk::ShaderStageFlags::VERTEX
+                                | vk::ShaderStageFlags::FRAGMENT
+                                | vk::ShaderStageFlags::COMPUTE,
+                        )
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(1)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(2)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(3)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE)
+                        .build(),
+                ])
                 .build(),
         );
 
         let per_descriptor_layouts: Vec<vk::DescriptorSetLayout> =
-            (0..NUM_BUFFERED_GPU_FRAMES).map(|_| descriptor_set_layout).collect();
+            (0..num_buffered_frames).map(|_| descriptor_set_layout).collect();
         let descriptor_sets = factory.allocate_descriptor_sets(
             &vk::DescriptorSetAllocateInfo::builder()
                 .descriptor_pool(descriptor_pool)
@@ -67,35 +138,100 @@ impl SharedFrameData {
                 .build(),
         );
 
-        let temp_buffer_infos: Vec<vk::DescriptorBufferInfo> = (0..NUM_BUFFERED_GPU_FRAMES)
-            .map(|frame| {
+        let descriptor_update_template = factory.create_descriptor_update_template(
+            &vk::DescriptorUpdateTemplateCreateInfo::builder()
+                .descriptor_update_entries(&[
+                    vk::DescriptorUpdateTemplateEntry::builder()
+                        .dst_binding(0)
+                        .dst_array_element(0)
+                        .descriptor_count(1)
+                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+                        .offset(0)
+                        .stride(std::mem::size_of::<vk::DescriptorBufferInfo>())
+                        .build(),
+                    vk::DescriptorUpdateTemplateEntry::builder()
+                        .dst_binding(1)
+                        .dst_array_element(0)
+                        .descriptor_count(2)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .offset(std::mem::size_of::<vk::DescriptorBufferInfo>())
+                        .stride(std::mem::size_of::<vk::DescriptorBufferInfo>())
+                        .build(),
+                ])
+                .template_type(vk::DescriptorUpdateTemplateType::DESCRIPTOR_SET)
+                .descriptor_set_layout(descriptor_set_layout)
+                .build(),
+        );
+        for (frame, descriptor_set) in descriptor_sets.iter().enumerate() {
+            // Buffer infos are laid out as the template entries: frame data, punctual lights, cluster lights
+            let buffer_infos = [
                 vk::DescriptorBufferInfo::builder()
-                    .buffer(frame_data_buffer.get_frame(frame).0)
+                    .buffer(frame_data_buffer.0)
                     .offset(0)
                     .range(std::mem::size_of::<PerFrameData>() as _)
-                    .build()
-            })
-            .collect();
-        let temp_writes: Vec<vk::WriteDescriptorSet> = (0..NUM_BUFFERED_GPU_FRAMES)
-            .map(|frame| {
+                    .build(),
+                vk::DescriptorBufferInfo::builder()
+                    .buffer(punctual_light_buffer.get_frame(frame).0)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build(),
+                vk::DescriptorBufferInfo::builder()
+                    .buffer(cluster_light_buffer.get_frame(frame).0)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)
+                    .build(),
+            ];
+            unsafe {
+                factory.update_descriptor_set_with_template(*descriptor_set, descriptor_update_template, &buffer_infos);
+            }
+        }
+
+        // Blue noise is the same for all frames, layers are selected by BlueNoiseLayer of the frame data
+        let blue_noise_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::REPEAT)
+                .address_mode_v(vk::SamplerAddressMode::REPEAT)
+                .min_lod(0.0)
+                .max_lod(0.0)
+                .build(),
+        );
+        let blue_noise_info = vk::DescriptorImageInfo::builder()
+            .image_view(pbr_resource_bundle.get_blue_noise_image_view())
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .sampler(blue_noise_sampler)
+            .build();
+        let blue_noise_writes: Vec<vk::WriteDescriptorSet> = descriptor_sets
+            .iter()
+            .map(|descriptor_set| {
                 vk::WriteDescriptorSet::builder()
-                    .dst_binding(0)
-                    .dst_set(descriptor_sets[frame])
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .buffer_info(&temp_buffer_infos[frame..=frame])
+                    .dst_set(*descriptor_set)
+                    .dst_binding(3)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(std::slice::from_ref(&blue_noise_info))
                     .build()
             })
             .collect();
-        factory.update_descriptor_sets(&temp_writes, &[]);
+        factory.update_descriptor_sets(&blue_noise_writes, &[]);
 
-        let frame_data_descriptor_set = FrameLocal::new(|frame| descriptor_sets[frame]);
+        let frame_data_descriptor_set = FrameLocal::new(num_buffered_frames, |frame| descriptor_sets[frame]);
         Self {
             descriptor_pool,
             descriptor_set_layout,
+            descriptor_update_template,
             frame_data_descriptor_set,
             frame_data_buffer,
+            frame_data_stride,
+            punctual_light_buffer,
+            cluster_light_buffer,
+            punctual_lights: Vec::new(),
+            blue_noise_sampler,
+            blue_noise_layer_count: pbr_resource_bundle.get_blue_noise_layer_count(),
             view_subsample_offset: Default::default(),
             view_subsample_index: Default::default(),
+            stereo_eye_separation: Default::default(),
+            stereo_view_projections: None,
             previous_view_projection: ultraviolet::mat::Mat4::identity(),
             view_projection: ultraviolet::mat::Mat4::identity(),
             subsample_view_projection: ultraviolet::mat::Mat4::identity(),
@@ -105,7 +241,12 @@ impl SharedFrameData {
     pub fn destroy(&mut self, factory: &mut DeviceFactory) {
         factory.destroy_descriptor_pool(self.descriptor_pool);
         factory.destroy_descriptor_set_layout(self.descriptor_set_layout);
-        self.frame_data_buffer
+        factory.destroy_descriptor_update_template(self.descriptor_update_template);
+        factory.destroy_sampler(self.blue_noise_sampler);
+        factory.deallocate_buffer(&self.frame_data_buffer);
+        self.punctual_light_buffer
+            .destroy(|buffer| factory.deallocate_buffer(buffer));
+        self.cluster_light_buffer
             .destroy(|buffer| factory.deallocate_buffer(buffer));
     }
 
@@ -118,9 +259,48 @@ impl SharedFrameData {
         self.view_subsample_offset = Default::default();
     }
 
-    pub fn update(&mut self, frame_context: &FrameContext, camera: &Camera, factory: &mut DeviceFactory) {
+    // Stereo view projections are used by multiview passes, both eyes match the camera when separation is zero
+    pub fn set_stereo_eye_separation(&mut self, eye_separation: f32) {
+        self.stereo_eye_separation = eye_separation;
+    }
+
+    // Explicit stereo view projections replace the ones derived from the camera and eye separation
+    pub fn set_stereo_view_projections(&mut self, view_projections: Option<[ultraviolet::mat::Mat4; 2]>) {
+        self.stereo_view_projections = view_projections;
+    }
+
+    // Lights beyond MAX_PUNCTUAL_LIGHTS are ignored
+    pub fn set_punctual_lights(&mut self, punctual_lights: &[PunctualLight]) {
+        if punctual_lights.len() > MAX_PUNCTUAL_LIGHTS {
+            log::warn!(
+                "{} punctual lights set, only {} are used",
+                punctual_lights.len(),
+                MAX_PUNCTUAL_LIGHTS
+            );
+        }
+        self.punctual_lights.clear();
+        self.punctual_lights
+            .extend_from_slice(&punctual_lights[0..punctual_lights.len().min(MAX_PUNCTUAL_LIGHTS)]);
+    }
+
+    pub fn get_punctual_lights(&self) -> &[PunctualLight] {
+        &self.punctual_lights
+    }
+
+    pub fn update(
+        &mut self,
+        frame_context: &FrameContext,
+        camera: &Camera,
+        render_scale: f32,
+        factory: &mut DeviceFactory,
+    ) {
+        // Subsample offsets are in pixels, scaled down resolution means larger offsets relative to the viewport
+        let view_subsample_offset = [
+            self.view_subsample_offset[0] / render_scale,
+            self.view_subsample_offset[1] / render_scale,
+        ];
         let view_position = -camera.position;
-        let (view_projection, subsample_view_projection) = camera.calculate_view_projection(self.view_subsample_offset);
+        let (view_projection, subsample_view_projection) = camera.calculate_view_projection(view_subsample_offset);
         let inverted_view_projection = view_projection.inversed();
         let view_reprojection = self.previous_view_projection * inverted_view_projection;
 
@@ -147,20 +327,50 @@ impl SharedFrameData {
             1.0 / viewport_size[0],
             1.0 / viewport_size[1],
         ];
+        per_frame_data.render_scale = [render_scale, render_scale, 0.0, 0.0];
+        let stereo_view_projections = self
+            .stereo_view_projections
+            .unwrap_or_else(|| camera.calculate_stereo_view_projections(self.stereo_eye_separation));
+        for (view_id, stereo_view_projection) in stereo_view_projections.iter().enumerate() {
+            per_frame_data.stereo_view_projection[view_id * 16..(view_id + 1) * 16]
+                .copy_from_slice(stereo_view_projection.as_slice());
+        }
+        per_frame_data.punctual_light_count[0] = self.punctual_lights.len() as _;
+        per_frame_data.blue_noise_layer = [
+            (frame_context.frame_index() % self.blue_noise_layer_count as u64) as _,
+            self.blue_noise_layer_count as _,
+            0,
+            0,
+        ];
         // per_frame_data
         //    .camera_orientation
         //    .copy_from_slice(camera.orientation.as_slice());
-        let frame_data_buffer = self.frame_data_buffer.get(frame_context);
-
-        let per_frame_memory = factory.map_allocation_memory(&frame_data_buffer);
+        // The slot of the current frame is not read by the GPU anymore, memory is coherent so no flush is needed
+        let frame_data_offset = self.get_frame_data_offset(frame_context) as usize;
+        let per_frame_memory = unsafe { self.frame_data_buffer.1.get_mapped_data().add(frame_data_offset) };
         copy_to_mapped_memory(&[per_frame_data], per_frame_memory);
-        factory.unmap_allocation_memory(&frame_data_buffer);
+
+        if !self.punctual_lights.is_empty() {
+            let light_data: Vec<PunctualLightData> = self
+                .punctual_lights
+                .iter()
+                .map(|punctual_light| punctual_light.to_light_data())
+                .collect();
+            let punctual_light_buffer = self.punctual_light_buffer.get(frame_context);
+            let punctual_light_memory = factory.map_allocation_memory(punctual_light_buffer);
+            copy_to_mapped_memory(&light_data, punctual_light_memory);
+            factory.unmap_allocation_memory(punctual_light_buffer);
+        }
 
         self.previous_view_projection = self.view_projection;
         self.view_projection = view_projection;
         self.subsample_view_projection = subsample_view_projection;
     }
 
+    pub fn get_subsample_offset(&self) -> [f32; 2] {
+        self.view_subsample_offset
+    }
+
     pub fn get_subsample_view_projection(&self) -> &ultraviolet::mat::Mat4 {
         &self.subsample_view_projection
     }
@@ -172,6 +382,15 @@ impl SharedFrameData {
     pub fn get_frame_data_descriptor_set(&self, frame_context: &FrameContext) -> &vk::DescriptorSet {
         self.frame_data_descriptor_set.get(frame_context)
     }
+
+    // Has to be passed as the dynamic offset every time the frame data descriptor set is bound
+    pub fn get_frame_data_offset(&self, frame_context: &FrameContext) -> u32 {
+        (frame_context.current_gpu_frame() * self.frame_data_stride) as _
+    }
+}
+
+fn get_frame_data_stride() -> usize {
+    std::mem::size_of::<PerFrameData>().div_ceil(FRAME_DATA_ALIGNMENT) * FRAME_DATA_ALIGNMENT
 }
 
 #[repr(C)]
@@ -183,6 +402,10 @@ struct PerFrameData {
     pub view_position: [f32; 4],
     pub camera_orientation: [f32; 4],
     pub viewport_size: [f32; 4],
+    pub render_scale: [f32; 4],
+    pub stereo_view_projection: [f32; 32], // left and right eye, indexed by gl_ViewIndex
+    pub punctual_light_count: [u32; 4],
+    pub blue_noise_layer: [u32; 4], // layer of the current frame and the layer count
 }
 
 const SUBSAMPLE_OFFSETS: [[f32; 2]; 8] = [
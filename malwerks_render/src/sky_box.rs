@@ -125,71 +125,76 @@ impl SkyBox {
                 .set_layouts(&[shared_frame_data.descriptor_set_layout, descriptor_set_layout])
                 .build(),
         );
+
+        // Sky only writes the color, remaining attachments keep their clear values
+        let mut color_blend_attachments = vec![
+            vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(false)
+                .color_write_mask(vk::ColorComponentFlags::empty())
+                .build();
+            target_layer.get_render_image_count()
+        ];
+        color_blend_attachments[0].color_write_mask = vk::ColorComponentFlags::R
+            | vk::ColorComponentFlags::G
+            | vk::ColorComponentFlags::B
+            | vk::ColorComponentFlags::A;
+
         let pipeline = factory.create_graphics_pipelines(
             vk::PipelineCache::null(),
-            &[vk::GraphicsPipelineCreateInfo::builder()
-                .stages(&[vertex_stage.build(), fragment_stage.build()])
-                .vertex_input_state(
-                    &vk::PipelineVertexInputStateCreateInfo::builder()
-                        .vertex_binding_descriptions(&[])
-                        .build(),
-                )
-                .input_assembly_state(
-                    &vk::PipelineInputAssemblyStateCreateInfo::builder()
-                        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-                        .primitive_restart_enable(false)
-                        .build(),
-                )
-                .tessellation_state(&Default::default())
-                .viewport_state(
-                    &vk::PipelineViewportStateCreateInfo::builder()
-                        .viewport_count(1)
-                        .scissor_count(1)
-                        .build(),
-                )
-                .rasterization_state(
-                    &vk::PipelineRasterizationStateCreateInfo::builder()
-                        .line_width(1.0)
-                        .build(),
-                )
-                .multisample_state(
-                    &vk::PipelineMultisampleStateCreateInfo::builder()
-                        .rasterization_samples(vk::SampleCountFlags::TYPE_1)
-                        .build(),
-                )
-                .depth_stencil_state(
-                    &vk::PipelineDepthStencilStateCreateInfo::builder()
-                        .flags(Default::default())
-                        .depth_test_enable(true)
-                        .depth_write_enable(false)
-                        .depth_compare_op(vk::CompareOp::EQUAL)
-                        .stencil_test_enable(false)
-                        .build(),
-                )
-                .color_blend_state(
-                    &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
-                        vk::PipelineColorBlendAttachmentState::builder()
-                            .blend_enable(false)
-                            .color_write_mask(
-                                vk::ColorComponentFlags::R
-                                    | vk::ColorComponentFlags::G
-                                    | vk::ColorComponentFlags::B
-                                    | vk::ColorComponentFlags::A,
-                            )
+            &[target_layer.make_pipeline_create_info(
+                vk::GraphicsPipelineCreateInfo::builder()
+                    .stages(&[vertex_stage.build(), fragment_stage.build()])
+                    .vertex_input_state(
+                        &vk::PipelineVertexInputStateCreateInfo::builder()
+                            .vertex_binding_descriptions(&[])
+                            .build(),
+                    )
+                    .input_assembly_state(
+                        &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                            .primitive_restart_enable(false)
+                            .build(),
+                    )
+                    .tessellation_state(&Default::default())
+                    .viewport_state(
+                        &vk::PipelineViewportStateCreateInfo::builder()
+                            .viewport_count(1)
+                            .scissor_count(1)
+                            .build(),
+                    )
+                    .rasterization_state(
+                        &vk::PipelineRasterizationStateCreateInfo::builder()
+                            .line_width(1.0)
                             .build(),
-                    ]),
-                )
-                .dynamic_state(
-                    &vk::PipelineDynamicStateCreateInfo::builder()
-                        .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
-                        .build(),
-                )
-                .layout(pipeline_layout)
-                .render_pass(target_layer.get_render_pass())
-                .subpass(0)
-                .base_pipeline_handle(vk::Pipeline::null())
-                .base_pipeline_index(0)
-                .build()],
+                    )
+                    .multisample_state(
+                        &vk::PipelineMultisampleStateCreateInfo::builder()
+                            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                            .build(),
+                    )
+                    .depth_stencil_state(
+                        &vk::PipelineDepthStencilStateCreateInfo::builder()
+                            .flags(Default::default())
+                            .depth_test_enable(true)
+                            .depth_write_enable(false)
+                            .depth_compare_op(vk::CompareOp::EQUAL)
+                            .stencil_test_enable(false)
+                            .build(),
+                    )
+                    .color_blend_state(
+                        &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&color_blend_attachments),
+                    )
+                    .dynamic_state(
+                        &vk::PipelineDynamicStateCreateInfo::builder()
+                            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                            .build(),
+                    )
+                    .layout(pipeline_layout)
+                    .subpass(0)
+                    .base_pipeline_handle(vk::Pipeline::null())
+                    .base_pipeline_index(0)
+                    .build(),
+            )],
         )[0];
 
         Self {
@@ -229,7 +234,7 @@ impl SkyBox {
                 *shared_frame_data.get_frame_data_descriptor_set(frame_context),
                 self.descriptor_set,
             ],
-            &[],
+            &[shared_frame_data.get_frame_data_offset(frame_context)],
         );
         command_buffer.draw(3, 1, 0, 0);
     }
@@ -0,0 +1,151 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+const STEREO_VIEW_MASK: u32 = 0b11;
+
+// Left and right eye rendered in one multiview pass, array layer 0 is the left eye.
+// Attachments mirror the main render layer so that the same material shaders can be used.
+pub struct StereoView {
+    stereo_layer: RenderLayer,
+}
+
+impl StereoView {
+    pub fn new(
+        render_width: u32,
+        render_height: u32,
+        color_format: vk::Format,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        let stereo_layer = RenderLayer::new(
+            device,
+            factory,
+            render_width,
+            render_height,
+            &RenderLayerParameters {
+                render_image_parameters: &[
+                    RenderImageParameters {
+                        image_format: color_format,
+                        image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                            | vk::ImageUsageFlags::SAMPLED
+                            | vk::ImageUsageFlags::TRANSFER_SRC,
+                        image_clear_value: vk::ClearValue::default(),
+                    },
+                    RenderImageParameters {
+                        image_format: vk::Format::R16G16B16A16_SFLOAT,
+                        image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                        image_clear_value: vk::ClearValue::default(),
+                    },
+                    RenderImageParameters {
+                        image_format: vk::Format::R8G8B8A8_UNORM,
+                        image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                        image_clear_value: vk::ClearValue::default(),
+                    },
+                ],
+                depth_image_parameters: Some(RenderImageParameters {
+                    image_format: vk::Format::D32_SFLOAT,
+                    image_usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                    image_clear_value: vk::ClearValue::default(),
+                }),
+                render_pass_parameters: &[RenderPassParameters {
+                    flags: vk::SubpassDescriptionFlags::default(),
+                    pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+                    input_attachments: None,
+                    color_attachments: Some(&[
+                        vk::AttachmentReference::builder()
+                            .attachment(0)
+                            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                            .build(),
+                        vk::AttachmentReference::builder()
+                            .attachment(1)
+                            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                            .build(),
+                        vk::AttachmentReference::builder()
+                            .attachment(2)
+                            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                            .build(),
+                    ]),
+                    resolve_attachments: None,
+                    depth_stencil_attachment: Some(
+                        &vk::AttachmentReference::builder()
+                            .attachment(3)
+                            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                            .build(),
+                    ),
+                    preserve_attachments: None,
+                }],
+                render_pass_dependencies: None,
+                view_mask: STEREO_VIEW_MASK,
+            },
+        );
+
+        Self { stereo_layer }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        self.stereo_layer.destroy(factory);
+    }
+
+    // Material pipelines are created against this layer with the MULTIVIEW macro defined
+    pub fn get_stereo_layer(&self) -> &RenderLayer {
+        &self.stereo_layer
+    }
+
+    // Scene geometry is recorded into the returned command buffer until end() is called
+    pub fn begin(
+        &mut self,
+        dependency_layer: &RenderLayer,
+        screen_area: vk::Rect2D,
+        frame_context: &FrameContext,
+        device: &mut Device,
+        factory: &mut DeviceFactory,
+    ) -> &mut CommandBuffer {
+        puffin::profile_function!();
+
+        self.stereo_layer
+            .add_dependency(frame_context, dependency_layer, vk::PipelineStageFlags::VERTEX_SHADER);
+        self.stereo_layer.acquire_frame(frame_context, device, factory);
+        self.stereo_layer.begin_render_pass(frame_context, screen_area);
+        self.stereo_layer.get_command_buffer(frame_context)
+    }
+
+    pub fn end(&mut self, frame_context: &FrameContext, queue: &mut DeviceQueue) {
+        puffin::profile_function!();
+
+        self.stereo_layer.end_render_pass(frame_context);
+        let stereo_image = self.stereo_layer.get_render_image(0).0;
+        let layer_count = self.stereo_layer.get_view_layer_count();
+        let command_buffer = self.stereo_layer.get_command_buffer(frame_context);
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[],
+            &[],
+            &[vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(stereo_image)
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(1)
+                        .base_array_layer(0)
+                        .layer_count(layer_count)
+                        .build(),
+                )
+                .build()],
+        );
+        self.stereo_layer.submit_commands(frame_context, queue);
+    }
+}
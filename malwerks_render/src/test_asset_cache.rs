@@ -0,0 +1,120 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::asset_cache::*;
+
+fn create_cache_folder(name: &str) -> std::path::PathBuf {
+    let cache_folder = std::env::temp_dir().join(format!("malwerks_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&cache_folder);
+    cache_folder
+}
+
+#[test]
+fn test_asset_cache_eviction() {
+    let cache_folder = create_cache_folder("asset_cache_eviction");
+    let mut asset_cache = AssetCache::new(&cache_folder, 300);
+
+    asset_cache.write("lantern", AssetCacheKind::ShaderCache, "a", &[0; 100]);
+    asset_cache.write("lantern", AssetCacheKind::PipelineCache, "b", &[0; 100]);
+    asset_cache.write("sponza", AssetCacheKind::Thumbnails, "c", &[0; 100]);
+    assert_eq!(asset_cache.get_total_size(), 300);
+
+    // Reading "a" makes "b" the least recently used entry
+    assert_eq!(
+        asset_cache.read("lantern", AssetCacheKind::ShaderCache, "a"),
+        Some(vec![0; 100])
+    );
+    asset_cache.write("sponza", AssetCacheKind::Thumbnails, "d", &[0; 100]);
+    assert!(asset_cache.contains("lantern", AssetCacheKind::ShaderCache, "a"));
+    assert!(!asset_cache.contains("lantern", AssetCacheKind::PipelineCache, "b"));
+    assert!(!asset_cache
+        .get_entry_path("lantern", AssetCacheKind::PipelineCache, "b")
+        .exists());
+    assert_eq!(asset_cache.get_total_size(), 300);
+
+    // Entries above the budget are kept until something else is cached
+    asset_cache.write("sponza", AssetCacheKind::Thumbnails, "e", &[0; 400]);
+    assert_eq!(asset_cache.get_bundle_sizes(), vec![("sponza".to_string(), 400)]);
+
+    let _ = std::fs::remove_dir_all(&cache_folder);
+}
+
+#[test]
+fn test_asset_cache_manifest() {
+    let cache_folder = create_cache_folder("asset_cache_manifest");
+    {
+        let mut asset_cache = AssetCache::new(&cache_folder, 1000);
+        asset_cache.write("lantern", AssetCacheKind::ShaderCache, "a", &[0; 10]);
+        asset_cache.write("lantern", AssetCacheKind::ImportManifest, "b", &[0; 20]);
+        asset_cache.write("sponza", AssetCacheKind::ShaderCache, "c", &[0; 30]);
+
+        // Reads only mark the manifest as dirty, it is written when the cache is dropped
+        let manifest_file = cache_folder.join("asset_cache.json");
+        let manifest = std::fs::read(&manifest_file).unwrap();
+        asset_cache.read("lantern", AssetCacheKind::ShaderCache, "a");
+        assert_eq!(std::fs::read(&manifest_file).unwrap(), manifest);
+    }
+
+    // Access order survives a restart, missing files are dropped
+    std::fs::remove_file(AssetCache::new(&cache_folder, 1000).get_entry_path(
+        "sponza",
+        AssetCacheKind::ShaderCache,
+        "c",
+    ))
+    .unwrap();
+    let mut asset_cache = AssetCache::new(&cache_folder, 1000);
+    assert_eq!(asset_cache.get_bundle_sizes(), vec![("lantern".to_string(), 30)]);
+    asset_cache.set_size_budget(15);
+    assert!(asset_cache.contains("lantern", AssetCacheKind::ShaderCache, "a"));
+    assert_eq!(asset_cache.get_total_size(), 10);
+
+    asset_cache.clear_bundle("lantern");
+    assert_eq!(asset_cache.get_total_size(), 0);
+    assert!(!asset_cache
+        .get_entry_path("lantern", AssetCacheKind::ShaderCache, "a")
+        .exists());
+
+    let _ = std::fs::remove_dir_all(&cache_folder);
+}
+
+#[test]
+fn test_asset_cache_write_failure() {
+    let cache_folder = create_cache_folder("asset_cache_write_failure");
+    let mut asset_cache = AssetCache::new(&cache_folder, 1000);
+
+    // Bundle folder can't be created when a file is in the way
+    std::fs::write(cache_folder.join("lantern"), []).unwrap();
+    asset_cache.write("lantern", AssetCacheKind::ShaderCache, "a", &[0; 10]);
+    assert!(!asset_cache.contains("lantern", AssetCacheKind::ShaderCache, "a"));
+
+    asset_cache.insert("sponza", AssetCacheKind::ShaderCache, "b");
+    assert!(!asset_cache.contains("sponza", AssetCacheKind::ShaderCache, "b"));
+    assert_eq!(asset_cache.get_total_size(), 0);
+
+    let _ = std::fs::remove_dir_all(&cache_folder);
+}
+
+#[test]
+fn test_asset_cache_bundle_name() {
+    let root_folder = create_cache_folder("asset_cache_bundle_name");
+    std::fs::create_dir_all(root_folder.join("a")).unwrap();
+    std::fs::create_dir_all(root_folder.join("b")).unwrap();
+
+    // Same file name in different folders
+    let bundle_a = get_asset_cache_bundle_name(&root_folder.join("a").join("scene.bundle"));
+    let bundle_b = get_asset_cache_bundle_name(&root_folder.join("b").join("scene.bundle"));
+    assert_ne!(bundle_a, bundle_b);
+    assert!(bundle_a.starts_with("scene_"));
+
+    // Different spellings of the same path, before and after the file is written
+    let bundle_file = root_folder.join("a").join("scene.bundle");
+    let other_spelling = root_folder.join("b").join("..").join("a").join("scene.bundle");
+    assert_eq!(get_asset_cache_bundle_name(&other_spelling), bundle_a);
+    std::fs::write(&bundle_file, []).unwrap();
+    assert_eq!(get_asset_cache_bundle_name(&bundle_file), bundle_a);
+    assert_eq!(get_asset_cache_bundle_name(&other_spelling), bundle_a);
+
+    let _ = std::fs::remove_dir_all(&root_folder);
+}
@@ -0,0 +1,46 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::blue_noise::*;
+
+#[test]
+fn test_blue_noise_layers_are_permutations() {
+    let ranks = generate_blue_noise_ranks(8, 4);
+    assert_eq!(ranks.len(), 8 * 8 * 4);
+    for layer in ranks.chunks(8 * 8) {
+        let mut sorted_layer = layer.to_vec();
+        sorted_layer.sort_unstable();
+        assert!(sorted_layer
+            .iter()
+            .enumerate()
+            .all(|(rank, value)| *value == rank as u32));
+    }
+
+    // Same noise has to be generated on every import
+    assert_eq!(ranks, generate_blue_noise_ranks(8, 4));
+}
+
+#[test]
+fn test_blue_noise_texels_change_over_time() {
+    // Every texel cycles through different values instead of repeating the first layer
+    let ranks = generate_blue_noise_ranks(8, 4);
+    let changing_texel_count = (0..8 * 8)
+        .filter(|texel| (1..4).any(|layer| ranks[layer * 8 * 8 + texel] != ranks[*texel]))
+        .count();
+    assert_eq!(changing_texel_count, 8 * 8);
+}
+
+#[test]
+fn test_blue_noise_image() {
+    let image = bake_blue_noise(8, 2);
+    assert_eq!((image.width, image.height, image.layer_count), (8, 8, 2));
+    assert_eq!(image.pixels.len(), 8 * 8 * 2);
+
+    // 64 ranks are spread over the whole 8 bit range with a step of 4
+    let mut first_layer = image.pixels[0..64].to_vec();
+    first_layer.sort_unstable();
+    assert_eq!(first_layer[0], 0);
+    assert_eq!(first_layer[63], 252);
+}
@@ -0,0 +1,47 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::command_registry::*;
+
+#[test]
+fn test_split_command_line() {
+    assert_eq!(split_command_line("  ").unwrap(), Vec::<String>::new());
+    assert_eq!(
+        split_command_line("load_bundle \"sponza/Sponza scene.gltf\"  1").unwrap(),
+        vec!["load_bundle", "sponza/Sponza scene.gltf", "1"]
+    );
+    assert_eq!(split_command_line("echo \"\"").unwrap(), vec!["echo", ""]);
+    assert!(split_command_line("echo \"unterminated").is_err());
+}
+
+#[test]
+fn test_command_registry() {
+    let mut registry = CommandRegistry::<Vec<String>>::new();
+    registry.register("push", "adds arguments to the list", |values, arguments| {
+        values.extend(arguments.iter().map(|argument| argument.to_string()));
+        Ok(format!("{} values", values.len()))
+    });
+    registry.register("clear", "removes all values", |values, _| {
+        values.clear();
+        Ok(String::new())
+    });
+
+    let mut values = Vec::new();
+    assert_eq!(registry.execute(&mut values, "push a b"), Ok("2 values".to_string()));
+    assert_eq!(values, vec!["a", "b"]);
+    assert_eq!(registry.execute(&mut values, ""), Ok(String::new()));
+    assert!(registry.execute(&mut values, "pop").is_err());
+
+    // Help lists commands in registration order, re-registered commands move to the end
+    registry.register("push", "disabled", |_, _| Err("disabled".to_string()));
+    assert_eq!(
+        registry.execute(&mut values, "help"),
+        Ok("clear - removes all values\npush - disabled".to_string())
+    );
+    assert!(registry.execute(&mut values, "push c").is_err());
+
+    registry.unregister("clear");
+    assert_eq!(registry.get_commands(), vec![("push", "disabled")]);
+}
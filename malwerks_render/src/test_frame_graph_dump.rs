@@ -0,0 +1,68 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::frame_graph_dump::*;
+
+fn make_pass(name: &str, images: &[(&str, u64)]) -> FrameGraphPass {
+    FrameGraphPass {
+        name: name.to_string(),
+        view_mask: 0,
+        dynamic_rendering: false,
+        attachments: images
+            .iter()
+            .map(|(attachment_name, image)| FrameGraphAttachment {
+                name: attachment_name.to_string(),
+                format: "R16G16B16A16_SFLOAT".to_string(),
+                image: *image,
+            })
+            .collect(),
+    }
+}
+
+fn make_frame_graph_dump() -> FrameGraphDump {
+    FrameGraphDump {
+        passes: vec![
+            make_pass("forward_lit", &[("color0", 1), ("depth", 2)]),
+            make_pass("water_surface", &[("color0", 1)]),
+            make_pass("surface", &[]),
+        ],
+        dependencies: vec![
+            FrameGraphDependency {
+                source_pass: Some("forward_lit".to_string()),
+                target_pass: "water_surface".to_string(),
+                wait_stage_mask: "TRANSFER".to_string(),
+            },
+            FrameGraphDependency {
+                source_pass: None,
+                target_pass: "surface".to_string(),
+                wait_stage_mask: "COLOR_ATTACHMENT_OUTPUT".to_string(),
+            },
+        ],
+    }
+}
+
+#[test]
+fn test_frame_graph_dump_dot() {
+    let dot = make_frame_graph_dump().to_dot();
+    assert!(dot.starts_with("digraph frame_graph {\n"));
+    assert!(dot.contains("pass_0 [label=\"{forward_lit|color0: R16G16B16A16_SFLOAT|depth: R16G16B16A16_SFLOAT}\"];"));
+    assert!(dot.contains("pass_2 [label=\"{surface}\"];"));
+    assert!(dot.contains("external [shape=ellipse];"));
+    assert!(dot.contains("pass_0 -> pass_1 [label=\"TRANSFER\"];"));
+    assert!(dot.contains("external -> pass_2 [label=\"COLOR_ATTACHMENT_OUTPUT\"];"));
+
+    // Water surface renders on top of the forward pass color
+    assert!(dot.contains("pass_0 -> pass_1 [style=dashed, label=\"color0\"];"));
+    assert_eq!(dot.matches("style=dashed").count(), 1);
+    assert!(dot.ends_with("}\n"));
+}
+
+#[test]
+fn test_frame_graph_dump_json() {
+    let frame_graph_dump = make_frame_graph_dump();
+    let json = frame_graph_dump.to_json();
+    assert_eq!(serde_json::from_str::<FrameGraphDump>(&json).unwrap(), frame_graph_dump);
+    assert!(json.contains("\"source_pass\": null"));
+}
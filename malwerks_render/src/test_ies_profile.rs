@@ -0,0 +1,52 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::ies_profile::*;
+
+// Quadrant symmetric profile, 3 vertical and 2 horizontal angles
+const TEST_PROFILE: &str = "IESNA:LM-63-2002
+[TEST] test
+[MANUFAC] malwerks
+TILT=NONE
+1 1000 2.0 3 2 1 1 0.0 0.0 0.0
+1.0 1.0 100
+0 45 90
+0 90
+100 50 0
+200 100 0
+";
+
+#[test]
+fn test_parse_ies_profile() {
+    let profile = IesProfile::parse(TEST_PROFILE).expect("failed to parse profile");
+    assert_eq!(profile.get_max_candela(), 400.0);
+
+    assert_eq!(profile.sample(0.0, 0.0), 200.0);
+    assert_eq!(profile.sample(22.5, 0.0), 150.0);
+    assert_eq!(profile.sample(0.0, 45.0), 300.0);
+    assert_eq!(profile.sample(120.0, 0.0), 0.0);
+
+    // Other quadrants are mirrored
+    assert_eq!(profile.sample(0.0, 180.0), 200.0);
+    assert_eq!(profile.sample(45.0, 270.0), 200.0);
+}
+
+#[test]
+fn test_invalid_ies_profile() {
+    assert!(IesProfile::parse("IESNA:LM-63-2002\n1 2 3").is_err());
+    assert!(IesProfile::parse("TILT=NONE\n1 1000 1.0 3 2 1").is_err());
+}
+
+#[test]
+fn test_bake_ies_profiles() {
+    let profile = IesProfile::parse(TEST_PROFILE).expect("failed to parse profile");
+    let image = bake_ies_profiles(&[profile]);
+    assert_eq!(image.layer_count, 1);
+    assert_eq!(
+        image.pixels.len(),
+        (IES_PROFILE_WIDTH * IES_PROFILE_HEIGHT) as usize * std::mem::size_of::<f32>()
+    );
+    assert_eq!(image.pixels[0..4], 0.5f32.to_le_bytes());
+}
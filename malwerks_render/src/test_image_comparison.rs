@@ -0,0 +1,81 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::image_comparison::*;
+
+// Horizontal RGB8 gradient with a brighter square in the middle
+fn create_test_pixels(width: u32, height: u32) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let inside = x >= width / 4 && x < width * 3 / 4 && y >= height / 4 && y < height * 3 / 4;
+            let value = (x * 128 / width) as u8 + if inside { 100 } else { 0 };
+            pixels.extend_from_slice(&[value, value / 2, 255 - value]);
+        }
+    }
+    pixels
+}
+
+#[test]
+fn test_decode_r11g11b10() {
+    // 1.0 has the exponent 15 and no mantissa in all channels
+    let one = (15 << 6) | ((15 << 6) << 11) | ((15 << 5) << 22);
+    assert_eq!(decode_r11g11b10(one), [1.0, 1.0, 1.0]);
+    assert_eq!(decode_r11g11b10(0), [0.0, 0.0, 0.0]);
+
+    // 0.75 in red, 3.0 in blue
+    let mixed = (14 << 6) | (1 << 5) | (((16 << 5) | (1 << 4)) << 22);
+    assert_eq!(decode_r11g11b10(mixed), [0.75, 0.0, 3.0]);
+}
+
+#[test]
+fn test_decode_half() {
+    assert_eq!(decode_half(0x3c00), 1.0);
+    assert_eq!(decode_half(0xc000), -2.0);
+    assert_eq!(decode_half(0x3a00), 0.75);
+    assert_eq!(decode_half(0x7bff), 65504.0);
+    assert_eq!(decode_half(0x0001), 2.0f32.powi(-24));
+}
+
+#[test]
+fn test_image_hash() {
+    let pixels = create_test_pixels(32, 32);
+    let mut changed_pixels = pixels.clone();
+    changed_pixels[100] ^= 1;
+
+    assert_eq!(hash_image_pixels(&pixels), hash_image_pixels(&pixels.clone()));
+    assert_ne!(hash_image_pixels(&pixels), hash_image_pixels(&changed_pixels));
+}
+
+#[test]
+fn test_image_metrics() {
+    let pixels = create_test_pixels(64, 64);
+    assert_eq!(calculate_psnr(&pixels, &pixels), f32::INFINITY);
+    assert!((calculate_ssim(&pixels, &pixels, 64, 64) - 1.0).abs() < 1e-6);
+
+    // Small noise keeps the images similar, a shifted image is not
+    let noisy_pixels: Vec<u8> = pixels
+        .iter()
+        .enumerate()
+        .map(|(i, value)| if i % 7 == 0 { value.saturating_add(2) } else { *value })
+        .collect();
+    let noisy = ImageComparison {
+        psnr: calculate_psnr(&noisy_pixels, &pixels),
+        ssim: calculate_ssim(&noisy_pixels, &pixels, 64, 64),
+    };
+    assert!(noisy.is_within(&ImageTolerance::default()), "{:?}", noisy);
+
+    let shifted_pixels: Vec<u8> = pixels
+        .iter()
+        .skip(3 * 8)
+        .chain(pixels.iter().take(3 * 8))
+        .copied()
+        .collect();
+    let shifted = ImageComparison {
+        psnr: calculate_psnr(&shifted_pixels, &pixels),
+        ssim: calculate_ssim(&shifted_pixels, &pixels, 64, 64),
+    };
+    assert!(!shifted.is_within(&ImageTolerance::default()), "{:?}", shifted);
+}
@@ -0,0 +1,32 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::nan_detection::*;
+
+fn make_scanned_buffers() -> Vec<(String, u32)> {
+    vec![("transforms".to_string(), 16), ("lights".to_string(), 4)]
+}
+
+#[test]
+fn test_nan_detection_result_finite() {
+    let result_data = [0, !0, 0, !0, !0];
+    let result = read_nan_detection_result(&result_data, &make_scanned_buffers(), &Default::default());
+    assert_eq!(result, NanDetectionResult::default());
+}
+
+#[test]
+fn test_nan_detection_result_non_finite() {
+    // First pixel is packed as (y << 16) | x, values are reported per element of the buffer stride
+    let result_data = [5, (7 << 16) | 3, 2, !0, 9];
+    let result = read_nan_detection_result(&result_data, &make_scanned_buffers(), &Default::default());
+    assert_eq!(result.pixel_count, 5);
+    assert_eq!(result.first_pixel, Some((3, 7)));
+    assert_eq!(result.value_count, 2);
+    assert_eq!(result.first_value, Some(("lights".to_string(), 2)));
+
+    // Entries past the scanned buffers are left over from earlier frames and ignored
+    let result = read_nan_detection_result(&result_data, &make_scanned_buffers()[..1], &result);
+    assert_eq!(result.first_value, None);
+}
@@ -6,13 +6,24 @@
 use malwerks_dds::*;
 use malwerks_vk::*;
 
+use crate::asset_cache::*;
 use crate::bundle_loader::*;
 use crate::camera::*;
+use crate::image_comparison::*;
+use crate::order_independent_transparency::*;
 use crate::pbr_forward_lit::*;
+use crate::render_target_capture::*;
 
 const RENDER_WIDTH: u32 = 1024;
 const RENDER_HEIGHT: u32 = 1024;
 
+// Frames rendered with the same camera before the golden image capture, so that temporal history settles.
+// Golden images are tested after all DDS references, which still see a single frame per camera.
+const TEST_FRAME_COUNT: usize = 4;
+
+// Missing golden images fail the test unless this is set, in which case they are written from the current output
+const BLESS_GOLDEN_IMAGES_VARIABLE: &str = "MALWERKS_BLESS_GOLDEN_IMAGES";
+
 trait CaptureRenderTargets {
     fn capture_render_targets(
         &self,
@@ -35,18 +46,19 @@ impl CaptureRenderTargets for PbrForwardLit {
         let stage_mask = vk::PipelineStageFlags::ALL_GRAPHICS;
 
         let color_image = capture_render_target(
-            signal_semaphore,
-            stage_mask,
-            self.get_render_layer().get_image_resource(0),
-            vk::Extent3D {
-                width: RENDER_WIDTH,
-                height: RENDER_HEIGHT,
-                depth: 1,
+            Some((signal_semaphore, stage_mask)),
+            &RenderTargetCaptureParameters {
+                image: self.get_render_layer().get_image_resource(0),
+                image_extent: vk::Extent3D {
+                    width: RENDER_WIDTH,
+                    height: RENDER_HEIGHT,
+                    depth: 1,
+                },
+                image_aspect: vk::ImageAspectFlags::COLOR,
+                image_dxgi_format: DXGI_FORMAT_R11G11B10_FLOAT,
+                num_mip_levels: 1,
+                num_array_layers: 1,
             },
-            vk::ImageAspectFlags::COLOR,
-            DXGI_FORMAT_R11G11B10_FLOAT,
-            1,
-            1,
             command_buffer,
             factory,
             queue,
@@ -54,12 +66,15 @@ impl CaptureRenderTargets for PbrForwardLit {
         vec![("color", color_image)]
 
         // let depth_image = capture_render_target(
-        //     self.get_depth_resource(),
-        //     self.get_extent(),
-        //     vk::ImageAspectFlags::DEPTH,
-        //     DXGI_FORMAT_D32_FLOAT,
-        //     1,
-        //     1,
+        //     None,
+        //     &RenderTargetCaptureParameters {
+        //         image: self.get_depth_resource(),
+        //         image_extent: self.get_extent(),
+        //         image_aspect: vk::ImageAspectFlags::DEPTH,
+        //         image_dxgi_format: DXGI_FORMAT_D32_FLOAT,
+        //         num_mip_levels: 1,
+        //         num_array_layers: 1,
+        //     },
         //     command_buffer,
         //     factory,
         //     queue,
@@ -68,163 +83,86 @@ impl CaptureRenderTargets for PbrForwardLit {
     }
 }
 
-fn capture_render_target(
-    wait_semaphore: vk::Semaphore,
-    wait_dst_stage_mask: vk::PipelineStageFlags,
-    image: &HeapAllocatedResource<vk::Image>,
-    image_extent: vk::Extent3D,
-    image_aspect: vk::ImageAspectFlags,
-    image_dxgi_format: u32,
-    num_mip_levels: usize,
-    num_array_layers: usize,
-    command_buffer: &mut CommandBuffer,
+// Renderer state every test frame is rendered with
+struct TestFrameInputs<'a> {
+    bundle_loader: &'a mut BundleLoader,
+    pbr_forward_lit: &'a mut PbrForwardLit,
+    camera: &'a mut Camera,
+}
+
+// Earlier frames are rendered with the same camera, only the last one is captured
+fn render_and_capture(
+    frame_count: usize,
+    inputs: &mut TestFrameInputs,
+
+    device: &mut Device,
     factory: &mut DeviceFactory,
     queue: &mut DeviceQueue,
-) -> ScratchImage {
-    command_buffer.reset();
-    command_buffer.begin(
-        &vk::CommandBufferBeginInfo::builder()
-            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
-            .build(),
-    );
-
-    let temp_buffer = factory.allocate_buffer(
-        &vk::BufferCreateInfo::builder()
-            .size(image.1.get_size() as _)
-            .usage(vk::BufferUsageFlags::TRANSFER_DST)
-            .build(),
-        &vk_mem::AllocationCreateInfo {
-            usage: vk_mem::MemoryUsage::CpuOnly,
-            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE,
-            ..Default::default()
-        },
-    );
+) -> Vec<(&'static str, ScratchImage)> {
+    let TestFrameInputs {
+        bundle_loader,
+        pbr_forward_lit,
+        camera,
+    } = inputs;
+
+    for _ in 0..frame_count - 1 {
+        let frame_context = device.begin_frame();
+        pbr_forward_lit.render(camera, &frame_context, device, factory, queue);
+        device.end_frame(frame_context);
+    }
 
-    command_buffer.pipeline_barrier(
-        vk::PipelineStageFlags::HOST,
-        vk::PipelineStageFlags::TRANSFER,
-        None,
-        &[],
-        &[],
-        &[vk::ImageMemoryBarrier::builder()
-            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-            .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-            .src_queue_family_index(!0)
-            .dst_queue_family_index(!0)
-            .image(image.0)
-            .subresource_range(
-                vk::ImageSubresourceRange::builder()
-                    .aspect_mask(image_aspect)
-                    .base_mip_level(0)
-                    .level_count(num_mip_levels as _)
-                    .base_array_layer(0)
-                    .layer_count(num_array_layers as _)
-                    .build(),
-            )
-            .build()],
-    );
-    command_buffer.copy_image_to_buffer(
-        image.0,
-        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-        temp_buffer.0,
-        &[vk::BufferImageCopy::builder()
-            .image_subresource(
-                vk::ImageSubresourceLayers::builder()
-                    .aspect_mask(image_aspect)
-                    .mip_level(0)
-                    .base_array_layer(0)
-                    .layer_count(1)
-                    .build(),
-            )
-            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
-            .image_extent(image_extent)
-            .buffer_offset(0)
-            .build()],
-    );
-    command_buffer.pipeline_barrier(
-        vk::PipelineStageFlags::TRANSFER,
-        vk::PipelineStageFlags::FRAGMENT_SHADER,
-        None,
-        &[],
-        &[],
-        &[vk::ImageMemoryBarrier::builder()
-            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-            .dst_access_mask(vk::AccessFlags::SHADER_READ)
-            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .src_queue_family_index(!0)
-            .dst_queue_family_index(!0)
-            .image(image.0)
-            .subresource_range(
-                vk::ImageSubresourceRange::builder()
-                    .aspect_mask(image_aspect)
-                    .base_mip_level(0)
-                    .level_count(num_mip_levels as _)
-                    .base_array_layer(0)
-                    .layer_count(num_array_layers as _)
-                    .build(),
-            )
-            .build()],
-    );
+    let frame_context = device.begin_frame();
+    pbr_forward_lit.render(camera, &frame_context, device, factory, queue);
 
-    command_buffer.end();
-    queue.submit(
-        &[vk::SubmitInfo::builder()
-            .wait_semaphores(&[wait_semaphore])
-            .wait_dst_stage_mask(&[wait_dst_stage_mask])
-            .command_buffers(&[command_buffer.clone().into()])
-            .build()],
-        vk::Fence::null(),
-    );
-    queue.wait_idle();
+    let command_buffer = bundle_loader.get_command_buffer_mut();
+    let images = pbr_forward_lit.capture_render_targets(&frame_context, command_buffer, factory, queue);
 
-    let mut scratch_image = ScratchImage::new(
-        image_extent.width,
-        image_extent.height,
-        image_extent.depth,
-        num_mip_levels as _,
-        num_array_layers as _,
-        image_dxgi_format,
-        false,
-    );
+    device.end_frame(frame_context);
 
-    let temp_memory = factory.map_allocation_memory(&temp_buffer);
-    unsafe {
-        assert_eq!(scratch_image.as_slice().len(), temp_buffer.1.get_size());
+    queue.wait_idle();
+    device.wait_idle();
+    images
+}
 
-        let dst_slice = scratch_image.as_slice_mut();
-        std::ptr::copy_nonoverlapping(temp_memory, dst_slice.as_mut_ptr(), dst_slice.len());
+// Golden images are LDR PNGs
+fn compare_golden_image(test_path: &std::path::Path, image_name: &str, scratch_image: &ScratchImage) {
+    let test_rgb8 = convert_capture_to_rgb8(scratch_image);
+    log::info!("frame hash: {:016x}", hash_image_pixels(test_rgb8.as_raw()));
+    let png_path = test_path.join(image_name).with_extension("png");
+    test_rgb8.save(&png_path).expect("failed to save test image");
+    let golden_path = test_path.join("reference").join(image_name).with_extension("png");
+    if std::env::var_os(BLESS_GOLDEN_IMAGES_VARIABLE).is_some() {
+        log::warn!("saving the current output as golden image {:?}", golden_path);
+        test_rgb8.save(&golden_path).expect("failed to save golden image");
+        return;
     }
-    factory.unmap_allocation_memory(&temp_buffer);
-
-    factory.deallocate_buffer(&temp_buffer);
 
-    scratch_image
+    let golden_image = image::open(&golden_path).unwrap_or_else(|_| {
+        panic!(
+            "golden image {:?} is missing, set {} to create it from the current output",
+            golden_path, BLESS_GOLDEN_IMAGES_VARIABLE
+        )
+    });
+    let comparison = compare_images(&test_rgb8, &golden_image.to_rgb8());
+    log::info!("PSNR: {} dB, SSIM: {}", comparison.psnr, comparison.ssim);
+    assert!(
+        comparison.is_within(&ImageTolerance::default()),
+        "{:?} differs from the golden image: {:?}",
+        png_path,
+        comparison
+    );
 }
 
 fn render_test_frame(
     test_path: &std::path::Path,
     test_name: &str,
-
-    bundle_loader: &mut BundleLoader,
-    pbr_forward_lit: &mut PbrForwardLit,
-    camera: &mut Camera,
+    inputs: &mut TestFrameInputs,
 
     device: &mut Device,
     factory: &mut DeviceFactory,
     queue: &mut DeviceQueue,
 ) {
-    let frame_context = device.begin_frame();
-    pbr_forward_lit.render(camera, &frame_context, device, factory, queue);
-
-    let command_buffer = bundle_loader.get_command_buffer_mut();
-    let images = pbr_forward_lit.capture_render_targets(&frame_context, command_buffer, factory, queue);
-
-    device.end_frame(frame_context);
-
-    queue.wait_idle();
-    device.wait_idle();
+    let images = render_and_capture(1, inputs, device, factory, queue);
 
     for (image_name, scratch_image) in images {
         log::info!("testing {}/{}", test_name, image_name);
@@ -315,10 +253,9 @@ fn test_render_passes() {
     pretty_env_logger::init();
     log::info!("base path set to {:?}", &base_path);
 
-    let mut device = Device::new(
-        &[],
+    let mut device = Device::from_surface_provider(
+        &HeadlessSurface,
         &[],
-        |_: &ash::Entry, _: &ash::Instance| (None, vk::SurfaceKHR::null()),
         DeviceOptions {
             enable_validation: true,
             enable_render_target_export: true,
@@ -338,6 +275,13 @@ fn test_render_passes() {
                 pbr_resource_folder: &base_path.join("assets").join("pbr_resources"),
                 force_import_bundles: true,
                 force_compile_shaders: true,
+                pack_mesh_geometry: false,
+                vertex_pulling: false,
+                compress_environment_probes: false,
+                shader_debug_printf: false,
+                stress_instance_count: 0,
+                stress_mesh: 0,
+                asset_cache_size_budget: DEFAULT_ASSET_CACHE_SIZE_BUDGET,
             },
             &device,
             &mut factory,
@@ -351,6 +295,9 @@ fn test_render_passes() {
                 target_layer: None,
                 bundle_loader: &bundle_loader,
                 enable_anti_aliasing: false,
+                transparency_mode: TransparencyMode::WeightedBlended,
+                hdr_color_format: HdrColorFormat::R11G11B10Float,
+                num_recording_threads: 0,
             },
             &device,
             &mut factory,
@@ -419,19 +366,24 @@ fn test_render_passes() {
                 ),
             ];
 
+            let mut inputs = TestFrameInputs {
+                bundle_loader: &mut bundle_loader,
+                pbr_forward_lit: &mut pbr_forward_lit,
+                camera: &mut camera,
+            };
             for (name, position, orientation) in test_cameras.iter() {
-                camera.position = *position;
-                camera.orientation = *orientation;
-                render_test_frame(
-                    &test_path,
-                    name,
-                    &mut bundle_loader,
-                    &mut pbr_forward_lit,
-                    &mut camera,
-                    &mut device,
-                    &mut factory,
-                    &mut queue,
-                );
+                inputs.camera.position = *position;
+                inputs.camera.orientation = *orientation;
+                render_test_frame(&test_path, name, &mut inputs, &mut device, &mut factory, &mut queue);
+            }
+            for (name, position, orientation) in test_cameras.iter() {
+                inputs.camera.position = *position;
+                inputs.camera.orientation = *orientation;
+                let images = render_and_capture(TEST_FRAME_COUNT, &mut inputs, &mut device, &mut factory, &mut queue);
+                for (image_name, scratch_image) in images {
+                    log::info!("testing {}/{} against the golden image", name, image_name);
+                    compare_golden_image(&test_path, &(String::from(*name) + "_" + image_name), &scratch_image);
+                }
             }
 
             pbr_forward_lit.destroy(&mut factory);
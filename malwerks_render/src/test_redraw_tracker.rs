@@ -0,0 +1,41 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::camera::*;
+use crate::redraw_tracker::*;
+
+fn render_until_idle(redraw_tracker: &mut RedrawTracker, camera: &Camera) -> usize {
+    let mut frame_count = 0;
+    loop {
+        redraw_tracker.update_camera(camera);
+        if !redraw_tracker.needs_redraw() {
+            return frame_count;
+        }
+        redraw_tracker.frame_rendered();
+        frame_count += 1;
+    }
+}
+
+#[test]
+fn test_redraw_tracker() {
+    let mut camera = Camera::new(
+        1.0,
+        Viewport {
+            x: 0,
+            y: 0,
+            width: 64,
+            height: 64,
+        },
+    );
+    let mut redraw_tracker = RedrawTracker::new(3);
+    assert_eq!(render_until_idle(&mut redraw_tracker, &camera), 3);
+    assert_eq!(render_until_idle(&mut redraw_tracker, &camera), 0);
+
+    camera.position.x += 1.0;
+    assert_eq!(render_until_idle(&mut redraw_tracker, &camera), 3);
+
+    redraw_tracker.invalidate();
+    assert_eq!(render_until_idle(&mut redraw_tracker, &camera), 3);
+}
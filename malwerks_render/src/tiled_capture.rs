@@ -0,0 +1,167 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_dds::*;
+use malwerks_vk::*;
+
+use crate::camera::*;
+use crate::pbr_forward_lit::*;
+use crate::render_target_capture::*;
+
+pub struct TiledCaptureParameters {
+    pub output_width: u32,
+    pub output_height: u32,
+    pub tile_width: u32,                  // has to fit into the render layer
+    pub tile_height: u32,                 // has to fit into the render layer
+    pub hdr_color_format: HdrColorFormat, // has to match the renderer
+}
+
+// Renders an image larger than the render layer one tile per frame and stitches the tiles on the CPU.
+// Captured color is the lit HDR image before anti-aliasing and tone mapping, so both the temporal
+// jitter and resolution scaling have to be disabled while capturing.
+pub struct TiledCapture {
+    output_image: ScratchImage,
+    hdr_color_format: HdrColorFormat,
+    output_width: u32,
+    output_height: u32,
+    tile_width: u32,
+    tile_height: u32,
+    tile_columns: u32,
+    tile_rows: u32,
+    current_tile: u32,
+}
+
+impl TiledCapture {
+    pub fn new(parameters: &TiledCaptureParameters) -> Self {
+        assert!(parameters.output_width > 0 && parameters.output_height > 0);
+        assert!(parameters.tile_width > 0 && parameters.tile_height > 0);
+
+        let tile_columns = parameters.output_width.div_ceil(parameters.tile_width);
+        let tile_rows = parameters.output_height.div_ceil(parameters.tile_height);
+        log::info!(
+            "tiled capture: {}x{} in {} tiles of {}x{}",
+            parameters.output_width,
+            parameters.output_height,
+            tile_columns * tile_rows,
+            parameters.tile_width,
+            parameters.tile_height
+        );
+
+        Self {
+            output_image: ScratchImage::new(
+                parameters.output_width,
+                parameters.output_height,
+                1,
+                1,
+                1,
+                parameters.hdr_color_format.get_dxgi_format(),
+                false,
+            ),
+            hdr_color_format: parameters.hdr_color_format,
+            output_width: parameters.output_width,
+            output_height: parameters.output_height,
+            tile_width: parameters.tile_width,
+            tile_height: parameters.tile_height,
+            tile_columns,
+            tile_rows,
+            current_tile: 0,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current_tile >= self.get_tile_count()
+    }
+
+    pub fn get_tile_count(&self) -> u32 {
+        self.tile_columns * self.tile_rows
+    }
+
+    pub fn get_current_tile(&self) -> u32 {
+        self.current_tile
+    }
+
+    // Edge tiles are rendered at full tile size, the part outside of the output image is dropped
+    pub fn prepare_camera(&self, camera: &mut Camera) {
+        assert!(!self.is_finished(), "prepare_camera() called after the last tile");
+        let (x, y) = self.get_tile_offset();
+        camera.set_projection_tile(Some(ProjectionTile {
+            full_width: self.output_width,
+            full_height: self.output_height,
+            x,
+            y,
+            width: self.tile_width,
+            height: self.tile_height,
+        }));
+    }
+
+    // Render layer must not be in use by the device, the frame that rendered the tile has to be waited on
+    pub fn capture_tile(
+        &mut self,
+        pbr_forward_lit: &PbrForwardLit,
+        command_buffer: &mut CommandBuffer,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) {
+        assert!(!self.is_finished(), "capture_tile() called after the last tile");
+        assert_eq!(
+            pbr_forward_lit.get_hdr_color_format(),
+            self.hdr_color_format,
+            "tiled capture and renderer use different HDR color formats"
+        );
+        let (tile_x, tile_y) = self.get_tile_offset();
+
+        let tile_image = capture_render_target(
+            None,
+            &RenderTargetCaptureParameters {
+                image: pbr_forward_lit.get_render_layer().get_image_resource(0),
+                image_extent: vk::Extent3D {
+                    width: self.tile_width,
+                    height: self.tile_height,
+                    depth: 1,
+                },
+                image_aspect: vk::ImageAspectFlags::COLOR,
+                image_dxgi_format: self.hdr_color_format.get_dxgi_format(),
+                num_mip_levels: 1,
+                num_array_layers: 1,
+            },
+            command_buffer,
+            factory,
+            queue,
+        );
+
+        let pixel_size = self.hdr_color_format.get_pixel_size();
+        let copy_width = self.tile_width.min(self.output_width - tile_x) as usize;
+        let copy_height = self.tile_height.min(self.output_height - tile_y) as usize;
+        let tile_data = tile_image.as_slice();
+        let output_data = self.output_image.as_slice_mut();
+        for row in 0..copy_height {
+            let src_offset = row * self.tile_width as usize * pixel_size;
+            let dst_offset = ((tile_y as usize + row) * self.output_width as usize + tile_x as usize) * pixel_size;
+            let row_size = copy_width * pixel_size;
+            output_data[dst_offset..dst_offset + row_size]
+                .copy_from_slice(&tile_data[src_offset..src_offset + row_size]);
+        }
+
+        self.current_tile += 1;
+        log::info!(
+            "tiled capture: tile {}/{} done",
+            self.current_tile,
+            self.get_tile_count()
+        );
+    }
+
+    pub fn finish(self, camera: &mut Camera) -> ScratchImage {
+        assert!(self.is_finished(), "finish() called before all tiles are captured");
+        camera.set_projection_tile(None);
+        self.output_image
+    }
+
+    fn get_tile_offset(&self) -> (u32, u32) {
+        (
+            (self.current_tile % self.tile_columns) * self.tile_width,
+            (self.current_tile / self.tile_columns) * self.tile_height,
+        )
+    }
+}
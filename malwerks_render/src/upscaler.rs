@@ -0,0 +1,40 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+pub struct UpscalerInputs<'a> {
+    pub source_layer: &'a RenderLayer, // color and depth images are in SHADER_READ_ONLY_OPTIMAL layout
+    pub dependency_layer: &'a RenderLayer, // last layer that has written to the source images
+    pub source_color_image: usize,
+    pub source_motion_vectors: Option<vk::ImageView>, // None means camera motion has to be derived from depth
+    pub frame_data_descriptor_set: vk::DescriptorSet,
+    pub frame_data_offset: u32,
+    pub subsample_offset: [f32; 2], // in render resolution pixels
+    pub render_area: vk::Rect2D,
+    pub output_area: vk::Rect2D,
+}
+
+// Upscalers run between the forward pass and post processing and are expected to
+// fill the whole output area, external upscaling libraries can be hooked up by implementing this
+pub trait Upscaler {
+    fn destroy(&mut self, factory: &mut DeviceFactory);
+
+    fn render(
+        &mut self,
+        inputs: &UpscalerInputs,
+        frame_context: &FrameContext,
+        device: &mut Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    );
+
+    // All layers that can be written by the upscaler, post processing builds its descriptor sets from them
+    fn get_output_layers(&self) -> Vec<&RenderLayer>;
+
+    // Index of the output layer that was written by the last render() call
+    fn get_output_index(&self) -> usize;
+}
@@ -0,0 +1,672 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::camera::*;
+use crate::common_shaders::*;
+use crate::half_resolution_effect::*;
+use crate::pbr_resource_bundle::*;
+use crate::shared_frame_data::*;
+
+const FROXEL_GRID_SIZE: [u32; 3] = [160, 90, 64];
+const FROXEL_GROUP_SIZE: u32 = 8;
+const FOG_HISTORY_WEIGHT: f32 = 0.9;
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct VolumetricFogParameters {
+    pub density: f32,
+    pub height: f32,
+    pub height_falloff: f32,
+    pub anisotropy: f32,
+    pub albedo: [f32; 3],
+    pub max_distance: f32,
+}
+
+impl Default for VolumetricFogParameters {
+    fn default() -> Self {
+        Self {
+            density: 0.02,
+            height: 0.0,
+            height_falloff: 0.2,
+            anisotropy: 0.3,
+            albedo: [1.0, 1.0, 1.0],
+            max_distance: 128.0,
+        }
+    }
+}
+
+pub struct VolumetricFog {
+    parameters: VolumetricFogParameters,
+
+    scattering_volumes: [(HeapAllocatedResource<vk::Image>, vk::ImageView); 2],
+    integrated_volume: (HeapAllocatedResource<vk::Image>, vk::ImageView),
+
+    linear_sampler: vk::Sampler,
+    point_sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    compute_descriptor_set_layout: vk::DescriptorSetLayout,
+    compute_descriptor_sets: Vec<vk::DescriptorSet>,
+    composite_descriptor_set_layout: vk::DescriptorSetLayout,
+    composite_descriptor_set: vk::DescriptorSet,
+
+    scattering_module: vk::ShaderModule,
+    integration_module: vk::ShaderModule,
+    vert_module: vk::ShaderModule,
+    frag_module: vk::ShaderModule,
+
+    compute_pipeline_layout: vk::PipelineLayout,
+    scattering_pipeline: vk::Pipeline,
+    integration_pipeline: vk::Pipeline,
+    composite_pipeline_layout: vk::PipelineLayout,
+    composite_pipeline: vk::Pipeline,
+
+    current_volume: usize,
+    previous_camera_position: [f32; 3],
+    layouts_initialized: bool,
+    history_valid: bool,
+}
+
+impl VolumetricFog {
+    pub fn new(
+        common_shaders: &DiskCommonShaders,
+        shared_frame_data: &SharedFrameData,
+        pbr_resource_bundle: &PbrResourceBundle,
+        source_layer: &RenderLayer,
+        target_layer: &RenderLayer,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        let scattering_volumes = [allocate_froxel_volume(factory), allocate_froxel_volume(factory)];
+        let integrated_volume = allocate_froxel_volume(factory);
+
+        let linear_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build(),
+        );
+        let point_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build(),
+        );
+
+        let descriptor_pool = factory.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder().max_sets(3).pool_sizes(&[
+                vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(4)
+                    .build(),
+                vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(4)
+                    .build(),
+            ]),
+        );
+        let compute_descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(2)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build(),
+            ]),
+        );
+        let composite_descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+            ]),
+        );
+        let descriptor_sets = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&[
+                    compute_descriptor_set_layout,
+                    compute_descriptor_set_layout,
+                    composite_descriptor_set_layout,
+                ])
+                .build(),
+        );
+        let compute_descriptor_sets = vec![descriptor_sets[0], descriptor_sets[1]];
+        let composite_descriptor_set = descriptor_sets[2];
+
+        // Each compute set writes one scattering volume and reads the other one as history
+        let source_depth_image = source_layer
+            .get_depth_image()
+            .expect("Depth image is required for volumetric fog")
+            .1;
+        for (set_id, descriptor_set) in compute_descriptor_sets.iter().enumerate() {
+            factory.update_descriptor_sets(
+                &[
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*descriptor_set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&[vk::DescriptorImageInfo::builder()
+                            .image_view(scattering_volumes[set_id].1)
+                            .image_layout(vk::ImageLayout::GENERAL)
+                            .build()])
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*descriptor_set)
+                        .dst_binding(1)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&[vk::DescriptorImageInfo::builder()
+                            .sampler(linear_sampler)
+                            .image_view(scattering_volumes[1 - set_id].1)
+                            .image_layout(vk::ImageLayout::GENERAL)
+                            .build()])
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*descriptor_set)
+                        .dst_binding(2)
+                        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                        .image_info(&[vk::DescriptorImageInfo::builder()
+                            .image_view(integrated_volume.1)
+                            .image_layout(vk::ImageLayout::GENERAL)
+                            .build()])
+                        .build(),
+                ],
+                &[],
+            );
+        }
+        factory.update_descriptor_sets(
+            &[
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(composite_descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .sampler(point_sampler)
+                        .image_view(source_depth_image)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .build()])
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(composite_descriptor_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .sampler(linear_sampler)
+                        .image_view(integrated_volume.1)
+                        .image_layout(vk::ImageLayout::GENERAL)
+                        .build()])
+                    .build(),
+            ],
+            &[],
+        );
+
+        let scattering_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.volumetric_fog_scattering_compute_stage)
+                .build(),
+        );
+        let integration_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.volumetric_fog_integration_compute_stage)
+                .build(),
+        );
+        let vert_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.volumetric_fog_vertex_stage)
+                .build(),
+        );
+        let frag_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.volumetric_fog_fragment_stage)
+                .build(),
+        );
+
+        let compute_pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[
+                    shared_frame_data.descriptor_set_layout,
+                    pbr_resource_bundle.descriptor_set_layout,
+                    compute_descriptor_set_layout,
+                ])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .offset(0)
+                    .size(std::mem::size_of::<VolumetricFogConstants>() as _)
+                    .build()])
+                .build(),
+        );
+        let composite_pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[shared_frame_data.descriptor_set_layout, composite_descriptor_set_layout])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .offset(0)
+                    .size(std::mem::size_of::<VolumetricFogConstants>() as _)
+                    .build()])
+                .build(),
+        );
+
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let compute_pipelines = factory.create_compute_pipelines(
+            vk::PipelineCache::null(),
+            &[
+                vk::ComputePipelineCreateInfo::builder()
+                    .stage(
+                        vk::PipelineShaderStageCreateInfo::builder()
+                            .name(&entry_name)
+                            .module(scattering_module)
+                            .stage(vk::ShaderStageFlags::COMPUTE)
+                            .build(),
+                    )
+                    .layout(compute_pipeline_layout)
+                    .build(),
+                vk::ComputePipelineCreateInfo::builder()
+                    .stage(
+                        vk::PipelineShaderStageCreateInfo::builder()
+                            .name(&entry_name)
+                            .module(integration_module)
+                            .stage(vk::ShaderStageFlags::COMPUTE)
+                            .build(),
+                    )
+                    .layout(compute_pipeline_layout)
+                    .build(),
+            ],
+        );
+
+        // Fog output is premultiplied, transmittance is accumulated in alpha
+        let vertex_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(vert_module)
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let fragment_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(frag_module)
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let composite_pipeline = factory.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[target_layer.make_pipeline_create_info(
+                vk::GraphicsPipelineCreateInfo::builder()
+                    .stages(&[vertex_stage, fragment_stage])
+                    .vertex_input_state(
+                        &vk::PipelineVertexInputStateCreateInfo::builder()
+                            .vertex_binding_descriptions(&[])
+                            .build(),
+                    )
+                    .input_assembly_state(
+                        &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                            .primitive_restart_enable(false)
+                            .build(),
+                    )
+                    .tessellation_state(&Default::default())
+                    .viewport_state(
+                        &vk::PipelineViewportStateCreateInfo::builder()
+                            .viewport_count(1)
+                            .scissor_count(1)
+                            .build(),
+                    )
+                    .rasterization_state(
+                        &vk::PipelineRasterizationStateCreateInfo::builder()
+                            .line_width(1.0)
+                            .build(),
+                    )
+                    .multisample_state(
+                        &vk::PipelineMultisampleStateCreateInfo::builder()
+                            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                            .build(),
+                    )
+                    .depth_stencil_state(&Default::default())
+                    .color_blend_state(
+                        &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                            vk::PipelineColorBlendAttachmentState::builder()
+                                .blend_enable(true)
+                                .src_color_blend_factor(vk::BlendFactor::ONE)
+                                .dst_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                                .color_blend_op(vk::BlendOp::ADD)
+                                .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+                                .dst_alpha_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                                .alpha_blend_op(vk::BlendOp::ADD)
+                                .color_write_mask(
+                                    vk::ColorComponentFlags::R
+                                        | vk::ColorComponentFlags::G
+                                        | vk::ColorComponentFlags::B
+                                        | vk::ColorComponentFlags::A,
+                                )
+                                .build(),
+                        ]),
+                    )
+                    .dynamic_state(
+                        &vk::PipelineDynamicStateCreateInfo::builder()
+                            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                            .build(),
+                    )
+                    .layout(composite_pipeline_layout)
+                    .subpass(0)
+                    .base_pipeline_handle(vk::Pipeline::null())
+                    .base_pipeline_index(0)
+                    .build(),
+            )],
+        )[0];
+
+        Self {
+            parameters: Default::default(),
+            scattering_volumes,
+            integrated_volume,
+            linear_sampler,
+            point_sampler,
+            descriptor_pool,
+            compute_descriptor_set_layout,
+            compute_descriptor_sets,
+            composite_descriptor_set_layout,
+            composite_descriptor_set,
+            scattering_module,
+            integration_module,
+            vert_module,
+            frag_module,
+            compute_pipeline_layout,
+            scattering_pipeline: compute_pipelines[0],
+            integration_pipeline: compute_pipelines[1],
+            composite_pipeline_layout,
+            composite_pipeline,
+            current_volume: 0,
+            previous_camera_position: [0.0; 3],
+            layouts_initialized: false,
+            history_valid: false,
+        }
+    }
+
+    pub fn set_parameters(&mut self, parameters: &VolumetricFogParameters) {
+        self.parameters = *parameters;
+    }
+
+    pub fn get_parameters(&self) -> &VolumetricFogParameters {
+        &self.parameters
+    }
+
+    // History is rejected on the next frame, used when fog was disabled for a while
+    pub fn reset_history(&mut self) {
+        self.history_valid = false;
+    }
+
+    // Fills the froxel grid and integrates it front to back, has to be recorded outside of a render pass
+    pub fn dispatch(
+        &mut self,
+        command_buffer: &mut CommandBuffer,
+        camera: &Camera,
+        frame_data_descriptor_set: vk::DescriptorSet,
+        frame_data_offset: u32,
+        pbr_resource_bundle: &PbrResourceBundle,
+    ) {
+        puffin::profile_function!();
+
+        if !self.layouts_initialized {
+            let image_barriers: Vec<vk::ImageMemoryBarrier> = [
+                self.scattering_volumes[0].0 .0,
+                self.scattering_volumes[1].0 .0,
+                self.integrated_volume.0 .0,
+            ]
+            .iter()
+            .map(|image| {
+                vk::ImageMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::default())
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::GENERAL)
+                    .src_queue_family_index(!0)
+                    .dst_queue_family_index(!0)
+                    .image(*image)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::builder()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .base_mip_level(0)
+                            .level_count(1)
+                            .base_array_layer(0)
+                            .layer_count(1)
+                            .build(),
+                    )
+                    .build()
+            })
+            .collect();
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                None,
+                &[],
+                &[],
+                &image_barriers,
+            );
+            self.layouts_initialized = true;
+        }
+
+        let camera_position = -camera.position;
+        let constants = VolumetricFogConstants {
+            density_height_falloff_anisotropy: [
+                self.parameters.density,
+                self.parameters.height,
+                self.parameters.height_falloff,
+                self.parameters.anisotropy.clamp(-0.99, 0.99),
+            ],
+            albedo_max_distance: [
+                self.parameters.albedo[0],
+                self.parameters.albedo[1],
+                self.parameters.albedo[2],
+                self.parameters.max_distance.max(1.0),
+            ],
+            previous_camera_position_unused: [
+                self.previous_camera_position[0],
+                self.previous_camera_position[1],
+                self.previous_camera_position[2],
+                0.0,
+            ],
+            history_weight_unused: [if self.history_valid { FOG_HISTORY_WEIGHT } else { 0.0 }, 0.0, 0.0, 0.0],
+        };
+
+        // Previous frame might still be sampling the integrated volume
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            None,
+            &[],
+            &[],
+            &[],
+        );
+
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            self.compute_pipeline_layout,
+            0,
+            &[
+                frame_data_descriptor_set,
+                pbr_resource_bundle.descriptor_sets[0],
+                self.compute_descriptor_sets[self.current_volume],
+            ],
+            &[frame_data_offset],
+        );
+        command_buffer.push_constants(
+            self.compute_pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &[constants],
+        );
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, self.scattering_pipeline);
+        command_buffer.dispatch(
+            FROXEL_GRID_SIZE[0].div_ceil(FROXEL_GROUP_SIZE),
+            FROXEL_GRID_SIZE[1].div_ceil(FROXEL_GROUP_SIZE),
+            FROXEL_GRID_SIZE[2],
+        );
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build()],
+            &[],
+            &[],
+        );
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::COMPUTE, self.integration_pipeline);
+        command_buffer.dispatch(
+            FROXEL_GRID_SIZE[0].div_ceil(FROXEL_GROUP_SIZE),
+            FROXEL_GRID_SIZE[1].div_ceil(FROXEL_GROUP_SIZE),
+            1,
+        );
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build()],
+            &[],
+            &[],
+        );
+
+        self.previous_camera_position = [camera_position.x, camera_position.y, camera_position.z];
+        self.current_volume = 1 - self.current_volume;
+        self.history_valid = true;
+    }
+}
+
+impl HalfResolutionEffect for VolumetricFog {
+    fn destroy(&mut self, factory: &mut DeviceFactory) {
+        for (image, image_view) in self
+            .scattering_volumes
+            .iter()
+            .chain(std::iter::once(&self.integrated_volume))
+        {
+            factory.deallocate_image(image);
+            factory.destroy_image_view(*image_view);
+        }
+        factory.destroy_sampler(self.linear_sampler);
+        factory.destroy_sampler(self.point_sampler);
+        factory.destroy_descriptor_pool(self.descriptor_pool);
+        factory.destroy_descriptor_set_layout(self.compute_descriptor_set_layout);
+        factory.destroy_descriptor_set_layout(self.composite_descriptor_set_layout);
+        factory.destroy_shader_module(self.scattering_module);
+        factory.destroy_shader_module(self.integration_module);
+        factory.destroy_shader_module(self.vert_module);
+        factory.destroy_shader_module(self.frag_module);
+        factory.destroy_pipeline_layout(self.compute_pipeline_layout);
+        factory.destroy_pipeline(self.scattering_pipeline);
+        factory.destroy_pipeline(self.integration_pipeline);
+        factory.destroy_pipeline_layout(self.composite_pipeline_layout);
+        factory.destroy_pipeline(self.composite_pipeline);
+    }
+
+    fn render(
+        &mut self,
+        command_buffer: &mut CommandBuffer,
+        _frame_context: &FrameContext,
+        frame_data_descriptor_set: vk::DescriptorSet,
+        frame_data_offset: u32,
+    ) {
+        let constants = VolumetricFogConstants {
+            albedo_max_distance: [0.0, 0.0, 0.0, self.parameters.max_distance.max(1.0)],
+            ..Default::default()
+        };
+
+        command_buffer.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, self.composite_pipeline);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            self.composite_pipeline_layout,
+            0,
+            &[frame_data_descriptor_set, self.composite_descriptor_set],
+            &[frame_data_offset],
+        );
+        command_buffer.push_constants(
+            self.composite_pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &[constants],
+        );
+        command_buffer.draw(3, 1, 0, 0);
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+struct VolumetricFogConstants {
+    density_height_falloff_anisotropy: [f32; 4],
+    albedo_max_distance: [f32; 4],
+    previous_camera_position_unused: [f32; 4],
+    history_weight_unused: [f32; 4],
+}
+
+fn allocate_froxel_volume(factory: &mut DeviceFactory) -> (HeapAllocatedResource<vk::Image>, vk::ImageView) {
+    let image = factory.allocate_image(
+        &vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_3D)
+            .format(vk::Format::R16G16B16A16_SFLOAT)
+            .extent(vk::Extent3D {
+                width: FROXEL_GRID_SIZE[0],
+                height: FROXEL_GRID_SIZE[1],
+                depth: FROXEL_GRID_SIZE[2],
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build(),
+        &vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ..Default::default()
+        },
+    );
+    let image_view = factory.create_image_view(
+        &vk::ImageViewCreateInfo::builder()
+            .image(image.0)
+            .view_type(vk::ImageViewType::TYPE_3D)
+            .format(vk::Format::R16G16B16A16_SFLOAT)
+            .components(vk::ComponentMapping::default())
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(0)
+                    .level_count(1)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .build(),
+    );
+
+    (image, image_view)
+}
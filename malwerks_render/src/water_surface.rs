@@ -0,0 +1,602 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_core::*;
+use malwerks_vk::*;
+
+use crate::common_shaders::*;
+use crate::pbr_resource_bundle::*;
+use crate::shared_frame_data::*;
+
+// Has to match the grid resolution in water_surface.glsl
+const GRID_RESOLUTION: u32 = 256;
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct WaterSurfaceParameters {
+    pub position: [f32; 3],
+    pub size: f32,
+    pub amplitude: f32,
+    pub wavelength: f32,
+    pub steepness: f32,
+    pub speed: f32,
+    pub direction: f32, // radians
+    pub absorption: [f32; 3],
+    pub refraction_strength: f32,
+}
+
+impl Default for WaterSurfaceParameters {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0, 0.0],
+            size: 64.0,
+            amplitude: 0.15,
+            wavelength: 6.0,
+            steepness: 0.6,
+            speed: 1.0,
+            direction: 0.0,
+            absorption: [0.45, 0.09, 0.06],
+            refraction_strength: 0.02,
+        }
+    }
+}
+
+// The source color image is refracted and also the target the water surface is rendered into
+pub struct WaterSurfaceLayerParameters<'a> {
+    pub source_layer: &'a RenderLayer,
+    pub source_color_image: usize,
+    pub source_color_format: vk::Format,
+    pub render_width: u32,
+    pub render_height: u32,
+}
+
+pub struct WaterSurfaceRenderParameters<'a> {
+    pub source_layer: &'a RenderLayer,
+    pub dependency_layer: &'a RenderLayer,
+    pub screen_area: vk::Rect2D,
+    pub shared_frame_data: &'a SharedFrameData,
+    pub pbr_resource_bundle: &'a PbrResourceBundle,
+}
+
+pub struct WaterSurface {
+    parameters: WaterSurfaceParameters,
+    water_layer: RenderLayer,
+    source_color_image: usize,
+    start_time: std::time::Instant,
+    pause_time: Option<std::time::Instant>,
+    animation_time: Option<f32>, // replaces the wall clock
+
+    scene_color_copy: HeapAllocatedResource<vk::Image>,
+    scene_color_copy_view: vk::ImageView,
+
+    linear_sampler: vk::Sampler,
+    point_sampler: vk::Sampler,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_set: vk::DescriptorSet,
+
+    vert_module: vk::ShaderModule,
+    frag_module: vk::ShaderModule,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl WaterSurface {
+    pub fn new(
+        common_shaders: &DiskCommonShaders,
+        shared_frame_data: &SharedFrameData,
+        pbr_resource_bundle: &PbrResourceBundle,
+        layer_parameters: &WaterSurfaceLayerParameters,
+        device: &Device,
+        factory: &mut DeviceFactory,
+    ) -> Self {
+        let &WaterSurfaceLayerParameters {
+            source_layer,
+            source_color_image,
+            source_color_format,
+            render_width,
+            render_height,
+        } = layer_parameters;
+
+        let water_layer = RenderLayer::from_shared_images(
+            device,
+            factory,
+            source_layer,
+            render_width,
+            render_height,
+            &[SharedImageParameters {
+                image_index: source_color_image,
+                image_format: source_color_format,
+                initial_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }],
+        );
+
+        // Water refracts the opaque scene, which is copied before water is rendered on top of it
+        let scene_color_copy = factory.allocate_image(
+            &vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(source_color_format)
+                .extent(vk::Extent3D {
+                    width: render_width,
+                    height: render_height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .build(),
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::GpuOnly,
+                required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ..Default::default()
+            },
+        );
+        let scene_color_copy_view = factory.create_image_view(
+            &vk::ImageViewCreateInfo::builder()
+                .image(scene_color_copy.0)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(source_color_format)
+                .components(vk::ComponentMapping::default())
+                .subresource_range(
+                    vk::ImageSubresourceRange::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .base_mip_level(0)
+                        .level_count(1)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build(),
+                )
+                .build(),
+        );
+
+        let linear_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::LINEAR)
+                .min_filter(vk::Filter::LINEAR)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build(),
+        );
+        let point_sampler = factory.create_sampler(
+            &vk::SamplerCreateInfo::builder()
+                .mag_filter(vk::Filter::NEAREST)
+                .min_filter(vk::Filter::NEAREST)
+                .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                .build(),
+        );
+
+        let descriptor_pool = factory.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::builder()
+                .max_sets(1)
+                .pool_sizes(&[vk::DescriptorPoolSize::builder()
+                    .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(2)
+                    .build()]),
+        );
+        let descriptor_set_layout = factory.create_descriptor_set_layout(
+            &vk::DescriptorSetLayoutCreateInfo::builder().bindings(&[
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .build(),
+            ]),
+        );
+        let descriptor_set = factory.allocate_descriptor_sets(
+            &vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&[descriptor_set_layout])
+                .build(),
+        )[0];
+
+        let source_depth_image = source_layer
+            .get_depth_image()
+            .expect("Depth image is required for water surface")
+            .1;
+        factory.update_descriptor_sets(
+            &[
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .sampler(linear_sampler)
+                        .image_view(scene_color_copy_view)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .build()])
+                    .build(),
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(1)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::builder()
+                        .sampler(point_sampler)
+                        .image_view(source_depth_image)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .build()])
+                    .build(),
+            ],
+            &[],
+        );
+
+        let vert_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.water_surface_vertex_stage)
+                .build(),
+        );
+        let frag_module = factory.create_shader_module(
+            &vk::ShaderModuleCreateInfo::builder()
+                .code(&common_shaders.water_surface_fragment_stage)
+                .build(),
+        );
+
+        let pipeline_layout = factory.create_pipeline_layout(
+            &vk::PipelineLayoutCreateInfo::builder()
+                .set_layouts(&[
+                    shared_frame_data.descriptor_set_layout,
+                    pbr_resource_bundle.descriptor_set_layout,
+                    descriptor_set_layout,
+                ])
+                .push_constant_ranges(&[vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+                    .offset(0)
+                    .size(std::mem::size_of::<WaterSurfaceConstants>() as _)
+                    .build()])
+                .build(),
+        );
+
+        // Refraction already contains the scene behind the water, no blending is needed
+        let entry_name = std::ffi::CString::new("main").expect("failed to allocate entry name");
+        let vertex_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(vert_module)
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .build();
+        let fragment_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .name(&entry_name)
+            .module(frag_module)
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .build();
+        let pipeline = factory.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[water_layer.make_pipeline_create_info(
+                vk::GraphicsPipelineCreateInfo::builder()
+                    .stages(&[vertex_stage, fragment_stage])
+                    .vertex_input_state(
+                        &vk::PipelineVertexInputStateCreateInfo::builder()
+                            .vertex_binding_descriptions(&[])
+                            .build(),
+                    )
+                    .input_assembly_state(
+                        &vk::PipelineInputAssemblyStateCreateInfo::builder()
+                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                            .primitive_restart_enable(false)
+                            .build(),
+                    )
+                    .tessellation_state(&Default::default())
+                    .viewport_state(
+                        &vk::PipelineViewportStateCreateInfo::builder()
+                            .viewport_count(1)
+                            .scissor_count(1)
+                            .build(),
+                    )
+                    .rasterization_state(
+                        &vk::PipelineRasterizationStateCreateInfo::builder()
+                            .cull_mode(vk::CullModeFlags::NONE)
+                            .line_width(1.0)
+                            .build(),
+                    )
+                    .multisample_state(
+                        &vk::PipelineMultisampleStateCreateInfo::builder()
+                            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                            .build(),
+                    )
+                    .depth_stencil_state(&Default::default())
+                    .color_blend_state(
+                        &vk::PipelineColorBlendStateCreateInfo::builder().attachments(&[
+                            vk::PipelineColorBlendAttachmentState::builder()
+                                .blend_enable(false)
+                                .color_write_mask(get_color_write_mask(source_color_format))
+                                .build(),
+                        ]),
+                    )
+                    .dynamic_state(
+                        &vk::PipelineDynamicStateCreateInfo::builder()
+                            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                            .build(),
+                    )
+                    .layout(pipeline_layout)
+                    .subpass(0)
+                    .base_pipeline_handle(vk::Pipeline::null())
+                    .base_pipeline_index(0)
+                    .build(),
+            )],
+        )[0];
+
+        Self {
+            parameters: Default::default(),
+            water_layer,
+            source_color_image,
+            start_time: std::time::Instant::now(),
+            pause_time: None,
+            animation_time: None,
+            scene_color_copy,
+            scene_color_copy_view,
+            linear_sampler,
+            point_sampler,
+            descriptor_pool,
+            descriptor_set_layout,
+            descriptor_set,
+            vert_module,
+            frag_module,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        self.water_layer.destroy(factory);
+        factory.deallocate_image(&self.scene_color_copy);
+        factory.destroy_image_view(self.scene_color_copy_view);
+        factory.destroy_sampler(self.linear_sampler);
+        factory.destroy_sampler(self.point_sampler);
+        factory.destroy_descriptor_pool(self.descriptor_pool);
+        factory.destroy_descriptor_set_layout(self.descriptor_set_layout);
+        factory.destroy_shader_module(self.vert_module);
+        factory.destroy_shader_module(self.frag_module);
+        factory.destroy_pipeline_layout(self.pipeline_layout);
+        factory.destroy_pipeline(self.pipeline);
+    }
+
+    pub fn set_parameters(&mut self, parameters: &WaterSurfaceParameters) {
+        self.parameters = *parameters;
+    }
+
+    pub fn get_parameters(&self) -> &WaterSurfaceParameters {
+        &self.parameters
+    }
+
+    pub fn set_animation_time(&mut self, animation_time: Option<f32>) {
+        self.animation_time = animation_time;
+    }
+
+    // Waves continue from the same phase after resume()
+    pub fn pause(&mut self) {
+        if self.pause_time.is_none() {
+            self.pause_time = Some(std::time::Instant::now());
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(pause_time) = self.pause_time.take() {
+            self.start_time += pause_time.elapsed();
+        }
+    }
+
+    // Signals when the source color image contains the water surface
+    pub fn get_water_layer(&self) -> &RenderLayer {
+        &self.water_layer
+    }
+
+    // Source color and depth are expected to be in SHADER_READ_ONLY_OPTIMAL layout
+    pub fn render(
+        &mut self,
+        parameters: &WaterSurfaceRenderParameters,
+        frame_context: &FrameContext,
+        device: &mut Device,
+        factory: &mut DeviceFactory,
+        queue: &mut DeviceQueue,
+    ) {
+        puffin::profile_function!();
+
+        let &WaterSurfaceRenderParameters {
+            source_layer,
+            dependency_layer,
+            screen_area,
+            shared_frame_data,
+            pbr_resource_bundle,
+        } = parameters;
+
+        let direction = [self.parameters.direction.cos(), self.parameters.direction.sin()];
+        let time = self
+            .animation_time
+            .unwrap_or_else(|| self.start_time.elapsed().as_secs_f32());
+        let mut constants = WaterSurfaceConstants {
+            subsample_view_projection: [0.0; 16],
+            position_size: [
+                self.parameters.position[0],
+                self.parameters.position[1],
+                self.parameters.position[2],
+                self.parameters.size.max(1.0),
+            ],
+            wave_amplitude_length_steepness_speed: [
+                self.parameters.amplitude.max(0.0),
+                self.parameters.wavelength.max(0.1),
+                self.parameters.steepness.clamp(0.0, 1.0),
+                self.parameters.speed,
+            ],
+            wave_direction_time_unused: [direction[0], direction[1], time, 0.0],
+            absorption_refraction: [
+                self.parameters.absorption[0],
+                self.parameters.absorption[1],
+                self.parameters.absorption[2],
+                self.parameters.refraction_strength,
+            ],
+        };
+        constants
+            .subsample_view_projection
+            .copy_from_slice(shared_frame_data.get_subsample_view_projection().as_slice());
+
+        let source_image = source_layer.get_render_image(self.source_color_image).0;
+        let color_subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+        let color_subresource_layers = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+
+        self.water_layer
+            .add_dependency(frame_context, dependency_layer, vk::PipelineStageFlags::TRANSFER);
+        self.water_layer.acquire_frame(frame_context, device, factory);
+        {
+            let command_buffer = self.water_layer.get_command_buffer(frame_context);
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                None,
+                &[],
+                &[],
+                &[
+                    vk::ImageMemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::MEMORY_READ)
+                        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                        .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                        .src_queue_family_index(!0)
+                        .dst_queue_family_index(!0)
+                        .image(source_image)
+                        .subresource_range(color_subresource_range)
+                        .build(),
+                    vk::ImageMemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::SHADER_READ)
+                        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                        .src_queue_family_index(!0)
+                        .dst_queue_family_index(!0)
+                        .image(self.scene_color_copy.0)
+                        .subresource_range(color_subresource_range)
+                        .build(),
+                ],
+            );
+            command_buffer.copy_image(
+                source_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.scene_color_copy.0,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[vk::ImageCopy::builder()
+                    .src_subresource(color_subresource_layers)
+                    .src_offset(vk::Offset3D {
+                        x: screen_area.offset.x,
+                        y: screen_area.offset.y,
+                        z: 0,
+                    })
+                    .dst_subresource(color_subresource_layers)
+                    .dst_offset(vk::Offset3D {
+                        x: screen_area.offset.x,
+                        y: screen_area.offset.y,
+                        z: 0,
+                    })
+                    .extent(vk::Extent3D {
+                        width: screen_area.extent.width,
+                        height: screen_area.extent.height,
+                        depth: 1,
+                    })
+                    .build()],
+            );
+            command_buffer.pipeline_barrier(
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                None,
+                &[],
+                &[],
+                &[
+                    vk::ImageMemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+                        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .src_queue_family_index(!0)
+                        .dst_queue_family_index(!0)
+                        .image(source_image)
+                        .subresource_range(color_subresource_range)
+                        .build(),
+                    vk::ImageMemoryBarrier::builder()
+                        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .src_queue_family_index(!0)
+                        .dst_queue_family_index(!0)
+                        .image(self.scene_color_copy.0)
+                        .subresource_range(color_subresource_range)
+                        .build(),
+                ],
+            );
+        }
+
+        self.water_layer.begin_render_pass(frame_context, screen_area);
+        {
+            let command_buffer = self.water_layer.get_command_buffer(frame_context);
+            command_buffer.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            command_buffer.bind_descriptor_sets(
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[
+                    *shared_frame_data.get_frame_data_descriptor_set(frame_context),
+                    pbr_resource_bundle.descriptor_sets[0],
+                    self.descriptor_set,
+                ],
+                &[shared_frame_data.get_frame_data_offset(frame_context)],
+            );
+            command_buffer.push_constants(
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                &[constants],
+            );
+            command_buffer.draw(GRID_RESOLUTION * GRID_RESOLUTION * 6, 1, 0, 0);
+        }
+        self.water_layer.end_render_pass(frame_context);
+
+        let command_buffer = self.water_layer.get_command_buffer(frame_context);
+        command_buffer.pipeline_barrier(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            None,
+            &[],
+            &[],
+            &[vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(source_image)
+                .subresource_range(color_subresource_range)
+                .build()],
+        );
+        self.water_layer.submit_commands(frame_context, queue);
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct WaterSurfaceConstants {
+    subsample_view_projection: [f32; 16],
+    position_size: [f32; 4],
+    wave_amplitude_length_steepness_speed: [f32; 4],
+    wave_direction_time_unused: [f32; 4],
+    absorption_refraction: [f32; 4],
+}
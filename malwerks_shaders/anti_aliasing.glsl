@@ -5,16 +5,7 @@
 
 #version 460 core
 
-#ifdef VERTEX_STAGE
-layout(location = 0) out vec2 VS_uv;
-
-void main() {
-    VS_uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
-    gl_Position = vec4(VS_uv * 2.0f + -1.0f, 0.0f, 1.0f);
-}
-#endif
-
-#ifdef FRAGMENT_STAGE
+// Vertex stage is full_screen.glsl
 layout(set = 0, binding = 0) uniform sampler PointSampler;
 layout(set = 0, binding = 1) uniform texture2D SourceColorImage;
 layout(set = 0, binding = 2) uniform texture2D SourceDepthImage;
@@ -27,6 +18,7 @@ layout (std140, set = 1, binding = 0) uniform PerFrame {
     vec4 CameraPosition;
     vec4 CameraOrientation;
     vec4 ViewportSize;
+    vec4 RenderScale;
 };
 
 layout(location = 0) in vec2 VS_uv;
@@ -133,12 +125,15 @@ vec3 sample_lanczos_rgb(texture2D image, sampler samp, vec2 uv) {
 }
 
 void main() {
-    float depth_sample = texture(sampler2D(SourceDepthImage, PointSampler), VS_uv).r;
+    // Source is rendered into the top left corner of the image when resolution scaling is active,
+    // output and history are always full resolution which makes this a simple temporal upscaler
+    vec2 source_uv = VS_uv * RenderScale.xy;
+    float depth_sample = texture(sampler2D(SourceDepthImage, PointSampler), source_uv).r;
     
-    vec3 source_sample = texture(sampler2D(SourceColorImage, PointSampler), VS_uv).rgb;
+    vec3 source_sample = texture(sampler2D(SourceColorImage, PointSampler), source_uv).rgb;
     vec3 clip_min = source_sample;
     vec3 clip_max = source_sample;
-    sample_clip_min_max(SourceColorImage, PointSampler, VS_uv, source_sample, clip_min, clip_max);
+    sample_clip_min_max(SourceColorImage, PointSampler, source_uv, source_sample, clip_min, clip_max);
 
     vec2 uv = reproject_uv(ViewReprojection, VS_uv, depth_sample);
     vec3 frame_sample = clip_color(clip_min, clip_max, sample_lanczos_rgb(FrameImage, PointSampler, uv));
@@ -150,4 +145,3 @@ void main() {
     float weight = mix(0.97, 0.999, luminance_weight * luminance_weight);
     Target0 = vec4(mix(source_sample, frame_sample, weight), 1.0);
 }
-#endif
@@ -5,6 +5,12 @@
 
 #version 460 core
 
+#include "debug_printf.glsl"
+
+// Clusters of all instances are packed into one set of buffers and culled with a single dispatch.
+// Draw counts have to be cleared before the dispatch (vkCmdFillBuffer), there is no way to reset them
+// from the shader because workgroups can't synchronize with each other.
+
 struct BoundingCone {
     vec4 cone_apex;
     vec4 cone_axis;
@@ -18,25 +24,40 @@ struct DrawIndexedIndirectCommand {
     uint first_instance;
 };
 
+// Instance clusters are contiguous, surviving draws are compacted at first_output_command
+struct InstanceMetadata {
+    mat4 transform; // has to use uniform scale, cone axes are only renormalized
+    uint first_cluster;
+    uint cluster_count;
+    uint first_output_command;
+    uint padding;
+};
+
 layout (std430, set = 0, binding = 0) restrict readonly buffer InputBoundingCones {
     BoundingCone input_cones[];
 };
+layout (std430, set = 0, binding = 1) restrict readonly buffer InputClusterInstances {
+    uint input_cluster_instances[]; // instance index of every cluster
+};
+layout (std430, set = 0, binding = 2) restrict readonly buffer InputInstanceMetadata {
+    InstanceMetadata input_instances[];
+};
 
-layout (std430, set = 0, binding = 1) restrict readonly buffer InputOccluderDrawCommands {
+layout (std430, set = 0, binding = 3) restrict readonly buffer InputOccluderDrawCommands {
     DrawIndexedIndirectCommand input_occluder_draw_commands[];
 };
-layout (std430, set = 0, binding = 2) restrict readonly buffer InputDrawCommands {
+layout (std430, set = 0, binding = 4) restrict readonly buffer InputDrawCommands {
     DrawIndexedIndirectCommand input_draw_commands[];
 };
 
-layout (std430, set = 0, binding = 3) restrict buffer DrawCommandsCount {
-    uvec2 output_count;
+layout (std430, set = 0, binding = 5) restrict buffer DrawCommandsCount {
+    uint output_counts[]; // one per instance
 };
 
-layout (std430, set = 0, binding = 4) restrict writeonly buffer OutputOccluderDrawCommands {
+layout (std430, set = 0, binding = 6) restrict writeonly buffer OutputOccluderDrawCommands {
     DrawIndexedIndirectCommand output_occluder_draw_commands[];
 };
-layout (std430, set = 0, binding = 5) restrict writeonly buffer OutputDrawCommands {
+layout (std430, set = 0, binding = 7) restrict writeonly buffer OutputDrawCommands {
     DrawIndexedIndirectCommand output_draw_commands[];
 };
 
@@ -48,34 +69,23 @@ bool cone_apex_test(vec3 apex, vec4 axis) {
     return dot(normalize(apex - CameraPosition.xyz), axis.xyz) < axis.w;
 }
 
-layout (local_size_x = 8, local_size_y = 1, local_size_z = 1) in;
+layout (local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
 void main() {
-    if (gl_GlobalInvocationID.x < input_cones.length()) {
-        if (gl_GlobalInvocationID.x == 0) {
-            output_count = uvec2(0, 0);
-        }
-
-        barrier();
-
-        BoundingCone input_cluster = input_cones[gl_GlobalInvocationID.x];
-
-        // vec3 apex = input_cluster.cone_apex.xyz;
-        // uint axis_bits = floatBitsToUint(input_cluster.cone_apex.w);
-        // vec4 axis = vec4(
-        //     float((axis_bits >>  0) & 0xFF) / 255.0,
-        //     float((axis_bits >>  8) & 0xFF) / 255.0,
-        //     float((axis_bits >> 16) & 0xFF) / 255.0,
-        //     float((axis_bits >> 24) & 0xFF) / 255.0
-        // );
+    uint cluster_index = gl_GlobalInvocationID.x;
+    if (cluster_index < input_cones.length()) {
+        uint instance_index = input_cluster_instances[cluster_index];
+        DEBUG_ASSERT1(instance_index < input_instances.length(), "apex culling: cluster %u has no instance", cluster_index);
+        InstanceMetadata instance_metadata = input_instances[instance_index];
+        BoundingCone input_cluster = input_cones[cluster_index];
 
-        vec3 apex = input_cluster.cone_apex.xyz;
-        vec4 axis = input_cluster.cone_axis;
+        vec3 apex = (instance_metadata.transform * vec4(input_cluster.cone_apex.xyz, 1.0)).xyz;
+        vec4 axis = vec4(normalize(mat3(instance_metadata.transform) * input_cluster.cone_axis.xyz), input_cluster.cone_axis.w);
 
         bool cull_result = axis.w >= 1.0 || cone_apex_test(apex, axis);
         if (cull_result) {
-            uint command_index = atomicAdd(output_count.x, 1);
-            output_occluder_draw_commands[command_index] = input_occluder_draw_commands[gl_GlobalInvocationID.x];
-            output_draw_commands[command_index] = input_draw_commands[gl_GlobalInvocationID.x];
+            uint command_index = instance_metadata.first_output_command + atomicAdd(output_counts[instance_index], 1);
+            output_occluder_draw_commands[command_index] = input_occluder_draw_commands[cluster_index];
+            output_draw_commands[command_index] = input_draw_commands[cluster_index];
         }
     }
 }
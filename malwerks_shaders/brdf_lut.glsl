@@ -0,0 +1,74 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+// Split-sum environment BRDF, same integration as precompute_brdf tool.
+// X axis is dot(N, V), Y axis is roughness, output is scale and bias of F0.
+
+layout (push_constant) uniform PC_LutParameters {
+    uint lut_width;
+    uint lut_height;
+    uint sample_count;
+};
+
+layout (std430, set = 0, binding = 0) restrict writeonly buffer BrdfLut {
+    vec2 output_lut[];
+};
+
+const float PI = 3.14159265359;
+
+vec2 hammersley(uint i, uint n) {
+    uint bits = i;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2(float(i) / float(n), float(bits) * 2.3283064365386963e-10);
+}
+
+// Normal is always +Z here
+vec3 ggx_importance_sample(vec2 xi, float roughness) {
+    float a = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+float ggx_geometry_schlick(float dot_nv, float roughness) {
+    float k = (roughness * roughness) / 2.0;
+    return dot_nv / (dot_nv * (1.0 - k) + k);
+}
+
+layout (local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+void main() {
+    if (gl_GlobalInvocationID.x >= lut_width || gl_GlobalInvocationID.y >= lut_height) {
+        return;
+    }
+
+    vec2 uv = (vec2(gl_GlobalInvocationID.xy) + 0.5) / vec2(lut_width, lut_height);
+    float dot_nv = uv.x;
+    float roughness = uv.y;
+    vec3 view = vec3(sqrt(1.0 - dot_nv * dot_nv), 0.0, dot_nv);
+
+    vec2 result = vec2(0.0);
+    for (uint i = 0; i < sample_count; i++) {
+        vec3 h = ggx_importance_sample(hammersley(i, sample_count), roughness);
+        vec3 l = normalize(2.0 * dot(view, h) * h - view);
+
+        float dot_nl = max(l.z, 0.0);
+        float dot_nh = max(h.z, 0.0);
+        float dot_vh = max(dot(view, h), 0.0);
+        if (dot_nl > 0.0) {
+            float g = ggx_geometry_schlick(dot_nl, roughness) * ggx_geometry_schlick(dot_nv, roughness);
+            float vis = (g * dot_vh) / (dot_nh * dot_nv);
+            float fc = pow(1.0 - dot_vh, 5.0);
+            result += vec2((1.0 - fc) * vis, fc * vis);
+        }
+    }
+    output_lut[gl_GlobalInvocationID.y * lut_width + gl_GlobalInvocationID.x] = result / float(sample_count);
+}
@@ -0,0 +1,31 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Shaders are compiled with SHADER_DEBUG_PRINTF when the device has debug printf enabled,
+// messages show up in the log and the shader console. Everything compiles out otherwise.
+// Has to be included before any declarations, GLSL has no variadic macros so every argument count has its own.
+
+#ifdef SHADER_DEBUG_PRINTF
+#extension GL_EXT_debug_printf : require
+
+#define DEBUG_PRINTF(format) debugPrintfEXT(format)
+#define DEBUG_PRINTF1(format, a) debugPrintfEXT(format, a)
+#define DEBUG_PRINTF2(format, a, b) debugPrintfEXT(format, a, b)
+#define DEBUG_PRINTF3(format, a, b, c) debugPrintfEXT(format, a, b, c)
+#define DEBUG_PRINTF4(format, a, b, c, d) debugPrintfEXT(format, a, b, c, d)
+
+// Failing invocations print the message and keep running
+#define DEBUG_ASSERT(condition, message) if (!(condition)) { debugPrintfEXT(message); }
+#define DEBUG_ASSERT1(condition, message, a) if (!(condition)) { debugPrintfEXT(message, a); }
+#else
+#define DEBUG_PRINTF(format)
+#define DEBUG_PRINTF1(format, a)
+#define DEBUG_PRINTF2(format, a, b)
+#define DEBUG_PRINTF3(format, a, b, c)
+#define DEBUG_PRINTF4(format, a, b, c, d)
+
+#define DEBUG_ASSERT(condition, message)
+#define DEBUG_ASSERT1(condition, message, a)
+#endif
@@ -0,0 +1,43 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+#ifdef VERTEX_STAGE
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(uv * 2.0f + -1.0f, 0.0f, 1.0f);
+}
+#endif
+
+#ifdef FRAGMENT_STAGE
+layout (push_constant) uniform PC_DepthView {
+    vec4 OutputOffsetRenderScaleZNear;
+    vec4 MaxDistanceLevel;
+};
+
+layout (set = 0, binding = 0) uniform sampler2D DepthImage;
+
+layout (location = 0) out vec4 Target0;
+
+// Reversed infinite Z, zero depth is infinitely far away
+float linearize_depth(float depth, float z_near) {
+    return depth > 0.0 ? z_near / depth : 1.0e30;
+}
+
+void main() {
+    // Output area is the whole viewport, depth has the render resolution
+    vec2 output_offset = OutputOffsetRenderScaleZNear.xy;
+    vec2 render_coord = output_offset + (gl_FragCoord.xy - output_offset) * OutputOffsetRenderScaleZNear.z;
+    int level = int(MaxDistanceLevel.y);
+    ivec2 coord = min(ivec2(render_coord) >> level, textureSize(DepthImage, level) - ivec2(1));
+    float distance = linearize_depth(texelFetch(DepthImage, coord, level).r, OutputOffsetRenderScaleZNear.w);
+
+    // Logarithmic scale keeps close and distant geometry distinguishable, white is at the near plane
+    float max_distance = MaxDistanceLevel.x;
+    float intensity = 1.0 - clamp(log2(1.0 + distance) / log2(1.0 + max_distance), 0.0, 1.0);
+    Target0 = vec4(vec3(intensity), 1.0);
+}
+#endif
@@ -0,0 +1,127 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+// Filters one mip of all 6 faces of a cube map from the source environment:
+// mode 0 is cosine convolution for IEM, mode 1 is GGX prefiltering for PMREM with N = V = R.
+// Samples read from source mips that match their solid angle, so that low sample counts don't alias.
+
+#define CONVOLUTION_MODE_IEM 0
+#define CONVOLUTION_MODE_PMREM 1
+
+layout (push_constant) uniform PC_ConvolutionParameters {
+    uint convolution_mode;
+    uint face_size;
+    uint sample_count;
+    float roughness;
+    uint output_offset; // texels, face 0
+    uint output_face_stride; // texels between faces
+};
+
+layout (set = 0, binding = 0) uniform samplerCube SourceEnvironment;
+layout (std430, set = 0, binding = 1) restrict writeonly buffer ConvolutionOutput {
+    uvec2 output_texels[]; // RGBA16F
+};
+
+const float PI = 3.14159265359;
+
+vec2 hammersley(uint i, uint n) {
+    uint bits = i;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2(float(i) / float(n), float(bits) * 2.3283064365386963e-10);
+}
+
+// Vulkan cube face orientation, uv is in [-1, 1] with v pointing down
+vec3 get_cube_direction(uint face, vec2 uv) {
+    switch (face) {
+    case 0: return normalize(vec3(1.0, -uv.y, -uv.x));
+    case 1: return normalize(vec3(-1.0, -uv.y, uv.x));
+    case 2: return normalize(vec3(uv.x, 1.0, uv.y));
+    case 3: return normalize(vec3(uv.x, -1.0, -uv.y));
+    case 4: return normalize(vec3(uv.x, -uv.y, 1.0));
+    default: return normalize(vec3(-uv.x, -uv.y, -1.0));
+    }
+}
+
+mat3 get_tangent_frame(vec3 normal) {
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+    return mat3(tangent, bitangent, normal);
+}
+
+// Mip of the source that has roughly one texel per sample
+float get_source_lod(float pdf) {
+    float source_size = float(textureSize(SourceEnvironment, 0).x);
+    float sample_solid_angle = 1.0 / (float(sample_count) * pdf + 1e-6);
+    float texel_solid_angle = 4.0 * PI / (6.0 * source_size * source_size);
+    return clamp(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0, float(textureQueryLevels(SourceEnvironment) - 1));
+}
+
+vec3 convolve_iem(vec3 normal) {
+    mat3 tangent_frame = get_tangent_frame(normal);
+    vec3 result = vec3(0.0);
+    for (uint i = 0; i < sample_count; i++) {
+        vec2 xi = hammersley(i, sample_count);
+        float phi = 2.0 * PI * xi.x;
+        float cos_theta = sqrt(1.0 - xi.y);
+        float sin_theta = sqrt(xi.y);
+        vec3 direction = tangent_frame * vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+        // Cosine weighted samples, the average is irradiance divided by pi
+        float pdf = cos_theta / PI;
+        result += textureLod(SourceEnvironment, direction, get_source_lod(pdf)).rgb;
+    }
+    return result / float(sample_count);
+}
+
+vec3 convolve_pmrem(vec3 normal) {
+    if (roughness == 0.0) {
+        return textureLod(SourceEnvironment, normal, 0.0).rgb;
+    }
+
+    mat3 tangent_frame = get_tangent_frame(normal);
+    float a = roughness * roughness;
+    vec3 result = vec3(0.0);
+    float total_weight = 0.0;
+    for (uint i = 0; i < sample_count; i++) {
+        vec2 xi = hammersley(i, sample_count);
+        float phi = 2.0 * PI * xi.x;
+        float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+        float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        vec3 h = tangent_frame * vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+        vec3 l = 2.0 * dot(normal, h) * h - normal;
+
+        float dot_nl = dot(normal, l);
+        if (dot_nl > 0.0) {
+            // D(h) * dot(n, h) / (4 * dot(v, h)) with n = v
+            float d = a * a / (PI * pow(cos_theta * cos_theta * (a * a - 1.0) + 1.0, 2.0));
+            float pdf = d / 4.0;
+            result += textureLod(SourceEnvironment, l, get_source_lod(pdf)).rgb * dot_nl;
+            total_weight += dot_nl;
+        }
+    }
+    return result / max(total_weight, 1e-6);
+}
+
+layout (local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+void main() {
+    if (gl_GlobalInvocationID.x >= face_size || gl_GlobalInvocationID.y >= face_size) {
+        return;
+    }
+
+    uint face = gl_GlobalInvocationID.z;
+    vec2 uv = (vec2(gl_GlobalInvocationID.xy) + 0.5) / float(face_size) * 2.0 - 1.0;
+    vec3 normal = get_cube_direction(face, uv);
+
+    vec3 result = convolution_mode == CONVOLUTION_MODE_IEM ? convolve_iem(normal) : convolve_pmrem(normal);
+    uint texel_id = output_offset + face * output_face_stride + gl_GlobalInvocationID.y * face_size + gl_GlobalInvocationID.x;
+    output_texels[texel_id] = uvec2(packHalf2x16(result.rg), packHalf2x16(vec2(result.b, 1.0)));
+}
@@ -0,0 +1,80 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+// Mode 0 resamples an equirectangular panorama into the top mip of all 6 cube faces,
+// mode 1 box filters the previous mip that is already in the output buffer into the next one.
+
+#define CONVERSION_MODE_PANORAMA 0
+#define CONVERSION_MODE_DOWNSAMPLE 1
+
+layout (push_constant) uniform PC_ConversionParameters {
+    uint conversion_mode;
+    uint face_size;
+    uint input_offset; // texels, face 0 of the previous mip
+    uint output_offset; // texels, face 0
+    uint face_stride; // texels between faces
+};
+
+layout (set = 0, binding = 0) uniform sampler2D Panorama;
+layout (std430, set = 0, binding = 1) restrict buffer CubeTexels {
+    uvec2 cube_texels[]; // RGBA16F
+};
+
+const float PI = 3.14159265359;
+
+// Vulkan cube face orientation, uv is in [-1, 1] with v pointing down
+vec3 get_cube_direction(uint face, vec2 uv) {
+    switch (face) {
+    case 0: return normalize(vec3(1.0, -uv.y, -uv.x));
+    case 1: return normalize(vec3(-1.0, -uv.y, uv.x));
+    case 2: return normalize(vec3(uv.x, 1.0, uv.y));
+    case 3: return normalize(vec3(uv.x, -1.0, -uv.y));
+    case 4: return normalize(vec3(uv.x, -uv.y, 1.0));
+    default: return normalize(vec3(-uv.x, -uv.y, -1.0));
+    }
+}
+
+// Panorama center looks along +Z, top row is +Y
+vec2 get_panorama_uv(vec3 direction) {
+    return vec2(atan(direction.x, direction.z) / (2.0 * PI) + 0.5, acos(clamp(direction.y, -1.0, 1.0)) / PI);
+}
+
+vec3 load_texel(uint texel_id) {
+    uvec2 texel = cube_texels[texel_id];
+    return vec3(unpackHalf2x16(texel.x), unpackHalf2x16(texel.y).x);
+}
+
+layout (local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+void main() {
+    if (gl_GlobalInvocationID.x >= face_size || gl_GlobalInvocationID.y >= face_size) {
+        return;
+    }
+
+    uint face = gl_GlobalInvocationID.z;
+    uvec2 texel = gl_GlobalInvocationID.xy;
+    vec3 result = vec3(0.0);
+    if (conversion_mode == CONVERSION_MODE_PANORAMA) {
+        // 2x2 supersampling, cube texels near face corners cover several panorama texels
+        for (uint sample_id = 0; sample_id < 4; sample_id++) {
+            vec2 offset = vec2(sample_id & 1, sample_id >> 1) * 0.5 + 0.25;
+            vec2 uv = (vec2(texel) + offset) / float(face_size) * 2.0 - 1.0;
+            result += textureLod(Panorama, get_panorama_uv(get_cube_direction(face, uv)), 0.0).rgb;
+        }
+        result *= 0.25;
+    } else {
+        uint input_size = face_size * 2;
+        uint input_base = input_offset + face * face_stride;
+        for (uint sample_id = 0; sample_id < 4; sample_id++) {
+            uvec2 input_texel = texel * 2 + uvec2(sample_id & 1, sample_id >> 1);
+            result += load_texel(input_base + input_texel.y * input_size + input_texel.x);
+        }
+        result *= 0.25;
+    }
+
+    uint texel_id = output_offset + face * face_stride + texel.y * face_size + texel.x;
+    cube_texels[texel_id] = uvec2(packHalf2x16(result.rg), packHalf2x16(vec2(result.b, 1.0)));
+}
@@ -0,0 +1,14 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+// Single triangle covering the target, uv is in [0, 1] inside of it
+layout(location = 0) out vec2 VS_uv;
+
+void main() {
+    VS_uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(VS_uv * 2.0f + -1.0f, 0.0f, 1.0f);
+}
@@ -5,8 +5,36 @@
 
 #version 460 core
 
+#ifdef MULTIVIEW
+#extension GL_EXT_multiview : require
+#endif
+
+#ifdef SHADER_CLOCK
+#extension GL_ARB_shader_clock : require
+#endif
+
+#include "debug_printf.glsl"
+
+#define CAMERA_NEAR_DISTANCE 0.1
+#define MAX_LOCAL_PROBES 8
+#define PI 3.14159265359
+
+// Has to match light_clustering.rs and light_clustering.glsl
+#define CLUSTER_GRID_SIZE_X 16
+#define CLUSTER_GRID_SIZE_Y 9
+#define CLUSTER_GRID_SIZE_Z 24
+#define CLUSTER_COUNT (CLUSTER_GRID_SIZE_X * CLUSTER_GRID_SIZE_Y * CLUSTER_GRID_SIZE_Z)
+#define CLUSTER_FAR_DISTANCE 512.0
+#define MAX_LIGHTS_PER_CLUSTER 32
+
+// Has to match pbr_resource_bundle.rs, ies_profile.rs and precompute_ltc.rs
+#define MAX_AREA_LIGHT_TEXTURES 8
+#define IES_PROFILE_SIZE vec2(64.0, 16.0)
+#define LTC_TABLE_SIZE 64.0
+
 #include "generated://attribute_fetch.glsl"
 #include "generated://image_mapping.glsl"
+#include "generated://material_parameters.glsl"
 
 layout (std140, set = 2, binding = 0) uniform PerFrame {
     mat4 ViewProjection;
@@ -15,6 +43,10 @@ layout (std140, set = 2, binding = 0) uniform PerFrame {
     vec4 CameraPosition;
     vec4 CameraOrientation;
     vec4 ViewportSize;
+    vec4 RenderScale;
+    mat4 StereoViewProjection[2];
+    uvec4 PunctualLightCount;
+    uvec4 BlueNoiseLayer;
 };
 
 #ifdef VERTEX_STAGE
@@ -24,26 +56,81 @@ layout (push_constant) uniform PC_ViewProjection {
 
 void main() {
     vec4 position = fetch_vertex_attributes();
-    gl_Position = ViewProjectionPC * position;
+    #ifdef MULTIVIEW
+        gl_Position = StereoViewProjection[gl_ViewIndex] * position;
+    #else
+        gl_Position = ViewProjectionPC * position;
+    #endif
 }
 #endif
 
 #ifdef FRAGMENT_STAGE
-layout (push_constant) uniform PC_MaterialInstance {
-    layout (offset = 64) vec4 base_color_factor;
-    layout (offset = 80) vec4 metallic_roughness_discard_unused;
-    layout (offset = 96) vec4 emissive_rgb_unused;
-    layout (offset = 112) vec4 unused;
-};
-
 layout (set = 3, binding = 0) uniform sampler2D PrecomputedBrdf;
 layout (set = 3, binding = 1) uniform samplerCube ProbeTexture;
 layout (set = 3, binding = 2) uniform samplerCube IemTexture;
 layout (set = 3, binding = 3) uniform samplerCube PmremTexture;
 
+struct LocalProbe {
+    vec4 position_blend_distance; // capture position, blend distance
+    vec4 box_min;
+    vec4 box_max;
+};
+
+// Local probes are sorted by priority, unused slots point to the global probe
+layout (set = 3, binding = 4) uniform samplerCube LocalIemTextures[MAX_LOCAL_PROBES];
+layout (set = 3, binding = 5) uniform samplerCube LocalPmremTextures[MAX_LOCAL_PROBES];
+layout (std140, set = 3, binding = 6) uniform LocalProbes {
+    LocalProbe local_probes[MAX_LOCAL_PROBES];
+    uvec4 local_probe_count_unused;
+    vec4 irradiance_volume_min;
+    vec4 irradiance_volume_max;
+};
+// Ambient cube faces (+X, -X, +Y, -Y, +Z, -Z) are stored as separate blocks of the probe grid along X
+layout (set = 3, binding = 7) uniform sampler3D IrradianceVolume;
+
+// IES profile layers store normalized intensity by vertical (X) and horizontal (Y) angle
+layout (set = 3, binding = 8) uniform sampler2DArray IesProfiles;
+// Inverse LTC matrices and GGX norms indexed by roughness (X) and sqrt(1 - cos(theta)) (Y)
+layout (set = 3, binding = 9) uniform sampler2D LtcMatrix;
+layout (set = 3, binding = 10) uniform sampler2D LtcAmplitude;
+layout (set = 3, binding = 11) uniform sampler2D AreaLightTextures[MAX_AREA_LIGHT_TEXTURES];
+
+struct PunctualLight {
+    vec4 position_range;
+    vec4 color_intensity;
+    vec4 direction_type; // light direction, 0.0 for point, 1.0 for spot, 2.0 for rect and 3.0 for two-sided rect lights
+    vec4 parameters; // spot scale and offset, IES profile or area light texture index, -1.0 means none
+    vec4 area_half_width_axis;
+    vec4 area_half_height_axis;
+};
+
+layout (std430, set = 2, binding = 1) restrict readonly buffer PunctualLights {
+    PunctualLight punctual_lights[];
+};
+layout (std430, set = 2, binding = 2) restrict readonly buffer ClusterLights {
+    uint cluster_light_counts[CLUSTER_COUNT];
+    uint cluster_light_indices[]; // MAX_LIGHTS_PER_CLUSTER slots per cluster
+};
+
+// Animated UVs are scaled and offset first, atlased textures wrap inside their rect
+// and gradients of the original UV keep mip selection continuous at the seams
+vec4 sample_material_texture(sampler2D material_texture, vec2 uv) {
+    #ifdef UV_ANIMATION
+        uv = uv * uv_animation_scale_offset.xy + uv_animation_scale_offset.zw;
+    #endif
+    #ifdef TEXTURE_ATLAS
+        if (atlas_scale_offset.x < 1.0) {
+            vec2 rect_half_texel = 0.5 / (vec2(textureSize(material_texture, 0)) * atlas_scale_offset.xy);
+            vec2 atlas_uv = clamp(fract(uv), rect_half_texel, vec2(1.0) - rect_half_texel) * atlas_scale_offset.xy + atlas_scale_offset.zw;
+            return textureGrad(material_texture, atlas_uv, dFdx(uv) * atlas_scale_offset.xy, dFdy(uv) * atlas_scale_offset.xy);
+        }
+    #endif
+    return texture(material_texture, uv);
+}
+
 vec4 sample_base_color() {
     #ifdef HAS_BaseColorTexture
-        vec4 color_sample = texture(BaseColorTexture, BaseColorTexture_UV) * base_color_factor;
+        vec4 color_sample = sample_material_texture(BaseColorTexture, BaseColorTexture_UV) * base_color_factor;
         #ifdef HAS_AlphaDiscard
             if (color_sample.a < metallic_roughness_discard_unused.z) {
                 discard;
@@ -57,7 +144,7 @@ vec4 sample_base_color() {
 
 vec2 sample_metallic_roughness() {
     #ifdef HAS_MetallicRoughnessTexture
-        return texture(MetallicRoughnessTexture, MetallicRoughnessTexture_UV).bg * metallic_roughness_discard_unused.xy;
+        return sample_material_texture(MetallicRoughnessTexture, MetallicRoughnessTexture_UV).bg * metallic_roughness_discard_unused.xy;
     #else
         return metallic_roughness_discard_unused.xy;
     #endif
@@ -92,7 +179,7 @@ vec3 sample_normal() {
         vec3 binormal = cross(normal, tangent) * input_tangent.w;
         mat3 tbn = mat3(tangent, binormal, normal);
 
-        vec3 normal_sample = texture(NormalTexture, NormalTexture_UV).xyz * 2.0 - 1.0;
+        vec3 normal_sample = sample_material_texture(NormalTexture, NormalTexture_UV).xyz * 2.0 - 1.0;
         return normalize(tbn * normal_sample);
     #else
         return normalize(input_normal);
@@ -101,50 +188,473 @@ vec3 sample_normal() {
 
 float sample_occlusion() {
     #ifdef HAS_OcclusionTexture
-        return texture(OcclusionTexture, OcclusionTexture_UV).r;
+        return sample_material_texture(OcclusionTexture, OcclusionTexture_UV).r;
     #else
         return 1.0;
     #endif
 }
 
+// Baked sky visibility darkens ambient light, the bent normal tilts the shading normal towards unoccluded sky
+vec3 apply_sky_visibility(vec3 normal, inout float occlusion) {
+    #ifdef HAS_VS_sky_visibility
+        occlusion *= VS_sky_visibility.w;
+        #ifdef HAS_VS_normal
+            return normalize(normal + normalize(VS_sky_visibility.xyz) - normalize(VS_normal));
+        #else
+            return normalize(VS_sky_visibility.xyz);
+        #endif
+    #else
+        return normal;
+    #endif
+}
+
 vec3 sample_emissive() {
     #ifdef HAS_EmissiveTexture
-        return texture(EmissiveTexture, EmissiveTexture_UV).rgb * emissive_rgb_unused.rgb;
+        return sample_material_texture(EmissiveTexture, EmissiveTexture_UV).rgb * emissive_rgb_unused.rgb;
     #else
         return emissive_rgb_unused.rgb;
     #endif
 }
 
+// 1.0 deep inside the box, fades out to 0.0 at the box sides
+float local_probe_weight(vec3 position, LocalProbe probe) {
+    vec3 side_distance = min(position - probe.box_min.xyz, probe.box_max.xyz - position);
+    float distance = min(min(side_distance.x, side_distance.y), side_distance.z);
+    return clamp(distance / max(probe.position_blend_distance.w, 1e-4), 0.0, 1.0);
+}
+
+// Intersects the reflection ray with the box and returns the direction from the capture position to the hit point
+vec3 box_project(vec3 position, vec3 direction, LocalProbe probe) {
+    vec3 max_plane = (probe.box_max.xyz - position) / direction;
+    vec3 min_plane = (probe.box_min.xyz - position) / direction;
+    vec3 exit_plane = max(max_plane, min_plane);
+    float distance = max(min(min(exit_plane.x, exit_plane.y), exit_plane.z), 0.0);
+    return position + direction * distance - probe.position_blend_distance.xyz;
+}
+
+// Samples one face block of the volume, coordinates are clamped to texel centers to avoid bleeding between blocks
+vec3 sample_irradiance_volume_face(vec3 grid_position, vec3 grid_size, int face) {
+    vec3 texel = clamp(grid_position, vec3(0.5), grid_size - vec3(0.5));
+    texel.x += grid_size.x * float(face);
+    return textureLod(IrradianceVolume, texel / vec3(grid_size.x * 6.0, grid_size.yz), 0.0).rgb;
+}
+
+// Baked diffuse transfer at the given position, multiplies irradiance of the global probe
+vec3 sample_irradiance_volume(vec3 position, vec3 normal) {
+    vec3 grid_size = vec3(textureSize(IrradianceVolume, 0)) / vec3(6.0, 1.0, 1.0);
+    vec3 volume_position = (position - irradiance_volume_min.xyz) / (irradiance_volume_max.xyz - irradiance_volume_min.xyz);
+    vec3 grid_position = volume_position * (grid_size - vec3(1.0)) + vec3(0.5);
+
+    vec3 normal_squared = normal * normal;
+    ivec3 face = ivec3(0, 2, 4) + ivec3(lessThan(normal, vec3(0.0)));
+    return normal_squared.x * sample_irradiance_volume_face(grid_position, grid_size, face.x) +
+           normal_squared.y * sample_irradiance_volume_face(grid_position, grid_size, face.y) +
+           normal_squared.z * sample_irradiance_volume_face(grid_position, grid_size, face.z);
+}
+
 float specular_occlusion(float dot_nv, float occlusion, float roughness) {
     return clamp(pow(dot_nv + occlusion, roughness) - 1.0 + occlusion, 0.0, 1.0);
 }
 
 vec3 calculate_ibl(
+    vec3 position,
     vec3 normal,
+    vec3 irradiance_normal,
     vec3 view_direction,
     vec3 diffuse_color,
     vec3 specular_color,
     float metallic,
     float roughness,
-    float occlusion
+    float occlusion,
+    out vec3 specular_reflectance
 ) {
     float dot_nv = clamp(dot(normal, view_direction), 0.0, 1.0);
     vec3 reflect_direction = normalize(reflect(-view_direction, normal));
 
-    vec3 irradiance = texture(IemTexture, normal).rgb;
-    vec3 radiance = textureLod(PmremTexture, reflect_direction, roughness * 10.0).rgb;
+    // Local probes are blended in priority order, the global probe fills the remaining weight
+    // and its irradiance is attenuated by the irradiance volume
+    vec3 irradiance = vec3(0.0);
+    vec3 radiance = vec3(0.0);
+    float remaining_weight = 1.0;
+    for (uint probe_id = 0; probe_id < local_probe_count_unused.x; probe_id++) {
+        float weight = local_probe_weight(position, local_probes[probe_id]) * remaining_weight;
+        if (weight > 0.0) {
+            vec3 probe_direction = box_project(position, reflect_direction, local_probes[probe_id]);
+            irradiance += textureLod(LocalIemTextures[probe_id], irradiance_normal, 0.0).rgb * weight;
+            radiance += textureLod(LocalPmremTextures[probe_id], probe_direction, roughness * 10.0).rgb * weight;
+            remaining_weight -= weight;
+        }
+    }
+    irradiance += texture(IemTexture, irradiance_normal).rgb * sample_irradiance_volume(position, irradiance_normal) *
+                  remaining_weight;
+    radiance += textureLod(PmremTexture, reflect_direction, roughness * 10.0).rgb * remaining_weight;
     vec2 brdf = texture(PrecomputedBrdf, vec2(dot_nv, roughness)).xy;
     float specular_occlusion = specular_occlusion(dot_nv, occlusion, roughness);
 
+    specular_reflectance = (specular_color * brdf.x + brdf.y) * specular_occlusion;
+
     vec3 diffuse_light = irradiance * diffuse_color * occlusion;
-    vec3 specular_light = radiance * (specular_color * brdf.x + brdf.y) * specular_occlusion;
+    vec3 specular_light = radiance * specular_reflectance;
 
     return diffuse_light + specular_light;
 }
 
+// Light clusters are found with the unjittered view projection, stereo views use the clusters of the center view
+bool find_light_cluster(vec3 position, out uint cluster_index) {
+    vec4 clip_position = ViewProjection * vec4(position, 1.0);
+    float slice = log(max(clip_position.w, CAMERA_NEAR_DISTANCE) / CAMERA_NEAR_DISTANCE) /
+                  log(CLUSTER_FAR_DISTANCE / CAMERA_NEAR_DISTANCE);
+    if (slice >= 1.0) {
+        return false;
+    }
+
+    vec2 cluster_uv = clamp(clip_position.xy / clip_position.w * 0.5 + vec2(0.5), vec2(0.0), vec2(0.99999));
+    uvec3 cluster = uvec3(vec3(cluster_uv, slice) * vec3(CLUSTER_GRID_SIZE_X, CLUSTER_GRID_SIZE_Y, CLUSTER_GRID_SIZE_Z));
+    cluster_index = (cluster.z * CLUSTER_GRID_SIZE_Y + cluster.y) * CLUSTER_GRID_SIZE_X + cluster.x;
+    return true;
+}
+
+// "Building an Orthonormal Basis, Revisited" by Duff et al.
+void build_orthonormal_basis(vec3 normal, out vec3 tangent, out vec3 bitangent) {
+    float sign_z = normal.z >= 0.0 ? 1.0 : -1.0;
+    float a = -1.0 / (sign_z + normal.z);
+    float b = normal.x * normal.y * a;
+    tangent = vec3(1.0 + sign_z * normal.x * normal.x * a, sign_z * b, -sign_z * normal.x);
+    bitangent = vec3(b, sign_z + normal.y * normal.y * a, -normal.y);
+}
+
+// Type C photometry, vertical angle is measured from the light axis and horizontal angle around it
+float sample_ies_profile(float profile, vec3 light_axis, vec3 emit_direction) {
+    vec3 tangent;
+    vec3 bitangent;
+    build_orthonormal_basis(light_axis, tangent, bitangent);
+
+    float vertical_angle = acos(clamp(dot(emit_direction, light_axis), -1.0, 1.0)) / PI;
+    float horizontal_angle = atan(dot(emit_direction, bitangent), dot(emit_direction, tangent)) / (2.0 * PI);
+    vec2 profile_uv = vec2(vertical_angle, fract(horizontal_angle + 1.0));
+    profile_uv = profile_uv * (IES_PROFILE_SIZE - vec2(1.0)) / IES_PROFILE_SIZE + vec2(0.5) / IES_PROFILE_SIZE;
+    return textureLod(IesProfiles, vec3(profile_uv, profile), 0.0).r;
+}
+
+// Lambert diffuse and GGX specular with KHR_lights_punctual attenuation
+vec3 calculate_punctual_light(
+    PunctualLight light,
+    vec3 position,
+    vec3 normal,
+    vec3 view_direction,
+    vec3 diffuse_color,
+    vec3 specular_color,
+    float roughness
+) {
+    vec3 light_vector = light.position_range.xyz - position;
+    float distance_squared = max(dot(light_vector, light_vector), 1e-4);
+    vec3 light_direction = light_vector * inversesqrt(distance_squared);
+
+    float range_ratio = distance_squared / (light.position_range.w * light.position_range.w);
+    float attenuation = clamp(1.0 - range_ratio * range_ratio, 0.0, 1.0) / distance_squared;
+    if (light.direction_type.w > 0.5) {
+        float spot_attenuation = clamp(
+            dot(light.direction_type.xyz, -light_direction) * light.parameters.x + light.parameters.y,
+            0.0,
+            1.0
+        );
+        attenuation *= spot_attenuation * spot_attenuation;
+    }
+    if (light.parameters.z >= 0.0 && attenuation > 0.0) {
+        attenuation *= sample_ies_profile(light.parameters.z, light.direction_type.xyz, -light_direction);
+    }
+
+    float dot_nl = clamp(dot(normal, light_direction), 0.0, 1.0);
+    if (attenuation * dot_nl <= 0.0) {
+        return vec3(0.0);
+    }
+
+    vec3 half_vector = normalize(light_direction + view_direction);
+    float dot_nv = clamp(dot(normal, view_direction), 1e-4, 1.0);
+    float dot_nh = clamp(dot(normal, half_vector), 0.0, 1.0);
+    float dot_vh = clamp(dot(view_direction, half_vector), 0.0, 1.0);
+
+    float alpha = max(roughness * roughness, 1e-3);
+    float alpha_squared = alpha * alpha;
+    float distribution_denominator = dot_nh * dot_nh * (alpha_squared - 1.0) + 1.0;
+    float distribution = alpha_squared / (PI * distribution_denominator * distribution_denominator);
+    float k = 0.5 * alpha;
+    float visibility = 0.25 / ((dot_nl * (1.0 - k) + k) * (dot_nv * (1.0 - k) + k));
+    vec3 fresnel = specular_color + (vec3(1.0) - specular_color) * pow(1.0 - dot_vh, 5.0);
+
+    vec3 radiance = light.color_intensity.rgb * light.color_intensity.a * attenuation * dot_nl;
+    return (diffuse_color / PI + fresnel * distribution * visibility) * radiance;
+}
+
+// Rect area lights are based on "Real-Time Polygonal-Light Shading with Linearly Transformed Cosines" by Heitz et al.
+float ltc_integrate_edge(vec3 v1, vec3 v2) {
+    float x = dot(v1, v2);
+    float y = abs(x);
+    float a = 0.8543985 + (0.4965155 + 0.0145206 * y) * y;
+    float b = 3.4175940 + (4.1616724 + y) * y;
+    float v = a / b;
+    float theta_sin_theta = x > 0.0 ? v : 0.5 * inversesqrt(max(1.0 - x * x, 1e-7)) - v;
+    return cross(v1, v2).z * theta_sin_theta;
+}
+
+void ltc_clip_quad_to_horizon(inout vec3 points[5], out int point_count) {
+    int config = 0;
+    if (points[0].z > 0.0) config += 1;
+    if (points[1].z > 0.0) config += 2;
+    if (points[2].z > 0.0) config += 4;
+    if (points[3].z > 0.0) config += 8;
+
+    // Configurations 5 and 10 can't happen with a convex quad and are skipped together with 0
+    point_count = 0;
+    if (config == 1) {
+        point_count = 3;
+        points[1] = -points[1].z * points[0] + points[0].z * points[1];
+        points[2] = -points[3].z * points[0] + points[0].z * points[3];
+    } else if (config == 2) {
+        point_count = 3;
+        points[0] = -points[0].z * points[1] + points[1].z * points[0];
+        points[2] = -points[2].z * points[1] + points[1].z * points[2];
+    } else if (config == 3) {
+        point_count = 4;
+        points[2] = -points[2].z * points[1] + points[1].z * points[2];
+        points[3] = -points[3].z * points[0] + points[0].z * points[3];
+    } else if (config == 4) {
+        point_count = 3;
+        points[0] = -points[3].z * points[2] + points[2].z * points[3];
+        points[1] = -points[1].z * points[2] + points[2].z * points[1];
+    } else if (config == 6) {
+        point_count = 4;
+        points[0] = -points[0].z * points[1] + points[1].z * points[0];
+        points[3] = -points[3].z * points[2] + points[2].z * points[3];
+    } else if (config == 7) {
+        point_count = 5;
+        points[4] = -points[3].z * points[0] + points[0].z * points[3];
+        points[3] = -points[3].z * points[2] + points[2].z * points[3];
+    } else if (config == 8) {
+        point_count = 3;
+        points[0] = -points[0].z * points[3] + points[3].z * points[0];
+        points[1] = -points[2].z * points[3] + points[3].z * points[2];
+        points[2] = points[3];
+    } else if (config == 9) {
+        point_count = 4;
+        points[1] = -points[1].z * points[0] + points[0].z * points[1];
+        points[2] = -points[2].z * points[3] + points[3].z * points[2];
+    } else if (config == 11) {
+        point_count = 5;
+        points[4] = points[3];
+        points[3] = -points[2].z * points[3] + points[3].z * points[2];
+        points[2] = -points[2].z * points[1] + points[1].z * points[2];
+    } else if (config == 12) {
+        point_count = 4;
+        points[1] = -points[1].z * points[2] + points[2].z * points[1];
+        points[0] = -points[0].z * points[3] + points[3].z * points[0];
+    } else if (config == 13) {
+        point_count = 5;
+        points[4] = points[3];
+        points[3] = points[2];
+        points[2] = -points[1].z * points[2] + points[2].z * points[1];
+        points[1] = -points[1].z * points[0] + points[0].z * points[1];
+    } else if (config == 14) {
+        point_count = 5;
+        points[4] = -points[0].z * points[3] + points[3].z * points[0];
+        points[0] = -points[0].z * points[1] + points[1].z * points[0];
+    } else if (config == 15) {
+        point_count = 4;
+    }
+
+    if (point_count == 3) points[3] = points[0];
+    if (point_count == 4) points[4] = points[0];
+}
+
+// Shading point is projected onto the light plane in cosine space, its distance selects the filtered mip
+vec3 fetch_area_light_texture(int texture_index, vec3 p0, vec3 p1, vec3 p3) {
+    vec3 v1 = p1 - p0;
+    vec3 v2 = p3 - p0;
+    vec3 plane_ortho = cross(v1, v2);
+    float plane_area_squared = dot(plane_ortho, plane_ortho);
+    float plane_distance_area = dot(plane_ortho, p0);
+    vec3 projected = plane_distance_area * plane_ortho / plane_area_squared - p0;
+
+    float dot_v1_v2 = dot(v1, v2);
+    float inverse_dot_v1_v1 = 1.0 / dot(v1, v1);
+    vec3 v2_orthogonal = v2 - v1 * dot_v1_v2 * inverse_dot_v1_v1;
+    vec2 texture_uv;
+    texture_uv.y = dot(v2_orthogonal, projected) / dot(v2_orthogonal, v2_orthogonal);
+    texture_uv.x = dot(v1, projected) * inverse_dot_v1_v1 - dot_v1_v2 * inverse_dot_v1_v1 * texture_uv.y;
+    texture_uv = clamp(vec2(texture_uv.x, 1.0 - texture_uv.y), vec2(0.0), vec2(1.0));
+    float filter_size = abs(plane_distance_area) / pow(plane_area_squared, 0.75);
+
+    // Light index may differ between neighbouring pixels, textures are only indexed with constants
+    vec3 texture_color = vec3(1.0);
+    for (int texture_id = 0; texture_id < MAX_AREA_LIGHT_TEXTURES; texture_id++) {
+        if (texture_id == texture_index) {
+            float texture_size = float(textureSize(AreaLightTextures[texture_id], 0).x);
+            texture_color = textureLod(AreaLightTextures[texture_id], texture_uv, log2(filter_size * texture_size)).rgb;
+        }
+    }
+    return texture_color;
+}
+
+// Integral of the transformed clamped cosine over the rect, rect corners are relative to the shading point
+vec3 ltc_evaluate(vec3 normal, vec3 view_direction, mat3 inverse_transform, vec3 corners[4], int texture_index) {
+    vec3 tangent = view_direction - normal * dot(view_direction, normal);
+    vec3 bitangent;
+    if (dot(tangent, tangent) > 1e-8) {
+        tangent = normalize(tangent);
+        bitangent = cross(normal, tangent);
+    } else {
+        build_orthonormal_basis(normal, tangent, bitangent);
+    }
+    inverse_transform = inverse_transform * transpose(mat3(tangent, bitangent, normal));
+
+    vec3 points[5];
+    for (int point_id = 0; point_id < 4; point_id++) {
+        points[point_id] = inverse_transform * corners[point_id];
+    }
+    points[4] = points[3];
+
+    vec3 texture_color = vec3(1.0);
+    if (texture_index >= 0) {
+        texture_color = fetch_area_light_texture(texture_index, points[0], points[1], points[3]);
+    }
+
+    int point_count;
+    ltc_clip_quad_to_horizon(points, point_count);
+    if (point_count == 0) {
+        return vec3(0.0);
+    }
+    for (int point_id = 0; point_id < 5; point_id++) {
+        points[point_id] = normalize(points[point_id]);
+    }
+
+    float sum = ltc_integrate_edge(points[0], points[1]);
+    sum += ltc_integrate_edge(points[1], points[2]);
+    sum += ltc_integrate_edge(points[2], points[3]);
+    if (point_count >= 4) sum += ltc_integrate_edge(points[3], points[4]);
+    if (point_count == 5) sum += ltc_integrate_edge(points[4], points[0]);
+
+    // Winding depends on the side the rect is seen from, both sides were already filtered by the caller
+    return vec3(abs(sum) / (2.0 * PI)) * texture_color;
+}
+
+vec3 calculate_rect_light(
+    PunctualLight light,
+    vec3 position,
+    vec3 normal,
+    vec3 view_direction,
+    vec3 diffuse_color,
+    vec3 specular_color,
+    float roughness
+) {
+    vec3 light_vector = light.position_range.xyz - position;
+    bool two_sided = light.direction_type.w > 2.5;
+    if (!two_sided && dot(light_vector, light.direction_type.xyz) >= 0.0) {
+        return vec3(0.0);
+    }
+
+    float range_ratio = dot(light_vector, light_vector) / (light.position_range.w * light.position_range.w);
+    float range_attenuation = clamp(1.0 - range_ratio * range_ratio, 0.0, 1.0);
+    if (range_attenuation <= 0.0) {
+        return vec3(0.0);
+    }
+
+    vec3 half_width = light.area_half_width_axis.xyz;
+    vec3 half_height = light.area_half_height_axis.xyz;
+    vec3 corners[4] = vec3[4](
+        light_vector - half_width - half_height,
+        light_vector + half_width - half_height,
+        light_vector + half_width + half_height,
+        light_vector - half_width + half_height
+    );
+
+    float dot_nv = clamp(dot(normal, view_direction), 0.0, 1.0);
+    vec2 ltc_uv = vec2(roughness, sqrt(1.0 - dot_nv));
+    ltc_uv = ltc_uv * (LTC_TABLE_SIZE - 1.0) / LTC_TABLE_SIZE + vec2(0.5 / LTC_TABLE_SIZE);
+    vec4 ltc_matrix = textureLod(LtcMatrix, ltc_uv, 0.0);
+    vec2 ltc_amplitude = textureLod(LtcAmplitude, ltc_uv, 0.0).xy;
+    mat3 inverse_transform = mat3(
+        vec3(ltc_matrix.x, 0.0, ltc_matrix.y),
+        vec3(0.0, 1.0, 0.0),
+        vec3(ltc_matrix.z, 0.0, ltc_matrix.w)
+    );
+
+    int texture_index = int(light.parameters.z);
+    vec3 diffuse = ltc_evaluate(normal, view_direction, mat3(1.0), corners, texture_index);
+    vec3 specular = ltc_evaluate(normal, view_direction, inverse_transform, corners, texture_index);
+    specular *= specular_color * ltc_amplitude.x + (vec3(1.0) - specular_color) * ltc_amplitude.y;
+
+    vec3 radiance = light.color_intensity.rgb * light.color_intensity.a * range_attenuation;
+    return (diffuse_color * diffuse + specular) * radiance;
+}
+
+vec3 calculate_punctual_lights(
+    vec3 position,
+    vec3 normal,
+    vec3 view_direction,
+    vec3 diffuse_color,
+    vec3 specular_color,
+    float roughness
+) {
+    vec3 lighting = vec3(0.0);
+    uint cluster_index;
+    if (PunctualLightCount.x > 0 && find_light_cluster(position, cluster_index)) {
+        uint light_count = cluster_light_counts[cluster_index];
+        for (uint light_slot = 0; light_slot < light_count; light_slot++) {
+            uint light_id = cluster_light_indices[cluster_index * MAX_LIGHTS_PER_CLUSTER + light_slot];
+            PunctualLight light = punctual_lights[light_id];
+            if (light.direction_type.w > 1.5) {
+                lighting += calculate_rect_light(
+                    light,
+                    position,
+                    normal,
+                    view_direction,
+                    diffuse_color,
+                    specular_color,
+                    roughness
+                );
+            } else {
+                lighting += calculate_punctual_light(
+                    light,
+                    position,
+                    normal,
+                    view_direction,
+                    diffuse_color,
+                    specular_color,
+                    roughness
+                );
+            }
+        }
+    }
+    return lighting;
+}
+
+#ifdef ALPHA_BLEND
+// Transparent materials are depth tested manually against the opaque scene
+layout (set = 4, binding = 0) uniform sampler2D SceneDepthImage;
+
+#ifdef OIT_LINKED_LISTS
+layout (set = 4, binding = 1, r32ui) uniform coherent uimage2D FragmentHeadImage;
+layout (std430, set = 4, binding = 2) coherent buffer FragmentNodeBuffer {
+    uint FragmentCounter;
+    uint FragmentNodeBufferUnused[3];
+    uvec4 FragmentNodes[]; // packed color, packed blue and alpha, depth, next node
+};
+#else
+layout (location = 0) out vec4 Target0; // weighted premultiplied color, weighted alpha
+layout (location = 1) out vec4 Target1; // revealage
+#endif
+#elif defined(SHADER_CLOCK)
+layout (location = 0) out vec4 Target0; // subgroup clock ticks spent shading the fragment
+#else
 layout (location = 0) out vec4 Target0;
+layout (location = 1) out vec4 Target1; // world space normal, roughness
+layout (location = 2) out vec4 Target2; // specular reflectance
+#endif
 
 void main() {
+#ifdef SHADER_CLOCK
+    uvec2 clock_start = clock2x32ARB();
+#endif
     vec4 base_color = sample_base_color();
     vec2 metallic_roughness = sample_metallic_roughness();
     vec3 normal = sample_normal();
@@ -160,17 +670,66 @@ void main() {
     vec3 diffuse_color = base_color.rgb * (vec3(1.0) - F0) * (1.0 - metallic);
     vec3 specular_color = mix(F0, base_color.rgb, metallic);
 
+    vec3 irradiance_normal = apply_sky_visibility(normal, occlusion);
+
+    vec3 specular_reflectance;
     vec3 ibl = calculate_ibl(
+        VS_position,
         normal,
+        irradiance_normal,
         view_direction,
         diffuse_color,
         specular_color,
         metallic,
         roughness,
-        occlusion
+        occlusion,
+        specular_reflectance
+    );
+
+    vec3 punctual_lighting = calculate_punctual_lights(
+        VS_position,
+        normal,
+        view_direction,
+        diffuse_color,
+        specular_color,
+        roughness
     );
 
-    vec3 final_color = ibl + emissive;
+    vec3 final_color = ibl + punctual_lighting + emissive;
+#ifdef ALPHA_BLEND
+    float scene_depth = texelFetch(SceneDepthImage, ivec2(gl_FragCoord.xy), 0).r;
+    if (gl_FragCoord.z < scene_depth) {
+        discard;
+    }
+
+    float alpha = base_color.a;
+    #ifdef OIT_LINKED_LISTS
+        uint node_index = atomicAdd(FragmentCounter, 1u);
+        if (node_index < uint(FragmentNodes.length())) {
+            uint next_index = imageAtomicExchange(FragmentHeadImage, ivec2(gl_FragCoord.xy), node_index);
+            FragmentNodes[node_index] = uvec4(
+                packHalf2x16(final_color.rg),
+                packHalf2x16(vec2(final_color.b, alpha)),
+                floatBitsToUint(gl_FragCoord.z),
+                next_index
+            );
+        }
+    #else
+        // Depth weight from the weighted blended OIT paper, closer fragments contribute more
+        float view_depth = CAMERA_NEAR_DISTANCE / max(gl_FragCoord.z, 1e-7);
+        float weight = alpha * clamp(0.03 / (1e-5 + pow(view_depth / 200.0, 4.0)), 1e-2, 3e3);
+        Target0 = vec4(final_color * alpha, alpha) * weight;
+        Target1 = vec4(alpha);
+    #endif
+#elif defined(SHADER_CLOCK)
+    // Shading result has to stay observable, otherwise the measured work is removed by the compiler
+    float shading_observer = min(abs(final_color.r + final_color.g + final_color.b), 0.0);
+    uint clock_ticks = clock2x32ARB().x - clock_start.x;
+    Target0 = vec4(float(clock_ticks) + shading_observer);
+#else
     Target0 = vec4(final_color, 1.0);
+    Target1 = vec4(normal, roughness);
+    Target2 = vec4(specular_reflectance, 1.0);
+#endif
 }
 #endif
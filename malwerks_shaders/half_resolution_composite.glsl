@@ -0,0 +1,66 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+#ifdef VERTEX_STAGE
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(uv * 2.0f + -1.0f, 0.0f, 1.0f);
+}
+#endif
+
+#ifdef FRAGMENT_STAGE
+layout(set = 0, binding = 0) uniform sampler PointSampler;
+layout(set = 0, binding = 1) uniform texture2D SourceDepthImage;
+layout(set = 0, binding = 2) uniform texture2D HalfColorImage;
+layout(set = 0, binding = 3) uniform texture2D HalfDepthImage;
+
+#ifdef DEPTH_DOWNSAMPLE
+void main() {
+    // Depth is reversed, keeping the farthest sample makes sure that
+    // half resolution effects are never occluded by thin geometry
+    ivec2 source_coord = ivec2(gl_FragCoord.xy) * 2;
+    ivec2 source_max = textureSize(sampler2D(SourceDepthImage, PointSampler), 0) - ivec2(1);
+    float depth_00 = texelFetch(sampler2D(SourceDepthImage, PointSampler), min(source_coord, source_max), 0).r;
+    float depth_10 = texelFetch(sampler2D(SourceDepthImage, PointSampler), min(source_coord + ivec2(1, 0), source_max), 0).r;
+    float depth_01 = texelFetch(sampler2D(SourceDepthImage, PointSampler), min(source_coord + ivec2(0, 1), source_max), 0).r;
+    float depth_11 = texelFetch(sampler2D(SourceDepthImage, PointSampler), min(source_coord + ivec2(1, 1), source_max), 0).r;
+    gl_FragDepth = min(min(depth_00, depth_10), min(depth_01, depth_11));
+}
+#else
+layout (push_constant) uniform PC_HalfResolutionArea {
+    ivec4 HalfResolutionArea; // min xy, max xy inclusive
+};
+
+layout(location = 0) out vec4 Target0;
+
+void main() {
+    float full_depth = texelFetch(sampler2D(SourceDepthImage, PointSampler), ivec2(gl_FragCoord.xy), 0).r;
+
+    // Bilinear footprint in the half resolution image, weights are biased towards samples with similar depth
+    vec2 half_position = gl_FragCoord.xy * 0.5 - vec2(0.5);
+    ivec2 base_coord = ivec2(floor(half_position));
+    vec2 f = half_position - vec2(base_coord);
+
+    vec4 composite = vec4(0.0);
+    float total_weight = 0.0;
+    for (int y = 0; y < 2; y++) {
+        for (int x = 0; x < 2; x++) {
+            ivec2 coord = clamp(base_coord + ivec2(x, y), HalfResolutionArea.xy, HalfResolutionArea.zw);
+            float half_depth = texelFetch(sampler2D(HalfDepthImage, PointSampler), coord, 0).r;
+            float bilinear_weight = (x == 0 ? 1.0 - f.x : f.x) * (y == 0 ? 1.0 - f.y : f.y);
+            float weight = (bilinear_weight + 1e-3) / (abs(full_depth - half_depth) + 1e-5);
+
+            composite += texelFetch(sampler2D(HalfColorImage, PointSampler), coord, 0) * weight;
+            total_weight += weight;
+        }
+    }
+
+    // Color is premultiplied and alpha contains transmittance
+    Target0 = composite / total_weight;
+}
+#endif
+#endif
@@ -0,0 +1,31 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+struct InstanceTransformUpdate {
+    mat4 transform;
+    uvec4 instance_index; // only x is used, yzw keep the std430 array stride
+};
+
+layout (push_constant) uniform PC_UpdateParameters {
+    uint update_offset;
+    uint update_count;
+};
+
+layout (std430, set = 0, binding = 0) restrict readonly buffer InstanceTransformUpdates {
+    InstanceTransformUpdate input_updates[];
+};
+layout (std430, set = 0, binding = 1) restrict writeonly buffer InstanceTransforms {
+    mat4 output_transforms[];
+};
+
+layout (local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+void main() {
+    if (gl_GlobalInvocationID.x < update_count) {
+        InstanceTransformUpdate update = input_updates[update_offset + gl_GlobalInvocationID.x];
+        output_transforms[update.instance_index.x] = update.transform;
+    }
+}
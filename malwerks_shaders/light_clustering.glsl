@@ -0,0 +1,98 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+// Assigns punctual lights to froxels, the view frustum is split into a screen space grid and
+// exponential depth slices. Lights are tested against world space bounding boxes of the froxels.
+
+#define CAMERA_NEAR_DISTANCE 0.1
+
+// Has to match light_clustering.rs and gltf_pbr_material.glsl
+#define CLUSTER_GRID_SIZE_X 16
+#define CLUSTER_GRID_SIZE_Y 9
+#define CLUSTER_GRID_SIZE_Z 24
+#define CLUSTER_COUNT (CLUSTER_GRID_SIZE_X * CLUSTER_GRID_SIZE_Y * CLUSTER_GRID_SIZE_Z)
+#define CLUSTER_FAR_DISTANCE 512.0
+#define MAX_LIGHTS_PER_CLUSTER 32
+
+layout (std140, set = 0, binding = 0) uniform PerFrame {
+    mat4 ViewProjection;
+    mat4 InverseViewProjection;
+    mat4 ViewReprojection;
+    vec4 CameraPosition;
+    vec4 CameraOrientation;
+    vec4 ViewportSize;
+    vec4 RenderScale;
+    mat4 StereoViewProjection[2];
+    uvec4 PunctualLightCount;
+    uvec4 BlueNoiseLayer;
+};
+
+struct PunctualLight {
+    vec4 position_range;
+    vec4 color_intensity;
+    vec4 direction_type; // light direction, 0.0 for point, 1.0 for spot, 2.0 for rect and 3.0 for two-sided rect lights
+    vec4 parameters; // spot scale and offset, IES profile or area light texture index, -1.0 means none
+    vec4 area_half_width_axis;
+    vec4 area_half_height_axis;
+};
+
+layout (std430, set = 0, binding = 1) restrict readonly buffer PunctualLights {
+    PunctualLight punctual_lights[];
+};
+layout (std430, set = 0, binding = 2) restrict writeonly buffer ClusterLights {
+    uint cluster_light_counts[CLUSTER_COUNT];
+    uint cluster_light_indices[]; // MAX_LIGHTS_PER_CLUSTER slots per cluster
+};
+
+float slice_view_depth(uint slice) {
+    return CAMERA_NEAR_DISTANCE * pow(CLUSTER_FAR_DISTANCE / CAMERA_NEAR_DISTANCE, float(slice) / float(CLUSTER_GRID_SIZE_Z));
+}
+
+// Projection uses reversed infinite Z, device depth is near distance divided by view depth
+vec3 unproject(vec2 ndc, float view_depth) {
+    vec4 world_position = InverseViewProjection * vec4(ndc, CAMERA_NEAR_DISTANCE / view_depth, 1.0);
+    return world_position.xyz / world_position.w;
+}
+
+layout (local_size_x = 4, local_size_y = 4, local_size_z = 4) in;
+void main() {
+    uvec3 cluster = gl_GlobalInvocationID;
+    if (any(greaterThanEqual(cluster, uvec3(CLUSTER_GRID_SIZE_X, CLUSTER_GRID_SIZE_Y, CLUSTER_GRID_SIZE_Z)))) {
+        return;
+    }
+
+    vec2 grid_size = vec2(CLUSTER_GRID_SIZE_X, CLUSTER_GRID_SIZE_Y);
+    vec2 ndc_min = vec2(cluster.xy) / grid_size * 2.0 - vec2(1.0);
+    vec2 ndc_max = vec2(cluster.xy + uvec2(1)) / grid_size * 2.0 - vec2(1.0);
+    float near_depth = slice_view_depth(cluster.z);
+    float far_depth = slice_view_depth(cluster.z + 1);
+
+    vec3 box_min = vec3(1e30);
+    vec3 box_max = vec3(-1e30);
+    for (uint corner = 0; corner < 8; corner++) {
+        vec2 ndc = vec2(
+            (corner & 1) != 0 ? ndc_max.x : ndc_min.x,
+            (corner & 2) != 0 ? ndc_max.y : ndc_min.y
+        );
+        vec3 corner_position = unproject(ndc, (corner & 4) != 0 ? far_depth : near_depth);
+        box_min = min(box_min, corner_position);
+        box_max = max(box_max, corner_position);
+    }
+
+    // Spot lights are conservatively tested with their bounding spheres
+    uint cluster_index = (cluster.z * CLUSTER_GRID_SIZE_Y + cluster.y) * CLUSTER_GRID_SIZE_X + cluster.x;
+    uint light_count = 0;
+    for (uint light_id = 0; light_id < PunctualLightCount.x && light_count < MAX_LIGHTS_PER_CLUSTER; light_id++) {
+        vec4 position_range = punctual_lights[light_id].position_range;
+        vec3 closest_offset = clamp(position_range.xyz, box_min, box_max) - position_range.xyz;
+        if (dot(closest_offset, closest_offset) <= position_range.w * position_range.w) {
+            cluster_light_indices[cluster_index * MAX_LIGHTS_PER_CLUSTER + light_count] = light_id;
+            light_count++;
+        }
+    }
+    cluster_light_counts[cluster_index] = light_count;
+}
@@ -0,0 +1,122 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+// Material ball thumbnail, an analytic unit sphere seen from the front and lit by the environment probe.
+// Shading follows the image based lighting of gltf_pbr_material.glsl without local probes and punctual lights.
+
+#define PI 3.14159265359
+#define MATERIAL_TEXTURE_COUNT 5
+#define BASE_COLOR_TEXTURE 0
+#define METALLIC_ROUGHNESS_TEXTURE 1
+#define NORMAL_TEXTURE 2
+#define OCCLUSION_TEXTURE 3
+#define EMISSIVE_TEXTURE 4
+
+layout (local_size_x = 8, local_size_y = 8) in;
+
+layout (push_constant) uniform PC_MaterialPreview {
+    vec4 base_color_factor;
+    vec4 metallic_roughness_discard_unused;
+    vec4 emissive_rgb_unused;
+    uvec4 texture_mask_size_alpha_test_unused;
+};
+
+layout (set = 0, binding = 0) uniform sampler2D PrecomputedBrdf;
+layout (set = 0, binding = 1) uniform samplerCube IemTexture;
+layout (set = 0, binding = 2) uniform samplerCube PmremTexture;
+layout (set = 0, binding = 3) uniform sampler2D MaterialTextures[MATERIAL_TEXTURE_COUNT];
+layout (set = 0, binding = 4, rgba8) uniform restrict writeonly image2D ThumbnailImage;
+
+bool has_texture(uint texture_id) {
+    return (texture_mask_size_alpha_test_unused.x & (1u << texture_id)) != 0u;
+}
+
+// Compute shaders have no derivatives, the mip is picked from the thumbnail size instead
+vec4 sample_material_texture(uint texture_id, vec2 uv) {
+    vec2 texture_size = vec2(textureSize(MaterialTextures[texture_id], 0));
+    float lod = log2(max(texture_size.x, texture_size.y) / float(texture_mask_size_alpha_test_unused.y));
+    return textureLod(MaterialTextures[texture_id], uv, max(lod, 0.0));
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    vec3 low = color * 12.92;
+    vec3 high = 1.055 * pow(color, vec3(1.0 / 2.4)) - vec3(0.055);
+    return mix(high, low, lessThanEqual(color, vec3(0.0031308)));
+}
+
+void main() {
+    uint thumbnail_size = texture_mask_size_alpha_test_unused.y;
+    ivec2 coord = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(coord, ivec2(thumbnail_size)))) {
+        return;
+    }
+
+    // Orthographic view along -Z, background stays transparent
+    vec2 position = (vec2(coord) + 0.5) / float(thumbnail_size) * 2.0 - 1.0;
+    position.y = -position.y;
+    float radius_squared = dot(position, position);
+    if (radius_squared > 1.0) {
+        imageStore(ThumbnailImage, coord, vec4(0.0));
+        return;
+    }
+    vec3 sphere_normal = vec3(position, sqrt(1.0 - radius_squared));
+    vec3 view_direction = vec3(0.0, 0.0, 1.0);
+
+    // Textures wrap twice around the sphere, same as most material ball meshes
+    vec2 uv = vec2(atan(sphere_normal.x, sphere_normal.z) / PI + 1.0, acos(clamp(sphere_normal.y, -1.0, 1.0)) / PI);
+
+    vec4 base_color = base_color_factor;
+    if (has_texture(BASE_COLOR_TEXTURE)) {
+        base_color *= sample_material_texture(BASE_COLOR_TEXTURE, uv);
+    }
+    if (texture_mask_size_alpha_test_unused.z != 0u && base_color.a < metallic_roughness_discard_unused.z) {
+        imageStore(ThumbnailImage, coord, vec4(0.0));
+        return;
+    }
+
+    vec2 metallic_roughness = metallic_roughness_discard_unused.xy;
+    if (has_texture(METALLIC_ROUGHNESS_TEXTURE)) {
+        metallic_roughness *= sample_material_texture(METALLIC_ROUGHNESS_TEXTURE, uv).bg;
+    }
+    float metallic = clamp(metallic_roughness.x, 0.0, 1.0);
+    float roughness = clamp(metallic_roughness.y, 0.0, 1.0);
+
+    vec3 normal = sphere_normal;
+    if (has_texture(NORMAL_TEXTURE)) {
+        vec3 tangent = normalize(vec3(sphere_normal.z, 0.0, -sphere_normal.x) + vec3(1.0e-5, 0.0, 0.0));
+        vec3 binormal = cross(sphere_normal, tangent);
+        vec3 normal_sample = sample_material_texture(NORMAL_TEXTURE, uv).xyz * 2.0 - 1.0;
+        normal = normalize(mat3(tangent, binormal, sphere_normal) * normal_sample);
+    }
+
+    float occlusion = 1.0;
+    if (has_texture(OCCLUSION_TEXTURE)) {
+        occlusion = sample_material_texture(OCCLUSION_TEXTURE, uv).r;
+    }
+
+    vec3 emissive = emissive_rgb_unused.rgb;
+    if (has_texture(EMISSIVE_TEXTURE)) {
+        emissive *= sample_material_texture(EMISSIVE_TEXTURE, uv).rgb;
+    }
+
+    const vec3 F0 = vec3(0.04);
+    vec3 diffuse_color = base_color.rgb * (vec3(1.0) - F0) * (1.0 - metallic);
+    vec3 specular_color = mix(F0, base_color.rgb, metallic);
+
+    float dot_nv = clamp(dot(normal, view_direction), 0.0, 1.0);
+    vec3 reflect_direction = normalize(reflect(-view_direction, normal));
+    vec3 irradiance = textureLod(IemTexture, normal, 0.0).rgb;
+    vec3 radiance = textureLod(PmremTexture, reflect_direction, roughness * 10.0).rgb;
+    vec2 brdf = textureLod(PrecomputedBrdf, vec2(dot_nv, roughness), 0.0).xy;
+
+    vec3 color = irradiance * diffuse_color * occlusion + radiance * (specular_color * brdf.x + brdf.y) * occlusion;
+    color += emissive;
+
+    // Reinhard is enough for a thumbnail, ImGui expects sRGB encoded colors
+    color = color / (vec3(1.0) + color);
+    imageStore(ThumbnailImage, coord, vec4(linear_to_srgb(color), 1.0));
+}
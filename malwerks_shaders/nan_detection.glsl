@@ -0,0 +1,83 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+#if defined(SCAN_IMAGE_STAGE) || defined(SCAN_BUFFER_STAGE)
+layout (push_constant) uniform PC_NanDetection {
+    uvec4 scan_area; // offset and size of the scanned image area, x is the scanned buffer id for buffer scans
+};
+
+layout (std430, set = 0, binding = 2) restrict buffer NanDetectionResult {
+    uint pixel_count;
+    uint first_pixel; // (y << 16) | x, 0xFFFFFFFF if every pixel is finite
+    uint value_count;
+    uint first_values[]; // value index per scanned buffer, 0xFFFFFFFF if every value is finite
+};
+#endif
+
+#ifdef SCAN_IMAGE_STAGE
+layout (local_size_x = 8, local_size_y = 8) in;
+
+layout (set = 0, binding = 0) uniform sampler2D HdrImage;
+layout (set = 0, binding = 1, r8) uniform restrict writeonly image2D NanMask;
+
+void main() {
+    uvec2 pixel = gl_GlobalInvocationID.xy;
+    if (any(greaterThanEqual(pixel, scan_area.zw))) {
+        return;
+    }
+
+    uvec2 coord = scan_area.xy + pixel;
+    vec4 value = texelFetch(HdrImage, ivec2(coord), 0);
+    bool non_finite = any(isnan(value)) || any(isinf(value));
+    imageStore(NanMask, ivec2(coord), vec4(non_finite ? 1.0 : 0.0));
+    if (non_finite) {
+        atomicAdd(pixel_count, 1);
+        atomicMin(first_pixel, (coord.y << 16) | coord.x);
+    }
+}
+#endif
+
+#ifdef SCAN_BUFFER_STAGE
+layout (local_size_x = 64) in;
+
+layout (std430, set = 1, binding = 0) restrict readonly buffer ScannedBuffer {
+    float values[];
+};
+
+void main() {
+    uint value_id = gl_GlobalInvocationID.x;
+    if (value_id >= values.length()) {
+        return;
+    }
+
+    float value = values[value_id];
+    if (isnan(value) || isinf(value)) {
+        atomicAdd(value_count, 1);
+        atomicMin(first_values[scan_area.x], value_id);
+    }
+}
+#endif
+
+#ifdef FRAGMENT_STAGE
+// Vertex stage is full_screen.glsl, the mask is tinted over the tone mapped output
+layout (push_constant) uniform PC_NanTint {
+    vec4 uv_scale;
+};
+
+layout(set = 0, binding = 0) uniform sampler PointSampler;
+layout(set = 0, binding = 1) uniform texture2D NanMask;
+
+layout(location = 0) in vec2 VS_uv;
+layout(location = 0) out vec4 Target0;
+
+void main() {
+    if (texture(sampler2D(NanMask, PointSampler), VS_uv * uv_scale.xy).r < 0.5) {
+        discard;
+    }
+    Target0 = vec4(1.0, 0.0, 1.0, 1.0);
+}
+#endif
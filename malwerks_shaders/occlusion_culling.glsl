@@ -5,6 +5,17 @@
 
 #version 460 core
 
+#include "debug_printf.glsl"
+
+// Two-phase occlusion culling with visibility kept across frames:
+// - early phase emits draws that were visible last frame, they are rendered first and build the Hi-Z
+// - occluders of all draws are tested against it and the resolve pass marks this frame's visibility
+// - late phase emits draws that became visible this frame and stores visibility for the next frame
+// Each draw has two visibility slots, [0] is written by the resolve pass and [1] is last frame's result.
+
+#define CULLING_PHASE_EARLY 0
+#define CULLING_PHASE_LATE 1
+
 struct DrawIndexedIndirectCommand {
     uint index_count;
     uint instance_count;
@@ -20,23 +31,39 @@ layout (std430, set = 0, binding = 1) restrict buffer VisibilityBuffer {
     uvec4 visibility[][2];
 };
 layout (std430, set = 0, binding = 2) restrict buffer DrawCommandsCount {
-    uvec2 output_count;
+    uvec2 output_count; // x is the early phase, y is the late phase, both cleared before the early phase
 };
-layout (std430, set = 0, binding = 3) restrict writeonly buffer OutputDrawCommands {
-    DrawIndexedIndirectCommand output_draw_commands[];
+layout (std430, set = 0, binding = 3) restrict writeonly buffer OutputEarlyDrawCommands {
+    DrawIndexedIndirectCommand output_early_draw_commands[];
+};
+layout (std430, set = 0, binding = 4) restrict writeonly buffer OutputLateDrawCommands {
+    DrawIndexedIndirectCommand output_late_draw_commands[];
 };
 
-layout (local_size_x = 8, local_size_y = 1, local_size_z = 1) in;
-void main() {
-    if (gl_GlobalInvocationID.x < visibility.length()) {
-        uvec4 visible = visibility[gl_GlobalInvocationID.x][0];
-        if (bool(visible.x)) {
-            uint command_index = atomicAdd(output_count.y, 1);
-            output_draw_commands[command_index] = input_draw_commands[gl_GlobalInvocationID.x];
-        }
+layout (push_constant) uniform PC_CullingPhase {
+    layout (offset = 0) uint CullingPhase;
+};
 
-        barrier();
+layout (local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+void main() {
+    uint draw_index = gl_GlobalInvocationID.x;
+    if (draw_index < visibility.length()) {
+        bool was_visible = bool(visibility[draw_index][1].x);
+        if (CullingPhase == CULLING_PHASE_EARLY) {
+            if (was_visible) {
+                uint command_index = atomicAdd(output_count.x, 1);
+                output_early_draw_commands[command_index] = input_draw_commands[draw_index];
+            }
+        } else {
+            uvec4 visible = visibility[draw_index][0];
+            if (bool(visible.x) && !was_visible) {
+                uint command_index = atomicAdd(output_count.y, 1);
+                output_late_draw_commands[command_index] = input_draw_commands[draw_index];
+            }
 
-        visibility[gl_GlobalInvocationID.x][0] = uvec4(0, 0, 0, 0);
+            // Every invocation only touches its own draw, no synchronization is needed
+            visibility[draw_index][1] = visible;
+            visibility[draw_index][0] = uvec4(0, 0, 0, 0);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,88 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+#define MAX_SORTED_FRAGMENTS 16
+#define INVALID_FRAGMENT_NODE 0xFFFFFFFFu
+
+#ifdef VERTEX_STAGE
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(uv * 2.0f + -1.0f, 0.0f, 1.0f);
+}
+#endif
+
+#ifdef FRAGMENT_STAGE
+// Output is premultiplied color and transmittance, blended on top of the scene color
+layout (location = 0) out vec4 Target0;
+
+#ifdef WEIGHTED_BLENDED_COMPOSITE
+layout (set = 0, binding = 0) uniform sampler2D AccumulationImage;
+layout (set = 0, binding = 1) uniform sampler2D RevealageImage;
+
+void main() {
+    ivec2 coord = ivec2(gl_FragCoord.xy);
+    float revealage = texelFetch(RevealageImage, coord, 0).r;
+    if (revealage >= 1.0) {
+        discard;
+    }
+
+    // Many bright layers can overflow half precision accumulation
+    vec4 accumulation = texelFetch(AccumulationImage, coord, 0);
+    if (any(isinf(accumulation.rgb))) {
+        accumulation.rgb = vec3(accumulation.a);
+    }
+
+    vec3 average_color = accumulation.rgb / max(accumulation.a, 1e-5);
+    Target0 = vec4(average_color * (1.0 - revealage), revealage);
+}
+#endif
+
+#ifdef LINKED_LISTS_COMPOSITE
+layout (set = 0, binding = 0, r32ui) uniform readonly uimage2D FragmentHeadImage;
+layout (std430, set = 0, binding = 1) readonly buffer FragmentNodeBuffer {
+    uint FragmentCounter;
+    uint FragmentNodeBufferUnused[3];
+    uvec4 FragmentNodes[]; // packed color, packed blue and alpha, depth, next node
+};
+
+void main() {
+    // Lists longer than MAX_SORTED_FRAGMENTS are truncated, the most recently rendered fragments are kept
+    uvec4 fragments[MAX_SORTED_FRAGMENTS];
+    int fragment_count = 0;
+    uint node_index = imageLoad(FragmentHeadImage, ivec2(gl_FragCoord.xy)).r;
+    while (node_index != INVALID_FRAGMENT_NODE && fragment_count < MAX_SORTED_FRAGMENTS) {
+        fragments[fragment_count] = FragmentNodes[node_index];
+        node_index = fragments[fragment_count].w;
+        fragment_count++;
+    }
+    if (fragment_count == 0) {
+        discard;
+    }
+
+    // Depth is reversed, sorting by ascending depth puts the farthest fragment first
+    for (int i = 1; i < fragment_count; i++) {
+        uvec4 fragment = fragments[i];
+        int j = i - 1;
+        while (j >= 0 && uintBitsToFloat(fragments[j].z) > uintBitsToFloat(fragment.z)) {
+            fragments[j + 1] = fragments[j];
+            j--;
+        }
+        fragments[j + 1] = fragment;
+    }
+
+    vec3 color = vec3(0.0);
+    float transmittance = 1.0;
+    for (int i = 0; i < fragment_count; i++) {
+        vec2 red_green = unpackHalf2x16(fragments[i].x);
+        vec2 blue_alpha = unpackHalf2x16(fragments[i].y);
+        color = mix(color, vec3(red_green, blue_alpha.x), blue_alpha.y);
+        transmittance *= 1.0 - blue_alpha.y;
+    }
+    Target0 = vec4(color, transmittance);
+}
+#endif
+#endif
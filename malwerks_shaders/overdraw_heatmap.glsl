@@ -0,0 +1,48 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+#define MAX_OVERDRAW 8.0
+
+#ifdef VERTEX_STAGE
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(uv * 2.0f + -1.0f, 0.0f, 1.0f);
+}
+#endif
+
+#ifdef FRAGMENT_STAGE
+layout (push_constant) uniform PC_OverdrawHeatmap {
+    vec4 OutputOffsetRenderScaleOpacity;
+};
+
+layout (set = 0, binding = 0) uniform sampler2D OverdrawImage;
+
+layout (location = 0) out vec4 Target0;
+
+// Black when nothing is rendered, then blue, cyan, green, yellow and red at MAX_OVERDRAW
+vec3 heat_color(float overdraw) {
+    const vec3 COLORS[6] = vec3[6](
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, 1.0),
+        vec3(0.0, 1.0, 1.0),
+        vec3(0.0, 1.0, 0.0),
+        vec3(1.0, 1.0, 0.0),
+        vec3(1.0, 0.0, 0.0)
+    );
+    float position = clamp(overdraw / MAX_OVERDRAW, 0.0, 1.0) * 5.0;
+    int index = min(int(position), 4);
+    return mix(COLORS[index], COLORS[index + 1], position - float(index));
+}
+
+void main() {
+    // Overlay covers the output area, overdraw is counted at the render resolution
+    vec2 output_offset = OutputOffsetRenderScaleOpacity.xy;
+    vec2 render_coord = output_offset + (gl_FragCoord.xy - output_offset) * OutputOffsetRenderScaleOpacity.z;
+    float overdraw = texelFetch(OverdrawImage, ivec2(render_coord), 0).r;
+    Target0 = vec4(heat_color(overdraw), OutputOffsetRenderScaleOpacity.w);
+}
+#endif
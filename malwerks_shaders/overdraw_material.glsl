@@ -0,0 +1,28 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+#include "generated://attribute_fetch.glsl"
+
+#ifdef VERTEX_STAGE
+layout (push_constant) uniform PC_ViewProjection {
+    layout (offset = 0) mat4 ViewProjectionPC;
+};
+
+void main() {
+    vec4 position = fetch_vertex_attributes();
+    gl_Position = ViewProjectionPC * position;
+}
+#endif
+
+#ifdef FRAGMENT_STAGE
+layout (location = 0) out vec4 Target0;
+
+// Every rasterized fragment is added to the overdraw counter
+void main() {
+    Target0 = vec4(1.0);
+}
+#endif
@@ -0,0 +1,572 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+#ifndef COMPUTE_STAGE
+#extension GL_NV_ray_tracing : require
+#extension GL_EXT_nonuniform_qualifier : require
+#endif
+
+#define PI 3.14159265359
+#define MAX_RAY_DISTANCE 1.0e27
+#define RAY_OFFSET 1.0e-3
+
+#ifdef COMPUTE_STAGE
+layout (push_constant) uniform PC_InstanceBatch {
+    uvec2 bottom_level_handle;
+    uint first_transform;
+    uint first_instance;
+    uint instance_count;
+    uint geometry_index;
+    uint instance_flags;
+    uint unused;
+};
+
+layout (std430, set = 0, binding = 0) restrict readonly buffer InstanceTransforms {
+    mat4 transforms[];
+};
+// VkGeometryInstanceNV, row major 3x4 transform followed by custom index, mask, hit group offset, flags and handle
+layout (std430, set = 0, binding = 1) restrict writeonly buffer GeometryInstances {
+    uint geometry_instances[];
+};
+
+layout (local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+void main() {
+    if (gl_GlobalInvocationID.x < instance_count) {
+        mat4 transform = transforms[first_transform + gl_GlobalInvocationID.x];
+        uint base = (first_instance + gl_GlobalInvocationID.x) * 16;
+        for (uint row = 0; row < 3; row++) {
+            for (uint column = 0; column < 4; column++) {
+                geometry_instances[base + row * 4 + column] = floatBitsToUint(transform[column][row]);
+            }
+        }
+        geometry_instances[base + 12] = geometry_index | (0xFFu << 24);
+        geometry_instances[base + 13] = instance_flags << 24;
+        geometry_instances[base + 14] = bottom_level_handle.x;
+        geometry_instances[base + 15] = bottom_level_handle.y;
+    }
+}
+#endif
+
+#ifndef COMPUTE_STAGE
+// Matches PathTracerGeometry in path_tracer.rs, unused attribute offsets and textures are ~0
+struct Geometry {
+    uvec4 buffers_first_index_vertex_offset; // vertex buffer, index buffer, first index, vertex offset
+    uvec4 stride_position_normal_uv; // vertex stride and attribute offsets
+    uvec4 uv_format_index_size_alpha_test; // 0 for float, 1 for R16G16_UNORM and 2 for R8G8_UNORM UVs
+    ivec4 textures; // base color, metallic roughness and emissive, -1 means none
+    vec4 base_color_factor;
+    vec4 metallic_roughness_alpha_cutoff;
+    vec4 emissive_factor;
+    vec4 atlas_scale_offset;
+};
+
+struct HitPayload {
+    vec4 position_distance; // negative distance on miss
+    vec4 normal_roughness;
+    vec4 geometric_normal_metallic;
+    vec4 base_color;
+    vec4 emissive; // environment radiance on miss
+};
+#endif
+
+#if defined(RAY_CLOSEST_HIT_STAGE) || defined(RAY_ANY_HIT_STAGE)
+layout (std430, set = 0, binding = 2) restrict readonly buffer SceneGeometry {
+    Geometry scene_geometry[];
+};
+layout (std430, set = 1, binding = 0) restrict readonly buffer SceneBuffer {
+    uint words[];
+} SceneBuffers[];
+layout (set = 2, binding = 0) uniform sampler2D SceneTextures[];
+
+hitAttributeNV vec2 HitBarycentrics;
+
+// Same unaligned loads as vertex pulling in material_shaders.rs
+uint load_scene_data(uint buffer_id, uint byte_offset, uint byte_count) {
+    uint word_id = byte_offset >> 2;
+    uint shift = (byte_offset & 3) * 8;
+    uint value = SceneBuffers[nonuniformEXT(buffer_id)].words[word_id] >> shift;
+    if (shift + byte_count * 8 > 32) {
+        value |= SceneBuffers[nonuniformEXT(buffer_id)].words[word_id + 1] << (32 - shift);
+    }
+    return value;
+}
+
+vec3 load_vec3(uint buffer_id, uint byte_offset) {
+    return vec3(
+        uintBitsToFloat(load_scene_data(buffer_id, byte_offset, 4)),
+        uintBitsToFloat(load_scene_data(buffer_id, byte_offset + 4, 4)),
+        uintBitsToFloat(load_scene_data(buffer_id, byte_offset + 8, 4))
+    );
+}
+
+uvec3 load_triangle(Geometry geometry) {
+    uint index_size = geometry.uv_format_index_size_alpha_test.y;
+    uint first_index = geometry.buffers_first_index_vertex_offset.z + gl_PrimitiveID * 3;
+    uvec3 triangle;
+    for (uint vertex_id = 0; vertex_id < 3; vertex_id++) {
+        uint index = load_scene_data(
+            geometry.buffers_first_index_vertex_offset.y,
+            (first_index + vertex_id) * index_size,
+            index_size
+        );
+        if (index_size == 2) {
+            index &= 0xFFFF;
+        }
+        triangle[vertex_id] = index + geometry.buffers_first_index_vertex_offset.w;
+    }
+    return triangle;
+}
+
+vec2 load_uv(Geometry geometry, uint vertex_index) {
+    uint byte_offset = vertex_index * geometry.stride_position_normal_uv.x + geometry.stride_position_normal_uv.w;
+    uint vertex_buffer = geometry.buffers_first_index_vertex_offset.x;
+    uint uv_format = geometry.uv_format_index_size_alpha_test.x;
+    if (uv_format == 1) {
+        return unpackUnorm2x16(load_scene_data(vertex_buffer, byte_offset, 4));
+    } else if (uv_format == 2) {
+        return unpackUnorm4x8(load_scene_data(vertex_buffer, byte_offset, 2)).xy;
+    }
+    return vec2(
+        uintBitsToFloat(load_scene_data(vertex_buffer, byte_offset, 4)),
+        uintBitsToFloat(load_scene_data(vertex_buffer, byte_offset + 4, 4))
+    );
+}
+
+vec3 get_barycentrics() {
+    return vec3(1.0 - HitBarycentrics.x - HitBarycentrics.y, HitBarycentrics.x, HitBarycentrics.y);
+}
+
+vec2 interpolate_uv(Geometry geometry, uvec3 triangle, vec3 barycentrics) {
+    if (geometry.stride_position_normal_uv.w == ~0u) {
+        return vec2(0.0);
+    }
+    return load_uv(geometry, triangle.x) * barycentrics.x
+        + load_uv(geometry, triangle.y) * barycentrics.y
+        + load_uv(geometry, triangle.z) * barycentrics.z;
+}
+
+// Atlased textures wrap inside their rect, rays don't have derivatives so the top mip is used
+vec4 sample_scene_texture(Geometry geometry, int texture_index, vec2 uv) {
+    if (geometry.atlas_scale_offset.x < 1.0) {
+        uv = fract(uv) * geometry.atlas_scale_offset.xy + geometry.atlas_scale_offset.zw;
+    }
+    return textureLod(SceneTextures[nonuniformEXT(texture_index)], uv, 0.0);
+}
+
+float sample_alpha(Geometry geometry, vec2 uv) {
+    float alpha = geometry.base_color_factor.a;
+    if (geometry.textures.x >= 0) {
+        alpha *= sample_scene_texture(geometry, geometry.textures.x, uv).a;
+    }
+    return alpha;
+}
+#endif
+
+#ifdef RAY_CLOSEST_HIT_STAGE
+layout (location = 0) rayPayloadInNV HitPayload Hit;
+
+vec3 transform_direction(vec3 v, mat3 m) {
+    return normalize(m * (v / vec3(dot(m[0], m[0]), dot(m[1], m[1]), dot(m[2], m[2]))));
+}
+
+void main() {
+    Geometry geometry = scene_geometry[gl_InstanceCustomIndexNV];
+    uvec3 triangle = load_triangle(geometry);
+    vec3 barycentrics = get_barycentrics();
+
+    uint vertex_buffer = geometry.buffers_first_index_vertex_offset.x;
+    uint vertex_stride = geometry.stride_position_normal_uv.x;
+    uint position_offset = geometry.stride_position_normal_uv.y;
+    vec3 p0 = load_vec3(vertex_buffer, triangle.x * vertex_stride + position_offset);
+    vec3 p1 = load_vec3(vertex_buffer, triangle.y * vertex_stride + position_offset);
+    vec3 p2 = load_vec3(vertex_buffer, triangle.z * vertex_stride + position_offset);
+
+    mat3 object_to_world = mat3(gl_ObjectToWorldNV);
+    vec3 geometric_normal = transform_direction(cross(p1 - p0, p2 - p0), object_to_world);
+    vec3 normal = geometric_normal;
+    uint normal_offset = geometry.stride_position_normal_uv.z;
+    if (normal_offset != ~0u) {
+        vec3 object_normal = load_vec3(vertex_buffer, triangle.x * vertex_stride + normal_offset) * barycentrics.x
+            + load_vec3(vertex_buffer, triangle.y * vertex_stride + normal_offset) * barycentrics.y
+            + load_vec3(vertex_buffer, triangle.z * vertex_stride + normal_offset) * barycentrics.z;
+        if (dot(object_normal, object_normal) > 0.0) {
+            normal = transform_direction(object_normal, object_to_world);
+        }
+    }
+
+    // Both sides are shaded, normals face the incoming ray
+    if (dot(geometric_normal, gl_WorldRayDirectionNV) > 0.0) {
+        geometric_normal = -geometric_normal;
+    }
+    if (dot(normal, geometric_normal) < 0.0) {
+        normal = -normal;
+    }
+
+    vec2 uv = interpolate_uv(geometry, triangle, barycentrics);
+    vec4 base_color = geometry.base_color_factor;
+    if (geometry.textures.x >= 0) {
+        base_color *= sample_scene_texture(geometry, geometry.textures.x, uv);
+    }
+    vec2 metallic_roughness = geometry.metallic_roughness_alpha_cutoff.xy;
+    if (geometry.textures.y >= 0) {
+        metallic_roughness *= sample_scene_texture(geometry, geometry.textures.y, uv).bg;
+    }
+    vec3 emissive = geometry.emissive_factor.rgb;
+    if (geometry.textures.z >= 0) {
+        emissive *= sample_scene_texture(geometry, geometry.textures.z, uv).rgb;
+    }
+
+    Hit.position_distance = vec4(gl_WorldRayOriginNV + gl_WorldRayDirectionNV * gl_HitTNV, gl_HitTNV);
+    Hit.normal_roughness = vec4(normal, clamp(metallic_roughness.y, 0.0, 1.0));
+    Hit.geometric_normal_metallic = vec4(geometric_normal, clamp(metallic_roughness.x, 0.0, 1.0));
+    Hit.base_color = base_color;
+    Hit.emissive = vec4(emissive, 0.0);
+}
+#endif
+
+#ifdef RAY_ANY_HIT_STAGE
+// Only invoked for instances that aren't forced opaque, blended materials are tested against the cutoff too
+void main() {
+    Geometry geometry = scene_geometry[gl_InstanceCustomIndexNV];
+    uvec3 triangle = load_triangle(geometry);
+    vec2 uv = interpolate_uv(geometry, triangle, get_barycentrics());
+    if (sample_alpha(geometry, uv) < geometry.metallic_roughness_alpha_cutoff.z) {
+        ignoreIntersectionNV();
+    }
+}
+#endif
+
+#ifdef RAY_MISS_STAGE
+layout (set = 0, binding = 4) uniform samplerCube EnvironmentTexture;
+
+layout (location = 0) rayPayloadInNV HitPayload Hit;
+
+void main() {
+    Hit.position_distance = vec4(0.0, 0.0, 0.0, -1.0);
+    Hit.emissive = vec4(textureLod(EnvironmentTexture, gl_WorldRayDirectionNV, 0.0).rgb, 0.0);
+}
+#endif
+
+#ifdef SHADOW_RAY_MISS_STAGE
+layout (location = 1) rayPayloadInNV float ShadowVisibility;
+
+void main() {
+    ShadowVisibility = 1.0;
+}
+#endif
+
+#ifdef RAY_GEN_STAGE
+// Matches PunctualLight struct in light_clustering.glsl
+struct PunctualLight {
+    vec4 position_range;
+    vec4 color_intensity;
+    vec4 direction_type; // light direction, 0.0 for point, 1.0 for spot, 2.0 for rect and 3.0 for two-sided rect lights
+    vec4 parameters; // spot scale and offset, IES profile or area light texture index, -1.0 means none
+    vec4 area_half_width_axis;
+    vec4 area_half_height_axis;
+};
+
+layout (push_constant) uniform PC_PathTracer {
+    mat4 inverse_view_projection;
+    vec4 camera_position;
+    uvec4 viewport_offset_unused;
+    uvec4 sample_index_light_count_max_bounces_unused;
+};
+
+layout (set = 0, binding = 0) uniform accelerationStructureNV SceneTopLevel;
+layout (set = 0, binding = 1, rgba32f) uniform image2D AccumulationImage;
+layout (std430, set = 0, binding = 3) restrict readonly buffer PunctualLights {
+    PunctualLight punctual_lights[];
+};
+
+layout (location = 0) rayPayloadNV HitPayload Hit;
+layout (location = 1) rayPayloadNV float ShadowVisibility;
+
+// PCG hash, "Hash Functions for GPU Rendering" by Jarzynski and Olano
+uint hash(uint value) {
+    uint state = value * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+float random(inout uint state) {
+    state = hash(state);
+    return float(state >> 8) / 16777216.0;
+}
+
+// "Building an Orthonormal Basis, Revisited" by Duff et al.
+void build_orthonormal_basis(vec3 normal, out vec3 tangent, out vec3 bitangent) {
+    float sign_z = normal.z >= 0.0 ? 1.0 : -1.0;
+    float a = -1.0 / (sign_z + normal.z);
+    float b = normal.x * normal.y * a;
+    tangent = vec3(1.0 + sign_z * normal.x * normal.x * a, sign_z * b, -sign_z * normal.x);
+    bitangent = vec3(b, sign_z + normal.y * normal.y * a, -normal.y);
+}
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+float trace_shadow_ray(vec3 origin, vec3 direction, float max_distance) {
+    ShadowVisibility = 0.0;
+    traceNV(
+        SceneTopLevel,
+        gl_RayFlagsTerminateOnFirstHitNV | gl_RayFlagsSkipClosestHitShaderNV,
+        0xFF,
+        1,
+        0,
+        1,
+        origin,
+        0.0,
+        direction,
+        max_distance,
+        1
+    );
+    return ShadowVisibility;
+}
+
+// Same lobes as calculate_punctual_light() in gltf_pbr_material.glsl
+vec3 evaluate_brdf(
+    vec3 normal,
+    vec3 view_direction,
+    vec3 light_direction,
+    vec3 diffuse_color,
+    vec3 specular_color,
+    float roughness
+) {
+    float dot_nl = clamp(dot(normal, light_direction), 0.0, 1.0);
+    if (dot_nl <= 0.0) {
+        return vec3(0.0);
+    }
+
+    vec3 half_vector = normalize(light_direction + view_direction);
+    float dot_nv = clamp(dot(normal, view_direction), 1e-4, 1.0);
+    float dot_nh = clamp(dot(normal, half_vector), 0.0, 1.0);
+    float dot_vh = clamp(dot(view_direction, half_vector), 0.0, 1.0);
+
+    float alpha = max(roughness * roughness, 1e-3);
+    float alpha_squared = alpha * alpha;
+    float distribution_denominator = dot_nh * dot_nh * (alpha_squared - 1.0) + 1.0;
+    float distribution = alpha_squared / (PI * distribution_denominator * distribution_denominator);
+    float k = 0.5 * alpha;
+    float visibility = 0.25 / ((dot_nl * (1.0 - k) + k) * (dot_nv * (1.0 - k) + k));
+    vec3 fresnel = specular_color + (vec3(1.0) - specular_color) * pow(1.0 - dot_vh, 5.0);
+
+    return (diffuse_color / PI + fresnel * distribution * visibility) * dot_nl;
+}
+
+// One randomly picked light per bounce, rect lights are sampled uniformly over their area
+vec3 sample_punctual_light(
+    vec3 position,
+    vec3 normal,
+    vec3 geometric_normal,
+    vec3 view_direction,
+    vec3 diffuse_color,
+    vec3 specular_color,
+    float roughness,
+    inout uint rng_state
+) {
+    uint light_count = sample_index_light_count_max_bounces_unused.y;
+    if (light_count == 0) {
+        return vec3(0.0);
+    }
+    uint light_id = min(uint(random(rng_state) * float(light_count)), light_count - 1);
+    PunctualLight light = punctual_lights[light_id];
+
+    vec3 light_position = light.position_range.xyz;
+    float light_attenuation;
+    if (light.direction_type.w > 1.5) {
+        vec3 half_width = light.area_half_width_axis.xyz;
+        vec3 half_height = light.area_half_height_axis.xyz;
+        light_position += half_width * (random(rng_state) * 2.0 - 1.0) + half_height * (random(rng_state) * 2.0 - 1.0);
+
+        vec3 light_vector = light_position - position;
+        float distance_squared = max(dot(light_vector, light_vector), 1e-4);
+        float cos_light = dot(light.direction_type.xyz, -light_vector) * inversesqrt(distance_squared);
+        bool two_sided = light.direction_type.w > 2.5;
+        if (two_sided) {
+            cos_light = abs(cos_light);
+        }
+
+        vec3 center_vector = light.position_range.xyz - position;
+        float range_ratio = dot(center_vector, center_vector) / (light.position_range.w * light.position_range.w);
+        float range_attenuation = clamp(1.0 - range_ratio * range_ratio, 0.0, 1.0);
+        float area = 4.0 * length(half_width) * length(half_height);
+        light_attenuation = range_attenuation * max(cos_light, 0.0) * area / distance_squared;
+    } else {
+        vec3 light_vector = light_position - position;
+        float distance_squared = max(dot(light_vector, light_vector), 1e-4);
+        float range_ratio = distance_squared / (light.position_range.w * light.position_range.w);
+        light_attenuation = clamp(1.0 - range_ratio * range_ratio, 0.0, 1.0) / distance_squared;
+        if (light.direction_type.w > 0.5) {
+            vec3 light_direction = light_vector * inversesqrt(distance_squared);
+            float spot_attenuation = clamp(
+                dot(light.direction_type.xyz, -light_direction) * light.parameters.x + light.parameters.y,
+                0.0,
+                1.0
+            );
+            light_attenuation *= spot_attenuation * spot_attenuation;
+        }
+    }
+
+    vec3 light_vector = light_position - position;
+    float light_distance = length(light_vector);
+    vec3 light_direction = light_vector / max(light_distance, 1e-4);
+    if (light_attenuation <= 0.0 || dot(light_direction, geometric_normal) <= 0.0) {
+        return vec3(0.0);
+    }
+
+    vec3 brdf = evaluate_brdf(normal, view_direction, light_direction, diffuse_color, specular_color, roughness);
+    if (dot(brdf, brdf) <= 0.0) {
+        return vec3(0.0);
+    }
+
+    vec3 origin = position + geometric_normal * RAY_OFFSET;
+    float visibility = trace_shadow_ray(origin, light_direction, max(light_distance - 2.0 * RAY_OFFSET, 0.0));
+    vec3 radiance = light.color_intensity.rgb * light.color_intensity.a * light_attenuation;
+    return brdf * radiance * visibility * float(light_count);
+}
+
+// Picks the diffuse or specular lobe, specular directions come from the GGX distribution of visible normals
+bool sample_brdf(
+    vec3 normal,
+    vec3 view_direction,
+    vec3 diffuse_color,
+    vec3 specular_color,
+    float roughness,
+    inout uint rng_state,
+    out vec3 direction,
+    out vec3 weight
+) {
+    vec3 tangent;
+    vec3 bitangent;
+    build_orthonormal_basis(normal, tangent, bitangent);
+    float dot_nv = clamp(dot(normal, view_direction), 1e-4, 1.0);
+
+    float diffuse_weight = luminance(diffuse_color);
+    float specular_weight = luminance(specular_color + (vec3(1.0) - specular_color) * pow(1.0 - dot_nv, 5.0));
+    float specular_probability = clamp(specular_weight / max(diffuse_weight + specular_weight, 1e-4), 0.1, 0.9);
+
+    float u0 = random(rng_state);
+    float u1 = random(rng_state);
+    if (random(rng_state) >= specular_probability) {
+        float radius = sqrt(u0);
+        float phi = 2.0 * PI * u1;
+        direction = normalize(
+            tangent * radius * cos(phi) + bitangent * radius * sin(phi) + normal * sqrt(max(1.0 - u0, 0.0))
+        );
+        weight = diffuse_color / (1.0 - specular_probability);
+        return true;
+    }
+
+    // "Sampling the GGX Distribution of Visible Normals" by Heitz
+    float alpha = max(roughness * roughness, 1e-3);
+    vec3 local_view = vec3(dot(view_direction, tangent), dot(view_direction, bitangent), dot_nv);
+    vec3 stretched_view = normalize(vec3(alpha * local_view.xy, local_view.z));
+    float length_squared = dot(stretched_view.xy, stretched_view.xy);
+    vec3 t1 = length_squared > 0.0 ? vec3(-stretched_view.y, stretched_view.x, 0.0) * inversesqrt(length_squared) : vec3(1.0, 0.0, 0.0);
+    vec3 t2 = cross(stretched_view, t1);
+    float radius = sqrt(u0);
+    float phi = 2.0 * PI * u1;
+    float p1 = radius * cos(phi);
+    float p2 = radius * sin(phi);
+    float s = 0.5 * (1.0 + stretched_view.z);
+    p2 = (1.0 - s) * sqrt(max(1.0 - p1 * p1, 0.0)) + s * p2;
+    vec3 stretched_normal = p1 * t1 + p2 * t2 + sqrt(max(1.0 - p1 * p1 - p2 * p2, 0.0)) * stretched_view;
+    vec3 local_half = normalize(vec3(alpha * stretched_normal.xy, max(stretched_normal.z, 0.0)));
+    vec3 half_vector = tangent * local_half.x + bitangent * local_half.y + normal * local_half.z;
+
+    direction = reflect(-view_direction, half_vector);
+    float dot_nl = dot(normal, direction);
+    if (dot_nl <= 0.0) {
+        return false;
+    }
+
+    // Reflectance divided by the VNDF pdf leaves fresnel and the masking of the light direction
+    float dot_vh = clamp(dot(view_direction, half_vector), 0.0, 1.0);
+    vec3 fresnel = specular_color + (vec3(1.0) - specular_color) * pow(1.0 - dot_vh, 5.0);
+    float alpha_squared = alpha * alpha;
+    float masking = 2.0 * dot_nl / (dot_nl + sqrt(alpha_squared + (1.0 - alpha_squared) * dot_nl * dot_nl));
+    weight = fresnel * masking / specular_probability;
+    return true;
+}
+
+void main() {
+    uint sample_index = sample_index_light_count_max_bounces_unused.x;
+    uint max_bounces = sample_index_light_count_max_bounces_unused.z;
+    uvec2 pixel = gl_LaunchIDNV.xy + viewport_offset_unused.xy;
+    uint rng_state = hash(pixel.x + hash(pixel.y + hash(sample_index)));
+
+    // Pixels are jittered every sample, which also antialiases the accumulated image
+    vec2 subpixel = vec2(random(rng_state), random(rng_state));
+    vec2 ndc = (vec2(gl_LaunchIDNV.xy) + subpixel) / vec2(gl_LaunchSizeNV.xy) * 2.0 - 1.0;
+    vec4 near_point = inverse_view_projection * vec4(ndc, 1.0, 1.0);
+    vec3 origin = camera_position.xyz;
+    vec3 direction = normalize(near_point.xyz / near_point.w - origin);
+
+    vec3 radiance = vec3(0.0);
+    vec3 throughput = vec3(1.0);
+    for (uint bounce = 0; bounce <= max_bounces; bounce++) {
+        traceNV(SceneTopLevel, gl_RayFlagsNoneNV, 0xFF, 0, 0, 0, origin, 0.0, direction, MAX_RAY_DISTANCE, 0);
+        radiance += throughput * Hit.emissive.rgb;
+        if (Hit.position_distance.w < 0.0) {
+            break;
+        }
+
+        vec3 position = Hit.position_distance.xyz;
+        vec3 normal = Hit.normal_roughness.xyz;
+        vec3 geometric_normal = Hit.geometric_normal_metallic.xyz;
+        float roughness = Hit.normal_roughness.w;
+        float metallic = Hit.geometric_normal_metallic.w;
+        vec3 base_color = Hit.base_color.rgb;
+        vec3 view_direction = -direction;
+
+        const vec3 F0 = vec3(0.04);
+        vec3 diffuse_color = base_color * (vec3(1.0) - F0) * (1.0 - metallic);
+        vec3 specular_color = mix(F0, base_color, metallic);
+
+        radiance += throughput * sample_punctual_light(
+            position,
+            normal,
+            geometric_normal,
+            view_direction,
+            diffuse_color,
+            specular_color,
+            roughness,
+            rng_state
+        );
+        if (bounce == max_bounces) {
+            break;
+        }
+
+        vec3 weight;
+        if (!sample_brdf(normal, view_direction, diffuse_color, specular_color, roughness, rng_state, direction, weight)) {
+            break;
+        }
+        if (dot(direction, geometric_normal) <= 0.0) {
+            break;
+        }
+        throughput *= weight;
+
+        // Russian roulette after the first couple of bounces
+        if (bounce >= 2) {
+            float survival = clamp(max(throughput.r, max(throughput.g, throughput.b)), 0.05, 1.0);
+            if (random(rng_state) >= survival) {
+                break;
+            }
+            throughput /= survival;
+        }
+        origin = position + geometric_normal * RAY_OFFSET;
+    }
+
+    // Running average of all samples since the last reset
+    ivec2 image_pixel = ivec2(pixel);
+    vec3 previous = sample_index > 0 ? imageLoad(AccumulationImage, image_pixel).rgb : vec3(0.0);
+    vec3 accumulated = mix(previous, radiance, 1.0 / float(sample_index + 1));
+    imageStore(AccumulationImage, image_pixel, vec4(accumulated, 1.0));
+}
+#endif
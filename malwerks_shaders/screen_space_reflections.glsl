@@ -0,0 +1,218 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+#define CAMERA_NEAR_DISTANCE 0.1
+#define MAX_FILTER_RADIUS 4.0
+
+layout (push_constant) uniform PC_ScreenSpaceReflections {
+    vec4 MaxDistanceThicknessMaxRoughnessUnused;
+    ivec4 RenderArea; // offset xy, extent zw
+    ivec4 MaxLevelMaxIterationsUnused;
+};
+
+#ifdef COMPUTE_STAGE
+layout (local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+#ifdef HI_Z_DOWNSAMPLE
+layout (set = 0, binding = 0) uniform sampler2D SourceDepthImage;
+layout (set = 0, binding = 1, r32f) uniform writeonly image2D HiZImage;
+
+void main() {
+    ivec2 coord = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 target_size = imageSize(HiZImage);
+    if (any(greaterThanEqual(coord, target_size))) {
+        return;
+    }
+
+    // Depth is reversed, each texel keeps the closest depth of its footprint,
+    // odd source sizes make the footprint cover an extra row or column
+    ivec2 source_size = textureSize(SourceDepthImage, 0);
+    ivec2 source_begin = coord * source_size / target_size;
+    ivec2 source_end = ((coord + ivec2(1)) * source_size + target_size - ivec2(1)) / target_size;
+
+    float closest_depth = 0.0;
+    for (int y = source_begin.y; y < source_end.y; y++) {
+        for (int x = source_begin.x; x < source_end.x; x++) {
+            closest_depth = max(closest_depth, texelFetch(SourceDepthImage, ivec2(x, y), 0).r);
+        }
+    }
+    imageStore(HiZImage, coord, vec4(closest_depth));
+}
+#endif
+
+#ifdef REFLECTION_TRACE
+layout (std140, set = 0, binding = 0) uniform PerFrame {
+    mat4 ViewProjection;
+    mat4 InverseViewProjection;
+    mat4 ViewReprojection;
+    vec4 CameraPosition;
+    vec4 CameraOrientation;
+    vec4 ViewportSize;
+    vec4 RenderScale;
+};
+
+layout (set = 1, binding = 0) uniform sampler2D PrecomputedBrdf;
+layout (set = 1, binding = 1) uniform samplerCube ProbeTexture;
+layout (set = 1, binding = 2) uniform samplerCube IemTexture;
+layout (set = 1, binding = 3) uniform samplerCube PmremTexture;
+
+layout (set = 2, binding = 0) uniform sampler2D SceneColorImage;
+layout (set = 2, binding = 1) uniform sampler2D SourceDepthImage;
+layout (set = 2, binding = 2) uniform sampler2D NormalRoughnessImage;
+layout (set = 2, binding = 3) uniform sampler2D SpecularReflectanceImage;
+layout (set = 2, binding = 4) uniform sampler2D HiZImage;
+layout (set = 2, binding = 5, rgba16f) uniform writeonly image2D ReflectionImage;
+
+vec3 project_to_screen(vec4 clip_position) {
+    vec3 ndc = clip_position.xyz / clip_position.w;
+    return vec3(vec2(RenderArea.xy) + (ndc.xy * 0.5 + vec2(0.5)) * vec2(RenderArea.zw), ndc.z);
+}
+
+// Hierarchical ray march against the closest depth pyramid, returns the ray parameter of the hit or -1.0
+float trace_hi_z(vec3 ray_start, vec3 ray_delta) {
+    float pixel_length = max(abs(ray_delta.x), abs(ray_delta.y));
+    if (pixel_length < 1.0) {
+        return -1.0;
+    }
+
+    vec2 area_min = vec2(RenderArea.xy);
+    vec2 area_max = vec2(RenderArea.xy + RenderArea.zw);
+    vec2 safe_delta = vec2(
+        ray_delta.x >= 0.0 ? max(ray_delta.x, 1e-5) : min(ray_delta.x, -1e-5),
+        ray_delta.y >= 0.0 ? max(ray_delta.y, 1e-5) : min(ray_delta.y, -1e-5)
+    );
+    float cell_epsilon = 0.01 / pixel_length;
+
+    // Start one pixel away from the surface to avoid self intersection
+    float t = 1.0 / pixel_length;
+    int level = 0;
+    for (int iteration = 0; iteration < MaxLevelMaxIterationsUnused.y; iteration++) {
+        vec3 position = ray_start + ray_delta * t;
+        if (t > 1.0 || any(lessThan(position.xy, area_min)) || any(greaterThanEqual(position.xy, area_max))) {
+            break;
+        }
+
+        // Last texel of odd sized mips covers the remaining pixels
+        ivec2 cell = min(ivec2(position.xy) >> level, textureSize(HiZImage, level) - ivec2(1));
+        float cell_depth = texelFetch(HiZImage, cell, level).r;
+
+        float cell_size = float(1 << level);
+        vec2 cell_boundary = (vec2(cell) + step(vec2(0.0), ray_delta.xy)) * cell_size;
+        vec2 boundary_t = (cell_boundary - ray_start.xy) / safe_delta;
+        float exit_t = min(boundary_t.x, boundary_t.y) + cell_epsilon;
+
+        // Reversed depth, the ray is in front of the cell while its depth is greater than the closest one
+        float surface_t = ray_delta.z < 0.0 ? (cell_depth - ray_start.z) / ray_delta.z : 1e10;
+        if (position.z > cell_depth && surface_t > exit_t) {
+            t = exit_t;
+            level = min(level + 1, MaxLevelMaxIterationsUnused.x);
+            continue;
+        }
+
+        t = max(t, min(surface_t, exit_t));
+        if (level == 0) {
+            // Reject hits behind thin geometry
+            float ray_distance = CAMERA_NEAR_DISTANCE / max(ray_start.z + ray_delta.z * t, 1e-7);
+            float surface_distance = CAMERA_NEAR_DISTANCE / max(cell_depth, 1e-7);
+            return ray_distance - surface_distance <= MaxDistanceThicknessMaxRoughnessUnused.y ? t : -1.0;
+        }
+        level--;
+    }
+    return -1.0;
+}
+
+void main() {
+    ivec2 coord = RenderArea.xy + ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(gl_GlobalInvocationID.xy, uvec2(RenderArea.zw)))) {
+        return;
+    }
+
+    float max_roughness = MaxDistanceThicknessMaxRoughnessUnused.z;
+    float depth = texelFetch(SourceDepthImage, coord, 0).r;
+    vec4 normal_roughness = texelFetch(NormalRoughnessImage, coord, 0);
+    float roughness = normal_roughness.w;
+    if (depth <= 0.0 || roughness >= max_roughness) {
+        imageStore(ReflectionImage, coord, vec4(0.0));
+        return;
+    }
+
+    vec2 uv = (vec2(coord - RenderArea.xy) + vec2(0.5)) / vec2(RenderArea.zw);
+    vec4 world_position = InverseViewProjection * vec4(uv * 2.0 - vec2(1.0), depth, 1.0);
+    vec3 position = world_position.xyz / world_position.w;
+    vec3 normal = normalize(normal_roughness.xyz);
+    vec3 reflect_direction = normalize(reflect(normalize(position - CameraPosition.xyz), normal));
+
+    // Clip the ray against the near plane, clip w is the view depth
+    float ray_length = MaxDistanceThicknessMaxRoughnessUnused.x;
+    vec4 start_clip = ViewProjection * vec4(position, 1.0);
+    vec4 end_clip = ViewProjection * vec4(position + reflect_direction * ray_length, 1.0);
+    if (end_clip.w < CAMERA_NEAR_DISTANCE) {
+        ray_length *= (start_clip.w - CAMERA_NEAR_DISTANCE) / (start_clip.w - end_clip.w);
+        end_clip = ViewProjection * vec4(position + reflect_direction * ray_length, 1.0);
+    }
+
+    vec3 ray_start = project_to_screen(start_clip);
+    vec3 ray_delta = project_to_screen(end_clip) - ray_start;
+    float hit_t = trace_hi_z(ray_start, ray_delta);
+    if (hit_t < 0.0) {
+        // Environment probe is already applied by the material
+        imageStore(ReflectionImage, coord, vec4(0.0));
+        return;
+    }
+
+    vec2 hit_position = ray_start.xy + ray_delta.xy * hit_t;
+    vec2 hit_uv = (hit_position - vec2(RenderArea.xy)) / vec2(RenderArea.zw);
+    vec2 edge_distance = min(hit_uv, vec2(1.0) - hit_uv);
+    float confidence = smoothstep(0.0, 0.1, min(edge_distance.x, edge_distance.y));
+    confidence *= 1.0 - smoothstep(0.8, 1.0, hit_t);
+    confidence *= 1.0 - smoothstep(max_roughness * 0.75, max_roughness, roughness);
+
+    // Replace the probe contribution with the traced one, output is signed and added on top of the scene
+    vec3 hit_color = textureLod(SceneColorImage, hit_position / vec2(textureSize(SceneColorImage, 0)), 0.0).rgb;
+    vec3 probe_color = textureLod(PmremTexture, reflect_direction, roughness * 10.0).rgb;
+    vec3 specular_reflectance = texelFetch(SpecularReflectanceImage, coord, 0).rgb;
+    imageStore(ReflectionImage, coord, vec4((hit_color - probe_color) * specular_reflectance * confidence, confidence));
+}
+#endif
+#endif
+
+#ifdef VERTEX_STAGE
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(uv * 2.0f + -1.0f, 0.0f, 1.0f);
+}
+#endif
+
+#ifdef FRAGMENT_STAGE
+layout (set = 0, binding = 0) uniform sampler2D ReflectionImage;
+layout (set = 0, binding = 1) uniform sampler2D NormalRoughnessImage;
+
+layout (location = 0) out vec4 Target0;
+
+void main() {
+    ivec2 coord = ivec2(gl_FragCoord.xy);
+    ivec2 area_min = RenderArea.xy;
+    ivec2 area_max = RenderArea.xy + RenderArea.zw - ivec2(1);
+    vec4 center_normal_roughness = texelFetch(NormalRoughnessImage, coord, 0);
+
+    // Rough surfaces get wider blur, samples are rejected across normal discontinuities
+    float radius = MAX_FILTER_RADIUS * center_normal_roughness.w / max(MaxDistanceThicknessMaxRoughnessUnused.z, 1e-3);
+    vec3 reflection = vec3(0.0);
+    float total_weight = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            ivec2 sample_coord = clamp(coord + ivec2(round(vec2(x, y) * radius)), area_min, area_max);
+            vec3 sample_normal = texelFetch(NormalRoughnessImage, sample_coord, 0).xyz;
+            float weight = pow(max(dot(sample_normal, center_normal_roughness.xyz), 0.0), 8.0) + 1e-4;
+
+            reflection += texelFetch(ReflectionImage, sample_coord, 0).rgb * weight;
+            total_weight += weight;
+        }
+    }
+    Target0 = vec4(reflection / total_weight, 0.0);
+}
+#endif
@@ -0,0 +1,37 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+// Vertex stage is full_screen.glsl, the heatmap replaces the tone mapped output
+layout (push_constant) uniform PC_ShaderClockHeatmap {
+    vec4 uv_scale_max_cost; // uv scale, clock ticks shown as red
+};
+
+layout(set = 0, binding = 0) uniform sampler PointSampler;
+layout(set = 0, binding = 1) uniform texture2D CostImage;
+
+layout(location = 0) in vec2 VS_uv;
+layout(location = 0) out vec4 Target0;
+
+// Black when nothing is shaded, then blue, cyan, green, yellow and red at the max cost
+vec3 heat_color(float cost) {
+    const vec3 COLORS[6] = vec3[6](
+        vec3(0.0, 0.0, 0.0),
+        vec3(0.0, 0.0, 1.0),
+        vec3(0.0, 1.0, 1.0),
+        vec3(0.0, 1.0, 0.0),
+        vec3(1.0, 1.0, 0.0),
+        vec3(1.0, 0.0, 0.0)
+    );
+    float position = clamp(cost / max(uv_scale_max_cost.z, 1.0), 0.0, 1.0) * 5.0;
+    int index = min(int(position), 4);
+    return mix(COLORS[index], COLORS[index + 1], position - float(index));
+}
+
+void main() {
+    float cost = texture(sampler2D(CostImage, PointSampler), VS_uv * uv_scale_max_cost.xy).r;
+    Target0 = vec4(heat_color(cost), 1.0);
+}
@@ -5,19 +5,30 @@
 
 #version 460 core
 
-#ifdef VERTEX_STAGE
-layout(location = 0) out vec2 VS_uv;
+// Vertex stage is full_screen.glsl
+layout (push_constant) uniform PC_ToneMap {
+    vec4 uv_scale_srgb_target; // z is 1.0 if the target format is _SRGB, w is 1.0 if the source format is signed
+    vec4 dither_amplitude_unused; // x is the noise amplitude in target format units, 0.0 disables dithering
+};
 
-void main() {
-    VS_uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
-    gl_Position = vec4(VS_uv * 2.0f + -1.0f, 0.0f, 1.0f);
-}
-#endif
-
-#ifdef FRAGMENT_STAGE
-layout(set = 0, binding = 0) uniform sampler PointSampler;
+layout(set = 0, binding = 0) uniform sampler LinearSampler;
 layout(set = 0, binding = 1) uniform texture2D FrameImage;
 
+layout (std140, set = 1, binding = 0) uniform PerFrame {
+    mat4 ViewProjection;
+    mat4 InverseViewProjection;
+    mat4 ViewReprojection;
+    vec4 CameraPosition;
+    vec4 CameraOrientation;
+    vec4 ViewportSize;
+    vec4 RenderScale;
+    mat4 StereoViewProjection[2];
+    uvec4 PunctualLightCount;
+    uvec4 BlueNoiseLayer;
+};
+
+layout (set = 1, binding = 3) uniform sampler2DArray BlueNoise;
+
 layout(location = 0) in vec2 VS_uv;
 layout(location = 0) out vec4 Target0;
 
@@ -29,8 +40,40 @@ vec3 tone_map(vec3 hdr)
     return vec3(hdr * (6.2 * hdr + .5)) / (hdr * (6.2 * hdr + 1.7) + 0.06);
 }
 
+// Undoes the gamma encoding for _SRGB targets, hardware encodes it again when writing
+vec3 srgb_to_linear(vec3 srgb)
+{
+    vec3 low = srgb / 12.92;
+    vec3 high = pow((srgb + vec3(0.055)) / 1.055, vec3(2.4));
+    return mix(high, low, lessThanEqual(srgb, vec3(0.04045)));
+}
+
+// Maps uniform noise to a triangular distribution in [-1, 1], unlike uniform noise its variance
+// doesn't depend on the signal, so dark gradients don't get visibly noisier than bright ones
+float triangular_noise(float uniform_noise)
+{
+    float signed_noise = uniform_noise * 2.0 - 1.0;
+    return sign(signed_noise) * (1.0 - sqrt(1.0 - abs(signed_noise)));
+}
+
 void main() {
-    vec3 frame_sample = texture(sampler2D(FrameImage, PointSampler), VS_uv).rgb;
-    Target0 = vec4(tone_map(frame_sample), 1.0);
+    vec3 frame_sample = texture(sampler2D(FrameImage, LinearSampler), VS_uv * uv_scale_srgb_target.xy).rgb;
+    if (uv_scale_srgb_target.w > 0.5) {
+        // Signed half floats keep negative values and NaNs produced by blending, unsigned formats clamp them
+        frame_sample = mix(frame_sample, vec3(0.0), isnan(frame_sample));
+        frame_sample = clamp(frame_sample, vec3(0.0), vec3(65504.0));
+    }
+    vec3 color = tone_map(frame_sample);
+    if (dither_amplitude_unused.x > 0.0) {
+        // Noise is added before quantization in the encoded space, the same value for all channels avoids color noise
+        ivec2 blue_noise_size = textureSize(BlueNoise, 0).xy;
+        ivec2 blue_noise_coord = ivec2(gl_FragCoord.xy) % blue_noise_size;
+        float blue_noise = texelFetch(BlueNoise, ivec3(blue_noise_coord, BlueNoiseLayer.x), 0).r;
+        float noise = triangular_noise((blue_noise * 255.0 + 0.5) / 256.0);
+        color = clamp(color + vec3(noise * dither_amplitude_unused.x), vec3(0.0), vec3(1.0));
+    }
+    if (uv_scale_srgb_target.z > 0.5) {
+        color = srgb_to_linear(color);
+    }
+    Target0 = vec4(color, 1.0);
 }
-#endif
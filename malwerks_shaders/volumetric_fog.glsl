@@ -0,0 +1,168 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+#define PI 3.1415926535897932384626433832795
+#define FROXEL_NEAR_DISTANCE 0.5
+
+layout (push_constant) uniform PC_VolumetricFog {
+    vec4 DensityHeightFalloffAnisotropy;
+    vec4 AlbedoMaxDistance;
+    vec4 PreviousCameraPositionUnused;
+    vec4 HistoryWeightUnused;
+};
+
+layout (std140, set = 0, binding = 0) uniform PerFrame {
+    mat4 ViewProjection;
+    mat4 InverseViewProjection;
+    mat4 ViewReprojection;
+    vec4 CameraPosition;
+    vec4 CameraOrientation;
+    vec4 ViewportSize;
+    vec4 RenderScale;
+    mat4 StereoViewProjection[2];
+    uvec4 PunctualLightCount;
+    uvec4 BlueNoiseLayer;
+};
+
+layout (set = 0, binding = 3) uniform sampler2DArray BlueNoise;
+
+// Froxel slices are distributed exponentially along the view ray
+float slice_to_distance(float w) {
+    return FROXEL_NEAR_DISTANCE * pow(AlbedoMaxDistance.w / FROXEL_NEAR_DISTANCE, w);
+}
+
+float distance_to_slice(float d) {
+    return log(max(d, FROXEL_NEAR_DISTANCE) / FROXEL_NEAR_DISTANCE) / log(AlbedoMaxDistance.w / FROXEL_NEAR_DISTANCE);
+}
+
+vec3 view_ray_direction(vec2 uv) {
+    // Projection is reversed and infinite, near plane is at depth 1
+    vec4 near_position = InverseViewProjection * vec4(uv * 2.0 - vec2(1.0), 1.0, 1.0);
+    return normalize(near_position.xyz / near_position.w - CameraPosition.xyz);
+}
+
+#ifdef COMPUTE_STAGE
+layout (set = 1, binding = 0) uniform sampler2D PrecomputedBrdf;
+layout (set = 1, binding = 1) uniform samplerCube ProbeTexture;
+layout (set = 1, binding = 2) uniform samplerCube IemTexture;
+layout (set = 1, binding = 3) uniform samplerCube PmremTexture;
+
+layout (set = 2, binding = 0, rgba16f) uniform image3D ScatteringVolume;
+layout (set = 2, binding = 1) uniform sampler3D HistoryScatteringVolume;
+layout (set = 2, binding = 2, rgba16f) uniform writeonly image3D IntegratedVolume;
+
+layout (local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+#ifdef FOG_SCATTERING
+float fog_density(vec3 position) {
+    float height_above_fog = max(position.y - DensityHeightFalloffAnisotropy.y, 0.0);
+    return DensityHeightFalloffAnisotropy.x * exp(-DensityHeightFalloffAnisotropy.z * height_above_fog);
+}
+
+vec3 in_scattered_light(vec3 view_direction) {
+    // Environment probe is the only light source for now, irradiance around the view direction
+    // is forward scattered towards the camera, irradiance from behind the camera is back scattered
+    vec3 forward_light = texture(IemTexture, view_direction).rgb / PI;
+    vec3 backward_light = texture(IemTexture, -view_direction).rgb / PI;
+    float forward_weight = 0.5 + 0.5 * DensityHeightFalloffAnisotropy.w;
+    return mix(backward_light, forward_light, forward_weight);
+}
+
+void main() {
+    ivec3 volume_size = imageSize(ScatteringVolume);
+    ivec3 coord = ivec3(gl_GlobalInvocationID.xyz);
+    if (any(greaterThanEqual(coord, volume_size))) {
+        return;
+    }
+
+    // Spatiotemporal blue noise spreads slice samples evenly over neighbouring froxels and over time
+    ivec2 blue_noise_size = textureSize(BlueNoise, 0).xy;
+    float jitter = texelFetch(BlueNoise, ivec3(coord.xy % blue_noise_size, BlueNoiseLayer.x), 0).r;
+    vec3 froxel_uvw = (vec3(coord) + vec3(0.5, 0.5, jitter)) / vec3(volume_size);
+    vec3 view_direction = view_ray_direction(froxel_uvw.xy);
+    vec3 position = CameraPosition.xyz + view_direction * slice_to_distance(froxel_uvw.z);
+
+    float extinction = fog_density(position);
+    vec3 scattering = extinction * AlbedoMaxDistance.rgb * in_scattered_light(view_direction);
+    vec4 froxel = vec4(scattering, extinction);
+
+    // Reproject into the previous frame and blend with history to hide slice jittering
+    vec4 clip_position = ViewProjection * vec4(position, 1.0);
+    vec4 previous_position = ViewReprojection * vec4(clip_position.xyz / clip_position.w, 1.0);
+    vec2 previous_uv = previous_position.xy / previous_position.w * 0.5 + vec2(0.5);
+    float previous_slice = distance_to_slice(length(position - PreviousCameraPositionUnused.xyz));
+    vec3 previous_uvw = vec3(previous_uv, previous_slice);
+    if (all(greaterThanEqual(previous_uvw, vec3(0.0))) && all(lessThanEqual(previous_uvw, vec3(1.0)))) {
+        vec4 history = textureLod(HistoryScatteringVolume, previous_uvw, 0.0);
+        froxel = mix(froxel, history, HistoryWeightUnused.x);
+    }
+
+    imageStore(ScatteringVolume, coord, froxel);
+}
+#endif
+
+#ifdef FOG_INTEGRATION
+void main() {
+    ivec3 volume_size = imageSize(ScatteringVolume);
+    ivec2 coord = ivec2(gl_GlobalInvocationID.xy);
+    if (any(greaterThanEqual(coord, volume_size.xy))) {
+        return;
+    }
+
+    vec3 accumulated_scattering = vec3(0.0);
+    float accumulated_transmittance = 1.0;
+    float previous_distance = 0.0;
+    for (int slice = 0; slice < volume_size.z; slice++) {
+        float slice_distance = slice_to_distance(float(slice + 1) / float(volume_size.z));
+        float step_length = slice_distance - previous_distance;
+        previous_distance = slice_distance;
+
+        // Energy conserving integration of the scattering over the froxel depth
+        vec4 froxel = imageLoad(ScatteringVolume, ivec3(coord, slice));
+        float extinction = max(froxel.a, 1e-5);
+        float step_transmittance = exp(-extinction * step_length);
+        vec3 step_scattering = (froxel.rgb - froxel.rgb * step_transmittance) / extinction;
+
+        accumulated_scattering += accumulated_transmittance * step_scattering;
+        accumulated_transmittance *= step_transmittance;
+        imageStore(IntegratedVolume, ivec3(coord, slice), vec4(accumulated_scattering, accumulated_transmittance));
+    }
+}
+#endif
+#endif
+
+#ifdef VERTEX_STAGE
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(uv * 2.0f + -1.0f, 0.0f, 1.0f);
+}
+#endif
+
+#ifdef FRAGMENT_STAGE
+layout (set = 1, binding = 0) uniform sampler2D SourceDepthImage;
+layout (set = 1, binding = 1) uniform sampler3D IntegratedVolume;
+
+layout (location = 0) out vec4 Target0;
+
+void main() {
+    // Rendered at half resolution into the top left corner of the target when resolution scaling is active
+    ivec2 source_coord = ivec2(gl_FragCoord.xy) * 2;
+    vec2 uv = gl_FragCoord.xy * 2.0 * ViewportSize.zw / RenderScale.xy;
+
+    float depth = texelFetch(SourceDepthImage, min(source_coord, textureSize(SourceDepthImage, 0) - ivec2(1)), 0).r;
+    float view_distance = AlbedoMaxDistance.w;
+    if (depth > 0.0) {
+        vec4 position = InverseViewProjection * vec4(uv * 2.0 - vec2(1.0), depth, 1.0);
+        view_distance = length(position.xyz / position.w - CameraPosition.xyz);
+    }
+
+    // Integrated froxel contains accumulated values up to its far edge
+    float slice = distance_to_slice(view_distance) - 0.5 / float(textureSize(IntegratedVolume, 0).z);
+    vec4 fog = texture(IntegratedVolume, vec3(uv, max(slice, 0.0)));
+    Target0 = vec4(fog.rgb, fog.a);
+}
+#endif
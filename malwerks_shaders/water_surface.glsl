@@ -0,0 +1,164 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#version 460 core
+
+#define PI 3.1415926535897932384626433832795
+#define GRID_RESOLUTION 256
+#define WAVE_COUNT 4
+#define REFLECTION_STEPS 32
+#define REFLECTION_DISTANCE 32.0
+#define CAMERA_NEAR_DISTANCE 0.1
+
+layout (push_constant) uniform PC_WaterSurface {
+    mat4 SubsampleViewProjection;
+    vec4 PositionSize;
+    vec4 WaveAmplitudeLengthSteepnessSpeed;
+    vec4 WaveDirectionTimeUnused;
+    vec4 AbsorptionRefraction;
+};
+
+layout (std140, set = 0, binding = 0) uniform PerFrame {
+    mat4 ViewProjection;
+    mat4 InverseViewProjection;
+    mat4 ViewReprojection;
+    vec4 CameraPosition;
+    vec4 CameraOrientation;
+    vec4 ViewportSize;
+    vec4 RenderScale;
+};
+
+#ifdef VERTEX_STAGE
+layout (location = 0) out vec3 VS_position;
+layout (location = 1) out vec3 VS_normal;
+
+// Smaller waves are rotated and scaled relative to the primary one
+const vec4 WAVE_ANGLE_LENGTH_SCALE[WAVE_COUNT] = vec4[WAVE_COUNT](
+    vec4(0.0, 1.0, 0.0, 0.0),
+    vec4(0.6, 0.57, 0.0, 0.0),
+    vec4(-0.8, 0.31, 0.0, 0.0),
+    vec4(1.7, 0.19, 0.0, 0.0)
+);
+
+void main() {
+    // Grid is generated from the vertex index, two triangles per cell
+    const ivec2 CORNERS[6] = ivec2[6](ivec2(0, 0), ivec2(0, 1), ivec2(1, 0), ivec2(1, 0), ivec2(0, 1), ivec2(1, 1));
+    int cell_index = gl_VertexIndex / 6;
+    ivec2 grid_coord = ivec2(cell_index % GRID_RESOLUTION, cell_index / GRID_RESOLUTION) + CORNERS[gl_VertexIndex % 6];
+    vec2 grid_position = (vec2(grid_coord) / float(GRID_RESOLUTION) - vec2(0.5)) * PositionSize.w;
+
+    vec3 position = PositionSize.xyz + vec3(grid_position.x, 0.0, grid_position.y);
+    vec3 normal = vec3(0.0, 1.0, 0.0);
+    float time = WaveDirectionTimeUnused.z;
+    for (int wave = 0; wave < WAVE_COUNT; wave++) {
+        // Sum of Gerstner waves, steepness is shared between all waves
+        float angle = WAVE_ANGLE_LENGTH_SCALE[wave].x;
+        vec2 direction = mat2(cos(angle), sin(angle), -sin(angle), cos(angle)) * WaveDirectionTimeUnused.xy;
+        float wavelength = WaveAmplitudeLengthSteepnessSpeed.y * WAVE_ANGLE_LENGTH_SCALE[wave].y;
+        float amplitude = WaveAmplitudeLengthSteepnessSpeed.x * WAVE_ANGLE_LENGTH_SCALE[wave].y;
+        float k = 2.0 * PI / wavelength;
+        float speed = sqrt(9.81 / k) * WaveAmplitudeLengthSteepnessSpeed.w;
+        float q = WaveAmplitudeLengthSteepnessSpeed.z / (k * amplitude * float(WAVE_COUNT) + 1e-5);
+        float f = k * (dot(direction, grid_position) - speed * time);
+
+        position.xz += q * amplitude * direction * cos(f);
+        position.y += amplitude * sin(f);
+        normal.xz -= direction * k * amplitude * cos(f);
+        normal.y -= q * k * amplitude * sin(f);
+    }
+
+    VS_position = position;
+    VS_normal = normal;
+    gl_Position = SubsampleViewProjection * vec4(position, 1.0);
+}
+#endif
+
+#ifdef FRAGMENT_STAGE
+layout (set = 1, binding = 0) uniform sampler2D PrecomputedBrdf;
+layout (set = 1, binding = 1) uniform samplerCube ProbeTexture;
+layout (set = 1, binding = 2) uniform samplerCube IemTexture;
+layout (set = 1, binding = 3) uniform samplerCube PmremTexture;
+
+layout (set = 2, binding = 0) uniform sampler2D SceneColorImage;
+layout (set = 2, binding = 1) uniform sampler2D SceneDepthImage;
+
+layout (location = 0) in vec3 VS_position;
+layout (location = 1) in vec3 VS_normal;
+
+layout (location = 0) out vec4 Target0;
+
+vec3 project_to_screen(vec3 position) {
+    vec4 clip_position = ViewProjection * vec4(position, 1.0);
+    vec3 ndc = clip_position.xyz / clip_position.w;
+    return vec3((ndc.xy * 0.5 + vec2(0.5)) * ViewportSize.xy * RenderScale.xy, ndc.z);
+}
+
+float linear_distance(float depth) {
+    return CAMERA_NEAR_DISTANCE / max(depth, 1e-7);
+}
+
+vec3 trace_reflection(vec3 position, vec3 reflect_direction, vec3 probe_color) {
+    // Short linear march against the scene depth, probe is used when nothing is hit
+    vec2 render_size = ViewportSize.xy * RenderScale.xy;
+    float step_length = REFLECTION_DISTANCE / float(REFLECTION_STEPS);
+    for (int step_index = 1; step_index <= REFLECTION_STEPS; step_index++) {
+        vec3 sample_position = project_to_screen(position + reflect_direction * step_length * float(step_index));
+        if (any(lessThan(sample_position.xy, vec2(0.0))) || any(greaterThanEqual(sample_position.xy, render_size)) ||
+            sample_position.z <= 0.0) {
+            break;
+        }
+
+        float scene_depth = texelFetch(SceneDepthImage, ivec2(sample_position.xy), 0).r;
+        float depth_difference = linear_distance(sample_position.z) - linear_distance(scene_depth);
+        if (depth_difference > 0.0 && depth_difference < step_length * 2.0) {
+            vec2 uv = sample_position.xy / render_size;
+            vec2 edge_distance = min(uv, vec2(1.0) - uv);
+            float confidence = smoothstep(0.0, 0.1, min(edge_distance.x, edge_distance.y));
+            vec3 hit_color = textureLod(SceneColorImage, sample_position.xy / vec2(textureSize(SceneColorImage, 0)), 0.0).rgb;
+            return mix(probe_color, hit_color, confidence);
+        }
+    }
+    return probe_color;
+}
+
+void main() {
+    // Scene depth is sampled instead of being attached, water is rejected behind opaque geometry
+    float scene_depth = texelFetch(SceneDepthImage, ivec2(gl_FragCoord.xy), 0).r;
+    if (gl_FragCoord.z < scene_depth) {
+        discard;
+    }
+
+    vec3 normal = normalize(VS_normal);
+    vec3 view_direction = normalize(CameraPosition.xyz - VS_position);
+    if (dot(normal, view_direction) < 0.0) {
+        normal = -normal;
+    }
+
+    // Refraction offsets the scene color lookup, samples in front of the water are rejected
+    vec2 scene_size = vec2(textureSize(SceneColorImage, 0));
+    vec2 refraction_coord = gl_FragCoord.xy + normal.xz * AbsorptionRefraction.w * ViewportSize.y * RenderScale.y;
+    refraction_coord = clamp(refraction_coord, vec2(0.0), ViewportSize.xy * RenderScale.xy - vec2(1.0));
+    float refraction_depth = texelFetch(SceneDepthImage, ivec2(refraction_coord), 0).r;
+    if (refraction_depth > gl_FragCoord.z) {
+        refraction_coord = gl_FragCoord.xy;
+        refraction_depth = scene_depth;
+    }
+
+    float water_thickness = max(linear_distance(refraction_depth) - linear_distance(gl_FragCoord.z), 0.0);
+    vec3 transmittance = exp(-AbsorptionRefraction.rgb * water_thickness);
+    vec3 scattered_light = texture(IemTexture, vec3(0.0, 1.0, 0.0)).rgb / PI * (vec3(1.0) - transmittance) * 0.1;
+    vec3 scene_color = textureLod(SceneColorImage, refraction_coord / scene_size, 0.0).rgb;
+    vec3 refraction = scene_color * transmittance + scattered_light;
+
+    vec3 reflect_direction = normalize(reflect(-view_direction, normal));
+    vec3 probe_color = textureLod(PmremTexture, reflect_direction, 0.0).rgb;
+    vec3 reflection = trace_reflection(VS_position, reflect_direction, probe_color);
+
+    // Schlick approximation for water
+    const float F0 = 0.02;
+    float fresnel = F0 + (1.0 - F0) * pow(1.0 - clamp(dot(normal, view_direction), 0.0, 1.0), 5.0);
+    Target0 = vec4(mix(refraction, reflection, fresnel), 1.0);
+}
+#endif
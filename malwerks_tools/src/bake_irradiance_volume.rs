@@ -0,0 +1,704 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+
+use ash::vk;
+use serde::{Deserialize, Serialize};
+use ultraviolet::vec::Vec3;
+
+mod scene_geometry;
+use scene_geometry::*;
+
+// Ambient cube face directions, same order as in the shader
+const FACE_DIRECTIONS: [[f32; 3]; 6] = [
+    [1.0, 0.0, 0.0],
+    [-1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, -1.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [0.0, 0.0, -1.0],
+];
+
+// Probes that see too many backfaces are inside geometry and are filled from their neighbours
+const MAX_BACKFACE_RATIO: f32 = 0.25;
+
+const MAX_PROBE_COUNT: u32 = 128;
+
+#[derive(Debug, structopt::StructOpt)]
+#[structopt(name = "bake_irradiance_volume", about = "Irradiance volume baking tool")]
+struct CommandLineOptions {
+    #[structopt(short = "i", long = "input", parse(from_os_str))]
+    input_file: std::path::PathBuf,
+
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output_file: std::path::PathBuf,
+
+    #[structopt(short = "p", long = "probe_spacing", default_value = "1.0")]
+    probe_spacing: f32,
+
+    #[structopt(short = "s", long = "sample_count", default_value = "256")]
+    sample_count: usize,
+
+    #[structopt(short = "b", long = "bounce_count", default_value = "2")]
+    bounce_count: usize,
+
+    // Zero disables denoising, larger values allow blurring across larger irradiance differences
+    #[structopt(long = "denoise_strength", default_value = "1.0")]
+    denoise_strength: f32,
+
+    #[structopt(long = "denoise_iterations", default_value = "3")]
+    denoise_iterations: usize,
+
+    // Samples of every bounce are split between passes, progress is saved to the checkpoint file after each one
+    #[structopt(long = "pass_count", default_value = "8")]
+    pass_count: usize,
+
+    #[structopt(short = "c", long = "checkpoint", parse(from_os_str))]
+    checkpoint_file: Option<std::path::PathBuf>,
+
+    // Seconds to bake before stopping, only useful together with a checkpoint file
+    #[structopt(short = "t", long = "time_limit")]
+    time_limit: Option<f32>,
+
+    // Stores probes as 32 bit floats instead of half floats
+    #[structopt(long = "full_precision")]
+    full_precision: bool,
+}
+
+type AmbientCube = [Vec3; 6];
+
+// Variance of the luminance estimate of every ambient cube face
+type AmbientCubeVariance = [f32; 6];
+
+// Every probe stores how much of the global environment irradiance reaches it from each direction,
+// escaped rays transfer the environment as is, hit rays reflect previous bounce of the volume.
+struct IrradianceVolume {
+    volume_min: Vec3,
+    volume_max: Vec3,
+    probe_counts: [u32; 3],
+    probes: Vec<AmbientCube>,
+}
+
+impl IrradianceVolume {
+    fn new(volume_min: Vec3, volume_max: Vec3, probe_counts: [u32; 3]) -> Self {
+        let probe_count = (probe_counts[0] * probe_counts[1] * probe_counts[2]) as usize;
+        Self {
+            volume_min,
+            volume_max,
+            probe_counts,
+            probes: vec![[Vec3::zero(); 6]; probe_count],
+        }
+    }
+
+    fn get_probe_index(&self, x: u32, y: u32, z: u32) -> usize {
+        ((z * self.probe_counts[1] + y) * self.probe_counts[0] + x) as usize
+    }
+
+    fn get_probe_position(&self, probe_id: usize) -> Vec3 {
+        let x = probe_id as u32 % self.probe_counts[0];
+        let y = (probe_id as u32 / self.probe_counts[0]) % self.probe_counts[1];
+        let z = probe_id as u32 / (self.probe_counts[0] * self.probe_counts[1]);
+        let cell_size = self.get_cell_size();
+        self.volume_min + Vec3::new(x as f32 * cell_size.x, y as f32 * cell_size.y, z as f32 * cell_size.z)
+    }
+
+    fn get_cell_size(&self) -> Vec3 {
+        let cell_counts = Vec3::new(
+            (self.probe_counts[0] - 1) as f32,
+            (self.probe_counts[1] - 1) as f32,
+            (self.probe_counts[2] - 1) as f32,
+        );
+        (self.volume_max - self.volume_min) / cell_counts
+    }
+
+    // Trilinear interpolation between probes, matches sample_irradiance_volume in the shader
+    fn sample(&self, position: Vec3, normal: Vec3) -> Vec3 {
+        let grid_position = (position - self.volume_min) / self.get_cell_size();
+        let mut base = [0u32; 3];
+        let mut fraction = [0.0f32; 3];
+        for axis in 0..3 {
+            let max_position = (self.probe_counts[axis] - 1) as f32;
+            let axis_position = grid_position.as_slice()[axis].clamp(0.0, max_position);
+            base[axis] = (axis_position.floor() as u32).min(self.probe_counts[axis].saturating_sub(2));
+            fraction[axis] = axis_position - base[axis] as f32;
+        }
+
+        let mut result = Vec3::zero();
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let mut weight = 1.0;
+            for axis in 0..3 {
+                weight *= if offset[axis] == 1 {
+                    fraction[axis]
+                } else {
+                    1.0 - fraction[axis]
+                };
+            }
+            if weight > 0.0 {
+                let probe_id = self.get_probe_index(base[0] + offset[0], base[1] + offset[1], base[2] + offset[2]);
+                result += evaluate_ambient_cube(&self.probes[probe_id], normal) * weight;
+            }
+        }
+        result
+    }
+
+    // Edge-avoiding a-trous filter over valid probes, every iteration doubles the step between taps.
+    // Neighbours are weighted down by how much their luminance differs relative to the estimated noise.
+    fn denoise(&mut self, variances: &[AmbientCubeVariance], valid_probes: &[bool], strength: f32, iterations: usize) {
+        use rayon::prelude::*;
+
+        const KERNEL: [f32; 3] = [0.25, 0.5, 0.25];
+
+        let mut variances = variances.to_vec();
+        for iteration in 0..iterations {
+            let step = 1i64 << iteration;
+            let filtered: Vec<(AmbientCube, AmbientCubeVariance)> = (0..self.probes.len())
+                .into_par_iter()
+                .map(|probe_id| {
+                    if !valid_probes[probe_id] {
+                        return (self.probes[probe_id], variances[probe_id]);
+                    }
+
+                    let x = (probe_id as u32 % self.probe_counts[0]) as i64;
+                    let y = ((probe_id as u32 / self.probe_counts[0]) % self.probe_counts[1]) as i64;
+                    let z = (probe_id as u32 / (self.probe_counts[0] * self.probe_counts[1])) as i64;
+
+                    let mut probe = [Vec3::zero(); 6];
+                    let mut variance = [0.0f32; 6];
+                    let mut weights = [0.0f32; 6];
+                    for tap in 0..27 {
+                        let offset = [tap % 3 - 1, (tap / 3) % 3 - 1, tap / 9 - 1];
+                        let neighbour = [x + offset[0] * step, y + offset[1] * step, z + offset[2] * step];
+                        let is_inside = neighbour
+                            .iter()
+                            .zip(self.probe_counts.iter())
+                            .all(|(position, count)| *position >= 0 && *position < *count as i64);
+                        if !is_inside {
+                            continue;
+                        }
+                        let neighbour_id =
+                            self.get_probe_index(neighbour[0] as u32, neighbour[1] as u32, neighbour[2] as u32);
+                        if !valid_probes[neighbour_id] {
+                            continue;
+                        }
+
+                        let kernel_weight = offset.iter().map(|o| KERNEL[(o + 1) as usize]).product::<f32>();
+                        for face in 0..6 {
+                            let center = self.probes[probe_id][face];
+                            let sample = self.probes[neighbour_id][face];
+                            let sigma = strength * variances[probe_id][face].sqrt() + 1e-6;
+                            let weight =
+                                kernel_weight * (-(get_luminance(center) - get_luminance(sample)).abs() / sigma).exp();
+                            probe[face] += sample * weight;
+                            variance[face] += variances[neighbour_id][face] * weight * weight;
+                            weights[face] += weight;
+                        }
+                    }
+
+                    // Center tap always has full weight, so the sums are never empty
+                    for face in 0..6 {
+                        probe[face] /= weights[face];
+                        variance[face] /= weights[face] * weights[face];
+                    }
+                    (probe, variance)
+                })
+                .collect();
+
+            for (probe_id, (probe, variance)) in filtered.into_iter().enumerate() {
+                self.probes[probe_id] = probe;
+                variances[probe_id] = variance;
+            }
+        }
+    }
+
+    // Invalid probes take the average of their valid neighbours, repeated until everything is filled
+    fn fill_invalid_probes(&mut self, valid_probes: &[bool]) {
+        let mut valid_probes = valid_probes.to_vec();
+        loop {
+            let mut filled_probes = Vec::new();
+            for probe_id in 0..self.probes.len() {
+                if valid_probes[probe_id] {
+                    continue;
+                }
+
+                let x = probe_id as u32 % self.probe_counts[0];
+                let y = (probe_id as u32 / self.probe_counts[0]) % self.probe_counts[1];
+                let z = probe_id as u32 / (self.probe_counts[0] * self.probe_counts[1]);
+                let neighbours = [
+                    (x.wrapping_sub(1), y, z),
+                    (x + 1, y, z),
+                    (x, y.wrapping_sub(1), z),
+                    (x, y + 1, z),
+                    (x, y, z.wrapping_sub(1)),
+                    (x, y, z + 1),
+                ];
+
+                let mut sum = [Vec3::zero(); 6];
+                let mut count = 0;
+                for (nx, ny, nz) in neighbours.iter() {
+                    if *nx >= self.probe_counts[0] || *ny >= self.probe_counts[1] || *nz >= self.probe_counts[2] {
+                        continue;
+                    }
+                    let neighbour_id = self.get_probe_index(*nx, *ny, *nz);
+                    if valid_probes[neighbour_id] {
+                        for (face, neighbour_face) in sum.iter_mut().zip(&self.probes[neighbour_id]) {
+                            *face += *neighbour_face;
+                        }
+                        count += 1;
+                    }
+                }
+                if count > 0 {
+                    for face in sum.iter_mut() {
+                        *face /= count as f32;
+                    }
+                    filled_probes.push((probe_id, sum));
+                }
+            }
+
+            if filled_probes.is_empty() {
+                break;
+            }
+            for (probe_id, probe) in filled_probes {
+                self.probes[probe_id] = probe;
+                valid_probes[probe_id] = true;
+            }
+        }
+    }
+
+    // Half floats are precise enough for irradiance and take half the memory
+    fn into_disk_volume(self, full_precision: bool) -> DiskIrradianceVolume {
+        let [count_x, count_y, count_z] = self.probe_counts;
+        let (format, component_size) = if full_precision {
+            (vk::Format::R32G32B32A32_SFLOAT, std::mem::size_of::<f32>())
+        } else {
+            (vk::Format::R16G16B16A16_SFLOAT, std::mem::size_of::<u16>())
+        };
+        let texel_size = 4 * component_size;
+        let mut pixels = vec![0u8; (count_x * count_y * count_z) as usize * 6 * texel_size];
+        for z in 0..count_z {
+            for y in 0..count_y {
+                for face in 0..6 {
+                    for x in 0..count_x {
+                        let probe = &self.probes[self.get_probe_index(x, y, z)];
+                        let texel_x = face * count_x + x;
+                        let offset = (((z * count_y + y) * count_x * 6 + texel_x) as usize) * texel_size;
+
+                        let value = probe[face as usize];
+                        let texel = [value.x, value.y, value.z, 1.0];
+                        for (component_id, component) in texel.iter().enumerate() {
+                            let component_offset = offset + component_id * component_size;
+                            let component_pixels = &mut pixels[component_offset..component_offset + component_size];
+                            if full_precision {
+                                component_pixels.copy_from_slice(&component.to_le_bytes());
+                            } else {
+                                component_pixels.copy_from_slice(&f32_to_f16(*component).to_le_bytes());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        DiskIrradianceVolume {
+            volume_min: [self.volume_min.x, self.volume_min.y, self.volume_min.z],
+            volume_max: [self.volume_max.x, self.volume_max.y, self.volume_max.z],
+            probe_counts: self.probe_counts,
+            ambient_cube_image: DiskImage {
+                width: count_x * 6,
+                height: count_y,
+                depth: count_z,
+                block_size: texel_size,
+                mipmap_count: 1,
+                layer_count: 1,
+                image_type: vk::ImageType::TYPE_3D.as_raw(),
+                view_type: vk::ImageViewType::TYPE_3D.as_raw(),
+                format: format.as_raw(),
+                color_space: DiskColorSpace::Linear,
+                generate_mipmaps: false,
+                pixels,
+            },
+        }
+    }
+}
+
+// Rounds to nearest, values outside of the half float range are clamped and small values become denormals
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x007f_ffff;
+    if exponent < -10 {
+        return sign;
+    }
+    if exponent <= 0 {
+        // Rounding the largest denormals up carries into the smallest normal encoding
+        let mantissa = mantissa | 0x0080_0000;
+        let shift = (14 - exponent) as u32;
+        return sign | ((mantissa >> shift) + ((mantissa >> (shift - 1)) & 1)) as u16;
+    }
+    if exponent >= 0x1f {
+        return sign | 0x7bff;
+    }
+
+    let half = (((exponent as u32) << 10) | (mantissa >> 13)) + ((mantissa >> 12) & 1);
+    sign | half.min(0x7bff) as u16
+}
+
+fn get_luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+fn evaluate_ambient_cube(probe: &AmbientCube, normal: Vec3) -> Vec3 {
+    let normal_squared = normal * normal;
+    let x = if normal.x >= 0.0 { probe[0] } else { probe[1] };
+    let y = if normal.y >= 0.0 { probe[2] } else { probe[3] };
+    let z = if normal.z >= 0.0 { probe[4] } else { probe[5] };
+    x * normal_squared.x + y * normal_squared.y + z * normal_squared.z
+}
+
+// Evenly distributed directions on the unit sphere
+fn fibonacci_sphere(sample_count: usize) -> Vec<Vec3> {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+    (0..sample_count)
+        .map(|sample_id| {
+            let y = 1.0 - 2.0 * (sample_id as f32 + 0.5) / sample_count as f32;
+            let radius = (1.0 - y * y).max(0.0).sqrt();
+            let phi = golden_angle * sample_id as f32;
+            Vec3::new(phi.cos() * radius, y, phi.sin() * radius)
+        })
+        .collect()
+}
+
+// Running sums of one probe, every pass adds an interleaved subset of the sample directions
+#[derive(Default, Clone, Serialize, Deserialize)]
+struct ProbeAccumulator {
+    radiance: AmbientCube,
+    luminance_squared: [f32; 6],
+    weights: [f32; 6],
+    weights_squared: [f32; 6],
+    backface_count: u32,
+    sample_count: u32,
+}
+
+impl ProbeAccumulator {
+    fn accumulate<'a>(
+        &mut self,
+        position: Vec3,
+        geometry: &SceneGeometry,
+        previous_bounce: Option<&IrradianceVolume>,
+        sample_directions: impl Iterator<Item = &'a Vec3>,
+    ) {
+        for direction in sample_directions {
+            let sample = match geometry.intersect(position, *direction, f32::MAX) {
+                Some(hit) => {
+                    if hit.is_backface {
+                        self.backface_count += 1;
+                    }
+                    match previous_bounce {
+                        Some(volume) => hit.albedo * volume.sample(position + *direction * hit.distance, hit.normal),
+                        None => Vec3::zero(),
+                    }
+                }
+                None => Vec3::one(),
+            };
+
+            for (face, face_direction) in FACE_DIRECTIONS.iter().enumerate() {
+                let weight = direction.dot(Vec3::from(*face_direction)).max(0.0);
+                self.radiance[face] += sample * weight;
+                self.luminance_squared[face] += get_luminance(sample) * get_luminance(sample) * weight;
+                self.weights[face] += weight;
+                self.weights_squared[face] += weight * weight;
+            }
+            self.sample_count += 1;
+        }
+    }
+
+    // Returns the cosine weighted ambient cube, its variance and whether the probe is outside of geometry
+    fn resolve(&self) -> (AmbientCube, AmbientCubeVariance, bool) {
+        // Variance of the weighted mean, shrinks with the sample count
+        let mut radiance = [Vec3::zero(); 6];
+        let mut variance = [0.0f32; 6];
+        for face in 0..6 {
+            let weight = self.weights[face].max(1e-6);
+            radiance[face] = self.radiance[face] / weight;
+            let luminance = get_luminance(radiance[face]);
+            let sample_variance = (self.luminance_squared[face] / weight - luminance * luminance).max(0.0);
+            variance[face] = sample_variance * self.weights_squared[face] / (weight * weight);
+        }
+        let is_valid = (self.backface_count as f32) < MAX_BACKFACE_RATIO * self.sample_count as f32;
+        (radiance, variance, is_valid)
+    }
+}
+
+// Bake state after the last finished pass, saved after every pass so that an interrupted bake can be resumed
+#[derive(Serialize, Deserialize)]
+struct BakeCheckpoint {
+    probe_counts: [u32; 3],
+    sample_count: usize,
+    pass_count: usize,
+    bounce: usize,
+    pass: usize,
+    previous_bounce: Option<Vec<AmbientCube>>,
+    accumulators: Vec<ProbeAccumulator>,
+}
+
+impl BakeCheckpoint {
+    fn new(probe_counts: [u32; 3], command_line: &CommandLineOptions) -> Self {
+        let probe_count = (probe_counts[0] * probe_counts[1] * probe_counts[2]) as usize;
+        Self {
+            probe_counts,
+            sample_count: command_line.sample_count,
+            pass_count: command_line.pass_count,
+            bounce: 0,
+            pass: 0,
+            previous_bounce: None,
+            accumulators: vec![Default::default(); probe_count],
+        }
+    }
+
+    // Checkpoints of a bake with different settings are ignored
+    fn load(path: &std::path::Path, probe_counts: [u32; 3], command_line: &CommandLineOptions) -> Option<Self> {
+        let file = std::fs::OpenOptions::new().read(true).open(path).ok()?;
+        let checkpoint: Self = bincode::deserialize_from(std::io::BufReader::new(file)).ok()?;
+        if checkpoint.probe_counts != probe_counts
+            || checkpoint.sample_count != command_line.sample_count
+            || checkpoint.pass_count != command_line.pass_count
+        {
+            log::warn!("checkpoint {:?} doesn't match bake settings, starting over", path);
+            return None;
+        }
+        Some(checkpoint)
+    }
+
+    // Writes to a temporary file first, so that interrupting the save doesn't lose the previous checkpoint
+    fn save(&self, path: &std::path::Path) {
+        let temp_path = path.with_extension("tmp");
+        {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(&temp_path)
+                .expect("failed to open checkpoint file");
+            bincode::serialize_into(std::io::BufWriter::new(file), self).expect("failed to serialize checkpoint");
+        }
+        std::fs::rename(&temp_path, path).expect("failed to replace checkpoint file");
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct BakeProgress {
+    bounce: usize,
+    bounce_count: usize,
+    pass: usize,
+    pass_count: usize,
+}
+
+impl BakeProgress {
+    fn get_finished_pass_count(&self) -> usize {
+        self.bounce * self.pass_count + self.pass
+    }
+
+    fn get_total_pass_count(&self) -> usize {
+        self.bounce_count * self.pass_count
+    }
+}
+
+// Continues the bake from the checkpoint, progress is reported after every pass and returning false from
+// the callback stops the bake. Returns the final volume or None if the bake was stopped.
+fn bake_irradiance_volume(
+    geometry: &SceneGeometry,
+    bounds: (Vec3, Vec3),
+    command_line: &CommandLineOptions,
+    checkpoint: &mut BakeCheckpoint,
+    on_progress: &mut dyn FnMut(&BakeProgress) -> bool,
+) -> Option<IrradianceVolume> {
+    use rayon::prelude::*;
+
+    let (bounds_min, bounds_max) = bounds;
+    let bounce_count = command_line.bounce_count + 1;
+    let sample_directions = fibonacci_sphere(checkpoint.sample_count);
+    loop {
+        let mut volume = IrradianceVolume::new(bounds_min, bounds_max, checkpoint.probe_counts);
+        let previous_bounce = checkpoint.previous_bounce.as_ref().map(|probes| IrradianceVolume {
+            probes: probes.clone(),
+            ..IrradianceVolume::new(bounds_min, bounds_max, checkpoint.probe_counts)
+        });
+
+        while checkpoint.pass < checkpoint.pass_count {
+            let progress = BakeProgress {
+                bounce: checkpoint.bounce,
+                bounce_count,
+                pass: checkpoint.pass,
+                pass_count: checkpoint.pass_count,
+            };
+            if !on_progress(&progress) {
+                return None;
+            }
+
+            let (pass, pass_count) = (checkpoint.pass, checkpoint.pass_count);
+            checkpoint
+                .accumulators
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(probe_id, accumulator)| {
+                    accumulator.accumulate(
+                        volume.get_probe_position(probe_id),
+                        geometry,
+                        previous_bounce.as_ref(),
+                        sample_directions.iter().skip(pass).step_by(pass_count),
+                    );
+                });
+            checkpoint.pass += 1;
+            if let Some(checkpoint_file) = &command_line.checkpoint_file {
+                checkpoint.save(checkpoint_file);
+            }
+        }
+
+        let resolved_probes: Vec<(AmbientCube, AmbientCubeVariance, bool)> = checkpoint
+            .accumulators
+            .iter()
+            .map(|accumulator| accumulator.resolve())
+            .collect();
+        let valid_probes: Vec<bool> = resolved_probes.iter().map(|(_, _, is_valid)| *is_valid).collect();
+        let variances: Vec<AmbientCubeVariance> = resolved_probes.iter().map(|(_, variance, _)| *variance).collect();
+        for (probe, (resolved_probe, _, _)) in volume.probes.iter_mut().zip(resolved_probes) {
+            *probe = resolved_probe;
+        }
+
+        // Denoise every bounce, so that the noise doesn't propagate into the next one
+        if command_line.denoise_strength > 0.0 {
+            volume.denoise(
+                &variances,
+                &valid_probes,
+                command_line.denoise_strength,
+                command_line.denoise_iterations,
+            );
+        }
+        volume.fill_invalid_probes(&valid_probes);
+
+        checkpoint.bounce += 1;
+        if checkpoint.bounce == bounce_count {
+            on_progress(&BakeProgress {
+                bounce: checkpoint.bounce,
+                bounce_count,
+                pass: 0,
+                pass_count: checkpoint.pass_count,
+            });
+            return Some(volume);
+        }
+
+        checkpoint.pass = 0;
+        checkpoint.previous_bounce = Some(volume.probes);
+        for accumulator in checkpoint.accumulators.iter_mut() {
+            *accumulator = Default::default();
+        }
+    }
+}
+
+fn main() {
+    if std::env::var("CARGO_MANIFEST_DIR").is_ok() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+
+    pretty_env_logger::init();
+
+    let command_line = {
+        use structopt::StructOpt;
+        CommandLineOptions::from_args()
+    };
+    assert!(
+        command_line.pass_count > 0 && command_line.pass_count <= command_line.sample_count,
+        "pass count has to be between 1 and the sample count"
+    );
+
+    let disk_bundle = {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .open(&command_line.input_file)
+            .expect("failed to open render bundle file");
+        DiskResourceBundle::deserialize_from(std::io::BufReader::new(file))
+            .expect("failed to deserialize render bundle")
+    };
+
+    let geometry = SceneGeometry::from_bundle(&disk_bundle);
+    let (bounds_min, bounds_max) = geometry.get_bounds();
+    let bounds_max = bounds_max.max_by_component(bounds_min + Vec3::broadcast(1e-3)); // flat scenes
+    let mut probe_counts = [0u32; 3];
+    let extent = bounds_max - bounds_min;
+    for (probe_count, axis_extent) in probe_counts.iter_mut().zip(extent.as_slice()) {
+        *probe_count = ((axis_extent / command_line.probe_spacing).ceil() as u32 + 1).clamp(2, MAX_PROBE_COUNT);
+    }
+    log::info!(
+        "baking {}x{}x{} probes for {} triangles, {} samples in {} passes, {} bounces, denoise strength {}",
+        probe_counts[0],
+        probe_counts[1],
+        probe_counts[2],
+        geometry.get_triangle_count(),
+        command_line.sample_count,
+        command_line.pass_count,
+        command_line.bounce_count,
+        command_line.denoise_strength,
+    );
+
+    let mut checkpoint = command_line
+        .checkpoint_file
+        .as_ref()
+        .and_then(|path| BakeCheckpoint::load(path, probe_counts, &command_line))
+        .unwrap_or_else(|| BakeCheckpoint::new(probe_counts, &command_line));
+    if checkpoint.bounce > 0 || checkpoint.pass > 0 {
+        log::info!("resuming at bounce {} pass {}", checkpoint.bounce, checkpoint.pass);
+    }
+
+    // The bake stops after the time limit, the next run with the same checkpoint file continues it
+    let start_time = std::time::Instant::now();
+    let time_limit = command_line.time_limit.map(std::time::Duration::from_secs_f32);
+    let progress_bar = indicatif::ProgressBar::new(0);
+    let volume = bake_irradiance_volume(
+        &geometry,
+        (bounds_min, bounds_max),
+        &command_line,
+        &mut checkpoint,
+        &mut |progress| {
+            progress_bar.set_length(progress.get_total_pass_count() as _);
+            progress_bar.set_position(progress.get_finished_pass_count() as _);
+            match time_limit {
+                Some(time_limit) => start_time.elapsed() < time_limit,
+                None => true,
+            }
+        },
+    );
+    progress_bar.finish_and_clear();
+
+    let volume = match volume {
+        Some(volume) => volume,
+        None => {
+            log::info!(
+                "time limit reached at bounce {} pass {}, rerun to continue",
+                checkpoint.bounce,
+                checkpoint.pass
+            );
+            return;
+        }
+    };
+
+    let disk_volume = volume.into_disk_volume(command_line.full_precision);
+    log::info!("saving irradiance volume to {:?}", &command_line.output_file);
+    {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&command_line.output_file)
+            .expect("failed to open output file");
+        disk_volume
+            .serialize_into(std::io::BufWriter::new(file), 0)
+            .expect("failed to serialize irradiance volume");
+    }
+    if let Some(checkpoint_file) = &command_line.checkpoint_file {
+        std::fs::remove_file(checkpoint_file).ok();
+    }
+}
@@ -0,0 +1,400 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+
+use ash::vk;
+use rayon::prelude::*;
+use ultraviolet as utv;
+use utv::vec::Vec3;
+
+#[allow(dead_code)] // only visibility of the rays is needed
+mod scene_geometry;
+use scene_geometry::*;
+
+// Has to match apply_sky_visibility in gltf_pbr_material.glsl
+const SKY_VISIBILITY_ATTRIBUTE: &str = "sky_visibility";
+const SKY_VISIBILITY_SIZE: usize = 4; // R8G8B8A8_UNORM
+
+#[derive(Debug, structopt::StructOpt)]
+#[structopt(
+    name = "bake_sky_occlusion",
+    about = "Per-vertex sky visibility and bent normal baking tool"
+)]
+struct CommandLineOptions {
+    #[structopt(short = "i", long = "input", parse(from_os_str))]
+    input_file: std::path::PathBuf,
+
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output_file: std::path::PathBuf,
+
+    #[structopt(short = "s", long = "sample_count", default_value = "128")]
+    sample_count: usize,
+
+    // Geometry further away doesn't occlude the sky, unlimited by default
+    #[structopt(short = "d", long = "max_distance")]
+    max_distance: Option<f32>,
+
+    // Ray origins are offset along the vertex normal to avoid hitting adjacent triangles
+    #[structopt(long = "normal_bias", default_value = "0.001")]
+    normal_bias: f32,
+}
+
+// Vertices of meshes that share the same range of a vertex buffer are baked together
+struct VertexRange {
+    vertex_buffer: usize,
+    vertex_offset: usize,
+    vertex_count: usize,
+    material: usize,
+    meshes: Vec<usize>,
+    transforms: Vec<utv::mat::Mat4>,
+}
+
+fn collect_vertex_ranges(bundle: &DiskResourceBundle) -> Vec<VertexRange> {
+    let mut ranges: Vec<VertexRange> = Vec::new();
+    for bucket in &bundle.buckets {
+        let transform_data = &bundle.buffers[bucket.instance_transform_buffer.index()].data;
+        let mut transform_id = 0;
+        for instance in &bucket.instances {
+            let mesh_id = instance.mesh.index();
+            let mesh = &bundle.meshes[mesh_id];
+            let range_id = match ranges.iter().position(|range| {
+                range.vertex_buffer == mesh.vertex_buffer.index() && range.vertex_offset == mesh.vertex_offset
+            }) {
+                Some(range_id) => range_id,
+                None => {
+                    ranges.push(VertexRange {
+                        vertex_buffer: mesh.vertex_buffer.index(),
+                        vertex_offset: mesh.vertex_offset,
+                        vertex_count: 0,
+                        material: bucket.material.index(),
+                        meshes: Vec::new(),
+                        transforms: Vec::new(),
+                    });
+                    ranges.len() - 1
+                }
+            };
+
+            let range = &mut ranges[range_id];
+            assert_eq!(
+                range.material,
+                bucket.material.index(),
+                "meshes sharing vertices have to use the same material"
+            );
+            if !range.meshes.contains(&mesh_id) {
+                let index_data = &bundle.buffers[mesh.index_buffer.1.index()].data;
+                let index_type = vk::IndexType::from_raw(mesh.index_buffer.0);
+                for index in mesh.first_index..mesh.first_index + mesh.index_count {
+                    range.vertex_count = range.vertex_count.max(read_index(index_data, index_type, index) + 1);
+                }
+                range.meshes.push(mesh_id);
+            }
+            for _ in 0..instance.total_instance_count {
+                range.transforms.push(read_transform(transform_data, transform_id));
+                transform_id += 1;
+            }
+        }
+    }
+    ranges
+}
+
+// Object space positions and normals, normals are averaged from triangles if the material doesn't have them
+fn read_range_vertices(bundle: &DiskResourceBundle, range: &VertexRange) -> Vec<(Vec3, Vec3)> {
+    let material = &bundle.materials[range.material];
+    let vertex_stride = material.vertex_stride as usize;
+    let vertex_data = &bundle.buffers[range.vertex_buffer].data;
+    let find_attribute = |semantic: DiskVertexSemantic| {
+        material.vertex_format.iter().find(|attribute| {
+            std::mem::discriminant(&attribute.attribute_semantic) == std::mem::discriminant(&semantic)
+                && attribute.attribute_format == vk::Format::R32G32B32_SFLOAT.as_raw()
+        })
+    };
+    let read_vec3 = |vertex_id: usize, attribute_offset: usize| {
+        let offset = (range.vertex_offset + vertex_id) * vertex_stride + attribute_offset;
+        Vec3::new(
+            read_f32(vertex_data, offset),
+            read_f32(vertex_data, offset + 4),
+            read_f32(vertex_data, offset + 8),
+        )
+    };
+
+    let position_offset = find_attribute(DiskVertexSemantic::Position)
+        .expect("material doesn't have vertex positions")
+        .attribute_offset;
+    let positions: Vec<Vec3> = (0..range.vertex_count)
+        .map(|vertex_id| read_vec3(vertex_id, position_offset))
+        .collect();
+
+    let normals: Vec<Vec3> = match find_attribute(DiskVertexSemantic::Normal) {
+        Some(attribute) => (0..range.vertex_count)
+            .map(|vertex_id| read_vec3(vertex_id, attribute.attribute_offset).normalized())
+            .collect(),
+        None => {
+            let mut normals = vec![Vec3::zero(); range.vertex_count];
+            for mesh_id in &range.meshes {
+                let mesh = &bundle.meshes[*mesh_id];
+                let index_data = &bundle.buffers[mesh.index_buffer.1.index()].data;
+                let index_type = vk::IndexType::from_raw(mesh.index_buffer.0);
+                for triangle_id in 0..mesh.index_count / 3 {
+                    let mut indices = [0; 3];
+                    for (corner, index) in indices.iter_mut().enumerate() {
+                        *index = read_index(index_data, index_type, mesh.first_index + triangle_id * 3 + corner);
+                    }
+                    // Area weighted
+                    let normal = (positions[indices[1]] - positions[indices[0]])
+                        .cross(positions[indices[2]] - positions[indices[0]]);
+                    for index in &indices {
+                        normals[*index] += normal;
+                    }
+                }
+            }
+            normals
+                .iter()
+                .map(|normal| {
+                    if normal.mag_sq() > 0.0 {
+                        normal.normalized()
+                    } else {
+                        Vec3::unit_y()
+                    }
+                })
+                .collect()
+        }
+    };
+
+    positions.into_iter().zip(normals).collect()
+}
+
+// Fibonacci spiral projected from the unit disk, directions are cosine distributed around +Z
+fn cosine_hemisphere(sample_count: usize) -> Vec<Vec3> {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+    (0..sample_count)
+        .map(|sample_id| {
+            let radius = ((sample_id as f32 + 0.5) / sample_count as f32).sqrt();
+            let phi = golden_angle * sample_id as f32;
+            Vec3::new(
+                phi.cos() * radius,
+                phi.sin() * radius,
+                (1.0 - radius * radius).max(0.0).sqrt(),
+            )
+        })
+        .collect()
+}
+
+// "Building an Orthonormal Basis, Revisited" by Duff et al.
+fn build_orthonormal_basis(normal: Vec3) -> (Vec3, Vec3) {
+    let sign_z = if normal.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (sign_z + normal.z);
+    let b = normal.x * normal.y * a;
+    (
+        Vec3::new(1.0 + sign_z * normal.x * normal.x * a, sign_z * b, -sign_z * normal.x),
+        Vec3::new(b, sign_z + normal.y * normal.y * a, -normal.y),
+    )
+}
+
+// Visibility and bent normals are averaged over all instances of the range,
+// bent normals are brought back to object space with the transposed instance transform.
+fn bake_vertex_range(
+    geometry: &SceneGeometry,
+    vertices: &[(Vec3, Vec3)],
+    transforms: &[utv::mat::Mat4],
+    directions: &[Vec3],
+    command_line: &CommandLineOptions,
+) -> Vec<[u8; SKY_VISIBILITY_SIZE]> {
+    let max_distance = command_line.max_distance.unwrap_or(f32::MAX);
+    vertices
+        .par_iter()
+        .map(|(position, normal)| {
+            let mut visibility = 0.0;
+            let mut bent_normal = Vec3::zero();
+            for transform in transforms {
+                let normal_transform = utv::mat::Mat3::new(
+                    transform.cols[0].truncated(),
+                    transform.cols[1].truncated(),
+                    transform.cols[2].truncated(),
+                );
+                let world_normal = {
+                    let scale_squared = Vec3::new(
+                        normal_transform.cols[0].mag_sq(),
+                        normal_transform.cols[1].mag_sq(),
+                        normal_transform.cols[2].mag_sq(),
+                    );
+                    (normal_transform * (*normal / scale_squared)).normalized()
+                };
+                let origin = transform.transform_point3(*position) + world_normal * command_line.normal_bias;
+                let (tangent, bitangent) = build_orthonormal_basis(world_normal);
+
+                let mut visible_direction_sum = Vec3::zero();
+                let mut visible_count = 0;
+                for direction in directions {
+                    let direction = tangent * direction.x + bitangent * direction.y + world_normal * direction.z;
+                    if geometry.intersect(origin, direction, max_distance).is_none() {
+                        visible_direction_sum += direction;
+                        visible_count += 1;
+                    }
+                }
+                visibility += visible_count as f32 / directions.len() as f32;
+                bent_normal += normal_transform.transposed() * visible_direction_sum;
+            }
+
+            let visibility = visibility / transforms.len() as f32;
+            let bent_normal = if bent_normal.mag_sq() > 1e-8 {
+                bent_normal.normalized()
+            } else {
+                *normal
+            };
+            let encode = |value: f32| (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+            [
+                encode(bent_normal.x * 0.5 + 0.5),
+                encode(bent_normal.y * 0.5 + 0.5),
+                encode(bent_normal.z * 0.5 + 0.5),
+                encode(visibility),
+            ]
+        })
+        .collect()
+}
+
+// Existing sky visibility attributes are overwritten, otherwise every vertex grows by one attribute.
+// Ranges keep their order in the buffer and are realigned to the new stride, mesh vertex offsets are updated.
+fn write_sky_visibility(
+    bundle: &mut DiskResourceBundle,
+    ranges: &[VertexRange],
+    baked_ranges: &[Vec<[u8; SKY_VISIBILITY_SIZE]>],
+) {
+    let mut material_layouts = Vec::with_capacity(bundle.materials.len());
+    for material in &bundle.materials {
+        let old_stride = material.vertex_stride as usize;
+        material_layouts.push(
+            match material
+                .vertex_format
+                .iter()
+                .find(|attribute| attribute.attribute_name == SKY_VISIBILITY_ATTRIBUTE)
+            {
+                Some(attribute) => (old_stride, old_stride, attribute.attribute_offset),
+                None => (old_stride, old_stride + SKY_VISIBILITY_SIZE, old_stride),
+            },
+        );
+    }
+
+    let mut vertex_buffers: Vec<usize> = ranges.iter().map(|range| range.vertex_buffer).collect();
+    vertex_buffers.sort_unstable();
+    vertex_buffers.dedup();
+    for buffer_id in vertex_buffers {
+        let mut buffer_ranges: Vec<usize> = (0..ranges.len())
+            .filter(|range_id| ranges[*range_id].vertex_buffer == buffer_id)
+            .collect();
+        buffer_ranges.sort_by_key(|range_id| {
+            let range = &ranges[*range_id];
+            range.vertex_offset * material_layouts[range.material].0
+        });
+
+        let old_data = std::mem::take(&mut bundle.buffers[buffer_id].data);
+        let mut new_data = Vec::with_capacity(old_data.len() + old_data.len() / 4);
+        let mut new_strides = Vec::new();
+        for range_id in buffer_ranges {
+            let range = &ranges[range_id];
+            let (old_stride, new_stride, attribute_offset) = material_layouts[range.material];
+            let new_offset = new_data.len().div_ceil(new_stride) * new_stride;
+            new_data.resize(new_offset, 0);
+            for (vertex_id, sky_visibility) in baked_ranges[range_id].iter().enumerate() {
+                let old_offset = (range.vertex_offset + vertex_id) * old_stride;
+                let vertex_start = new_data.len();
+                new_data.extend_from_slice(&old_data[old_offset..old_offset + old_stride]);
+                new_data.resize(vertex_start + new_stride, 0);
+                new_data[vertex_start + attribute_offset..vertex_start + attribute_offset + SKY_VISIBILITY_SIZE]
+                    .copy_from_slice(sky_visibility);
+            }
+            for mesh_id in &range.meshes {
+                bundle.meshes[*mesh_id].vertex_offset = new_offset / new_stride;
+            }
+            new_strides.push(new_stride);
+        }
+
+        // Packed buffers with mixed strides are addressed in bytes
+        new_strides.dedup();
+        let buffer = &mut bundle.buffers[buffer_id];
+        buffer.stride = if new_strides.len() == 1 { new_strides[0] as _ } else { 1 };
+        buffer.data = new_data;
+    }
+
+    for (material_id, material) in bundle.materials.iter_mut().enumerate() {
+        let (old_stride, new_stride, _) = material_layouts[material_id];
+        if new_stride == old_stride || !ranges.iter().any(|range| range.material == material_id) {
+            continue;
+        }
+        let attribute_location = material
+            .vertex_format
+            .iter()
+            .map(|attribute| attribute.attribute_location + 1)
+            .max()
+            .unwrap_or(0);
+        material.vertex_format.push(DiskVertexAttribute {
+            attribute_name: SKY_VISIBILITY_ATTRIBUTE.to_string(),
+            attribute_semantic: DiskVertexSemantic::SkyVisibility,
+            attribute_format: vk::Format::R8G8B8A8_UNORM.as_raw(),
+            attribute_location,
+            attribute_offset: old_stride,
+        });
+        material.vertex_stride = new_stride as u64;
+    }
+}
+
+fn main() {
+    if std::env::var("CARGO_MANIFEST_DIR").is_ok() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+
+    pretty_env_logger::init();
+
+    let command_line = {
+        use structopt::StructOpt;
+        CommandLineOptions::from_args()
+    };
+    assert!(command_line.sample_count > 0, "sample count has to be positive");
+
+    let mut disk_bundle = {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .open(&command_line.input_file)
+            .expect("failed to open render bundle file");
+        DiskResourceBundle::deserialize_from(std::io::BufReader::new(file))
+            .expect("failed to deserialize render bundle")
+    };
+
+    let geometry = SceneGeometry::from_bundle(&disk_bundle);
+    let ranges = collect_vertex_ranges(&disk_bundle);
+    let total_vertex_count: usize = ranges.iter().map(|range| range.vertex_count).sum();
+    log::info!(
+        "baking sky occlusion of {} vertices against {} triangles, {} samples",
+        total_vertex_count,
+        geometry.get_triangle_count(),
+        command_line.sample_count,
+    );
+
+    let directions = cosine_hemisphere(command_line.sample_count);
+    let progress_bar = indicatif::ProgressBar::new(total_vertex_count as _);
+    let baked_ranges: Vec<Vec<[u8; SKY_VISIBILITY_SIZE]>> = ranges
+        .iter()
+        .map(|range| {
+            let vertices = read_range_vertices(&disk_bundle, range);
+            let baked = bake_vertex_range(&geometry, &vertices, &range.transforms, &directions, &command_line);
+            progress_bar.inc(range.vertex_count as _);
+            baked
+        })
+        .collect();
+    progress_bar.finish_and_clear();
+
+    write_sky_visibility(&mut disk_bundle, &ranges, &baked_ranges);
+
+    log::info!("saving render bundle to {:?}", &command_line.output_file);
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&command_line.output_file)
+        .expect("failed to open output file");
+    disk_bundle
+        .serialize_into(std::io::BufWriter::new(file), 0)
+        .expect("failed to serialize render bundle");
+}
@@ -0,0 +1,281 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use ash::vk;
+use malwerks_bundles::*;
+use serde::Serialize;
+
+#[derive(Debug, structopt::StructOpt)]
+#[structopt(name = "bundle_info", about = "Prints a summary of the given resource bundle")]
+struct CommandLineOptions {
+    #[structopt(short = "i", long = "input", parse(from_os_str))]
+    input_file: std::path::PathBuf,
+
+    #[structopt(long = "json")]
+    json: bool,
+
+    #[structopt(short = "n", long = "largest", default_value = "10")]
+    largest_count: usize,
+}
+
+// Stored sizes include encoding and lz4 compression, same as in the bundle file
+#[derive(Serialize, Default)]
+struct ResourceSummary {
+    count: usize,
+    size: u64,
+    stored_size: u64,
+    compression_ratio: f64,
+}
+
+#[derive(Serialize)]
+struct BufferInfo {
+    buffer_id: usize,
+    usage: String,
+    encoding: String,
+    stride: u64,
+    size: u64,
+    stored_size: u64,
+}
+
+#[derive(Serialize)]
+struct ImageInfo {
+    image_id: usize,
+    width: u32,
+    height: u32,
+    depth: u32,
+    format: String,
+    mipmap_count: usize,
+    layer_count: usize,
+    generate_mipmaps: bool,
+    size: u64,
+    stored_size: u64,
+}
+
+#[derive(Serialize)]
+struct LargestResource {
+    resource: String,
+    size: u64,
+    stored_size: u64,
+}
+
+#[derive(Serialize)]
+struct BundleInfo {
+    file_size: u64,
+    buffers: ResourceSummary,
+    images: ResourceSummary,
+    mesh_count: usize,
+    index_count: usize,
+    sampler_count: usize,
+    material_layout_count: usize,
+    material_count: usize,
+    material_instance_count: usize,
+    material_animation_count: usize,
+    bucket_count: usize,
+    instance_count: usize,
+    draw_count: usize,
+    scene_node_count: usize,
+    collision_count: usize,
+    buffer_infos: Vec<BufferInfo>,
+    image_infos: Vec<ImageInfo>,
+    largest_resources: Vec<LargestResource>,
+}
+
+fn summarize<I>(sizes: I) -> ResourceSummary
+where
+    I: Iterator<Item = (u64, u64)>,
+{
+    let mut summary = sizes.fold(ResourceSummary::default(), |mut summary, (size, stored_size)| {
+        summary.count += 1;
+        summary.size += size;
+        summary.stored_size += stored_size;
+        summary
+    });
+    summary.compression_ratio = compression_ratio(summary.size, summary.stored_size);
+    summary
+}
+
+fn compression_ratio(size: u64, stored_size: u64) -> f64 {
+    if stored_size > 0 {
+        size as f64 / stored_size as f64
+    } else {
+        1.0
+    }
+}
+
+fn stored_size<T: Serialize>(resource: &T) -> u64 {
+    bincode::serialized_size(resource).expect("failed to measure resource")
+}
+
+fn collect_bundle_info(bundle: &DiskResourceBundle, file_size: u64, largest_count: usize) -> BundleInfo {
+    let buffer_infos: Vec<BufferInfo> = bundle
+        .buffers
+        .iter()
+        .enumerate()
+        .map(|(buffer_id, buffer)| BufferInfo {
+            buffer_id,
+            usage: format!("{:?}", vk::BufferUsageFlags::from_raw(buffer.usage_flags)),
+            encoding: format!("{:?}", buffer.encoding),
+            stride: buffer.stride,
+            size: buffer.data.len() as _,
+            stored_size: stored_size(buffer),
+        })
+        .collect();
+    let image_infos: Vec<ImageInfo> = bundle
+        .images
+        .iter()
+        .enumerate()
+        .map(|(image_id, image)| ImageInfo {
+            image_id,
+            width: image.width,
+            height: image.height,
+            depth: image.depth,
+            format: format!("{:?}", vk::Format::from_raw(image.format)),
+            mipmap_count: image.mipmap_count,
+            layer_count: image.layer_count,
+            generate_mipmaps: image.generate_mipmaps,
+            size: image.pixels.len() as _,
+            stored_size: stored_size(image),
+        })
+        .collect();
+
+    let mut largest_resources: Vec<LargestResource> = buffer_infos
+        .iter()
+        .map(|buffer_info| LargestResource {
+            resource: format!("buffer {}", buffer_info.buffer_id),
+            size: buffer_info.size,
+            stored_size: buffer_info.stored_size,
+        })
+        .chain(image_infos.iter().map(|image_info| LargestResource {
+            resource: format!("image {}", image_info.image_id),
+            size: image_info.size,
+            stored_size: image_info.stored_size,
+        }))
+        .collect();
+    largest_resources.sort_by_key(|resource| std::cmp::Reverse(resource.stored_size));
+    largest_resources.truncate(largest_count);
+
+    let instances = bundle.buckets.iter().flat_map(|bucket| bucket.instances.iter());
+    BundleInfo {
+        file_size,
+        buffers: summarize(buffer_infos.iter().map(|info| (info.size, info.stored_size))),
+        images: summarize(image_infos.iter().map(|info| (info.size, info.stored_size))),
+        mesh_count: bundle.meshes.len(),
+        index_count: bundle.meshes.iter().map(|mesh| mesh.index_count).sum(),
+        sampler_count: bundle.samplers.len(),
+        material_layout_count: bundle.material_layouts.len(),
+        material_count: bundle.materials.len(),
+        material_instance_count: bundle.material_instances.len(),
+        material_animation_count: bundle.material_animations.len(),
+        bucket_count: bundle.buckets.len(),
+        instance_count: instances.clone().map(|instance| instance.total_instance_count).sum(),
+        draw_count: instances.map(|instance| instance.total_draw_count).sum(),
+        scene_node_count: bundle.scene_nodes.len(),
+        collision_count: bundle.collision.len(),
+        buffer_infos,
+        image_infos,
+        largest_resources,
+    }
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn print_summary(name: &str, summary: &ResourceSummary) {
+    println!(
+        "{}: {}, {} in memory, {} stored, {:.2}x compression",
+        name,
+        summary.count,
+        format_size(summary.size),
+        format_size(summary.stored_size),
+        summary.compression_ratio
+    );
+}
+
+fn print_bundle_info(info: &BundleInfo) {
+    println!("file size: {}", format_size(info.file_size));
+    print_summary("buffers", &info.buffers);
+    print_summary("images", &info.images);
+    println!("meshes: {}, {} indices", info.mesh_count, info.index_count);
+    println!(
+        "materials: {}, {} instances, {} layouts, {} samplers, {} animations",
+        info.material_count,
+        info.material_instance_count,
+        info.material_layout_count,
+        info.sampler_count,
+        info.material_animation_count
+    );
+    println!(
+        "buckets: {}, {} instances, {} draws",
+        info.bucket_count, info.instance_count, info.draw_count
+    );
+    println!(
+        "scene nodes: {}, collision shapes: {}",
+        info.scene_node_count, info.collision_count
+    );
+
+    println!();
+    println!("images:");
+    for image_info in &info.image_infos {
+        println!(
+            "  {:4}: {}x{}x{} {} mips: {}{} layers: {} {} ({:.2}x)",
+            image_info.image_id,
+            image_info.width,
+            image_info.height,
+            image_info.depth,
+            image_info.format,
+            image_info.mipmap_count,
+            if image_info.generate_mipmaps {
+                " (generated)"
+            } else {
+                ""
+            },
+            image_info.layer_count,
+            format_size(image_info.size),
+            compression_ratio(image_info.size, image_info.stored_size)
+        );
+    }
+
+    println!();
+    println!("largest resources:");
+    for resource in &info.largest_resources {
+        println!(
+            "  {:12} {} stored, {} in memory",
+            resource.resource,
+            format_size(resource.stored_size),
+            format_size(resource.size)
+        );
+    }
+}
+
+fn main() {
+    let command_line = {
+        use structopt::StructOpt;
+        CommandLineOptions::from_args()
+    };
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .open(&command_line.input_file)
+        .expect("failed to open render bundle file");
+    let file_size = file.metadata().expect("failed to query render bundle file size").len();
+    let disk_bundle = DiskResourceBundle::deserialize_from(std::io::BufReader::new(file))
+        .expect("failed to deserialize render bundle");
+
+    let bundle_info = collect_bundle_info(&disk_bundle, file_size, command_line.largest_count);
+    if command_line.json {
+        serde_json::to_writer_pretty(std::io::stdout(), &bundle_info).expect("failed to write bundle info");
+        println!();
+    } else {
+        print_bundle_info(&bundle_info);
+    }
+}
@@ -0,0 +1,247 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+
+use rayon::prelude::*;
+use ultraviolet::vec::Vec3;
+
+#[allow(dead_code)] // hit shading isn't needed for placement
+mod scene_geometry;
+use scene_geometry::*;
+
+// Candidates that see too many backfaces are inside geometry
+const MAX_BACKFACE_RATIO: f32 = 0.25;
+
+// Directions within this cone of a box axis are used to find the wall on that side
+const MIN_AXIS_COSINE: f32 = 0.8;
+
+const SAMPLE_COUNT: usize = 256;
+
+#[derive(Debug, structopt::StructOpt)]
+#[structopt(name = "place_environment_probes")]
+struct CommandLineOptions {
+    #[structopt(short = "i", long = "input", parse(from_os_str))]
+    input_file: std::path::PathBuf,
+
+    // Written in the local_probes.json format that the bundle loader reads
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output_file: std::path::PathBuf,
+
+    #[structopt(short = "p", long = "probe_spacing", default_value = "2.0")]
+    probe_spacing: f32,
+
+    // Renderer uses up to 8 local probes
+    #[structopt(short = "m", long = "max_probe_count", default_value = "8")]
+    max_probe_count: usize,
+
+    #[structopt(short = "b", long = "blend_distance", default_value = "0.5")]
+    blend_distance: f32,
+}
+
+#[derive(serde::Serialize)]
+struct LocalProbeDescription {
+    name: String,
+    position: [f32; 3],
+    box_min: [f32; 3],
+    box_max: [f32; 3],
+    blend_distance: f32,
+}
+
+struct ProbeCandidate {
+    position: Vec3,
+    box_min: Vec3,
+    box_max: Vec3,
+}
+
+impl ProbeCandidate {
+    fn get_volume(&self) -> f32 {
+        let extent = self.box_max - self.box_min;
+        extent.x * extent.y * extent.z
+    }
+
+    fn contains(&self, position: Vec3) -> bool {
+        position.x >= self.box_min.x
+            && position.y >= self.box_min.y
+            && position.z >= self.box_min.z
+            && position.x <= self.box_max.x
+            && position.y <= self.box_max.y
+            && position.z <= self.box_max.z
+    }
+}
+
+// Evenly distributed directions on the unit sphere
+fn fibonacci_sphere(sample_count: usize) -> Vec<Vec3> {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+    (0..sample_count)
+        .map(|sample_id| {
+            let y = 1.0 - 2.0 * (sample_id as f32 + 0.5) / sample_count as f32;
+            let radius = (1.0 - y * y).max(0.0).sqrt();
+            let phi = golden_angle * sample_id as f32;
+            Vec3::new(phi.cos() * radius, y, phi.sin() * radius)
+        })
+        .collect()
+}
+
+// Box faces are placed at the median wall distance around each axis, so doorways and clutter don't move them.
+// Candidates that are inside geometry or too close to it are rejected.
+fn analyze_candidate(
+    geometry: &SceneGeometry,
+    bounds: (Vec3, Vec3),
+    position: Vec3,
+    directions: &[Vec3],
+    min_clearance: f32,
+) -> Option<ProbeCandidate> {
+    let (bounds_min, bounds_max) = bounds;
+    let max_distance = (bounds_max - bounds_min).mag();
+
+    let mut backface_count = 0;
+    let mut axis_distances: [Vec<f32>; 6] = Default::default();
+    for direction in directions {
+        let distance = match geometry.intersect(position, *direction, max_distance) {
+            Some(hit) => {
+                if hit.is_backface {
+                    backface_count += 1;
+                }
+                if hit.distance < min_clearance {
+                    return None;
+                }
+                hit.distance
+            }
+            None => max_distance,
+        };
+
+        for (axis, component) in direction.as_slice().iter().enumerate() {
+            if component.abs() >= MIN_AXIS_COSINE {
+                let side = axis * 2 + (*component < 0.0) as usize;
+                axis_distances[side].push(distance * component.abs());
+            }
+        }
+    }
+    if backface_count as f32 >= MAX_BACKFACE_RATIO * directions.len() as f32 {
+        return None;
+    }
+
+    let mut box_min = position;
+    let mut box_max = position;
+    for (side, distances) in axis_distances.iter_mut().enumerate() {
+        if distances.is_empty() {
+            continue;
+        }
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let distance = distances[distances.len() / 2];
+        let axis = side / 2;
+        if side % 2 == 0 {
+            box_max[axis] = (position[axis] + distance).min(bounds_max[axis]);
+        } else {
+            box_min[axis] = (position[axis] - distance).max(bounds_min[axis]);
+        }
+    }
+
+    Some(ProbeCandidate {
+        position,
+        box_min,
+        box_max,
+    })
+}
+
+// Candidates are placed on a grid and the ones with the largest boxes win, every accepted probe claims
+// the candidates inside its box, which usually leaves one probe per room
+fn place_environment_probes(geometry: &SceneGeometry, command_line: &CommandLineOptions) -> Vec<ProbeCandidate> {
+    let (bounds_min, bounds_max) = geometry.get_bounds();
+    let extent = bounds_max - bounds_min;
+    let mut candidate_counts = [0usize; 3];
+    for (candidate_count, axis_extent) in candidate_counts.iter_mut().zip(extent.as_slice()) {
+        *candidate_count = ((axis_extent / command_line.probe_spacing).floor() as usize).max(1);
+    }
+    let cell_size = Vec3::new(
+        extent.x / candidate_counts[0] as f32,
+        extent.y / candidate_counts[1] as f32,
+        extent.z / candidate_counts[2] as f32,
+    );
+    log::info!(
+        "testing {}x{}x{} probe candidates against {} triangles",
+        candidate_counts[0],
+        candidate_counts[1],
+        candidate_counts[2],
+        geometry.get_triangle_count()
+    );
+
+    let directions = fibonacci_sphere(SAMPLE_COUNT);
+    let min_clearance = 0.25 * command_line.probe_spacing;
+    let candidate_count = candidate_counts.iter().product::<usize>();
+    let mut candidates: Vec<ProbeCandidate> = (0..candidate_count)
+        .into_par_iter()
+        .filter_map(|candidate_id| {
+            let x = candidate_id % candidate_counts[0];
+            let y = (candidate_id / candidate_counts[0]) % candidate_counts[1];
+            let z = candidate_id / (candidate_counts[0] * candidate_counts[1]);
+            let position = bounds_min + cell_size * Vec3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5);
+            analyze_candidate(geometry, (bounds_min, bounds_max), position, &directions, min_clearance)
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.get_volume().partial_cmp(&a.get_volume()).unwrap());
+
+    let mut probes: Vec<ProbeCandidate> = Vec::new();
+    for candidate in candidates {
+        if probes.len() == command_line.max_probe_count {
+            break;
+        }
+        if probes.iter().all(|probe| !probe.contains(candidate.position)) {
+            probes.push(candidate);
+        }
+    }
+    probes
+}
+
+fn main() {
+    if std::env::var("CARGO_MANIFEST_DIR").is_ok() {
+        std::env::set_var("RUST_LOG", "info");
+    }
+
+    pretty_env_logger::init();
+
+    let command_line = {
+        use structopt::StructOpt;
+        CommandLineOptions::from_args()
+    };
+    assert!(command_line.probe_spacing > 0.0, "probe spacing has to be positive");
+
+    let disk_bundle = {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .open(&command_line.input_file)
+            .expect("failed to open render bundle file");
+        DiskResourceBundle::deserialize_from(std::io::BufReader::new(file))
+            .expect("failed to deserialize render bundle")
+    };
+
+    let geometry = SceneGeometry::from_bundle(&disk_bundle);
+    let probes = place_environment_probes(&geometry, &command_line);
+    log::info!("placed {} probes", probes.len());
+
+    // Every probe expects its images in a folder with the same name next to local_probes.json
+    let descriptions: Vec<LocalProbeDescription> = probes
+        .iter()
+        .enumerate()
+        .map(|(probe_id, probe)| LocalProbeDescription {
+            name: format!("probe_{}", probe_id),
+            position: *probe.position.as_array(),
+            box_min: *probe.box_min.as_array(),
+            box_max: *probe.box_max.as_array(),
+            blend_distance: command_line.blend_distance,
+        })
+        .collect();
+
+    log::info!("saving probe descriptions to {:?}", &command_line.output_file);
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&command_line.output_file)
+        .expect("failed to open output file");
+    serde_json::to_writer_pretty(std::io::BufWriter::new(file), &descriptions)
+        .expect("failed to write probe descriptions");
+}
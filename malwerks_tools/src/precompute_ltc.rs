@@ -0,0 +1,334 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Fits linearly transformed cosines to GGX, based on
+// "Real-Time Polygonal-Light Shading with Linearly Transformed Cosines" by Heitz et al.
+
+use malwerks_bundles::*;
+
+use ash::vk;
+use ultraviolet::mat::*;
+use ultraviolet::vec::*;
+
+const TABLE_SIZE: usize = 64;
+const SAMPLE_COUNT: usize = 32;
+const MIN_ALPHA: f32 = 0.00001;
+
+const FIT_MAX_ITERATIONS: usize = 200;
+const FIT_TOLERANCE: f32 = 1e-5; // relative to the smallest error
+const FIT_DELTA: f32 = 0.05;
+
+// Height correlated Smith GGX, returns the BRDF multiplied by cosine and the pdf of sampling it
+fn ggx_evaluate(view: Vec3, light: Vec3, alpha: f32) -> (f32, f32) {
+    if view.z <= 0.0 || light.z <= 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let lambda = |direction: Vec3| {
+        if direction.z >= 1.0 {
+            return 0.0;
+        }
+        let tan_theta = (1.0 - direction.z * direction.z).sqrt() / direction.z;
+        let a = 1.0 / (alpha * tan_theta);
+        0.5 * (-1.0 + (1.0 + 1.0 / (a * a)).sqrt())
+    };
+
+    let half_vector = (view + light).normalized();
+    let slope_x = half_vector.x / half_vector.z;
+    let slope_y = half_vector.y / half_vector.z;
+    let distribution = 1.0 / (1.0 + (slope_x * slope_x + slope_y * slope_y) / (alpha * alpha));
+    let distribution = distribution * distribution / (std::f32::consts::PI * alpha * alpha * half_vector.z.powi(4));
+
+    let pdf = (distribution * half_vector.z / (4.0 * view.dot(half_vector))).abs();
+    let geometry = 1.0 / (1.0 + lambda(view) + lambda(light));
+    (distribution * geometry / (4.0 * view.z), pdf)
+}
+
+fn ggx_sample(view: Vec3, alpha: f32, u1: f32, u2: f32) -> Vec3 {
+    let phi = 2.0 * std::f32::consts::PI * u1;
+    let r = alpha * (u2 / (1.0 - u2)).sqrt();
+    let normal = Vec3::new(r * phi.cos(), r * phi.sin(), 1.0).normalized();
+    -view + normal * 2.0 * normal.dot(view)
+}
+
+// Clamped cosine distribution transformed by a matrix built from the fitted parameters
+#[derive(Copy, Clone)]
+struct Ltc {
+    m11: f32,
+    m22: f32,
+    m13: f32,
+    amplitude: f32,
+    basis: Mat3,
+    matrix: Mat3,
+    inverse_matrix: Mat3,
+    determinant: f32,
+}
+
+impl Ltc {
+    fn new(average_direction: Vec3, amplitude: f32) -> Self {
+        let basis = Mat3::new(
+            Vec3::new(average_direction.z, 0.0, -average_direction.x),
+            Vec3::new(0.0, 1.0, 0.0),
+            average_direction,
+        );
+        let mut ltc = Self {
+            m11: 1.0,
+            m22: 1.0,
+            m13: 0.0,
+            amplitude,
+            basis,
+            matrix: Mat3::identity(),
+            inverse_matrix: Mat3::identity(),
+            determinant: 1.0,
+        };
+        ltc.update([1.0, 1.0, 0.0]);
+        ltc
+    }
+
+    fn update(&mut self, parameters: [f32; 3]) {
+        self.m11 = parameters[0].abs().max(MIN_ALPHA);
+        self.m22 = parameters[1].abs().max(MIN_ALPHA);
+        self.m13 = parameters[2];
+
+        let scale = Mat3::new(
+            Vec3::new(self.m11, 0.0, 0.0),
+            Vec3::new(0.0, self.m22, 0.0),
+            Vec3::new(self.m13, 0.0, 1.0),
+        );
+        self.matrix = self.basis * scale;
+        self.inverse_matrix = self.matrix.inversed();
+        self.determinant = self.matrix.determinant().abs();
+    }
+
+    fn evaluate(&self, light: Vec3) -> f32 {
+        let original_light = (self.inverse_matrix * light).normalized();
+        let transformed_length = (self.matrix * original_light).mag();
+        let jacobian = self.determinant / transformed_length.powi(3);
+        let distribution = original_light.z.max(0.0) / std::f32::consts::PI;
+        self.amplitude * distribution / jacobian
+    }
+
+    fn sample(&self, u1: f32, u2: f32) -> Vec3 {
+        let theta = u1.sqrt().acos();
+        let phi = 2.0 * std::f32::consts::PI * u2;
+        let direction = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+        (self.matrix * direction).normalized()
+    }
+}
+
+fn sample_grid() -> impl Iterator<Item = (f32, f32)> {
+    (0..SAMPLE_COUNT * SAMPLE_COUNT).map(|sample_id| {
+        let u1 = ((sample_id % SAMPLE_COUNT) as f32 + 0.5) / SAMPLE_COUNT as f32;
+        let u2 = ((sample_id / SAMPLE_COUNT) as f32 + 0.5) / SAMPLE_COUNT as f32;
+        (u1, u2)
+    })
+}
+
+// Returns BRDF norm, its Fresnel part and the average light direction
+fn compute_average_terms(view: Vec3, alpha: f32) -> (f32, f32, Vec3) {
+    let mut norm = 0.0;
+    let mut fresnel = 0.0;
+    let mut average_direction = Vec3::zero();
+    for (u1, u2) in sample_grid() {
+        let light = ggx_sample(view, alpha, u1, u2);
+        let (value, pdf) = ggx_evaluate(view, light, alpha);
+        if pdf > 0.0 {
+            let weight = value / pdf;
+            let half_vector = (view + light).normalized();
+            norm += weight;
+            fresnel += weight * (1.0 - view.dot(half_vector).max(0.0)).powi(5);
+            average_direction += light * weight;
+        }
+    }
+
+    let sample_count = (SAMPLE_COUNT * SAMPLE_COUNT) as f32;
+    average_direction.y = 0.0;
+    (
+        norm / sample_count,
+        fresnel / sample_count,
+        average_direction.normalized(),
+    )
+}
+
+// Cubed difference of both distributions with multiple importance sampling
+fn compute_error(ltc: &Ltc, view: Vec3, alpha: f32) -> f32 {
+    let mut error = 0.0;
+    for (u1, u2) in sample_grid() {
+        for light in [ltc.sample(u1, u2), ggx_sample(view, alpha, u1, u2)].iter() {
+            let (brdf_value, brdf_pdf) = ggx_evaluate(view, *light, alpha);
+            let ltc_value = ltc.evaluate(*light);
+            let ltc_pdf = ltc_value / ltc.amplitude;
+            if brdf_pdf + ltc_pdf > 0.0 {
+                error += (brdf_value - ltc_value).abs().powi(3) / (brdf_pdf + ltc_pdf);
+            }
+        }
+    }
+    error / (SAMPLE_COUNT * SAMPLE_COUNT) as f32
+}
+
+fn nelder_mead<F>(start: [f32; 3], objective: F) -> [f32; 3]
+where
+    F: Fn([f32; 3]) -> f32,
+{
+    let mut simplex = [start; 4];
+    for (point_id, point) in simplex.iter_mut().enumerate().skip(1) {
+        point[point_id - 1] += FIT_DELTA;
+    }
+    let mut values = [0.0; 4];
+    for (value, point) in values.iter_mut().zip(simplex.iter()) {
+        *value = objective(*point);
+    }
+
+    let combine = |a: [f32; 3], b: [f32; 3], t: f32| {
+        [
+            a[0] + (b[0] - a[0]) * t,
+            a[1] + (b[1] - a[1]) * t,
+            a[2] + (b[2] - a[2]) * t,
+        ]
+    };
+    for _ in 0..FIT_MAX_ITERATIONS {
+        let mut order = [0, 1, 2, 3];
+        order.sort_by(|a, b| values[*a].partial_cmp(&values[*b]).unwrap());
+        let (best, second_worst, worst) = (order[0], order[2], order[3]);
+        if values[worst] - values[best] <= FIT_TOLERANCE * values[best].abs() {
+            break;
+        }
+
+        let mut centroid = [0.0; 3];
+        for point_id in order.iter().take(3) {
+            for axis in 0..3 {
+                centroid[axis] += simplex[*point_id][axis] / 3.0;
+            }
+        }
+
+        let reflected = combine(centroid, simplex[worst], -1.0);
+        let reflected_value = objective(reflected);
+        if reflected_value < values[best] {
+            let expanded = combine(centroid, simplex[worst], -2.0);
+            let expanded_value = objective(expanded);
+            if expanded_value < reflected_value {
+                simplex[worst] = expanded;
+                values[worst] = expanded_value;
+            } else {
+                simplex[worst] = reflected;
+                values[worst] = reflected_value;
+            }
+        } else if reflected_value < values[second_worst] {
+            simplex[worst] = reflected;
+            values[worst] = reflected_value;
+        } else {
+            let contracted = combine(centroid, simplex[worst], 0.5);
+            let contracted_value = objective(contracted);
+            if contracted_value < values[worst] {
+                simplex[worst] = contracted;
+                values[worst] = contracted_value;
+            } else {
+                for point_id in order.iter().skip(1) {
+                    simplex[*point_id] = combine(simplex[best], simplex[*point_id], 0.5);
+                    values[*point_id] = objective(simplex[*point_id]);
+                }
+            }
+        }
+    }
+
+    let best = (0..4)
+        .min_by(|a, b| values[*a].partial_cmp(&values[*b]).unwrap())
+        .unwrap();
+    simplex[best]
+}
+
+// Fits one roughness row, every angle starts from the result of the previous one
+fn fit_row(roughness_id: usize) -> Vec<([f32; 4], [f32; 4])> {
+    let roughness = roughness_id as f32 / (TABLE_SIZE - 1) as f32;
+    let alpha = (roughness * roughness).max(MIN_ALPHA);
+
+    let mut parameters = [alpha, alpha, 0.0];
+    let mut row = Vec::with_capacity(TABLE_SIZE);
+    for theta_id in 0..TABLE_SIZE {
+        let x = theta_id as f32 / (TABLE_SIZE - 1) as f32;
+        let theta = (1.0 - x * x).acos().min(1.57);
+        let view = Vec3::new(theta.sin(), 0.0, theta.cos());
+
+        let (norm, fresnel, average_direction) = compute_average_terms(view, alpha);
+        let mut ltc = Ltc::new(average_direction, norm);
+        parameters = nelder_mead(parameters, |parameters| {
+            let mut ltc = ltc;
+            ltc.update(parameters);
+            compute_error(&ltc, view, alpha)
+        });
+        ltc.update(parameters);
+
+        let inverse_matrix = ltc.inverse_matrix * (1.0 / ltc.inverse_matrix.cols[1].y);
+        let [column0, _, column2] = inverse_matrix.cols;
+        row.push(([column0.x, column0.z, column2.x, column2.z], [norm, fresnel, 0.0, 0.0]));
+    }
+    row
+}
+
+fn into_disk_image(texels: impl Iterator<Item = [f32; 4]>) -> DiskImage {
+    let texel_size = 4 * std::mem::size_of::<f32>();
+    let mut pixels = Vec::with_capacity(TABLE_SIZE * TABLE_SIZE * texel_size);
+    for texel in texels {
+        for component in texel.iter() {
+            pixels.extend_from_slice(&component.to_le_bytes());
+        }
+    }
+
+    DiskImage {
+        width: TABLE_SIZE as _,
+        height: TABLE_SIZE as _,
+        depth: 1,
+        block_size: texel_size,
+        mipmap_count: 1,
+        layer_count: 1,
+        image_type: vk::ImageType::TYPE_2D.as_raw(),
+        view_type: vk::ImageViewType::TYPE_2D.as_raw(),
+        format: vk::Format::R32G32B32A32_SFLOAT.as_raw(),
+        color_space: DiskColorSpace::Linear,
+        generate_mipmaps: false,
+        pixels,
+    }
+}
+
+fn main() {
+    use rayon::prelude::*;
+
+    pretty_env_logger::init();
+    log::info!(
+        "ltc: {}x{}, {} samples per fit",
+        TABLE_SIZE,
+        TABLE_SIZE,
+        SAMPLE_COUNT * SAMPLE_COUNT
+    );
+
+    let progress = indicatif::ProgressBar::new(TABLE_SIZE as _);
+    let rows: Vec<Vec<([f32; 4], [f32; 4])>> = (0..TABLE_SIZE)
+        .into_par_iter()
+        .map(|roughness_id| {
+            let row = fit_row(roughness_id);
+            progress.inc(1);
+            row
+        })
+        .collect();
+    progress.finish_and_clear();
+
+    // Roughness goes along X and angle along Y
+    let texel = |theta_id: usize, roughness_id: usize| rows[roughness_id][theta_id];
+    let texel_indices = || (0..TABLE_SIZE * TABLE_SIZE).map(|index| (index / TABLE_SIZE, index % TABLE_SIZE));
+    let ltc_tables = DiskLtcTables {
+        ltc_matrix_image: into_disk_image(texel_indices().map(|(y, x)| texel(y, x).0)),
+        ltc_amplitude_image: into_disk_image(texel_indices().map(|(y, x)| texel(y, x).1)),
+    };
+
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open("ltc_tables.bin")
+        .expect("failed to open output file");
+    ltc_tables
+        .serialize_into(std::io::BufWriter::new(file), 0)
+        .expect("failed to serialize ltc tables");
+}
@@ -0,0 +1,297 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use malwerks_bundles::*;
+
+use ash::vk;
+use ultraviolet as utv;
+use utv::vec::Vec3;
+
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+struct Triangle {
+    vertices: [Vec3; 3],
+    albedo: Vec3,
+}
+
+struct BvhNode {
+    bounds_min: Vec3,
+    bounds_max: Vec3,
+    first: usize, // first child node for inner nodes, first triangle for leaves
+    count: usize, // 0 for inner nodes
+}
+
+pub struct RayHit {
+    pub distance: f32,
+    pub normal: Vec3, // geometric normal facing against the ray
+    pub albedo: Vec3,
+    pub is_backface: bool,
+}
+
+// World space triangles of all bucket instances in a render bundle
+pub struct SceneGeometry {
+    triangles: Vec<Triangle>,
+    nodes: Vec<BvhNode>,
+}
+
+impl SceneGeometry {
+    pub fn from_bundle(bundle: &DiskResourceBundle) -> Self {
+        let mut triangles = Vec::new();
+        for bucket in &bundle.buckets {
+            let material = &bundle.materials[bucket.material.index()];
+            let position_offset = material
+                .vertex_format
+                .iter()
+                .find(|attribute| matches!(attribute.attribute_semantic, DiskVertexSemantic::Position))
+                .expect("material doesn't have vertex positions")
+                .attribute_offset;
+            let vertex_stride = material.vertex_stride as usize;
+
+            let transform_data = &bundle.buffers[bucket.instance_transform_buffer.index()].data;
+            let mut transform_id = 0;
+            for instance in &bucket.instances {
+                let mesh = &bundle.meshes[instance.mesh.index()];
+                let vertex_data = &bundle.buffers[mesh.vertex_buffer.index()].data;
+                let index_data = &bundle.buffers[mesh.index_buffer.1.index()].data;
+                let index_type = vk::IndexType::from_raw(mesh.index_buffer.0);
+
+                // Material instance data starts with base color factor
+                let material_data =
+                    &bundle.material_instances[instance.material_instance.index()].material_instance_data;
+                let albedo = Vec3::new(
+                    read_f32(material_data, 0),
+                    read_f32(material_data, 4),
+                    read_f32(material_data, 8),
+                );
+
+                for _ in 0..instance.total_instance_count {
+                    let transform = read_transform(transform_data, transform_id);
+                    transform_id += 1;
+
+                    let read_vertex = |index: usize| {
+                        let offset = (mesh.vertex_offset + index) * vertex_stride + position_offset;
+                        transform.transform_point3(Vec3::new(
+                            read_f32(vertex_data, offset),
+                            read_f32(vertex_data, offset + 4),
+                            read_f32(vertex_data, offset + 8),
+                        ))
+                    };
+                    for triangle_id in 0..mesh.index_count / 3 {
+                        let mut vertices = [Vec3::zero(); 3];
+                        for (corner, vertex) in vertices.iter_mut().enumerate() {
+                            *vertex = read_vertex(read_index(
+                                index_data,
+                                index_type,
+                                mesh.first_index + triangle_id * 3 + corner,
+                            ));
+                        }
+                        triangles.push(Triangle { vertices, albedo });
+                    }
+                }
+            }
+        }
+
+        let mut geometry = Self {
+            triangles,
+            nodes: Vec::new(),
+        };
+        if !geometry.triangles.is_empty() {
+            geometry.nodes.push(BvhNode {
+                bounds_min: Vec3::zero(),
+                bounds_max: Vec3::zero(),
+                first: 0,
+                count: geometry.triangles.len(),
+            });
+            geometry.subdivide(0);
+        }
+        geometry
+    }
+
+    pub fn get_triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn get_bounds(&self) -> (Vec3, Vec3) {
+        match self.nodes.first() {
+            Some(root) => (root.bounds_min, root.bounds_max),
+            None => (Vec3::zero(), Vec3::zero()),
+        }
+    }
+
+    pub fn intersect(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inverse_direction = Vec3::one() / direction;
+        let mut closest: Option<(f32, usize)> = None;
+        let mut stack = vec![0];
+        while let Some(node_id) = stack.pop() {
+            let node = &self.nodes[node_id];
+            let closest_distance = closest.map_or(max_distance, |(distance, _)| distance);
+            if !intersect_bounds(node, origin, inverse_direction, closest_distance) {
+                continue;
+            }
+
+            if node.count == 0 {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+            } else {
+                for triangle_id in node.first..node.first + node.count {
+                    if let Some(distance) = intersect_triangle(&self.triangles[triangle_id], origin, direction) {
+                        if distance < closest.map_or(max_distance, |(distance, _)| distance) {
+                            closest = Some((distance, triangle_id));
+                        }
+                    }
+                }
+            }
+        }
+
+        closest.map(|(distance, triangle_id)| {
+            let triangle = &self.triangles[triangle_id];
+            let normal = (triangle.vertices[1] - triangle.vertices[0])
+                .cross(triangle.vertices[2] - triangle.vertices[0])
+                .normalized();
+            let is_backface = normal.dot(direction) > 0.0;
+            RayHit {
+                distance,
+                normal: if is_backface { -normal } else { normal },
+                albedo: triangle.albedo,
+                is_backface,
+            }
+        })
+    }
+
+    // Median split along the longest axis of triangle centroids
+    fn subdivide(&mut self, node_id: usize) {
+        let (first, count) = (self.nodes[node_id].first, self.nodes[node_id].count);
+        let mut bounds_min = Vec3::broadcast(f32::MAX);
+        let mut bounds_max = Vec3::broadcast(f32::MIN);
+        let mut centroid_min = Vec3::broadcast(f32::MAX);
+        let mut centroid_max = Vec3::broadcast(f32::MIN);
+        for triangle in &self.triangles[first..first + count] {
+            for vertex in &triangle.vertices {
+                bounds_min = bounds_min.min_by_component(*vertex);
+                bounds_max = bounds_max.max_by_component(*vertex);
+            }
+            let centroid = get_centroid(triangle);
+            centroid_min = centroid_min.min_by_component(centroid);
+            centroid_max = centroid_max.max_by_component(centroid);
+        }
+        self.nodes[node_id].bounds_min = bounds_min;
+        self.nodes[node_id].bounds_max = bounds_max;
+        if count <= MAX_LEAF_TRIANGLES {
+            return;
+        }
+
+        let extent = centroid_max - centroid_min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        let axis_value = |triangle: &Triangle| get_centroid(triangle).as_slice()[axis];
+        self.triangles[first..first + count]
+            .sort_unstable_by(|a, b| axis_value(a).partial_cmp(&axis_value(b)).unwrap());
+
+        let half_count = count / 2;
+        let child_id = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bounds_min: Vec3::zero(),
+            bounds_max: Vec3::zero(),
+            first,
+            count: half_count,
+        });
+        self.nodes.push(BvhNode {
+            bounds_min: Vec3::zero(),
+            bounds_max: Vec3::zero(),
+            first: first + half_count,
+            count: count - half_count,
+        });
+        self.nodes[node_id].first = child_id;
+        self.nodes[node_id].count = 0;
+
+        self.subdivide(child_id);
+        self.subdivide(child_id + 1);
+    }
+}
+
+fn get_centroid(triangle: &Triangle) -> Vec3 {
+    (triangle.vertices[0] + triangle.vertices[1] + triangle.vertices[2]) / 3.0
+}
+
+fn intersect_bounds(node: &BvhNode, origin: Vec3, inverse_direction: Vec3, max_distance: f32) -> bool {
+    let t0 = (node.bounds_min - origin) * inverse_direction;
+    let t1 = (node.bounds_max - origin) * inverse_direction;
+    let near = t0.min_by_component(t1).component_max().max(0.0);
+    let far = t0.max_by_component(t1).component_min().min(max_distance);
+    near <= far
+}
+
+// Moller-Trumbore, both sides of the triangle are hit
+fn intersect_triangle(triangle: &Triangle, origin: Vec3, direction: Vec3) -> Option<f32> {
+    let edge0 = triangle.vertices[1] - triangle.vertices[0];
+    let edge1 = triangle.vertices[2] - triangle.vertices[0];
+    let p = direction.cross(edge1);
+    let determinant = edge0.dot(p);
+    if determinant.abs() < 1e-8 {
+        return None;
+    }
+
+    let inverse_determinant = 1.0 / determinant;
+    let s = origin - triangle.vertices[0];
+    let u = s.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(edge0);
+    let v = direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = edge1.dot(q) * inverse_determinant;
+    if distance > 1e-4 {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+pub fn read_f32(data: &[u8], offset: usize) -> f32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    f32::from_le_bytes(bytes)
+}
+
+pub fn read_index(data: &[u8], index_type: vk::IndexType, index: usize) -> usize {
+    match index_type {
+        vk::IndexType::UINT16 => u16::from_le_bytes([data[index * 2], data[index * 2 + 1]]) as usize,
+        vk::IndexType::UINT32 => {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&data[index * 4..index * 4 + 4]);
+            u32::from_le_bytes(bytes) as usize
+        }
+        _ => panic!("unsupported index type: {:?}", index_type),
+    }
+}
+
+// Instance transforms are column major
+pub fn read_transform(data: &[u8], transform_id: usize) -> utv::mat::Mat4 {
+    let offset = transform_id * std::mem::size_of::<[f32; 16]>();
+    let column = |column_id: usize| {
+        let column_offset = offset + column_id * 16;
+        utv::vec::Vec4::new(
+            read_f32(data, column_offset),
+            read_f32(data, column_offset + 4),
+            read_f32(data, column_offset + 8),
+            read_f32(data, column_offset + 12),
+        )
+    };
+    utv::mat::Mat4::new(column(0), column(1), column(2), column(3))
+}
@@ -3,6 +3,8 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
 // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use crate::command_buffer_validation::*;
+use crate::dynamic_rendering::*;
 use crate::internal::*;
 
 use ash::vk;
@@ -33,6 +35,7 @@ impl From<CommandBuffer> for vk::CommandBuffer {
 impl CommandBuffer {
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkResetCommandBuffer.html"]
     pub fn reset(&mut self) {
+        track_reset(&[self.0]);
         unsafe {
             let error_code = ash_static().fp_10.reset_command_buffer(self.0, std::mem::transmute(0));
             match error_code {
@@ -44,6 +47,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkResetCommandBuffer.html"]
     pub fn reset_with_flags(&mut self, flags: vk::CommandBufferResetFlags) {
+        track_reset(&[self.0]);
         unsafe {
             let error_code = ash_static().fp_10.reset_command_buffer(self.0, flags);
             match error_code {
@@ -55,6 +59,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkBeginCommandBuffer.html"]
     pub fn begin(&mut self, begin_info: &vk::CommandBufferBeginInfo) {
+        track_begin(self.0, begin_info.flags);
         unsafe {
             let error_code = ash_static().fp_10.begin_command_buffer(self.0, begin_info);
             match error_code {
@@ -66,6 +71,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkEndCommandBuffer.html"]
     pub fn end(&mut self) {
+        track_end(self.0);
         unsafe {
             let error_code = ash_static().fp_10.end_command_buffer(self.0);
             match error_code {
@@ -126,6 +132,7 @@ impl CommandBuffer {
         clear_color_value: &vk::ClearColorValue,
         ranges: &[vk::ImageSubresourceRange],
     ) {
+        validate_outside_render_pass(self.0, "clear_color_image()");
         unsafe {
             ash_static().fp_10.cmd_clear_color_image(
                 self.0,
@@ -146,6 +153,7 @@ impl CommandBuffer {
         clear_depth_stencil_value: vk::ClearDepthStencilValue,
         ranges: &[vk::ImageSubresourceRange],
     ) {
+        validate_outside_render_pass(self.0, "clear_depth_stencil_image()");
         unsafe {
             ash_static().fp_10.cmd_clear_depth_stencil_image(
                 self.0,
@@ -160,6 +168,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdClearAttachments.html"]
     pub fn clear_attachments(&mut self, attachments: &[vk::ClearAttachment], rects: &[vk::ClearRect]) {
+        validate_inside_render_pass(self.0, "clear_attachments()");
         unsafe {
             ash_static().fp_10.cmd_clear_attachments(
                 self.0,
@@ -180,6 +189,7 @@ impl CommandBuffer {
         vertex_offset: i32,
         first_instance: u32,
     ) {
+        validate_draw(self.0, "draw_indexed()");
         unsafe {
             ash_static().fp_10.cmd_draw_indexed(
                 self.0,
@@ -194,6 +204,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdDrawIndexedIndirect.html"]
     pub fn draw_indexed_indirect(&mut self, buffer: vk::Buffer, offset: vk::DeviceSize, draw_count: u32, stride: u32) {
+        validate_draw(self.0, "draw_indexed_indirect()");
         unsafe {
             ash_static()
                 .fp_10
@@ -269,6 +280,13 @@ impl CommandBuffer {
         offset: u32,
         constants: &[T],
     ) {
+        validate_push_constants(
+            self.0,
+            layout,
+            stage_flags,
+            offset,
+            std::mem::size_of_val(constants) as _,
+        );
         unsafe {
             ash_static().fp_10.cmd_push_constants(
                 self.0,
@@ -283,6 +301,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdBeginRenderPass.html"]
     pub fn begin_render_pass(&mut self, begin_info: &vk::RenderPassBeginInfo, contents: vk::SubpassContents) {
+        track_begin_render_pass(self.0, "begin_render_pass()");
         unsafe {
             ash_static().fp_10.cmd_begin_render_pass(self.0, begin_info, contents);
         }
@@ -290,6 +309,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdNextSubpass.html"]
     pub fn next_subpass(&mut self, contents: vk::SubpassContents) {
+        validate_inside_render_pass(self.0, "next_subpass()");
         unsafe {
             ash_static().fp_10.cmd_next_subpass(self.0, contents);
         }
@@ -297,6 +317,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdBindPipeline.html"]
     pub fn bind_pipeline(&mut self, pipeline_bind_point: vk::PipelineBindPoint, pipeline: vk::Pipeline) {
+        track_bind_pipeline(self.0, pipeline_bind_point, pipeline);
         unsafe {
             ash_static()
                 .fp_10
@@ -335,6 +356,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdEndRenderPass.html"]
     pub fn end_render_pass(&mut self) {
+        track_end_render_pass(self.0, "end_render_pass()");
         unsafe {
             ash_static().fp_10.cmd_end_render_pass(self.0);
         }
@@ -342,6 +364,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdDraw.html"]
     pub fn draw(&mut self, vertex_count: u32, instance_count: u32, first_vertex: u32, first_instance: u32) {
+        validate_draw(self.0, "draw()");
         unsafe {
             ash_static()
                 .fp_10
@@ -351,6 +374,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdDrawIndirect.html"]
     pub fn draw_indirect(&mut self, buffer: vk::Buffer, offset: vk::DeviceSize, draw_count: u32, stride: u32) {
+        validate_draw(self.0, "draw_indirect()");
         unsafe {
             ash_static()
                 .fp_10
@@ -360,6 +384,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdDispatch.html"]
     pub fn dispatch(&mut self, group_count_x: u32, group_count_y: u32, group_count_z: u32) {
+        validate_dispatch(self.0, vk::PipelineBindPoint::COMPUTE, "dispatch()");
         unsafe {
             ash_static()
                 .fp_10
@@ -369,6 +394,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdDispatchIndirect.html"]
     pub fn dispatch_indirect(&mut self, buffer: vk::Buffer, offset: vk::DeviceSize) {
+        validate_dispatch(self.0, vk::PipelineBindPoint::COMPUTE, "dispatch_indirect()");
         unsafe {
             ash_static().fp_10.cmd_dispatch_indirect(self.0, buffer, offset);
         }
@@ -503,6 +529,7 @@ impl CommandBuffer {
         regions: &[vk::ImageBlit],
         filter: vk::Filter,
     ) {
+        validate_outside_render_pass(self.0, "blit_image()");
         unsafe {
             ash_static().fp_10.cmd_blit_image(
                 self.0,
@@ -526,6 +553,7 @@ impl CommandBuffer {
         dst_image_layout: vk::ImageLayout,
         regions: &[vk::ImageResolve],
     ) {
+        validate_outside_render_pass(self.0, "resolve_image()");
         unsafe {
             ash_static().fp_10.cmd_resolve_image(
                 self.0,
@@ -541,6 +569,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdFillBuffer.html"]
     pub fn fill_buffer(&mut self, buffer: vk::Buffer, offset: vk::DeviceSize, size: vk::DeviceSize, data: u32) {
+        validate_outside_render_pass(self.0, "fill_buffer()");
         unsafe {
             ash_static().fp_10.cmd_fill_buffer(self.0, buffer, offset, size, data);
         }
@@ -548,6 +577,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdUpdateBuffer.html"]
     pub fn update_buffer(&mut self, buffer: vk::Buffer, offset: vk::DeviceSize, data: &[u8]) {
+        validate_outside_render_pass(self.0, "update_buffer()");
         unsafe {
             ash_static()
                 .fp_10
@@ -557,6 +587,7 @@ impl CommandBuffer {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBuffer.html"]
     pub fn copy_buffer(&mut self, src_buffer: vk::Buffer, dst_buffer: vk::Buffer, regions: &[vk::BufferCopy]) {
+        validate_outside_render_pass(self.0, "copy_buffer()");
         unsafe {
             ash_static()
                 .fp_10
@@ -572,6 +603,7 @@ impl CommandBuffer {
         dst_buffer: vk::Buffer,
         regions: &[vk::BufferImageCopy],
     ) {
+        validate_outside_render_pass(self.0, "copy_image_to_buffer()");
         unsafe {
             ash_static().fp_10.cmd_copy_image_to_buffer(
                 self.0,
@@ -592,6 +624,7 @@ impl CommandBuffer {
         dst_image_layout: vk::ImageLayout,
         regions: &[vk::BufferImageCopy],
     ) {
+        validate_outside_render_pass(self.0, "copy_buffer_to_image()");
         unsafe {
             ash_static().fp_10.cmd_copy_buffer_to_image(
                 self.0,
@@ -613,6 +646,7 @@ impl CommandBuffer {
         dst_image_layout: vk::ImageLayout,
         regions: &[vk::ImageCopy],
     ) {
+        validate_outside_render_pass(self.0, "copy_image()");
         unsafe {
             ash_static().fp_10.cmd_copy_image(
                 self.0,
@@ -640,6 +674,7 @@ impl CommandBuffer {
         max_draw_count: u32,
         stride: u32,
     ) {
+        validate_draw(self.0, "draw_indirect_count()");
         unsafe {
             ash_static().draw_indirect_count.cmd_draw_indirect_count_khr(
                 self.0,
@@ -663,6 +698,7 @@ impl CommandBuffer {
         max_draw_count: u32,
         stride: u32,
     ) {
+        validate_draw(self.0, "draw_indexed_indirect_count()");
         unsafe {
             ash_static().draw_indirect_count.cmd_draw_indexed_indirect_count_khr(
                 self.0,
@@ -677,6 +713,61 @@ impl CommandBuffer {
     }
 }
 
+// VK_KHR_push_descriptor
+
+impl CommandBuffer {
+    #[doc = "<https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPushDescriptorSetKHR.html>"]
+    pub fn push_descriptor_set(
+        &mut self,
+        pipeline_bind_point: vk::PipelineBindPoint,
+        layout: vk::PipelineLayout,
+        set: u32,
+        descriptor_writes: &[vk::WriteDescriptorSet],
+    ) {
+        unsafe {
+            ash_static().push_descriptor.cmd_push_descriptor_set_khr(
+                self.0,
+                pipeline_bind_point,
+                layout,
+                set,
+                descriptor_writes.len() as _,
+                descriptor_writes.as_ptr(),
+            );
+        }
+    }
+}
+
+// VK_EXT_conditional_rendering
+
+impl CommandBuffer {
+    // Commands until the matching end are discarded if the u32 at `offset` is zero, or non-zero when inverted
+    #[doc = "<https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdBeginConditionalRenderingEXT.html>"]
+    pub fn begin_conditional_rendering(&mut self, buffer: vk::Buffer, offset: vk::DeviceSize, inverted: bool) {
+        let begin_info = vk::ConditionalRenderingBeginInfoEXT::builder()
+            .buffer(buffer)
+            .offset(offset)
+            .flags(if inverted {
+                vk::ConditionalRenderingFlagsEXT::INVERTED
+            } else {
+                vk::ConditionalRenderingFlagsEXT::empty()
+            });
+        unsafe {
+            ash_static()
+                .conditional_rendering
+                .cmd_begin_conditional_rendering_ext(self.0, &*begin_info);
+        }
+    }
+
+    #[doc = "<https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdEndConditionalRenderingEXT.html>"]
+    pub fn end_conditional_rendering(&mut self) {
+        unsafe {
+            ash_static()
+                .conditional_rendering
+                .cmd_end_conditional_rendering_ext(self.0);
+        }
+    }
+}
+
 // ray tracing nv
 
 impl CommandBuffer {
@@ -693,6 +784,7 @@ impl CommandBuffer {
         scratch: vk::Buffer,
         scratch_offset: vk::DeviceSize,
     ) {
+        validate_outside_render_pass(self.0, "build_acceleration_structure_nv()");
         unsafe {
             ash_static().ray_tracing_nv.cmd_build_acceleration_structure_nv(
                 self.0,
@@ -715,6 +807,7 @@ impl CommandBuffer {
         src: vk::AccelerationStructureNV,
         mode: vk::CopyAccelerationStructureModeNV,
     ) {
+        validate_outside_render_pass(self.0, "copy_acceleration_structure_nv()");
         unsafe {
             ash_static()
                 .ray_tracing_nv
@@ -741,6 +834,7 @@ impl CommandBuffer {
         height: u32,
         depth: u32,
     ) {
+        validate_dispatch(self.0, vk::PipelineBindPoint::RAY_TRACING_NV, "trace_rays_nv()");
         unsafe {
             ash_static().ray_tracing_nv.cmd_trace_rays_nv(
                 self.0,
@@ -784,3 +878,25 @@ impl CommandBuffer {
         }
     }
 }
+
+// VK_KHR_dynamic_rendering
+
+impl CommandBuffer {
+    #[doc = "<https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/vkCmdBeginRendering.html>"]
+    pub fn begin_rendering(&mut self, rendering_info: &RenderingInfoKHR) {
+        track_begin_render_pass(self.0, "begin_rendering()");
+        unsafe {
+            ash_static()
+                .dynamic_rendering
+                .cmd_begin_rendering_khr(self.0, rendering_info);
+        }
+    }
+
+    #[doc = "<https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/vkCmdEndRendering.html>"]
+    pub fn end_rendering(&mut self) {
+        track_end_render_pass(self.0, "end_rendering()");
+        unsafe {
+            ash_static().dynamic_rendering.cmd_end_rendering_khr(self.0);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Debug-only command buffer state tracking, catches common mistakes with a readable panic
+// before they reach the driver. CommandBuffer is a plain handle, so the state is kept on the side.
+// Release builds compile all of this into no-ops.
+
+use ash::vk;
+
+#[cfg(debug_assertions)]
+mod tracking {
+    use ash::vk;
+    use ash::vk::Handle;
+
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct CommandBufferState {
+        is_recording: bool,
+        render_pass_scope: Option<&'static str>, // function that opened the current render pass scope
+        graphics_pipeline: bool,
+        compute_pipeline: bool,
+        ray_tracing_pipeline: bool,
+    }
+
+    #[derive(Default)]
+    struct ValidationState {
+        command_buffers: HashMap<u64, CommandBufferState>,
+        push_constant_ranges: HashMap<u64, Vec<vk::PushConstantRange>>,
+    }
+
+    // Secondary command buffers that continue a render pass are inside of it from the start
+    const RENDER_PASS_CONTINUE_SCOPE: &str = "begin() with RENDER_PASS_CONTINUE";
+
+    static VALIDATION_STATE: Mutex<Option<ValidationState>> = Mutex::new(None);
+
+    fn with_state<R, F: FnOnce(&mut ValidationState) -> R>(func: F) -> R {
+        let mut state = VALIDATION_STATE.lock().unwrap_or_else(|error| error.into_inner());
+        func(state.get_or_insert_with(Default::default))
+    }
+
+    fn with_command_buffer<R, F: FnOnce(&mut CommandBufferState) -> R>(
+        command_buffer: vk::CommandBuffer,
+        func: F,
+    ) -> R {
+        with_state(|state| func(state.command_buffers.entry(command_buffer.as_raw()).or_default()))
+    }
+
+    pub fn track_begin(command_buffer: vk::CommandBuffer, flags: vk::CommandBufferUsageFlags) {
+        with_command_buffer(command_buffer, |state| {
+            if state.is_recording {
+                panic!(
+                    "begin() called on command buffer {:?} that is already recording",
+                    command_buffer
+                );
+            }
+            *state = CommandBufferState {
+                is_recording: true,
+                render_pass_scope: if flags.contains(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE) {
+                    Some(RENDER_PASS_CONTINUE_SCOPE)
+                } else {
+                    None
+                },
+                ..Default::default()
+            };
+        });
+    }
+
+    pub fn track_end(command_buffer: vk::CommandBuffer) {
+        with_command_buffer(command_buffer, |state| {
+            if !state.is_recording {
+                panic!(
+                    "end() called on command buffer {:?} that is not recording",
+                    command_buffer
+                );
+            }
+            if let Some(scope) = state.render_pass_scope {
+                if scope != RENDER_PASS_CONTINUE_SCOPE {
+                    panic!(
+                        "end() called on command buffer {:?} inside a render pass started with {}",
+                        command_buffer, scope
+                    );
+                }
+            }
+            state.is_recording = false;
+        });
+    }
+
+    pub fn track_reset(command_buffers: &[vk::CommandBuffer]) {
+        with_state(|state| {
+            for command_buffer in command_buffers {
+                state.command_buffers.remove(&command_buffer.as_raw());
+            }
+        });
+    }
+
+    pub fn track_begin_render_pass(command_buffer: vk::CommandBuffer, function_name: &'static str) {
+        with_command_buffer(command_buffer, |state| {
+            validate_recording(command_buffer, state, function_name);
+            if let Some(scope) = state.render_pass_scope {
+                panic!(
+                    "{} called on command buffer {:?} inside a render pass started with {}",
+                    function_name, command_buffer, scope
+                );
+            }
+            state.render_pass_scope = Some(function_name);
+        });
+    }
+
+    pub fn track_end_render_pass(command_buffer: vk::CommandBuffer, function_name: &'static str) {
+        with_command_buffer(command_buffer, |state| {
+            validate_recording(command_buffer, state, function_name);
+            if state.render_pass_scope.is_none() {
+                panic!(
+                    "{} called on command buffer {:?} outside of a render pass",
+                    function_name, command_buffer
+                );
+            }
+            state.render_pass_scope = None;
+        });
+    }
+
+    pub fn validate_inside_render_pass(command_buffer: vk::CommandBuffer, function_name: &'static str) {
+        with_command_buffer(command_buffer, |state| {
+            validate_recording(command_buffer, state, function_name);
+            if state.render_pass_scope.is_none() {
+                panic!(
+                    "{} called on command buffer {:?} outside of a render pass",
+                    function_name, command_buffer
+                );
+            }
+        });
+    }
+
+    pub fn track_bind_pipeline(
+        command_buffer: vk::CommandBuffer,
+        pipeline_bind_point: vk::PipelineBindPoint,
+        pipeline: vk::Pipeline,
+    ) {
+        with_command_buffer(command_buffer, |state| {
+            validate_recording(command_buffer, state, "bind_pipeline()");
+            let is_bound = pipeline != vk::Pipeline::null();
+            match pipeline_bind_point {
+                vk::PipelineBindPoint::GRAPHICS => state.graphics_pipeline = is_bound,
+                vk::PipelineBindPoint::COMPUTE => state.compute_pipeline = is_bound,
+                vk::PipelineBindPoint::RAY_TRACING_NV => state.ray_tracing_pipeline = is_bound,
+                _ => {}
+            }
+        });
+    }
+
+    pub fn validate_draw(command_buffer: vk::CommandBuffer, function_name: &'static str) {
+        with_command_buffer(command_buffer, |state| {
+            validate_recording(command_buffer, state, function_name);
+            if state.render_pass_scope.is_none() {
+                panic!(
+                    "{} called on command buffer {:?} outside of a render pass",
+                    function_name, command_buffer
+                );
+            }
+            if !state.graphics_pipeline {
+                panic!(
+                    "{} called on command buffer {:?} without a bound graphics pipeline",
+                    function_name, command_buffer
+                );
+            }
+        });
+    }
+
+    pub fn validate_dispatch(
+        command_buffer: vk::CommandBuffer,
+        pipeline_bind_point: vk::PipelineBindPoint,
+        function_name: &'static str,
+    ) {
+        with_command_buffer(command_buffer, |state| {
+            validate_recording(command_buffer, state, function_name);
+            if let Some(scope) = state.render_pass_scope {
+                panic!(
+                    "{} called on command buffer {:?} inside a render pass started with {}",
+                    function_name, command_buffer, scope
+                );
+            }
+            let is_bound = match pipeline_bind_point {
+                vk::PipelineBindPoint::RAY_TRACING_NV => state.ray_tracing_pipeline,
+                _ => state.compute_pipeline,
+            };
+            if !is_bound {
+                panic!(
+                    "{} called on command buffer {:?} without a bound {:?} pipeline",
+                    function_name, command_buffer, pipeline_bind_point
+                );
+            }
+        });
+    }
+
+    pub fn validate_outside_render_pass(command_buffer: vk::CommandBuffer, function_name: &'static str) {
+        with_command_buffer(command_buffer, |state| {
+            validate_recording(command_buffer, state, function_name);
+            if let Some(scope) = state.render_pass_scope {
+                panic!(
+                    "{} called on command buffer {:?} inside a render pass started with {}",
+                    function_name, command_buffer, scope
+                );
+            }
+        });
+    }
+
+    // Every byte has to be covered by a range of the layout for every stage that is updated
+    pub fn validate_push_constants(
+        command_buffer: vk::CommandBuffer,
+        layout: vk::PipelineLayout,
+        stage_flags: vk::ShaderStageFlags,
+        offset: u32,
+        size: u32,
+    ) {
+        with_state(|state| {
+            if let Some(ranges) = state.push_constant_ranges.get(&layout.as_raw()) {
+                for byte in offset..offset + size {
+                    let covered_stages = ranges
+                        .iter()
+                        .filter(|range| byte >= range.offset && byte < range.offset + range.size)
+                        .fold(vk::ShaderStageFlags::empty(), |stages, range| {
+                            stages | range.stage_flags
+                        });
+                    if !covered_stages.contains(stage_flags) {
+                        panic!(
+                            "push_constants() on command buffer {:?} updates bytes {}..{} for {:?}, which are outside of pipeline layout {:?} ranges {:?}",
+                            command_buffer,
+                            offset,
+                            offset + size,
+                            stage_flags,
+                            layout,
+                            ranges
+                        );
+                    }
+                }
+            }
+        });
+    }
+
+    pub fn track_pipeline_layout(layout: vk::PipelineLayout, ranges: &[vk::PushConstantRange]) {
+        with_state(|state| {
+            state.push_constant_ranges.insert(layout.as_raw(), ranges.to_vec());
+        });
+    }
+
+    pub fn untrack_pipeline_layout(layout: vk::PipelineLayout) {
+        with_state(|state| {
+            state.push_constant_ranges.remove(&layout.as_raw());
+        });
+    }
+
+    fn validate_recording(command_buffer: vk::CommandBuffer, state: &CommandBufferState, function_name: &'static str) {
+        if !state.is_recording {
+            panic!(
+                "{} called on command buffer {:?} that is not recording",
+                function_name, command_buffer
+            );
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+pub(crate) use tracking::*;
+
+#[cfg(not(debug_assertions))]
+mod no_tracking {
+    use ash::vk;
+
+    #[inline(always)]
+    pub fn track_begin(_: vk::CommandBuffer, _: vk::CommandBufferUsageFlags) {}
+    #[inline(always)]
+    pub fn track_end(_: vk::CommandBuffer) {}
+    #[inline(always)]
+    pub fn track_reset(_: &[vk::CommandBuffer]) {}
+    #[inline(always)]
+    pub fn track_begin_render_pass(_: vk::CommandBuffer, _: &'static str) {}
+    #[inline(always)]
+    pub fn track_end_render_pass(_: vk::CommandBuffer, _: &'static str) {}
+    #[inline(always)]
+    pub fn validate_inside_render_pass(_: vk::CommandBuffer, _: &'static str) {}
+    #[inline(always)]
+    pub fn track_bind_pipeline(_: vk::CommandBuffer, _: vk::PipelineBindPoint, _: vk::Pipeline) {}
+    #[inline(always)]
+    pub fn validate_draw(_: vk::CommandBuffer, _: &'static str) {}
+    #[inline(always)]
+    pub fn validate_dispatch(_: vk::CommandBuffer, _: vk::PipelineBindPoint, _: &'static str) {}
+    #[inline(always)]
+    pub fn validate_outside_render_pass(_: vk::CommandBuffer, _: &'static str) {}
+    #[inline(always)]
+    pub fn validate_push_constants(
+        _: vk::CommandBuffer,
+        _: vk::PipelineLayout,
+        _: vk::ShaderStageFlags,
+        _: u32,
+        _: u32,
+    ) {
+    }
+    #[inline(always)]
+    pub fn track_pipeline_layout(_: vk::PipelineLayout, _: &[vk::PushConstantRange]) {}
+    #[inline(always)]
+    pub fn untrack_pipeline_layout(_: vk::PipelineLayout) {}
+}
+
+#[cfg(not(debug_assertions))]
+pub(crate) use no_tracking::*;
+
+// Push constant ranges are only available through the create info pointer
+pub(crate) fn get_push_constant_ranges(create_info: &vk::PipelineLayoutCreateInfo) -> &[vk::PushConstantRange] {
+    if create_info.push_constant_range_count == 0 {
+        &[]
+    } else {
+        unsafe {
+            std::slice::from_raw_parts(
+                create_info.p_push_constant_ranges,
+                create_info.push_constant_range_count as usize,
+            )
+        }
+    }
+}
@@ -6,17 +6,44 @@
 use ash::version::*;
 use ash::vk;
 
+use crate::diagnostics::*;
+use crate::dynamic_rendering::*;
 use crate::frame_context::*;
+use crate::graphics_pipeline_library::*;
 use crate::internal::*;
+use crate::surface_provider::*;
 
 use std::ffi::{CStr, CString};
 use std::os::raw::{c_char, c_void};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Queues don't have access to the device, so device loss is tracked globally
+static DEVICE_LOST: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn mark_device_lost(function_name: &str) {
+    if !DEVICE_LOST.swap(true, Ordering::Relaxed) {
+        log::error!("{} failed: device lost", function_name);
+    }
+}
 
 #[derive(Default, Clone, Copy)]
 pub struct DeviceOptions {
     pub enable_validation: bool,
     pub enable_ray_tracing_nv: bool,
     pub enable_render_target_export: bool,
+    pub enable_dynamic_rendering: bool,
+    pub enable_push_descriptors: bool,
+    pub enable_conditional_rendering: bool,
+    pub enable_buffer_device_address: bool,
+    pub enable_multiview: bool,
+    pub enable_shader_debug_printf: bool,       // only works with validation enabled
+    pub enable_resource_tracking: bool,         // factories report resources that outlive them
+    pub enable_pipeline_statistics: bool,       // query scopes collect shader invocation counts
+    pub enable_shader_clock: bool,              // shaders can read the subgroup clock to measure their own cost
+    pub enable_graphics_pipeline_library: bool, // material pipelines are linked from shared partial pipelines
+    pub disable_resizable_bar: bool,            // dynamic buffers stay in host visible memory
+    pub num_buffered_frames: usize,             // 0 means DEFAULT_NUM_BUFFERED_GPU_FRAMES
     pub _reserved: bool,
 }
 
@@ -28,9 +55,22 @@ pub struct Device {
     graphics_queue: InternalQueue,
     surface_loader: Option<ash::extensions::khr::Surface>,
     surface_khr: vk::SurfaceKHR,
-    _debug_report: Option<DebugReportCallback>,
+    debug_report: Option<DebugReportCallback>,
     options: DeviceOptions,
+    dynamic_rendering_enabled: bool,
+    push_descriptor_enabled: bool,
+    conditional_rendering_enabled: bool,
+    buffer_device_address_enabled: bool,
+    multiview_enabled: bool,
+    shader_debug_printf_enabled: bool,
+    pipeline_statistics_enabled: bool,
+    shader_clock_enabled: bool,
+    graphics_pipeline_library_enabled: bool,
+    max_sampler_anisotropy: f32, // 1.0 if anisotropic filtering is not supported
+    num_buffered_frames: usize,
     current_gpu_frame: usize,
+    frame_index: u64,
+    stage_sync: Arc<FrameStageSync>,
 }
 
 impl Device {
@@ -42,8 +82,32 @@ impl Device {
     ) -> Self
     where
         T: Fn(&ash::Entry, &ash::Instance) -> (Option<ash::extensions::khr::Surface>, vk::SurfaceKHR),
+    {
+        Self::with_physical_device_filter(
+            instance_extensions,
+            device_extensions,
+            create_surface,
+            |_, _| true,
+            options,
+        )
+    }
+
+    // Physical devices rejected by the filter are never considered, used when an external API
+    // (e.g. an XR runtime) dictates which GPU has to be used
+    pub fn with_physical_device_filter<T, F>(
+        instance_extensions: &[&CStr],
+        device_extensions: &[&CStr],
+        create_surface: T,
+        physical_device_filter: F,
+        options: DeviceOptions,
+    ) -> Self
+    where
+        T: Fn(&ash::Entry, &ash::Instance) -> (Option<ash::extensions::khr::Surface>, vk::SurfaceKHR),
+        F: Fn(&ash::Instance, vk::PhysicalDevice) -> bool,
     {
         let entry = ash::Entry::new().unwrap();
+        let shader_debug_printf_requested = options.enable_validation && options.enable_shader_debug_printf;
+        let mut instance_extension_names = Vec::with_capacity(instance_extensions.len() + 2);
         let instance = unsafe {
             let mut layer_name_data = Vec::with_capacity(1);
             let mut layer_names = Vec::with_capacity(1);
@@ -52,7 +116,6 @@ impl Device {
                 layer_names.push(layer_name_data.last().unwrap().as_ptr());
             }
 
-            let mut instance_extension_names = Vec::with_capacity(instance_extensions.len() + 2);
             for ext in instance_extensions {
                 instance_extension_names.push(ext.as_ptr());
             }
@@ -60,6 +123,9 @@ impl Device {
             if options.enable_validation {
                 instance_extension_names.push(ash::extensions::ext::DebugReport::name().as_ptr());
             }
+            if shader_debug_printf_requested {
+                instance_extension_names.push(vk::ExtValidationFeaturesFn::name().as_ptr());
+            }
             if options.enable_ray_tracing_nv {
                 instance_extension_names.push(vk::KhrGetPhysicalDeviceProperties2Fn::name().as_ptr());
             }
@@ -74,7 +140,16 @@ impl Device {
                 .api_version(vk_make_version(1, 1, 0))
                 .build();
 
+            // Debug printf is implemented by the validation layer, messages come back through the debug report
+            let enabled_validation_features = [vk::ValidationFeatureEnableEXT::DEBUG_PRINTF];
+            let mut validation_features = vk::ValidationFeaturesEXT::builder()
+                .enabled_validation_features(&enabled_validation_features)
+                .build();
+
             let mut instance_create_info = vk::InstanceCreateInfo::builder().application_info(&application_info);
+            if shader_debug_printf_requested {
+                instance_create_info = instance_create_info.push_next(&mut validation_features);
+            }
             if !layer_names.is_empty() {
                 instance_create_info = instance_create_info.enabled_layer_names(&layer_names);
             }
@@ -97,7 +172,12 @@ impl Device {
                             .flags(
                                 vk::DebugReportFlagsEXT::ERROR
                                     | vk::DebugReportFlagsEXT::WARNING
-                                    | vk::DebugReportFlagsEXT::PERFORMANCE_WARNING,
+                                    | vk::DebugReportFlagsEXT::PERFORMANCE_WARNING
+                                    | if shader_debug_printf_requested {
+                                        vk::DebugReportFlagsEXT::INFORMATION
+                                    } else {
+                                        vk::DebugReportFlagsEXT::empty()
+                                    },
                             )
                             .pfn_callback(Some(vulkan_debug_callback))
                             .build(),
@@ -144,7 +224,7 @@ impl Device {
                         supports_vk_khr_swapchain
                     };
 
-                    if supports_needed_extensions {
+                    if supports_needed_extensions && physical_device_filter(&instance, *device) {
                         log::info!("Suitable physical device: {:?}", device);
                         log::info!("Supported features: {:?}", features);
                         Some((device, properties, features))
@@ -199,11 +279,114 @@ impl Device {
                 .expect("Couldn't find suitable device.")
         };
 
+        // Dynamic rendering is optional, RenderLayer falls back to render passes when it's not available
+        let dynamic_rendering_enabled = options.enable_dynamic_rendering
+            && supports_device_extension(&instance, physical_device, KhrDynamicRenderingFn::name());
+        log::info!("dynamic rendering enabled: {}", dynamic_rendering_enabled);
+
+        // Push descriptors are optional, descriptor sets are preallocated when they're not available
+        let push_descriptor_enabled = options.enable_push_descriptors
+            && supports_device_extension(&instance, physical_device, vk::KhrPushDescriptorFn::name());
+        log::info!("push descriptors enabled: {}", push_descriptor_enabled);
+
+        // Conditional rendering is optional, draws are recorded unconditionally when it's not available
+        let conditional_rendering_enabled = options.enable_conditional_rendering
+            && supports_device_extension(&instance, physical_device, vk::ExtConditionalRenderingFn::name())
+            && supports_conditional_rendering(&instance, physical_device);
+        log::info!("conditional rendering enabled: {}", conditional_rendering_enabled);
+
+        // Buffer device address is optional, shaders bind buffers through descriptors when it's not available
+        let buffer_device_address_enabled = options.enable_buffer_device_address
+            && supports_device_extension(&instance, physical_device, vk::KhrBufferDeviceAddressFn::name())
+            && supports_buffer_device_address(&instance, physical_device);
+        log::info!("buffer device address enabled: {}", buffer_device_address_enabled);
+
+        let shader_debug_printf_enabled = shader_debug_printf_requested
+            && supports_device_extension(&instance, physical_device, vk::KhrShaderNonSemanticInfoFn::name());
+        log::info!("shader debug printf enabled: {}", shader_debug_printf_enabled);
+
+        // Multiview is core in Vulkan 1.1, but it's still an optional feature
+        let multiview_enabled = options.enable_multiview && supports_multiview(&instance, physical_device);
+        log::info!("multiview enabled: {}", multiview_enabled);
+
+        // Secondary command buffers are executed while statistics queries are active, so inherited queries are needed
+        let pipeline_statistics_enabled = options.enable_pipeline_statistics && {
+            let features = unsafe { instance.get_physical_device_features(physical_device) };
+            features.pipeline_statistics_query == vk::TRUE && features.inherited_queries == vk::TRUE
+        };
+        log::info!("pipeline statistics enabled: {}", pipeline_statistics_enabled);
+
+        // Only the subgroup clock is used, device clock is not required to measure shader cost
+        let shader_clock_enabled = options.enable_shader_clock
+            && supports_device_extension(&instance, physical_device, vk::KhrShaderClockFn::name())
+            && supports_shader_clock(&instance, physical_device);
+        log::info!("shader clock enabled: {}", shader_clock_enabled);
+
+        // Graphics pipeline libraries are optional, pipelines are created monolithically when they're not available
+        let graphics_pipeline_library_enabled = options.enable_graphics_pipeline_library
+            && supports_device_extension(&instance, physical_device, vk::KhrPipelineLibraryFn::name())
+            && supports_device_extension(&instance, physical_device, ExtGraphicsPipelineLibraryFn::name())
+            && supports_graphics_pipeline_library(&instance, physical_device);
+        log::info!(
+            "graphics pipeline library enabled: {}",
+            graphics_pipeline_library_enabled
+        );
+
+        // Anisotropic filtering is optional, samplers requesting it fall back to regular filtering
+        let max_sampler_anisotropy = unsafe {
+            if instance
+                .get_physical_device_features(physical_device)
+                .sampler_anisotropy
+                == vk::TRUE
+            {
+                instance
+                    .get_physical_device_properties(physical_device)
+                    .limits
+                    .max_sampler_anisotropy
+                    .max(1.0)
+            } else {
+                1.0
+            }
+        };
+        log::info!("max sampler anisotropy: {}", max_sampler_anisotropy);
+
+        // Each buffered frame presents its own swapchain image, so the count has to fit into surface limits
+        let num_buffered_frames = if options.num_buffered_frames == 0 {
+            DEFAULT_NUM_BUFFERED_GPU_FRAMES
+        } else {
+            options.num_buffered_frames
+        };
+        if let Some(surface_loader) = &surface_loader {
+            let surface_caps = unsafe {
+                surface_loader
+                    .get_physical_device_surface_capabilities(physical_device, surface_khr)
+                    .unwrap()
+            };
+            let min_image_count = surface_caps.min_image_count as usize;
+            let max_image_count = surface_caps.max_image_count as usize;
+            assert!(
+                num_buffered_frames >= min_image_count
+                    && (max_image_count == 0 || num_buffered_frames <= max_image_count),
+                "{} buffered frames requested, surface supports [{}, {}] images",
+                num_buffered_frames,
+                min_image_count,
+                max_image_count
+            );
+        }
+        log::info!("buffered frames: {}", num_buffered_frames);
+
         let device = {
             let mut enabled_device_features = vk::PhysicalDeviceFeatures2::default();
             enabled_device_features.features.texture_compression_bc = vk::TRUE;
             enabled_device_features.features.multi_draw_indirect = vk::TRUE;
             enabled_device_features.features.fragment_stores_and_atomics = vk::TRUE;
+            if max_sampler_anisotropy > 1.0 {
+                enabled_device_features.features.sampler_anisotropy = vk::TRUE;
+            }
+            if pipeline_statistics_enabled {
+                enabled_device_features.features.pipeline_statistics_query = vk::TRUE;
+                enabled_device_features.features.inherited_queries = vk::TRUE;
+            }
 
             let queue_priorities = [1.0];
             let queue_create_info = [vk::DeviceQueueCreateInfo::builder()
@@ -229,12 +412,38 @@ impl Device {
             let mut descriptor_indexing = vk::PhysicalDeviceDescriptorIndexingFeaturesEXT::builder()
                 .descriptor_binding_variable_descriptor_count(true)
                 .runtime_descriptor_array(true)
+                .shader_sampled_image_array_non_uniform_indexing(true)
+                .shader_storage_buffer_array_non_uniform_indexing(true)
                 .build();
 
             let mut scalar_block = vk::PhysicalDeviceScalarBlockLayoutFeaturesEXT::builder()
                 .scalar_block_layout(true)
                 .build();
 
+            let mut dynamic_rendering = PhysicalDeviceDynamicRenderingFeaturesKHR {
+                dynamic_rendering: vk::TRUE,
+                ..Default::default()
+            };
+
+            let mut multiview = vk::PhysicalDeviceMultiviewFeatures::builder().multiview(true).build();
+
+            let mut conditional_rendering = vk::PhysicalDeviceConditionalRenderingFeaturesEXT::builder()
+                .conditional_rendering(true)
+                .build();
+
+            let mut buffer_device_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::builder()
+                .buffer_device_address(true)
+                .build();
+
+            let mut shader_clock = vk::PhysicalDeviceShaderClockFeaturesKHR::builder()
+                .shader_subgroup_clock(true)
+                .build();
+
+            let mut graphics_pipeline_library = PhysicalDeviceGraphicsPipelineLibraryFeaturesEXT {
+                graphics_pipeline_library: vk::TRUE,
+                ..Default::default()
+            };
+
             let mut device_create_info = vk::DeviceCreateInfo::builder()
                 .queue_create_infos(&queue_create_info)
                 .push_next(&mut enabled_device_features);
@@ -253,11 +462,94 @@ impl Device {
                     .push_next(&mut scalar_block);
             }
 
+            if dynamic_rendering_enabled {
+                device_extension_names.push(KhrDynamicRenderingFn::name().as_ptr());
+                device_create_info = device_create_info.push_next(&mut dynamic_rendering);
+            }
+
+            if push_descriptor_enabled {
+                device_extension_names.push(vk::KhrPushDescriptorFn::name().as_ptr());
+            }
+
+            if conditional_rendering_enabled {
+                device_extension_names.push(vk::ExtConditionalRenderingFn::name().as_ptr());
+                device_create_info = device_create_info.push_next(&mut conditional_rendering);
+            }
+
+            if buffer_device_address_enabled {
+                device_extension_names.push(vk::KhrBufferDeviceAddressFn::name().as_ptr());
+                device_create_info = device_create_info.push_next(&mut buffer_device_address);
+            }
+
+            if shader_debug_printf_enabled {
+                device_extension_names.push(vk::KhrShaderNonSemanticInfoFn::name().as_ptr());
+            }
+
+            if multiview_enabled {
+                device_create_info = device_create_info.push_next(&mut multiview);
+            }
+
+            if shader_clock_enabled {
+                device_extension_names.push(vk::KhrShaderClockFn::name().as_ptr());
+                device_create_info = device_create_info.push_next(&mut shader_clock);
+            }
+
+            if graphics_pipeline_library_enabled {
+                device_extension_names.push(vk::KhrPipelineLibraryFn::name().as_ptr());
+                device_extension_names.push(ExtGraphicsPipelineLibraryFn::name().as_ptr());
+                device_create_info = device_create_info.push_next(&mut graphics_pipeline_library);
+            }
+
             if !device_extension_names.is_empty() {
                 log::info!("requested device extensions: {:?}", &device_extension_names);
                 device_create_info = device_create_info.enabled_extension_names(&device_extension_names);
             }
 
+            let mut enabled_feature_names = vec![
+                "texture_compression_bc",
+                "multi_draw_indirect",
+                "fragment_stores_and_atomics",
+            ];
+            if options.enable_ray_tracing_nv {
+                enabled_feature_names.push("descriptor_binding_variable_descriptor_count");
+                enabled_feature_names.push("runtime_descriptor_array");
+                enabled_feature_names.push("shader_sampled_image_array_non_uniform_indexing");
+                enabled_feature_names.push("shader_storage_buffer_array_non_uniform_indexing");
+                enabled_feature_names.push("scalar_block_layout");
+            }
+            if dynamic_rendering_enabled {
+                enabled_feature_names.push("dynamic_rendering");
+            }
+            if conditional_rendering_enabled {
+                enabled_feature_names.push("conditional_rendering");
+            }
+            if buffer_device_address_enabled {
+                enabled_feature_names.push("buffer_device_address");
+            }
+            if multiview_enabled {
+                enabled_feature_names.push("multiview");
+            }
+            if max_sampler_anisotropy > 1.0 {
+                enabled_feature_names.push("sampler_anisotropy");
+            }
+            if pipeline_statistics_enabled {
+                enabled_feature_names.push("pipeline_statistics_query");
+                enabled_feature_names.push("inherited_queries");
+            }
+            if shader_clock_enabled {
+                enabled_feature_names.push("shader_subgroup_clock");
+            }
+            if graphics_pipeline_library_enabled {
+                enabled_feature_names.push("graphics_pipeline_library");
+            }
+            record_device_diagnostics(
+                &instance,
+                physical_device,
+                &enabled_feature_names,
+                &instance_extension_names,
+                &device_extension_names,
+            );
+
             unsafe {
                 instance
                     .create_device(physical_device, &device_create_info.build(), None)
@@ -273,12 +565,29 @@ impl Device {
             let ray_tracing_nv = vk::NvRayTracingFn::load(|name| {
                 std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
             });
-            ash_static_init(
-                device.fp_v1_0().clone(),
-                device.fp_v1_1().clone(),
+            let push_descriptor = vk::KhrPushDescriptorFn::load(|name| {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            });
+            let dynamic_rendering = KhrDynamicRenderingFn::load(|name| {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            });
+            let conditional_rendering = vk::ExtConditionalRenderingFn::load(|name| {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            });
+            let buffer_device_address = vk::KhrBufferDeviceAddressFn::load(|name| {
+                std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            });
+            ash_static_init(AshStatic {
+                fp_10: device.fp_v1_0().clone(),
+                fp_11: device.fp_v1_1().clone(),
                 draw_indirect_count,
                 ray_tracing_nv,
-            );
+                push_descriptor,
+                dynamic_rendering,
+                conditional_rendering,
+                buffer_device_address,
+                get_device_proc_addr: instance.fp_v1_0().get_device_proc_addr,
+            });
         }
         let graphics_queue = unsafe { device.get_device_queue(graphics_queue_index, 0) };
 
@@ -293,11 +602,41 @@ impl Device {
             },
             surface_loader,
             surface_khr,
-            _debug_report: debug_report,
+            debug_report,
             options,
+            dynamic_rendering_enabled,
+            push_descriptor_enabled,
+            conditional_rendering_enabled,
+            buffer_device_address_enabled,
+            multiview_enabled,
+            shader_debug_printf_enabled,
+            pipeline_statistics_enabled,
+            shader_clock_enabled,
+            graphics_pipeline_library_enabled,
+            max_sampler_anisotropy,
+            num_buffered_frames,
             current_gpu_frame: 0,
+            frame_index: 0,
+            stage_sync: Default::default(),
         }
     }
+
+    pub fn from_surface_provider<T>(surface_provider: &T, device_extensions: &[&CStr], options: DeviceOptions) -> Self
+    where
+        T: SurfaceProvider + ?Sized,
+    {
+        let instance_extensions = surface_provider.get_required_instance_extensions();
+        Self::new(
+            &instance_extensions,
+            device_extensions,
+            |entry: &ash::Entry, instance: &ash::Instance| {
+                surface_provider
+                    .create_surface(entry, instance)
+                    .expect("create_surface() failed")
+            },
+            options,
+        )
+    }
 }
 
 impl Device {
@@ -310,7 +649,11 @@ impl Device {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkWaitForFences.html"]
     pub fn wait_for_fences(&self, fences: &[vk::Fence], wait_all: bool, timeout: u64) {
-        unsafe { self.device.wait_for_fences(fences, wait_all, timeout).unwrap() }
+        match unsafe { self.device.wait_for_fences(fences, wait_all, timeout) } {
+            Ok(_) => {}
+            Err(vk::Result::ERROR_DEVICE_LOST) => mark_device_lost("wait_for_fences()"),
+            Err(err) => panic!("wait_for_fences() failed: {:?}", err),
+        }
     }
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkGetFenceStatus.html"]
@@ -320,8 +663,10 @@ impl Device {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkDeviceWaitIdle.html"]
     pub fn wait_idle(&self) {
-        unsafe {
-            self.device.device_wait_idle().expect("wait_idle() failed");
+        match unsafe { self.device.device_wait_idle() } {
+            Ok(_) => {}
+            Err(vk::Result::ERROR_DEVICE_LOST) => mark_device_lost("wait_idle()"),
+            Err(err) => panic!("wait_idle() failed: {:?}", err),
         }
     }
 
@@ -331,14 +676,61 @@ impl Device {
     }
 }
 
+impl Device {
+    // Factory has to be destroyed before the device, nothing created by this device can be used afterwards
+    pub fn destroy(&mut self) {
+        unsafe {
+            self.device.destroy_device(None);
+            if let Some(surface_loader) = &self.surface_loader {
+                surface_loader.destroy_surface(self.surface_khr, None);
+            }
+            if let Some(debug_report) = &self.debug_report {
+                debug_report
+                    .loader
+                    .destroy_debug_report_callback(debug_report.callback, None);
+            }
+            self.instance.destroy_instance(None);
+            ash_static_reset();
+        }
+        DEVICE_LOST.store(false, Ordering::Relaxed);
+    }
+
+    // Device has to be destroyed and created again once it's lost
+    pub fn is_device_lost(&self) -> bool {
+        DEVICE_LOST.load(Ordering::Relaxed)
+    }
+}
+
 impl Device {
     pub fn begin_frame(&self) -> FrameContext {
-        FrameContext::new(self.current_gpu_frame)
+        FrameContext::new(
+            self.current_gpu_frame,
+            self.num_buffered_frames,
+            self.frame_index,
+            self.stage_sync.clone(),
+        )
     }
 
     pub fn end_frame(&mut self, frame_context: FrameContext) {
         assert_eq!(frame_context.current_gpu_frame, self.current_gpu_frame);
-        self.current_gpu_frame = (self.current_gpu_frame + 1) % NUM_BUFFERED_GPU_FRAMES;
+        self.current_gpu_frame = (self.current_gpu_frame + 1) % self.num_buffered_frames;
+        self.frame_index += 1;
+        frame_context.signal_stage(FrameStage::Present);
+        advance_diagnostic_frame_index();
+    }
+
+    pub fn get_num_buffered_frames(&self) -> usize {
+        self.num_buffered_frames
+    }
+
+    // Index of the frame returned by the next begin_frame()
+    pub fn get_frame_index(&self) -> u64 {
+        self.frame_index
+    }
+
+    // Stage sync outlives frame contexts, threads that run ahead of the render thread wait on it
+    pub fn get_frame_stage_sync(&self) -> Arc<FrameStageSync> {
+        self.stage_sync.clone()
     }
 }
 
@@ -378,7 +770,25 @@ impl Device {
 
 impl Device {
     pub fn create_factory(&self) -> crate::device_factory::DeviceFactory {
-        crate::device_factory::DeviceFactory::new(self.device.clone(), self.instance.clone(), self.physical_device)
+        let mut factory = crate::device_factory::DeviceFactory::new(
+            self.device.clone(),
+            self.instance.clone(),
+            self.physical_device,
+            self.num_buffered_frames,
+            self.max_sampler_anisotropy,
+            self.buffer_device_address_enabled,
+        );
+        if self.options.enable_resource_tracking {
+            factory.enable_resource_tracking();
+        }
+        if self.options.disable_resizable_bar {
+            factory.disable_resizable_bar();
+        }
+        factory
+    }
+
+    pub fn get_physical_device_properties(&self) -> vk::PhysicalDeviceProperties {
+        unsafe { self.instance.get_physical_device_properties(self.physical_device) }
     }
 
     pub fn get_ray_tracing_properties_nv(&self) -> vk::PhysicalDeviceRayTracingPropertiesNV {
@@ -394,6 +804,147 @@ impl Device {
     pub fn get_device_options(&self) -> DeviceOptions {
         self.options
     }
+
+    pub fn is_dynamic_rendering_enabled(&self) -> bool {
+        self.dynamic_rendering_enabled
+    }
+
+    pub fn is_push_descriptor_enabled(&self) -> bool {
+        self.push_descriptor_enabled
+    }
+
+    pub fn is_conditional_rendering_enabled(&self) -> bool {
+        self.conditional_rendering_enabled
+    }
+
+    pub fn is_buffer_device_address_enabled(&self) -> bool {
+        self.buffer_device_address_enabled
+    }
+
+    // Shaders have to be compiled with SHADER_DEBUG_PRINTF for debug_printf.glsl helpers to do anything
+    pub fn is_shader_debug_printf_enabled(&self) -> bool {
+        self.shader_debug_printf_enabled
+    }
+
+    pub fn is_multiview_enabled(&self) -> bool {
+        self.multiview_enabled
+    }
+
+    pub fn is_pipeline_statistics_enabled(&self) -> bool {
+        self.pipeline_statistics_enabled
+    }
+
+    // Shaders have to be compiled with GL_ARB_shader_clock to read the subgroup clock
+    pub fn is_shader_clock_enabled(&self) -> bool {
+        self.shader_clock_enabled
+    }
+
+    // Pipeline bundles link material pipelines from vertex input, pre-rasterization, fragment shader
+    // and fragment output libraries, libraries are shared between materials
+    pub fn is_graphics_pipeline_library_enabled(&self) -> bool {
+        self.graphics_pipeline_library_enabled
+    }
+
+    pub fn get_max_sampler_anisotropy(&self) -> f32 {
+        self.max_sampler_anisotropy
+    }
+}
+
+fn supports_device_extension(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    extension_name: &CStr,
+) -> bool {
+    let extensions = unsafe { instance.enumerate_device_extension_properties(physical_device) };
+    extensions
+        .unwrap()
+        .iter()
+        .any(|extension| unsafe { libc::strcmp(extension.extension_name.as_ptr(), extension_name.as_ptr()) == 0 })
+}
+
+fn supports_multiview(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> bool {
+    // ash doesn't allow chaining multiview features into PhysicalDeviceFeatures2, p_next is set directly
+    let mut multiview = vk::PhysicalDeviceMultiviewFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut multiview as *mut vk::PhysicalDeviceMultiviewFeatures as *mut std::ffi::c_void,
+        ..Default::default()
+    };
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    multiview.multiview == vk::TRUE
+}
+
+fn supports_conditional_rendering(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let mut conditional_rendering = vk::PhysicalDeviceConditionalRenderingFeaturesEXT::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut conditional_rendering as *mut vk::PhysicalDeviceConditionalRenderingFeaturesEXT
+            as *mut std::ffi::c_void,
+        ..Default::default()
+    };
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    conditional_rendering.conditional_rendering == vk::TRUE
+}
+
+fn supports_buffer_device_address(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let mut buffer_device_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut buffer_device_address as *mut vk::PhysicalDeviceBufferDeviceAddressFeatures
+            as *mut std::ffi::c_void,
+        ..Default::default()
+    };
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    buffer_device_address.buffer_device_address == vk::TRUE
+}
+
+fn supports_shader_clock(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let mut shader_clock = vk::PhysicalDeviceShaderClockFeaturesKHR::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut shader_clock as *mut vk::PhysicalDeviceShaderClockFeaturesKHR as *mut std::ffi::c_void,
+        ..Default::default()
+    };
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    shader_clock.shader_subgroup_clock == vk::TRUE
+}
+
+fn supports_graphics_pipeline_library(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let mut graphics_pipeline_library = PhysicalDeviceGraphicsPipelineLibraryFeaturesEXT::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut graphics_pipeline_library as *mut PhysicalDeviceGraphicsPipelineLibraryFeaturesEXT
+            as *mut std::ffi::c_void,
+        ..Default::default()
+    };
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    graphics_pipeline_library.graphics_pipeline_library == vk::TRUE
+}
+
+fn record_device_diagnostics(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    enabled_feature_names: &[&str],
+    instance_extension_names: &[*const c_char],
+    device_extension_names: &[*const c_char],
+) {
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    let version_string = |version: u32| format!("{}.{}.{}", version >> 22, (version >> 12) & 0x3ff, version & 0xfff);
+    let device_info = vec![
+        format!("device name: {}", unsafe {
+            CStr::from_ptr(properties.device_name.as_ptr()).to_string_lossy()
+        }),
+        format!("device type: {:?}", properties.device_type),
+        format!("vendor id: {:#06x}", properties.vendor_id),
+        format!("device id: {:#06x}", properties.device_id),
+        format!("driver version: {:#x}", properties.driver_version),
+        format!("api version: {}", version_string(properties.api_version)),
+    ];
+    let extension_names = instance_extension_names
+        .iter()
+        .chain(device_extension_names)
+        .map(|name| unsafe { CStr::from_ptr(*name).to_string_lossy().into_owned() })
+        .collect();
+    record_device_info(
+        device_info,
+        enabled_feature_names.iter().map(|name| name.to_string()).collect(),
+        extension_names,
+    );
 }
 
 struct InternalQueue {
@@ -401,7 +952,6 @@ struct InternalQueue {
     index: u32,
 }
 
-#[allow(dead_code)]
 struct DebugReportCallback {
     loader: ash::extensions::ext::DebugReport,
     callback: vk::DebugReportCallbackEXT,
@@ -417,6 +967,16 @@ unsafe extern "system" fn vulkan_debug_callback(
     p_message: *const c_char,
     _: *mut c_void,
 ) -> u32 {
+    let message = CStr::from_ptr(p_message).to_string_lossy();
+    if message.contains("DEBUG-PRINTF") {
+        // Only the text after the last separator is printed by the shader
+        let shader_message = message.rsplit(" | ").next().unwrap_or(&message);
+        log::info!("shader: {}", shader_message);
+        record_shader_debug_message(shader_message.to_string());
+        return vk::FALSE;
+    }
+
+    record_validation_message(format!("{:?}: {}", flags, message));
     if flags & vk::DebugReportFlagsEXT::INFORMATION == vk::DebugReportFlagsEXT::INFORMATION {
         log::info!("{:?}", CStr::from_ptr(p_message));
     } else {
@@ -5,22 +5,58 @@
 
 use ash::version::*;
 use ash::vk;
+use ash::vk::Handle;
+
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
 
 use crate::command_buffer::*;
+use crate::command_buffer_validation::*;
 use crate::internal::*;
+use crate::resource_tracking::*;
+
+// Host visible device local heaps up to this size are the legacy BAR window, anything larger is resizable BAR
+// or unified memory that can take frequently updated buffers without running out
+const LEGACY_BAR_HEAP_SIZE: vk::DeviceSize = 256 * 1024 * 1024;
 
 pub struct DeviceFactory {
     device: ash::Device,
     allocator: vk_mem::Allocator,
+    num_buffered_frames: usize,
+    max_sampler_anisotropy: f32,
+    sampler_anisotropy_override: Option<f32>,
+    texture_lod_bias: f32,
+    max_texture_resolution: Option<u32>,
+    buffer_device_address_enabled: bool,
+    resizable_bar_memory_type_bits: u32, // 0 if resizable BAR is not available or disabled
+    resource_tracker: Option<ResourceTracker>,
 }
 
 impl DeviceFactory {
-    pub(crate) fn new(device: ash::Device, instance: ash::Instance, physical_device: vk::PhysicalDevice) -> Self {
+    pub(crate) fn new(
+        device: ash::Device,
+        instance: ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        num_buffered_frames: usize,
+        max_sampler_anisotropy: f32,
+        buffer_device_address_enabled: bool,
+    ) -> Self {
+        // The bundled VMA is built without buffer device address support, so its device
+        // gets a vkAllocateMemory that requests device addresses for every allocation
+        let allocator_device = if buffer_device_address_enabled {
+            unsafe { load_device_address_allocator_device(&instance, &device) }
+        } else {
+            device.clone()
+        };
+        let resizable_bar_memory_type_bits = find_resizable_bar_memory_types(&unsafe {
+            instance.get_physical_device_memory_properties(physical_device)
+        });
+        log::info!("resizable BAR available: {}", resizable_bar_memory_type_bits != 0);
         DeviceFactory {
             device: device.clone(),
             allocator: vk_mem::Allocator::new(&vk_mem::AllocatorCreateInfo {
                 physical_device,
-                device,
+                device: allocator_device,
                 instance,
                 flags: vk_mem::AllocatorCreateFlags::NONE,
                 preferred_large_heap_block_size: 0,
@@ -28,6 +64,137 @@ impl DeviceFactory {
                 heap_size_limits: None,
             })
             .expect("failed to create VMA allocator"),
+            num_buffered_frames,
+            max_sampler_anisotropy,
+            sampler_anisotropy_override: None,
+            texture_lod_bias: 0.0,
+            max_texture_resolution: None,
+            buffer_device_address_enabled,
+            resizable_bar_memory_type_bits,
+            resource_tracker: None,
+        }
+    }
+
+    pub fn destroy(&mut self) {
+        if let Some(resource_tracker) = &self.resource_tracker {
+            let leaked_resource_count = resource_tracker.report_leaks();
+            if leaked_resource_count > 0 {
+                log::error!(
+                    "{} resources are still alive when the factory is destroyed",
+                    leaked_resource_count
+                );
+            }
+        }
+        self.allocator.destroy();
+    }
+
+    pub fn get_num_buffered_frames(&self) -> usize {
+        self.num_buffered_frames
+    }
+
+    pub fn get_max_sampler_anisotropy(&self) -> f32 {
+        self.max_sampler_anisotropy
+    }
+
+    // Replaces max anisotropy of all samplers created afterwards that have anisotropy enabled
+    pub fn set_sampler_anisotropy_override(&mut self, max_anisotropy: Option<f32>) {
+        self.sampler_anisotropy_override = max_anisotropy;
+    }
+
+    pub fn get_sampler_anisotropy_override(&self) -> Option<f32> {
+        self.sampler_anisotropy_override
+    }
+
+    // Material samplers created afterwards add the bias and skip mips above the resolution,
+    // the factory only stores the values since it doesn't know which images are sampled
+    pub fn set_texture_lod_override(&mut self, lod_bias: f32, max_texture_resolution: Option<u32>) {
+        self.texture_lod_bias = lod_bias;
+        self.max_texture_resolution = max_texture_resolution;
+    }
+
+    pub fn get_texture_lod_bias(&self) -> f32 {
+        self.texture_lod_bias
+    }
+
+    pub fn get_max_texture_resolution(&self) -> Option<u32> {
+        self.max_texture_resolution
+    }
+
+    // Dynamic buffers are placed in regular host visible memory afterwards
+    pub fn disable_resizable_bar(&mut self) {
+        self.resizable_bar_memory_type_bits = 0;
+    }
+
+    pub fn is_resizable_bar_enabled(&self) -> bool {
+        self.resizable_bar_memory_type_bits != 0
+    }
+
+    // Buffers created with SHADER_DEVICE_ADDRESS usage can be accessed through GPU pointers in shaders
+    pub fn is_buffer_device_address_enabled(&self) -> bool {
+        self.buffer_device_address_enabled
+    }
+
+    #[doc = "<https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkGetBufferDeviceAddress.html>"]
+    pub fn get_buffer_device_address(&self, buffer: vk::Buffer) -> vk::DeviceAddress {
+        assert!(
+            self.buffer_device_address_enabled,
+            "buffer device address is not enabled"
+        );
+        let address_info = vk::BufferDeviceAddressInfo::builder().buffer(buffer);
+        unsafe {
+            ash_static()
+                .buffer_device_address
+                .get_buffer_device_address_khr(self.device.handle(), &*address_info)
+        }
+    }
+}
+
+// resource tracking
+
+impl DeviceFactory {
+    // Records buffers, images, pipelines and descriptor pools created afterwards with their call sites,
+    // anything that is still alive is reported as leaked in destroy()
+    pub fn enable_resource_tracking(&mut self) {
+        if self.resource_tracker.is_none() {
+            self.resource_tracker = Some(ResourceTracker::new());
+        }
+    }
+
+    pub fn is_resource_tracking_enabled(&self) -> bool {
+        self.resource_tracker.is_some()
+    }
+
+    // Tags are attached to resources created until the matching pop_resource_tag()
+    pub fn push_resource_tag(&mut self, tag: &str) {
+        if let Some(resource_tracker) = &mut self.resource_tracker {
+            resource_tracker.push_tag(tag);
+        }
+    }
+
+    pub fn pop_resource_tag(&mut self) {
+        if let Some(resource_tracker) = &mut self.resource_tracker {
+            resource_tracker.pop_tag();
+        }
+    }
+
+    pub fn get_tracked_resources(&self) -> Vec<&TrackedResource> {
+        match &self.resource_tracker {
+            Some(resource_tracker) => resource_tracker.get_resources(),
+            None => Vec::new(),
+        }
+    }
+
+    #[track_caller]
+    fn track_create(&mut self, resource_type: TrackedResourceType, handle: u64) {
+        if let Some(resource_tracker) = &mut self.resource_tracker {
+            resource_tracker.track_create(resource_type, handle, std::panic::Location::caller());
+        }
+    }
+
+    #[track_caller]
+    fn track_destroy(&mut self, resource_type: TrackedResourceType, handle: u64) {
+        if let Some(resource_tracker) = &mut self.resource_tracker {
+            resource_tracker.track_destroy(resource_type, handle, std::panic::Location::caller());
         }
     }
 }
@@ -58,25 +225,85 @@ impl DeviceFactory {
             .expect("deallocate_memory() failed");
     }
 
+    #[track_caller]
     pub fn allocate_buffer(
         &mut self,
         create_info: &vk::BufferCreateInfo,
         allocate_info: &vk_mem::AllocationCreateInfo,
     ) -> HeapAllocatedResource<vk::Buffer> {
-        let (buffer, alloc, info) = self
-            .allocator
-            .create_buffer(create_info, allocate_info)
-            .expect("allocate_buffer() failed");
+        // VMA refuses buffers with device addresses, they're created and bound here instead
+        let (buffer, alloc, info) = if create_info.usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+            assert!(
+                self.buffer_device_address_enabled,
+                "buffer device address is not enabled"
+            );
+            let buffer = unsafe {
+                self.device
+                    .create_buffer(create_info, None)
+                    .expect("allocate_buffer() failed")
+            };
+            let (alloc, info) = self
+                .allocator
+                .allocate_memory_for_buffer(buffer, allocate_info)
+                .expect("allocate_buffer() failed");
+            self.allocator
+                .bind_buffer_memory(buffer, &alloc)
+                .expect("allocate_buffer() failed");
+            (buffer, alloc, info)
+        } else {
+            self.allocator
+                .create_buffer(create_info, allocate_info)
+                .expect("allocate_buffer() failed")
+        };
+        self.track_create(TrackedResourceType::Buffer, buffer.as_raw());
 
         HeapAllocatedResource(buffer, info, alloc)
     }
 
+    // Buffers that are written by the CPU every frame go to device local memory when resizable BAR is enabled,
+    // the GPU reads them without a staging copy. Otherwise or if the heap is full they stay in host visible memory
+    #[track_caller]
+    pub fn allocate_dynamic_buffer(
+        &mut self,
+        create_info: &vk::BufferCreateInfo,
+        flags: vk_mem::AllocationCreateFlags,
+    ) -> HeapAllocatedResource<vk::Buffer> {
+        if self.resizable_bar_memory_type_bits != 0 {
+            let resizable_bar_allocate_info = vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::CpuToGpu,
+                flags,
+                required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL
+                    | vk::MemoryPropertyFlags::HOST_VISIBLE
+                    | vk::MemoryPropertyFlags::HOST_COHERENT,
+                memory_type_bits: self.resizable_bar_memory_type_bits,
+                ..Default::default()
+            };
+            if let Ok((buffer, alloc, info)) = self.allocator.create_buffer(create_info, &resizable_bar_allocate_info) {
+                self.track_create(TrackedResourceType::Buffer, buffer.as_raw());
+                return HeapAllocatedResource(buffer, info, alloc);
+            }
+            log::warn!("resizable BAR heap is full, falling back to host visible memory");
+        }
+        self.allocate_buffer(
+            create_info,
+            &vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::CpuToGpu,
+                flags,
+                required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[track_caller]
     pub fn deallocate_buffer(&mut self, buffer: &HeapAllocatedResource<vk::Buffer>) {
+        self.track_destroy(TrackedResourceType::Buffer, buffer.0.as_raw());
         self.allocator
             .destroy_buffer(buffer.0, &buffer.2)
             .expect("deallocate_buffer() failed");
     }
 
+    #[track_caller]
     pub fn allocate_image(
         &mut self,
         create_info: &vk::ImageCreateInfo,
@@ -86,16 +313,36 @@ impl DeviceFactory {
             .allocator
             .create_image(create_info, allocate_info)
             .expect("allocate_image() failed");
+        self.track_create(TrackedResourceType::Image, image.as_raw());
 
         HeapAllocatedResource(image, info, alloc)
     }
 
+    #[track_caller]
     pub fn deallocate_image(&mut self, image: &HeapAllocatedResource<vk::Image>) {
+        self.track_destroy(TrackedResourceType::Image, image.0.as_raw());
         self.allocator
             .destroy_image(image.0, &image.2)
             .expect("deallocate_image() failed");
     }
 
+    // Binds the image to a part of an existing allocation, the image is destroyed with destroy_image()
+    // and the memory has to outlive it
+    #[track_caller]
+    pub fn bind_aliased_image(
+        &mut self,
+        image: vk::Image,
+        memory: &HeapAllocatedMemory,
+        offset: vk::DeviceSize,
+    ) -> HeapAllocatedResource<vk::Image> {
+        self.bind_image_memory(
+            image,
+            memory.0.get_device_memory(),
+            memory.0.get_offset() as u64 + offset,
+        );
+        HeapAllocatedResource(image, memory.0.clone(), memory.1)
+    }
+
     pub fn map_allocation_memory<T>(&mut self, item: &HeapAllocatedResource<T>) -> *mut u8 {
         self.allocator.map_memory(&item.2).expect("map_memory() failed")
     }
@@ -105,14 +352,46 @@ impl DeviceFactory {
     }
 }
 
+fn find_resizable_bar_memory_types(memory_properties: &vk::PhysicalDeviceMemoryProperties) -> u32 {
+    let required_flags = vk::MemoryPropertyFlags::DEVICE_LOCAL
+        | vk::MemoryPropertyFlags::HOST_VISIBLE
+        | vk::MemoryPropertyFlags::HOST_COHERENT;
+    let mut memory_type_bits = 0;
+    for (memory_type_index, memory_type) in memory_properties.memory_types
+        [0..memory_properties.memory_type_count as usize]
+        .iter()
+        .enumerate()
+    {
+        let memory_heap = &memory_properties.memory_heaps[memory_type.heap_index as usize];
+        if memory_type.property_flags.contains(required_flags)
+            && memory_heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL)
+            && memory_heap.size > LEGACY_BAR_HEAP_SIZE
+        {
+            memory_type_bits |= 1 << memory_type_index;
+        }
+    }
+    memory_type_bits
+}
+
 impl DeviceFactory {
     // samplers
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCreateSampler.html"]
     pub fn create_sampler(&mut self, create_info: &vk::SamplerCreateInfo) -> vk::Sampler {
+        // Anisotropy is clamped to the device limit and disabled when the device doesn't support it
+        let mut create_info = *create_info;
+        if create_info.anisotropy_enable == vk::TRUE {
+            let max_anisotropy = self
+                .sampler_anisotropy_override
+                .unwrap_or(create_info.max_anisotropy)
+                .min(self.max_sampler_anisotropy);
+            create_info.anisotropy_enable = (max_anisotropy > 1.0) as vk::Bool32;
+            create_info.max_anisotropy = max_anisotropy.max(1.0);
+        }
+
         unsafe {
             self.device
-                .create_sampler(create_info, None)
+                .create_sampler(&create_info, None)
                 .expect("create_sampler() failed")
         }
     }
@@ -198,6 +477,7 @@ impl DeviceFactory {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkAllocateCommandBuffers.html"]
     pub fn free_command_buffers(&mut self, command_pool: vk::CommandPool, command_buffers: &[CommandBuffer]) {
+        track_reset(&command_buffers.iter().map(|item| (*item).into()).collect::<Vec<_>>());
         unsafe {
             self.device.fp_v1_0().free_command_buffers(
                 self.device.handle(),
@@ -289,6 +569,39 @@ impl DeviceFactory {
         }
     }
 
+    // ash only reads one value per query, pipeline statistics queries write one value per enabled statistic
+    pub fn get_query_pool_results_with_stride(
+        &mut self,
+        query_pool: vk::QueryPool,
+        first_query: u32,
+        query_count: u32,
+        values_per_query: usize,
+        data: &mut [u64],
+        flags: vk::QueryResultFlags,
+    ) -> Result<(), vk::Result> {
+        assert!(
+            query_count as usize * values_per_query <= data.len(),
+            "query results don't fit into the slice"
+        );
+        let stride = values_per_query * std::mem::size_of::<u64>();
+        let result = unsafe {
+            self.device.fp_v1_0().get_query_pool_results(
+                self.device.handle(),
+                query_pool,
+                first_query,
+                query_count,
+                query_count as usize * stride,
+                data.as_mut_ptr() as *mut c_void,
+                stride as _,
+                flags | vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        match result {
+            vk::Result::SUCCESS => Ok(()),
+            error => Err(error),
+        }
+    }
+
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkDestroyQueryPool.html"]
     pub fn destroy_query_pool(&mut self, pool: vk::QueryPool) {
         unsafe {
@@ -325,12 +638,17 @@ impl DeviceFactory {
     // images and image views
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCreateImage.html"]
+    #[track_caller]
     pub fn create_image(&mut self, create_info: &vk::ImageCreateInfo) -> vk::Image {
-        unsafe { self.device.create_image(create_info, None).unwrap() }
+        let image = unsafe { self.device.create_image(create_info, None).unwrap() };
+        self.track_create(TrackedResourceType::Image, image.as_raw());
+        image
     }
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkDestroyImage.html"]
+    #[track_caller]
     pub fn destroy_image(&mut self, image: vk::Image) {
+        self.track_destroy(TrackedResourceType::Image, image.as_raw());
         unsafe {
             self.device.destroy_image(image, None);
         }
@@ -372,12 +690,17 @@ impl DeviceFactory {
     // buffers and buffer views
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCreateBuffer.html"]
+    #[track_caller]
     pub fn create_buffer(&mut self, create_info: &vk::BufferCreateInfo) -> vk::Buffer {
-        unsafe { self.device.create_buffer(create_info, None).unwrap() }
+        let buffer = unsafe { self.device.create_buffer(create_info, None).unwrap() };
+        self.track_create(TrackedResourceType::Buffer, buffer.as_raw());
+        buffer
     }
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkDestroyBuffer.html"]
+    #[track_caller]
     pub fn destroy_buffer(&mut self, buffer: vk::Buffer) {
+        self.track_destroy(TrackedResourceType::Buffer, buffer.as_raw());
         unsafe {
             self.device.destroy_buffer(buffer, None);
         }
@@ -437,11 +760,14 @@ impl DeviceFactory {
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCreatePipelineLayout.html"]
     pub fn create_pipeline_layout(&mut self, create_info: &vk::PipelineLayoutCreateInfo) -> vk::PipelineLayout {
-        unsafe { self.device.create_pipeline_layout(create_info, None).unwrap() }
+        let pipeline_layout = unsafe { self.device.create_pipeline_layout(create_info, None).unwrap() };
+        track_pipeline_layout(pipeline_layout, get_push_constant_ranges(create_info));
+        pipeline_layout
     }
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkDestroyPipelineLayout.html"]
     pub fn destroy_pipeline_layout(&mut self, pipeline_layout: vk::PipelineLayout) {
+        untrack_pipeline_layout(pipeline_layout);
         unsafe {
             self.device.destroy_pipeline_layout(pipeline_layout, None);
         }
@@ -465,33 +791,45 @@ impl DeviceFactory {
     }
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCreateGraphicsPipelines.html"]
+    #[track_caller]
     pub fn create_graphics_pipelines(
         &mut self,
         pipeline_cache: vk::PipelineCache,
         create_infos: &[vk::GraphicsPipelineCreateInfo],
     ) -> Vec<vk::Pipeline> {
-        unsafe {
+        let pipelines = unsafe {
             self.device
                 .create_graphics_pipelines(pipeline_cache, create_infos, None)
                 .unwrap()
+        };
+        for pipeline in &pipelines {
+            self.track_create(TrackedResourceType::Pipeline, pipeline.as_raw());
         }
+        pipelines
     }
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCreateComputePipelines.html"]
+    #[track_caller]
     pub fn create_compute_pipelines(
         &mut self,
         pipeline_cache: vk::PipelineCache,
         create_infos: &[vk::ComputePipelineCreateInfo],
     ) -> Vec<vk::Pipeline> {
-        unsafe {
+        let pipelines = unsafe {
             self.device
                 .create_compute_pipelines(pipeline_cache, create_infos, None)
                 .unwrap()
+        };
+        for pipeline in &pipelines {
+            self.track_create(TrackedResourceType::Pipeline, pipeline.as_raw());
         }
+        pipelines
     }
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkDestroyPipeline.html"]
+    #[track_caller]
     pub fn destroy_pipeline(&mut self, pipeline: vk::Pipeline) {
+        self.track_destroy(TrackedResourceType::Pipeline, pipeline.as_raw());
         unsafe {
             self.device.destroy_pipeline(pipeline, None);
         }
@@ -514,12 +852,17 @@ impl DeviceFactory {
     // descriptors and descriptor sets
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCreateDescriptorPool.html"]
+    #[track_caller]
     pub fn create_descriptor_pool(&mut self, create_info: &vk::DescriptorPoolCreateInfo) -> vk::DescriptorPool {
-        unsafe { self.device.create_descriptor_pool(create_info, None).unwrap() }
+        let pool = unsafe { self.device.create_descriptor_pool(create_info, None).unwrap() };
+        self.track_create(TrackedResourceType::DescriptorPool, pool.as_raw());
+        pool
     }
 
     #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkDestroyDescriptorPool.html"]
+    #[track_caller]
     pub fn destroy_descriptor_pool(&mut self, pool: vk::DescriptorPool) {
+        self.track_destroy(TrackedResourceType::DescriptorPool, pool.as_raw());
         unsafe {
             self.device.destroy_descriptor_pool(pool, None);
         }
@@ -576,18 +919,56 @@ impl DeviceFactory {
             self.device.destroy_descriptor_set_layout(layout, None);
         }
     }
+
+    #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCreateDescriptorUpdateTemplate.html"]
+    pub fn create_descriptor_update_template(
+        &mut self,
+        create_info: &vk::DescriptorUpdateTemplateCreateInfo,
+    ) -> vk::DescriptorUpdateTemplate {
+        unsafe {
+            self.device
+                .create_descriptor_update_template(create_info, None)
+                .unwrap()
+        }
+    }
+
+    #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkDestroyDescriptorUpdateTemplate.html"]
+    pub fn destroy_descriptor_update_template(&mut self, descriptor_update_template: vk::DescriptorUpdateTemplate) {
+        unsafe {
+            self.device
+                .destroy_descriptor_update_template(descriptor_update_template, None);
+        }
+    }
+
+    #[doc = "https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkUpdateDescriptorSetWithTemplate.html"]
+    ///
+    /// # Safety
+    /// data is read at the offsets and strides of the template entries, so its layout has to match them.
+    pub unsafe fn update_descriptor_set_with_template<T>(
+        &mut self,
+        descriptor_set: vk::DescriptorSet,
+        descriptor_update_template: vk::DescriptorUpdateTemplate,
+        data: &T,
+    ) {
+        self.device.update_descriptor_set_with_template(
+            descriptor_set,
+            descriptor_update_template,
+            data as *const T as _,
+        );
+    }
 }
 
 // ray tracing nv
 
 impl DeviceFactory {
     #[doc = "<https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCreateRayTracingPipelinesNV.html>"]
+    #[track_caller]
     pub fn create_ray_tracing_pipelines_nv(
         &mut self,
         pipeline_cache: vk::PipelineCache,
         create_info: &[vk::RayTracingPipelineCreateInfoNV],
     ) -> Vec<vk::Pipeline> {
-        unsafe {
+        let pipelines = unsafe {
             use ash::RawPtr;
 
             let allocation_callbacks = None;
@@ -604,7 +985,11 @@ impl DeviceFactory {
                 vk::Result::SUCCESS => pipelines,
                 _ => panic!("create_ray_tracing_pipelines_nv() failed: {:?}", err_code),
             }
+        };
+        for pipeline in &pipelines {
+            self.track_create(TrackedResourceType::Pipeline, pipeline.as_raw());
         }
+        pipelines
     }
 
     #[doc = "<https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCreateAccelerationStructureNV.html>"]
@@ -728,3 +1113,42 @@ impl DeviceFactory {
         }
     }
 }
+
+unsafe fn load_device_address_allocator_device(instance: &ash::Instance, device: &ash::Device) -> ash::Device {
+    let mut instance_fn = instance.fp_v1_0().clone();
+    instance_fn.get_device_proc_addr = get_device_address_allocator_proc_addr;
+    ash::Device::load(&instance_fn, device.handle())
+}
+
+extern "system" fn get_device_address_allocator_proc_addr(
+    device: vk::Device,
+    name: *const c_char,
+) -> vk::PFN_vkVoidFunction {
+    unsafe {
+        if CStr::from_ptr(name).to_bytes() == b"vkAllocateMemory" {
+            return Some(std::mem::transmute::<
+                vk::PFN_vkAllocateMemory,
+                unsafe extern "system" fn() -> c_void,
+            >(allocate_device_address_memory));
+        }
+        (ash_static().get_device_proc_addr)(device, name)
+    }
+}
+
+extern "system" fn allocate_device_address_memory(
+    device: vk::Device,
+    allocate_info: *const vk::MemoryAllocateInfo,
+    allocator: *const vk::AllocationCallbacks,
+    memory: *mut vk::DeviceMemory,
+) -> vk::Result {
+    unsafe {
+        let flags_info = vk::MemoryAllocateFlagsInfo {
+            p_next: (*allocate_info).p_next,
+            flags: vk::MemoryAllocateFlags::DEVICE_ADDRESS,
+            ..Default::default()
+        };
+        let mut allocate_info = *allocate_info;
+        allocate_info.p_next = &flags_info as *const vk::MemoryAllocateFlagsInfo as *const c_void;
+        (ash_static().fp_10.allocate_memory)(device, &allocate_info, allocator, memory)
+    }
+}
@@ -3,6 +3,7 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
 // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use crate::device::*;
 use crate::internal::*;
 
 use ash::vk;
@@ -25,7 +26,8 @@ impl DeviceQueue {
             let error_code = ash_static().fp_10.queue_wait_idle(self.0);
             match error_code {
                 vk::Result::SUCCESS => {}
-                err => panic!("queue_submit() failed: {:?}", err),
+                vk::Result::ERROR_DEVICE_LOST => mark_device_lost("queue_wait_idle()"),
+                err => panic!("queue_wait_idle() failed: {:?}", err),
             }
         }
     }
@@ -38,7 +40,8 @@ impl DeviceQueue {
                 .queue_submit(self.0, submits.len() as _, submits.as_ptr(), fence);
             match error_code {
                 vk::Result::SUCCESS => {}
-                err => panic!("queue_wait_idle() failed: {:?}", err),
+                vk::Result::ERROR_DEVICE_LOST => mark_device_lost("queue_submit()"),
+                err => panic!("queue_submit() failed: {:?}", err),
             }
         }
     }
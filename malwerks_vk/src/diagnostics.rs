@@ -0,0 +1,125 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+const MAX_VALIDATION_MESSAGES: usize = 64;
+const MAX_SHADER_DEBUG_MESSAGES: usize = 1024;
+
+// Process-wide state that is dumped when the application crashes or loses the device
+struct DiagnosticState {
+    device_info: Vec<String>,
+    enabled_features: Vec<String>,
+    enabled_extensions: Vec<String>,
+    validation_messages: VecDeque<String>,
+    shader_debug_messages: VecDeque<String>, // debug printf output, drained by the UI
+    loaded_bundles: Vec<String>,
+    frame_index: u64,
+}
+
+static DIAGNOSTIC_STATE: Mutex<DiagnosticState> = Mutex::new(DiagnosticState {
+    device_info: Vec::new(),
+    enabled_features: Vec::new(),
+    enabled_extensions: Vec::new(),
+    validation_messages: VecDeque::new(),
+    shader_debug_messages: VecDeque::new(),
+    loaded_bundles: Vec::new(),
+    frame_index: 0,
+});
+
+// Reports are written from the panic hook, so poisoning is ignored
+fn lock_diagnostic_state() -> std::sync::MutexGuard<'static, DiagnosticState> {
+    DIAGNOSTIC_STATE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub(crate) fn record_device_info(
+    device_info: Vec<String>,
+    enabled_features: Vec<String>,
+    enabled_extensions: Vec<String>,
+) {
+    let mut state = lock_diagnostic_state();
+    state.device_info = device_info;
+    state.enabled_features = enabled_features;
+    state.enabled_extensions = enabled_extensions;
+}
+
+pub(crate) fn record_validation_message(message: String) {
+    let mut state = lock_diagnostic_state();
+    if state.validation_messages.len() == MAX_VALIDATION_MESSAGES {
+        state.validation_messages.pop_front();
+    }
+    state.validation_messages.push_back(message);
+}
+
+pub(crate) fn record_shader_debug_message(message: String) {
+    let mut state = lock_diagnostic_state();
+    if state.shader_debug_messages.len() == MAX_SHADER_DEBUG_MESSAGES {
+        state.shader_debug_messages.pop_front();
+    }
+    state.shader_debug_messages.push_back(message);
+}
+
+// Returns debug printf messages received since the last call
+pub fn take_shader_debug_messages() -> Vec<String> {
+    lock_diagnostic_state().shader_debug_messages.drain(..).collect()
+}
+
+pub(crate) fn advance_diagnostic_frame_index() {
+    lock_diagnostic_state().frame_index += 1;
+}
+
+pub fn register_loaded_bundle(bundle_name: &str) {
+    lock_diagnostic_state().loaded_bundles.push(bundle_name.to_string());
+}
+
+pub fn unregister_loaded_bundle(bundle_name: &str) {
+    let mut state = lock_diagnostic_state();
+    if let Some(index) = state.loaded_bundles.iter().position(|name| name == bundle_name) {
+        state.loaded_bundles.swap_remove(index);
+    }
+}
+
+// Writes the report into a new timestamped folder and returns its path
+pub fn write_diagnostic_report(output_folder: &std::path::Path, reason: &str) -> std::io::Result<std::path::PathBuf> {
+    use std::io::Write;
+
+    let state = lock_diagnostic_state();
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let report_folder = output_folder.join(format!("crash_{}", seconds));
+    std::fs::create_dir_all(&report_folder)?;
+
+    let mut report = std::io::BufWriter::new(std::fs::File::create(report_folder.join("report.txt"))?);
+    writeln!(report, "reason: {}", reason)?;
+    writeln!(report, "frame index: {}", state.frame_index)?;
+    writeln!(report, "\n[device]")?;
+    for line in &state.device_info {
+        writeln!(report, "{}", line)?;
+    }
+    writeln!(report, "\n[enabled features]")?;
+    for feature in &state.enabled_features {
+        writeln!(report, "{}", feature)?;
+    }
+    writeln!(report, "\n[enabled extensions]")?;
+    for extension in &state.enabled_extensions {
+        writeln!(report, "{}", extension)?;
+    }
+    writeln!(report, "\n[loaded bundles]")?;
+    for bundle_name in &state.loaded_bundles {
+        writeln!(report, "{}", bundle_name)?;
+    }
+    report.flush()?;
+
+    // Validation messages can be long, they're stored separately
+    let mut validation_log = std::io::BufWriter::new(std::fs::File::create(report_folder.join("validation.log"))?);
+    for message in &state.validation_messages {
+        writeln!(validation_log, "{}", message)?;
+    }
+    validation_log.flush()?;
+
+    Ok(report_folder)
+}
@@ -0,0 +1,200 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// VK_KHR_dynamic_rendering is newer than the ash version we're using, so the required types
+// and function pointers are declared here manually. Layouts match vulkan_core.h.
+
+use ash::vk;
+
+use std::ffi::CStr;
+use std::os::raw::c_void;
+
+pub const STRUCTURE_TYPE_RENDERING_INFO_KHR: vk::StructureType = vk::StructureType::from_raw(1_000_044_000);
+pub const STRUCTURE_TYPE_RENDERING_ATTACHMENT_INFO_KHR: vk::StructureType = vk::StructureType::from_raw(1_000_044_001);
+pub const STRUCTURE_TYPE_PIPELINE_RENDERING_CREATE_INFO_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_002);
+pub const STRUCTURE_TYPE_PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES_KHR: vk::StructureType =
+    vk::StructureType::from_raw(1_000_044_003);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/VkRenderingAttachmentInfo.html>"]
+pub struct RenderingAttachmentInfoKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub image_view: vk::ImageView,
+    pub image_layout: vk::ImageLayout,
+    pub resolve_mode: vk::ResolveModeFlags,
+    pub resolve_image_view: vk::ImageView,
+    pub resolve_image_layout: vk::ImageLayout,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    pub clear_value: vk::ClearValue,
+}
+
+impl Default for RenderingAttachmentInfoKHR {
+    fn default() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_RENDERING_ATTACHMENT_INFO_KHR,
+            p_next: std::ptr::null(),
+            image_view: vk::ImageView::null(),
+            image_layout: vk::ImageLayout::UNDEFINED,
+            resolve_mode: vk::ResolveModeFlags::NONE,
+            resolve_image_view: vk::ImageView::null(),
+            resolve_image_layout: vk::ImageLayout::UNDEFINED,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            clear_value: vk::ClearValue::default(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+#[doc = "<https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/VkRenderingInfo.html>"]
+pub struct RenderingInfoKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub flags: vk::Flags,
+    pub render_area: vk::Rect2D,
+    pub layer_count: u32,
+    pub view_mask: u32,
+    pub color_attachment_count: u32,
+    pub p_color_attachments: *const RenderingAttachmentInfoKHR,
+    pub p_depth_attachment: *const RenderingAttachmentInfoKHR,
+    pub p_stencil_attachment: *const RenderingAttachmentInfoKHR,
+}
+
+impl Default for RenderingInfoKHR {
+    fn default() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_RENDERING_INFO_KHR,
+            p_next: std::ptr::null(),
+            flags: 0,
+            render_area: vk::Rect2D::default(),
+            layer_count: 1,
+            view_mask: 0,
+            color_attachment_count: 0,
+            p_color_attachments: std::ptr::null(),
+            p_depth_attachment: std::ptr::null(),
+            p_stencil_attachment: std::ptr::null(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+#[doc = "<https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/VkPipelineRenderingCreateInfo.html>"]
+pub struct PipelineRenderingCreateInfoKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *const c_void,
+    pub view_mask: u32,
+    pub color_attachment_count: u32,
+    pub p_color_attachment_formats: *const vk::Format,
+    pub depth_attachment_format: vk::Format,
+    pub stencil_attachment_format: vk::Format,
+}
+
+impl Default for PipelineRenderingCreateInfoKHR {
+    fn default() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_PIPELINE_RENDERING_CREATE_INFO_KHR,
+            p_next: std::ptr::null(),
+            view_mask: 0,
+            color_attachment_count: 0,
+            p_color_attachment_formats: std::ptr::null(),
+            depth_attachment_format: vk::Format::UNDEFINED,
+            stencil_attachment_format: vk::Format::UNDEFINED,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsGraphicsPipelineCreateInfo for PipelineRenderingCreateInfoKHR {}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+#[doc = "<https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/VkPhysicalDeviceDynamicRenderingFeatures.html>"]
+pub struct PhysicalDeviceDynamicRenderingFeaturesKHR {
+    pub s_type: vk::StructureType,
+    pub p_next: *mut c_void,
+    pub dynamic_rendering: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceDynamicRenderingFeaturesKHR {
+    fn default() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES_KHR,
+            p_next: std::ptr::null_mut(),
+            dynamic_rendering: vk::FALSE,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceDynamicRenderingFeaturesKHR {}
+
+#[allow(non_camel_case_types)]
+type PFN_vkCmdBeginRenderingKHR =
+    extern "system" fn(command_buffer: vk::CommandBuffer, p_rendering_info: *const RenderingInfoKHR);
+#[allow(non_camel_case_types)]
+type PFN_vkCmdEndRenderingKHR = extern "system" fn(command_buffer: vk::CommandBuffer);
+
+#[derive(Clone)]
+pub(crate) struct KhrDynamicRenderingFn {
+    cmd_begin_rendering_khr: PFN_vkCmdBeginRenderingKHR,
+    cmd_end_rendering_khr: PFN_vkCmdEndRenderingKHR,
+}
+
+impl KhrDynamicRenderingFn {
+    pub fn name() -> &'static CStr {
+        CStr::from_bytes_with_nul(b"VK_KHR_dynamic_rendering\0").expect("Wrong extension string")
+    }
+
+    pub fn load<F>(mut _f: F) -> Self
+    where
+        F: FnMut(&CStr) -> *const c_void,
+    {
+        Self {
+            cmd_begin_rendering_khr: unsafe {
+                extern "system" fn cmd_begin_rendering_khr(
+                    _command_buffer: vk::CommandBuffer,
+                    _p_rendering_info: *const RenderingInfoKHR,
+                ) {
+                    panic!("Unable to load cmd_begin_rendering_khr")
+                }
+                let raw_name = CStr::from_bytes_with_nul_unchecked(b"vkCmdBeginRenderingKHR\0");
+                let val = _f(raw_name);
+                if val.is_null() {
+                    cmd_begin_rendering_khr
+                } else {
+                    std::mem::transmute::<*const c_void, PFN_vkCmdBeginRenderingKHR>(val)
+                }
+            },
+            cmd_end_rendering_khr: unsafe {
+                extern "system" fn cmd_end_rendering_khr(_command_buffer: vk::CommandBuffer) {
+                    panic!("Unable to load cmd_end_rendering_khr")
+                }
+                let raw_name = CStr::from_bytes_with_nul_unchecked(b"vkCmdEndRenderingKHR\0");
+                let val = _f(raw_name);
+                if val.is_null() {
+                    cmd_end_rendering_khr
+                } else {
+                    std::mem::transmute::<*const c_void, PFN_vkCmdEndRenderingKHR>(val)
+                }
+            },
+        }
+    }
+
+    pub unsafe fn cmd_begin_rendering_khr(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        p_rendering_info: *const RenderingInfoKHR,
+    ) {
+        (self.cmd_begin_rendering_khr)(command_buffer, p_rendering_info)
+    }
+
+    pub unsafe fn cmd_end_rendering_khr(&self, command_buffer: vk::CommandBuffer) {
+        (self.cmd_end_rendering_khr)(command_buffer)
+    }
+}
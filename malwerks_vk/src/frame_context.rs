@@ -3,18 +3,115 @@
 // This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
 // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-pub const NUM_BUFFERED_GPU_FRAMES: usize = 3;
+use std::sync::{Arc, Condvar, Mutex};
+
+pub const DEFAULT_NUM_BUFFERED_GPU_FRAMES: usize = 3;
+
+// Stages every frame goes through, each of them can run on a different thread
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameStage {
+    Simulate,
+    Record,
+    Present,
+}
+
+const FRAME_STAGE_COUNT: usize = 3;
+
+// Last frame that finished each stage, shared between the threads of the frame pipeline.
+// Frame indices only grow, waiting for a frame also waits for all frames before it.
+pub struct FrameStageSync {
+    completed_frames: Mutex<[Option<u64>; FRAME_STAGE_COUNT]>,
+    stage_completed: Condvar,
+}
+
+impl Default for FrameStageSync {
+    fn default() -> Self {
+        Self {
+            completed_frames: Mutex::new([None; FRAME_STAGE_COUNT]),
+            stage_completed: Condvar::new(),
+        }
+    }
+}
+
+impl FrameStageSync {
+    pub fn signal(&self, stage: FrameStage, frame_index: u64) {
+        let mut completed_frames = self.completed_frames.lock().unwrap();
+        let completed_frame = &mut completed_frames[stage as usize];
+        assert!(
+            *completed_frame < Some(frame_index),
+            "{:?} stage of frame {} signaled out of order",
+            stage,
+            frame_index
+        );
+        *completed_frame = Some(frame_index);
+        self.stage_completed.notify_all();
+    }
+
+    pub fn wait(&self, stage: FrameStage, frame_index: u64) {
+        let mut completed_frames = self.completed_frames.lock().unwrap();
+        while !is_frame_completed(&completed_frames, stage, frame_index) {
+            completed_frames = self.stage_completed.wait(completed_frames).unwrap();
+        }
+    }
+
+    pub fn is_completed(&self, stage: FrameStage, frame_index: u64) -> bool {
+        is_frame_completed(&self.completed_frames.lock().unwrap(), stage, frame_index)
+    }
+}
 
 pub struct FrameContext {
     pub(crate) current_gpu_frame: usize,
+    pub(crate) num_buffered_frames: usize,
+    pub(crate) frame_index: u64,
+    pub(crate) stage_sync: Arc<FrameStageSync>,
 }
 
 impl FrameContext {
-    pub(crate) fn new(current_gpu_frame: usize) -> Self {
-        Self { current_gpu_frame }
+    pub(crate) fn new(
+        current_gpu_frame: usize,
+        num_buffered_frames: usize,
+        frame_index: u64,
+        stage_sync: Arc<FrameStageSync>,
+    ) -> Self {
+        Self {
+            current_gpu_frame,
+            num_buffered_frames,
+            frame_index,
+            stage_sync,
+        }
     }
 
     pub fn current_gpu_frame(&self) -> usize {
         self.current_gpu_frame
     }
+
+    pub fn num_buffered_frames(&self) -> usize {
+        self.num_buffered_frames
+    }
+
+    // Monotonic frame counter, unlike current_gpu_frame() it never wraps around
+    pub fn frame_index(&self) -> u64 {
+        self.frame_index
+    }
+
+    pub fn signal_stage(&self, stage: FrameStage) {
+        self.stage_sync.signal(stage, self.frame_index);
+    }
+
+    pub fn wait_for_stage(&self, stage: FrameStage) {
+        self.stage_sync.wait(stage, self.frame_index);
+    }
+
+    // Threads outside of the render thread keep their own reference to the stage sync
+    pub fn get_stage_sync(&self) -> &Arc<FrameStageSync> {
+        &self.stage_sync
+    }
+}
+
+fn is_frame_completed(
+    completed_frames: &[Option<u64>; FRAME_STAGE_COUNT],
+    stage: FrameStage,
+    frame_index: u64,
+) -> bool {
+    completed_frames[stage as usize] >= Some(frame_index)
 }
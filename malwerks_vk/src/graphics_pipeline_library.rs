@@ -0,0 +1,73 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// VK_EXT_graphics_pipeline_library is newer than the ash version we're using, so the required types
+// are declared here manually. Layouts match vulkan_core.h. The extension has no commands, libraries
+// are created and linked with vkCreateGraphicsPipelines.
+
+use ash::vk;
+
+use std::ffi::CStr;
+use std::os::raw::c_void;
+
+pub const STRUCTURE_TYPE_PHYSICAL_DEVICE_GRAPHICS_PIPELINE_LIBRARY_FEATURES_EXT: vk::StructureType =
+    vk::StructureType::from_raw(1_000_320_000);
+pub const STRUCTURE_TYPE_GRAPHICS_PIPELINE_LIBRARY_CREATE_INFO_EXT: vk::StructureType =
+    vk::StructureType::from_raw(1_000_320_002);
+
+pub const GRAPHICS_PIPELINE_LIBRARY_VERTEX_INPUT_INTERFACE_EXT: vk::Flags = 0x1;
+pub const GRAPHICS_PIPELINE_LIBRARY_PRE_RASTERIZATION_SHADERS_EXT: vk::Flags = 0x2;
+pub const GRAPHICS_PIPELINE_LIBRARY_FRAGMENT_SHADER_EXT: vk::Flags = 0x4;
+pub const GRAPHICS_PIPELINE_LIBRARY_FRAGMENT_OUTPUT_INTERFACE_EXT: vk::Flags = 0x8;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+#[doc = "<https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/VkGraphicsPipelineLibraryCreateInfoEXT.html>"]
+pub struct GraphicsPipelineLibraryCreateInfoEXT {
+    pub s_type: vk::StructureType,
+    pub p_next: *mut c_void,
+    pub flags: vk::Flags,
+}
+
+impl Default for GraphicsPipelineLibraryCreateInfoEXT {
+    fn default() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_GRAPHICS_PIPELINE_LIBRARY_CREATE_INFO_EXT,
+            p_next: std::ptr::null_mut(),
+            flags: 0,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsGraphicsPipelineCreateInfo for GraphicsPipelineLibraryCreateInfoEXT {}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+#[doc = "<https://www.khronos.org/registry/vulkan/specs/1.3-extensions/man/html/VkPhysicalDeviceGraphicsPipelineLibraryFeaturesEXT.html>"]
+pub struct PhysicalDeviceGraphicsPipelineLibraryFeaturesEXT {
+    pub s_type: vk::StructureType,
+    pub p_next: *mut c_void,
+    pub graphics_pipeline_library: vk::Bool32,
+}
+
+impl Default for PhysicalDeviceGraphicsPipelineLibraryFeaturesEXT {
+    fn default() -> Self {
+        Self {
+            s_type: STRUCTURE_TYPE_PHYSICAL_DEVICE_GRAPHICS_PIPELINE_LIBRARY_FEATURES_EXT,
+            p_next: std::ptr::null_mut(),
+            graphics_pipeline_library: vk::FALSE,
+        }
+    }
+}
+
+unsafe impl vk::ExtendsDeviceCreateInfo for PhysicalDeviceGraphicsPipelineLibraryFeaturesEXT {}
+
+pub(crate) struct ExtGraphicsPipelineLibraryFn {}
+
+impl ExtGraphicsPipelineLibraryFn {
+    pub fn name() -> &'static CStr {
+        CStr::from_bytes_with_nul(b"VK_EXT_graphics_pipeline_library\0").expect("Wrong extension string")
+    }
+}
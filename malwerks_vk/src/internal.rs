@@ -5,11 +5,18 @@
 
 use ash::vk;
 
+use crate::dynamic_rendering::*;
+
 pub(crate) struct AshStatic {
     pub fp_10: vk::DeviceFnV1_0,
     pub fp_11: vk::DeviceFnV1_1,
     pub draw_indirect_count: vk::KhrDrawIndirectCountFn,
     pub ray_tracing_nv: vk::NvRayTracingFn,
+    pub push_descriptor: vk::KhrPushDescriptorFn,
+    pub dynamic_rendering: KhrDynamicRenderingFn,
+    pub conditional_rendering: vk::ExtConditionalRenderingFn,
+    pub buffer_device_address: vk::KhrBufferDeviceAddressFn,
+    pub get_device_proc_addr: vk::PFN_vkGetDeviceProcAddr, // used to load device functions with hooks
 }
 
 static mut ASH_STATIC: Option<AshStatic> = None;
@@ -19,21 +26,15 @@ pub(crate) unsafe fn ash_static() -> &'static AshStatic {
     ASH_STATIC.as_ref().unwrap()
 }
 
-pub(crate) unsafe fn ash_static_init(
-    fp_10: vk::DeviceFnV1_0,
-    fp_11: vk::DeviceFnV1_1,
-    draw_indirect_count: vk::KhrDrawIndirectCountFn,
-    ray_tracing_nv: vk::NvRayTracingFn,
-) {
+pub(crate) unsafe fn ash_static_init(ash_static: AshStatic) {
     match ASH_STATIC {
         None => {
-            ASH_STATIC = Some(AshStatic {
-                fp_10,
-                fp_11,
-                draw_indirect_count,
-                ray_tracing_nv,
-            });
+            ASH_STATIC = Some(ash_static);
         }
         Some(_) => panic!("ash static data initialized twice"),
     }
 }
+
+pub(crate) unsafe fn ash_static_reset() {
+    ASH_STATIC = None;
+}
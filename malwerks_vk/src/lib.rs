@@ -4,19 +4,38 @@
 // If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 mod command_buffer;
+mod command_buffer_validation;
 mod device;
 mod device_factory;
 mod device_queue;
+mod diagnostics;
+mod dynamic_rendering;
 mod frame_context;
+mod graphics_pipeline_library;
+mod query_scope;
+mod resource_tracking;
+mod surface_provider;
 mod utils;
 
+#[cfg(feature = "mock")]
+mod mock_device;
+
 pub use command_buffer::*;
 pub use device::*;
 pub use device_factory::*;
 pub use device_queue::*;
+pub use diagnostics::*;
+pub use dynamic_rendering::*;
 pub use frame_context::*;
+pub use graphics_pipeline_library::*;
+pub use query_scope::*;
+pub use resource_tracking::*;
+pub use surface_provider::*;
 pub use utils::*;
 
+#[cfg(feature = "mock")]
+pub use mock_device::*;
+
 pub use ash::vk;
 pub use vk_mem;
 
@@ -0,0 +1,995 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// CPU-only Vulkan device for unit tests.
+// Mock entry points are plugged into the same function tables the real device uses, so DeviceFactory,
+// CommandBuffer, DeviceQueue and VMA work unmodified. Calls that matter for tests are recorded,
+// any entry point that isn't mocked panics with "Unable to load ..." when called.
+
+use ash::version::*;
+use ash::vk;
+use ash::vk::Handle;
+
+use std::collections::HashMap;
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::command_buffer::*;
+use crate::device_factory::*;
+use crate::device_queue::*;
+use crate::dynamic_rendering::*;
+use crate::internal::*;
+
+const MOCK_HEAP_SIZE: vk::DeviceSize = 256 * 1024 * 1024;
+const MOCK_MEMORY_ALIGNMENT: vk::DeviceSize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockCall {
+    AllocateMemory {
+        memory: vk::DeviceMemory,
+        size: vk::DeviceSize,
+        device_address: bool, // VK_MEMORY_ALLOCATE_DEVICE_ADDRESS_BIT is in the chain
+    },
+    FreeMemory {
+        memory: vk::DeviceMemory,
+    },
+    CreateBuffer {
+        buffer: vk::Buffer,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    },
+    DestroyBuffer {
+        buffer: vk::Buffer,
+    },
+    CreateImage {
+        image: vk::Image,
+        format: vk::Format,
+        extent: (u32, u32, u32),
+        mip_levels: u32,
+        array_layers: u32,
+    },
+    DestroyImage {
+        image: vk::Image,
+    },
+    CreateImageView {
+        image_view: vk::ImageView,
+        image: vk::Image,
+    },
+    DestroyImageView {
+        image_view: vk::ImageView,
+    },
+    CreateSampler {
+        sampler: vk::Sampler,
+        max_anisotropy: Option<f32>,
+        mip_lod_bias: f32,
+        min_lod: f32,
+    },
+    DestroySampler {
+        sampler: vk::Sampler,
+    },
+    CreateDescriptorSetLayout {
+        layout: vk::DescriptorSetLayout,
+        binding_count: u32,
+    },
+    DestroyDescriptorSetLayout {
+        layout: vk::DescriptorSetLayout,
+    },
+    CreateDescriptorPool {
+        pool: vk::DescriptorPool,
+        max_sets: u32,
+    },
+    DestroyDescriptorPool {
+        pool: vk::DescriptorPool,
+    },
+    AllocateDescriptorSets {
+        pool: vk::DescriptorPool,
+        descriptor_sets: Vec<vk::DescriptorSet>,
+    },
+    WriteDescriptorSet {
+        descriptor_set: vk::DescriptorSet,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        image_views: Vec<vk::ImageView>,
+        buffers: Vec<vk::Buffer>,
+    },
+    ResetCommandBuffer {
+        command_buffer: vk::CommandBuffer,
+    },
+    BeginCommandBuffer {
+        command_buffer: vk::CommandBuffer,
+    },
+    EndCommandBuffer {
+        command_buffer: vk::CommandBuffer,
+    },
+    PipelineBarrier {
+        command_buffer: vk::CommandBuffer,
+        src_stage_mask: vk::PipelineStageFlags,
+        dst_stage_mask: vk::PipelineStageFlags,
+        buffers: Vec<vk::Buffer>,
+        images: Vec<(vk::Image, vk::ImageLayout, vk::ImageLayout)>, // image, old_layout, new_layout
+    },
+    CopyBuffer {
+        command_buffer: vk::CommandBuffer,
+        src_buffer: vk::Buffer,
+        dst_buffer: vk::Buffer,
+        region_count: u32,
+    },
+    CopyBufferToImage {
+        command_buffer: vk::CommandBuffer,
+        src_buffer: vk::Buffer,
+        dst_image: vk::Image,
+        dst_image_layout: vk::ImageLayout,
+        region_count: u32,
+    },
+    BlitImage {
+        command_buffer: vk::CommandBuffer,
+        src_image: vk::Image,
+        dst_image: vk::Image,
+        mip_levels: Vec<(u32, u32)>, // source and destination mip of every region
+    },
+    BindPipeline {
+        command_buffer: vk::CommandBuffer,
+        pipeline_bind_point: vk::PipelineBindPoint,
+        pipeline: vk::Pipeline,
+    },
+    BindDescriptorSets {
+        command_buffer: vk::CommandBuffer,
+        first_set: u32,
+        descriptor_sets: Vec<vk::DescriptorSet>,
+    },
+    Draw {
+        command_buffer: vk::CommandBuffer,
+        vertex_count: u32,
+        instance_count: u32,
+    },
+    DrawIndexed {
+        command_buffer: vk::CommandBuffer,
+        index_count: u32,
+        instance_count: u32,
+    },
+    BeginConditionalRendering {
+        command_buffer: vk::CommandBuffer,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        inverted: bool,
+    },
+    EndConditionalRendering {
+        command_buffer: vk::CommandBuffer,
+    },
+    Dispatch {
+        command_buffer: vk::CommandBuffer,
+        group_count: (u32, u32, u32),
+    },
+    QueueSubmit {
+        queue: vk::Queue,
+        command_buffers: Vec<vk::CommandBuffer>,
+    },
+    QueueWaitIdle {
+        queue: vk::Queue,
+    },
+}
+
+struct MockState {
+    next_handle: u64,
+    memory: HashMap<vk::DeviceMemory, Vec<u8>>,
+    buffer_sizes: HashMap<vk::Buffer, vk::DeviceSize>,
+    image_sizes: HashMap<vk::Image, vk::DeviceSize>,
+    calls: Vec<MockCall>,
+}
+
+impl MockState {
+    fn create_handle<T: vk::Handle>(&mut self) -> T {
+        self.next_handle += 1;
+        T::from_raw(self.next_handle)
+    }
+}
+
+// Only one mock device can exist at a time, function tables are global
+static MOCK_DEVICE_LOCK: Mutex<()> = Mutex::new(());
+static MOCK_STATE: Mutex<Option<MockState>> = Mutex::new(None);
+
+fn with_mock_state<F: FnOnce(&mut MockState)>(func: F) {
+    let mut state = MOCK_STATE.lock().unwrap_or_else(|error| error.into_inner());
+    if let Some(state) = state.as_mut() {
+        func(state);
+    }
+}
+
+pub struct MockDevice {
+    instance: ash::Instance,
+    device: ash::Device,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Drop for MockDevice {
+    fn drop(&mut self) {
+        unsafe {
+            ash_static_reset();
+        }
+        *MOCK_STATE.lock().unwrap_or_else(|error| error.into_inner()) = None;
+    }
+}
+
+impl Default for MockDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockDevice {
+    pub fn new() -> Self {
+        let lock = MOCK_DEVICE_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        *MOCK_STATE.lock().unwrap_or_else(|error| error.into_inner()) = Some(MockState {
+            next_handle: 0,
+            memory: HashMap::new(),
+            buffer_sizes: HashMap::new(),
+            image_sizes: HashMap::new(),
+            calls: Vec::new(),
+        });
+
+        unsafe {
+            let static_fn = vk::StaticFn {
+                get_instance_proc_addr: mock_get_instance_proc_addr,
+            };
+            let instance = ash::Instance::load(&static_fn, vk::Instance::from_raw(1));
+            let device = ash::Device::load(instance.fp_v1_0(), vk::Device::from_raw(1));
+
+            let load_device_function =
+                |name: &CStr| std::mem::transmute::<vk::PFN_vkVoidFunction, *const c_void>(find_mock_function(name));
+            ash_static_init(AshStatic {
+                fp_10: device.fp_v1_0().clone(),
+                fp_11: device.fp_v1_1().clone(),
+                draw_indirect_count: vk::KhrDrawIndirectCountFn::load(load_device_function),
+                ray_tracing_nv: vk::NvRayTracingFn::load(load_device_function),
+                push_descriptor: vk::KhrPushDescriptorFn::load(load_device_function),
+                dynamic_rendering: KhrDynamicRenderingFn::load(load_device_function),
+                conditional_rendering: vk::ExtConditionalRenderingFn::load(load_device_function),
+                buffer_device_address: vk::KhrBufferDeviceAddressFn::load(load_device_function),
+                get_device_proc_addr: instance.fp_v1_0().get_device_proc_addr,
+            });
+
+            Self {
+                instance,
+                device,
+                _lock: lock,
+            }
+        }
+    }
+
+    pub fn create_factory(&self) -> DeviceFactory {
+        DeviceFactory::new(
+            self.device.clone(),
+            self.instance.clone(),
+            vk::PhysicalDevice::from_raw(1),
+            1,
+            16.0,
+            false,
+        )
+    }
+
+    pub fn create_factory_with_buffer_device_address(&self) -> DeviceFactory {
+        DeviceFactory::new(
+            self.device.clone(),
+            self.instance.clone(),
+            vk::PhysicalDevice::from_raw(1),
+            1,
+            16.0,
+            true,
+        )
+    }
+
+    pub fn get_queue(&self) -> DeviceQueue {
+        DeviceQueue(vk::Queue::from_raw(1))
+    }
+
+    // Command buffers don't need a pool, they are only used for recording
+    pub fn create_command_buffer(&self) -> CommandBuffer {
+        let mut command_buffer = vk::CommandBuffer::null();
+        with_mock_state(|state| command_buffer = state.create_handle());
+        CommandBuffer::from_raw(command_buffer.as_raw())
+    }
+
+    // Returns all calls recorded since the last time this was called
+    pub fn take_calls(&self) -> Vec<MockCall> {
+        let mut calls = Vec::new();
+        with_mock_state(|state| calls = std::mem::take(&mut state.calls));
+        calls
+    }
+}
+
+unsafe fn find_mock_function(name: &CStr) -> vk::PFN_vkVoidFunction {
+    let function: *const c_void = match name.to_bytes() {
+        b"vkGetDeviceProcAddr" => mock_get_device_proc_addr as *const c_void,
+        b"vkGetPhysicalDeviceProperties" => get_physical_device_properties as *const c_void,
+        b"vkGetPhysicalDeviceMemoryProperties" => get_physical_device_memory_properties as *const c_void,
+        b"vkAllocateMemory" => allocate_memory as *const c_void,
+        b"vkFreeMemory" => free_memory as *const c_void,
+        b"vkMapMemory" => map_memory as *const c_void,
+        b"vkUnmapMemory" => unmap_memory as *const c_void,
+        b"vkFlushMappedMemoryRanges" => mapped_memory_ranges as *const c_void,
+        b"vkInvalidateMappedMemoryRanges" => mapped_memory_ranges as *const c_void,
+        b"vkCreateBuffer" => create_buffer as *const c_void,
+        b"vkDestroyBuffer" => destroy_buffer as *const c_void,
+        b"vkGetBufferDeviceAddressKHR" => get_buffer_device_address as *const c_void,
+        b"vkGetBufferMemoryRequirements" => get_buffer_memory_requirements as *const c_void,
+        b"vkBindBufferMemory" => bind_buffer_memory as *const c_void,
+        b"vkCreateImage" => create_image as *const c_void,
+        b"vkDestroyImage" => destroy_image as *const c_void,
+        b"vkGetImageMemoryRequirements" => get_image_memory_requirements as *const c_void,
+        b"vkBindImageMemory" => bind_image_memory as *const c_void,
+        b"vkCreateImageView" => create_image_view as *const c_void,
+        b"vkDestroyImageView" => destroy_image_view as *const c_void,
+        b"vkCreateSampler" => create_sampler as *const c_void,
+        b"vkDestroySampler" => destroy_sampler as *const c_void,
+        b"vkCreateDescriptorSetLayout" => create_descriptor_set_layout as *const c_void,
+        b"vkDestroyDescriptorSetLayout" => destroy_descriptor_set_layout as *const c_void,
+        b"vkCreateDescriptorPool" => create_descriptor_pool as *const c_void,
+        b"vkDestroyDescriptorPool" => destroy_descriptor_pool as *const c_void,
+        b"vkAllocateDescriptorSets" => allocate_descriptor_sets as *const c_void,
+        b"vkUpdateDescriptorSets" => update_descriptor_sets as *const c_void,
+        b"vkResetCommandBuffer" => reset_command_buffer as *const c_void,
+        b"vkBeginCommandBuffer" => begin_command_buffer as *const c_void,
+        b"vkEndCommandBuffer" => end_command_buffer as *const c_void,
+        b"vkCmdPipelineBarrier" => cmd_pipeline_barrier as *const c_void,
+        b"vkCmdCopyBuffer" => cmd_copy_buffer as *const c_void,
+        b"vkCmdCopyBufferToImage" => cmd_copy_buffer_to_image as *const c_void,
+        b"vkCmdBlitImage" => cmd_blit_image as *const c_void,
+        b"vkCmdBindPipeline" => cmd_bind_pipeline as *const c_void,
+        b"vkCmdBindDescriptorSets" => cmd_bind_descriptor_sets as *const c_void,
+        b"vkCmdDraw" => cmd_draw as *const c_void,
+        b"vkCmdDrawIndexed" => cmd_draw_indexed as *const c_void,
+        b"vkCmdBeginConditionalRenderingEXT" => cmd_begin_conditional_rendering as *const c_void,
+        b"vkCmdEndConditionalRenderingEXT" => cmd_end_conditional_rendering as *const c_void,
+        b"vkCmdDispatch" => cmd_dispatch as *const c_void,
+        b"vkQueueSubmit" => queue_submit as *const c_void,
+        b"vkQueueWaitIdle" => queue_wait_idle as *const c_void,
+        _ => return None,
+    };
+    Some(std::mem::transmute::<
+        *const c_void,
+        unsafe extern "system" fn() -> c_void,
+    >(function))
+}
+
+extern "system" fn mock_get_instance_proc_addr(_instance: vk::Instance, name: *const c_char) -> vk::PFN_vkVoidFunction {
+    unsafe { find_mock_function(CStr::from_ptr(name)) }
+}
+
+extern "system" fn mock_get_device_proc_addr(_device: vk::Device, name: *const c_char) -> vk::PFN_vkVoidFunction {
+    unsafe { find_mock_function(CStr::from_ptr(name)) }
+}
+
+// Single heap and memory type that is both device local and host visible
+unsafe extern "system" fn get_physical_device_properties(
+    _physical_device: vk::PhysicalDevice,
+    properties: *mut vk::PhysicalDeviceProperties,
+) {
+    let mut mock_properties = vk::PhysicalDeviceProperties {
+        api_version: vk::make_version(1, 2, 0),
+        device_type: vk::PhysicalDeviceType::CPU,
+        ..Default::default()
+    };
+    mock_properties.limits.max_memory_allocation_count = 4096;
+    mock_properties.limits.buffer_image_granularity = 1;
+    mock_properties.limits.non_coherent_atom_size = 1;
+    *properties = mock_properties;
+}
+
+unsafe extern "system" fn get_physical_device_memory_properties(
+    _physical_device: vk::PhysicalDevice,
+    memory_properties: *mut vk::PhysicalDeviceMemoryProperties,
+) {
+    let mut mock_memory_properties = vk::PhysicalDeviceMemoryProperties {
+        memory_type_count: 1,
+        memory_heap_count: 1,
+        ..Default::default()
+    };
+    mock_memory_properties.memory_types[0] = vk::MemoryType {
+        property_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL
+            | vk::MemoryPropertyFlags::HOST_VISIBLE
+            | vk::MemoryPropertyFlags::HOST_COHERENT
+            | vk::MemoryPropertyFlags::HOST_CACHED,
+        heap_index: 0,
+    };
+    mock_memory_properties.memory_heaps[0] = vk::MemoryHeap {
+        size: MOCK_HEAP_SIZE,
+        flags: vk::MemoryHeapFlags::DEVICE_LOCAL,
+    };
+    *memory_properties = mock_memory_properties;
+}
+
+unsafe extern "system" fn allocate_memory(
+    _device: vk::Device,
+    allocate_info: *const vk::MemoryAllocateInfo,
+    _allocator: *const vk::AllocationCallbacks,
+    memory: *mut vk::DeviceMemory,
+) -> vk::Result {
+    let size = (*allocate_info).allocation_size;
+    let mut device_address = false;
+    let mut next = (*allocate_info).p_next as *const vk::BaseInStructure;
+    while !next.is_null() {
+        if (*next).s_type == vk::StructureType::MEMORY_ALLOCATE_FLAGS_INFO {
+            let flags_info = &*(next as *const vk::MemoryAllocateFlagsInfo);
+            device_address |= flags_info.flags.contains(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+        }
+        next = (*next).p_next;
+    }
+    with_mock_state(|state| {
+        let new_memory = state.create_handle();
+        state.memory.insert(new_memory, vec![0u8; size as usize]);
+        state.calls.push(MockCall::AllocateMemory {
+            memory: new_memory,
+            size,
+            device_address,
+        });
+        *memory = new_memory;
+    });
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn free_memory(
+    _device: vk::Device,
+    memory: vk::DeviceMemory,
+    _allocator: *const vk::AllocationCallbacks,
+) {
+    with_mock_state(|state| {
+        state.memory.remove(&memory);
+        state.calls.push(MockCall::FreeMemory { memory });
+    });
+}
+
+unsafe extern "system" fn map_memory(
+    _device: vk::Device,
+    memory: vk::DeviceMemory,
+    offset: vk::DeviceSize,
+    _size: vk::DeviceSize,
+    _flags: vk::MemoryMapFlags,
+    data: *mut *mut c_void,
+) -> vk::Result {
+    let mut result = vk::Result::ERROR_MEMORY_MAP_FAILED;
+    with_mock_state(|state| {
+        if let Some(mock_memory) = state.memory.get_mut(&memory) {
+            *data = mock_memory.as_mut_ptr().add(offset as usize) as _;
+            result = vk::Result::SUCCESS;
+        }
+    });
+    result
+}
+
+unsafe extern "system" fn unmap_memory(_device: vk::Device, _memory: vk::DeviceMemory) {}
+
+unsafe extern "system" fn mapped_memory_ranges(
+    _device: vk::Device,
+    _memory_range_count: u32,
+    _memory_ranges: *const vk::MappedMemoryRange,
+) -> vk::Result {
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn create_buffer(
+    _device: vk::Device,
+    create_info: *const vk::BufferCreateInfo,
+    _allocator: *const vk::AllocationCallbacks,
+    buffer: *mut vk::Buffer,
+) -> vk::Result {
+    let create_info = &*create_info;
+    with_mock_state(|state| {
+        let new_buffer = state.create_handle();
+        state.buffer_sizes.insert(new_buffer, create_info.size);
+        state.calls.push(MockCall::CreateBuffer {
+            buffer: new_buffer,
+            size: create_info.size,
+            usage: create_info.usage,
+        });
+        *buffer = new_buffer;
+    });
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn destroy_buffer(
+    _device: vk::Device,
+    buffer: vk::Buffer,
+    _allocator: *const vk::AllocationCallbacks,
+) {
+    with_mock_state(|state| {
+        state.buffer_sizes.remove(&buffer);
+        state.calls.push(MockCall::DestroyBuffer { buffer });
+    });
+}
+
+unsafe extern "system" fn get_buffer_memory_requirements(
+    _device: vk::Device,
+    buffer: vk::Buffer,
+    memory_requirements: *mut vk::MemoryRequirements,
+) {
+    let mut size = 0;
+    with_mock_state(|state| size = state.buffer_sizes.get(&buffer).copied().unwrap_or(0));
+    *memory_requirements = get_mock_memory_requirements(size);
+}
+
+// Addresses keep the buffer handle in the upper dword, tests can map them back to buffers
+unsafe extern "system" fn get_buffer_device_address(
+    _device: vk::Device,
+    address_info: *const vk::BufferDeviceAddressInfo,
+) -> vk::DeviceAddress {
+    (*address_info).buffer.as_raw() << 32
+}
+
+unsafe extern "system" fn bind_buffer_memory(
+    _device: vk::Device,
+    _buffer: vk::Buffer,
+    _memory: vk::DeviceMemory,
+    _memory_offset: vk::DeviceSize,
+) -> vk::Result {
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn create_image(
+    _device: vk::Device,
+    create_info: *const vk::ImageCreateInfo,
+    _allocator: *const vk::AllocationCallbacks,
+    image: *mut vk::Image,
+) -> vk::Result {
+    let create_info = &*create_info;
+    let extent = create_info.extent;
+    with_mock_state(|state| {
+        // Enough for the full mip chain of uncompressed 128 bit texels
+        let size = 2
+            * 16
+            * extent.width as vk::DeviceSize
+            * extent.height as vk::DeviceSize
+            * extent.depth as vk::DeviceSize
+            * create_info.array_layers as vk::DeviceSize;
+
+        let new_image = state.create_handle();
+        state.image_sizes.insert(new_image, size);
+        state.calls.push(MockCall::CreateImage {
+            image: new_image,
+            format: create_info.format,
+            extent: (extent.width, extent.height, extent.depth),
+            mip_levels: create_info.mip_levels,
+            array_layers: create_info.array_layers,
+        });
+        *image = new_image;
+    });
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn destroy_image(
+    _device: vk::Device,
+    image: vk::Image,
+    _allocator: *const vk::AllocationCallbacks,
+) {
+    with_mock_state(|state| {
+        state.image_sizes.remove(&image);
+        state.calls.push(MockCall::DestroyImage { image });
+    });
+}
+
+unsafe extern "system" fn get_image_memory_requirements(
+    _device: vk::Device,
+    image: vk::Image,
+    memory_requirements: *mut vk::MemoryRequirements,
+) {
+    let mut size = 0;
+    with_mock_state(|state| size = state.image_sizes.get(&image).copied().unwrap_or(0));
+    *memory_requirements = get_mock_memory_requirements(size);
+}
+
+unsafe extern "system" fn bind_image_memory(
+    _device: vk::Device,
+    _image: vk::Image,
+    _memory: vk::DeviceMemory,
+    _memory_offset: vk::DeviceSize,
+) -> vk::Result {
+    vk::Result::SUCCESS
+}
+
+fn get_mock_memory_requirements(size: vk::DeviceSize) -> vk::MemoryRequirements {
+    vk::MemoryRequirements {
+        size: size.div_ceil(MOCK_MEMORY_ALIGNMENT).max(1) * MOCK_MEMORY_ALIGNMENT,
+        alignment: MOCK_MEMORY_ALIGNMENT,
+        memory_type_bits: 1,
+    }
+}
+
+unsafe extern "system" fn create_image_view(
+    _device: vk::Device,
+    create_info: *const vk::ImageViewCreateInfo,
+    _allocator: *const vk::AllocationCallbacks,
+    image_view: *mut vk::ImageView,
+) -> vk::Result {
+    with_mock_state(|state| {
+        let new_image_view = state.create_handle();
+        state.calls.push(MockCall::CreateImageView {
+            image_view: new_image_view,
+            image: (*create_info).image,
+        });
+        *image_view = new_image_view;
+    });
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn destroy_image_view(
+    _device: vk::Device,
+    image_view: vk::ImageView,
+    _allocator: *const vk::AllocationCallbacks,
+) {
+    with_mock_state(|state| state.calls.push(MockCall::DestroyImageView { image_view }));
+}
+
+unsafe extern "system" fn create_sampler(
+    _device: vk::Device,
+    create_info: *const vk::SamplerCreateInfo,
+    _allocator: *const vk::AllocationCallbacks,
+    sampler: *mut vk::Sampler,
+) -> vk::Result {
+    let create_info = &*create_info;
+    let max_anisotropy = if create_info.anisotropy_enable == vk::TRUE {
+        Some(create_info.max_anisotropy)
+    } else {
+        None
+    };
+    with_mock_state(|state| {
+        let new_sampler = state.create_handle();
+        state.calls.push(MockCall::CreateSampler {
+            sampler: new_sampler,
+            max_anisotropy,
+            mip_lod_bias: create_info.mip_lod_bias,
+            min_lod: create_info.min_lod,
+        });
+        *sampler = new_sampler;
+    });
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn destroy_sampler(
+    _device: vk::Device,
+    sampler: vk::Sampler,
+    _allocator: *const vk::AllocationCallbacks,
+) {
+    with_mock_state(|state| state.calls.push(MockCall::DestroySampler { sampler }));
+}
+
+unsafe extern "system" fn create_descriptor_set_layout(
+    _device: vk::Device,
+    create_info: *const vk::DescriptorSetLayoutCreateInfo,
+    _allocator: *const vk::AllocationCallbacks,
+    layout: *mut vk::DescriptorSetLayout,
+) -> vk::Result {
+    with_mock_state(|state| {
+        let new_layout = state.create_handle();
+        state.calls.push(MockCall::CreateDescriptorSetLayout {
+            layout: new_layout,
+            binding_count: (*create_info).binding_count,
+        });
+        *layout = new_layout;
+    });
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn destroy_descriptor_set_layout(
+    _device: vk::Device,
+    layout: vk::DescriptorSetLayout,
+    _allocator: *const vk::AllocationCallbacks,
+) {
+    with_mock_state(|state| state.calls.push(MockCall::DestroyDescriptorSetLayout { layout }));
+}
+
+unsafe extern "system" fn create_descriptor_pool(
+    _device: vk::Device,
+    create_info: *const vk::DescriptorPoolCreateInfo,
+    _allocator: *const vk::AllocationCallbacks,
+    pool: *mut vk::DescriptorPool,
+) -> vk::Result {
+    with_mock_state(|state| {
+        let new_pool = state.create_handle();
+        state.calls.push(MockCall::CreateDescriptorPool {
+            pool: new_pool,
+            max_sets: (*create_info).max_sets,
+        });
+        *pool = new_pool;
+    });
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn destroy_descriptor_pool(
+    _device: vk::Device,
+    pool: vk::DescriptorPool,
+    _allocator: *const vk::AllocationCallbacks,
+) {
+    with_mock_state(|state| state.calls.push(MockCall::DestroyDescriptorPool { pool }));
+}
+
+unsafe extern "system" fn allocate_descriptor_sets(
+    _device: vk::Device,
+    allocate_info: *const vk::DescriptorSetAllocateInfo,
+    descriptor_sets: *mut vk::DescriptorSet,
+) -> vk::Result {
+    let allocate_info = &*allocate_info;
+    with_mock_state(|state| {
+        let new_descriptor_sets: Vec<vk::DescriptorSet> = (0..allocate_info.descriptor_set_count)
+            .map(|_| state.create_handle())
+            .collect();
+        std::ptr::copy_nonoverlapping(new_descriptor_sets.as_ptr(), descriptor_sets, new_descriptor_sets.len());
+        state.calls.push(MockCall::AllocateDescriptorSets {
+            pool: allocate_info.descriptor_pool,
+            descriptor_sets: new_descriptor_sets,
+        });
+    });
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn update_descriptor_sets(
+    _device: vk::Device,
+    descriptor_write_count: u32,
+    descriptor_writes: *const vk::WriteDescriptorSet,
+    _descriptor_copy_count: u32,
+    _descriptor_copies: *const vk::CopyDescriptorSet,
+) {
+    let descriptor_writes = get_slice(descriptor_writes, descriptor_write_count);
+    with_mock_state(|state| {
+        for write in descriptor_writes {
+            let image_views = get_slice(write.p_image_info, write.descriptor_count)
+                .iter()
+                .map(|image_info| image_info.image_view)
+                .collect();
+            let buffers = get_slice(write.p_buffer_info, write.descriptor_count)
+                .iter()
+                .map(|buffer_info| buffer_info.buffer)
+                .collect();
+            state.calls.push(MockCall::WriteDescriptorSet {
+                descriptor_set: write.dst_set,
+                binding: write.dst_binding,
+                descriptor_type: write.descriptor_type,
+                image_views,
+                buffers,
+            });
+        }
+    });
+}
+
+unsafe extern "system" fn reset_command_buffer(
+    command_buffer: vk::CommandBuffer,
+    _flags: vk::CommandBufferResetFlags,
+) -> vk::Result {
+    with_mock_state(|state| state.calls.push(MockCall::ResetCommandBuffer { command_buffer }));
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn begin_command_buffer(
+    command_buffer: vk::CommandBuffer,
+    _begin_info: *const vk::CommandBufferBeginInfo,
+) -> vk::Result {
+    with_mock_state(|state| state.calls.push(MockCall::BeginCommandBuffer { command_buffer }));
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn end_command_buffer(command_buffer: vk::CommandBuffer) -> vk::Result {
+    with_mock_state(|state| state.calls.push(MockCall::EndCommandBuffer { command_buffer }));
+    vk::Result::SUCCESS
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "system" fn cmd_pipeline_barrier(
+    command_buffer: vk::CommandBuffer,
+    src_stage_mask: vk::PipelineStageFlags,
+    dst_stage_mask: vk::PipelineStageFlags,
+    _dependency_flags: vk::DependencyFlags,
+    _memory_barrier_count: u32,
+    _memory_barriers: *const vk::MemoryBarrier,
+    buffer_memory_barrier_count: u32,
+    buffer_memory_barriers: *const vk::BufferMemoryBarrier,
+    image_memory_barrier_count: u32,
+    image_memory_barriers: *const vk::ImageMemoryBarrier,
+) {
+    let buffers = get_slice(buffer_memory_barriers, buffer_memory_barrier_count)
+        .iter()
+        .map(|barrier| barrier.buffer)
+        .collect();
+    let images = get_slice(image_memory_barriers, image_memory_barrier_count)
+        .iter()
+        .map(|barrier| (barrier.image, barrier.old_layout, barrier.new_layout))
+        .collect();
+    with_mock_state(|state| {
+        state.calls.push(MockCall::PipelineBarrier {
+            command_buffer,
+            src_stage_mask,
+            dst_stage_mask,
+            buffers,
+            images,
+        })
+    });
+}
+
+unsafe extern "system" fn cmd_copy_buffer(
+    command_buffer: vk::CommandBuffer,
+    src_buffer: vk::Buffer,
+    dst_buffer: vk::Buffer,
+    region_count: u32,
+    _regions: *const vk::BufferCopy,
+) {
+    with_mock_state(|state| {
+        state.calls.push(MockCall::CopyBuffer {
+            command_buffer,
+            src_buffer,
+            dst_buffer,
+            region_count,
+        })
+    });
+}
+
+unsafe extern "system" fn cmd_copy_buffer_to_image(
+    command_buffer: vk::CommandBuffer,
+    src_buffer: vk::Buffer,
+    dst_image: vk::Image,
+    dst_image_layout: vk::ImageLayout,
+    region_count: u32,
+    _regions: *const vk::BufferImageCopy,
+) {
+    with_mock_state(|state| {
+        state.calls.push(MockCall::CopyBufferToImage {
+            command_buffer,
+            src_buffer,
+            dst_image,
+            dst_image_layout,
+            region_count,
+        })
+    });
+}
+
+unsafe extern "system" fn cmd_blit_image(
+    command_buffer: vk::CommandBuffer,
+    src_image: vk::Image,
+    _src_image_layout: vk::ImageLayout,
+    dst_image: vk::Image,
+    _dst_image_layout: vk::ImageLayout,
+    region_count: u32,
+    regions: *const vk::ImageBlit,
+    _filter: vk::Filter,
+) {
+    let regions = std::slice::from_raw_parts(regions, region_count as usize);
+    let mip_levels = regions
+        .iter()
+        .map(|region| (region.src_subresource.mip_level, region.dst_subresource.mip_level))
+        .collect();
+    with_mock_state(|state| {
+        state.calls.push(MockCall::BlitImage {
+            command_buffer,
+            src_image,
+            dst_image,
+            mip_levels,
+        })
+    });
+}
+
+unsafe extern "system" fn cmd_bind_pipeline(
+    command_buffer: vk::CommandBuffer,
+    pipeline_bind_point: vk::PipelineBindPoint,
+    pipeline: vk::Pipeline,
+) {
+    with_mock_state(|state| {
+        state.calls.push(MockCall::BindPipeline {
+            command_buffer,
+            pipeline_bind_point,
+            pipeline,
+        })
+    });
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "system" fn cmd_bind_descriptor_sets(
+    command_buffer: vk::CommandBuffer,
+    _pipeline_bind_point: vk::PipelineBindPoint,
+    _layout: vk::PipelineLayout,
+    first_set: u32,
+    descriptor_set_count: u32,
+    descriptor_sets: *const vk::DescriptorSet,
+    _dynamic_offset_count: u32,
+    _dynamic_offsets: *const u32,
+) {
+    let descriptor_sets = get_slice(descriptor_sets, descriptor_set_count).to_vec();
+    with_mock_state(|state| {
+        state.calls.push(MockCall::BindDescriptorSets {
+            command_buffer,
+            first_set,
+            descriptor_sets,
+        })
+    });
+}
+
+unsafe extern "system" fn cmd_draw(
+    command_buffer: vk::CommandBuffer,
+    vertex_count: u32,
+    instance_count: u32,
+    _first_vertex: u32,
+    _first_instance: u32,
+) {
+    with_mock_state(|state| {
+        state.calls.push(MockCall::Draw {
+            command_buffer,
+            vertex_count,
+            instance_count,
+        })
+    });
+}
+
+unsafe extern "system" fn cmd_draw_indexed(
+    command_buffer: vk::CommandBuffer,
+    index_count: u32,
+    instance_count: u32,
+    _first_index: u32,
+    _vertex_offset: i32,
+    _first_instance: u32,
+) {
+    with_mock_state(|state| {
+        state.calls.push(MockCall::DrawIndexed {
+            command_buffer,
+            index_count,
+            instance_count,
+        })
+    });
+}
+
+unsafe extern "system" fn cmd_begin_conditional_rendering(
+    command_buffer: vk::CommandBuffer,
+    begin_info: *const vk::ConditionalRenderingBeginInfoEXT,
+) {
+    let begin_info = &*begin_info;
+    with_mock_state(|state| {
+        state.calls.push(MockCall::BeginConditionalRendering {
+            command_buffer,
+            buffer: begin_info.buffer,
+            offset: begin_info.offset,
+            inverted: begin_info.flags.contains(vk::ConditionalRenderingFlagsEXT::INVERTED),
+        })
+    });
+}
+
+unsafe extern "system" fn cmd_end_conditional_rendering(command_buffer: vk::CommandBuffer) {
+    with_mock_state(|state| state.calls.push(MockCall::EndConditionalRendering { command_buffer }));
+}
+
+unsafe extern "system" fn cmd_dispatch(
+    command_buffer: vk::CommandBuffer,
+    group_count_x: u32,
+    group_count_y: u32,
+    group_count_z: u32,
+) {
+    with_mock_state(|state| {
+        state.calls.push(MockCall::Dispatch {
+            command_buffer,
+            group_count: (group_count_x, group_count_y, group_count_z),
+        })
+    });
+}
+
+unsafe extern "system" fn queue_submit(
+    queue: vk::Queue,
+    submit_count: u32,
+    submits: *const vk::SubmitInfo,
+    _fence: vk::Fence,
+) -> vk::Result {
+    let command_buffers = get_slice(submits, submit_count)
+        .iter()
+        .flat_map(|submit| get_slice(submit.p_command_buffers, submit.command_buffer_count).to_vec())
+        .collect();
+    with_mock_state(|state| state.calls.push(MockCall::QueueSubmit { queue, command_buffers }));
+    vk::Result::SUCCESS
+}
+
+unsafe extern "system" fn queue_wait_idle(queue: vk::Queue) -> vk::Result {
+    with_mock_state(|state| state.calls.push(MockCall::QueueWaitIdle { queue }));
+    vk::Result::SUCCESS
+}
+
+unsafe fn get_slice<'a, T>(data: *const T, count: u32) -> &'a [T] {
+    if data.is_null() || count == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, count as usize)
+    }
+}
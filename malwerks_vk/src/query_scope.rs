@@ -0,0 +1,177 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use ash::vk;
+
+use crate::command_buffer::*;
+use crate::device::*;
+use crate::device_factory::*;
+use crate::frame_context::*;
+
+// Results are written in bit order of the flags
+pub const PIPELINE_STATISTICS_FLAGS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+    vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS.as_raw(),
+);
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PipelineStatistics {
+    pub vertex_shader_invocations: u64,
+    pub fragment_shader_invocations: u64,
+    pub compute_shader_invocations: u64,
+}
+
+impl std::ops::AddAssign for PipelineStatistics {
+    fn add_assign(&mut self, other: Self) {
+        self.vertex_shader_invocations += other.vertex_shader_invocations;
+        self.fragment_shader_invocations += other.fragment_shader_invocations;
+        self.compute_shader_invocations += other.compute_shader_invocations;
+    }
+}
+
+// Begin and end timestamps and optionally pipeline statistics of the commands recorded between begin() and end(),
+// one set of queries per buffered frame. Results are read from the oldest buffered frame, the one the GPU is
+// expected to have finished. Pipeline statistics are only collected if the device has them enabled.
+pub struct QueryScope {
+    timestamp_query_pool: vk::QueryPool,
+    statistics_query_pool: Option<vk::QueryPool>,
+    active: bool,
+}
+
+impl QueryScope {
+    pub fn new(device: &Device, factory: &mut DeviceFactory) -> Self {
+        let num_buffered_frames = factory.get_num_buffered_frames();
+        let timestamp_query_pool = factory.create_query_pool(
+            &vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count((2 * num_buffered_frames) as _)
+                .build(),
+        );
+        let statistics_query_pool = if device.is_pipeline_statistics_enabled() {
+            Some(
+                factory.create_query_pool(
+                    &vk::QueryPoolCreateInfo::builder()
+                        .query_type(vk::QueryType::PIPELINE_STATISTICS)
+                        .query_count(num_buffered_frames as _)
+                        .pipeline_statistics(PIPELINE_STATISTICS_FLAGS)
+                        .build(),
+                ),
+            )
+        } else {
+            None
+        };
+
+        Self {
+            timestamp_query_pool,
+            statistics_query_pool,
+            active: false,
+        }
+    }
+
+    pub fn destroy(&mut self, factory: &mut DeviceFactory) {
+        factory.destroy_query_pool(self.timestamp_query_pool);
+        if let Some(statistics_query_pool) = self.statistics_query_pool {
+            factory.destroy_query_pool(statistics_query_pool);
+        }
+    }
+
+    pub fn has_pipeline_statistics(&self) -> bool {
+        self.statistics_query_pool.is_some()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // Queries of the frame are reset here, so it has to be recorded outside of a render pass
+    pub fn begin(&mut self, command_buffer: &mut CommandBuffer, frame_context: &FrameContext) {
+        assert!(!self.active, "query scope is already active");
+        self.active = true;
+
+        let frame = frame_context.current_gpu_frame();
+        command_buffer.reset_query_pool(self.timestamp_query_pool, (frame * 2) as _, 2);
+        if let Some(statistics_query_pool) = self.statistics_query_pool {
+            command_buffer.reset_query_pool(statistics_query_pool, frame as _, 1);
+        }
+
+        command_buffer.write_timestamp(
+            vk::PipelineStageFlags::ALL_GRAPHICS,
+            self.timestamp_query_pool,
+            (frame * 2) as _,
+        );
+        if let Some(statistics_query_pool) = self.statistics_query_pool {
+            command_buffer.begin_query(statistics_query_pool, frame as _, vk::QueryControlFlags::empty());
+        }
+    }
+
+    // Has to be recorded outside of a render pass if begin() was
+    pub fn end(&mut self, command_buffer: &mut CommandBuffer, frame_context: &FrameContext) {
+        assert!(self.active, "query scope is not active");
+        self.active = false;
+
+        let frame = frame_context.current_gpu_frame();
+        if let Some(statistics_query_pool) = self.statistics_query_pool {
+            command_buffer.end_query(statistics_query_pool, frame as _);
+        }
+        command_buffer.write_timestamp(
+            vk::PipelineStageFlags::ALL_GRAPHICS,
+            self.timestamp_query_pool,
+            (frame * 2 + 1) as _,
+        );
+    }
+
+    pub fn try_get_oldest_timestamps(
+        &self,
+        frame_context: &FrameContext,
+        factory: &mut DeviceFactory,
+    ) -> Option<[u64; 2]> {
+        let mut data = [0u64; 2];
+        let result = factory.get_query_pool_results(
+            self.timestamp_query_pool,
+            (get_oldest_frame(frame_context) * 2) as _,
+            2,
+            &mut data,
+            vk::QueryResultFlags::TYPE_64,
+        );
+        match result {
+            Ok(_) => Some(data),
+            Err(vk::Result::NOT_READY) => None,
+            Err(code) => panic!("try_get_oldest_timestamps(): Internal GPU error: {}", code),
+        }
+    }
+
+    // None if pipeline statistics are disabled or not available yet
+    pub fn try_get_oldest_pipeline_statistics(
+        &self,
+        frame_context: &FrameContext,
+        factory: &mut DeviceFactory,
+    ) -> Option<PipelineStatistics> {
+        let statistics_query_pool = self.statistics_query_pool?;
+        let mut data = [0u64; 3];
+        let result = factory.get_query_pool_results_with_stride(
+            statistics_query_pool,
+            get_oldest_frame(frame_context) as _,
+            1,
+            data.len(),
+            &mut data,
+            vk::QueryResultFlags::TYPE_64,
+        );
+        match result {
+            Ok(_) => Some(PipelineStatistics {
+                vertex_shader_invocations: data[0],
+                fragment_shader_invocations: data[1],
+                compute_shader_invocations: data[2],
+            }),
+            Err(vk::Result::NOT_READY) => None,
+            Err(code) => panic!("try_get_oldest_pipeline_statistics(): Internal GPU error: {}", code),
+        }
+    }
+}
+
+fn get_oldest_frame(frame_context: &FrameContext) -> usize {
+    let num_buffered_frames = frame_context.num_buffered_frames();
+    (frame_context.current_gpu_frame() + num_buffered_frames) % num_buffered_frames
+}
@@ -0,0 +1,111 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::panic::Location;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TrackedResourceType {
+    Buffer,
+    Image,
+    Pipeline,
+    DescriptorPool,
+}
+
+pub struct TrackedResource {
+    pub resource_type: TrackedResourceType,
+    pub handle: u64,
+    pub tag: String, // resource tags that were active when the resource was created, joined with '/'
+    pub location: &'static Location<'static>,
+}
+
+// Resources created through DeviceFactory that are still alive, only used when tracking is enabled
+pub(crate) struct ResourceTracker {
+    resources: HashMap<(TrackedResourceType, u64), TrackedResource>,
+    tags: Vec<String>,
+}
+
+impl ResourceTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            resources: HashMap::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push_tag(&mut self, tag: &str) {
+        self.tags.push(String::from(tag));
+    }
+
+    pub(crate) fn pop_tag(&mut self) {
+        self.tags.pop().expect("resource tag stack is empty");
+    }
+
+    pub(crate) fn track_create(
+        &mut self,
+        resource_type: TrackedResourceType,
+        handle: u64,
+        location: &'static Location<'static>,
+    ) {
+        let tag = self.tags.join("/");
+        log::debug!("created {:?} {:#x} [{}] at {}", resource_type, handle, tag, location);
+        self.resources.insert(
+            (resource_type, handle),
+            TrackedResource {
+                resource_type,
+                handle,
+                tag,
+                location,
+            },
+        );
+    }
+
+    // Null handles are ignored, destroying them is valid
+    pub(crate) fn track_destroy(
+        &mut self,
+        resource_type: TrackedResourceType,
+        handle: u64,
+        location: &'static Location<'static>,
+    ) {
+        if handle == 0 {
+            return;
+        }
+        match self.resources.remove(&(resource_type, handle)) {
+            Some(resource) => log::debug!(
+                "destroyed {:?} {:#x} [{}] at {}",
+                resource_type,
+                handle,
+                resource.tag,
+                location
+            ),
+            None => log::warn!(
+                "destroyed {:?} {:#x} at {} that was not created with resource tracking",
+                resource_type,
+                handle,
+                location
+            ),
+        }
+    }
+
+    pub(crate) fn get_resources(&self) -> Vec<&TrackedResource> {
+        let mut resources: Vec<&TrackedResource> = self.resources.values().collect();
+        resources.sort_by_key(|resource| (resource.location.file(), resource.location.line(), resource.handle));
+        resources
+    }
+
+    pub(crate) fn report_leaks(&self) -> usize {
+        let resources = self.get_resources();
+        for resource in &resources {
+            log::error!(
+                "leaked {:?} {:#x} [{}] created at {}",
+                resource.resource_type,
+                resource.handle,
+                resource.tag,
+                resource.location
+            );
+        }
+        resources.len()
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use ash::vk;
+
+use std::ffi::CStr;
+
+pub trait SurfaceProvider {
+    fn get_required_instance_extensions(&self) -> Vec<&'static CStr>;
+
+    fn create_surface(
+        &self,
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+    ) -> Result<(Option<ash::extensions::khr::Surface>, vk::SurfaceKHR), vk::Result>;
+}
+
+// Any window that exposes a raw handle (winit, SDL2, etc.) can be used to create a surface,
+// ash_window takes care of Win32, Xlib, Xcb, Wayland, Android and Metal platforms.
+impl<T> SurfaceProvider for T
+where
+    T: raw_window_handle::HasRawWindowHandle,
+{
+    fn get_required_instance_extensions(&self) -> Vec<&'static CStr> {
+        ash_window::enumerate_required_extensions(self).expect("enumerate_required_extensions() failed")
+    }
+
+    fn create_surface(
+        &self,
+        entry: &ash::Entry,
+        instance: &ash::Instance,
+    ) -> Result<(Option<ash::extensions::khr::Surface>, vk::SurfaceKHR), vk::Result> {
+        unsafe {
+            let surface_loader = ash::extensions::khr::Surface::new(entry, instance);
+            let surface = ash_window::create_surface(entry, instance, self, None)?;
+
+            Ok((Some(surface_loader), surface))
+        }
+    }
+}
+
+pub struct HeadlessSurface;
+
+impl SurfaceProvider for HeadlessSurface {
+    fn get_required_instance_extensions(&self) -> Vec<&'static CStr> {
+        Vec::new()
+    }
+
+    fn create_surface(
+        &self,
+        _entry: &ash::Entry,
+        _instance: &ash::Instance,
+    ) -> Result<(Option<ash::extensions::khr::Surface>, vk::SurfaceKHR), vk::Result> {
+        Ok((None, vk::SurfaceKHR::null()))
+    }
+}
@@ -6,18 +6,16 @@
 use crate::frame_context::*;
 
 pub struct FrameLocal<T> {
-    frame_resources: [T; NUM_BUFFERED_GPU_FRAMES],
+    frame_resources: Vec<T>,
 }
 
 impl<T> FrameLocal<T> {
-    pub fn new<F>(mut closure: F) -> Self
+    pub fn new<F>(num_buffered_frames: usize, closure: F) -> Self
     where
         F: FnMut(usize) -> T,
     {
-        // TODO: this is stupid, make a proper macro or smth
-        assert_eq!(NUM_BUFFERED_GPU_FRAMES, 3);
         Self {
-            frame_resources: [closure(0), closure(1), closure(2)],
+            frame_resources: (0..num_buffered_frames).map(closure).collect(),
         }
     }
 
@@ -41,4 +39,11 @@ impl<T> FrameLocal<T> {
     pub fn get_mut(&mut self, frame_context: &FrameContext) -> &mut T {
         &mut self.frame_resources[frame_context.current_gpu_frame()]
     }
+
+    pub fn contains(&self, resource: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        self.frame_resources.contains(resource)
+    }
 }
@@ -0,0 +1,11 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod openxr_ffi;
+mod xr_context;
+mod xr_session;
+
+pub use xr_context::*;
+pub use xr_session::*;
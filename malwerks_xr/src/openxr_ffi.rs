@@ -0,0 +1,658 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+// Subset of OpenXR 1.0 and XR_KHR_vulkan_enable that is needed to present a stereo view to a headset.
+// Types are declared manually and the loader is opened at runtime, so the crate builds without an
+// OpenXR SDK installed. Layouts match openxr.h and openxr_platform.h for 64-bit platforms.
+
+#![allow(non_camel_case_types)]
+
+use ash::vk;
+
+use std::os::raw::{c_char, c_void};
+
+pub type XrResult = i32;
+pub type XrBool32 = u32;
+pub type XrTime = i64;
+pub type XrDuration = i64;
+pub type XrVersion = u64;
+pub type XrFlags64 = u64;
+pub type XrSystemId = u64;
+pub type XrStructureType = i32;
+
+// Handles are pointers to opaque types on 64-bit platforms
+pub type XrInstance = u64;
+pub type XrSession = u64;
+pub type XrSpace = u64;
+pub type XrSwapchain = u64;
+
+pub const XR_NULL_HANDLE: u64 = 0;
+pub const XR_NULL_SYSTEM_ID: XrSystemId = 0;
+pub const XR_TRUE: XrBool32 = 1;
+pub const XR_INFINITE_DURATION: XrDuration = 0x7fff_ffff_ffff_ffff;
+
+pub const XR_SUCCESS: XrResult = 0;
+pub const XR_SESSION_LOSS_PENDING: XrResult = 3;
+pub const XR_EVENT_UNAVAILABLE: XrResult = 4;
+
+pub const XR_MAX_APPLICATION_NAME_SIZE: usize = 128;
+pub const XR_MAX_ENGINE_NAME_SIZE: usize = 128;
+pub const XR_MAX_EXTENSION_NAME_SIZE: usize = 128;
+
+pub const XR_KHR_VULKAN_ENABLE_EXTENSION_NAME: &[u8] = b"XR_KHR_vulkan_enable\0";
+
+pub const fn xr_make_version(major: u64, minor: u64, patch: u64) -> XrVersion {
+    ((major & 0xffff) << 48) | ((minor & 0xffff) << 32) | (patch & 0xffff_ffff)
+}
+
+pub const XR_TYPE_EXTENSION_PROPERTIES: XrStructureType = 2;
+pub const XR_TYPE_INSTANCE_CREATE_INFO: XrStructureType = 3;
+pub const XR_TYPE_SYSTEM_GET_INFO: XrStructureType = 4;
+pub const XR_TYPE_VIEW_LOCATE_INFO: XrStructureType = 6;
+pub const XR_TYPE_VIEW: XrStructureType = 7;
+pub const XR_TYPE_SESSION_CREATE_INFO: XrStructureType = 8;
+pub const XR_TYPE_SWAPCHAIN_CREATE_INFO: XrStructureType = 9;
+pub const XR_TYPE_SESSION_BEGIN_INFO: XrStructureType = 10;
+pub const XR_TYPE_VIEW_STATE: XrStructureType = 11;
+pub const XR_TYPE_FRAME_END_INFO: XrStructureType = 12;
+pub const XR_TYPE_EVENT_DATA_BUFFER: XrStructureType = 16;
+pub const XR_TYPE_EVENT_DATA_INSTANCE_LOSS_PENDING: XrStructureType = 17;
+pub const XR_TYPE_EVENT_DATA_SESSION_STATE_CHANGED: XrStructureType = 18;
+pub const XR_TYPE_FRAME_WAIT_INFO: XrStructureType = 33;
+pub const XR_TYPE_COMPOSITION_LAYER_PROJECTION: XrStructureType = 35;
+pub const XR_TYPE_REFERENCE_SPACE_CREATE_INFO: XrStructureType = 37;
+pub const XR_TYPE_VIEW_CONFIGURATION_VIEW: XrStructureType = 41;
+pub const XR_TYPE_FRAME_STATE: XrStructureType = 44;
+pub const XR_TYPE_FRAME_BEGIN_INFO: XrStructureType = 46;
+pub const XR_TYPE_COMPOSITION_LAYER_PROJECTION_VIEW: XrStructureType = 48;
+pub const XR_TYPE_SWAPCHAIN_IMAGE_ACQUIRE_INFO: XrStructureType = 55;
+pub const XR_TYPE_SWAPCHAIN_IMAGE_WAIT_INFO: XrStructureType = 56;
+pub const XR_TYPE_SWAPCHAIN_IMAGE_RELEASE_INFO: XrStructureType = 57;
+pub const XR_TYPE_GRAPHICS_BINDING_VULKAN_KHR: XrStructureType = 1_000_025_000;
+pub const XR_TYPE_SWAPCHAIN_IMAGE_VULKAN_KHR: XrStructureType = 1_000_025_001;
+pub const XR_TYPE_GRAPHICS_REQUIREMENTS_VULKAN_KHR: XrStructureType = 1_000_025_002;
+
+pub const XR_FORM_FACTOR_HEAD_MOUNTED_DISPLAY: i32 = 1;
+pub const XR_VIEW_CONFIGURATION_TYPE_PRIMARY_STEREO: i32 = 2;
+pub const XR_REFERENCE_SPACE_TYPE_LOCAL: i32 = 2;
+pub const XR_ENVIRONMENT_BLEND_MODE_OPAQUE: i32 = 1;
+
+pub const XR_SESSION_STATE_READY: i32 = 2;
+pub const XR_SESSION_STATE_STOPPING: i32 = 6;
+pub const XR_SESSION_STATE_LOSS_PENDING: i32 = 7;
+pub const XR_SESSION_STATE_EXITING: i32 = 8;
+
+pub const XR_SWAPCHAIN_USAGE_COLOR_ATTACHMENT_BIT: XrFlags64 = 0x0000_0001;
+pub const XR_SWAPCHAIN_USAGE_TRANSFER_DST_BIT: XrFlags64 = 0x0000_0010;
+
+pub const XR_VIEW_STATE_ORIENTATION_VALID_BIT: XrFlags64 = 0x0000_0001;
+pub const XR_VIEW_STATE_POSITION_VALID_BIT: XrFlags64 = 0x0000_0002;
+
+// All structures below are plain data, zero is a valid value for every field
+macro_rules! impl_xr_default {
+    ($name: ident, $structure_type: expr) => {
+        impl Default for $name {
+            fn default() -> Self {
+                let mut value: Self = unsafe { std::mem::zeroed() };
+                value.ty = $structure_type;
+                value
+            }
+        }
+    };
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrExtensionProperties.html>"]
+pub struct XrExtensionProperties {
+    pub ty: XrStructureType,
+    pub next: *mut c_void,
+    pub extension_name: [c_char; XR_MAX_EXTENSION_NAME_SIZE],
+    pub extension_version: u32,
+}
+impl_xr_default!(XrExtensionProperties, XR_TYPE_EXTENSION_PROPERTIES);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrApplicationInfo.html>"]
+pub struct XrApplicationInfo {
+    pub application_name: [c_char; XR_MAX_APPLICATION_NAME_SIZE],
+    pub application_version: u32,
+    pub engine_name: [c_char; XR_MAX_ENGINE_NAME_SIZE],
+    pub engine_version: u32,
+    pub api_version: XrVersion,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrInstanceCreateInfo.html>"]
+pub struct XrInstanceCreateInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub create_flags: XrFlags64,
+    pub application_info: XrApplicationInfo,
+    pub enabled_api_layer_count: u32,
+    pub enabled_api_layer_names: *const *const c_char,
+    pub enabled_extension_count: u32,
+    pub enabled_extension_names: *const *const c_char,
+}
+impl_xr_default!(XrInstanceCreateInfo, XR_TYPE_INSTANCE_CREATE_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrSystemGetInfo.html>"]
+pub struct XrSystemGetInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub form_factor: i32,
+}
+impl_xr_default!(XrSystemGetInfo, XR_TYPE_SYSTEM_GET_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrGraphicsRequirementsVulkanKHR.html>"]
+pub struct XrGraphicsRequirementsVulkanKHR {
+    pub ty: XrStructureType,
+    pub next: *mut c_void,
+    pub min_api_version_supported: XrVersion,
+    pub max_api_version_supported: XrVersion,
+}
+impl_xr_default!(
+    XrGraphicsRequirementsVulkanKHR,
+    XR_TYPE_GRAPHICS_REQUIREMENTS_VULKAN_KHR
+);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrGraphicsBindingVulkanKHR.html>"]
+pub struct XrGraphicsBindingVulkanKHR {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub instance: vk::Instance,
+    pub physical_device: vk::PhysicalDevice,
+    pub device: vk::Device,
+    pub queue_family_index: u32,
+    pub queue_index: u32,
+}
+impl_xr_default!(XrGraphicsBindingVulkanKHR, XR_TYPE_GRAPHICS_BINDING_VULKAN_KHR);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrSessionCreateInfo.html>"]
+pub struct XrSessionCreateInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub create_flags: XrFlags64,
+    pub system_id: XrSystemId,
+}
+impl_xr_default!(XrSessionCreateInfo, XR_TYPE_SESSION_CREATE_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrSessionBeginInfo.html>"]
+pub struct XrSessionBeginInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub primary_view_configuration_type: i32,
+}
+impl_xr_default!(XrSessionBeginInfo, XR_TYPE_SESSION_BEGIN_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct XrQuaternionf {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct XrVector3f {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrPosef.html>"]
+pub struct XrPosef {
+    pub orientation: XrQuaternionf,
+    pub position: XrVector3f,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrFovf.html>"]
+pub struct XrFovf {
+    pub angle_left: f32,
+    pub angle_right: f32,
+    pub angle_up: f32,
+    pub angle_down: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct XrOffset2Di {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct XrExtent2Di {
+    pub width: i32,
+    pub height: i32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct XrRect2Di {
+    pub offset: XrOffset2Di,
+    pub extent: XrExtent2Di,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrReferenceSpaceCreateInfo.html>"]
+pub struct XrReferenceSpaceCreateInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub reference_space_type: i32,
+    pub pose_in_reference_space: XrPosef,
+}
+impl_xr_default!(XrReferenceSpaceCreateInfo, XR_TYPE_REFERENCE_SPACE_CREATE_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrViewConfigurationView.html>"]
+pub struct XrViewConfigurationView {
+    pub ty: XrStructureType,
+    pub next: *mut c_void,
+    pub recommended_image_rect_width: u32,
+    pub max_image_rect_width: u32,
+    pub recommended_image_rect_height: u32,
+    pub max_image_rect_height: u32,
+    pub recommended_swapchain_sample_count: u32,
+    pub max_swapchain_sample_count: u32,
+}
+impl_xr_default!(XrViewConfigurationView, XR_TYPE_VIEW_CONFIGURATION_VIEW);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrSwapchainCreateInfo.html>"]
+pub struct XrSwapchainCreateInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub create_flags: XrFlags64,
+    pub usage_flags: XrFlags64,
+    pub format: i64,
+    pub sample_count: u32,
+    pub width: u32,
+    pub height: u32,
+    pub face_count: u32,
+    pub array_size: u32,
+    pub mip_count: u32,
+}
+impl_xr_default!(XrSwapchainCreateInfo, XR_TYPE_SWAPCHAIN_CREATE_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrSwapchainImageVulkanKHR.html>"]
+pub struct XrSwapchainImageVulkanKHR {
+    pub ty: XrStructureType,
+    pub next: *mut c_void,
+    pub image: vk::Image,
+}
+impl_xr_default!(XrSwapchainImageVulkanKHR, XR_TYPE_SWAPCHAIN_IMAGE_VULKAN_KHR);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrSwapchainImageAcquireInfo.html>"]
+pub struct XrSwapchainImageAcquireInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+}
+impl_xr_default!(XrSwapchainImageAcquireInfo, XR_TYPE_SWAPCHAIN_IMAGE_ACQUIRE_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrSwapchainImageWaitInfo.html>"]
+pub struct XrSwapchainImageWaitInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub timeout: XrDuration,
+}
+impl_xr_default!(XrSwapchainImageWaitInfo, XR_TYPE_SWAPCHAIN_IMAGE_WAIT_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrSwapchainImageReleaseInfo.html>"]
+pub struct XrSwapchainImageReleaseInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+}
+impl_xr_default!(XrSwapchainImageReleaseInfo, XR_TYPE_SWAPCHAIN_IMAGE_RELEASE_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrFrameWaitInfo.html>"]
+pub struct XrFrameWaitInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+}
+impl_xr_default!(XrFrameWaitInfo, XR_TYPE_FRAME_WAIT_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrFrameState.html>"]
+pub struct XrFrameState {
+    pub ty: XrStructureType,
+    pub next: *mut c_void,
+    pub predicted_display_time: XrTime,
+    pub predicted_display_period: XrDuration,
+    pub should_render: XrBool32,
+}
+impl_xr_default!(XrFrameState, XR_TYPE_FRAME_STATE);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrFrameBeginInfo.html>"]
+pub struct XrFrameBeginInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+}
+impl_xr_default!(XrFrameBeginInfo, XR_TYPE_FRAME_BEGIN_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrViewLocateInfo.html>"]
+pub struct XrViewLocateInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub view_configuration_type: i32,
+    pub display_time: XrTime,
+    pub space: XrSpace,
+}
+impl_xr_default!(XrViewLocateInfo, XR_TYPE_VIEW_LOCATE_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrViewState.html>"]
+pub struct XrViewState {
+    pub ty: XrStructureType,
+    pub next: *mut c_void,
+    pub view_state_flags: XrFlags64,
+}
+impl_xr_default!(XrViewState, XR_TYPE_VIEW_STATE);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrView.html>"]
+pub struct XrView {
+    pub ty: XrStructureType,
+    pub next: *mut c_void,
+    pub pose: XrPosef,
+    pub fov: XrFovf,
+}
+impl_xr_default!(XrView, XR_TYPE_VIEW);
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrSwapchainSubImage.html>"]
+pub struct XrSwapchainSubImage {
+    pub swapchain: XrSwapchain,
+    pub image_rect: XrRect2Di,
+    pub image_array_index: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrCompositionLayerProjectionView.html>"]
+pub struct XrCompositionLayerProjectionView {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub pose: XrPosef,
+    pub fov: XrFovf,
+    pub sub_image: XrSwapchainSubImage,
+}
+impl_xr_default!(
+    XrCompositionLayerProjectionView,
+    XR_TYPE_COMPOSITION_LAYER_PROJECTION_VIEW
+);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrCompositionLayerProjection.html>"]
+pub struct XrCompositionLayerProjection {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub layer_flags: XrFlags64,
+    pub space: XrSpace,
+    pub view_count: u32,
+    pub views: *const XrCompositionLayerProjectionView,
+}
+impl_xr_default!(XrCompositionLayerProjection, XR_TYPE_COMPOSITION_LAYER_PROJECTION);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrFrameEndInfo.html>"]
+pub struct XrFrameEndInfo {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub display_time: XrTime,
+    pub environment_blend_mode: i32,
+    pub layer_count: u32,
+    pub layers: *const *const XrCompositionLayerProjection, // XrCompositionLayerBaseHeader in openxr.h
+}
+impl_xr_default!(XrFrameEndInfo, XR_TYPE_FRAME_END_INFO);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrEventDataBuffer.html>"]
+pub struct XrEventDataBuffer {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub varying: [u8; 4000],
+}
+impl_xr_default!(XrEventDataBuffer, XR_TYPE_EVENT_DATA_BUFFER);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[doc = "<https://www.khronos.org/registry/OpenXR/specs/1.0/man/html/XrEventDataSessionStateChanged.html>"]
+pub struct XrEventDataSessionStateChanged {
+    pub ty: XrStructureType,
+    pub next: *const c_void,
+    pub session: XrSession,
+    pub state: i32,
+    pub time: XrTime,
+}
+
+pub type PFN_xrVoidFunction = unsafe extern "system" fn();
+pub type PFN_xrGetInstanceProcAddr =
+    unsafe extern "system" fn(XrInstance, *const c_char, *mut Option<PFN_xrVoidFunction>) -> XrResult;
+
+pub type PFN_xrEnumerateInstanceExtensionProperties =
+    unsafe extern "system" fn(*const c_char, u32, *mut u32, *mut XrExtensionProperties) -> XrResult;
+pub type PFN_xrCreateInstance = unsafe extern "system" fn(*const XrInstanceCreateInfo, *mut XrInstance) -> XrResult;
+pub type PFN_xrDestroyInstance = unsafe extern "system" fn(XrInstance) -> XrResult;
+pub type PFN_xrGetSystem = unsafe extern "system" fn(XrInstance, *const XrSystemGetInfo, *mut XrSystemId) -> XrResult;
+pub type PFN_xrPollEvent = unsafe extern "system" fn(XrInstance, *mut XrEventDataBuffer) -> XrResult;
+pub type PFN_xrEnumerateViewConfigurationViews =
+    unsafe extern "system" fn(XrInstance, XrSystemId, i32, u32, *mut u32, *mut XrViewConfigurationView) -> XrResult;
+pub type PFN_xrCreateSession =
+    unsafe extern "system" fn(XrInstance, *const XrSessionCreateInfo, *mut XrSession) -> XrResult;
+pub type PFN_xrDestroySession = unsafe extern "system" fn(XrSession) -> XrResult;
+pub type PFN_xrBeginSession = unsafe extern "system" fn(XrSession, *const XrSessionBeginInfo) -> XrResult;
+pub type PFN_xrEndSession = unsafe extern "system" fn(XrSession) -> XrResult;
+pub type PFN_xrRequestExitSession = unsafe extern "system" fn(XrSession) -> XrResult;
+pub type PFN_xrCreateReferenceSpace =
+    unsafe extern "system" fn(XrSession, *const XrReferenceSpaceCreateInfo, *mut XrSpace) -> XrResult;
+pub type PFN_xrDestroySpace = unsafe extern "system" fn(XrSpace) -> XrResult;
+pub type PFN_xrEnumerateSwapchainFormats = unsafe extern "system" fn(XrSession, u32, *mut u32, *mut i64) -> XrResult;
+pub type PFN_xrCreateSwapchain =
+    unsafe extern "system" fn(XrSession, *const XrSwapchainCreateInfo, *mut XrSwapchain) -> XrResult;
+pub type PFN_xrDestroySwapchain = unsafe extern "system" fn(XrSwapchain) -> XrResult;
+pub type PFN_xrEnumerateSwapchainImages =
+    unsafe extern "system" fn(XrSwapchain, u32, *mut u32, *mut XrSwapchainImageVulkanKHR) -> XrResult;
+pub type PFN_xrAcquireSwapchainImage =
+    unsafe extern "system" fn(XrSwapchain, *const XrSwapchainImageAcquireInfo, *mut u32) -> XrResult;
+pub type PFN_xrWaitSwapchainImage = unsafe extern "system" fn(XrSwapchain, *const XrSwapchainImageWaitInfo) -> XrResult;
+pub type PFN_xrReleaseSwapchainImage =
+    unsafe extern "system" fn(XrSwapchain, *const XrSwapchainImageReleaseInfo) -> XrResult;
+pub type PFN_xrWaitFrame = unsafe extern "system" fn(XrSession, *const XrFrameWaitInfo, *mut XrFrameState) -> XrResult;
+pub type PFN_xrBeginFrame = unsafe extern "system" fn(XrSession, *const XrFrameBeginInfo) -> XrResult;
+pub type PFN_xrEndFrame = unsafe extern "system" fn(XrSession, *const XrFrameEndInfo) -> XrResult;
+pub type PFN_xrLocateViews = unsafe extern "system" fn(
+    XrSession,
+    *const XrViewLocateInfo,
+    *mut XrViewState,
+    u32,
+    *mut u32,
+    *mut XrView,
+) -> XrResult;
+
+pub type PFN_xrGetVulkanGraphicsRequirementsKHR =
+    unsafe extern "system" fn(XrInstance, XrSystemId, *mut XrGraphicsRequirementsVulkanKHR) -> XrResult;
+pub type PFN_xrGetVulkanInstanceExtensionsKHR =
+    unsafe extern "system" fn(XrInstance, XrSystemId, u32, *mut u32, *mut c_char) -> XrResult;
+pub type PFN_xrGetVulkanDeviceExtensionsKHR =
+    unsafe extern "system" fn(XrInstance, XrSystemId, u32, *mut u32, *mut c_char) -> XrResult;
+pub type PFN_xrGetVulkanGraphicsDeviceKHR =
+    unsafe extern "system" fn(XrInstance, XrSystemId, vk::Instance, *mut vk::PhysicalDevice) -> XrResult;
+
+// Functions that don't require an instance
+pub struct OpenXrEntry {
+    pub get_instance_proc_addr: PFN_xrGetInstanceProcAddr,
+    pub enumerate_instance_extension_properties: PFN_xrEnumerateInstanceExtensionProperties,
+    pub create_instance: PFN_xrCreateInstance,
+}
+
+pub struct OpenXrInstance {
+    pub destroy_instance: PFN_xrDestroyInstance,
+    pub get_system: PFN_xrGetSystem,
+    pub poll_event: PFN_xrPollEvent,
+    pub enumerate_view_configuration_views: PFN_xrEnumerateViewConfigurationViews,
+    pub create_session: PFN_xrCreateSession,
+    pub destroy_session: PFN_xrDestroySession,
+    pub begin_session: PFN_xrBeginSession,
+    pub end_session: PFN_xrEndSession,
+    pub request_exit_session: PFN_xrRequestExitSession,
+    pub create_reference_space: PFN_xrCreateReferenceSpace,
+    pub destroy_space: PFN_xrDestroySpace,
+    pub enumerate_swapchain_formats: PFN_xrEnumerateSwapchainFormats,
+    pub create_swapchain: PFN_xrCreateSwapchain,
+    pub destroy_swapchain: PFN_xrDestroySwapchain,
+    pub enumerate_swapchain_images: PFN_xrEnumerateSwapchainImages,
+    pub acquire_swapchain_image: PFN_xrAcquireSwapchainImage,
+    pub wait_swapchain_image: PFN_xrWaitSwapchainImage,
+    pub release_swapchain_image: PFN_xrReleaseSwapchainImage,
+    pub wait_frame: PFN_xrWaitFrame,
+    pub begin_frame: PFN_xrBeginFrame,
+    pub end_frame: PFN_xrEndFrame,
+    pub locate_views: PFN_xrLocateViews,
+
+    pub get_vulkan_graphics_requirements_khr: PFN_xrGetVulkanGraphicsRequirementsKHR,
+    pub get_vulkan_instance_extensions_khr: PFN_xrGetVulkanInstanceExtensionsKHR,
+    pub get_vulkan_device_extensions_khr: PFN_xrGetVulkanDeviceExtensionsKHR,
+    pub get_vulkan_graphics_device_khr: PFN_xrGetVulkanGraphicsDeviceKHR,
+}
+
+unsafe fn load_function<T: Copy>(
+    get_instance_proc_addr: PFN_xrGetInstanceProcAddr,
+    instance: XrInstance,
+    name: &[u8],
+) -> T {
+    assert_eq!(std::mem::size_of::<T>(), std::mem::size_of::<PFN_xrVoidFunction>());
+
+    let mut function = None;
+    let result = get_instance_proc_addr(instance, name.as_ptr() as *const c_char, &mut function);
+    match (result, function) {
+        (XR_SUCCESS, Some(function)) => std::mem::transmute_copy(&function),
+        _ => panic!(
+            "xrGetInstanceProcAddr() failed to load {:?}: {}",
+            std::ffi::CStr::from_bytes_with_nul(name).unwrap(),
+            result
+        ),
+    }
+}
+
+impl OpenXrEntry {
+    pub unsafe fn load(get_instance_proc_addr: PFN_xrGetInstanceProcAddr) -> Self {
+        Self {
+            get_instance_proc_addr,
+            enumerate_instance_extension_properties: load_function(
+                get_instance_proc_addr,
+                XR_NULL_HANDLE,
+                b"xrEnumerateInstanceExtensionProperties\0",
+            ),
+            create_instance: load_function(get_instance_proc_addr, XR_NULL_HANDLE, b"xrCreateInstance\0"),
+        }
+    }
+}
+
+impl OpenXrInstance {
+    pub unsafe fn load(entry: &OpenXrEntry, instance: XrInstance) -> Self {
+        let get_instance_proc_addr = entry.get_instance_proc_addr;
+        Self {
+            destroy_instance: load_function(get_instance_proc_addr, instance, b"xrDestroyInstance\0"),
+            get_system: load_function(get_instance_proc_addr, instance, b"xrGetSystem\0"),
+            poll_event: load_function(get_instance_proc_addr, instance, b"xrPollEvent\0"),
+            enumerate_view_configuration_views: load_function(
+                get_instance_proc_addr,
+                instance,
+                b"xrEnumerateViewConfigurationViews\0",
+            ),
+            create_session: load_function(get_instance_proc_addr, instance, b"xrCreateSession\0"),
+            destroy_session: load_function(get_instance_proc_addr, instance, b"xrDestroySession\0"),
+            begin_session: load_function(get_instance_proc_addr, instance, b"xrBeginSession\0"),
+            end_session: load_function(get_instance_proc_addr, instance, b"xrEndSession\0"),
+            request_exit_session: load_function(get_instance_proc_addr, instance, b"xrRequestExitSession\0"),
+            create_reference_space: load_function(get_instance_proc_addr, instance, b"xrCreateReferenceSpace\0"),
+            destroy_space: load_function(get_instance_proc_addr, instance, b"xrDestroySpace\0"),
+            enumerate_swapchain_formats: load_function(
+                get_instance_proc_addr,
+                instance,
+                b"xrEnumerateSwapchainFormats\0",
+            ),
+            create_swapchain: load_function(get_instance_proc_addr, instance, b"xrCreateSwapchain\0"),
+            destroy_swapchain: load_function(get_instance_proc_addr, instance, b"xrDestroySwapchain\0"),
+            enumerate_swapchain_images: load_function(
+                get_instance_proc_addr,
+                instance,
+                b"xrEnumerateSwapchainImages\0",
+            ),
+            acquire_swapchain_image: load_function(get_instance_proc_addr, instance, b"xrAcquireSwapchainImage\0"),
+            wait_swapchain_image: load_function(get_instance_proc_addr, instance, b"xrWaitSwapchainImage\0"),
+            release_swapchain_image: load_function(get_instance_proc_addr, instance, b"xrReleaseSwapchainImage\0"),
+            wait_frame: load_function(get_instance_proc_addr, instance, b"xrWaitFrame\0"),
+            begin_frame: load_function(get_instance_proc_addr, instance, b"xrBeginFrame\0"),
+            end_frame: load_function(get_instance_proc_addr, instance, b"xrEndFrame\0"),
+            locate_views: load_function(get_instance_proc_addr, instance, b"xrLocateViews\0"),
+
+            get_vulkan_graphics_requirements_khr: load_function(
+                get_instance_proc_addr,
+                instance,
+                b"xrGetVulkanGraphicsRequirementsKHR\0",
+            ),
+            get_vulkan_instance_extensions_khr: load_function(
+                get_instance_proc_addr,
+                instance,
+                b"xrGetVulkanInstanceExtensionsKHR\0",
+            ),
+            get_vulkan_device_extensions_khr: load_function(
+                get_instance_proc_addr,
+                instance,
+                b"xrGetVulkanDeviceExtensionsKHR\0",
+            ),
+            get_vulkan_graphics_device_khr: load_function(
+                get_instance_proc_addr,
+                instance,
+                b"xrGetVulkanGraphicsDeviceKHR\0",
+            ),
+        }
+    }
+}
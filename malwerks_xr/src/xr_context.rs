@@ -0,0 +1,233 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use ash::version::InstanceV1_0;
+use malwerks_vk::*;
+
+use crate::openxr_ffi::*;
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+
+#[cfg(windows)]
+const OPENXR_LOADER_NAME: &str = "openxr_loader.dll";
+#[cfg(not(windows))]
+const OPENXR_LOADER_NAME: &str = "libopenxr_loader.so.1";
+
+// Vulkan version the device is created with, has to be within the runtime requirements
+const VULKAN_API_VERSION: XrVersion = xr_make_version(1, 1, 0);
+
+// OpenXR instance with a head mounted display system, owns the loader library
+pub struct XrContext {
+    pub(crate) functions: OpenXrInstance,
+    pub(crate) instance: XrInstance,
+    pub(crate) system_id: XrSystemId,
+    _library: libloading::Library,
+}
+
+impl XrContext {
+    // Returns None when there is no OpenXR runtime installed or no headset connected
+    pub fn new(application_name: &str) -> Option<Self> {
+        let library = match libloading::Library::new(OPENXR_LOADER_NAME) {
+            Ok(library) => library,
+            Err(error) => {
+                log::warn!("failed to load {}: {}", OPENXR_LOADER_NAME, error);
+                return None;
+            }
+        };
+
+        unsafe {
+            let get_instance_proc_addr = match library.get::<PFN_xrGetInstanceProcAddr>(b"xrGetInstanceProcAddr\0") {
+                Ok(function) => *function,
+                Err(error) => {
+                    log::warn!("failed to find xrGetInstanceProcAddr: {}", error);
+                    return None;
+                }
+            };
+            let entry = OpenXrEntry::load(get_instance_proc_addr);
+            if !supports_vulkan_enable(&entry) {
+                log::warn!("OpenXR runtime doesn't support XR_KHR_vulkan_enable");
+                return None;
+            }
+
+            let mut application_info = XrApplicationInfo {
+                application_name: [0; XR_MAX_APPLICATION_NAME_SIZE],
+                application_version: 0,
+                engine_name: [0; XR_MAX_ENGINE_NAME_SIZE],
+                engine_version: 0,
+                api_version: xr_make_version(1, 0, 0),
+            };
+            copy_name(&mut application_info.application_name, application_name);
+            copy_name(&mut application_info.engine_name, "malwerks");
+
+            let extension_names = [XR_KHR_VULKAN_ENABLE_EXTENSION_NAME.as_ptr() as *const c_char];
+            let mut instance = XR_NULL_HANDLE;
+            let result = (entry.create_instance)(
+                &XrInstanceCreateInfo {
+                    application_info,
+                    enabled_extension_count: extension_names.len() as _,
+                    enabled_extension_names: extension_names.as_ptr(),
+                    ..Default::default()
+                },
+                &mut instance,
+            );
+            if result != XR_SUCCESS {
+                log::warn!("xrCreateInstance() failed: {}", result);
+                return None;
+            }
+            let functions = OpenXrInstance::load(&entry, instance);
+
+            let mut system_id = XR_NULL_SYSTEM_ID;
+            let result = (functions.get_system)(
+                instance,
+                &XrSystemGetInfo {
+                    form_factor: XR_FORM_FACTOR_HEAD_MOUNTED_DISPLAY,
+                    ..Default::default()
+                },
+                &mut system_id,
+            );
+            if result != XR_SUCCESS {
+                log::warn!("xrGetSystem() failed, is the headset connected? {}", result);
+                (functions.destroy_instance)(instance);
+                return None;
+            }
+            log::info!("OpenXR system: {}", system_id);
+
+            Some(Self {
+                functions,
+                instance,
+                system_id,
+                _library: library,
+            })
+        }
+    }
+
+    pub fn destroy(&mut self) {
+        unsafe {
+            (self.functions.destroy_instance)(self.instance);
+        }
+        self.instance = XR_NULL_HANDLE;
+    }
+
+    // Device is created on the GPU the headset is connected to, multiview is always requested
+    pub fn create_device<T>(&self, surface_provider: &T, device_extensions: &[&CStr], options: DeviceOptions) -> Device
+    where
+        T: SurfaceProvider + ?Sized,
+    {
+        let mut graphics_requirements = XrGraphicsRequirementsVulkanKHR::default();
+        let result = unsafe {
+            (self.functions.get_vulkan_graphics_requirements_khr)(
+                self.instance,
+                self.system_id,
+                &mut graphics_requirements,
+            )
+        };
+        assert_eq!(result, XR_SUCCESS, "xrGetVulkanGraphicsRequirementsKHR() failed");
+        assert!(
+            VULKAN_API_VERSION >= graphics_requirements.min_api_version_supported,
+            "OpenXR runtime requires a newer Vulkan version"
+        );
+
+        let xr_instance_extensions = self.get_vulkan_extensions(self.functions.get_vulkan_instance_extensions_khr);
+        let xr_device_extensions = self.get_vulkan_extensions(self.functions.get_vulkan_device_extensions_khr);
+        log::info!("OpenXR instance extensions: {:?}", &xr_instance_extensions);
+        log::info!("OpenXR device extensions: {:?}", &xr_device_extensions);
+
+        let mut instance_extensions: Vec<&CStr> = surface_provider.get_required_instance_extensions();
+        for extension in &xr_instance_extensions {
+            if !instance_extensions.contains(&extension.as_c_str()) {
+                instance_extensions.push(extension);
+            }
+        }
+        let mut all_device_extensions = device_extensions.to_vec();
+        for extension in &xr_device_extensions {
+            if !all_device_extensions.contains(&extension.as_c_str()) {
+                all_device_extensions.push(extension);
+            }
+        }
+
+        Device::with_physical_device_filter(
+            &instance_extensions,
+            &all_device_extensions,
+            |entry: &ash::Entry, instance: &ash::Instance| {
+                surface_provider
+                    .create_surface(entry, instance)
+                    .expect("create_surface() failed")
+            },
+            |instance: &ash::Instance, physical_device: vk::PhysicalDevice| {
+                let mut xr_physical_device = vk::PhysicalDevice::null();
+                let result = unsafe {
+                    (self.functions.get_vulkan_graphics_device_khr)(
+                        self.instance,
+                        self.system_id,
+                        instance.handle(),
+                        &mut xr_physical_device,
+                    )
+                };
+                assert_eq!(result, XR_SUCCESS, "xrGetVulkanGraphicsDeviceKHR() failed");
+                xr_physical_device == physical_device
+            },
+            DeviceOptions {
+                enable_multiview: true,
+                ..options
+            },
+        )
+    }
+
+    // Extension lists are returned as a single space separated string
+    fn get_vulkan_extensions(
+        &self,
+        get_extensions: unsafe extern "system" fn(XrInstance, XrSystemId, u32, *mut u32, *mut c_char) -> XrResult,
+    ) -> Vec<CString> {
+        unsafe {
+            let mut count = 0;
+            let result = get_extensions(self.instance, self.system_id, 0, &mut count, std::ptr::null_mut());
+            assert_eq!(result, XR_SUCCESS, "failed to query OpenXR Vulkan extensions");
+
+            let mut buffer = vec![0 as c_char; count as usize];
+            let result = get_extensions(self.instance, self.system_id, count, &mut count, buffer.as_mut_ptr());
+            assert_eq!(result, XR_SUCCESS, "failed to query OpenXR Vulkan extensions");
+
+            CStr::from_ptr(buffer.as_ptr())
+                .to_str()
+                .expect("OpenXR extension names are not valid UTF-8")
+                .split(' ')
+                .filter(|name| !name.is_empty())
+                .map(|name| CString::new(name).unwrap())
+                .collect()
+        }
+    }
+}
+
+fn supports_vulkan_enable(entry: &OpenXrEntry) -> bool {
+    unsafe {
+        let mut count = 0;
+        if (entry.enumerate_instance_extension_properties)(std::ptr::null(), 0, &mut count, std::ptr::null_mut())
+            != XR_SUCCESS
+        {
+            return false;
+        }
+
+        let mut properties = vec![XrExtensionProperties::default(); count as usize];
+        if (entry.enumerate_instance_extension_properties)(std::ptr::null(), count, &mut count, properties.as_mut_ptr())
+            != XR_SUCCESS
+        {
+            return false;
+        }
+
+        let required_name = CStr::from_bytes_with_nul(XR_KHR_VULKAN_ENABLE_EXTENSION_NAME).unwrap();
+        properties
+            .iter()
+            .any(|property| CStr::from_ptr(property.extension_name.as_ptr()) == required_name)
+    }
+}
+
+// Names are truncated to fit, the last character is always a null terminator
+fn copy_name(destination: &mut [c_char], name: &str) {
+    let max_length = destination.len() - 1;
+    for (destination, source) in destination.iter_mut().zip(name.bytes().take(max_length)) {
+        *destination = source as c_char;
+    }
+}
@@ -0,0 +1,613 @@
+// Copyright (c) 2020-2021 Kyrylo Bazhenov
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+// If a copy of the MPL was not distributed with this file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use ash::version::InstanceV1_0;
+use malwerks_core::*;
+use malwerks_render::*;
+use malwerks_vk::*;
+
+use crate::openxr_ffi::*;
+use crate::xr_context::*;
+
+const VIEW_COUNT: usize = 2;
+
+// Swapchain formats in order of preference, the compositor expects sRGB encoded images
+const SWAPCHAIN_FORMATS: [vk::Format; 2] = [vk::Format::R8G8B8A8_SRGB, vk::Format::B8G8R8A8_SRGB];
+
+// Views located for a frame, has to be passed back to end_frame() even if nothing is rendered
+pub struct XrFrame {
+    display_time: XrTime,
+    should_render: bool,
+    views: [XrView; VIEW_COUNT],
+}
+
+impl XrFrame {
+    pub fn should_render(&self) -> bool {
+        self.should_render
+    }
+
+    // Headset pose is relative to the camera, view 0 is the left eye
+    pub fn calculate_view_projections(&self, camera: &Camera) -> [ultraviolet::mat::Mat4; VIEW_COUNT] {
+        let calculate = |view: &XrView| {
+            camera.calculate_eye_view_projection(
+                &pose_to_matrix(&view.pose),
+                &EyeFieldOfView {
+                    angle_left: view.fov.angle_left,
+                    angle_right: view.fov.angle_right,
+                    angle_up: view.fov.angle_up,
+                    angle_down: view.fov.angle_down,
+                },
+            )
+        };
+        [calculate(&self.views[0]), calculate(&self.views[1])]
+    }
+}
+
+// Session presents the stereo layer of PbrForwardLit to the headset. Both eyes share one swapchain
+// with an array layer per eye, matching the multiview layout of the stereo layer.
+pub struct XrSession {
+    session: crate::openxr_ffi::XrSession,
+    space: XrSpace,
+    swapchain: XrSwapchain,
+    swapchain_images: Vec<vk::Image>,
+    swapchain_extent: vk::Extent2D,
+
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<CommandBuffer>, // one per swapchain image
+    command_fences: Vec<vk::Fence>,
+
+    session_running: bool,
+    exit_requested: bool,
+}
+
+impl XrSession {
+    pub fn new(context: &XrContext, device: &Device, factory: &mut DeviceFactory) -> Self {
+        let functions = &context.functions;
+        unsafe {
+            let graphics_binding = XrGraphicsBindingVulkanKHR {
+                instance: device.get_instance().handle(),
+                physical_device: device.get_physical_device(),
+                device: device.get_device().handle(),
+                queue_family_index: device.get_graphics_queue_index(),
+                queue_index: 0,
+                ..Default::default()
+            };
+            let mut session = XR_NULL_HANDLE;
+            let result = (functions.create_session)(
+                context.instance,
+                &XrSessionCreateInfo {
+                    next: &graphics_binding as *const XrGraphicsBindingVulkanKHR as _,
+                    system_id: context.system_id,
+                    ..Default::default()
+                },
+                &mut session,
+            );
+            assert_eq!(result, XR_SUCCESS, "xrCreateSession() failed");
+
+            // Local space origin is where the headset was when the session started
+            let mut space = XR_NULL_HANDLE;
+            let result = (functions.create_reference_space)(
+                session,
+                &XrReferenceSpaceCreateInfo {
+                    reference_space_type: XR_REFERENCE_SPACE_TYPE_LOCAL,
+                    pose_in_reference_space: XrPosef {
+                        orientation: XrQuaternionf {
+                            w: 1.0,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                &mut space,
+            );
+            assert_eq!(result, XR_SUCCESS, "xrCreateReferenceSpace() failed");
+
+            let mut view_count = 0;
+            let mut view_configuration_views = [XrViewConfigurationView::default(); VIEW_COUNT];
+            let result = (functions.enumerate_view_configuration_views)(
+                context.instance,
+                context.system_id,
+                XR_VIEW_CONFIGURATION_TYPE_PRIMARY_STEREO,
+                VIEW_COUNT as _,
+                &mut view_count,
+                view_configuration_views.as_mut_ptr(),
+            );
+            assert_eq!(result, XR_SUCCESS, "xrEnumerateViewConfigurationViews() failed");
+            assert_eq!(
+                view_count as usize, VIEW_COUNT,
+                "stereo view configuration has to have 2 views"
+            );
+            let swapchain_extent = vk::Extent2D {
+                width: view_configuration_views[0].recommended_image_rect_width,
+                height: view_configuration_views[0].recommended_image_rect_height,
+            };
+            log::info!("OpenXR eye resolution: {:?}", swapchain_extent);
+
+            let swapchain_format = choose_swapchain_format(context, session);
+            let mut swapchain = XR_NULL_HANDLE;
+            let result = (functions.create_swapchain)(
+                session,
+                &XrSwapchainCreateInfo {
+                    usage_flags: XR_SWAPCHAIN_USAGE_COLOR_ATTACHMENT_BIT | XR_SWAPCHAIN_USAGE_TRANSFER_DST_BIT,
+                    format: swapchain_format.as_raw() as _,
+                    sample_count: 1,
+                    width: swapchain_extent.width,
+                    height: swapchain_extent.height,
+                    face_count: 1,
+                    array_size: VIEW_COUNT as _,
+                    mip_count: 1,
+                    ..Default::default()
+                },
+                &mut swapchain,
+            );
+            assert_eq!(result, XR_SUCCESS, "xrCreateSwapchain() failed");
+
+            let mut image_count = 0;
+            let result = (functions.enumerate_swapchain_images)(swapchain, 0, &mut image_count, std::ptr::null_mut());
+            assert_eq!(result, XR_SUCCESS, "xrEnumerateSwapchainImages() failed");
+            let mut images = vec![XrSwapchainImageVulkanKHR::default(); image_count as usize];
+            let result =
+                (functions.enumerate_swapchain_images)(swapchain, image_count, &mut image_count, images.as_mut_ptr());
+            assert_eq!(result, XR_SUCCESS, "xrEnumerateSwapchainImages() failed");
+            let swapchain_images: Vec<vk::Image> = images.iter().map(|image| image.image).collect();
+
+            let command_pool = factory.create_command_pool(
+                &vk::CommandPoolCreateInfo::builder()
+                    .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
+                    .queue_family_index(device.get_graphics_queue_index())
+                    .build(),
+            );
+            let command_buffers = factory.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(swapchain_images.len() as _)
+                    .build(),
+            );
+            let command_fences = swapchain_images
+                .iter()
+                .map(|_| {
+                    factory.create_fence(
+                        &vk::FenceCreateInfo::builder()
+                            .flags(vk::FenceCreateFlags::SIGNALED)
+                            .build(),
+                    )
+                })
+                .collect();
+
+            Self {
+                session,
+                space,
+                swapchain,
+                swapchain_images,
+                swapchain_extent,
+                command_pool,
+                command_buffers,
+                command_fences,
+                session_running: false,
+                exit_requested: false,
+            }
+        }
+    }
+
+    pub fn destroy(&mut self, context: &XrContext, factory: &mut DeviceFactory) {
+        let functions = &context.functions;
+        unsafe {
+            if self.session_running {
+                (functions.end_session)(self.session);
+            }
+            (functions.destroy_swapchain)(self.swapchain);
+            (functions.destroy_space)(self.space);
+            (functions.destroy_session)(self.session);
+        }
+
+        for fence in &self.command_fences {
+            factory.destroy_fence(*fence);
+        }
+        factory.free_command_buffers(self.command_pool, &self.command_buffers);
+        factory.destroy_command_pool(self.command_pool);
+    }
+
+    pub fn get_swapchain_extent(&self) -> vk::Extent2D {
+        self.swapchain_extent
+    }
+
+    pub fn is_session_running(&self) -> bool {
+        self.session_running
+    }
+
+    // Runtime asked the application to quit, e.g. the headset was disconnected
+    pub fn is_exit_requested(&self) -> bool {
+        self.exit_requested
+    }
+
+    pub fn request_exit(&mut self, context: &XrContext) {
+        if self.session_running {
+            unsafe {
+                (context.functions.request_exit_session)(self.session);
+            }
+        } else {
+            self.exit_requested = true;
+        }
+    }
+
+    // Has to be called every frame, session is started and stopped here when the runtime asks for it
+    pub fn poll_events(&mut self, context: &XrContext) {
+        let functions = &context.functions;
+        loop {
+            let mut event = XrEventDataBuffer::default();
+            let result = unsafe { (functions.poll_event)(context.instance, &mut event) };
+            if result == XR_EVENT_UNAVAILABLE {
+                break;
+            }
+            assert_eq!(result, XR_SUCCESS, "xrPollEvent() failed");
+
+            match event.ty {
+                XR_TYPE_EVENT_DATA_SESSION_STATE_CHANGED => {
+                    let state_changed =
+                        unsafe { &*(&event as *const XrEventDataBuffer as *const XrEventDataSessionStateChanged) };
+                    log::info!("OpenXR session state: {}", state_changed.state);
+                    match state_changed.state {
+                        XR_SESSION_STATE_READY => {
+                            let result = unsafe {
+                                (functions.begin_session)(
+                                    self.session,
+                                    &XrSessionBeginInfo {
+                                        primary_view_configuration_type: XR_VIEW_CONFIGURATION_TYPE_PRIMARY_STEREO,
+                                        ..Default::default()
+                                    },
+                                )
+                            };
+                            assert_eq!(result, XR_SUCCESS, "xrBeginSession() failed");
+                            self.session_running = true;
+                        }
+                        XR_SESSION_STATE_STOPPING => {
+                            let result = unsafe { (functions.end_session)(self.session) };
+                            assert_eq!(result, XR_SUCCESS, "xrEndSession() failed");
+                            self.session_running = false;
+                        }
+                        XR_SESSION_STATE_EXITING | XR_SESSION_STATE_LOSS_PENDING => {
+                            self.exit_requested = true;
+                        }
+                        _ => {}
+                    }
+                }
+                XR_TYPE_EVENT_DATA_INSTANCE_LOSS_PENDING => {
+                    log::warn!("OpenXR instance loss pending");
+                    self.exit_requested = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Blocks until the runtime wants the next frame, returns None while the session is not running or being lost
+    pub fn begin_frame(&mut self, context: &XrContext) -> Option<XrFrame> {
+        if !self.session_running {
+            return None;
+        }
+
+        let functions = &context.functions;
+        unsafe {
+            let mut frame_state = XrFrameState::default();
+            let result = (functions.wait_frame)(self.session, &XrFrameWaitInfo::default(), &mut frame_state);
+            assert!(result >= XR_SUCCESS, "xrWaitFrame() failed: {}", result);
+            if result == XR_SESSION_LOSS_PENDING {
+                log::warn!("OpenXR session loss pending");
+                self.exit_requested = true;
+                return None;
+            }
+            let result = (functions.begin_frame)(self.session, &XrFrameBeginInfo::default());
+            assert!(result >= XR_SUCCESS, "xrBeginFrame() failed: {}", result);
+
+            let mut view_state = XrViewState::default();
+            let mut views = [XrView::default(); VIEW_COUNT];
+            let mut view_count = 0;
+            let result = (functions.locate_views)(
+                self.session,
+                &XrViewLocateInfo {
+                    view_configuration_type: XR_VIEW_CONFIGURATION_TYPE_PRIMARY_STEREO,
+                    display_time: frame_state.predicted_display_time,
+                    space: self.space,
+                    ..Default::default()
+                },
+                &mut view_state,
+                VIEW_COUNT as _,
+                &mut view_count,
+                views.as_mut_ptr(),
+            );
+            assert_eq!(result, XR_SUCCESS, "xrLocateViews() failed");
+
+            // Views can't be used without tracking, the frame is still ended but nothing is submitted
+            let views_valid = view_state.view_state_flags
+                & (XR_VIEW_STATE_ORIENTATION_VALID_BIT | XR_VIEW_STATE_POSITION_VALID_BIT)
+                != 0;
+            Some(XrFrame {
+                display_time: frame_state.predicted_display_time,
+                should_render: frame_state.should_render == XR_TRUE && views_valid,
+                views,
+            })
+        }
+    }
+
+    // Stereo layer is copied into the swapchain and submitted to the compositor. Commands are recorded
+    // after the stereo layer on the same queue, a pipeline barrier is enough to wait for it.
+    // There is no tone mapping, HDR values are clamped by the copy.
+    pub fn end_frame(
+        &mut self,
+        context: &XrContext,
+        frame: XrFrame,
+        stereo_layer: Option<&RenderLayer>,
+        render_area: vk::Rect2D,
+        device: &Device,
+        queue: &mut DeviceQueue,
+    ) {
+        let functions = &context.functions;
+        let stereo_layer = match stereo_layer {
+            Some(stereo_layer) if frame.should_render => stereo_layer,
+            _ => {
+                let result = unsafe {
+                    (functions.end_frame)(
+                        self.session,
+                        &XrFrameEndInfo {
+                            display_time: frame.display_time,
+                            environment_blend_mode: XR_ENVIRONMENT_BLEND_MODE_OPAQUE,
+                            ..Default::default()
+                        },
+                    )
+                };
+                assert!(result >= XR_SUCCESS, "xrEndFrame() failed: {}", result);
+                return;
+            }
+        };
+        assert_eq!(
+            stereo_layer.get_view_layer_count() as usize,
+            VIEW_COUNT,
+            "stereo layer has to have one array layer per eye"
+        );
+
+        unsafe {
+            let mut image_index = 0;
+            let result = (functions.acquire_swapchain_image)(
+                self.swapchain,
+                &XrSwapchainImageAcquireInfo::default(),
+                &mut image_index,
+            );
+            assert_eq!(result, XR_SUCCESS, "xrAcquireSwapchainImage() failed");
+            let result = (functions.wait_swapchain_image)(
+                self.swapchain,
+                &XrSwapchainImageWaitInfo {
+                    timeout: XR_INFINITE_DURATION,
+                    ..Default::default()
+                },
+            );
+            assert_eq!(result, XR_SUCCESS, "xrWaitSwapchainImage() failed");
+
+            let image_index = image_index as usize;
+            let command_fence = self.command_fences[image_index];
+            device.wait_for_fences(&[command_fence], true, u64::MAX);
+            device.reset_fences(&[command_fence]);
+
+            let command_buffer = &mut self.command_buffers[image_index];
+            record_swapchain_copy(
+                command_buffer,
+                stereo_layer.get_render_image(0).0,
+                render_area,
+                self.swapchain_images[image_index],
+                self.swapchain_extent,
+            );
+            queue.submit(
+                &[vk::SubmitInfo::builder()
+                    .command_buffers(&[(*command_buffer).into()])
+                    .build()],
+                command_fence,
+            );
+
+            let result = (functions.release_swapchain_image)(self.swapchain, &XrSwapchainImageReleaseInfo::default());
+            assert_eq!(result, XR_SUCCESS, "xrReleaseSwapchainImage() failed");
+
+            let mut projection_views = [XrCompositionLayerProjectionView::default(); VIEW_COUNT];
+            for (view_id, projection_view) in projection_views.iter_mut().enumerate() {
+                projection_view.pose = frame.views[view_id].pose;
+                projection_view.fov = frame.views[view_id].fov;
+                projection_view.sub_image = XrSwapchainSubImage {
+                    swapchain: self.swapchain,
+                    image_rect: XrRect2Di {
+                        offset: XrOffset2Di { x: 0, y: 0 },
+                        extent: XrExtent2Di {
+                            width: self.swapchain_extent.width as _,
+                            height: self.swapchain_extent.height as _,
+                        },
+                    },
+                    image_array_index: view_id as _,
+                };
+            }
+            let projection_layer = XrCompositionLayerProjection {
+                space: self.space,
+                view_count: VIEW_COUNT as _,
+                views: projection_views.as_ptr(),
+                ..Default::default()
+            };
+            let layers = [&projection_layer as *const XrCompositionLayerProjection];
+            let result = (functions.end_frame)(
+                self.session,
+                &XrFrameEndInfo {
+                    display_time: frame.display_time,
+                    environment_blend_mode: XR_ENVIRONMENT_BLEND_MODE_OPAQUE,
+                    layer_count: layers.len() as _,
+                    layers: layers.as_ptr(),
+                    ..Default::default()
+                },
+            );
+            assert!(result >= XR_SUCCESS, "xrEndFrame() failed: {}", result);
+        }
+    }
+}
+
+fn choose_swapchain_format(context: &XrContext, session: crate::openxr_ffi::XrSession) -> vk::Format {
+    let functions = &context.functions;
+    unsafe {
+        let mut format_count = 0;
+        let result = (functions.enumerate_swapchain_formats)(session, 0, &mut format_count, std::ptr::null_mut());
+        assert_eq!(result, XR_SUCCESS, "xrEnumerateSwapchainFormats() failed");
+        let mut formats = vec![0i64; format_count as usize];
+        let result =
+            (functions.enumerate_swapchain_formats)(session, format_count, &mut format_count, formats.as_mut_ptr());
+        assert_eq!(result, XR_SUCCESS, "xrEnumerateSwapchainFormats() failed");
+
+        SWAPCHAIN_FORMATS
+            .iter()
+            .find(|format| formats.contains(&(format.as_raw() as i64)))
+            .copied()
+            .expect("OpenXR runtime doesn't support any of the swapchain formats")
+    }
+}
+
+// Swapchain images are in color attachment layout outside of acquire/release
+fn record_swapchain_copy(
+    command_buffer: &mut CommandBuffer,
+    stereo_image: vk::Image,
+    render_area: vk::Rect2D,
+    swapchain_image: vk::Image,
+    swapchain_extent: vk::Extent2D,
+) {
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(VIEW_COUNT as _)
+        .build();
+    let subresource_layers = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(VIEW_COUNT as _)
+        .build();
+
+    command_buffer.reset();
+    command_buffer.begin(
+        &vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build(),
+    );
+    command_buffer.pipeline_barrier(
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::PipelineStageFlags::TRANSFER,
+        None,
+        &[],
+        &[],
+        &[
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(stereo_image)
+                .subresource_range(subresource_range)
+                .build(),
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(swapchain_image)
+                .subresource_range(subresource_range)
+                .build(),
+        ],
+    );
+    command_buffer.blit_image(
+        stereo_image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        swapchain_image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[vk::ImageBlit::builder()
+            .src_subresource(subresource_layers)
+            .src_offsets([
+                vk::Offset3D {
+                    x: render_area.offset.x,
+                    y: render_area.offset.y,
+                    z: 0,
+                },
+                vk::Offset3D {
+                    x: render_area.offset.x + render_area.extent.width as i32,
+                    y: render_area.offset.y + render_area.extent.height as i32,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(subresource_layers)
+            .dst_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: swapchain_extent.width as _,
+                    y: swapchain_extent.height as _,
+                    z: 1,
+                },
+            ])
+            .build()],
+        vk::Filter::LINEAR,
+    );
+    command_buffer.pipeline_barrier(
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        None,
+        &[],
+        &[],
+        &[
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(stereo_image)
+                .subresource_range(subresource_range)
+                .build(),
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .src_queue_family_index(!0)
+                .dst_queue_family_index(!0)
+                .image(swapchain_image)
+                .subresource_range(subresource_range)
+                .build(),
+        ],
+    );
+    command_buffer.end();
+}
+
+// OpenXR poses are right handed with Y up and -Z forward, same as camera view space
+fn pose_to_matrix(pose: &XrPosef) -> ultraviolet::mat::Mat4 {
+    let XrQuaternionf { x, y, z, w } = pose.orientation;
+    ultraviolet::mat::Mat4::new(
+        ultraviolet::vec::Vec4::new(
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y + z * w),
+            2.0 * (x * z - y * w),
+            0.0,
+        ),
+        ultraviolet::vec::Vec4::new(
+            2.0 * (x * y - z * w),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z + x * w),
+            0.0,
+        ),
+        ultraviolet::vec::Vec4::new(
+            2.0 * (x * z + y * w),
+            2.0 * (y * z - x * w),
+            1.0 - 2.0 * (x * x + y * y),
+            0.0,
+        ),
+        ultraviolet::vec::Vec4::new(pose.position.x, pose.position.y, pose.position.z, 1.0),
+    )
+}